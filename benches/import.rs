@@ -0,0 +1,11 @@
+//! Block import, timed and with its allocations counted. See `blockhead::bench`.
+use blockhead::bench::{self, Bencher, CountingAllocator};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let bencher = Bencher::from_args();
+    bench::block_import(&bencher);
+    bench::block_import_allocations(&bencher);
+}
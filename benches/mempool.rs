@@ -0,0 +1,6 @@
+//! Mempool admission. See `blockhead::bench`.
+use blockhead::bench::{self, Bencher};
+
+fn main() {
+    bench::mempool_insert(&Bencher::from_args());
+}
@@ -0,0 +1,10 @@
+//! Block, balance and log queries, and reads during block production. See `blockhead::bench`.
+use blockhead::bench::{self, Bencher};
+
+fn main() {
+    let bencher = Bencher::from_args();
+    bench::queries(&bencher);
+    bench::get_logs(&bencher);
+    bench::get_logs_by_address(&bencher);
+    bench::mixed_workload(&bencher);
+}
@@ -0,0 +1,7 @@
+//! Blocks in their canonical encoding, as served raw by `GET /blocks` and sent to `POST /blocks`.
+//! See [`blockhead::fuzzing::block`].
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| blockhead::fuzzing::block(data));
@@ -0,0 +1,6 @@
+//! GraphQL queries as posted to /graphql. See [`blockhead::fuzzing::graphql`].
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| blockhead::fuzzing::graphql(data));
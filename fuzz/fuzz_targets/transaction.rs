@@ -0,0 +1,7 @@
+//! Transactions as sent raw to `POST /transactions`. See
+//! [`blockhead::fuzzing::transaction`].
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| blockhead::fuzzing::transaction(data));
@@ -1,3 +1,96 @@
+use crate::error::{Error, Result};
+use crate::hash::{decode_bytes32, decode_hex32, HashAlgorithm, HashBuilder};
+
 /// An address in the blockhead blockchain.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub(crate) struct Address(pub [u8; 32]);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub [u8; 32]);
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl serde::Serialize for Address {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Address {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        decode_hex32(&s)
+            .map(Address)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<[u8; 32]> for Address {
+    fn from(bytes: [u8; 32]) -> Self {
+        Address(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for Address {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        decode_bytes32(bytes).map(Address)
+    }
+}
+
+impl Address {
+    /// The all-zero address, which no key controls. Calls without a sender come from it.
+    pub const fn zero() -> Self {
+        Address([0; 32])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 32]
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The address of the contract deployed by `deployer`'s transaction with `nonce`, on a chain
+    /// that hashes with `algorithm`.
+    pub fn for_contract(deployer: Address, nonce: u64, algorithm: HashAlgorithm) -> Address {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(b"contract");
+        hasher.update(deployer.as_bytes());
+        hasher.update(nonce.to_be_bytes());
+        Address::from(*hasher.finalize().as_bytes())
+    }
+
+    /// The address of the token created by `creator`'s transaction with `nonce`, on a chain that
+    /// hashes with `algorithm`.
+    pub fn for_token(creator: Address, nonce: u64, algorithm: HashAlgorithm) -> Address {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(b"token");
+        hasher.update(creator.as_bytes());
+        hasher.update(nonce.to_be_bytes());
+        Address::from(*hasher.finalize().as_bytes())
+    }
+
+    /// The address controlled by the holder of the private key for `key`, on a chain that hashes
+    /// with `algorithm`.
+    pub fn from_public_key(key: &k256::ecdsa::VerifyingKey, algorithm: HashAlgorithm) -> Address {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(key.to_encoded_point(false).as_bytes());
+        Address::from(*hasher.finalize().as_bytes())
+    }
+
+    /// The `n`th reserved system address, `0x00..00n`.
+    pub const fn reserved(n: u8) -> Address {
+        let mut bytes = [0; 32];
+        bytes[31] = n;
+        Address(bytes)
+    }
+}
@@ -0,0 +1,100 @@
+//! Names for addresses, kept in a local TOML file and accepted wherever the CLI takes an address.
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::hash::decode_hex32;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AddressBook {
+    #[serde(default)]
+    pub addresses: BTreeMap<String, Address>,
+}
+
+/// `$BLOCKHEAD_ADDRESS_BOOK`, or `~/.blockhead/addresses.toml`.
+pub(crate) fn default_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("BLOCKHEAD_ADDRESS_BOOK") {
+        return PathBuf::from(path);
+    }
+    let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+    Path::new(&home).join(".blockhead").join("addresses.toml")
+}
+
+/// Names start with a letter and continue with letters, digits, `-` and `_`, so they can never
+/// be mistaken for a hex address.
+fn check_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::new(format!("invalid address name {name:?}")));
+    }
+    Ok(())
+}
+
+impl AddressBook {
+    /// Read the book at `path`, which is empty if the file does not exist yet.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Name `address`, replacing whatever the name referred to before.
+    pub(crate) fn add(&mut self, name: &str, address: Address) -> Result<()> {
+        check_name(name)?;
+        self.addresses.insert(name.to_string(), address);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Result<Address> {
+        self.addresses
+            .remove(name)
+            .ok_or_else(|| Error::new(format!("no address named {name:?}")))
+    }
+
+    /// Parse `s` as a `0x`-prefixed hex address or look it up as a name.
+    pub(crate) fn resolve(&self, s: &str) -> Result<Address> {
+        if s.starts_with("0x") {
+            return Ok(Address(decode_hex32(s)?));
+        }
+        self.addresses
+            .get(s)
+            .copied()
+            .ok_or_else(|| Error::new(format!("no address named {s:?}")))
+    }
+}
+
+#[test]
+fn test_address_book() {
+    let path = std::env::temp_dir().join(format!(
+        "blockhead-address-book-{}/addresses.toml",
+        std::process::id()
+    ));
+    let mut book = AddressBook::load(&path).unwrap();
+    assert!(book.addresses.is_empty());
+    let alice = Address([0xa1; 32]);
+    book.add("alice", alice).unwrap();
+    assert!(book.add("0xalice", alice).is_err());
+    assert!(book.add("", alice).is_err());
+    book.save(&path).unwrap();
+
+    let mut book = AddressBook::load(&path).unwrap();
+    assert_eq!(book.resolve("alice").unwrap(), alice);
+    assert_eq!(book.resolve(&alice.to_string()).unwrap(), alice);
+    assert!(book.resolve("bob").is_err());
+    assert_eq!(book.remove("alice").unwrap(), alice);
+    assert!(book.remove("alice").is_err());
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,293 @@
+//! Node control for operators, served under `/admin` by [`crate::http`].
+//!
+//! The endpoints change the running node rather than read the chain: set the log level, prune
+//! and compact the database, pause and resume block production, dump the mempool and shut the
+//! node down. `GET /admin/db_stats` reports the row counts and sizes of the database's tables and
+//! indexes, which takes a scan of the whole database. They are off unless
+//! [`HttpConfig::admin_token`] is set, and every request must carry it as
+//! `Authorization: Bearer <token>`.
+//!
+//! [`HttpConfig::admin_token`]: crate::http::HttpConfig::admin_token
+use crate::db;
+use crate::error::Result;
+#[cfg(feature = "http")]
+use crate::http::{Request, Response};
+#[cfg(feature = "http")]
+use crate::maintenance::DbStats;
+#[cfg(feature = "http")]
+use crate::runtime;
+use crate::Blockhead;
+#[cfg(feature = "http")]
+use serde_json::json;
+use std::sync::atomic::Ordering;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+
+impl Blockhead {
+    /// Make [`Blockhead::produce_block`] fail until [`Blockhead::resume_production`]. Imports
+    /// carry on.
+    pub(crate) fn pause_production(&self) {
+        self.production_paused.store(true, Ordering::SeqCst);
+        log::info!("paused block production");
+    }
+
+    pub(crate) fn resume_production(&self) {
+        self.production_paused.store(false, Ordering::SeqCst);
+        log::info!("resumed block production");
+    }
+
+    pub(crate) fn is_production_paused(&self) -> bool {
+        self.production_paused.load(Ordering::SeqCst)
+    }
+
+    /// Record the state checkpoints and delete the traces the config calls for now, rather than
+    /// at the next block. Useful after lowering [`crate::PruningConfig::checkpoint_interval`] or
+    /// [`crate::TraceConfig::retain_blocks`].
+    pub(crate) fn prune(&self) -> Result<()> {
+        self.ensure_writable("prune the database")?;
+        let _guard = self.write_lock.lock().unwrap();
+        db::transaction(&self.connection, || {
+            self.checkpoint_state(self.finalized()?.number)?;
+            let retain_blocks = self.config.trace.retain_blocks;
+            if retain_blocks > 0 {
+                let head = self.head()?;
+                db::delete_traces_before(
+                    &self.connection,
+                    (head.number + 1).saturating_sub(retain_blocks),
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Ask whoever runs the node to stop it. See [`Blockhead::shutdown_requested`].
+    pub(crate) fn request_shutdown(&self) {
+        log::info!("shutdown requested");
+        self.shutdown.notify_one();
+    }
+
+    /// Wait until [`Blockhead::request_shutdown`] is called, or return at once if it already was.
+    pub(crate) async fn shutdown_requested(&self) {
+        self.shutdown.notified().await;
+    }
+}
+
+/// Whether `authorization`, the value of a request's `Authorization` header, carries `token`.
+/// Compares in constant time, so response timing does not leak how much of a guess matched.
+#[cfg(feature = "http")]
+fn is_authorized(authorization: Option<&str>, token: &str) -> bool {
+    let Some(presented) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Answer `/admin/<action>`, if the request carries the admin token.
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, request: &Request) -> Response {
+    let Some(token) = &blockhead.config.http.admin_token else {
+        return Response::error(404, format!("no endpoint {}", request.path));
+    };
+    if !is_authorized(request.authorization.as_deref(), token) {
+        return Response::error(401, "missing or wrong admin token");
+    }
+    let action = request.path.trim_start_matches("/admin/");
+    if request.method == "GET" && action == "mempool" {
+        return dump_mempool(blockhead);
+    }
+    if request.method == "GET" && action == "db_stats" {
+        let blockhead = blockhead.clone();
+        return match runtime::spawn_blocking(move || blockhead.db_stats()).await {
+            Ok(stats) => Response::json(200, &db_stats_json(&stats)),
+            Err(error) => Response::error(500, error.message()),
+        };
+    }
+    if request.method != "POST" {
+        return Response::error(405, "use POST");
+    }
+    let result = match action {
+        "log_level" => {
+            let level = request.query_param("level").unwrap_or_default();
+            match level.parse::<log::LevelFilter>() {
+                Ok(level) => {
+                    log::set_max_level(level);
+                    Ok(json!({"log_level": level.to_string()}))
+                }
+                Err(_) => return Response::error(400, format!("bad log level {level:?}")),
+            }
+        }
+        "pause" => {
+            blockhead.pause_production();
+            Ok(json!({"paused": true}))
+        }
+        "resume" => {
+            blockhead.resume_production();
+            Ok(json!({"paused": false}))
+        }
+        "prune" => {
+            let pruner = blockhead.clone();
+            runtime::spawn_blocking(move || pruner.prune())
+                .await
+                .map(|()| json!({"pruned": true}))
+        }
+        "compact" => {
+            let compactor = blockhead.clone();
+            runtime::spawn_blocking(move || compactor.compact())
+                .await
+                .map(|report| {
+                    json!({"size_before": report.size_before, "size_after": report.size_after})
+                })
+        }
+        "shutdown" => {
+            blockhead.request_shutdown();
+            Ok(json!({"shutting_down": true}))
+        }
+        _ => return Response::error(404, format!("no endpoint {}", request.path)),
+    };
+    match result {
+        Ok(body) => Response::json(200, &body),
+        Err(error) => Response::error(500, error.message()),
+    }
+}
+
+#[cfg(feature = "http")]
+fn db_stats_json(stats: &DbStats) -> serde_json::Value {
+    let tables: Vec<_> = stats
+        .tables
+        .iter()
+        .map(|table| {
+            let indexes: serde_json::Map<_, _> = table
+                .indexes
+                .iter()
+                .map(|(name, size)| (name.clone(), (*size).into()))
+                .collect();
+            json!({
+                "name": table.name,
+                "rows": table.rows,
+                "bytes": table.size,
+                "index_bytes": indexes,
+            })
+        })
+        .collect();
+    json!({
+        "database_bytes": stats.files.database,
+        "free_bytes": stats.files.free,
+        "wal_bytes": stats.files.wal,
+        "tables": tables,
+    })
+}
+
+/// Every transaction in the mempool in admission order, encoded to send again elsewhere.
+#[cfg(feature = "http")]
+fn dump_mempool(blockhead: &Blockhead) -> Response {
+    let transactions: Vec<_> = blockhead
+        .mempool
+        .lock()
+        .unwrap()
+        .pending()
+        .into_iter()
+        .map(|transaction| {
+            json!({
+                "hash": transaction.compute_hash(blockhead.config.hash).to_string(),
+                "from": transaction.from_address.to_string(),
+                "nonce": transaction.nonce,
+                "raw": format!("0x{}", hex::encode(transaction.encode())),
+            })
+        })
+        .collect();
+    Response::json(200, &json!({"transactions": transactions}))
+}
+
+#[tokio::test]
+async fn test_admin() {
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    chain.blockhead.pause_production();
+    assert!(chain.blockhead.produce_block().is_err());
+    #[cfg(feature = "cli")]
+    assert!(chain.blockhead.seal_pending().unwrap().is_empty());
+    chain.blockhead.resume_production();
+    chain.produce();
+    chain.blockhead.prune().unwrap();
+
+    chain.blockhead.request_shutdown();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        chain.blockhead.shutdown_requested(),
+    )
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_admin_endpoints() {
+    use crate::http::{send, serve_locally};
+    use crate::testkit::{self, TestChain};
+
+    assert!(is_authorized(Some("Bearer secret"), "secret"));
+    assert!(!is_authorized(Some("Bearer secreT"), "secret"));
+    assert!(!is_authorized(Some("secret"), "secret"));
+    assert!(!is_authorized(None, "secret"));
+
+    let address = serve_locally(Arc::new(TestChain::new().blockhead)).await;
+    let (status, _) = send(address, "POST /admin/pause HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 404);
+
+    let mut config = testkit::config();
+    config.http.admin_token = Some("secret".to_string());
+    let chain = TestChain::with_config(config);
+    chain
+        .transfer(chain.validator, crate::address::Address([8; 32]), 1)
+        .await;
+    let blockhead = Arc::new(chain.blockhead);
+    let address = serve_locally(blockhead.clone()).await;
+    let admin = |request: &str| {
+        let request = format!("{request} HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+        async move { send(address, &request).await }
+    };
+    let (status, _) = send(address, "POST /admin/pause HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 401);
+    let (status, body) = admin("POST /admin/pause").await;
+    assert_eq!((status, &body["paused"]), (200, &json!(true)));
+    assert!(blockhead.is_production_paused());
+    let (status, _) = admin("POST /admin/resume").await;
+    assert_eq!(status, 200);
+    assert!(!blockhead.is_production_paused());
+    let (status, _) = admin("GET /admin/pause").await;
+    assert_eq!(status, 405);
+
+    let (status, body) = admin("POST /admin/log_level?level=debug").await;
+    assert_eq!((status, &body["log_level"]), (200, &json!("DEBUG")));
+    assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    let (status, _) = admin("POST /admin/log_level?level=loud").await;
+    assert_eq!(status, 400);
+
+    let (status, body) = admin("GET /admin/mempool").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["transactions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["transactions"][0]["nonce"], 0);
+    let (status, body) = admin("GET /admin/db_stats").await;
+    assert_eq!(status, 200);
+    let tables = body["tables"].as_array().unwrap();
+    let block = tables
+        .iter()
+        .find(|table| table["name"] == "block")
+        .unwrap();
+    assert_eq!(block["rows"], 1);
+    assert!(block["index_bytes"]["block_number"].as_u64().unwrap() > 0);
+    let (status, _) = admin("POST /admin/prune").await;
+    assert_eq!(status, 200);
+    let (status, body) = admin("POST /admin/compact").await;
+    assert_eq!(status, 200);
+    assert!(body["size_after"].as_u64().unwrap() > 0);
+    let (status, _) = admin("POST /admin/shutdown").await;
+    assert_eq!(status, 200);
+    blockhead.shutdown_requested().await;
+}
@@ -0,0 +1,331 @@
+//! State history: the state of past blocks, kept in full by archive nodes and at checkpoints by
+//! pruned ones.
+//!
+//! An archive node records the accounts, storage slots and token slots each block changed, by
+//! height, so a query at any canonical block reads the versions current there directly, on a read
+//! connection of its own.
+//!
+//! A pruned node keeps the latest state and reaches older state with
+//! [`Blockhead::with_state_at`], which holds off block production while it runs. Every
+//! [`PruningConfig::checkpoint_interval`] blocks, once they are finalized, it merges the state
+//! diffs since the previous checkpoint into the same history and deletes the undo records of the
+//! blocks it covers, which can no longer be reorganized away. The state after any block is then
+//! at most that many blocks from a checkpoint, and is reached by restoring the checkpoint and
+//! applying the state diffs since, or by rewinding from the head with undo records if that is
+//! closer.
+//!
+//! The archive is built from the stored state diffs when a node first opens in archive mode. A
+//! node switched back to pruned mode stops recording every block but keeps what it has, and
+//! catches up from the state diffs of the blocks it missed if it is switched to archive mode
+//! again.
+use crate::block::Block;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::state::{self, StateDiff};
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+
+/// How a node keeps the state of past blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateHistory {
+    /// Keep the latest state, and rewind to reach older state.
+    #[default]
+    Pruned,
+    /// Also keep every version of the state.
+    Archive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PruningConfig {
+    /// How many blocks apart pruned nodes checkpoint the state, in place of the undo records of
+    /// the finalized blocks in between. Zero keeps every undo record.
+    pub checkpoint_interval: u64,
+}
+
+impl Default for PruningConfig {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval: 1024,
+        }
+    }
+}
+
+impl Blockhead {
+    /// Record the state checkpoints due at or below the newly finalized block `finalized`,
+    /// deleting the undo records they replace. Does nothing on archive nodes, which keep them.
+    pub(crate) fn checkpoint_state(&self, finalized: u64) -> Result<()> {
+        let interval = self.config.pruning.checkpoint_interval;
+        if self.config.history != StateHistory::Pruned || interval == 0 {
+            return Ok(());
+        }
+        let mut last = db::read_last_state_checkpoint(&self.connection)?;
+        loop {
+            let next = last.map_or(0, |last| last + interval);
+            if next > finalized {
+                return Ok(());
+            }
+            let mut merged = StateDiff::default();
+            for number in last.map_or(0, |last| last + 1)..=next {
+                let header = db::read_canonical_header(&self.connection, number)?
+                    .ok_or_else(|| Error::new(format!("missing canonical block {number}")))?;
+                let diff =
+                    db::read_state_diff(&self.connection, header.hash)?.ok_or_else(|| {
+                        Error::new(format!("block {} has no state diff", header.hash))
+                    })?;
+                merged = merged.then(&diff);
+            }
+            let header = db::read_canonical_header(&self.connection, next)?
+                .ok_or_else(|| Error::new(format!("missing canonical block {next}")))?;
+            db::write_state_history(&self.connection, &header, &merged)?;
+            db::write_state_checkpoint(&self.connection, &header)?;
+            db::delete_undo_through(&self.connection, next)?;
+            log::debug!("checkpointed the state at block {next}");
+            last = Some(next);
+        }
+    }
+
+    /// The closest height at or below canonical block `number` whose state the history holds in
+    /// full: `number` itself if archived, or else the nearest archived block or state checkpoint.
+    pub(crate) fn nearest_recorded_state(&self, number: u64) -> Result<Option<u64>> {
+        let archived = db::read_archived_through(&self.connection)?.map(|a| a.min(number));
+        let checkpoint = db::read_state_checkpoint_at_or_below(&self.connection, number)?;
+        Ok(archived.max(checkpoint))
+    }
+
+    /// Overwrite the state with that after canonical block `number`, restored from the state
+    /// recorded at block `recorded` and brought forward with the state diffs since, dropping
+    /// what the blocks above `number` wrote. Only for scratch transactions.
+    pub(crate) fn restore_state(&self, recorded: u64, number: u64) -> Result<()> {
+        db::delete_effects_above(&self.connection, number)?;
+        db::restore_state_history(&self.connection, recorded)?;
+        for number in recorded + 1..=number {
+            let header = db::read_canonical_header(&self.connection, number)?
+                .ok_or_else(|| Error::new(format!("missing canonical block {number}")))?;
+            let diff = db::read_state_diff(&self.connection, header.hash)?
+                .ok_or_else(|| Error::new(format!("block {} has no state diff", header.hash)))?;
+            state::apply_diff(&self.connection, &diff)?;
+        }
+        Ok(())
+    }
+
+    /// Record the state history of the canonical blocks the archive is missing, from their state
+    /// diffs.
+    pub(crate) fn sync_archive(&self) -> Result<()> {
+        self.ensure_writable("build the state archive")?;
+        let _guard = self.write_lock.lock().unwrap();
+        let head = self.head()?;
+        let start = match db::read_archived_through(&self.connection)? {
+            Some(number) if number >= head.number => return Ok(()),
+            Some(number) => number + 1,
+            None => 0,
+        };
+        db::transaction(&self.connection, || {
+            for number in start..=head.number {
+                let header = db::read_canonical_header(&self.connection, number)?
+                    .ok_or_else(|| Error::new(format!("missing canonical block {number}")))?;
+                let diff =
+                    db::read_state_diff(&self.connection, header.hash)?.ok_or_else(|| {
+                        Error::new(format!("block {} has no state diff", header.hash))
+                    })?;
+                db::write_state_history(&self.connection, &header, &diff)?;
+            }
+            db::write_archived_through(&self.connection, head.number)
+        })?;
+        log::info!("archived the state of blocks {start} to {}", head.number);
+        Ok(())
+    }
+
+    /// Run the read-only query `f` against the state after canonical `block` as the archive
+    /// recorded it.
+    pub(crate) fn with_archived_state<T>(
+        &self,
+        block: &Block,
+        f: impl FnOnce(&sqlite::Connection) -> Result<T>,
+    ) -> Result<T> {
+        let connection = self.reader();
+        // Views shadow the tables for every statement on a connection, so the writer must be kept
+        // from producing blocks through them.
+        let _guard = (!connection.is_pooled()).then(|| self.write_lock.lock().unwrap());
+        db::scratch(&connection, || {
+            let archived = db::read_archived_through(&connection)?;
+            if archived.is_none_or(|number| number < block.number) {
+                return Err(Error::new(format!(
+                    "the state of block {} is not archived yet",
+                    block.number
+                )));
+            }
+            db::create_history_views(&connection, block.number)?;
+            let result = f(&connection);
+            db::drop_history_views(&connection)?;
+            result
+        })
+    }
+}
+
+/// The balance of `address` after canonical block `number`, read from the archive.
+#[cfg(test)]
+fn archived_balance(
+    blockhead: &Blockhead,
+    number: u64,
+    address: crate::address::Address,
+) -> Result<Option<u64>> {
+    let block = blockhead.canonical_block(crate::block::BlockId::Number(number))?;
+    blockhead.with_archived_state(&block, |connection| {
+        Ok(db::read_account(connection, address)?.map(|account| account.balance))
+    })
+}
+
+#[tokio::test]
+async fn test_archive() {
+    use crate::address::Address;
+    use crate::block::BlockId;
+    use crate::genesis::ChainConfig;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit::{self, TestChain};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let config = ChainConfig {
+        history: StateHistory::Archive,
+        ..testkit::config()
+    };
+    let chain = TestChain::with_config(config);
+    let recipient = Address([8; 32]);
+    for value in [5, 6] {
+        chain.transfer(chain.validator, recipient, value).await;
+        chain.produce();
+    }
+    let connection = &chain.blockhead.connection;
+    assert_eq!(db::read_archived_through(connection).unwrap(), Some(2));
+    assert_eq!(
+        archived_balance(&chain.blockhead, 0, recipient).unwrap(),
+        None
+    );
+    assert_eq!(
+        archived_balance(&chain.blockhead, 1, recipient).unwrap(),
+        Some(5)
+    );
+    assert_eq!(
+        archived_balance(&chain.blockhead, 2, recipient).unwrap(),
+        Some(11)
+    );
+    let balance = chain
+        .blockhead
+        .get_balance_at(recipient, BlockId::Number(1));
+    assert_eq!(balance.await.unwrap(), 5);
+    // The archived accounts of a block hash to its state root.
+    let block1 = chain.blockhead.canonical_block(BlockId::Number(1)).unwrap();
+    let proof = chain.blockhead.get_proof(recipient, BlockId::Number(1));
+    assert!(proof
+        .await
+        .unwrap()
+        .unwrap()
+        .verify(block1.state_root, algorithm));
+    // The views are gone once the query is done.
+    chain.assert_balance(recipient, 11);
+
+    // A reorg drops the history of the blocks it reverts, and records that of the new branch.
+    let genesis = chain.blockhead.canonical_block(BlockId::Number(0)).unwrap();
+    chain.fork(&genesis, 3);
+    assert_eq!(db::read_archived_through(connection).unwrap(), Some(3));
+    assert_eq!(
+        archived_balance(&chain.blockhead, 2, recipient).unwrap(),
+        None
+    );
+
+    // A node that ran pruned for a while catches up from the state diffs.
+    let mut chain = TestChain::new();
+    chain.transfer(chain.validator, recipient, 5).await;
+    chain.produce();
+    chain.blockhead.config.history = StateHistory::Archive;
+    let error = archived_balance(&chain.blockhead, 1, recipient).unwrap_err();
+    assert!(error.message().contains("not archived"));
+    chain.blockhead.sync_archive().unwrap();
+    assert_eq!(
+        archived_balance(&chain.blockhead, 1, recipient).unwrap(),
+        Some(5)
+    );
+
+    // File-backed nodes query on read connections, alongside the writer.
+    let (mut blockhead, path) = crate::pool::file_chain("archive");
+    blockhead.config.history = StateHistory::Archive;
+    blockhead.sync_archive().unwrap();
+    blockhead.produce_block().unwrap();
+    let balance = blockhead.get_balance_at(testkit::validator(), BlockId::Number(0));
+    assert_eq!(balance.await.unwrap(), 1_000_000);
+    drop(blockhead);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_state_checkpoints() {
+    use crate::address::Address;
+    use crate::block::BlockId;
+    use crate::hash::HashAlgorithm;
+    use crate::state;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (mut blockhead, validator) = crate::chain::staked_chain(32);
+    blockhead.config.pruning.checkpoint_interval = 2;
+    let recipient = Address([8; 32]);
+    let transaction = |kind, to_address, value, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        value,
+        data: data.into(),
+        gas_limit: 21_512,
+        ..testkit::transaction(kind, validator, nonce)
+    };
+    let transfer = |value, nonce| {
+        transaction(
+            TransactionKind::Transfer,
+            Some(recipient),
+            value,
+            vec![],
+            nonce,
+        )
+    };
+    blockhead.send_transaction(transfer(5, 0)).await.unwrap();
+    let block1 = blockhead.produce_block().unwrap();
+    let block2 = blockhead.produce_block().unwrap();
+    assert_eq!(
+        db::read_last_state_checkpoint(&blockhead.connection).unwrap(),
+        None
+    );
+    let attestation = testkit::attestation(
+        block2.hash,
+        1,
+        blockhead.config.chain_id,
+        blockhead.config.hash,
+    );
+    blockhead.send_transaction(attestation).await.unwrap();
+    blockhead.produce_block().unwrap();
+    // Finalizing block 2 checkpoints genesis and block 2, and prunes their undo records.
+    assert_eq!(
+        db::read_last_state_checkpoint(&blockhead.connection).unwrap(),
+        Some(2)
+    );
+    assert!(state::revert_block(&blockhead.connection, block1.hash).is_err());
+    blockhead.send_transaction(transfer(6, 2)).await.unwrap();
+    for _ in 4..=6 {
+        blockhead.produce_block().unwrap();
+    }
+
+    // Block 1 can only be reached from a checkpoint, block 3 is closer to one than to the head,
+    // and blocks 4 and 5 are rewound to.
+    for (number, balance) in [(0, 0), (1, 5), (2, 5), (3, 5), (4, 11), (5, 11)] {
+        let at = blockhead.get_balance_at(recipient, BlockId::Number(number));
+        assert_eq!(at.await.unwrap(), balance, "at block {number}");
+    }
+    let proof = blockhead.get_proof(recipient, BlockId::Number(1));
+    assert!(proof
+        .await
+        .unwrap()
+        .unwrap()
+        .verify(block1.state_root, algorithm));
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 11);
+}
@@ -0,0 +1,434 @@
+//! A shell over a node that is already running: `blockhead attach [<endpoint>]`.
+//!
+//! `blockhead console` and commands such as `balance` open the database themselves, which
+//! contends with the node that holds it. `attach` instead sends each command to the running
+//! node's HTTP API, over its IPC socket or the network (see [`crate::http::Endpoint`]), failing
+//! over between endpoints if given several (see [`crate::remote`]). It reads
+//! one command per line, as the console does, but offers only what the API serves: queries go
+//! through `/graphql`, and sends take transactions signed elsewhere, such as by `blockhead
+//! multisig sign`.
+//!
+//! `blockhead send` goes through the same API, signing with whatever [`Signer`] holds the
+//! sender's key. See [`Attached::transfer`].
+use crate::address_book::AddressBook;
+use crate::client;
+use crate::error::{Error, Result};
+use crate::hash::{decode_hex32, Hash, HashAlgorithm};
+use crate::remote::Remote;
+use crate::signer::Signer;
+use crate::transaction::{Transaction, TransactionKind};
+use bytes::Bytes;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const HELP: &str = "\
+head                           the canonical head
+block <number|hash|latest>     a canonical block
+tx <hash>                      a transaction and its receipt
+balance <address>              an account's balance and nonce
+send <transaction>             send a signed transaction, hex-encoded
+help | exit";
+
+/// How often [`Attached::wait_for_confirmations`] asks the node how deep a transaction is.
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The fields of a block that `head` and `block` show.
+const BLOCK_FIELDS: &str = "number hash parentHash proposer timestamp transactionCount";
+
+pub(crate) struct Attached {
+    remote: Remote,
+    book: AddressBook,
+}
+
+impl Attached {
+    pub(crate) fn new(remote: Remote, book: AddressBook) -> Self {
+        Self { remote, book }
+    }
+
+    /// Run the command on `line`, returning what to print, or `None` to end the session.
+    pub(crate) async fn execute(&self, line: &str) -> Result<Option<String>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["exit"] | ["quit"] => return Ok(None),
+            ["help"] => HELP.to_string(),
+            ["head"] | ["block", "latest"] => {
+                let data = self.query(&format!("{{head {{{BLOCK_FIELDS}}}}}")).await?;
+                describe_block(&data["head"])
+            }
+            ["block", id] => {
+                let argument = if id.starts_with("0x") {
+                    format!("hash: \"{}\"", parse_hash(id)?)
+                } else {
+                    format!("number: {}", id.parse::<u64>()?)
+                };
+                let query = format!("{{block({argument}) {{{BLOCK_FIELDS}}}}}");
+                match &self.query(&query).await?["block"] {
+                    Value::Null => format!("no block {id}"),
+                    block => describe_block(block),
+                }
+            }
+            ["tx", hash] => self.describe_transaction(parse_hash(hash)?).await?,
+            ["balance", address] => {
+                let address = self.book.resolve(address)?;
+                let query = format!("{{account(address: \"{address}\") {{balance nonce}}}}");
+                let account = &self.query(&query).await?["account"];
+                format!(
+                    "{address}: balance {}, nonce {}",
+                    account["balance"], account["nonce"]
+                )
+            }
+            ["send", transaction] => {
+                let body = json!({"raw": transaction}).to_string();
+                let sent = self
+                    .remote
+                    .call("POST", "/transactions", body.as_bytes())
+                    .await?;
+                text(&sent["hash"])
+            }
+            _ => return Err(Error::new(format!("unknown command {line:?}; try `help`"))),
+        };
+        Ok(Some(output))
+    }
+
+    /// Send `value` from `signer`'s account to `to`, an address or a name in the address book,
+    /// at its next nonce and `gas_price`, returning the transfer's hash.
+    pub(crate) async fn transfer(
+        &self,
+        signer: &dyn Signer,
+        to: &str,
+        value: u64,
+        gas_price: u64,
+    ) -> Result<Hash> {
+        let to = self.book.resolve(to)?;
+        let algorithm = self.hash_algorithm().await?;
+        let from = signer.address(algorithm)?;
+        let data = self
+            .query(&format!(
+                "{{chainId estimateGas account(address: \"{from}\") {{pendingNonce}}}}"
+            ))
+            .await?;
+        let number = |value: &Value| {
+            value
+                .as_u64()
+                .ok_or_else(|| Error::new(format!("expected a number, not {value}")))
+        };
+        let mut transaction = Transaction {
+            kind: TransactionKind::Transfer,
+            from_address: from,
+            to_address: Some(to),
+            value,
+            data: Bytes::new(),
+            gas_limit: number(&data["estimateGas"])?,
+            gas_price,
+            nonce: number(&data["account"]["pendingNonce"])?,
+            signatures: Vec::new(),
+        };
+        transaction.sign(signer, number(&data["chainId"])?, algorithm)?;
+        self.send(&transaction).await
+    }
+
+    /// Replace pending transaction `hash`, sent from `signer`'s account, with a no-op, returning
+    /// the replacement's hash. See [`client::cancellation`].
+    pub(crate) async fn cancel(&self, signer: &dyn Signer, hash: Hash) -> Result<Hash> {
+        let query = format!(
+            "{{chainId hashAlgorithm transaction(hash: \"{hash}\") {{raw block {{number}}}}}}"
+        );
+        let data = self.query(&query).await?;
+        let transaction = &data["transaction"];
+        if transaction.is_null() {
+            return Err(Error::new(format!("transaction {hash} is not known")));
+        }
+        if !transaction["block"].is_null() {
+            return Err(Error::new(format!(
+                "cannot cancel transaction {hash}: it is included already"
+            )));
+        }
+        let raw = hex::decode(text(&transaction["raw"]).trim_start_matches("0x"))
+            .map_err(|error| Error::new(format!("bad transaction hex: {error}")))?;
+        let stuck = Transaction::decode(&raw)?;
+        let chain_id = (data["chainId"].as_u64())
+            .ok_or_else(|| Error::new(format!("bad chain ID {}", data["chainId"])))?;
+        let algorithm = serde_json::from_value(data["hashAlgorithm"].clone())?;
+        let mut cancellation = client::cancellation(hash, &stuck)?;
+        cancellation.sign(signer, chain_id, algorithm)?;
+        self.send(&cancellation).await
+    }
+
+    /// The hash function of the node's chain, which addresses and signatures for it are derived
+    /// with.
+    pub(crate) async fn hash_algorithm(&self) -> Result<HashAlgorithm> {
+        let data = self.query("{hashAlgorithm}").await?;
+        Ok(serde_json::from_value(data["hashAlgorithm"].clone())?)
+    }
+
+    /// The gas price the node suggests for a transaction to be included in the usual time. See
+    /// [`crate::FeeEstimate`].
+    pub(crate) async fn normal_gas_price(&self) -> Result<u64> {
+        let estimate = self.remote.call("GET", "/fees", b"").await?;
+        (estimate["normal"].as_u64())
+            .ok_or_else(|| Error::new(format!("bad fee estimate {estimate}")))
+    }
+
+    /// Wait until transaction `hash` has at least `confirmations` confirmations, then return
+    /// whether it succeeded. The depth is asked afresh each time, so a reorg that moves the
+    /// transaction off the canonical chain restarts the count. Fails if the node no longer knows
+    /// the transaction, as when it was dropped or replaced.
+    pub(crate) async fn wait_for_confirmations(
+        &self,
+        hash: Hash,
+        confirmations: u64,
+    ) -> Result<bool> {
+        let query =
+            format!("{{transaction(hash: \"{hash}\") {{confirmations receipt {{status}}}}}}");
+        loop {
+            let data = self.query(&query).await?;
+            let transaction = &data["transaction"];
+            if transaction.is_null() {
+                return Err(Error::new(format!(
+                    "transaction {hash} is not known to the node"
+                )));
+            }
+            let depth = transaction["confirmations"].as_u64().unwrap_or_default();
+            // The receipt may trail the block on nodes that index receipts apart.
+            let status = transaction["receipt"]["status"].as_bool();
+            if let (true, Some(status)) = (depth >= confirmations.max(1), status) {
+                return Ok(status);
+            }
+            tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Send signed `transaction`, returning its hash.
+    async fn send(&self, transaction: &Transaction) -> Result<Hash> {
+        let raw = format!("0x{}", hex::encode(transaction.encode()));
+        let body = json!({"raw": raw}).to_string();
+        let sent = self
+            .remote
+            .call("POST", "/transactions", body.as_bytes())
+            .await?;
+        parse_hash(&text(&sent["hash"]))
+    }
+
+    /// The `data` of GraphQL query `query`, or its first error.
+    async fn query(&self, query: &str) -> Result<Value> {
+        let body = json!({"query": query}).to_string();
+        let mut response = self
+            .remote
+            .call("POST", "/graphql", body.as_bytes())
+            .await?;
+        if let Some(message) = response["errors"][0]["message"].as_str() {
+            return Err(Error::new(message));
+        }
+        Ok(response["data"].take())
+    }
+
+    async fn describe_transaction(&self, hash: Hash) -> Result<String> {
+        let query = format!(
+            "{{transaction(hash: \"{hash}\") {{kind from to value nonce gasLimit gasPrice \
+             block {{number}} receipt {{status gasUsed contractAddress}}}}}}"
+        );
+        let data = self.query(&query).await?;
+        let transaction = &data["transaction"];
+        if transaction.is_null() {
+            return Ok(format!("no transaction {hash}"));
+        }
+        let to = match &transaction["to"] {
+            Value::Null => "a new contract".to_string(),
+            to => text(to),
+        };
+        let receipt = &transaction["receipt"];
+        let status = match &transaction["block"]["number"] {
+            Value::Null => "pending".to_string(),
+            number => format!(
+                "included in block {number}: {}, gas used {}{}",
+                if receipt["status"] == true {
+                    "success"
+                } else {
+                    "reverted"
+                },
+                receipt["gasUsed"],
+                receipt["contractAddress"]
+                    .as_str()
+                    .map(|address| format!(", created {address}"))
+                    .unwrap_or_default()
+            ),
+        };
+        Ok(format!(
+            "{hash}: {} of {} from {} to {to}, nonce {}, gas limit {} at {}\nstatus: {status}",
+            text(&transaction["kind"]),
+            transaction["value"],
+            text(&transaction["from"]),
+            transaction["nonce"],
+            transaction["gasLimit"],
+            transaction["gasPrice"],
+        ))
+    }
+}
+
+/// A block as the console describes it, from its [`BLOCK_FIELDS`].
+fn describe_block(block: &Value) -> String {
+    format!(
+        "block {} {}: parent {}, proposer {}, timestamp {}, {} transactions",
+        block["number"],
+        text(&block["hash"]),
+        text(&block["parentHash"]),
+        text(&block["proposer"]),
+        block["timestamp"],
+        block["transactionCount"]
+    )
+}
+
+/// `value` without the quotes JSON puts around strings.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn parse_hash(s: &str) -> Result<Hash> {
+    Ok(Hash::from(decode_hex32(s)?))
+}
+
+/// Read commands from stdin and print their results until `exit` or the end of input.
+pub(crate) async fn run(attached: Attached) -> Result<()> {
+    let head = attached.query("{head {number}}").await?;
+    println!(
+        "attached to {} at block {}; try `help`",
+        attached.remote, head["head"]["number"]
+    );
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        match attached.execute(&line).await {
+            Ok(Some(output)) if output.is_empty() => {}
+            Ok(Some(output)) => println!("{output}"),
+            Ok(None) => return Ok(()),
+            Err(error) => eprintln!("error: {error}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_attach() {
+    use crate::address::Address;
+    use crate::http::Endpoint;
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use std::sync::Arc;
+
+    let algorithm = HashAlgorithm::default();
+    let chain = TestChain::new();
+    let validator = chain.validator;
+    let blockhead = Arc::new(chain.blockhead);
+    let address = crate::http::serve_locally(blockhead.clone()).await;
+    let mut book = AddressBook::default();
+    book.add("bob", Address([8; 32])).unwrap();
+    let attached = Attached::new(
+        Remote::new(vec![Endpoint::Tcp(address.to_string())]).unwrap(),
+        book,
+    );
+    let execute = |line: String| {
+        let attached = &attached;
+        async move { attached.execute(&line).await.unwrap().unwrap() }
+    };
+
+    assert!(execute("head".into()).await.starts_with("block 0 "));
+    let transfer = testkit::transfer(validator, Address([8; 32]), 5, 0);
+    let raw = format!("0x{}", hex::encode(transfer.encode()));
+    let hash = execute(format!("send {raw}")).await;
+    assert_eq!(hash, transfer.compute_hash(algorithm).to_string());
+    assert!(attached.execute(&format!("send {raw}")).await.is_err());
+    assert!(execute(format!("tx {hash}"))
+        .await
+        .ends_with("status: pending"));
+
+    blockhead.produce_block().unwrap();
+    let tx = execute(format!("tx {hash}")).await;
+    assert!(tx.starts_with(&format!("{hash}: Transfer of 5 from {validator}")));
+    assert!(tx.ends_with("status: included in block 1: success, gas used 21000"));
+    assert_eq!(
+        execute("balance bob".into()).await,
+        format!("{}: balance 5, nonce 0", Address([8; 32]))
+    );
+    let block = execute("block 1".into()).await;
+    assert!(block.starts_with("block 1 "));
+    assert!(block.ends_with("1 transactions"));
+    let by_hash = execute(format!("block {}", blockhead.head().unwrap().hash)).await;
+    assert_eq!(by_hash, block);
+    assert_eq!(execute("block 9".into()).await, "no block 9");
+    assert!(attached.execute("bogus").await.is_err());
+    assert_eq!(attached.execute("exit").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_transfer() {
+    use crate::address::Address;
+    use crate::http::Endpoint;
+    use crate::spec;
+    use crate::status::TransactionStatus;
+    use crate::testkit;
+    use crate::Blockchain;
+    use std::sync::Arc;
+
+    let algorithm = HashAlgorithm::default();
+    // The test chain, like any but a dev chain, takes only signed transactions.
+    let blockhead = spec::open_dev(":memory:", spec::load("test").unwrap()).unwrap();
+    let blockhead = Arc::new(blockhead);
+    let address = crate::http::serve_locally(blockhead.clone()).await;
+    let account = spec::dev_wallet().account(0).unwrap();
+    let (sender, recipient) = (account.address(algorithm), Address([8; 32]));
+    let mut book = AddressBook::default();
+    book.add("bob", recipient).unwrap();
+    let attached = Attached::new(
+        Remote::new(vec![Endpoint::Tcp(address.to_string())]).unwrap(),
+        book,
+    );
+    let unsigned = testkit::transfer(sender, recipient, 5, 0);
+    let raw = format!("0x{}", hex::encode(unsigned.encode()));
+    assert!(attached.execute(&format!("send {raw}")).await.is_err());
+
+    let first = attached
+        .transfer(&account, &recipient.to_string(), 5, 1)
+        .await
+        .unwrap();
+    let second = attached.transfer(&account, "bob", 6, 1).await.unwrap();
+    assert!(attached.transfer(&account, "carol", 7, 1).await.is_err());
+    blockhead.produce_block().unwrap();
+    for (hash, nonce) in [(first, 0), (second, 1)] {
+        let transaction = blockhead.get_transaction(hash).await.unwrap().unwrap();
+        assert_eq!(transaction.nonce, nonce);
+    }
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 11);
+    assert_eq!(attached.normal_gas_price().await.unwrap(), 1);
+
+    assert!(attached.wait_for_confirmations(first, 1).await.unwrap());
+    let deeper = attached.wait_for_confirmations(first, 2);
+    assert!(tokio::time::timeout(Duration::from_millis(100), deeper)
+        .await
+        .is_err());
+    blockhead.produce_block().unwrap();
+    assert!(attached.wait_for_confirmations(first, 2).await.unwrap());
+    let unknown = Hash::digest_of("unknown");
+    assert!(attached.wait_for_confirmations(unknown, 1).await.is_err());
+
+    // A pending transfer is cancelled by a self-send in its place; an included one cannot be.
+    assert!(attached.cancel(&account, first).await.is_err());
+    assert!(attached.cancel(&account, unknown).await.is_err());
+    let stuck = attached.transfer(&account, "bob", 7, 1).await.unwrap();
+    let replacement = attached.cancel(&account, stuck).await.unwrap();
+    assert_eq!(
+        blockhead.get_transaction_status(stuck).await.unwrap(),
+        TransactionStatus::Replaced { by: replacement }
+    );
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 11);
+    assert_eq!(blockhead.get_nonce(sender).await.unwrap(), 3);
+}
@@ -0,0 +1,78 @@
+//! Balances of many accounts at once, served at `POST /balances` by [`crate::http`], for
+//! exchanges and indexers that track thousands of accounts and would otherwise ask for each.
+//!
+//! The body is `{"addresses": ["0x...", ...]}` and the answer `{"balances": [...]}`, a balance
+//! for each address in the order given, read in one query. See [`Blockchain::get_balances`].
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::hash::decode_hex32;
+use crate::http::Response;
+use crate::{Blockchain, Blockhead};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, body: &[u8]) -> Response {
+    let addresses = match parse(body) {
+        Ok(addresses) => addresses,
+        Err(error) => return Response::from_error(400, &error),
+    };
+    match blockhead.get_balances(&addresses).await {
+        Ok(balances) => Response::json(200, &json!({"balances": balances})),
+        Err(error) => Response::from_error(500, &error),
+    }
+}
+
+fn parse(body: &[u8]) -> Result<Vec<Address>> {
+    let body: Value = serde_json::from_slice(body)?;
+    let addresses = body["addresses"]
+        .as_array()
+        .ok_or_else(|| Error::new("body needs an \"addresses\" array"))?;
+    addresses
+        .iter()
+        .map(|address| {
+            let address = address
+                .as_str()
+                .ok_or_else(|| Error::new(format!("{address} is not an address")))?;
+            Ok(Address::from(decode_hex32(address)?))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_balances() {
+    use crate::http::{send, serve_locally};
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let (alice, bob) = (Address([8; 32]), Address([9; 32]));
+    chain.transfer(chain.validator, alice, 5).await;
+    chain.transfer(chain.validator, bob, 7).await;
+    chain.produce();
+    let validator = chain.validator;
+    let blockhead = Arc::new(chain.blockhead);
+    let addresses = [bob, Address([10; 32]), alice, bob, validator];
+    let balances = blockhead.get_balances(&addresses).await.unwrap();
+    let mut expected = Vec::new();
+    for address in addresses {
+        expected.push(blockhead.get_balance(address).await.unwrap());
+    }
+    assert_eq!(balances, expected);
+    assert_eq!(&balances[..4], [7, 0, 5, 7]);
+    assert!(blockhead.get_balances(&[]).await.unwrap().is_empty());
+
+    let address = serve_locally(blockhead).await;
+    let request = |body: String| {
+        format!(
+            "POST /balances HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    };
+    let body = json!({"addresses": [alice.to_string(), bob.to_string()]});
+    let (status, body) = send(address, &request(body.to_string())).await;
+    assert_eq!((status, body), (200, json!({"balances": [5, 7]})));
+    let (status, body) = send(address, &request(r#"{"addresses": ["0x12"]}"#.into())).await;
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap().contains("0x12"), "{body}");
+    let (status, _) = send(address, &request("{}".into())).await;
+    assert_eq!(status, 400);
+}
@@ -0,0 +1,548 @@
+//! Benchmarks of the storage-bound paths: block import, block and balance queries, log queries,
+//! reads during block production and mempool admission.
+//!
+//! Each benchmark is a function timed by a [`Bencher`] against file-backed databases, like the
+//! node uses, and run by the targets in `benches/`. Built for tests and with the `bench` feature:
+//! run them with `cargo bench --features bench`, adding `-- <filter>` to run only those whose
+//! names contain the filter. Each prints one `bench <name>: <ns> ns/op` line so runs before and
+//! after a change can be compared.
+//!
+//! A benchmark binary that installs [`CountingAllocator`] as its global allocator also gets the
+//! allocations of importing a block, as `bench <name>: <n> allocs/op`. SQLite allocates through
+//! its own allocator, so only Rust's allocations count.
+use crate::address::Address;
+use crate::block::{Block, BlockId};
+use crate::gas::GasConfig;
+use crate::hash::HashAlgorithm;
+use crate::mempool::Mempool;
+use crate::pool::file_chain;
+use crate::testkit;
+use crate::transaction::Transaction;
+use crate::{Blockchain, Blockhead};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Blocks in the chains the benchmarks build.
+const BLOCKS: u64 = 200;
+/// Transfers in each of those blocks.
+const TRANSFERS_PER_BLOCK: u64 = 10;
+/// Bytes of data in each transfer of the chain [`block_import_allocations`] imports.
+const DATA_LEN: usize = 1024;
+/// Logs in each of the blocks of the chain [`get_logs`] queries, a million in all.
+const LOGS_PER_BLOCK: u64 = 5_000;
+/// Blocks in the chain [`get_logs_by_address`] queries, each with a few logs, as most blocks
+/// have.
+const SPARSE_BLOCKS: u64 = 5_000;
+/// Timed runs of each benchmark, after one untimed warm-up run.
+const SAMPLES: usize = 10;
+
+/// The system allocator, counting allocations and the bytes they ask for.
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The time one operation of a benchmark took, over its samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+    pub median: Duration,
+    pub min: Duration,
+}
+
+/// Runs the benchmarks whose names match its filter, timing each over a warm-up run and
+/// [`SAMPLES`] timed ones.
+pub struct Bencher {
+    filter: Option<String>,
+    samples: usize,
+}
+
+impl Bencher {
+    /// A bencher with the filter `cargo bench -- <filter>` passes, ignoring flags such as the
+    /// `--bench` cargo adds itself.
+    pub fn from_args() -> Self {
+        Self {
+            filter: std::env::args().skip(1).find(|arg| !arg.starts_with('-')),
+            samples: SAMPLES,
+        }
+    }
+
+    fn wants(&self, name: &str) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| name.contains(filter.as_str()))
+    }
+
+    /// Time `routine`, which performs `operations` operations per run. `None` if the filter
+    /// leaves the benchmark out.
+    pub fn iter(
+        &self,
+        name: &str,
+        operations: u64,
+        mut routine: impl FnMut(),
+    ) -> Option<Measurement> {
+        self.iter_batched(name, operations, || (), |_| routine())
+    }
+
+    /// Like [`Self::iter`], but handing each run a fresh input from `setup`, which is not timed
+    /// and neither is dropping the input afterwards.
+    pub fn iter_batched<S>(
+        &self,
+        name: &str,
+        operations: u64,
+        mut setup: impl FnMut() -> S,
+        mut routine: impl FnMut(&mut S),
+    ) -> Option<Measurement> {
+        if !self.wants(name) {
+            return None;
+        }
+        routine(&mut setup());
+        let mut times = Vec::with_capacity(self.samples);
+        for _ in 0..self.samples {
+            let mut input = setup();
+            let start = Instant::now();
+            routine(&mut input);
+            times.push(start.elapsed() / operations as u32);
+            drop(input);
+        }
+        times.sort();
+        let measurement = Measurement {
+            median: times[times.len() / 2],
+            min: times[0],
+        };
+        println!(
+            "bench {name}: {} ns/op (min {}, {} samples of {operations} ops)",
+            measurement.median.as_nanos(),
+            measurement.min.as_nanos(),
+            self.samples
+        );
+        Some(measurement)
+    }
+
+    /// Count the heap allocations of one run of `routine`, which performs `operations`
+    /// operations, on an input from `setup`. They read zero unless the binary installs
+    /// [`CountingAllocator`].
+    pub fn allocations<S>(
+        &self,
+        name: &str,
+        operations: u64,
+        setup: impl FnOnce() -> S,
+        routine: impl FnOnce(&mut S),
+    ) -> Option<u64> {
+        if !self.wants(name) {
+            return None;
+        }
+        let mut input = setup();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        routine(&mut input);
+        let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / operations;
+        let bytes = (ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes) / operations;
+        println!("bench {name}: {allocations} allocs/op, {bytes} bytes/op ({operations} ops)");
+        Some(allocations)
+    }
+}
+
+/// A node on a database file of its own, removed when it is dropped.
+struct FileChain {
+    blockhead: Blockhead,
+    // Dropped after the node, once the database is closed.
+    _path: RemoveOnDrop,
+}
+
+impl FileChain {
+    fn new(name: &str) -> Self {
+        let (blockhead, path) = file_chain(name);
+        Self {
+            blockhead,
+            _path: RemoveOnDrop(path),
+        }
+    }
+}
+
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", self.0.display()));
+        }
+    }
+}
+
+fn transfer(nonce: u64, to: Address, data_len: usize) -> Transaction {
+    Transaction {
+        data: vec![0xab; data_len].into(),
+        gas_limit: GasConfig::default().intrinsic_gas(data_len),
+        ..testkit::transfer(testkit::validator(), to, 1, nonce)
+    }
+}
+
+/// Produce [`BLOCKS`] blocks of transfers to distinct recipients, each with `data_len` bytes of
+/// data, on the chain called `name`.
+fn produce_chain(name: &str, data_len: usize) -> (FileChain, Vec<Block>) {
+    let chain = FileChain::new(name);
+    let blockhead = &chain.blockhead;
+    let mut blocks = Vec::new();
+    for number in 0..BLOCKS {
+        for i in 0..TRANSFERS_PER_BLOCK {
+            let nonce = number * TRANSFERS_PER_BLOCK + i;
+            let recipient = Address::reserved((nonce % 200) as u8 + 16);
+            let transaction = transfer(nonce, recipient, data_len);
+            blockhead
+                .mempool
+                .lock()
+                .unwrap()
+                .insert(transaction, blockhead.now_nanos())
+                .unwrap();
+        }
+        blocks.push(blockhead.produce_block().unwrap());
+    }
+    (chain, blocks)
+}
+
+fn import_all(chain: &mut FileChain, blocks: &[Block]) {
+    for block in blocks {
+        chain.blockhead.import_block(block).unwrap();
+    }
+    let head = chain.blockhead.head().unwrap();
+    assert_eq!(head.hash, blocks.last().unwrap().hash);
+}
+
+pub fn block_import(bencher: &Bencher) {
+    if !bencher.wants("block_import") {
+        return;
+    }
+    let (_source, blocks) = produce_chain("bench-source", 0);
+    bencher.iter_batched(
+        "block_import",
+        BLOCKS,
+        || FileChain::new("bench-import"),
+        |chain| import_all(chain, &blocks),
+    );
+}
+
+pub fn block_import_allocations(bencher: &Bencher) {
+    if !bencher.wants("block_import_allocations") {
+        return;
+    }
+    let (_source, blocks) = produce_chain("bench-allocations-source", DATA_LEN);
+    bencher.allocations(
+        "block_import_allocations",
+        BLOCKS,
+        || FileChain::new("bench-allocations"),
+        |chain| import_all(chain, &blocks),
+    );
+}
+
+pub fn queries(bencher: &Bencher) {
+    if !bencher.wants("get_block_by_number") && !bencher.wants("get_balance") {
+        return;
+    }
+    let (chain, _) = produce_chain("bench-queries", 0);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let rounds = 2_000;
+    bencher.iter("get_block_by_number", rounds, || {
+        for round in 0..rounds {
+            let number = round % (BLOCKS + 1);
+            let block = runtime.block_on(chain.blockhead.get_block(BlockId::Number(number)));
+            assert!(block.unwrap().is_some());
+        }
+    });
+    bencher.iter("get_balance", rounds, || {
+        for round in 0..rounds {
+            let address = Address::reserved((round % 200) as u8 + 16);
+            runtime
+                .block_on(chain.blockhead.get_balance(address))
+                .unwrap();
+        }
+    });
+}
+
+pub fn get_logs(bencher: &Bencher) {
+    use crate::db;
+    use crate::hash::Hash;
+    use crate::logs::LogFilter;
+    use crate::{Log, TransactionReceipt};
+
+    if !bencher.wants("get_logs_by_topic") && !bencher.wants("get_logs_by_scan") {
+        return;
+    }
+    // Every log has one of four event topics, then a topic of its own but for one in 10,000,
+    // which share the sought one.
+    let chain = FileChain::new("bench-logs");
+    let blockhead = &chain.blockhead;
+    let event = |i: u64| Hash::digest_of(&format!("event {}", i % 4));
+    let sought = Hash::digest_of("sought");
+    for _ in 0..BLOCKS {
+        let block = blockhead.produce_block().unwrap();
+        let logs = (0..LOGS_PER_BLOCK)
+            .map(|i| {
+                let i = block.number * LOGS_PER_BLOCK + i;
+                let own = match i % 10_000 {
+                    0 => sought,
+                    _ => Hash::digest_of(&i.to_string()),
+                };
+                Log {
+                    address: Address::reserved((i % 200) as u8 + 16),
+                    topics: vec![event(i), own],
+                    data: Vec::new(),
+                }
+            })
+            .collect();
+        let receipt = TransactionReceipt {
+            transaction_hash: block.hash,
+            block_hash: block.hash,
+            status: true,
+            gas_used: 0,
+            contract_address: None,
+            return_data: Vec::new(),
+            logs,
+        };
+        db::transaction(&blockhead.connection, || {
+            db::write_logs(&blockhead.connection, &block, &[receipt])
+        })
+        .unwrap();
+    }
+    let filter = LogFilter {
+        addresses: Vec::new(),
+        topics: vec![vec![event(0)], vec![sought]],
+    };
+    let expected = (BLOCKS * LOGS_PER_BLOCK).div_ceil(10_000) as usize;
+    let connection = blockhead.reader();
+
+    bencher.iter("get_logs_by_topic", 1, || {
+        let logs = db::read_logs(&connection, &filter, 0, BLOCKS).unwrap();
+        assert_eq!(logs.len(), expected);
+    });
+
+    // Reading every log of the range and matching each, as before the topic index.
+    bencher.iter("get_logs_by_scan", 1, || {
+        let mut logs = Vec::new();
+        for number in 0..=BLOCKS {
+            let header = db::read_canonical_header(&connection, number).unwrap();
+            let entries = db::read_block_logs(&connection, header.unwrap().hash).unwrap();
+            logs.extend(
+                entries
+                    .into_iter()
+                    .filter(|entry| filter.matches(&entry.log)),
+            );
+        }
+        assert_eq!(logs.len(), expected);
+    });
+}
+
+pub fn get_logs_by_address(bencher: &Bencher) {
+    use crate::db;
+    use crate::hash::Hash;
+    use crate::logs::LogFilter;
+    use crate::{Log, TransactionReceipt};
+
+    if !bencher.wants("get_logs_by_bloom") && !bencher.wants("get_logs_by_address_scan") {
+        return;
+    }
+    // Each block has ten logs from its own contract and one block in 1,000 another from the
+    // sought one.
+    let chain = FileChain::new("bench-logs-by-address");
+    let blockhead = &chain.blockhead;
+    let sought = Address::reserved(250);
+    for _ in 0..SPARSE_BLOCKS {
+        let block = blockhead.produce_block().unwrap();
+        let mut logs: Vec<Log> = (0..10)
+            .map(|i| Log {
+                address: Address(Hash::digest_of(&block.number.to_string()).0),
+                topics: vec![Hash::digest_of(&format!("event {i}"))],
+                data: Vec::new(),
+            })
+            .collect();
+        if block.number.is_multiple_of(1_000) {
+            logs.push(Log {
+                address: sought,
+                ..logs[0].clone()
+            });
+        }
+        let receipt = TransactionReceipt {
+            transaction_hash: block.hash,
+            block_hash: block.hash,
+            status: true,
+            gas_used: 0,
+            contract_address: None,
+            return_data: Vec::new(),
+            logs,
+        };
+        db::transaction(&blockhead.connection, || {
+            db::write_logs(&blockhead.connection, &block, &[receipt])
+        })
+        .unwrap();
+    }
+    let filter = LogFilter {
+        addresses: vec![sought],
+        topics: Vec::new(),
+    };
+    let expected = (SPARSE_BLOCKS / 1_000) as usize;
+    let connection = blockhead.reader();
+
+    bencher.iter("get_logs_by_bloom", 1, || {
+        let logs = db::read_logs(&connection, &filter, 0, SPARSE_BLOCKS).unwrap();
+        assert_eq!(logs.len(), expected);
+    });
+
+    // Reading every log of the range and matching each, as before the blooms.
+    bencher.iter("get_logs_by_address_scan", 1, || {
+        let mut logs = Vec::new();
+        for number in 0..=SPARSE_BLOCKS {
+            let header = db::read_canonical_header(&connection, number).unwrap();
+            let entries = db::read_block_logs(&connection, header.unwrap().hash).unwrap();
+            logs.extend(
+                entries
+                    .into_iter()
+                    .filter(|entry| filter.matches(&entry.log)),
+            );
+        }
+        assert_eq!(logs.len(), expected);
+    });
+}
+
+/// Block production while four threads read accounts, with the reads on the writer's
+/// connection and on the pool's (see [`crate::pool`]). Each also prints the reads per second the
+/// threads got through.
+pub fn mixed_workload(bencher: &Bencher) {
+    use crate::pool::ReaderPool;
+    use std::sync::atomic::AtomicBool;
+
+    for pooled in [false, true] {
+        let name = match pooled {
+            false => "mixed_workload_unpooled",
+            true => "mixed_workload_pooled",
+        };
+        let (reads, elapsed) = (AtomicU64::new(0), std::cell::Cell::new(Duration::ZERO));
+        let setup = || {
+            let mut chain = FileChain::new("bench-mixed");
+            if !pooled {
+                chain.blockhead.readers = ReaderPool::empty();
+            }
+            chain
+        };
+        let measurement = bencher.iter_batched(name, BLOCKS, setup, |chain| {
+            let blockhead = &chain.blockhead;
+            let done = AtomicBool::new(false);
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        while !done.load(Ordering::Relaxed) {
+                            crate::db::read_account(&blockhead.reader(), Address([8; 32])).unwrap();
+                            reads.fetch_add(1, Ordering::Relaxed);
+                        }
+                    });
+                }
+                let start = Instant::now();
+                for nonce in 0..BLOCKS {
+                    blockhead
+                        .mempool
+                        .lock()
+                        .unwrap()
+                        .insert(transfer(nonce, Address([8; 32]), 0), 0)
+                        .unwrap();
+                    blockhead.produce_block().unwrap();
+                }
+                elapsed.set(elapsed.get() + start.elapsed());
+                done.store(true, Ordering::Relaxed);
+            });
+        });
+        if measurement.is_some() {
+            let per_second = reads.load(Ordering::Relaxed) as f64 / elapsed.get().as_secs_f64();
+            println!("bench {name}: {per_second:.0} reads/s");
+        }
+    }
+}
+
+pub fn mempool_insert(bencher: &Bencher) {
+    let transactions = 5_000;
+    let mut config = testkit::config().mempool;
+    config.max_per_sender = transactions as usize;
+    bencher.iter_batched(
+        "mempool_insert",
+        transactions,
+        || Mempool::new(GasConfig::default(), &config, HashAlgorithm::default()),
+        |mempool| {
+            for nonce in 0..transactions {
+                mempool
+                    .insert(transfer(nonce, Address([8; 32]), 0), 0)
+                    .unwrap();
+            }
+        },
+    );
+}
+
+#[test]
+fn test_bencher() {
+    use std::cell::Cell;
+
+    let bencher = Bencher {
+        filter: Some("match".to_string()),
+        samples: 3,
+    };
+    let runs = Cell::new(0);
+    assert_eq!(bencher.iter("other", 1, || runs.set(runs.get() + 1)), None);
+    assert_eq!(runs.get(), 0);
+    let measurement = bencher
+        .iter("matching", 10, || runs.set(runs.get() + 1))
+        .unwrap();
+    // A warm-up run, then the samples.
+    assert_eq!(runs.get(), 4);
+    assert!(measurement.min <= measurement.median);
+
+    let setups = Cell::new(0);
+    bencher.iter_batched(
+        "matching",
+        1,
+        || setups.set(setups.get() + 1),
+        |_| runs.set(runs.get() + 1),
+    );
+    assert_eq!((setups.get(), runs.get()), (4, 8));
+
+    let allocations = bencher.allocations(
+        "matching",
+        2,
+        || (),
+        |_| {
+            std::hint::black_box(vec![0u8; 16]);
+            std::hint::black_box(vec![0u8; 16]);
+        },
+    );
+    assert!(allocations.unwrap() >= 1);
+}
+
+#[test]
+fn test_mempool_insert_bench() {
+    let bencher = Bencher {
+        filter: None,
+        samples: 1,
+    };
+    mempool_insert(&bencher);
+}
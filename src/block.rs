@@ -1,11 +1,437 @@
-use crate::hash::Hash;
+use crate::address::Address;
+use crate::encoding::{self, Reader};
+use crate::error::{Error, Result};
+use crate::hash::{Hash, HashAlgorithm, HashBuilder};
+use crate::signer::{Signature, Signer};
 use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
-pub(crate) struct Block {
+/// The fields of a block that identify it and commit to its contents, so that headers can be
+/// stored, queried and synced without the transactions they summarize.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
     pub hash: Hash,
     pub parent_hash: Hash,
     pub number: u64,
     pub timestamp: u64,
+    pub proposer: Address,
+    /// Commits to the body. See [`Body::transactions_root`].
+    pub transactions_root: Hash,
+    /// Commits to the accounts after the block. See [`crate::proof`].
+    pub state_root: Hash,
+    /// The most gas the block's transactions may use together. See
+    /// [`crate::gas::GasConfig::next_block_gas_limit`].
+    pub gas_limit: u64,
+    /// The proposer's signature over [`Header::signing_hash`], which the hash leaves out. Only
+    /// the genesis block, and blocks stored before blocks were signed, have none.
+    pub signature: Option<Signature>,
+}
+
+/// The length of [`Header::encode_signed`] for a signed header.
+pub(crate) const SIGNED_HEADER_LEN: usize = 32 + 8 + 8 + 32 + 32 + 32 + 8 + 1 + 65;
+
+#[derive(Debug, Clone, Default)]
+pub struct Body {
     pub transactions: Vec<(Hash, Transaction)>,
 }
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub header: Header,
+    pub body: Body,
+}
+
+impl std::ops::Deref for Block {
+    type Target = Header;
+
+    fn deref(&self) -> &Header {
+        &self.header
+    }
+}
+
+/// A reference to a block, accepted by every block-scoped query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockId {
+    /// The canonical head.
+    #[default]
+    Latest,
+    /// The highest finalized checkpoint.
+    Finalized,
+    /// The block that would be produced next from the mempool.
+    Pending,
+    /// The canonical block at a height.
+    Number(u64),
+    /// A block by hash. State queries only accept canonical blocks.
+    Hash(Hash),
+}
+
+impl std::fmt::Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockId::Latest => write!(f, "latest"),
+            BlockId::Finalized => write!(f, "finalized"),
+            BlockId::Pending => write!(f, "pending"),
+            BlockId::Number(number) => write!(f, "#{number}"),
+            BlockId::Hash(hash) => write!(f, "{hash}"),
+        }
+    }
+}
+
+impl Header {
+    /// The canonical encoding of every field but the hash. See [`crate::encoding`].
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.parent_hash.0);
+        out.extend_from_slice(&self.number.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.proposer.0);
+        out.extend_from_slice(&self.transactions_root.0);
+        out.extend_from_slice(&self.state_root.0);
+        out.extend_from_slice(&self.gas_limit.to_be_bytes());
+        out
+    }
+
+    /// The encoding of every field but the hash: [`Header::encode`], then whether the header is
+    /// signed and the signature.
+    pub(crate) fn encode_signed(&self) -> Vec<u8> {
+        let mut out = self.encode();
+        match self.signature {
+            Some(signature) => {
+                out.push(1);
+                out.extend_from_slice(&signature.0);
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Read a header encoded by [`Header::encode_signed`], computing its hash with `algorithm`.
+    pub(crate) fn read_signed(reader: &mut Reader, algorithm: HashAlgorithm) -> Result<Self> {
+        let mut header = Self::read(reader, algorithm)?;
+        header.signature = match reader.u8()? {
+            0 => None,
+            1 => Some(Signature(reader.take(65)?.try_into().unwrap())),
+            tag => return Err(Error::new(format!("bad signature tag {tag}"))),
+        };
+        Ok(header)
+    }
+
+    /// Read an encoded header, computing its hash with `algorithm`.
+    pub(crate) fn read(reader: &mut Reader, algorithm: HashAlgorithm) -> Result<Self> {
+        let mut header = Header {
+            hash: Hash::zero(),
+            parent_hash: Hash::from(reader.bytes32()?),
+            number: reader.u64()?,
+            timestamp: reader.u64()?,
+            proposer: Address::from(reader.bytes32()?),
+            transactions_root: Hash::from(reader.bytes32()?),
+            state_root: Hash::from(reader.bytes32()?),
+            gas_limit: reader.u64()?,
+            signature: None,
+        };
+        header.hash = header.compute_hash(algorithm);
+        Ok(header)
+    }
+
+    /// The block hash is the hash of the header encoding.
+    pub(crate) fn compute_hash(&self, algorithm: HashAlgorithm) -> Hash {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(self.encode());
+        hasher.finalize()
+    }
+
+    /// The hash the proposer signs: the block hash, prefixed with the ID of the chain the block
+    /// belongs to, as for [`Transaction::signing_hash`].
+    pub(crate) fn signing_hash(&self, chain_id: u64, algorithm: HashAlgorithm) -> Hash {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(chain_id.to_be_bytes());
+        hasher.update(self.hash.0);
+        hasher.finalize()
+    }
+
+    /// Sign the header for chain `chain_id`, which hashes with `algorithm`, with `signer`, which
+    /// must hold the proposer's key.
+    pub(crate) fn sign(
+        &mut self,
+        signer: &dyn Signer,
+        chain_id: u64,
+        algorithm: HashAlgorithm,
+    ) -> Result<()> {
+        let address = signer.address(algorithm)?;
+        if address != self.proposer {
+            return Err(Error::new(format!(
+                "signer {address} cannot sign for proposer {}",
+                self.proposer
+            )));
+        }
+        self.signature = Some(signer.sign_hash(self.signing_hash(chain_id, algorithm))?);
+        Ok(())
+    }
+
+    /// Check that the proposer signed the header for chain `chain_id`, which hashes with
+    /// `algorithm`.
+    pub(crate) fn check_signature(&self, chain_id: u64, algorithm: HashAlgorithm) -> Result<()> {
+        let signature = self
+            .signature
+            .ok_or_else(|| Error::new(format!("block {} is not signed", self.hash)))?;
+        let signer = signature.recover(self.signing_hash(chain_id, algorithm), algorithm)?;
+        if signer != self.proposer {
+            return Err(Error::new(format!(
+                "block {} is signed by {signer}, not its proposer {}",
+                self.hash, self.proposer
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Body {
+    /// The hash of the transaction hashes, in order.
+    pub(crate) fn transactions_root(&self, algorithm: HashAlgorithm) -> Hash {
+        let mut hasher = HashBuilder::new(algorithm);
+        for (transaction_hash, _) in &self.transactions {
+            hasher.update(transaction_hash.0);
+        }
+        hasher.finalize()
+    }
+}
+
+impl Block {
+    /// Assemble a block, computing the transactions root and the hash with `algorithm`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        algorithm: HashAlgorithm,
+        parent_hash: Hash,
+        number: u64,
+        timestamp: u64,
+        proposer: Address,
+        state_root: Hash,
+        gas_limit: u64,
+        body: Body,
+    ) -> Self {
+        let mut header = Header {
+            hash: Hash::zero(),
+            parent_hash,
+            number,
+            timestamp,
+            proposer,
+            transactions_root: body.transactions_root(algorithm),
+            state_root,
+            gas_limit,
+            signature: None,
+        };
+        header.hash = header.compute_hash(algorithm);
+        Self { header, body }
+    }
+
+    /// Start a block whose hashes are computed with `algorithm`, and fields checked, by
+    /// [`Builder::build`].
+    pub(crate) fn builder(algorithm: HashAlgorithm) -> Builder {
+        Builder {
+            algorithm,
+            ..Default::default()
+        }
+    }
+
+    /// Check that the header hash, the transactions root and every transaction hash match the
+    /// contents they commit to under `algorithm`.
+    pub(crate) fn check_contents(&self, algorithm: HashAlgorithm) -> Result<()> {
+        if self.header.compute_hash(algorithm) != self.hash {
+            return Err(Error::new(format!(
+                "block {} does not match its header",
+                self.hash
+            )));
+        }
+        for (hash, transaction) in &self.body.transactions {
+            if transaction.compute_hash(algorithm) != *hash {
+                return Err(Error::new(format!(
+                    "transaction {hash} does not match its contents"
+                )));
+            }
+        }
+        if self.body.transactions_root(algorithm) != self.transactions_root {
+            return Err(Error::new(format!(
+                "block {} does not match its transactions root",
+                self.hash
+            )));
+        }
+        Ok(())
+    }
+
+    /// The canonical encoding: the signed header, then the transaction count and each
+    /// length-prefixed transaction encoding. See [`crate::encoding`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.header.encode_signed();
+        out.extend_from_slice(&(self.body.transactions.len() as u32).to_be_bytes());
+        for (_, transaction) in &self.body.transactions {
+            encoding::write_var_bytes(&mut out, &transaction.encode());
+        }
+        out
+    }
+
+    /// Decode a block of a chain that hashes with `algorithm`, computing its hash and those of
+    /// its transactions.
+    pub fn decode(bytes: &[u8], algorithm: HashAlgorithm) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let header = Header::read_signed(&mut reader, algorithm)?;
+        let count = reader.u32()?;
+        let mut body = Body::default();
+        for _ in 0..count {
+            let transaction = Transaction::decode(reader.var_bytes()?)
+                .map_err(|error| Error::new(format!("bad transaction in block: {error}")))?;
+            body.transactions
+                .push((transaction.compute_hash(algorithm), transaction));
+        }
+        reader.finish()?;
+        let block = Block { header, body };
+        block.check_contents(algorithm)?;
+        Ok(block)
+    }
+}
+
+/// Assembles a [`Block`] from its fields, computing the transaction hashes, the transactions
+/// root and the block hash, so that the result always matches its contents.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Builder {
+    algorithm: HashAlgorithm,
+    parent_hash: Option<Hash>,
+    number: Option<u64>,
+    timestamp: Option<u64>,
+    proposer: Option<Address>,
+    state_root: Option<Hash>,
+    gas_limit: Option<u64>,
+    transactions: Vec<Transaction>,
+}
+
+impl Builder {
+    /// Build on `parent`, at the next height.
+    pub(crate) fn parent(mut self, parent: &Header) -> Self {
+        self.parent_hash = Some(parent.hash);
+        self.number = Some(parent.number + 1);
+        self
+    }
+
+    /// Build a genesis block, which has no parent.
+    pub(crate) fn genesis(mut self) -> Self {
+        self.parent_hash = Some(Hash::zero());
+        self.number = Some(0);
+        self
+    }
+
+    pub(crate) fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub(crate) fn proposer(mut self, proposer: Address) -> Self {
+        self.proposer = Some(proposer);
+        self
+    }
+
+    pub(crate) fn state_root(mut self, state_root: Hash) -> Self {
+        self.state_root = Some(state_root);
+        self
+    }
+
+    pub(crate) fn gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = Some(gas_limit);
+        self
+    }
+
+    pub(crate) fn transaction(mut self, transaction: Transaction) -> Self {
+        self.transactions.push(transaction);
+        self
+    }
+
+    pub(crate) fn transactions(
+        mut self,
+        transactions: impl IntoIterator<Item = Transaction>,
+    ) -> Self {
+        self.transactions.extend(transactions);
+        self
+    }
+
+    /// The block, or an error naming the first field that is missing or inconsistent.
+    pub(crate) fn build(self) -> Result<Block> {
+        fn required<T>(field: Option<T>, name: &str) -> Result<T> {
+            field.ok_or_else(|| Error::new(format!("block has no {name}")))
+        }
+        let (parent_hash, number) = match (self.parent_hash, self.number) {
+            (Some(parent_hash), Some(number)) => (parent_hash, number),
+            _ => {
+                return Err(Error::new(
+                    "block has no parent; build it on one, or as genesis",
+                ))
+            }
+        };
+        let timestamp = required(self.timestamp, "timestamp")?;
+        let body = Body {
+            transactions: self
+                .transactions
+                .into_iter()
+                .map(|transaction| (transaction.compute_hash(self.algorithm), transaction))
+                .collect(),
+        };
+        Ok(Block::new(
+            self.algorithm,
+            parent_hash,
+            number,
+            timestamp,
+            required(self.proposer, "proposer")?,
+            required(self.state_root, "state root")?,
+            required(self.gas_limit, "gas limit")?,
+            body,
+        ))
+    }
+}
+
+#[test]
+fn test_block_builder() {
+    use crate::transaction::TransactionKind;
+
+    let algorithm = HashAlgorithm::default();
+    let genesis = Block::builder(algorithm)
+        .genesis()
+        .timestamp(5)
+        .proposer(Address::zero())
+        .state_root(Hash::digest_of("state"))
+        .gas_limit(1_000_000)
+        .build()
+        .unwrap();
+    assert_eq!(genesis.number, 0);
+    assert!(genesis.parent_hash.is_zero());
+    genesis.check_contents(algorithm).unwrap();
+
+    let transfer = Transaction::builder(TransactionKind::Transfer)
+        .from(Address([7; 32]))
+        .to(Address([8; 32]))
+        .value(5)
+        .gas_limit(21_000)
+        .nonce(0)
+        .build()
+        .unwrap();
+    let child = || {
+        Block::builder(algorithm)
+            .parent(&genesis)
+            .timestamp(6)
+            .proposer(Address([7; 32]))
+            .state_root(Hash::digest_of("state"))
+            .gas_limit(1_000_000)
+    };
+    let block = child().transaction(transfer.clone()).build().unwrap();
+    assert_eq!(block.number, 1);
+    assert_eq!(block.parent_hash, genesis.hash);
+    assert_eq!(
+        block.body.transactions[0].0,
+        transfer.compute_hash(algorithm)
+    );
+    block.check_contents(algorithm).unwrap();
+    assert_ne!(child().build().unwrap().hash, block.hash);
+
+    let error = (Block::builder(algorithm).genesis().timestamp(5).build()).unwrap_err();
+    assert_eq!(error.message(), "block has no proposer");
+    assert!(Block::builder(algorithm).timestamp(5).build().is_err());
+    // The same contents hash differently under another algorithm.
+    assert!(block.check_contents(HashAlgorithm::Keccak256).is_err());
+}
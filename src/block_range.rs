@@ -0,0 +1,226 @@
+//! Canonical blocks streamed in order, for indexers backfilling history: [`BlockRange`] in
+//! process, and `GET /blocks?from=<number>&to=<number>` over [`crate::http`].
+//!
+//! A range is read [`BATCH`] blocks at a time, so streaming all of history holds no more than a
+//! batch in memory. Over HTTP each request answers one page of the range, of at most
+//! [`MAX_PAGE`] blocks, as [`crate::cursor`] describes: `limit` sizes it, `cursor` continues
+//! after the page whose `next` it is, and `total=true` adds how many blocks the range holds.
+use crate::block::Block;
+#[cfg(feature = "http")]
+use crate::cursor;
+use crate::db;
+use crate::error::{Error, Result};
+#[cfg(feature = "http")]
+use crate::http::{Request, Response};
+use crate::runtime;
+use crate::Blockhead;
+#[cfg(feature = "http")]
+use serde_json::json;
+use std::collections::VecDeque;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+
+/// How many blocks a [`BlockRange`] reads at once.
+const BATCH: u64 = 64;
+/// The most blocks one `/blocks` page holds.
+#[cfg(feature = "http")]
+const MAX_PAGE: u64 = 1000;
+/// The blocks one `/blocks` page holds unless asked for fewer.
+#[cfg(feature = "http")]
+const DEFAULT_PAGE: u64 = 100;
+
+/// The canonical blocks from one number to another. See [`Blockhead::get_blocks_range`].
+pub struct BlockRange<'a> {
+    blockhead: &'a Blockhead,
+    /// The number of the first block not yet read.
+    next: u64,
+    to: u64,
+    batch: VecDeque<Block>,
+    /// Set once the last batch has been read.
+    done: bool,
+    /// The hash of the last block returned, which the next must build on.
+    parent: Option<crate::hash::Hash>,
+}
+
+impl Blockhead {
+    /// Stream the canonical blocks numbered `from` to `to`, inclusive, in order. The range ends
+    /// early at the head if `to` is past it.
+    pub fn get_blocks_range(&self, from: u64, to: u64) -> BlockRange<'_> {
+        BlockRange {
+            blockhead: self,
+            next: from,
+            to,
+            batch: VecDeque::new(),
+            done: from > to,
+            parent: None,
+        }
+    }
+}
+
+impl BlockRange<'_> {
+    /// The next block of the range, or `None` once it is done. Fails if the chain reorganized
+    /// past the blocks already returned, which would make the next one not build on them.
+    pub async fn next_block(&mut self) -> Result<Option<Block>> {
+        if self.batch.is_empty() && !self.done {
+            let last = self.to.min(self.next.saturating_add(BATCH - 1));
+            let blocks = runtime::block(|| {
+                db::read_canonical_range(&self.blockhead.reader(), self.next, last, BATCH)
+            })?;
+            // A short batch means the head came first.
+            match last.checked_add(1) {
+                Some(next) if next <= self.to && blocks.len() as u64 == next - self.next => {
+                    self.next = next
+                }
+                _ => self.done = true,
+            }
+            self.batch = blocks.into();
+        }
+        let Some(block) = self.batch.pop_front() else {
+            return Ok(None);
+        };
+        if self
+            .parent
+            .is_some_and(|parent| parent != block.parent_hash)
+        {
+            return Err(Error::new(format!(
+                "the chain reorganized below block {} while it was streamed",
+                block.number
+            )));
+        }
+        self.parent = Some(block.hash);
+        Ok(Some(block))
+    }
+}
+
+/// Answer `GET /blocks` with a page of canonical blocks, each with its number, hash and
+/// canonical encoding.
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, request: &Request) -> Response {
+    let (blockhead, request) = (blockhead.clone(), request.clone());
+    match runtime::spawn_blocking(move || page(&blockhead, &request)).await {
+        Ok(page) => Response::json(200, &page),
+        Err(error) => Response::from_error(400, &error),
+    }
+}
+
+#[cfg(feature = "http")]
+fn page(blockhead: &Blockhead, request: &Request) -> Result<serde_json::Value> {
+    let param = |name: &str| {
+        request
+            .query_param(name)
+            .map(str::parse::<u64>)
+            .transpose()
+            .map_err(|error| Error::new(format!("bad {name}: {error}")))
+    };
+    let from = param("from")?.unwrap_or(0);
+    let to = param("to")?.unwrap_or(u64::MAX);
+    let limit = cursor::page_size(param("limit")?, DEFAULT_PAGE, MAX_PAGE);
+    let start = match request.query_param("cursor") {
+        Some(after) => {
+            let [after] = cursor::decode("blocks", after)?;
+            from.max(after.saturating_add(1))
+        }
+        None => from,
+    };
+    let connection = blockhead.reader();
+    let blocks = db::read_canonical_range(&connection, start, to, limit)?;
+    let next = match blocks.last() {
+        Some(last) if blocks.len() as u64 == limit && last.number < to => {
+            Some(cursor::encode("blocks", &[last.number]))
+        }
+        _ => None,
+    };
+    let blocks: Vec<_> = blocks
+        .iter()
+        .map(|block| {
+            json!({
+                "number": block.number,
+                "hash": block.hash.to_string(),
+                "raw": format!("0x{}", hex::encode(block.encode())),
+            })
+        })
+        .collect();
+    let mut page = json!({"blocks": blocks, "next": next});
+    if request.query_param("total") == Some("true") {
+        let head = db::read_head(&connection)?.map_or(0, |head| head.number);
+        page["total"] = (to.min(head) + 1).saturating_sub(from).into();
+    }
+    Ok(page)
+}
+
+#[tokio::test]
+async fn test_block_range() {
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    chain.produce_many(150);
+    let blockhead = &chain.blockhead;
+    let mut range = blockhead.get_blocks_range(10, 140);
+    let mut numbers = Vec::new();
+    while let Some(block) = range.next_block().await.unwrap() {
+        numbers.push(block.number);
+    }
+    assert_eq!(numbers, (10..=140).collect::<Vec<_>>());
+
+    let mut range = blockhead.get_blocks_range(145, u64::MAX);
+    let mut numbers = Vec::new();
+    while let Some(block) = range.next_block().await.unwrap() {
+        numbers.push(block.number);
+    }
+    assert_eq!(numbers, (145..=150).collect::<Vec<_>>());
+    assert!(range.next_block().await.unwrap().is_none());
+    let mut range = blockhead.get_blocks_range(200, 300);
+    assert!(range.next_block().await.unwrap().is_none());
+
+    let mut range = blockhead.get_blocks_range(0, 0);
+    let genesis = range.next_block().await.unwrap().unwrap();
+    assert_eq!(genesis.number, 0);
+    assert!(range.next_block().await.unwrap().is_none());
+
+    #[cfg(feature = "http")]
+    {
+        use crate::http::{send, serve_locally};
+
+        let algorithm = chain.blockhead.config.hash;
+        let address = serve_locally(Arc::new(chain.blockhead)).await;
+        let get = |target: String| async move {
+            send(address, &format!("GET {target} HTTP/1.1\r\n\r\n")).await
+        };
+        let (status, body) = get("/blocks?from=5&limit=3&total=true".into()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["total"], 146);
+        let numbers = |body: &serde_json::Value| -> Vec<u64> {
+            let blocks = body["blocks"].as_array().unwrap();
+            blocks
+                .iter()
+                .map(|b| b["number"].as_u64().unwrap())
+                .collect()
+        };
+        assert_eq!(numbers(&body), [5, 6, 7]);
+        let raw = body["blocks"][0]["raw"].as_str().unwrap();
+        let block = Block::decode(&hex::decode(&raw[2..]).unwrap(), algorithm).unwrap();
+        assert_eq!(block.hash.to_string(), body["blocks"][0]["hash"]);
+        let next = body["next"].as_str().unwrap();
+        let (_, body) = get(format!("/blocks?from=5&limit=3&cursor={next}")).await;
+        assert_eq!(numbers(&body), [8, 9, 10]);
+        assert!(body.get("total").is_none());
+
+        let (_, body) = get("/blocks?from=140&to=149&limit=5".into()).await;
+        let next = body["next"].as_str().unwrap();
+        let (_, body) = get(format!("/blocks?from=140&to=149&limit=5&cursor={next}")).await;
+        assert_eq!(
+            (numbers(&body), &body["next"]),
+            (vec![145, 146, 147, 148, 149], &json!(null))
+        );
+        let (_, body) = get("/blocks?from=149".into()).await;
+        assert_eq!(
+            (numbers(&body), &body["next"]),
+            (vec![149, 150], &json!(null))
+        );
+        let cursor = crate::cursor::encode("transactions", &[3, 0]);
+        let (status, _) = get(format!("/blocks?cursor={cursor}")).await;
+        assert_eq!(status, 400);
+        let (status, _) = get("/blocks?from=x".into()).await;
+        assert_eq!(status, 400);
+    }
+}
@@ -0,0 +1,129 @@
+//! Summaries of the logs each block emitted, so that log queries can pass over the blocks that
+//! cannot hold a match without reading their logs.
+//!
+//! A [`LogBloom`] is a 2048-bit Bloom filter, like the one Ethereum keeps in each header: the
+//! address and every topic of each log set three bits, picked by the first six bytes of the
+//! item's Keccak-256 hash. It can rule a block out but never in, since the bits of an item may all
+//! have been set by others. Blooms are kept by each node beside the logs rather than agreed in
+//! headers, so they hash with Keccak-256 whatever the chain's [`crate::hash::HashAlgorithm`].
+use crate::error::{Error, Result};
+use crate::logs::LogFilter;
+use crate::Log;
+use sha3::{Digest, Keccak256};
+
+/// The bytes of a [`LogBloom`].
+const BYTES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LogBloom([u8; BYTES]);
+
+impl Default for LogBloom {
+    fn default() -> Self {
+        Self([0; BYTES])
+    }
+}
+
+impl TryFrom<&[u8]> for LogBloom {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes.try_into().map_err(|_| {
+            Error::new(format!("a log bloom is {BYTES} bytes, not {}", bytes.len()))
+        })?;
+        Ok(Self(bytes))
+    }
+}
+
+impl LogBloom {
+    /// The bloom of `logs`.
+    pub(crate) fn of<'a>(logs: impl IntoIterator<Item = &'a Log>) -> Self {
+        let mut bloom = Self::default();
+        for log in logs {
+            bloom.add_log(log);
+        }
+        bloom
+    }
+
+    pub(crate) fn add_log(&mut self, log: &Log) {
+        self.add(&log.address.0);
+        for topic in &log.topics {
+            self.add(&topic.0);
+        }
+    }
+
+    /// Add the items of `other`, so that the bloom summarizes both sets of logs.
+    pub(crate) fn include(&mut self, other: &LogBloom) {
+        for (byte, other) in self.0.iter_mut().zip(other.0) {
+            *byte |= other;
+        }
+    }
+
+    fn add(&mut self, item: &[u8]) {
+        for (byte, bit) in bits(item) {
+            self.0[byte] |= bit;
+        }
+    }
+
+    fn contains(&self, item: &[u8]) -> bool {
+        bits(item).all(|(byte, bit)| self.0[byte] & bit != 0)
+    }
+
+    /// Whether a log of the block may match `filter`: one of its addresses, if any, and one of
+    /// the topics at each position that has some, are all in the bloom.
+    pub(crate) fn may_match(&self, filter: &LogFilter) -> bool {
+        let any = |items: &mut dyn Iterator<Item = &[u8; 32]>| {
+            let mut items = items.peekable();
+            items.peek().is_none() || items.any(|item| self.contains(item))
+        };
+        any(&mut filter.addresses.iter().map(|address| &address.0))
+            && (filter.topics.iter()).all(|topics| any(&mut topics.iter().map(|topic| &topic.0)))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The byte of the bloom and the bit in it for each of the three bits `item` sets.
+fn bits(item: &[u8]) -> impl Iterator<Item = (usize, u8)> {
+    let hash = Keccak256::digest(item);
+    (0..3).map(move |i| {
+        let index = usize::from(u16::from_be_bytes([hash[2 * i], hash[2 * i + 1]]) & 0x7ff);
+        (BYTES - 1 - index / 8, 1 << (index % 8))
+    })
+}
+
+#[test]
+fn test_log_bloom() {
+    use crate::address::Address;
+    use crate::hash::Hash;
+
+    let log = Log {
+        address: Address([9; 32]),
+        topics: vec![Hash([1; 32]), Hash([2; 32])],
+        data: vec![3],
+    };
+    let bloom = LogBloom::of([&log]);
+    assert_eq!(bloom.0.iter().map(|byte| byte.count_ones()).sum::<u32>(), 9);
+    assert_eq!(LogBloom::try_from(bloom.as_bytes()).unwrap(), bloom);
+    assert!(LogBloom::try_from(&[0; 32][..]).is_err());
+
+    let filter = |addresses: Vec<u8>, topics: Vec<Vec<u8>>| LogFilter {
+        addresses: addresses.into_iter().map(|a| Address([a; 32])).collect(),
+        topics: (topics.into_iter())
+            .map(|topics| topics.into_iter().map(|t| Hash([t; 32])).collect())
+            .collect(),
+    };
+    assert!(bloom.may_match(&filter(vec![], vec![])));
+    assert!(bloom.may_match(&filter(vec![8, 9], vec![vec![], vec![1, 4]])));
+    assert!(bloom.may_match(&filter(vec![], vec![vec![2], vec![1]])));
+    assert!(!bloom.may_match(&filter(vec![8], vec![])));
+    assert!(!bloom.may_match(&filter(vec![9], vec![vec![1], vec![4]])));
+    assert!(!LogBloom::default().may_match(&filter(vec![], vec![vec![1]])));
+    let mut both = LogBloom::of([&Log {
+        address: Address([8; 32]),
+        ..log.clone()
+    }]);
+    both.include(&bloom);
+    assert!(both.may_match(&filter(vec![8], vec![])) && both.may_match(&filter(vec![9], vec![])));
+}
@@ -0,0 +1,145 @@
+//! The order in which a proposer tries the mempool's transactions when building a block.
+//!
+//! Which transactions a block takes, and in what order, is up to its proposer: other nodes only
+//! check that each one applies. So the policy is set per node, by [`MempoolConfig::ordering`].
+//! Every policy but [`Fifo`] puts each sender's transactions in nonce order, since each can
+//! only apply after the sender's previous one.
+//!
+//! [`MempoolConfig::ordering`]: crate::mempool::MempoolConfig::ordering
+use crate::address::Address;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Chooses the order of a block's candidate transactions.
+pub(crate) trait BlockBuilder: Send + Sync {
+    /// Order `pending`, given in admission order.
+    fn order(&self, pending: Vec<Transaction>) -> Vec<Transaction>;
+}
+
+/// The [`BlockBuilder`] policies a node can be configured with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockOrdering {
+    #[default]
+    Fifo,
+    HighestFee,
+    AccountFair,
+}
+
+impl BlockOrdering {
+    pub(crate) fn builder(self) -> &'static dyn BlockBuilder {
+        match self {
+            BlockOrdering::Fifo => &Fifo,
+            BlockOrdering::HighestFee => &HighestFee,
+            BlockOrdering::AccountFair => &AccountFair,
+        }
+    }
+}
+
+/// Admission order, unchanged.
+pub(crate) struct Fifo;
+
+impl BlockBuilder for Fifo {
+    fn order(&self, pending: Vec<Transaction>) -> Vec<Transaction> {
+        pending
+    }
+}
+
+/// The transaction paying the highest gas price among each sender's next, repeatedly, with
+/// earlier senders first among equals. A sender's cheap transaction holds back its pricier
+/// successors, as they cannot go first.
+pub(crate) struct HighestFee;
+
+impl BlockBuilder for HighestFee {
+    fn order(&self, pending: Vec<Transaction>) -> Vec<Transaction> {
+        let mut queues = sender_queues(pending);
+        let mut ordered = Vec::new();
+        loop {
+            let best = queues
+                .iter()
+                .enumerate()
+                .filter_map(|(i, queue)| Some((i, queue.front()?.gas_price)))
+                .max_by_key(|&(i, gas_price)| (gas_price, std::cmp::Reverse(i)));
+            let Some((i, _)) = best else {
+                return ordered;
+            };
+            ordered.extend(queues[i].pop_front());
+        }
+    }
+}
+
+/// One transaction from each sender in turn, so that no sender can fill a block while others
+/// wait.
+pub(crate) struct AccountFair;
+
+impl BlockBuilder for AccountFair {
+    fn order(&self, pending: Vec<Transaction>) -> Vec<Transaction> {
+        let mut queues = sender_queues(pending);
+        let mut ordered = Vec::new();
+        while queues.iter().any(|queue| !queue.is_empty()) {
+            ordered.extend(queues.iter_mut().filter_map(VecDeque::pop_front));
+        }
+        ordered
+    }
+}
+
+/// Each sender's transactions in nonce order, senders in the order they were first admitted.
+fn sender_queues(pending: Vec<Transaction>) -> Vec<VecDeque<Transaction>> {
+    let mut positions: HashMap<Address, usize> = HashMap::new();
+    let mut queues: Vec<Vec<Transaction>> = Vec::new();
+    for transaction in pending {
+        let position = *positions
+            .entry(transaction.from_address)
+            .or_insert_with(|| {
+                queues.push(Vec::new());
+                queues.len() - 1
+            });
+        queues[position].push(transaction);
+    }
+    queues
+        .into_iter()
+        .map(|mut queue| {
+            queue.sort_by_key(|transaction| transaction.nonce);
+            queue.into()
+        })
+        .collect()
+}
+
+#[test]
+fn test_block_ordering() {
+    use crate::testkit;
+
+    let transaction = |sender: u8, nonce, gas_price| Transaction {
+        gas_price,
+        ..testkit::transfer(Address([sender; 32]), Address([0; 32]), 1, nonce)
+    };
+    let pending = vec![
+        transaction(1, 1, 50),
+        transaction(1, 0, 1),
+        transaction(1, 2, 1),
+        transaction(2, 0, 10),
+        transaction(3, 0, 10),
+        transaction(3, 1, 20),
+    ];
+    let order = |ordering: BlockOrdering| -> Vec<(u8, u64)> {
+        ordering
+            .builder()
+            .order(pending.clone())
+            .iter()
+            .map(|transaction| (transaction.from_address.0[0], transaction.nonce))
+            .collect()
+    };
+    assert_eq!(
+        order(BlockOrdering::Fifo),
+        vec![(1, 1), (1, 0), (1, 2), (2, 0), (3, 0), (3, 1)]
+    );
+    assert_eq!(
+        order(BlockOrdering::HighestFee),
+        vec![(2, 0), (3, 0), (3, 1), (1, 0), (1, 1), (1, 2)]
+    );
+    assert_eq!(
+        order(BlockOrdering::AccountFair),
+        vec![(1, 0), (2, 0), (3, 0), (1, 1), (3, 1), (1, 2)]
+    );
+}
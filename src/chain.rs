@@ -0,0 +1,1581 @@
+//! Block production and import.
+use crate::archive::StateHistory;
+use crate::block::{Block, BlockId, Header};
+use crate::clock;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::events::ChainEvent;
+use crate::execution::{self, ExecutionOutcome};
+use crate::execution_cache::Applied;
+use crate::finality;
+use crate::hash::Hash;
+use crate::indexer;
+use crate::parallel;
+use crate::reward;
+use crate::staking::{self, ValidatorSet};
+use crate::state::{self, StateOverlay};
+use crate::status::TransactionStatus;
+use crate::transaction::Transaction;
+use crate::verify;
+use crate::{Blockhead, TransactionReceipt};
+use std::time::Instant;
+
+/// A block built by [`Blockhead::build_block`], with its effects not yet committed.
+struct BuiltBlock<'a> {
+    block: Block,
+    state: StateOverlay<'a>,
+    outcomes: Vec<ExecutionOutcome>,
+    /// The transactions left out because they failed to apply, with the reason.
+    dropped: Vec<(Hash, String)>,
+}
+
+impl Blockhead {
+    /// The canonical chain head.
+    pub(crate) fn head(&self) -> Result<Block> {
+        db::read_head(&self.connection)?.ok_or_else(|| Error::new("chain has no genesis block"))
+    }
+
+    /// The highest finalized checkpoint. The genesis block is always final.
+    pub(crate) fn finalized(&self) -> Result<Block> {
+        db::read_finalized(&self.connection)?
+            .ok_or_else(|| Error::new("chain has no finalized block"))
+    }
+
+    pub(crate) fn validator_set(&self, epoch: u64) -> Result<ValidatorSet> {
+        db::read_validator_set(&self.connection, epoch)?
+            .ok_or_else(|| Error::new(format!("no validator set for epoch {epoch}")))
+    }
+
+    /// The block identified by `id`. A hash may name a block off the canonical chain.
+    pub(crate) fn block(&self, id: BlockId) -> Result<Option<Block>> {
+        let connection = self.reader();
+        match id {
+            BlockId::Latest => db::read_head(&connection),
+            BlockId::Finalized => db::read_finalized(&connection),
+            BlockId::Pending => self.with_pending_block(|block| Ok(Some(block.clone()))),
+            BlockId::Number(number) => db::read_canonical_block(&connection, number),
+            BlockId::Hash(hash) => db::read_block(&connection, hash),
+        }
+    }
+
+    /// The header of the block identified by `id`, as for [`Blockhead::block`].
+    pub(crate) fn header(&self, id: BlockId) -> Result<Option<Header>> {
+        match id {
+            BlockId::Number(number) => db::read_canonical_header(&self.reader(), number),
+            BlockId::Hash(hash) => db::read_header(&self.reader(), hash),
+            _ => Ok(self.block(id)?.map(|block| block.header)),
+        }
+    }
+
+    /// The canonical block identified by `id`.
+    pub(crate) fn canonical_block(&self, id: BlockId) -> Result<Block> {
+        let block = match id {
+            BlockId::Hash(hash) => match db::read_block_is_canonical(&self.reader(), hash)? {
+                Some(true) => self.block(id)?,
+                _ => None,
+            },
+            BlockId::Pending => None,
+            _ => self.block(id)?,
+        };
+        block.ok_or_else(|| Error::new(format!("no canonical block {id}")))
+    }
+
+    /// Run the read-only query `f` against the state after block `id`: that of a canonical block
+    /// read from the archive with [`Blockhead::with_archived_state`] in archive mode, or rewound
+    /// to with [`Blockhead::with_state_at`] otherwise, or that of the pending block applied with
+    /// [`Blockhead::with_pending_block`].
+    pub(crate) fn query_at<T>(
+        &self,
+        id: BlockId,
+        f: impl FnOnce(&sqlite::Connection) -> Result<T>,
+    ) -> Result<T> {
+        match id {
+            BlockId::Latest => return f(&self.reader()),
+            BlockId::Pending => return self.with_pending_block(|_| f(&self.connection)),
+            _ => {}
+        }
+        let block = self.canonical_block(id)?;
+        if self.config.history == StateHistory::Archive {
+            return self.with_archived_state(&block, f);
+        }
+        self.with_state_at(&block, || f(&self.connection))
+    }
+
+    /// Run `f` with the database rewound to the state after canonical `block`, discarding
+    /// whatever `f` writes. Blocks production and import while it runs. Rewinding needs write
+    /// access, so a read-only database only supports the head.
+    ///
+    /// The state is restored from the closest state recorded at or below `block`, if that is
+    /// nearer than the head or the undo records to rewind from the head were pruned, and then
+    /// brought forward with the state diffs of the blocks since. See [`crate::archive`].
+    pub(crate) fn with_state_at<T>(
+        &self,
+        block: &Block,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        if self.read_only && block.hash == self.head()?.hash {
+            return f();
+        }
+        self.ensure_writable("rewind to historical state")?;
+        let _guard = self.write_lock.lock().unwrap();
+        db::scratch(&self.connection, || {
+            let mut head = self.head()?;
+            let pruned = db::read_last_state_checkpoint(&self.connection)?
+                .is_some_and(|checkpoint| checkpoint > block.number);
+            let recorded = self.nearest_recorded_state(block.number)?;
+            if let Some(recorded) = recorded
+                .filter(|recorded| pruned || block.number - recorded < head.number - block.number)
+            {
+                self.restore_state(recorded, block.number)?;
+                return f();
+            }
+            while head.number > block.number {
+                state::revert_block(&self.connection, head.hash)?;
+                head = db::read_block(&self.connection, head.parent_hash)?
+                    .ok_or_else(|| Error::new(format!("missing ancestor {}", head.parent_hash)))?;
+            }
+            f()
+        })
+    }
+
+    /// Return the validator set in force for the child of `parent`, running the epoch transition
+    /// into `state` first if the child opens a new epoch.
+    pub(crate) fn enter_block(
+        &self,
+        state: &mut StateOverlay,
+        parent: &Block,
+    ) -> Result<ValidatorSet> {
+        let staking = &self.config.staking;
+        let number = parent.number + 1;
+        let epoch = staking.epoch_of(number);
+        if staking.is_epoch_start(number) {
+            let previous = self.validator_set(epoch - 1)?;
+            return staking::begin_epoch(state, staking, &previous, parent.hash, self.config.hash);
+        }
+        self.validator_set(epoch)
+    }
+
+    /// The median timestamp of `parent` and its closest ancestors.
+    pub(crate) fn median_time_past(&self, parent: &Header) -> Result<u64> {
+        let mut timestamps = vec![parent.timestamp];
+        let mut header = parent.clone();
+        while timestamps.len() < self.config.timestamp.median_window && header.number > 0 {
+            header = db::read_header(&self.connection, header.parent_hash)?
+                .ok_or_else(|| Error::new(format!("missing ancestor {}", header.parent_hash)))?;
+            timestamps.push(header.timestamp);
+        }
+        Ok(clock::median_time_past(timestamps))
+    }
+
+    fn validate_timestamp(&self, parent: &Block, block: &Block) -> Result<()> {
+        let median = self.median_time_past(parent)?;
+        if block.timestamp <= median {
+            return Err(Error::new(format!(
+                "block {} has timestamp {} at or before the median time past {median}",
+                block.hash, block.timestamp
+            )));
+        }
+        let limit = self.now_nanos() + self.config.timestamp.max_future_drift.as_nanos() as u64;
+        if block.timestamp > limit {
+            return Err(Error::new(format!(
+                "block {} has timestamp {} beyond the allowed drift {limit}",
+                block.hash, block.timestamp
+            )));
+        }
+        Ok(())
+    }
+
+    /// Commit the executed state of a stored `block` along with the receipts of its transactions,
+    /// make it canonical and advance finality, returning the newly finalized checkpoint, if any.
+    fn commit_block(
+        &self,
+        state: StateOverlay,
+        block: &Block,
+        outcomes: &[ExecutionOutcome],
+    ) -> Result<Option<Block>> {
+        let checkpoints: Vec<Hash> = state.attestations.iter().map(|(c, _)| *c).collect();
+        let diff = state.commit(block.hash)?;
+        if self.config.history == StateHistory::Archive {
+            db::write_state_history(&self.connection, block, &diff)?;
+            db::write_archived_through(&self.connection, block.number)?;
+        }
+        let mut receipts = Vec::with_capacity(outcomes.len());
+        for ((transaction_hash, _), outcome) in block.body.transactions.iter().zip(outcomes) {
+            receipts.push(TransactionReceipt {
+                transaction_hash: *transaction_hash,
+                block_hash: block.hash,
+                status: outcome.status,
+                gas_used: outcome.gas_used,
+                contract_address: outcome.contract_address,
+                return_data: outcome.return_data.clone(),
+                logs: outcome.logs.clone(),
+            });
+            if let Some(trace) = &outcome.trace {
+                db::write_trace(&self.connection, *transaction_hash, block, trace)?;
+            }
+        }
+        db::write_pending_receipts(&self.connection, block, &receipts)?;
+        db::crash_point("commit_block");
+        db::write_logs(&self.connection, block, &receipts)?;
+        indexer::enforce_max_lag(&self.connection, &self.config.indexer)?;
+        let retain_blocks = self.config.trace.retain_blocks;
+        if retain_blocks > 0 {
+            db::delete_traces_before(
+                &self.connection,
+                (block.number + 1).saturating_sub(retain_blocks),
+            )?;
+        }
+        db::set_canonical(&self.connection, block.hash, true)?;
+        let finalized =
+            finality::update_finalized(&self.connection, &self.config.staking, checkpoints)?;
+        if let Some(finalized) = &finalized {
+            self.checkpoint_state(finalized.number)?;
+        }
+        Ok(finalized)
+    }
+
+    /// Execute a stored `block` on top of the canonical head `parent`. See
+    /// [`Blockhead::commit_block`].
+    fn apply_block(&self, parent: &Block, block: &Block) -> Result<Option<Block>> {
+        let mut state = StateOverlay::new(&self.connection);
+        let validator_set = self.enter_block(&mut state, parent)?;
+        if validator_set.select_proposer(block.number, self.config.hash) != Some(block.proposer) {
+            return Err(Error::new(format!(
+                "block {} was proposed by {}, who was not elected for height {}",
+                block.hash, block.proposer, block.number
+            )));
+        }
+        if let Some(applied) = self.execution_cache.applied(block.hash) {
+            let state = StateOverlay::from_contents(&self.connection, applied.state.clone());
+            return self.commit_block(state, block, &applied.outcomes);
+        }
+        let mut outcomes = Vec::new();
+        let mut gas_used = 0;
+        let mut fees = 0;
+        let transactions = block.body.transactions.iter().map(|(_, t)| t);
+        let signers = verify::recover_signers(transactions, self.config.chain_id, self.config.hash);
+        let executed = parallel::execute_transactions(
+            &self.connection,
+            &mut state,
+            &self.config,
+            &block.body.transactions,
+            signers,
+        )?;
+        for ((hash, transaction), outcome) in block.body.transactions.iter().zip(executed) {
+            let outcome = outcome
+                .map_err(|error| Error::new(format!("transaction {hash} failed: {error}")))?;
+            gas_used += outcome.gas_used;
+            fees += outcome.gas_used * transaction.gas_price;
+            outcomes.push(outcome);
+        }
+        if gas_used > block.gas_limit {
+            return Err(Error::new(format!(
+                "block {} uses {gas_used} gas, above its limit of {}",
+                block.hash, block.gas_limit
+            )));
+        }
+        reward::credit(&mut state, &self.config.reward, block.proposer, fees)?;
+        let state_root = state.state_root(self.config.hash)?;
+        if state_root != block.state_root {
+            return Err(Error::new(format!(
+                "block {} claims state root {} but its state has root {state_root}",
+                block.hash, block.state_root
+            )));
+        }
+        self.remember_execution(block, &state, &outcomes);
+        self.commit_block(state, block, &outcomes)
+    }
+
+    /// Keep the results of executing `block` for a reorg back onto it. See
+    /// [`crate::execution_cache`].
+    fn remember_execution(
+        &self,
+        block: &Block,
+        state: &StateOverlay,
+        outcomes: &[ExecutionOutcome],
+    ) {
+        let applied = Applied {
+            state: state.contents(),
+            outcomes: outcomes.to_vec(),
+        };
+        self.execution_cache.insert_applied(block.hash, applied);
+    }
+
+    /// Build a block on top of the head out of `pending`, as the proposer elected for its height,
+    /// leaving its effects, including the proposer's reward, in the returned state. Transactions
+    /// are tried in the order of [`crate::mempool::MempoolConfig::ordering`], and those that fail
+    /// to apply are dropped.
+    /// Once a transaction's gas limit does not fit in what is left of the block's, or the
+    /// [`crate::mempool::MempoolConfig::build_time`] has run out, it and every transaction after
+    /// it are left in the pool for a later block, which keeps each sender's nonces in order. The
+    /// first transaction is tried whatever the time, so the pool drains even if the budget is too
+    /// short for one. The timestamp comes from the node's time, nudged past the median time past
+    /// if the clock lags behind the chain, unless dev mode has fixed it (see
+    /// [`Blockhead::set_next_block_timestamp`]).
+    fn build_block(&self, pending: &[Transaction]) -> Result<BuiltBlock<'_>> {
+        let deadline = self
+            .config
+            .mempool
+            .build_time
+            .map(|build_time| Instant::now() + build_time);
+        let parent = self.head()?;
+        let number = parent.number + 1;
+        let mut state = StateOverlay::new(&self.connection);
+        let validator_set = self.enter_block(&mut state, &parent)?;
+        let proposer = validator_set
+            .select_proposer(number, self.config.hash)
+            .ok_or_else(|| Error::new(format!("no validators to propose block {number}")))?;
+        let timestamp = match self.time_travel.next_timestamp() {
+            Some(timestamp) => timestamp,
+            None => self.now_nanos().max(self.median_time_past(&parent)? + 1),
+        };
+        let gas_limit = self.config.gas.next_block_gas_limit(parent.gas_limit);
+        let mut gas_used = 0;
+        let mut fees = 0;
+        let mut transactions = Vec::new();
+        let mut outcomes = Vec::new();
+        let mut dropped = Vec::new();
+        let pending = self
+            .config
+            .mempool
+            .ordering
+            .builder()
+            .order(pending.to_vec());
+        let signers =
+            verify::recover_signers(pending.iter(), self.config.chain_id, self.config.hash);
+        for (i, (transaction, signers)) in pending.iter().zip(signers).enumerate() {
+            let out_of_time = i > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if out_of_time || transaction.gas_limit > gas_limit - gas_used {
+                break;
+            }
+            let hash = transaction.compute_hash(self.config.hash);
+            let result = signers.and_then(|signers| {
+                execution::execute_transaction(&mut state, &self.config, transaction, &signers)
+            });
+            match result {
+                Ok(outcome) => {
+                    gas_used += outcome.gas_used;
+                    fees += outcome.gas_used * transaction.gas_price;
+                    transactions.push(transaction.clone());
+                    outcomes.push(outcome);
+                }
+                Err(error) => {
+                    log::warn!("dropping transaction {hash}: {error}");
+                    dropped.push((hash, error.to_string()));
+                }
+            }
+        }
+        reward::credit(&mut state, &self.config.reward, proposer, fees)?;
+        let block = Block::builder(self.config.hash)
+            .parent(&parent)
+            .timestamp(timestamp)
+            .proposer(proposer)
+            .state_root(state.state_root(self.config.hash)?)
+            .gas_limit(gas_limit)
+            .transactions(transactions)
+            .build()?;
+        Ok(BuiltBlock {
+            block,
+            state,
+            outcomes,
+            dropped,
+        })
+    }
+
+    /// Sign `block` with the key of its proposer, which the node must hold. See
+    /// [`Blockhead::with_proposer_key`].
+    fn sign_block(&self, block: &mut Block) -> Result<()> {
+        let key = self.proposer_keys.get(&block.proposer).ok_or_else(|| {
+            Error::new(format!(
+                "block {} falls to {}, whose key this node does not hold",
+                block.number, block.proposer
+            ))
+        })?;
+        (block.header).sign(key.as_ref(), self.config.chain_id, self.config.hash)
+    }
+
+    /// Build, sign and commit a block out of the mempool. See [`Blockhead::build_block`].
+    pub fn produce_block(&self) -> Result<Block> {
+        self.ensure_writable("produce a block")?;
+        if self.is_production_paused() {
+            return Err(Error::new("block production is paused"));
+        }
+        let _guard = self.write_lock.lock().unwrap();
+        // Build from a copy, so that admissions in the meantime are checked against the whole
+        // pool. Transactions leave it only once the block is committed.
+        let pending = {
+            let mut mempool = self.mempool.lock().unwrap();
+            self.drop_expired(&mut mempool);
+            mempool.pending()
+        };
+        let result = db::transaction(&self.connection, || {
+            let mut built = self.build_block(&pending)?;
+            self.sign_block(&mut built.block)?;
+            db::write_block(&self.connection, &built.block, false)?;
+            self.remember_execution(&built.block, &built.state, &built.outcomes);
+            let finalized = self.commit_block(built.state, &built.block, &built.outcomes)?;
+            Ok((built.block, finalized, built.dropped))
+        });
+        let (block, finalized, dropped) = result?;
+        self.imports.remember(block.hash);
+        self.took_next_timestamp(block.timestamp);
+        let hashes: Vec<Hash> = dropped.iter().map(|(hash, _)| *hash).collect();
+        self.mempool.lock().unwrap().remove(&hashes);
+        // Publishing the new block removes its transactions from the mempool.
+        let mut events = vec![ChainEvent::NewBlock(block.clone())];
+        events.extend(finalized.map(|block| ChainEvent::FinalizedBlock(block.header)));
+        self.publish_chain_events(events);
+        let mut status = self.status.lock().unwrap();
+        for (hash, reason) in dropped {
+            status.publish(hash, TransactionStatus::Dropped { reason });
+        }
+        Ok(block)
+    }
+
+    /// Run `f` with the pending block, the block [`Blockhead::produce_block`] would build now,
+    /// committed on top of the head, then discard it. The mempool is left untouched.
+    pub(crate) fn with_pending_block<T>(&self, f: impl FnOnce(&Block) -> Result<T>) -> Result<T> {
+        self.ensure_writable("build the pending block")?;
+        let _guard = self.write_lock.lock().unwrap();
+        let pending = self.mempool.lock().unwrap().pending();
+        db::scratch(&self.connection, || {
+            let built = self.build_block(&pending)?;
+            db::write_block(&self.connection, &built.block, false)?;
+            self.commit_block(built.state, &built.block, &built.outcomes)?;
+            f(&built.block)
+        })
+    }
+
+    /// Validate and store a block received from elsewhere, then run the fork choice: the longest
+    /// chain wins, except that no reorg may revert the finalized checkpoint. Blocks already
+    /// stored are ignored. The block must be signed by its proposer, a validator of its epoch
+    /// if that epoch's set is known, and the elected one once the block is executed.
+    pub fn import_block(&self, block: &Block) -> Result<()> {
+        self.ensure_writable("import a block")?;
+        if self.is_known_block(block.hash)? {
+            return Ok(());
+        }
+        let _guard = self.write_lock.lock().unwrap();
+        block.check_contents(self.config.hash)?;
+        block.check_signature(self.config.chain_id, self.config.hash)?;
+        let parent = db::read_block(&self.connection, block.parent_hash)?
+            .ok_or_else(|| Error::new(format!("block {} has unknown parent", block.hash)))?;
+        if block.number != parent.number + 1 {
+            return Err(Error::new(format!(
+                "block {} has number {} but its parent has number {}",
+                block.hash, block.number, parent.number
+            )));
+        }
+        self.validate_timestamp(&parent, block)?;
+        self.config
+            .gas
+            .check_block_gas_limit(parent.gas_limit, block.gas_limit)?;
+        let finalized = self.finalized()?;
+        if block.number <= finalized.number {
+            return Err(Error::new(format!(
+                "block {} conflicts with finalized checkpoint {} at height {}",
+                block.hash, finalized.hash, finalized.number
+            )));
+        }
+        let epoch = self.config.staking.epoch_of(block.number);
+        if let Some(validator_set) = db::read_validator_set(&self.connection, epoch)? {
+            if !validator_set.contains(block.proposer) {
+                return Err(Error::new(format!(
+                    "block {} was proposed by non-validator {}",
+                    block.hash, block.proposer
+                )));
+            }
+        }
+        let events = db::transaction(&self.connection, || {
+            db::write_block(&self.connection, block, false)?;
+            let head = self.head()?;
+            if block.number > head.number {
+                return self.reorg_to(&head, block);
+            }
+            Ok(Vec::new())
+        })?;
+        self.imports.remember(block.hash);
+        self.publish_chain_events(events);
+        Ok(())
+    }
+
+    /// Make the stored `new_head` canonical: revert canonical blocks back to the fork point, then
+    /// execute the new branch, returning the events describing the switch. A plain extension of
+    /// the head is a reorg of depth zero.
+    fn reorg_to(&self, head: &Block, new_head: &Block) -> Result<Vec<ChainEvent>> {
+        let mut branch = vec![new_head.clone()];
+        let ancestor = loop {
+            let parent_hash = branch.last().unwrap().parent_hash;
+            let parent = db::read_block(&self.connection, parent_hash)?
+                .ok_or_else(|| Error::new(format!("missing ancestor {parent_hash}")))?;
+            if db::read_block_is_canonical(&self.connection, parent_hash)? == Some(true) {
+                break parent;
+            }
+            branch.push(parent);
+        };
+        let finalized = self.finalized()?;
+        if ancestor.number < finalized.number {
+            return Err(Error::new(format!(
+                "refusing to reorg to {} past finalized checkpoint {} at height {}",
+                new_head.hash, finalized.hash, finalized.number
+            )));
+        }
+        let mut events = Vec::new();
+        if head.hash != ancestor.hash {
+            log::info!(
+                "reorg from {} to {}, reverting {} blocks",
+                head.hash,
+                new_head.hash,
+                head.number - ancestor.number
+            );
+            let mut reverted = Vec::new();
+            let mut block = head.clone();
+            while block.hash != ancestor.hash {
+                state::revert_block(&self.connection, block.hash)?;
+                db::set_canonical(&self.connection, block.hash, false)?;
+                let parent_hash = block.parent_hash;
+                reverted.push(block);
+                block = db::read_block(&self.connection, parent_hash)?
+                    .ok_or_else(|| Error::new(format!("missing ancestor {parent_hash}")))?;
+            }
+            db::delete_epochs_after(
+                &self.connection,
+                self.config.staking.epoch_of(ancestor.number),
+            )?;
+            events.push(ChainEvent::Reorg {
+                ancestor: ancestor.hash,
+                reverted,
+            });
+            db::crash_point("reorg_to");
+        }
+        let mut parent = ancestor;
+        for block in branch.into_iter().rev() {
+            let finalized = self.apply_block(&parent, &block)?;
+            events.push(ChainEvent::NewBlock(block.clone()));
+            events.extend(finalized.map(|block| ChainEvent::FinalizedBlock(block.header)));
+            parent = block;
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn staked_chain(epoch_length: u64) -> (Blockhead, crate::address::Address) {
+    use crate::finality::FinalityConfig;
+    use crate::genesis::{ChainConfig, Genesis};
+    use crate::staking::StakingConfig;
+    use crate::testkit;
+
+    let validator = testkit::validator();
+    let genesis = Genesis {
+        alloc: vec![(validator, 100_000)],
+        validators: vec![(validator, 100)],
+        config: ChainConfig {
+            staking: StakingConfig {
+                epoch_length,
+                min_validator_stake: 10,
+            },
+            finality: FinalityConfig {
+                checkpoint_interval: 2,
+            },
+            ..testkit::config()
+        },
+        ..Default::default()
+    };
+    let blockhead = Blockhead::with_genesis(":memory:", genesis)
+        .unwrap()
+        .with_clock(std::sync::Arc::new(crate::clock::ManualClock::new(0)))
+        .with_proposer_key(testkit::validator_key())
+        .unwrap();
+    (blockhead, validator)
+}
+
+#[tokio::test]
+async fn test_stake_and_unstake_across_epochs() {
+    use crate::address::Address;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+    use k256::ecdsa::SigningKey;
+
+    let algorithm = HashAlgorithm::default();
+    // The newcomer proposes blocks once staked, so the node holds its key too.
+    let newcomer_key = SigningKey::from_slice(&[8; 32]).unwrap();
+    let newcomer = Address::from_public_key(newcomer_key.verifying_key(), algorithm);
+    let (blockhead, validator) = staked_chain(2);
+    let blockhead = blockhead.with_proposer_key(newcomer_key).unwrap();
+    let transfer = testkit::transfer(validator, newcomer, 500, 0);
+    let stake = Transaction {
+        value: 400,
+        ..testkit::transaction(TransactionKind::Stake, newcomer, 0)
+    };
+    blockhead.send_transaction(transfer).await.unwrap();
+    // The stake is only admitted once the transfer has funded the newcomer.
+    assert!(blockhead.send_transaction(stake.clone()).await.is_err());
+    blockhead.produce_block().unwrap();
+    blockhead.send_transaction(stake).await.unwrap();
+    let block = blockhead.produce_block().unwrap();
+    assert_eq!(block.body.transactions.len(), 1);
+    assert_eq!(blockhead.get_balance(newcomer).await.unwrap(), 100);
+    assert!(!blockhead.validator_set(1).unwrap().contains(newcomer));
+
+    // Block 4 opens epoch 2, which snapshots the new stake.
+    blockhead.produce_block().unwrap();
+    blockhead.produce_block().unwrap();
+    assert!(blockhead.validator_set(2).unwrap().contains(newcomer));
+
+    let unstake = Transaction {
+        value: 400,
+        ..testkit::transaction(TransactionKind::Unstake, newcomer, 1)
+    };
+    blockhead.send_transaction(unstake).await.unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(newcomer).await.unwrap(), 100);
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(newcomer).await.unwrap(), 500);
+    assert!(!blockhead.validator_set(3).unwrap().contains(newcomer));
+}
+
+#[tokio::test]
+async fn test_double_sign_is_slashed() {
+    use crate::address::Address;
+    use crate::gas::GasConfig;
+    use crate::hash::HashAlgorithm;
+    use crate::signer::Signer;
+    use crate::staking::DoubleSignEvidence;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    let genesis = blockhead.head().unwrap();
+    let canonical = blockhead.produce_block().unwrap();
+    let conflicting = side_block(&blockhead, &genesis, 2);
+    assert_eq!(conflicting.number, canonical.number);
+    blockhead.import_block(&conflicting).unwrap();
+    assert_eq!(blockhead.head().unwrap().hash, canonical.hash);
+
+    // A block naming the validator as proposer, signed by someone else, is neither imported nor
+    // evidence against the validator.
+    let forger = k256::ecdsa::SigningKey::from_slice(&[9; 32]).unwrap();
+    let mut forged = side_block(&blockhead, &genesis, 3);
+    forged.header.signature = None;
+    assert!(blockhead.import_block(&forged).is_err());
+    let signing_hash = forged.signing_hash(blockhead.config.chain_id, algorithm);
+    forged.header.signature = Some(forger.sign_hash(signing_hash).unwrap());
+    assert!(blockhead.import_block(&forged).is_err());
+    let reporter = Address([9; 32]);
+    let report = |second: &Block, nonce| Transaction {
+        data: DoubleSignEvidence {
+            first: canonical.header.clone(),
+            second: second.header.clone(),
+        }
+        .encode()
+        .into(),
+        gas_limit: GasConfig::default().intrinsic_gas(DoubleSignEvidence::LEN),
+        ..testkit::transaction(TransactionKind::ReportDoubleSign, reporter, nonce)
+    };
+    blockhead
+        .send_transaction(report(&forged, 0))
+        .await
+        .unwrap();
+    assert!(blockhead
+        .produce_block()
+        .unwrap()
+        .body
+        .transactions
+        .is_empty());
+    let stake = || {
+        let account = crate::db::read_account(&blockhead.connection, validator).unwrap();
+        account.unwrap().stake
+    };
+    assert_eq!(stake(), 100);
+
+    blockhead
+        .send_transaction(report(&conflicting, 0))
+        .await
+        .unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(stake(), 0);
+    assert_eq!(blockhead.get_balance(reporter).await.unwrap(), 10);
+}
+
+/// An empty block on `parent`, proposed and signed by the test validator, for importing into
+/// `blockhead` as a side branch.
+#[cfg(test)]
+pub(crate) fn side_block(blockhead: &Blockhead, parent: &Block, timestamp: u64) -> Block {
+    use crate::testkit;
+
+    let config = &blockhead.config;
+    let mut block = Block::builder(config.hash)
+        .parent(parent)
+        .timestamp(timestamp)
+        .proposer(testkit::validator_of(config.hash))
+        .state_root(parent.state_root)
+        .gas_limit(parent.gas_limit)
+        .build()
+        .unwrap();
+    (block.header)
+        .sign(&testkit::validator_key(), config.chain_id, config.hash)
+        .unwrap();
+    block
+}
+
+/// Produce a block on `blockhead` on another thread, stalling it once it has copied the mempool
+/// to run `during` on this one, as when transactions arrive while a block is being built. Returns
+/// the node, the block and what `during` returned.
+#[cfg(test)]
+pub(crate) async fn produce_during<F: std::future::Future>(
+    blockhead: Blockhead,
+    during: impl FnOnce(std::sync::Arc<Blockhead>) -> F,
+) -> (std::sync::Arc<Blockhead>, Block, F::Output) {
+    use crate::clock::Clock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, ThreadId};
+
+    /// Time that stands still at zero, and stops the producing thread the second time it reads
+    /// it: the first read expires the mempool, the second timestamps the block built from it.
+    struct Stall {
+        producer: Mutex<Option<ThreadId>>,
+        reads: AtomicUsize,
+        stalled: Mutex<Sender<()>>,
+        release: Mutex<Receiver<()>>,
+    }
+
+    impl Clock for Stall {
+        fn now_nanos(&self) -> u64 {
+            let producing = *self.producer.lock().unwrap() == Some(thread::current().id());
+            if producing && self.reads.fetch_add(1, Ordering::SeqCst) == 1 {
+                self.stalled.lock().unwrap().send(()).unwrap();
+                self.release.lock().unwrap().recv().unwrap();
+            }
+            0
+        }
+    }
+
+    let (stalled, on_stall) = mpsc::channel();
+    let (release, on_release) = mpsc::channel();
+    let clock = Arc::new(Stall {
+        producer: Mutex::new(None),
+        reads: AtomicUsize::new(0),
+        stalled: Mutex::new(stalled),
+        release: Mutex::new(on_release),
+    });
+    let blockhead = Arc::new(blockhead.with_clock(clock.clone()));
+    let producer = {
+        let blockhead = blockhead.clone();
+        thread::spawn(move || {
+            *clock.producer.lock().unwrap() = Some(thread::current().id());
+            blockhead.produce_block().unwrap()
+        })
+    };
+    on_stall.recv().unwrap();
+    let output = during(blockhead.clone()).await;
+    release.send(()).unwrap();
+    (blockhead, producer.join().unwrap(), output)
+}
+
+#[tokio::test]
+async fn test_longer_side_branch_reorgs_state() {
+    use crate::address::Address;
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = staked_chain(32);
+    let genesis = blockhead.head().unwrap();
+    let recipient = Address([8; 32]);
+    let transfer = testkit::transfer(validator, recipient, 5, 0);
+    blockhead.send_transaction(transfer).await.unwrap();
+    let block1 = blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 5);
+    let diff = blockhead
+        .get_state_diff(block1.hash)
+        .await
+        .unwrap()
+        .unwrap();
+    let changes: Vec<_> = diff
+        .accounts
+        .iter()
+        .map(|a| {
+            (
+                a.address,
+                a.after.balance as i64 - a.before.balance as i64,
+                a.after.nonce,
+            )
+        })
+        .collect();
+    assert_eq!(changes, vec![(recipient, 5, 0), (validator, -5, 1)]);
+    assert!(diff.storage.is_empty());
+
+    let side1 = side_block(&blockhead, &genesis, 11);
+    let side2 = side_block(&blockhead, &side1, 12);
+    blockhead.import_block(&side1).unwrap();
+    assert_eq!(blockhead.head().unwrap().number, 1);
+    blockhead.import_block(&side2).unwrap();
+    assert_eq!(blockhead.head().unwrap().hash, side2.hash);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 0);
+    assert_eq!(blockhead.get_balance(validator).await.unwrap(), 100_000);
+    assert!(blockhead
+        .get_state_diff(block1.hash)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_finality_blocks_reorg() {
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let (blockhead, _) = staked_chain(32);
+    let block1 = blockhead.produce_block().unwrap();
+    // A side fork off block 1, stored before the checkpoint finalizes.
+    let side2 = side_block(&blockhead, &block1, 12);
+    let checkpoint = blockhead.produce_block().unwrap();
+    blockhead.import_block(&side2).unwrap();
+
+    // Attestations must be signed even on a chain that takes unsigned transactions.
+    let mut attestation = testkit::attestation(
+        checkpoint.hash,
+        0,
+        blockhead.config.chain_id,
+        blockhead.config.hash,
+    );
+    let signatures = std::mem::take(&mut attestation.signatures);
+    assert!(!blockhead.config.require_signatures);
+    assert!(blockhead
+        .send_transaction(attestation.clone())
+        .await
+        .is_err());
+    attestation.signatures = signatures;
+    blockhead.send_transaction(attestation).await.unwrap();
+    let block3 = blockhead.produce_block().unwrap();
+    assert_eq!(block3.body.transactions.len(), 1);
+    let finalized = blockhead
+        .get_block(BlockId::Finalized)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(finalized.hash, checkpoint.hash);
+
+    assert!(blockhead
+        .import_block(&side_block(&blockhead, &block1, 22))
+        .is_err());
+    let side3 = side_block(&blockhead, &side2, 13);
+    blockhead.import_block(&side3).unwrap();
+    let side4 = side_block(&blockhead, &side3, 14);
+    assert!(blockhead.import_block(&side4).is_err());
+    assert_eq!(blockhead.head().unwrap().hash, block3.hash);
+}
+
+#[test]
+fn test_block_timestamps_are_validated() {
+    let (blockhead, _) = staked_chain(32);
+    let block1 = blockhead.produce_block().unwrap();
+    let block2 = blockhead.produce_block().unwrap();
+    assert!(block2.timestamp > block1.timestamp);
+
+    // At or before the median of [block2, block1, genesis].
+    assert!(blockhead
+        .import_block(&side_block(&blockhead, &block2, block1.timestamp))
+        .is_err());
+    // Too far ahead of the clock.
+    let drift = blockhead.config.timestamp.max_future_drift.as_nanos() as u64;
+    assert!(blockhead
+        .import_block(&side_block(&blockhead, &block2, drift + 1))
+        .is_err());
+    blockhead
+        .import_block(&side_block(&blockhead, &block2, drift))
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_intrinsic_gas_is_enforced_and_charged() {
+    use crate::address::Address;
+    use crate::block::Body;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    let mut transfer = Transaction {
+        data: vec![0; 10].into(),
+        gas_price: 1,
+        ..testkit::transfer(validator, Address([8; 32]), 5, 0)
+    };
+    assert!(blockhead.send_transaction(transfer.clone()).await.is_err());
+    transfer.gas_limit = 21_160;
+    blockhead.send_transaction(transfer.clone()).await.unwrap();
+    let block = blockhead.produce_block().unwrap();
+    // The sender is the proposer, so the fee comes back as its reward.
+    let reward = blockhead
+        .get_block_rewards(BlockId::Hash(block.hash))
+        .await
+        .unwrap();
+    assert_eq!(reward.fees, 21_160);
+    assert_eq!(blockhead.get_balance(validator).await.unwrap(), 100_000 - 5);
+
+    // The same transaction, under-gassed, also invalidates a block that carries it.
+    let head = blockhead.head().unwrap();
+    transfer.gas_limit = 100;
+    let body = Body {
+        transactions: vec![(transfer.compute_hash(algorithm), transfer)],
+    };
+    let block = Block::new(
+        algorithm,
+        head.hash,
+        head.timestamp + 1,
+        head.number + 1,
+        validator,
+        head.state_root,
+        head.gas_limit,
+        body,
+    );
+    assert!(blockhead.import_block(&block).is_err());
+}
+
+#[tokio::test]
+async fn test_block_gas_limit() {
+    use crate::address::Address;
+    use crate::block::Body;
+    use crate::gas::GasConfig;
+    use crate::genesis::{ChainConfig, Genesis};
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let validator = testkit::validator();
+    let genesis = Genesis {
+        alloc: vec![(validator, 100_000)],
+        validators: vec![(validator, 100)],
+        config: ChainConfig {
+            gas: GasConfig {
+                block_gas_target: 50_000,
+                ..Default::default()
+            },
+            ..testkit::config()
+        },
+        ..Default::default()
+    };
+    let blockhead = Blockhead::with_genesis(":memory:", genesis)
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap()
+        .with_clock(std::sync::Arc::new(crate::clock::ManualClock::new(0)));
+    let transfer = |nonce| testkit::transfer(validator, Address([8; 32]), 1, nonce);
+    let too_big = Transaction {
+        gas_limit: 50_001,
+        ..transfer(0)
+    };
+    assert!(blockhead.send_transaction(too_big).await.is_err());
+    let mut hashes = Vec::new();
+    for nonce in 0..3 {
+        hashes.push(blockhead.send_transaction(transfer(nonce)).await.unwrap());
+    }
+
+    // Two transfers fill the block; the third waits for the next one.
+    let block1 = blockhead.produce_block().unwrap();
+    assert_eq!(block1.gas_limit, 50_000);
+    assert_eq!(block1.body.transactions.len(), 2);
+    assert_eq!(
+        blockhead.get_transaction_status(hashes[2]).await.unwrap(),
+        TransactionStatus::Pending
+    );
+    let block2 = blockhead.produce_block().unwrap();
+    assert_eq!(block2.body.transactions[0].0, hashes[2]);
+
+    // A block may not move the limit further than the adjustment allows.
+    let jump = Block::new(
+        algorithm,
+        block2.hash,
+        block2.timestamp + 1,
+        block2.number + 1,
+        validator,
+        block2.state_root,
+        block2.gas_limit + 50_000 / 1024 + 1,
+        Body::default(),
+    );
+    assert!(blockhead.import_block(&jump).is_err());
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_contract_deployment() {
+    use crate::address::Address;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    let deployment = Transaction {
+        value: 7,
+        data: vec![0xde, 0xad].into(),
+        gas_limit: 53_032,
+        ..testkit::transaction(TransactionKind::Transfer, validator, 0)
+    };
+    let hash = blockhead
+        .send_transaction(deployment.clone())
+        .await
+        .unwrap();
+    // Replaying the same nonce without outbidding the first copy is rejected by the mempool.
+    assert!(blockhead.send_transaction(deployment).await.is_err());
+    let block = blockhead.produce_block().unwrap();
+    assert_eq!(block.body.transactions.len(), 1);
+
+    let contract = Address::for_contract(validator, 0, algorithm);
+    let receipt = blockhead
+        .get_transaction_receipt(hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(receipt.contract_address, Some(contract));
+    assert_eq!(receipt.block_hash, block.hash);
+    assert_eq!(blockhead.get_balance(contract).await.unwrap(), 7);
+    assert_eq!(blockhead.get_nonce(validator).await.unwrap(), 1);
+    assert_eq!(
+        db::read_code(&blockhead.connection, contract).unwrap(),
+        Some(vec![0xde, 0xad])
+    );
+}
+
+#[cfg(not(feature = "vm"))]
+#[tokio::test]
+async fn test_contracts_need_the_vm() {
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+
+    let (blockhead, validator) = staked_chain(32);
+    let deployment = Transaction {
+        data: vec![0xde, 0xad].into(),
+        gas_limit: 53_032,
+        ..testkit::transaction(TransactionKind::Transfer, validator, 0)
+    };
+    let hash = blockhead.send_transaction(deployment).await.unwrap();
+    let block = blockhead.produce_block().unwrap();
+    assert!(block.body.transactions.is_empty());
+    let status = blockhead.get_transaction_status(hash).await.unwrap();
+    assert!(format!("{status:?}").contains("vm feature"), "{status:?}");
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_contract_revert_is_recorded() {
+    use crate::address::Address;
+    use crate::error::ErrorKind;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::vm::{opcode::*, push};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    // Store the first call data word in slot 0, reverting with 99 if it is zero.
+    let prelude = [
+        push(0),
+        vec![CALLDATALOAD, DUP],
+        push(0),
+        vec![SSTORE, ISZERO],
+    ]
+    .concat();
+    let revert = [
+        push(99),
+        push(0),
+        vec![MSTORE],
+        push(8),
+        push(0),
+        vec![REVERT],
+    ]
+    .concat();
+    let code = [
+        prelude.clone(),
+        push(prelude.len() as u64 + 11),
+        vec![JUMPI, STOP],
+        revert,
+    ]
+    .concat();
+    let deployment = Transaction {
+        data: code.into(),
+        gas_limit: 100_000,
+        ..testkit::transaction(TransactionKind::Transfer, validator, 0)
+    };
+    blockhead.send_transaction(deployment).await.unwrap();
+    blockhead.produce_block().unwrap();
+    let contract = Address::for_contract(validator, 0, algorithm);
+
+    let invoke = |word: u64, nonce| Transaction {
+        data: word.to_be_bytes().to_vec().into(),
+        gas_limit: 50_000,
+        gas_price: 1,
+        ..testkit::transfer(validator, contract, 5, nonce)
+    };
+    let balance = blockhead.get_balance(validator).await.unwrap();
+    let hash = blockhead.send_transaction(invoke(0, 1)).await.unwrap();
+    let block = blockhead.produce_block().unwrap();
+    let receipt = blockhead
+        .get_transaction_receipt(hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!receipt.status);
+    assert_eq!(receipt.return_data, 99u64.to_be_bytes());
+    assert!(receipt.gas_used > 21_128 && receipt.gas_used < 50_000);
+    // Only the gas used is charged; the value transfer is rolled back. The sender proposed the
+    // block, so the fee comes back to it.
+    let reward = blockhead
+        .get_block_rewards(BlockId::Hash(block.hash))
+        .await
+        .unwrap();
+    assert_eq!(reward.fees, receipt.gas_used);
+    assert_eq!(blockhead.get_balance(validator).await.unwrap(), balance);
+    assert_eq!(blockhead.get_balance(contract).await.unwrap(), 0);
+    assert_eq!(blockhead.get_nonce(validator).await.unwrap(), 2);
+
+    let error = blockhead
+        .call(
+            contract,
+            0u64.to_be_bytes().to_vec(),
+            Default::default(),
+            BlockId::Latest,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::Reverted {
+            return_data: 99u64.to_be_bytes().to_vec()
+        }
+    );
+    assert_eq!(
+        blockhead
+            .call(
+                contract,
+                3u64.to_be_bytes().to_vec(),
+                Default::default(),
+                BlockId::Latest
+            )
+            .await
+            .unwrap(),
+        Vec::<u8>::new()
+    );
+    // The call's storage write was discarded.
+    assert_eq!(
+        db::read_storage(&blockhead.connection, contract, 0).unwrap(),
+        0
+    );
+
+    let hash = blockhead.send_transaction(invoke(3, 2)).await.unwrap();
+    blockhead.produce_block().unwrap();
+    let receipt = blockhead
+        .get_transaction_receipt(hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(receipt.status);
+    assert_eq!(
+        db::read_storage(&blockhead.connection, contract, 0).unwrap(),
+        3
+    );
+    assert_eq!(blockhead.get_balance(contract).await.unwrap(), 5);
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_call_at_historical_block() {
+    use crate::address::Address;
+    use crate::block::BlockId;
+    use crate::execution::CallOverrides;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::vm::{opcode::*, push};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    // With call data, store its first word in slot 0. Without, return slot 0 plus the value.
+    let getter = [
+        push(0),
+        vec![SLOAD, CALLVALUE, ADD],
+        push(0),
+        vec![MSTORE],
+        push(8),
+        push(0),
+        vec![RETURN],
+    ]
+    .concat();
+    let setter = [push(0), vec![CALLDATALOAD], push(0), vec![SSTORE, STOP]].concat();
+    let code = [
+        vec![CALLDATASIZE],
+        push(11 + getter.len() as u64),
+        vec![JUMPI],
+        getter,
+        setter,
+    ]
+    .concat();
+    let transaction = |to_address, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        data: data.into(),
+        gas_limit: 60_000,
+        ..testkit::transaction(TransactionKind::Transfer, validator, nonce)
+    };
+    blockhead
+        .send_transaction(transaction(None, code, 0))
+        .await
+        .unwrap();
+    blockhead.produce_block().unwrap();
+    let contract = Address::for_contract(validator, 0, algorithm);
+    let set = |word: u64, nonce| transaction(Some(contract), word.to_be_bytes().to_vec(), nonce);
+    blockhead.send_transaction(set(3, 1)).await.unwrap();
+    let block2 = blockhead.produce_block().unwrap();
+    blockhead.send_transaction(set(5, 2)).await.unwrap();
+    blockhead.produce_block().unwrap();
+
+    let get = |overrides, block| blockhead.call(contract, vec![], overrides, block);
+    let word = |value: u64| value.to_be_bytes().to_vec();
+    assert_eq!(
+        get(Default::default(), BlockId::Latest).await.unwrap(),
+        word(5)
+    );
+    assert_eq!(
+        get(Default::default(), BlockId::Number(2)).await.unwrap(),
+        word(3)
+    );
+    assert_eq!(
+        get(Default::default(), BlockId::Hash(block2.hash))
+            .await
+            .unwrap(),
+        word(3)
+    );
+    assert_eq!(
+        get(Default::default(), BlockId::Number(1)).await.unwrap(),
+        word(0)
+    );
+    assert!(get(Default::default(), BlockId::Number(9)).await.is_err());
+    assert_eq!(
+        blockhead
+            .get_balance_at(validator, BlockId::Finalized)
+            .await
+            .unwrap(),
+        100_000
+    );
+
+    let paid = CallOverrides {
+        from: Some(validator),
+        value: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(get(paid, BlockId::Latest).await.unwrap(), word(7));
+    let starved = CallOverrides {
+        gas: Some(10),
+        ..Default::default()
+    };
+    assert!(get(starved, BlockId::Latest).await.is_err());
+    let broke = CallOverrides {
+        value: Some(1),
+        ..Default::default()
+    };
+    assert!(get(broke, BlockId::Latest).await.is_err());
+    // The historical calls left the chain untouched.
+    assert_eq!(blockhead.head().unwrap().number, 3);
+    assert_eq!(blockhead.get_balance(contract).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_raw_block_and_transaction() {
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    let transfer = Transaction {
+        data: vec![1, 2].into(),
+        gas_limit: 21_032,
+        ..testkit::transfer(validator, crate::address::Address([8; 32]), 5, 0)
+    };
+    let hash = blockhead.send_transaction(transfer).await.unwrap();
+    let block = blockhead.produce_block().unwrap();
+
+    let raw = blockhead.get_raw_block(block.hash).await.unwrap().unwrap();
+    let decoded = Block::decode(
+        &hex::decode(raw.strip_prefix("0x").unwrap()).unwrap(),
+        algorithm,
+    )
+    .unwrap();
+    assert_eq!(decoded.hash, block.hash);
+    assert_eq!(decoded.body.transactions[0].0, hash);
+
+    let raw = blockhead.get_raw_transaction(hash).await.unwrap().unwrap();
+    let bytes = hex::decode(raw.strip_prefix("0x").unwrap()).unwrap();
+    let transaction = crate::transaction::Transaction::decode(&bytes).unwrap();
+    assert_eq!(transaction.compute_hash(algorithm), hash);
+    assert!(blockhead
+        .get_raw_transaction(Hash([0; 32]))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_headers() {
+    use crate::block::Body;
+    use crate::hash::HashAlgorithm;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = staked_chain(32);
+    let block = blockhead.produce_block().unwrap();
+    let header = blockhead
+        .get_header(BlockId::Number(block.number))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(header, block.header);
+    assert_eq!(header.proposer, validator);
+    assert_eq!(
+        header.transactions_root,
+        Body::default().transactions_root(algorithm)
+    );
+    assert_eq!(
+        blockhead
+            .get_header(BlockId::Hash(block.hash))
+            .await
+            .unwrap(),
+        Some(header)
+    );
+
+    let mut tampered = block.clone();
+    tampered.header.transactions_root = Hash([1; 32]);
+    assert!(tampered.check_contents(algorithm).is_err());
+}
+
+#[tokio::test]
+async fn test_pending_block() {
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = staked_chain(32);
+    let recipient = crate::address::Address([8; 32]);
+    let transfer = testkit::transfer(validator, recipient, 5, 0);
+    let hash = blockhead.send_transaction(transfer).await.unwrap();
+
+    let pending = blockhead
+        .get_block(BlockId::Pending)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pending.number, 1);
+    assert_eq!(pending.parent_hash, blockhead.head().unwrap().hash);
+    assert_eq!(pending.body.transactions[0].0, hash);
+    assert_eq!(
+        blockhead
+            .get_balance_at(recipient, BlockId::Pending)
+            .await
+            .unwrap(),
+        5
+    );
+    // Building the pending block changed neither the chain nor the mempool.
+    assert_eq!(blockhead.head().unwrap().number, 0);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 0);
+    assert!(blockhead.canonical_block(BlockId::Pending).is_err());
+
+    let produced = blockhead.produce_block().unwrap();
+    assert_eq!(produced.body.transactions[0].0, hash);
+}
+
+#[tokio::test]
+async fn test_build_time_defers_transactions() {
+    use crate::address::Address;
+    use crate::testkit::{self, TestChain};
+    use std::time::Duration;
+
+    let mut config = testkit::config();
+    config.mempool.build_time = Some(Duration::ZERO);
+    let chain = TestChain::with_config(config);
+    let recipient = Address([8; 32]);
+    for _ in 0..3 {
+        chain.transfer(chain.validator, recipient, 1).await;
+    }
+    // With no time to spare, each block takes only the first transaction.
+    for remaining in [2, 1, 0] {
+        assert_eq!(chain.produce().body.transactions.len(), 1);
+        assert_eq!(chain.blockhead.mempool.lock().unwrap().len(), remaining);
+    }
+    chain.assert_balance(recipient, 3);
+}
+
+#[tokio::test]
+async fn test_chains_of_different_hash_algorithms() {
+    use crate::address::Address;
+    use crate::genesis::ChainConfig;
+    use crate::hash::HashAlgorithm;
+    use crate::signer::Signer;
+    use crate::testkit::{self, TestChain};
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    // Hashing for one chain must not fix the algorithm of the next one the process opens.
+    let key = testkit::validator_key();
+    let blake2s = key.address(HashAlgorithm::Blake2s).unwrap();
+    let default_chain = TestChain::new();
+    let keccak_chain = TestChain::with_config(ChainConfig {
+        hash: HashAlgorithm::Keccak256,
+        require_signatures: true,
+        ..testkit::config()
+    });
+    let keccak = key.address(HashAlgorithm::Keccak256).unwrap();
+    assert_ne!(keccak, blake2s);
+    assert_eq!(keccak_chain.validator, keccak);
+    assert_eq!(default_chain.validator, blake2s);
+
+    let recipient = Address([8; 32]);
+    for (chain, algorithm) in [
+        (&keccak_chain, HashAlgorithm::Keccak256),
+        (&default_chain, HashAlgorithm::Blake2s),
+    ] {
+        let blockhead = &chain.blockhead;
+        let mut transfer = testkit::transfer(chain.validator, recipient, 5, 0);
+        transfer
+            .sign(&key, blockhead.config.chain_id, algorithm)
+            .unwrap();
+        let hash = chain.send(transfer.clone()).await;
+        assert_eq!(hash, transfer.compute_hash(algorithm));
+        let block = chain.produce();
+        assert_eq!(block.body.transactions[0].0, hash);
+        chain.assert_balance(recipient, 5);
+        let receipt = blockhead.get_transaction_receipt(hash).await.unwrap();
+        assert!(receipt.unwrap().status);
+
+        let raw = blockhead.get_raw_block(block.hash).await.unwrap().unwrap();
+        let bytes = hex::decode(raw.strip_prefix("0x").unwrap()).unwrap();
+        assert_eq!(Block::decode(&bytes, algorithm).unwrap().hash, block.hash);
+        let proof = blockhead.get_proof(recipient, BlockId::Latest).await;
+        assert!(proof.unwrap().unwrap().verify(block.state_root, algorithm));
+        assert_eq!(blockhead.hash_algorithm().await, algorithm);
+    }
+    // Each chain's blocks only check out under its own algorithm.
+    let head = keccak_chain.head();
+    assert!(head.check_contents(HashAlgorithm::Keccak256).is_ok());
+    assert!(head.check_contents(HashAlgorithm::Blake2s).is_err());
+    // A signature over the transaction's Blake2s signing hash is worthless on the Keccak chain.
+    let chain_id = keccak_chain.blockhead.config.chain_id;
+    let mut transfer: Transaction = testkit::transfer(keccak, recipient, 5, 1);
+    let signing_hash = transfer.signing_hash(chain_id, HashAlgorithm::Blake2s);
+    transfer.signatures = vec![key.sign_hash(signing_hash).unwrap()];
+    assert!(keccak_chain
+        .blockhead
+        .send_transaction(transfer)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_admission_during_production() {
+    use crate::address::Address;
+    use crate::error::ErrorKind;
+    use crate::genesis::ChainConfig;
+    use crate::mempool::MempoolConfig;
+    use crate::testkit::{self, TestChain, VALIDATOR_BALANCE};
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let chain = TestChain::with_config(ChainConfig {
+        mempool: MempoolConfig {
+            max_per_sender: 2,
+            ..testkit::config().mempool
+        },
+        ..testkit::config()
+    });
+    let (validator, recipient) = (chain.validator, Address([8; 32]));
+    let transfer = |value, nonce| testkit::transfer(validator, recipient, value, nonce);
+    chain.send(transfer(5, 0)).await;
+    chain.send(transfer(5, 1)).await;
+    let newcomer = testkit::transfer(Address([9; 32]), recipient, 0, 0);
+    let newcomer_hash = newcomer.compute_hash(chain.blockhead.config.hash);
+    let (blockhead, block, ()) = produce_during(chain.blockhead, |blockhead| async move {
+        // The block being built holds both transfers, but the pool still counts them: they
+        // commit the sender's balance, fill its quota, and can only be replaced by outbidding.
+        let error = blockhead
+            .send_transaction(transfer(5, 1))
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Underpriced { .. }));
+        let overdraft = transfer(VALIDATOR_BALANCE - 5, 2);
+        let error = blockhead.send_transaction(overdraft).await.unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::InsufficientFunds { .. }));
+        let error = blockhead
+            .send_transaction(transfer(0, 2))
+            .await
+            .unwrap_err();
+        assert!(error
+            .message()
+            .contains("already has 2 transactions waiting"));
+        blockhead.send_transaction(newcomer).await.unwrap();
+    })
+    .await;
+    assert_eq!(block.body.transactions.len(), 2);
+    // The transfers left the pool with the block; the transaction that arrived meanwhile stays.
+    let algorithm = blockhead.config.hash;
+    let pending: Vec<Transaction> = blockhead.mempool.lock().unwrap().pending();
+    let pending: Vec<_> = pending.iter().map(|t| t.compute_hash(algorithm)).collect();
+    assert_eq!(pending, [newcomer_hash]);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn test_pending_nonce_during_production() {
+    use crate::address::Address;
+    use crate::client;
+    use crate::status::TransactionStatus;
+    use crate::testkit::{self, TestChain};
+    use crate::Blockchain;
+
+    let chain = TestChain::new();
+    let (validator, recipient) = (chain.validator, Address([8; 32]));
+    let transfer = |value, nonce| testkit::transfer(validator, recipient, value, nonce);
+    chain.send(transfer(5, 0)).await;
+    let stuck = chain.send(transfer(5, 1)).await;
+    let (blockhead, block, cancellation) =
+        produce_during(chain.blockhead, |blockhead| async move {
+            // The transfers being built into the block are still pending to clients.
+            assert_eq!(blockhead.get_pending_nonce(validator).await.unwrap(), 2);
+            assert_eq!(blockhead.get_nonce(validator).await.unwrap(), 0);
+            let pending = blockhead.get_transaction(stuck).await.unwrap().unwrap();
+            assert_eq!(pending.nonce, 1);
+            let status = blockhead.get_transaction_status(stuck).await.unwrap();
+            assert_eq!(status, TransactionStatus::Pending);
+            let cancellation =
+                client::cancel_transaction(&*blockhead, stuck, &testkit::validator_key())
+                    .await
+                    .unwrap();
+            blockhead.send_transaction(transfer(5, 2)).await.unwrap();
+            assert_eq!(blockhead.get_pending_nonce(validator).await.unwrap(), 3);
+            cancellation
+        })
+        .await;
+    // The block took the transfer the cancellation was to replace, so the cancellation is dropped
+    // rather than left to fail inclusion; the transfer admitted at the pending nonce remains.
+    assert_eq!(block.body.transactions.len(), 2);
+    assert_eq!(block.body.transactions[1].0, stuck);
+    let status = blockhead
+        .get_transaction_status(cancellation)
+        .await
+        .unwrap();
+    assert!(matches!(status, TransactionStatus::Dropped { .. }));
+    assert_eq!(blockhead.get_nonce(validator).await.unwrap(), 2);
+    assert_eq!(blockhead.get_pending_nonce(validator).await.unwrap(), 3);
+    assert_eq!(blockhead.mempool.lock().unwrap().pending().len(), 1);
+}
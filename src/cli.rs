@@ -0,0 +1,854 @@
+//! Command-line entry points.
+//!
+//! Wherever a command takes an address, it also accepts a name from the address book. See
+//! [`crate::address_book`].
+//!
+//! Commands that read an existing chain, such as `db check` and `balance`, take its data
+//! directory, or its database file alone. See [`open_existing`].
+//!
+//! With `--json` anywhere among the arguments, every command prints its result as one JSON
+//! value per line instead of text, for scripts. Errors are reported on stderr either way.
+//!
+//! With `--explorer`, `run` and `console` also serve the block explorer. See [`crate::explorer`].
+use crate::address_book::{self, AddressBook};
+use crate::attach::{self, Attached};
+use crate::console::{self, Console};
+use crate::dev;
+use crate::error::{Error, Result};
+use crate::genesis::Genesis;
+use crate::hash::{decode_hex32, Hash, HashAlgorithm};
+use crate::http::{self, Endpoint};
+use crate::import_queue;
+use crate::indexer;
+use crate::maintenance::{self, MaintenanceConfig};
+use crate::mempool;
+use crate::multisig::MultisigPolicy;
+use crate::peers;
+use crate::remote::Remote;
+use crate::spec::{self, DataDir};
+use crate::transaction::Transaction;
+use crate::wallet::{self, Wallet};
+use crate::{Blockchain, Blockhead};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+const USAGE: &str =
+    "usage: blockhead [--json] [--explorer] [init [--chain dev|test|<spec>] <dir> | run [--dev] <dir> \
+                     | run --dev | console [--dev] <dir> | console --dev | db check <path> | db repair <path> | db compact <path> \
+                     | replay --from <block> --to <block> <dir> | balance <path> <address> | address add <name> <address> \
+                     | address list | address remove <name> | wallet new \
+                     | wallet address [--chain dev|test|<spec>] <index> \
+                     | multisig address [--chain dev|test|<spec>] <threshold> <signer>... \
+                     | multisig sign [--chain dev|test|<spec>] <index> <transaction> \
+                     | multisig combine [--chain dev|test|<spec>] <transaction>... \
+                     | snapshot export <path> <snapshot> \
+                     | snapshot import [--chain dev|test|<spec>] <snapshot> <path> <checkpoint> \
+                     | call <endpoint>[,<endpoint>...] <path> [<body>] \
+                     | attach [<endpoint>[,<endpoint>...]] \
+                     | faucet <endpoint> <address> \
+                     | send <endpoint> --account <index> --to <address> --value <amount> [--gas-price <price>] \
+                       [--wait <confirmations>] \
+                     | cancel <endpoint> --account <index> <hash>]";
+
+/// How a command prints its results.
+#[derive(Debug, Clone, Copy)]
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    /// Print `value` on a line of its own with `--json`, and otherwise the text `text` renders.
+    fn emit(self, value: Value, text: impl FnOnce(&Value) -> String) {
+        if self.json {
+            println!("{value}");
+        } else {
+            println!("{}", text(&value));
+        }
+    }
+}
+
+pub(crate) async fn run(args: &[String]) -> Result<()> {
+    let out = Output {
+        json: args.iter().any(|arg| arg == "--json"),
+    };
+    let explorer = args.iter().any(|arg| arg == "--explorer");
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json" && *arg != "--explorer")
+        .collect();
+    let node = NodeOptions {
+        explorer,
+        dev: false,
+    };
+    match args.as_slice() {
+        [] => demo(out).await,
+        ["init", dir] => init(out, "dev", dir).await,
+        ["init", "--chain", chain, dir] => init(out, chain, dir).await,
+        ["run", "--dev"] => run_node(out, node, None, true).await,
+        ["run", "--dev", dir] => run_node(out, node, Some(dir), true).await,
+        ["run", dir] => run_node(out, node, Some(dir), false).await,
+        ["console", "--dev"] => run_console(out, node, None, true).await,
+        ["console", "--dev", dir] => run_console(out, node, Some(dir), true).await,
+        ["console", dir] => run_console(out, node, Some(dir), false).await,
+        ["db", "check", path] => db_check(out, path),
+        ["db", "repair", path] => db_repair(out, path),
+        ["db", "compact", path] => db_compact(out, path),
+        ["replay", "--from", from, "--to", to, dir] => replay(out, from, to, dir),
+        ["balance", path, address] => balance(out, path, address).await,
+        ["address", "add", name, address] => address_add(out, name, address),
+        ["address", "list"] => address_list(out),
+        ["address", "remove", name] => address_remove(out, name),
+        ["wallet", "new"] => wallet_new(out),
+        ["wallet", "address", index] => wallet_address(out, "dev", index),
+        ["wallet", "address", "--chain", chain, index] => wallet_address(out, chain, index),
+        ["multisig", "address", "--chain", chain, threshold, signers @ ..]
+            if !signers.is_empty() =>
+        {
+            multisig_address(out, chain, threshold, signers)
+        }
+        ["multisig", "address", threshold, signers @ ..] if !signers.is_empty() => {
+            multisig_address(out, "dev", threshold, signers)
+        }
+        ["multisig", "sign", index, transaction] => multisig_sign(out, "dev", index, transaction),
+        ["multisig", "sign", "--chain", chain, index, transaction] => {
+            multisig_sign(out, chain, index, transaction)
+        }
+        ["multisig", "combine", "--chain", chain, transactions @ ..]
+            if !transactions.is_empty() =>
+        {
+            multisig_combine(out, chain, transactions)
+        }
+        ["multisig", "combine", transactions @ ..] if !transactions.is_empty() => {
+            multisig_combine(out, "dev", transactions)
+        }
+        ["snapshot", "export", path, snapshot] => snapshot_export(out, path, snapshot),
+        ["snapshot", "import", snapshot, path, checkpoint] => {
+            snapshot_import(out, Genesis::default(), snapshot, path, checkpoint)
+        }
+        ["snapshot", "import", "--chain", chain, snapshot, path, checkpoint] => {
+            snapshot_import(out, spec::load(chain)?, snapshot, path, checkpoint)
+        }
+        ["call", endpoint, target] => call(out, endpoint, target, None).await,
+        ["call", endpoint, target, body] => call(out, endpoint, target, Some(body)).await,
+        ["attach"] => attach(None).await,
+        ["attach", endpoint] => attach(Some(endpoint)).await,
+        ["faucet", endpoint, address] => faucet(out, endpoint, address).await,
+        ["send", endpoint, flags @ ..] => send(out, endpoint, flags).await,
+        ["cancel", endpoint, "--account", index, hash] => cancel(out, endpoint, index, hash).await,
+        _ => Err(Error::new(USAGE)),
+    }
+}
+
+/// The runtime to [`run`] `args` on: for `run` and `console` with a data directory, sized by its
+/// node config's `runtime` table, and otherwise with tokio's defaults.
+pub(crate) fn runtime(args: &[String]) -> Result<tokio::runtime::Runtime> {
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let config = match args.as_slice() {
+        ["run" | "console", dir] => {
+            let dir = DataDir::new(dir);
+            // A directory without a config fails to open later, with a better message.
+            match dir.config_path().exists() {
+                true => dir.node_config()?.runtime,
+                false => Default::default(),
+            }
+        }
+        _ => Default::default(),
+    };
+    config.build()
+}
+
+async fn demo(out: Output) -> Result<()> {
+    let client = Blockhead::new(":memory:")?;
+    let balance = client.get_balance(crate::address::Address::zero()).await?;
+    let gas_price = client.gas_price().await;
+    out.emit(json!({"balance": balance, "gas_price": gas_price}), |_| {
+        format!("Balance: {}, Gas Price: {}", balance, gas_price)
+    });
+    Ok(())
+}
+
+/// Create a data directory at `dir` for the chain described by `chain`, a preset name or a spec
+/// file. Presets fund the public dev accounts, whose seed phrase is printed for importing into a
+/// wallet.
+async fn init(out: Output, chain: &str, dir: &str) -> Result<()> {
+    let genesis = spec::load(chain)?;
+    let dir = DataDir::new(dir);
+    let blockhead = dir.init(&genesis)?;
+    let mut balances = serde_json::Map::new();
+    for (address, _) in &genesis.alloc {
+        let balance = blockhead.get_balance(*address).await?;
+        balances.insert(address.to_string(), balance.into());
+    }
+    let seed_phrase = spec::preset(chain)?.map(|_| spec::dev_mnemonic());
+    out.emit(
+        json!({
+            "path": dir.path,
+            "genesis": blockhead.head()?.hash,
+            "seed_phrase": seed_phrase,
+            "balances": balances,
+        }),
+        |value| {
+            let mut lines = vec![format!(
+                "initialized {} at genesis block {}",
+                dir.path.display(),
+                value["genesis"].as_str().unwrap()
+            )];
+            if let Some(seed_phrase) = &seed_phrase {
+                lines.push(format!("dev seed phrase: {seed_phrase}"));
+            }
+            lines.extend(
+                balances
+                    .iter()
+                    .map(|(address, balance)| format!("{address} {balance}")),
+            );
+            lines.join("\n")
+        },
+    );
+    Ok(())
+}
+
+/// Where a dev chain in memory serves HTTP. See [`crate::http`].
+const DEV_HTTP_ADDRESS: &str = "127.0.0.1:8545";
+
+/// How `run` and `console` override the node config.
+#[derive(Debug, Clone, Copy)]
+struct NodeOptions {
+    /// Serve the block explorer. See [`crate::explorer`].
+    explorer: bool,
+    /// Serve the `/dev` endpoints. See [`crate::dev`].
+    dev: bool,
+}
+
+/// Open the node in data directory `dir`, or without one, a throwaway chain of the `dev` preset
+/// in memory, serving HTTP at [`DEV_HTTP_ADDRESS`].
+fn open_node(out: Output, node: NodeOptions, dir: Option<&str>) -> Result<Arc<Blockhead>> {
+    let blockhead = match dir {
+        Some(dir) => {
+            let dir = DataDir::new(dir);
+            let mut genesis = dir.genesis()?;
+            genesis.config.http.explorer |= node.explorer;
+            genesis.config.http.dev |= node.dev;
+            let blockhead = dir.open_with(genesis)?;
+            let restored = blockhead.restore_mempool()?;
+            if restored > 0 {
+                log::info!("restored {restored} pending transactions");
+            }
+            blockhead
+        }
+        None => {
+            let mut genesis = spec::load("dev")?;
+            genesis.config.http.listen = Some(DEV_HTTP_ADDRESS.to_string());
+            genesis.config.http.explorer |= node.explorer;
+            genesis.config.http.dev |= node.dev;
+            genesis.config.faucet.enabled = true;
+            print_dev_accounts(out, &genesis)?;
+            spec::open_dev(":memory:", genesis)?
+        }
+    };
+    let http = &blockhead.config.http;
+    if http.explorer {
+        match &http.listen {
+            Some(address) => {
+                let url = format!("http://{address}/explorer");
+                out.emit(json!({"explorer": url}), |_| format!("explorer at {url}"));
+            }
+            None => log::warn!("the explorer is on, but HTTP is not: set http.listen"),
+        }
+    }
+    Ok(Arc::new(blockhead))
+}
+
+/// Print the dev seed phrase and the keys of the dev accounts `genesis` funds, which are the
+/// same every run, so that scripts can sign with them.
+fn print_dev_accounts(out: Output, genesis: &Genesis) -> Result<()> {
+    let seed_phrase = spec::dev_mnemonic();
+    let accounts: Vec<Value> = spec::funded_dev_accounts(genesis)?
+        .into_iter()
+        .map(|(index, account)| {
+            json!({
+                "index": index,
+                "address": account.address(genesis.config.hash),
+                "private_key": format!("0x{}", hex::encode(account.key.to_bytes())),
+            })
+        })
+        .collect();
+    out.emit(
+        json!({"seed_phrase": seed_phrase, "accounts": accounts}),
+        |value| {
+            let mut lines = vec![format!("dev seed phrase: {seed_phrase}")];
+            lines.extend(value["accounts"].as_array().unwrap().iter().map(|account| {
+                format!(
+                    "account {}: {} key {}",
+                    account["index"],
+                    account["address"].as_str().unwrap(),
+                    account["private_key"].as_str().unwrap()
+                )
+            }));
+            lines.join("\n")
+        },
+    );
+    Ok(())
+}
+
+/// Start the node's background tasks. With `dev`, seal a block as soon as a transaction arrives.
+/// With a data directory `dir`, reload its node config on `SIGHUP`.
+fn spawn_tasks(blockhead: &Arc<Blockhead>, dir: Option<&str>, dev: bool) -> Vec<JoinHandle<()>> {
+    let mut tasks = vec![
+        tokio::spawn(maintenance::run(
+            blockhead.clone(),
+            MaintenanceConfig::default(),
+        )),
+        tokio::spawn(indexer::run(blockhead.clone())),
+        tokio::spawn(import_queue::run(blockhead.clone())),
+        tokio::spawn(peers::run(blockhead.clone())),
+        tokio::spawn(serve_http(
+            blockhead.clone(),
+            dir.map(|dir| DataDir::new(dir).ipc_path()),
+        )),
+        tokio::spawn(mempool::run(blockhead.clone())),
+    ];
+    if dev {
+        tasks.push(tokio::spawn(dev::run(blockhead.clone())));
+    }
+    #[cfg(unix)]
+    if let Some(dir) = dir {
+        tasks.push(tokio::spawn(reload_on_hangup(
+            blockhead.clone(),
+            DataDir::new(dir),
+        )));
+    }
+    tasks
+}
+
+/// Reload the node config in `dir` whenever the process gets `SIGHUP`, until the task is
+/// dropped. See [`DataDir::reload`].
+#[cfg(unix)]
+async fn reload_on_hangup(blockhead: Arc<Blockhead>, dir: DataDir) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            log::error!("cannot listen for SIGHUP: {error}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(error) = dir.reload(&blockhead) {
+            log::error!("reloading the node config failed: {error}");
+        }
+    }
+}
+
+/// Stop the tasks of [`spawn_tasks`], then save the mempool of a node with a data directory and
+/// remove its IPC socket.
+fn stop_tasks(blockhead: &Blockhead, tasks: Vec<JoinHandle<()>>, dir: Option<&str>) {
+    for task in tasks {
+        task.abort();
+    }
+    if let Some(dir) = dir {
+        if let Err(error) = blockhead.persist_mempool() {
+            log::error!("saving the mempool failed: {error}");
+        }
+        if blockhead.config.http.ipc {
+            let _ = std::fs::remove_file(DataDir::new(dir).ipc_path());
+        }
+    }
+}
+
+/// Serve HTTP, and with a data directory, on the Unix socket `ipc` in it. See [`http::run`].
+async fn serve_http(blockhead: Arc<Blockhead>, ipc: Option<PathBuf>) {
+    if let Err(error) = http::run(blockhead, ipc.as_deref()).await {
+        log::error!("HTTP server failed: {error}");
+    }
+}
+
+/// Run the node until interrupted or asked to shut down. See [`open_node`] and [`spawn_tasks`].
+async fn run_node(out: Output, node: NodeOptions, dir: Option<&str>, dev: bool) -> Result<()> {
+    let blockhead = open_node(out, NodeOptions { dev, ..node }, dir)?;
+    let tasks = spawn_tasks(&blockhead, dir, dev);
+    out.emit(json!({"running": blockhead.head()?.number}), |value| {
+        format!("running at block {}", value["running"])
+    });
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => interrupted?,
+        () = blockhead.shutdown_requested() => {}
+    }
+    stop_tasks(&blockhead, tasks, dir);
+    Ok(())
+}
+
+/// Run the node with an interactive [`console`] until it exits or the node is asked to shut
+/// down. A dev chain in memory starts with dev account 0 unlocked.
+async fn run_console(out: Output, node: NodeOptions, dir: Option<&str>, dev: bool) -> Result<()> {
+    let blockhead = open_node(out, NodeOptions { dev, ..node }, dir)?;
+    let tasks = spawn_tasks(&blockhead, dir, dev);
+    let book = AddressBook::load(&address_book::default_path())?;
+    let mut console = Console::new(blockhead.clone(), book);
+    if dir.is_none() {
+        console.unlock(spec::dev_wallet().account(0)?);
+    }
+    let result = tokio::select! {
+        result = console::run(console) => result,
+        () = blockhead.shutdown_requested() => Ok(()),
+    };
+    stop_tasks(&blockhead, tasks, dir);
+    result
+}
+
+/// Open the chain at `path`, which must exist: a data directory, with its genesis and node
+/// config, or a database file, with the chain config it records.
+fn open_existing(path: &str) -> Result<Blockhead> {
+    if Path::new(path).is_dir() {
+        return DataDir::new(path).open_existing();
+    }
+    Blockhead::open(path)
+}
+
+fn db_check(out: Output, path: &str) -> Result<()> {
+    let report = open_existing(path)?.check_integrity()?;
+    out.emit(
+        json!({
+            "consistent": report.is_consistent(),
+            "last_consistent": report.last_consistent,
+            "problems": report.problems,
+        }),
+        |_| {
+            let mut lines = report.problems.clone();
+            if report.is_consistent() {
+                lines.push(format!(
+                    "ok: chain consistent up to block {}",
+                    report.last_consistent
+                ));
+            }
+            lines.join("\n")
+        },
+    );
+    if !report.is_consistent() {
+        return Err(Error::new(format!(
+            "{} problems found; `blockhead db repair` would truncate to block {}",
+            report.problems.len(),
+            report.last_consistent
+        )));
+    }
+    Ok(())
+}
+
+fn db_repair(out: Output, path: &str) -> Result<()> {
+    let report = open_existing(path)?.repair()?;
+    out.emit(
+        json!({
+            "fixed": report.problems,
+            "last_consistent": report.last_consistent,
+        }),
+        |_| {
+            if report.is_consistent() {
+                "nothing to repair".to_string()
+            } else {
+                format!(
+                    "fixed {} problems, truncated to block {}",
+                    report.problems.len(),
+                    report.last_consistent
+                )
+            }
+        },
+    );
+    Ok(())
+}
+
+fn db_compact(out: Output, path: &str) -> Result<()> {
+    let blockhead = open_existing(path)?;
+    let report = blockhead.compact()?;
+    let tables = blockhead.table_sizes()?;
+    out.emit(
+        json!({
+            "size_before": report.size_before,
+            "size_after": report.size_after,
+            "tables": tables
+                .iter()
+                .map(|(name, size)| (name.clone(), Value::from(*size)))
+                .collect::<serde_json::Map<_, _>>(),
+        }),
+        |_| {
+            let mut lines = vec![format!(
+                "compacted from {} to {} bytes",
+                report.size_before, report.size_after
+            )];
+            lines.extend(
+                tables
+                    .iter()
+                    .map(|(name, size)| format!("{size:>12} {name}")),
+            );
+            lines.join("\n")
+        },
+    );
+    Ok(())
+}
+
+/// Re-execute the blocks `from` to `to` of the chain in data directory `dir` under its own
+/// genesis and node config, as the blocks were executed with them.
+fn replay(out: Output, from: &str, to: &str, dir: &str) -> Result<()> {
+    let blockhead = DataDir::new(dir).open_existing()?;
+    let report = blockhead.replay(from.parse()?, to.parse()?)?;
+    let divergence = report.divergence.as_ref();
+    out.emit(
+        json!({
+            "replayed": report.replayed,
+            "divergence": divergence.map(|divergence| json!({
+                "block_number": divergence.block_number,
+                "block_hash": divergence.block_hash.to_string(),
+                "transaction": divergence.transaction.map(|hash| hash.to_string()),
+                "reason": divergence.reason,
+            })),
+        }),
+        |_| match divergence {
+            None => format!("ok: {} blocks match", report.replayed),
+            Some(divergence) => {
+                let at = match divergence.transaction {
+                    Some(hash) => format!("transaction {hash} of block"),
+                    None => "block".to_string(),
+                };
+                format!(
+                    "{at} {} ({}): {}",
+                    divergence.block_number, divergence.block_hash, divergence.reason
+                )
+            }
+        },
+    );
+    if let Some(divergence) = divergence {
+        return Err(Error::new(format!(
+            "replay diverged at block {}",
+            divergence.block_number
+        )));
+    }
+    Ok(())
+}
+
+async fn balance(out: Output, path: &str, address: &str) -> Result<()> {
+    let address = AddressBook::load(&address_book::default_path())?.resolve(address)?;
+    let balance = open_existing(path)?.get_balance(address).await?;
+    out.emit(json!({"address": address, "balance": balance}), |value| {
+        value["balance"].to_string()
+    });
+    Ok(())
+}
+
+fn address_add(out: Output, name: &str, address: &str) -> Result<()> {
+    let path = address_book::default_path();
+    let mut book = AddressBook::load(&path)?;
+    let address = book.resolve(address)?;
+    book.add(name, address)?;
+    book.save(&path)?;
+    if out.json {
+        out.emit(json!({"name": name, "address": address}), |_| String::new());
+    }
+    Ok(())
+}
+
+fn address_list(out: Output) -> Result<()> {
+    for (name, address) in AddressBook::load(&address_book::default_path())?.addresses {
+        out.emit(json!({"name": name, "address": address}), |_| {
+            format!("{name} {address}")
+        });
+    }
+    Ok(())
+}
+
+fn address_remove(out: Output, name: &str) -> Result<()> {
+    let path = address_book::default_path();
+    let mut book = AddressBook::load(&path)?;
+    book.remove(name)?;
+    book.save(&path)?;
+    if out.json {
+        out.emit(json!({"removed": name}), |_| String::new());
+    }
+    Ok(())
+}
+
+fn wallet_new(out: Output) -> Result<()> {
+    let mnemonic = wallet::generate_mnemonic()?;
+    out.emit(json!({"seed_phrase": mnemonic}), |_| mnemonic.clone());
+    Ok(())
+}
+
+/// Read the seed phrase from stdin, which keeps it out of the shell history, and the passphrase,
+/// if any, from `$BLOCKHEAD_PASSPHRASE`.
+fn read_wallet() -> Result<Wallet> {
+    let mut phrase = String::new();
+    std::io::stdin().read_line(&mut phrase)?;
+    let passphrase = std::env::var("BLOCKHEAD_PASSPHRASE").unwrap_or_default();
+    Wallet::from_mnemonic(&phrase, &passphrase)
+}
+
+/// Print the address on the chain of spec `chain` of account `index` of the seed phrase read
+/// from stdin, which keeps the phrase out of the shell history. The passphrase, if any, comes
+/// from `$BLOCKHEAD_PASSPHRASE`.
+fn wallet_address(out: Output, chain: &str, index: &str) -> Result<()> {
+    let algorithm = spec::load(chain)?.config.hash;
+    let index: u32 = index.parse()?;
+    let address = read_wallet()?.account(index)?.address(algorithm);
+    out.emit(json!({"index": index, "address": address}), |_| {
+        address.to_string()
+    });
+    Ok(())
+}
+
+/// Transactions pass between signers as the hex of their encoding, as `get_raw_transaction`
+/// returns them.
+fn decode_transaction(hex: &str) -> Result<Transaction> {
+    let bytes = hex::decode(hex.trim_start_matches("0x"))
+        .map_err(|error| Error::new(format!("bad transaction hex: {error}")))?;
+    Transaction::decode(&bytes)
+}
+
+fn encode_transaction(transaction: &Transaction) -> String {
+    format!("0x{}", hex::encode(transaction.encode()))
+}
+
+/// Print a transaction being passed between signers, with its hash under `algorithm` and its
+/// signature count under `--json`.
+fn emit_transaction(out: Output, transaction: &Transaction, algorithm: HashAlgorithm) {
+    let encoded = encode_transaction(transaction);
+    out.emit(
+        json!({
+            "hash": transaction.compute_hash(algorithm),
+            "signatures": transaction.signatures.len(),
+            "transaction": encoded,
+        }),
+        |_| encoded.clone(),
+    );
+}
+
+/// Print the address on the chain of spec `chain` of the account that `threshold` of `signers`
+/// control.
+fn multisig_address(out: Output, chain: &str, threshold: &str, signers: &[&str]) -> Result<()> {
+    let algorithm = spec::load(chain)?.config.hash;
+    let book = AddressBook::load(&address_book::default_path())?;
+    let signers = signers
+        .iter()
+        .map(|signer| book.resolve(signer))
+        .collect::<Result<Vec<_>>>()?;
+    let threshold: u8 = threshold.parse()?;
+    let address = MultisigPolicy::new(threshold, signers.clone())?.address(algorithm);
+    out.emit(
+        json!({"address": address, "threshold": threshold, "signers": signers}),
+        |_| address.to_string(),
+    );
+    Ok(())
+}
+
+/// Add the signature of account `index`, of the wallet read as [`wallet_address`] reads it, to
+/// `transaction`, for the chain of spec `chain`, and print the result for the next signer or for
+/// `multisig combine`.
+fn multisig_sign(out: Output, chain: &str, index: &str, transaction: &str) -> Result<()> {
+    let config = spec::load(chain)?.config;
+    let mut transaction = decode_transaction(transaction)?;
+    let signer = read_wallet()?.account(index.parse()?)?;
+    transaction.add_signature(&signer, config.chain_id, config.hash)?;
+    emit_transaction(out, &transaction, config.hash);
+    Ok(())
+}
+
+/// Merge the signatures of copies of one transaction signed separately, for the chain of spec
+/// `chain`.
+fn multisig_combine(out: Output, chain: &str, transactions: &[&str]) -> Result<()> {
+    let algorithm = spec::load(chain)?.config.hash;
+    let mut combined = decode_transaction(transactions[0])?;
+    for transaction in &transactions[1..] {
+        combined.combine(&decode_transaction(transaction)?)?;
+    }
+    emit_transaction(out, &combined, algorithm);
+    Ok(())
+}
+
+fn snapshot_export(out: Output, path: &str, snapshot: &str) -> Result<()> {
+    let header = open_existing(path)?.export_snapshot(Path::new(snapshot))?;
+    out.emit(
+        json!({"number": header.number, "hash": header.hash}),
+        |_| format!("snapshot at block {} {}", header.number, header.hash),
+    );
+    Ok(())
+}
+
+/// Start a new node at `path` from `snapshot` of the chain of `genesis`, which is trusted
+/// only as far as it matches `checkpoint`, a block hash obtained from a source the operator
+/// trusts.
+fn snapshot_import(
+    out: Output,
+    genesis: Genesis,
+    snapshot: &str,
+    path: &str,
+    checkpoint: &str,
+) -> Result<()> {
+    let checkpoint = Hash::from(decode_hex32(checkpoint)?);
+    let blockhead =
+        Blockhead::from_snapshot(Path::new(snapshot), Path::new(path), genesis, checkpoint)?;
+    let head = blockhead.head()?;
+    out.emit(json!({"number": head.number, "hash": head.hash}), |_| {
+        format!("synced to block {}", head.number)
+    });
+    Ok(())
+}
+
+/// Send a request to `target` of the node serving at `endpoint` and print the response: a
+/// `GET`, or with `body`, a `POST` of it. `endpoint` may list several, comma-separated, to fail
+/// over between; see [`Remote::parse`].
+async fn call(out: Output, endpoint: &str, target: &str, body: Option<&str>) -> Result<()> {
+    let method = if body.is_some() { "POST" } else { "GET" };
+    let body = body.unwrap_or_default().as_bytes();
+    let value = Remote::parse(endpoint)?.call(method, target, body).await?;
+    out.emit(value, |value| {
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    });
+    Ok(())
+}
+
+/// Run an [`attach`] shell against the node at `endpoint`, which may list several to fail over
+/// between (see [`Remote::parse`]). Without one, that is the node running in the current
+/// directory, if it is a data directory, and otherwise a dev chain in memory at
+/// [`DEV_HTTP_ADDRESS`].
+async fn attach(endpoint: Option<&str>) -> Result<()> {
+    let remote = match endpoint {
+        Some(endpoint) => Remote::parse(endpoint)?,
+        None if DataDir::new(".").ipc_path().exists() => {
+            Remote::new(vec![Endpoint::Ipc(DataDir::new(".").ipc_path())])?
+        }
+        None => Remote::new(vec![Endpoint::Tcp(DEV_HTTP_ADDRESS.to_string())])?,
+    };
+    let book = AddressBook::load(&address_book::default_path())?;
+    attach::run(Attached::new(remote, book)).await
+}
+
+/// Ask the faucet of the node at `endpoint` to fund `address`. See [`crate::faucet`].
+async fn faucet(out: Output, endpoint: &str, address: &str) -> Result<()> {
+    let address = AddressBook::load(&address_book::default_path())?.resolve(address)?;
+    let target = format!("/faucet?address={address}");
+    let value = Endpoint::parse(endpoint).call("POST", &target, &[]).await?;
+    out.emit(value, |value| {
+        format!(
+            "sent {} to {address} in {}",
+            value["amount"],
+            value["hash"].as_str().unwrap_or_default()
+        )
+    });
+    Ok(())
+}
+
+/// The values of the `--name value` pairs in `args`, which may come in any order, each named in
+/// `names`.
+fn parse_flags<'a>(args: &[&'a str], names: &[&str]) -> Result<HashMap<&'a str, &'a str>> {
+    let mut flags = HashMap::new();
+    let mut args = args.iter();
+    while let Some(&name) = args.next() {
+        if !names.contains(&name) {
+            return Err(Error::new(format!("unexpected argument {name}\n{USAGE}")));
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| Error::new(format!("{name} needs a value")))?;
+        if flags.insert(name, *value).is_some() {
+            return Err(Error::new(format!("{name} is given twice")));
+        }
+    }
+    Ok(flags)
+}
+
+/// Send a transfer through the node at `endpoint`, signed by account `--account` of the wallet
+/// read as [`wallet_address`] reads it. It pays `--gas-price`, or else the node's normal fee
+/// estimate. With `--wait`, returns only once the transfer is that many blocks deep.
+async fn send(out: Output, endpoint: &str, flags: &[&str]) -> Result<()> {
+    let names = ["--account", "--to", "--value", "--gas-price", "--wait"];
+    let flags = parse_flags(flags, &names)?;
+    let flag =
+        |name| (flags.get(name).copied()).ok_or_else(|| Error::new(format!("send needs {name}")));
+    let book = AddressBook::load(&address_book::default_path())?;
+    let value: u64 = flag("--value")?.parse()?;
+    let gas_price: Option<u64> = flags.get("--gas-price").map(|p| p.parse()).transpose()?;
+    let signer = read_wallet()?.account(flag("--account")?.parse()?)?;
+    let attached = Attached::new(Remote::parse(endpoint)?, book);
+    let gas_price = match gas_price {
+        Some(gas_price) => gas_price,
+        None => attached.normal_gas_price().await?,
+    };
+    let hash = attached
+        .transfer(&signer, flag("--to")?, value, gas_price)
+        .await?;
+    let Some(confirmations) = flags.get("--wait") else {
+        out.emit(json!({"hash": hash}), |_| hash.to_string());
+        return Ok(());
+    };
+    let confirmations: u64 = confirmations.parse()?;
+    let status = attached.wait_for_confirmations(hash, confirmations).await?;
+    out.emit(
+        json!({"hash": hash, "confirmations": confirmations, "status": status}),
+        |_| {
+            let outcome = if status { "succeeded" } else { "reverted" };
+            format!("{hash} {outcome}, {confirmations} blocks deep")
+        },
+    );
+    Ok(())
+}
+
+/// Replace pending transaction `hash` at the node at `endpoint` with a no-op signed by account
+/// `index` of the wallet read as [`wallet_address`] reads it, which must have sent it.
+async fn cancel(out: Output, endpoint: &str, index: &str, hash: &str) -> Result<()> {
+    let hash = Hash::from(decode_hex32(hash)?);
+    let signer = read_wallet()?.account(index.parse()?)?;
+    let book = AddressBook::load(&address_book::default_path())?;
+    let attached = Attached::new(Remote::parse(endpoint)?, book);
+    let replacement = attached.cancel(&signer, hash).await?;
+    out.emit(json!({"cancelled": hash, "hash": replacement}), |_| {
+        format!("replaced {hash} with {replacement}")
+    });
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_open_existing() {
+    let path = std::env::temp_dir().join(format!("blockhead-existing-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let missing = path.join("chain.db");
+    assert!(open_existing(missing.to_str().unwrap()).is_err());
+    assert!(open_existing(path.to_str().unwrap()).is_err());
+    assert!(!path.exists());
+
+    // A data directory and its database alone both open with the chain's own config.
+    let dir = DataDir::new(&path);
+    drop(dir.init(&spec::load("dev").unwrap()).unwrap());
+    for path in [&path, &dir.db_path()] {
+        let blockhead = open_existing(path.to_str().unwrap()).unwrap();
+        assert_eq!(blockhead.chain_id().await, spec::DEV_CHAIN_ID);
+        assert!(blockhead.check_integrity().unwrap().is_consistent());
+    }
+    std::fs::remove_file(dir.db_path()).unwrap();
+    assert!(open_existing(path.to_str().unwrap()).is_err());
+    assert!(!dir.db_path().exists());
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_replay_data_dir() {
+    let path = std::env::temp_dir().join(format!("blockhead-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let dir = DataDir::new(&path);
+    let blockhead = dir.init(&spec::load("dev").unwrap()).unwrap();
+    blockhead.produce_block().unwrap();
+    blockhead.produce_block().unwrap();
+    drop(blockhead);
+
+    // The dev chain's own genesis replays without divergence, where the default one would not
+    // even open it.
+    let out = Output { json: true };
+    replay(out, "1", "2", path.to_str().unwrap()).unwrap();
+    assert!(Blockhead::new(dir.db_path()).is_err());
+    assert!(replay(out, "1", "2", path.join("missing").to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(&path).unwrap();
+}
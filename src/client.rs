@@ -0,0 +1,488 @@
+//! Helpers for applications that drive a node through the [`Blockchain`] trait.
+//!
+//! [`Client`] wraps any [`Blockchain`], whether a [`crate::Blockhead`] embedded in the process or
+//! a mock, with the flows every application otherwise writes itself: filling in and signing a
+//! transfer or a deployment, waiting for a transaction's receipt, and watching a balance. Waits
+//! poll the chain, so they work the same over every implementation.
+//!
+//! A [`Client`] takes nonces from its [`NonceManager`] rather than asking the chain each time, so
+//! many transfers from one account can be sent at once without two being given the same nonce.
+use crate::address::Address;
+use crate::block::{BlockId, Header};
+use crate::error::{Error, ErrorKind, Result};
+use crate::execution::StateOverrides;
+use crate::hash::{Hash, HashAlgorithm};
+use crate::signer::Signer;
+use crate::status::TransactionStatus;
+use crate::transaction::{Transaction, TransactionKind};
+use crate::{Blockchain, TransactionReceipt};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often a [`Client`] polls the chain while waiting, unless told otherwise.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Conveniences over the [`Blockchain`] `chain`. See the [module documentation](self).
+pub struct Client<C> {
+    chain: C,
+    poll_interval: Duration,
+    nonces: NonceManager,
+}
+
+impl<C: Blockchain + Sync> Client<C> {
+    pub fn new(chain: C) -> Self {
+        Self {
+            chain,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            nonces: NonceManager::default(),
+        }
+    }
+
+    /// Poll every `poll_interval` while waiting, rather than every 250ms.
+    pub fn with_poll_interval(self, poll_interval: Duration) -> Self {
+        Self {
+            poll_interval,
+            ..self
+        }
+    }
+
+    /// The wrapped chain, for everything the client does not cover.
+    pub fn chain(&self) -> &C {
+        &self.chain
+    }
+
+    /// The nonces this client assigns, for sending transactions it does not build alongside those
+    /// it does.
+    pub fn nonces(&self) -> &NonceManager {
+        &self.nonces
+    }
+
+    /// Send `amount` from `from`'s account to `to`, at the next free nonce and the normal gas
+    /// price, returning the transfer's hash.
+    pub async fn transfer(&self, from: &dyn Signer, to: Address, amount: u64) -> Result<Hash> {
+        let gas_limit = self.chain.estimate_gas(to, Vec::new()).await;
+        let gas_price = self.chain.estimate_fee().await?.normal;
+        let algorithm = self.chain.hash_algorithm().await;
+        self.nonces
+            .send(&self.chain, from.address(algorithm)?, |nonce| async move {
+                let transaction = Transaction {
+                    to_address: Some(to),
+                    value: amount,
+                    gas_limit,
+                    gas_price,
+                    ..fill(from, nonce, algorithm)?
+                };
+                self.sign_and_send(transaction, from).await
+            })
+            .await
+    }
+
+    /// Deploy `code` as a contract from `from`'s account, returning the deployment's hash and the
+    /// contract's address. The gas limit is what a simulation of the deployment uses, and a
+    /// deployment the simulation finds would fail is not sent, but returned as an error with
+    /// [`ErrorKind::Reverted`].
+    pub async fn deploy_contract(
+        &self,
+        from: &dyn Signer,
+        code: Vec<u8>,
+    ) -> Result<(Hash, Address)> {
+        let head = self.head().await?;
+        let gas_price = self.chain.estimate_fee().await?.normal;
+        let algorithm = self.chain.hash_algorithm().await;
+        self.nonces
+            .send(&self.chain, from.address(algorithm)?, |nonce| async move {
+                let mut transaction = Transaction {
+                    data: code.into(),
+                    gas_limit: head.gas_limit,
+                    ..fill(from, nonce, algorithm)?
+                };
+                let simulation = self
+                    .chain
+                    .simulate_transaction(
+                        transaction.clone(),
+                        StateOverrides::default(),
+                        BlockId::Pending,
+                    )
+                    .await?;
+                if !simulation.status {
+                    return Err(Error::with_kind(
+                        ErrorKind::Reverted {
+                            return_data: simulation.return_data,
+                        },
+                        "the deployment would fail",
+                    ));
+                }
+                transaction.gas_limit = simulation.gas_used;
+                transaction.gas_price = gas_price;
+                let contract = Address::for_contract(transaction.from_address, nonce, algorithm);
+                Ok((self.sign_and_send(transaction, from).await?, contract))
+            })
+            .await
+    }
+
+    /// Wait up to `timeout` for transaction `hash` to be included in the canonical chain,
+    /// returning its receipt. Fails at once if the transaction is dropped or replaced, or if the
+    /// chain has never heard of it.
+    pub async fn wait_for_receipt(
+        &self,
+        hash: Hash,
+        timeout: Duration,
+    ) -> Result<TransactionReceipt> {
+        let wait = async {
+            loop {
+                match self.chain.get_transaction_status(hash).await? {
+                    TransactionStatus::Included { .. } => {
+                        // The receipt may trail the status on chains that write them apart.
+                        if let Some(receipt) = self.chain.get_transaction_receipt(hash).await? {
+                            return Ok(receipt);
+                        }
+                    }
+                    TransactionStatus::Pending => {}
+                    status => {
+                        return Err(Error::new(format!(
+                            "transaction {hash} will not be included: it is {status:?}"
+                        )))
+                    }
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            Error::new(format!(
+                "transaction {hash} was not included within {}s",
+                timeout.as_secs_f64()
+            ))
+        })?
+    }
+
+    /// Follow `address`'s balance. See [`BalanceWatch`].
+    pub fn watch_balance(&self, address: Address) -> BalanceWatch<'_, C> {
+        BalanceWatch {
+            client: self,
+            address,
+            last: None,
+        }
+    }
+
+    /// Replace pending transaction `hash` with a no-op. See [`cancel_transaction`].
+    pub async fn cancel(&self, hash: Hash, signer: &dyn Signer) -> Result<Hash> {
+        cancel_transaction(&self.chain, hash, signer).await
+    }
+
+    async fn head(&self) -> Result<Header> {
+        self.chain
+            .get_header(BlockId::Latest)
+            .await?
+            .ok_or_else(|| Error::new("the chain has no head"))
+    }
+
+    async fn sign_and_send(&self, mut transaction: Transaction, from: &dyn Signer) -> Result<Hash> {
+        let algorithm = self.chain.hash_algorithm().await;
+        transaction.sign(from, self.chain.chain_id().await, algorithm)?;
+        self.chain.send_transaction(transaction).await
+    }
+}
+
+/// An unsigned transfer of nothing from `from` at `nonce`, free and with no gas, for callers to
+/// fill in. `from`'s address is derived with `algorithm`.
+fn fill(from: &dyn Signer, nonce: u64, algorithm: HashAlgorithm) -> Result<Transaction> {
+    Ok(Transaction {
+        kind: TransactionKind::Transfer,
+        from_address: from.address(algorithm)?,
+        to_address: None,
+        value: 0,
+        data: Bytes::new(),
+        gas_limit: 0,
+        gas_price: 0,
+        nonce,
+        signatures: Vec::new(),
+    })
+}
+
+/// Assigns nonces to the transactions of each account, counting up from the chain's pending
+/// nonce, so that many can be sent at once without asking the chain for each.
+///
+/// Sends from one account take turns, each holding its account's next nonce until it is sent or
+/// fails, so no two are given the same nonce. A send that fails leaves no gap: its nonce goes to
+/// the next send, after the next nonce is read again from the chain, in case the failure was that
+/// the chain disagreed. If a transaction sent is later dropped, which leaves a gap that nothing
+/// after it can be included past, call [`NonceManager::reset`].
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    /// Each account's next nonce, or `None` if it is to be read from the chain.
+    next: Mutex<HashMap<Address, Arc<tokio::sync::Mutex<Option<u64>>>>>,
+}
+
+impl NonceManager {
+    /// Run `send` with the next nonce of `from`, taking turns with other sends from `from`. The
+    /// nonce counts as used only if `send` succeeds.
+    pub async fn send<T, F: Future<Output = Result<T>>>(
+        &self,
+        chain: &(impl Blockchain + ?Sized),
+        from: Address,
+        send: impl FnOnce(u64) -> F,
+    ) -> Result<T> {
+        let account = self.next.lock().unwrap().entry(from).or_default().clone();
+        let mut next = account.lock().await;
+        let nonce = match *next {
+            Some(nonce) => nonce,
+            None => chain.get_pending_nonce(from).await?,
+        };
+        let result = send(nonce).await;
+        *next = match result {
+            Ok(_) => Some(nonce + 1),
+            Err(_) => None,
+        };
+        result
+    }
+
+    /// Read `from`'s next nonce from the chain again before its next send.
+    pub async fn reset(&self, from: Address) {
+        let account = self.next.lock().unwrap().get(&from).cloned();
+        if let Some(account) = account {
+            *account.lock().await = None;
+        }
+    }
+}
+
+/// A balance followed by [`Client::watch_balance`].
+pub struct BalanceWatch<'a, C> {
+    client: &'a Client<C>,
+    address: Address,
+    last: Option<u64>,
+}
+
+impl<C: Blockchain + Sync> BalanceWatch<'_, C> {
+    /// The balance once it differs from the one this last returned, polling until it does. The
+    /// first call returns the balance at once.
+    pub async fn changed(&mut self) -> Result<u64> {
+        loop {
+            let balance = self.client.chain.get_balance(self.address).await?;
+            if self.last != Some(balance) {
+                self.last = Some(balance);
+                return Ok(balance);
+            }
+            tokio::time::sleep(self.client.poll_interval).await;
+        }
+    }
+}
+
+/// How much more than the stuck transaction a cancellation pays per unit of gas, in percent. The
+/// mempool only requires more, but a clear margin also outbids other nodes' replacement rules.
+const CANCEL_PRICE_BUMP_PERCENT: u64 = 10;
+
+/// Replace pending transaction `hash` with a zero-value transfer from its sender to itself, at
+/// the same nonce and a higher gas price, returning the hash of the replacement. `signer` must
+/// hold the sender's key. The stuck transaction leaves the mempool as
+/// [`TransactionStatus::Replaced`], and once the replacement is included, its nonce is spent.
+pub(crate) async fn cancel_transaction(
+    chain: &(impl Blockchain + ?Sized),
+    hash: Hash,
+    signer: &dyn Signer,
+) -> Result<Hash> {
+    let status = chain.get_transaction_status(hash).await?;
+    if status != TransactionStatus::Pending {
+        return Err(Error::new(format!(
+            "cannot cancel transaction {hash}: it is {status:?}, not pending"
+        )));
+    }
+    let stuck = chain
+        .get_transaction(hash)
+        .await?
+        .ok_or_else(|| Error::new(format!("transaction {hash} is not known")))?;
+    let mut cancellation = cancellation(hash, &stuck)?;
+    cancellation.sign(signer, chain.chain_id().await, chain.hash_algorithm().await)?;
+    chain.send_transaction(cancellation).await
+}
+
+/// The unsigned replacement that cancels `stuck`, pending transaction `hash`: a zero-value
+/// transfer from its sender to itself at the same nonce, paying [`CANCEL_PRICE_BUMP_PERCENT`]
+/// percent more per unit of gas.
+pub(crate) fn cancellation(hash: Hash, stuck: &Transaction) -> Result<Transaction> {
+    let bump = (stuck.gas_price.saturating_mul(CANCEL_PRICE_BUMP_PERCENT) / 100).max(1);
+    Ok(Transaction {
+        kind: TransactionKind::Transfer,
+        from_address: stuck.from_address,
+        to_address: Some(stuck.from_address),
+        value: 0,
+        data: Bytes::new(),
+        // At least the intrinsic gas of an empty transfer, as the stuck transaction's covered its
+        // own data; whatever goes unused is refunded.
+        gas_limit: stuck.gas_limit,
+        gas_price: stuck.gas_price.checked_add(bump).ok_or_else(|| {
+            Error::new(format!("cannot outbid the gas price of transaction {hash}"))
+        })?,
+        nonce: stuck.nonce,
+        signatures: Vec::new(),
+    })
+}
+
+#[tokio::test]
+async fn test_cancel_transaction() {
+    use crate::address::Address;
+    use crate::spec;
+    use crate::testkit;
+
+    let algorithm = HashAlgorithm::default();
+    let blockhead = spec::open_dev(":memory:", spec::load("dev").unwrap()).unwrap();
+    let account = spec::dev_wallet().account(0).unwrap();
+    let sender = account.address(algorithm);
+    let recipient = Address([8; 32]);
+    let mut transfer = Transaction {
+        gas_price: 30,
+        ..testkit::transfer(sender, recipient, 5, 0)
+    };
+    transfer
+        .sign(&account, spec::DEV_CHAIN_ID, algorithm)
+        .unwrap();
+    let stuck = blockhead.send_transaction(transfer).await.unwrap();
+    let other = spec::dev_wallet().account(1).unwrap();
+    assert!(cancel_transaction(&blockhead, stuck, &other).await.is_err());
+
+    let cancellation = cancel_transaction(&blockhead, stuck, &account)
+        .await
+        .unwrap();
+    assert_eq!(
+        blockhead.get_transaction_status(stuck).await.unwrap(),
+        TransactionStatus::Replaced { by: cancellation }
+    );
+    let replacement = blockhead
+        .get_transaction(cancellation)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(replacement.to_address, Some(sender));
+    assert_eq!(replacement.value, 0);
+    assert_eq!(replacement.nonce, 0);
+    assert_eq!(replacement.gas_price, 33);
+
+    let block = blockhead.produce_block().unwrap();
+    assert_eq!(block.body.transactions[0].0, cancellation);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 0);
+    assert_eq!(blockhead.get_nonce(sender).await.unwrap(), 1);
+    let error = cancel_transaction(&blockhead, cancellation, &account)
+        .await
+        .unwrap_err();
+    assert!(error.message().contains("not pending"));
+}
+
+#[tokio::test]
+async fn test_client() {
+    use crate::spec;
+
+    let blockhead = spec::open_dev(":memory:", spec::load("dev").unwrap()).unwrap();
+    let client = Client::new(blockhead).with_poll_interval(Duration::from_millis(1));
+    let blockhead = client.chain();
+    let account = spec::dev_wallet().account(0).unwrap();
+    let recipient = Address([8; 32]);
+    let mut watch = client.watch_balance(recipient);
+    assert_eq!(watch.changed().await.unwrap(), 0);
+
+    let hash = client.transfer(&account, recipient, 5).await.unwrap();
+    let second = client.transfer(&account, recipient, 6).await.unwrap();
+    assert_eq!(
+        blockhead
+            .get_transaction(second)
+            .await
+            .unwrap()
+            .unwrap()
+            .nonce,
+        1
+    );
+    let (receipt, block) = tokio::join!(
+        client.wait_for_receipt(hash, Duration::from_secs(5)),
+        async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            blockhead.produce_block().unwrap()
+        }
+    );
+    let receipt = receipt.unwrap();
+    assert_eq!((receipt.block_hash, receipt.status), (block.hash, true));
+    assert_eq!(watch.changed().await.unwrap(), 11);
+
+    let unknown = Hash::from([0; 32]);
+    let error = client
+        .wait_for_receipt(unknown, Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    assert!(error.message().contains("Unknown"), "{error}");
+    let pending = client.transfer(&account, recipient, 1).await.unwrap();
+    let error = client
+        .wait_for_receipt(pending, Duration::from_millis(20))
+        .await
+        .unwrap_err();
+    assert!(error.message().contains("not included within"));
+
+    #[cfg(feature = "vm")]
+    {
+        let (hash, contract) = client
+            .deploy_contract(&account, vec![0xde, 0xad])
+            .await
+            .unwrap();
+        blockhead.produce_block().unwrap();
+        let receipt = client
+            .wait_for_receipt(hash, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(receipt.contract_address, Some(contract));
+        assert_eq!(receipt.gas_used, 53_032);
+    }
+}
+
+#[tokio::test]
+async fn test_nonce_manager() {
+    use crate::spec;
+
+    let algorithm = HashAlgorithm::default();
+    let mut genesis = spec::load("dev").unwrap();
+    genesis.config.mempool.max_per_sender = 1000;
+    let client = Arc::new(Client::new(spec::open_dev(":memory:", genesis).unwrap()));
+    let account = Arc::new(spec::dev_wallet().account(0).unwrap());
+    let from = account.address(algorithm);
+    let mut sends = tokio::task::JoinSet::new();
+    for i in 0..200 {
+        let (client, account) = (client.clone(), account.clone());
+        sends.spawn(async move { client.transfer(&*account, Address([8; 32]), i).await });
+    }
+    let mut nonces = Vec::new();
+    while let Some(hash) = sends.join_next().await {
+        let hash = hash.unwrap().unwrap();
+        let transaction = client.chain().get_transaction(hash).await.unwrap().unwrap();
+        nonces.push(transaction.nonce);
+    }
+    nonces.sort();
+    assert_eq!(nonces, (0..200).collect::<Vec<_>>());
+
+    // A failed send leaves no gap.
+    let nonces = client.nonces();
+    let failed: Result<()> = nonces
+        .send(client.chain(), from, |nonce| async move {
+            assert_eq!(nonce, 200);
+            Err(Error::new("not sent"))
+        })
+        .await;
+    assert!(failed.is_err());
+    client
+        .transfer(&*account, Address([8; 32]), 1)
+        .await
+        .unwrap();
+    assert_eq!(client.chain().get_pending_nonce(from).await.unwrap(), 201);
+
+    // Nonces used behind the manager's back are picked up once it is reset.
+    let mut transaction = Transaction {
+        to_address: Some(Address([8; 32])),
+        gas_limit: 21_000,
+        gas_price: 1,
+        ..fill(&*account, 201, algorithm).unwrap()
+    };
+    transaction
+        .sign(&*account, client.chain().chain_id().await, algorithm)
+        .unwrap();
+    client.chain().send_transaction(transaction).await.unwrap();
+    nonces.reset(from).await;
+    let next = nonces.send(client.chain(), from, |nonce| async move { Ok(nonce) });
+    assert_eq!(next.await.unwrap(), 202);
+}
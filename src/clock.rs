@@ -0,0 +1,106 @@
+//! Time sources for block production and timestamp validation.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of block timestamps, in nanoseconds since the Unix epoch.
+pub(crate) trait Clock: Send + Sync {
+    fn now_nanos(&self) -> u64;
+}
+
+/// Wall-clock time that never runs backwards. The wall clock is sampled once at startup and
+/// advanced by a monotonic [`Instant`], so adjustments to the system clock while the node is
+/// running cannot make block timestamps regress.
+pub(crate) struct SystemClock {
+    start_nanos: u64,
+    start: Instant,
+}
+
+impl SystemClock {
+    pub(crate) fn new() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            start_nanos: since_epoch.as_nanos() as u64,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_nanos(&self) -> u64 {
+        self.start_nanos + self.start.elapsed().as_nanos() as u64
+    }
+}
+
+/// A clock that only moves when told to.
+pub(crate) struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub(crate) fn new(nanos: u64) -> Self {
+        Self {
+            nanos: AtomicU64::new(nanos),
+        }
+    }
+
+    pub(crate) fn set(&self, nanos: u64) {
+        self.nanos.store(nanos, Ordering::SeqCst);
+    }
+
+    pub(crate) fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_nanos(&self) -> u64 {
+        self.nanos.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimestampConfig {
+    /// How many ancestors, starting with the parent, contribute to the median time past.
+    pub median_window: usize,
+    /// How far ahead of the local clock a block timestamp may be.
+    pub max_future_drift: Duration,
+}
+
+impl Default for TimestampConfig {
+    fn default() -> Self {
+        Self {
+            median_window: 11,
+            max_future_drift: Duration::from_secs(15),
+        }
+    }
+}
+
+/// The median of the given ancestor timestamps. A block must be strictly later than this.
+pub(crate) fn median_time_past(mut timestamps: Vec<u64>) -> u64 {
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+}
+
+#[test]
+fn test_system_clock_is_monotonic() {
+    let clock = SystemClock::new();
+    let mut previous = clock.now_nanos();
+    for _ in 0..1000 {
+        let now = clock.now_nanos();
+        assert!(now >= previous);
+        previous = now;
+    }
+}
+
+#[test]
+fn test_median_time_past() {
+    assert_eq!(median_time_past(vec![]), 0);
+    assert_eq!(median_time_past(vec![5]), 5);
+    assert_eq!(median_time_past(vec![9, 1, 5]), 5);
+    assert_eq!(median_time_past(vec![0, 11]), 11);
+}
@@ -0,0 +1,205 @@
+//! Cold storage: the transactions, receipts and logs of old blocks, moved out of the main
+//! database into a file of their own so that the main one stays small and its indexes fast.
+//!
+//! With [`ColdStorageConfig::after_blocks`] set, the receipt indexer's background task moves the
+//! rows of canonical blocks that are finalized, indexed and that many blocks below the head into
+//! the cold file. Every connection attaches the file as the `cold` schema, where temporary views
+//! named after the moved tables shadow the main ones, joining the rows of both files, so queries
+//! read moved rows as before. Writes name the `main` tables. Block headers, the state and
+//! everything else stay in the main database, and snapshots copy only the main database.
+//!
+//! SQLite commits a transaction across databases in WAL mode atomically in each file but not
+//! across them, so a move copies rows to the cold file and records how far it has moved in one
+//! transaction, then deletes them from the main database in another. A node that stopped in
+//! between finishes the deletion when it next opens.
+use crate::db;
+use crate::error::{Error, Result};
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColdStorageConfig {
+    /// How many blocks below the head a finalized block's transactions, receipts and logs move
+    /// to cold storage. Zero keeps everything in the main database.
+    pub after_blocks: u64,
+    /// The cold file. Unset, it is the database's path with `.cold` appended.
+    pub path: Option<PathBuf>,
+}
+
+impl ColdStorageConfig {
+    /// The cold file of the database at `database`, or `None` if cold storage is off.
+    pub(crate) fn path_for(&self, database: &Path) -> Result<Option<PathBuf>> {
+        if self.after_blocks == 0 {
+            return Ok(None);
+        }
+        match &self.path {
+            Some(path) => Ok(Some(path.clone())),
+            None if database == Path::new(":memory:") => Err(Error::new(
+                "cold storage of an in-memory database needs a path",
+            )),
+            None => Ok(Some(default_path(database))),
+        }
+    }
+}
+
+/// The cold file of the database at `database` when none is configured.
+pub(crate) fn default_path(database: &Path) -> PathBuf {
+    let mut path = database.as_os_str().to_owned();
+    path.push(".cold");
+    path.into()
+}
+
+impl Blockhead {
+    /// Move the rows of up to `limit` blocks to cold storage, oldest first, returning how many
+    /// blocks were moved. Does nothing unless cold storage is on.
+    pub(crate) fn move_to_cold(&self, limit: u64) -> Result<u64> {
+        let after_blocks = self.config.cold.after_blocks;
+        if after_blocks == 0 || limit == 0 {
+            return Ok(0);
+        }
+        self.ensure_writable("move blocks to cold storage")?;
+        let _guard = self.write_lock.lock().unwrap();
+        let moved = db::read_cold_through(&self.connection)?;
+        let mut through = self
+            .finalized()?
+            .number
+            .min(self.head()?.number.saturating_sub(after_blocks));
+        // Receipts still queued would be indexed into the main database.
+        if let (_, Some(lowest)) = db::read_pending_receipts_backlog(&self.connection)? {
+            match lowest.checked_sub(1) {
+                Some(indexed) => through = through.min(indexed),
+                None => return Ok(0),
+            }
+        }
+        let first = moved.map_or(0, |moved| moved + 1);
+        through = through.min(first.saturating_add(limit - 1));
+        if through < first {
+            return Ok(0);
+        }
+        db::transaction(&self.connection, || {
+            db::copy_to_cold(&self.connection, first, through)
+        })?;
+        db::crash_point("move_to_cold");
+        db::transaction(&self.connection, || {
+            db::delete_moved(&self.connection, first, through)
+        })?;
+        log::debug!("moved blocks {first} to {through} to cold storage");
+        Ok(through - first + 1)
+    }
+}
+
+#[tokio::test]
+async fn test_cold_storage() {
+    use crate::address::Address;
+    use crate::genesis::Genesis;
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let path = std::env::temp_dir().join(format!("blockhead-cold-{}.db", std::process::id()));
+    let cold = default_path(&path);
+    for file in [&path, &cold] {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", file.display()));
+        }
+    }
+    let validator = testkit::validator();
+    let mut genesis = Genesis {
+        alloc: vec![(validator, 1_000_000)],
+        validators: vec![(validator, 100)],
+        config: testkit::config(),
+        ..Default::default()
+    };
+    genesis.config.staking.min_validator_stake = 10;
+    genesis.config.finality.checkpoint_interval = 2;
+    genesis.config.cold.after_blocks = 4;
+    let blockhead = Blockhead::with_genesis(&path, genesis.clone())
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap();
+    let mut hashes = Vec::new();
+    for _ in 0..12 {
+        let transaction = testkit::transfer(
+            validator,
+            Address([8; 32]),
+            1,
+            blockhead.get_nonce(validator).await.unwrap(),
+        );
+        hashes.push(blockhead.send_transaction(transaction).await.unwrap());
+        blockhead.produce_block().unwrap();
+    }
+    // Finalize block 12, leaving the head at 13.
+    let checkpoint = blockhead.head().unwrap().hash;
+    let nonce = blockhead.get_nonce(validator).await.unwrap();
+    let attestation = testkit::attestation(
+        checkpoint,
+        nonce,
+        blockhead.config.chain_id,
+        blockhead.config.hash,
+    );
+    blockhead.send_transaction(attestation).await.unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.finalized().unwrap().hash, checkpoint);
+    blockhead.index_receipts(usize::MAX).unwrap();
+    let rows =
+        |blockhead: &Blockhead, table: &str| db::count_rows(&blockhead.connection, table).unwrap();
+    assert_eq!(rows(&blockhead, "transactions"), 13);
+
+    let through = 13 - 4;
+    assert_eq!(blockhead.move_to_cold(2).unwrap(), 2);
+    assert_eq!(blockhead.move_to_cold(100).unwrap(), through - 1);
+    assert_eq!(blockhead.move_to_cold(100).unwrap(), 0);
+    assert_eq!(rows(&blockhead, "transactions"), 13 - through);
+    assert_eq!(rows(&blockhead, "receipt"), 13 - through);
+    assert!(cold.exists());
+
+    // Moved rows read as before, on pooled readers as on the writer.
+    async fn check(blockhead: &Blockhead, hashes: &[crate::Hash]) {
+        for &hash in hashes {
+            let receipt = blockhead.get_transaction_receipt(hash).await.unwrap();
+            assert_eq!(receipt.unwrap().transaction_hash, hash);
+            let transaction = blockhead.get_transaction(hash).await.unwrap();
+            assert_eq!(transaction.unwrap().to_address, Some(Address([8; 32])));
+        }
+        let block = blockhead.get_block(crate::BlockId::Number(1)).await;
+        assert_eq!(block.unwrap().unwrap().body.transactions.len(), 1);
+    }
+    check(&blockhead, &hashes).await;
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+    drop(blockhead);
+
+    // A node that stopped between copying and deleting finishes on opening.
+    let blockhead = Blockhead::with_genesis(&path, genesis.clone()).unwrap();
+    let hot = through + 2;
+    db::transaction(&blockhead.connection, || {
+        db::copy_to_cold(&blockhead.connection, through + 1, hot)
+    })
+    .unwrap();
+    drop(blockhead);
+    let blockhead = Blockhead::with_genesis(&path, genesis).unwrap();
+    assert_eq!(rows(&blockhead, "transactions"), 13 - hot);
+    check(&blockhead, &hashes).await;
+    let reader = Blockhead::new_read_only(&path).unwrap();
+    check(&reader, &hashes).await;
+
+    // Rewinding past moved blocks takes them out of cold storage too.
+    db::transaction(&blockhead.connection, || {
+        db::delete_blocks_above(&blockhead.connection, 5)
+    })
+    .unwrap();
+    assert_eq!(
+        db::read_cold_through(&blockhead.connection).unwrap(),
+        Some(5)
+    );
+    assert!(blockhead
+        .get_transaction(hashes[4])
+        .await
+        .unwrap()
+        .is_some());
+    assert!(blockhead
+        .get_transaction(hashes[5])
+        .await
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,410 @@
+//! An interactive shell over an in-process node: `blockhead console`.
+//!
+//! The console reads one command per line: queries of blocks, transactions and accounts, sends
+//! from wallet accounts unlocked for the session, and a subscription that prints each new block
+//! or matching log as it arrives. Addresses may be names from the address book, as on the command
+//! line.
+use crate::address::Address;
+use crate::address_book::AddressBook;
+use crate::block::{Block, BlockId};
+use crate::client;
+use crate::error::{Error, Result};
+use crate::events::ChainEvent;
+use crate::hash::{decode_hex32, Hash};
+use crate::logs::{LogEntry, LogFilter, LogSubscription};
+use crate::runtime;
+use crate::transaction::{Transaction, TransactionKind};
+use crate::wallet::{ExtendedKey, Wallet};
+use crate::{Blockchain, Blockhead};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+
+const HELP: &str = "\
+head                           the canonical head
+block <number|hash|latest|finalized|pending>
+tx <hash>                      a transaction and its status
+receipt <hash>                 a transaction's receipt
+balance <address>              an account's balance and nonce
+unlock <index> <seed phrase>   unlock a wallet account for this session
+accounts                       the unlocked accounts
+send <from> <to> <value>       transfer from an unlocked account
+cancel <hash>                  replace a pending transaction from an unlocked account
+subscribe | unsubscribe        print new blocks as they arrive
+subscribe logs [<address>] [from <number>]
+                               print logs as they arrive, from a past block if given
+help | exit";
+
+pub(crate) struct Console {
+    blockhead: Arc<Blockhead>,
+    book: AddressBook,
+    /// Accounts that `send` may sign for.
+    accounts: BTreeMap<Address, ExtendedKey>,
+    subscription: Option<Subscription>,
+}
+
+enum Subscription {
+    Blocks(broadcast::Receiver<ChainEvent>),
+    Logs(LogSubscription),
+}
+
+impl Console {
+    pub(crate) fn new(blockhead: Arc<Blockhead>, book: AddressBook) -> Self {
+        Self {
+            blockhead,
+            book,
+            accounts: BTreeMap::new(),
+            subscription: None,
+        }
+    }
+
+    /// Let `send` sign for `account`, returning its address.
+    pub(crate) fn unlock(&mut self, account: ExtendedKey) -> Address {
+        let address = account.address(self.blockhead.config.hash);
+        self.accounts.insert(address, account);
+        address
+    }
+
+    /// Run the command on `line`, returning what to print, or `None` to end the session.
+    pub(crate) async fn execute(&mut self, line: &str) -> Result<Option<String>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["exit"] | ["quit"] => return Ok(None),
+            ["help"] => HELP.to_string(),
+            ["head"] => describe_block(&runtime::block(|| self.blockhead.head())?),
+            ["block", id] => match self.blockhead.get_block(parse_block_id(id)?).await? {
+                Some(block) => describe_block(&block),
+                None => format!("no block {id}"),
+            },
+            ["tx", hash] => self.describe_transaction(parse_hash(hash)?).await?,
+            ["receipt", hash] => {
+                let hash = parse_hash(hash)?;
+                match self.blockhead.get_transaction_receipt(hash).await? {
+                    Some(receipt) => format!(
+                        "{} in block {}: {}, gas used {}{}",
+                        receipt.transaction_hash,
+                        receipt.block_hash,
+                        if receipt.status {
+                            "success"
+                        } else {
+                            "reverted"
+                        },
+                        receipt.gas_used,
+                        receipt
+                            .contract_address
+                            .map(|address| format!(", created {address}"))
+                            .unwrap_or_default()
+                    ),
+                    None => format!("no receipt for {hash}"),
+                }
+            }
+            ["balance", address] => {
+                let address = self.book.resolve(address)?;
+                format!(
+                    "{address}: balance {}, nonce {}",
+                    self.blockhead.get_balance(address).await?,
+                    self.blockhead.get_nonce(address).await?
+                )
+            }
+            ["unlock", index, phrase @ ..] if !phrase.is_empty() => {
+                let passphrase = std::env::var("BLOCKHEAD_PASSPHRASE").unwrap_or_default();
+                let wallet = Wallet::from_mnemonic(&phrase.join(" "), &passphrase)?;
+                format!("unlocked {}", self.unlock(wallet.account(index.parse()?)?))
+            }
+            ["accounts"] => self
+                .accounts
+                .keys()
+                .map(Address::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ["send", from, to, value] => {
+                let from = self.book.resolve(from)?;
+                let to = self.book.resolve(to)?;
+                self.send(from, to, value.parse()?).await?.to_string()
+            }
+            ["cancel", hash] => self.cancel(parse_hash(hash)?).await?.to_string(),
+            ["subscribe"] => {
+                let events = self.blockhead.subscribe_chain_events();
+                self.subscription = Some(Subscription::Blocks(events));
+                "subscribed to new blocks".to_string()
+            }
+            ["subscribe", "logs", options @ ..] => {
+                let mut filter = LogFilter::default();
+                let mut from_block = None;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    if *option != "from" {
+                        filter.addresses.push(self.book.resolve(option)?);
+                        continue;
+                    }
+                    let number = options
+                        .next()
+                        .ok_or_else(|| Error::new("`from` needs a block number"))?;
+                    from_block = Some(number.parse()?);
+                }
+                let logs = runtime::block(|| self.blockhead.subscribe_logs(filter, from_block))?;
+                self.subscription = Some(Subscription::Logs(logs));
+                "subscribed to logs".to_string()
+            }
+            ["unsubscribe"] => {
+                self.subscription = None;
+                "unsubscribed".to_string()
+            }
+            _ => return Err(Error::new(format!("unknown command {line:?}; try `help`"))),
+        };
+        Ok(Some(output))
+    }
+
+    async fn describe_transaction(&self, hash: Hash) -> Result<String> {
+        let Some(transaction) = self.blockhead.get_transaction(hash).await? else {
+            return Ok(format!("no transaction {hash}"));
+        };
+        let to = match transaction.to_address {
+            Some(address) => address.to_string(),
+            None => "a new contract".to_string(),
+        };
+        Ok(format!(
+            "{hash}: {:?} of {} from {} to {to}, nonce {}, gas limit {} at {}\nstatus: {:?}",
+            transaction.kind,
+            transaction.value,
+            transaction.from_address,
+            transaction.nonce,
+            transaction.gas_limit,
+            transaction.gas_price,
+            self.blockhead.get_transaction_status(hash).await?
+        ))
+    }
+
+    /// Transfer `value` from unlocked account `from` to `to` at the normal fee estimate.
+    async fn send(&self, from: Address, to: Address, value: u64) -> Result<Hash> {
+        let account = self
+            .accounts
+            .get(&from)
+            .ok_or_else(|| Error::new(format!("{from} is not unlocked")))?;
+        let mut transaction = Transaction {
+            kind: TransactionKind::Transfer,
+            from_address: from,
+            to_address: Some(to),
+            value,
+            data: Bytes::new(),
+            gas_limit: self.blockhead.config.gas.intrinsic_gas(0),
+            gas_price: self.blockhead.estimate_fee().await?.normal,
+            nonce: self.blockhead.get_pending_nonce(from).await?,
+            signatures: Vec::new(),
+        };
+        transaction.sign(
+            account,
+            self.blockhead.config.chain_id,
+            self.blockhead.config.hash,
+        )?;
+        self.blockhead.send_transaction(transaction).await
+    }
+
+    /// Cancel pending transaction `hash`, which an unlocked account sent. See
+    /// [`client::cancel_transaction`].
+    async fn cancel(&self, hash: Hash) -> Result<Hash> {
+        let transaction = self
+            .blockhead
+            .get_transaction(hash)
+            .await?
+            .ok_or_else(|| Error::new(format!("no transaction {hash}")))?;
+        let from = transaction.from_address;
+        let account = self
+            .accounts
+            .get(&from)
+            .ok_or_else(|| Error::new(format!("{from} is not unlocked")))?;
+        client::cancel_transaction(self.blockhead.as_ref(), hash, account).await
+    }
+
+    /// The next event to print for the subscription, waiting forever without one.
+    async fn next_event(&mut self) -> String {
+        while let Some(subscription) = &mut self.subscription {
+            let events = match subscription {
+                Subscription::Blocks(events) => events,
+                Subscription::Logs(logs) => {
+                    return match logs.next().await {
+                        Ok(entries) => entries
+                            .iter()
+                            .map(describe_log)
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                        Err(error) => {
+                            self.subscription = None;
+                            format!("log subscription ended: {error}")
+                        }
+                    };
+                }
+            };
+            return match events.recv().await {
+                Ok(ChainEvent::NewBlock(block)) => format!("new {}", describe_block(&block)),
+                Ok(ChainEvent::Reorg { ancestor, reverted }) => {
+                    let mut lines = vec![format!(
+                        "reorg: reverted {} blocks to {ancestor}",
+                        reverted.len()
+                    )];
+                    lines.extend(
+                        reverted
+                            .iter()
+                            .map(|block| format!("removed block {} {}", block.number, block.hash)),
+                    );
+                    lines.join("\n")
+                }
+                Ok(ChainEvent::FinalizedBlock(header)) => {
+                    format!("finalized block {} {}", header.number, header.hash)
+                }
+                Ok(ChainEvent::ExpiredPendingTx(hash)) => {
+                    format!("dropped expired transaction {hash}")
+                }
+                Ok(ChainEvent::NewPendingTx(_)) => continue,
+                Err(RecvError::Lagged(missed)) => format!("missed {missed} events"),
+                Err(RecvError::Closed) => {
+                    self.subscription = None;
+                    continue;
+                }
+            };
+        }
+        std::future::pending().await
+    }
+}
+
+fn describe_block(block: &Block) -> String {
+    format!(
+        "block {} {}: parent {}, proposer {}, timestamp {}, {} transactions",
+        block.number,
+        block.hash,
+        block.parent_hash,
+        block.proposer,
+        block.timestamp,
+        block.body.transactions.len()
+    )
+}
+
+fn describe_log(entry: &LogEntry) -> String {
+    let topics: Vec<String> = entry.log.topics.iter().map(Hash::to_string).collect();
+    format!(
+        "{}log {} of block {} {} from {}: topics [{}], data 0x{}",
+        if entry.removed { "removed " } else { "" },
+        entry.log_index,
+        entry.block_number,
+        entry.block_hash,
+        entry.log.address,
+        topics.join(", "),
+        hex::encode(&entry.log.data)
+    )
+}
+
+fn parse_hash(s: &str) -> Result<Hash> {
+    Ok(Hash::from(decode_hex32(s)?))
+}
+
+fn parse_block_id(s: &str) -> Result<BlockId> {
+    Ok(match s {
+        "latest" => BlockId::Latest,
+        "finalized" => BlockId::Finalized,
+        "pending" => BlockId::Pending,
+        _ if s.starts_with("0x") => BlockId::Hash(parse_hash(s)?),
+        _ => BlockId::Number(s.parse()?),
+    })
+}
+
+/// Read commands from stdin and print their results, and the subscription's events as they
+/// arrive, until `exit` or the end of input.
+pub(crate) async fn run(mut console: Console) -> Result<()> {
+    // Stdin blocks, so a thread forwards its lines while the shell waits on events too.
+    let (sender, mut lines) = mpsc::channel(1);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            if sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let head = console.blockhead.head()?;
+    println!("blockhead console at block {}; try `help`", head.number);
+    prompt()?;
+    loop {
+        tokio::select! {
+            line = lines.recv() => {
+                let Some(line) = line else {
+                    return Ok(());
+                };
+                match console.execute(&line?).await {
+                    Ok(Some(output)) if output.is_empty() => {}
+                    Ok(Some(output)) => println!("{output}"),
+                    Ok(None) => return Ok(()),
+                    Err(error) => eprintln!("error: {error}"),
+                }
+                prompt()?;
+            }
+            event = console.next_event() => println!("{event}"),
+        }
+    }
+}
+
+fn prompt() -> Result<()> {
+    print!("> ");
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_console() {
+    use crate::hash::HashAlgorithm;
+    use crate::spec;
+
+    let algorithm = HashAlgorithm::default();
+    let genesis = spec::load("dev").unwrap();
+    let blockhead = Arc::new(spec::open_dev(":memory:", genesis).unwrap());
+    let mut book = AddressBook::default();
+    book.add("bob", Address([8; 32])).unwrap();
+    let mut console = Console::new(blockhead.clone(), book);
+    let dev = spec::dev_wallet().account(0).unwrap().address(algorithm);
+
+    let head = console.execute("head").await.unwrap().unwrap();
+    assert!(head.starts_with("block 0 "));
+    assert!(console.execute(&format!("send {dev} bob 5")).await.is_err());
+    let unlock = format!("unlock 0 {}", spec::dev_mnemonic());
+    let unlocked = console.execute(&unlock).await.unwrap().unwrap();
+    assert_eq!(unlocked, format!("unlocked {dev}"));
+    console.execute("subscribe").await.unwrap();
+
+    let send = format!("send {dev} bob 5");
+    let hash = console.execute(&send).await.unwrap().unwrap();
+    blockhead.seal_pending().unwrap();
+    let event = console.next_event().await;
+    assert!(event.starts_with("new block 1 "));
+    assert!(event.ends_with("1 transactions"));
+
+    let tx = console
+        .execute(&format!("tx {hash}"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(tx.contains("Included"));
+    let receipt = console.execute(&format!("receipt {hash}")).await;
+    assert!(receipt.unwrap().unwrap().contains("success"));
+    assert_eq!(
+        console.execute("balance bob").await.unwrap().unwrap(),
+        format!("{}: balance 5, nonce 0", Address([8; 32]))
+    );
+    let block = console.execute("block 1").await.unwrap().unwrap();
+    assert!(block.starts_with("block 1 "));
+    let missing = console.execute("block 9").await.unwrap().unwrap();
+    assert_eq!(missing, "no block 9");
+    assert!(console.execute("frobnicate").await.is_err());
+    let cancelled = console.execute(&send).await.unwrap().unwrap();
+    let cancel = console.execute(&format!("cancel {cancelled}")).await;
+    let replacement = cancel.unwrap().unwrap();
+    let tx = console.execute(&format!("tx {replacement}")).await.unwrap();
+    assert!(tx
+        .unwrap()
+        .contains(&format!("Transfer of 0 from {dev} to {dev}")));
+    let subscribe = console.execute("subscribe logs bob from 0").await;
+    assert_eq!(subscribe.unwrap().unwrap(), "subscribed to logs");
+    assert!(console.execute("subscribe logs from").await.is_err());
+    assert_eq!(console.execute("exit").await.unwrap(), None);
+}
@@ -0,0 +1,66 @@
+//! Cursors for paging through long lists, so that no query answers with an unbounded list.
+//!
+//! Each list answers at most a page, of a size its caller asks for up to the list's maximum,
+//! with a cursor that its next page starts after. Cursors are opaque to callers: they name a
+//! position in one list, which only that list's pages accept, and stay valid as the list grows,
+//! since they name an item rather than an offset. Lists that can count their items cheaply also
+//! answer a total when asked.
+//!
+//! | list                  | surface                                    | cursor names     |
+//! |-----------------------|--------------------------------------------|------------------|
+//! | canonical blocks      | `GET /blocks?cursor=&limit=&total=true`    | a block number   |
+//! | an account's history  | GraphQL `Account.transactions(after:)`     | a transaction    |
+use crate::error::{Error, Result};
+
+/// A cursor naming `position` in the list named `list`.
+pub(crate) fn encode(list: &str, position: &[u64]) -> String {
+    let mut cursor = list.to_string();
+    for part in position {
+        cursor.push_str(&format!(":{part}"));
+    }
+    hex::encode(cursor)
+}
+
+/// The position `cursor` names in the list named `list`. Fails if it is not a cursor of that
+/// list.
+pub(crate) fn decode<const N: usize>(list: &str, cursor: &str) -> Result<[u64; N]> {
+    let invalid = || Error::new(format!("{cursor:?} is not a cursor of {list}"));
+    let decoded = hex::decode(cursor).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let mut parts = decoded.split(':');
+    if parts.next() != Some(list) {
+        return Err(invalid());
+    }
+    let mut position = [0; N];
+    for part in &mut position {
+        *part = parts
+            .next()
+            .and_then(|part| part.parse().ok())
+            .ok_or_else(invalid)?;
+    }
+    match parts.next() {
+        Some(_) => Err(invalid()),
+        None => Ok(position),
+    }
+}
+
+/// The size of a page asked for as `requested`: `default` if unasked, and between one and `max`.
+pub(crate) fn page_size(requested: Option<u64>, default: u64, max: u64) -> u64 {
+    requested.unwrap_or(default).clamp(1, max)
+}
+
+#[test]
+fn test_cursor() {
+    let cursor = encode("blocks", &[7]);
+    assert_eq!(decode::<1>("blocks", &cursor).unwrap(), [7]);
+    assert!(decode::<1>("transactions", &cursor).is_err());
+    assert!(decode::<2>("blocks", &cursor).is_err());
+    let cursor = encode("transactions", &[3, 0]);
+    assert_eq!(decode::<2>("transactions", &cursor).unwrap(), [3, 0]);
+    assert!(decode::<1>("transactions", &cursor).is_err());
+    assert!(decode::<1>("blocks", "zz").is_err());
+    assert!(decode::<1>("blocks", &hex::encode("blocks:x")).is_err());
+    assert_eq!(page_size(None, 20, 100), 20);
+    assert_eq!(page_size(Some(0), 20, 100), 1);
+    assert_eq!(page_size(Some(500), 20, 100), 100);
+}
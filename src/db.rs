@@ -0,0 +1,2788 @@
+//! Row-level access to the blockhead SQLite schema.
+use crate::address::Address;
+use crate::block::{Block, Body, Header};
+use crate::bloom::LogBloom;
+use crate::error::{Error, Result};
+use crate::genesis::ChainConfig;
+use crate::hash::{decode_hex32, Hash};
+use crate::logs::{LogEntry, LogFilter};
+use crate::multisig::MultisigPolicy;
+use crate::signer::Signature;
+use crate::staking::{Validator, ValidatorSet};
+use crate::state::{Account, StateDiff};
+use crate::token::{TokenInfo, TokenSlot};
+use crate::trace::Trace;
+use crate::transaction::{Transaction, TransactionKind};
+use crate::{Log, TransactionReceipt};
+use bytes::Bytes;
+use sqlite::Connection;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+pub(crate) const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS address (
+        id INTEGER PRIMARY KEY,
+        address BLOB UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS block (
+        hash BLOB PRIMARY KEY,
+        parent_hash BLOB,
+        number INTEGER,
+        timestamp_nanos INTEGER,
+        proposer BLOB,
+        canonical INTEGER,
+        transactions_root BLOB,
+        state_root BLOB,
+        gas_limit INTEGER,
+        signature BLOB
+    );
+    CREATE INDEX IF NOT EXISTS block_number ON block (number);
+    CREATE TABLE IF NOT EXISTS transactions (
+        hash BLOB,
+        block_hash BLOB,
+        position INTEGER,
+        kind INTEGER,
+        from_id INTEGER,
+        to_id INTEGER,
+        value INTEGER,
+        data BLOB,
+        nonce INTEGER,
+        gas_limit INTEGER,
+        gas_price INTEGER,
+        signatures BLOB
+    );
+    CREATE INDEX IF NOT EXISTS transactions_hash ON transactions (hash);
+    CREATE INDEX IF NOT EXISTS transactions_block_hash ON transactions (block_hash);
+    CREATE INDEX IF NOT EXISTS transactions_from_id ON transactions (from_id);
+    CREATE INDEX IF NOT EXISTS transactions_to_id ON transactions (to_id);
+    CREATE TABLE IF NOT EXISTS account (
+        address BLOB PRIMARY KEY,
+        balance INTEGER,
+        nonce INTEGER,
+        stake INTEGER,
+        unbonding INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS epoch (
+        epoch INTEGER PRIMARY KEY,
+        seed BLOB
+    );
+    CREATE TABLE IF NOT EXISTS validator (
+        epoch INTEGER,
+        address BLOB,
+        stake INTEGER,
+        PRIMARY KEY (epoch, address)
+    );
+    CREATE TABLE IF NOT EXISTS account_undo (
+        block_hash BLOB,
+        address BLOB,
+        balance INTEGER,
+        nonce INTEGER,
+        stake INTEGER,
+        unbonding INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS account_undo_block_hash ON account_undo (block_hash);
+    CREATE TABLE IF NOT EXISTS attestation (
+        checkpoint BLOB,
+        validator BLOB,
+        block_hash BLOB,
+        PRIMARY KEY (checkpoint, validator)
+    );
+    CREATE TABLE IF NOT EXISTS code (
+        address BLOB PRIMARY KEY,
+        code BLOB,
+        block_hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS multisig (
+        address BLOB PRIMARY KEY,
+        policy BLOB,
+        block_hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS token (
+        address BLOB PRIMARY KEY,
+        info BLOB,
+        block_hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS token_slot (
+        token BLOB,
+        slot BLOB,
+        amount INTEGER,
+        PRIMARY KEY (token, slot)
+    );
+    CREATE TABLE IF NOT EXISTS token_slot_undo (
+        block_hash BLOB,
+        token BLOB,
+        slot BLOB,
+        amount INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS token_slot_undo_block_hash ON token_slot_undo (block_hash);
+    CREATE TABLE IF NOT EXISTS receipt (
+        transaction_hash BLOB,
+        block_hash BLOB,
+        status INTEGER,
+        gas_used INTEGER,
+        contract_address BLOB,
+        return_data BLOB
+    );
+    CREATE INDEX IF NOT EXISTS receipt_transaction_hash ON receipt (transaction_hash);
+    CREATE TABLE IF NOT EXISTS pending_receipts (
+        block_hash BLOB PRIMARY KEY,
+        number INTEGER,
+        receipts TEXT
+    );
+    CREATE INDEX IF NOT EXISTS pending_receipts_number ON pending_receipts (number);
+    CREATE TABLE IF NOT EXISTS mempool (
+        position INTEGER PRIMARY KEY,
+        raw BLOB
+    );
+    CREATE TABLE IF NOT EXISTS log (
+        block_hash BLOB,
+        number INTEGER,
+        transaction_hash BLOB,
+        log_index INTEGER,
+        address_id INTEGER,
+        topics TEXT,
+        data BLOB
+    );
+    CREATE INDEX IF NOT EXISTS log_position ON log (block_hash, log_index);
+    CREATE INDEX IF NOT EXISTS log_transaction_hash ON log (transaction_hash);
+    CREATE TABLE IF NOT EXISTS log_topic (
+        topic BLOB,
+        position INTEGER,
+        block_hash BLOB,
+        number INTEGER,
+        log_index INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS log_topic_topic ON log_topic (topic, position, number);
+    CREATE INDEX IF NOT EXISTS log_topic_block_hash ON log_topic (block_hash);
+    CREATE TABLE IF NOT EXISTS log_bloom (
+        block_hash BLOB PRIMARY KEY,
+        number INTEGER,
+        bloom BLOB
+    );
+    CREATE INDEX IF NOT EXISTS log_bloom_number ON log_bloom (number);
+    CREATE TABLE IF NOT EXISTS storage (
+        address BLOB,
+        key INTEGER,
+        value INTEGER,
+        PRIMARY KEY (address, key)
+    );
+    CREATE TABLE IF NOT EXISTS storage_undo (
+        block_hash BLOB,
+        address BLOB,
+        key INTEGER,
+        value INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS storage_undo_block_hash ON storage_undo (block_hash);
+    CREATE TABLE IF NOT EXISTS state_diff (
+        block_hash BLOB PRIMARY KEY,
+        diff TEXT
+    );
+    CREATE TABLE IF NOT EXISTS trace (
+        transaction_hash BLOB,
+        block_hash BLOB,
+        number INTEGER,
+        trace TEXT
+    );
+    CREATE INDEX IF NOT EXISTS trace_transaction_hash ON trace (transaction_hash);
+    CREATE INDEX IF NOT EXISTS trace_number ON trace (number);
+    CREATE TABLE IF NOT EXISTS finalized (
+        number INTEGER PRIMARY KEY,
+        hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS account_history (
+        address BLOB,
+        number INTEGER,
+        block_hash BLOB,
+        balance INTEGER,
+        nonce INTEGER,
+        stake INTEGER,
+        unbonding INTEGER,
+        PRIMARY KEY (address, number)
+    );
+    CREATE INDEX IF NOT EXISTS account_history_block_hash ON account_history (block_hash);
+    CREATE TABLE IF NOT EXISTS storage_history (
+        address BLOB,
+        key INTEGER,
+        number INTEGER,
+        block_hash BLOB,
+        value INTEGER,
+        PRIMARY KEY (address, key, number)
+    );
+    CREATE INDEX IF NOT EXISTS storage_history_block_hash ON storage_history (block_hash);
+    CREATE TABLE IF NOT EXISTS token_slot_history (
+        token BLOB,
+        slot BLOB,
+        number INTEGER,
+        block_hash BLOB,
+        amount INTEGER,
+        PRIMARY KEY (token, slot, number)
+    );
+    CREATE INDEX IF NOT EXISTS token_slot_history_block_hash ON token_slot_history (block_hash);
+    CREATE TABLE IF NOT EXISTS archive (
+        number INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS state_checkpoint (
+        number INTEGER PRIMARY KEY,
+        block_hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS head (
+        hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS chain_config (
+        config TEXT
+    );
+    PRAGMA user_version = 4;
+";
+
+/// The `user_version` that [`SCHEMA`] and [`COLD_SCHEMA`] record. Databases written before
+/// version 1 held hashes and addresses as hex text rather than bytes, before version 2 had no
+/// `log_topic` index, before version 3 no `log_bloom` of each block's logs, and before version 4
+/// no block signatures.
+const VERSION: i64 = 4;
+
+/// The version of the tables in `schema`, or `None` if it has no `table` yet.
+fn schema_version(connection: &Connection, schema: &str, table: &str) -> Result<Option<i64>> {
+    if !has_table(connection, schema, table)? {
+        return Ok(None);
+    }
+    let query = format!("PRAGMA {schema}.user_version");
+    Ok(Some(match connection.prepare(query)?.into_iter().next() {
+        Some(row) => row?.read::<i64, _>("user_version"),
+        None => 0,
+    }))
+}
+
+fn has_table(connection: &Connection, schema: &str, table: &str) -> Result<bool> {
+    let query = format!("SELECT 1 FROM {schema}.sqlite_schema WHERE type = 'table' AND name = ?");
+    let mut rows = connection.prepare(query)?.into_iter().bind((1, table))?;
+    Ok(rows.next().is_some())
+}
+
+/// Fail if the main database needs [`upgrade`], for connections that cannot write it.
+pub(crate) fn check_upgraded(connection: &Connection) -> Result<()> {
+    if schema_version(connection, "main", "block")?.is_some_and(|version| version < VERSION) {
+        return Err(Error::new(
+            "the database was written by an older version; open it for writing once to upgrade it",
+        ));
+    }
+    Ok(())
+}
+
+/// Bring a database written by an older version up to [`VERSION`]: store each hash and address
+/// as its bytes and intern addresses if it predates version 1, create the tables it lacks, index
+/// the topics of its logs if it predates version 2, and summarize each block's logs in a bloom.
+/// Does nothing to a new or current database. Must run before [`SCHEMA`] first does.
+pub(crate) fn upgrade(connection: &Connection) -> Result<()> {
+    let Some(version) = schema_version(connection, "main", "block")? else {
+        return Ok(());
+    };
+    if version >= VERSION {
+        return Ok(());
+    }
+    let mut tables = Vec::new();
+    if version == 0 {
+        log::info!("converting the database's hashes and addresses to bytes");
+        let query = "SELECT name FROM main.sqlite_schema
+            WHERE type = 'table' AND name NOT LIKE 'sqlite_%'";
+        for row in connection.prepare(query)?.into_iter() {
+            tables.push(row?.read::<&str, _>("name").to_string());
+        }
+    }
+    log::info!("upgrading the database from version {version}");
+    transaction(connection, || {
+        set_aside(connection, "main", &tables)?;
+        if version > 0 {
+            connection.execute("ALTER TABLE main.block ADD COLUMN signature BLOB")?;
+        }
+        // Replaced by `log_position`, which also finds a log by its index.
+        connection.execute("DROP INDEX IF EXISTS main.log_block_hash")?;
+        connection.execute(SCHEMA)?;
+        for table in &tables {
+            convert_rows(connection, "main", table)?;
+        }
+        if version < 2 {
+            index_log_topics(connection, "main")?;
+        }
+        if version < 3 {
+            index_log_blooms(connection, "main")?;
+        }
+        Ok(())
+    })
+}
+
+/// Fill `log_topic` in `schema` afresh from the topics of its logs.
+fn index_log_topics(connection: &Connection, schema: &str) -> Result<()> {
+    connection.execute(format!("DELETE FROM {schema}.log_topic"))?;
+    let query = format!("SELECT block_hash, number, log_index, topics FROM {schema}.log");
+    let mut rows = connection.prepare(query)?;
+    while rows.next()? == sqlite::State::Row {
+        let block_hash = read_hash(&rows.read::<Vec<u8>, _>("block_hash")?)?;
+        let topics: Vec<Hash> = serde_json::from_str(&rows.read::<String, _>("topics")?)?;
+        let number = rows.read::<i64, _>("number")?;
+        let log_index = rows.read::<i64, _>("log_index")?;
+        write_log_topics(connection, schema, block_hash, number, log_index, &topics)?;
+    }
+    Ok(())
+}
+
+/// Summarize the logs in `schema` in `log_bloom`, which is in the main database, adding them to
+/// the blooms already there.
+fn index_log_blooms(connection: &Connection, schema: &str) -> Result<()> {
+    let query = format!(
+        "SELECT log.block_hash, log.number, log.topics, address.address FROM {schema}.log
+            JOIN main.address ON address.id = log.address_id
+            ORDER BY log.block_hash"
+    );
+    let mut rows = connection.prepare(query)?;
+    let mut block: Option<(Hash, i64, LogBloom)> = None;
+    while rows.next()? == sqlite::State::Row {
+        let block_hash = read_hash(&rows.read::<Vec<u8>, _>("block_hash")?)?;
+        if block.as_ref().is_some_and(|&(hash, ..)| hash != block_hash) {
+            let (hash, number, bloom) = block.take().expect("checked above");
+            write_log_bloom(connection, hash, number, bloom)?;
+        }
+        let number = rows.read::<i64, _>("number")?;
+        let (_, _, bloom) = block.get_or_insert((block_hash, number, LogBloom::default()));
+        bloom.add_log(&Log {
+            address: read_address(&rows.read::<Vec<u8>, _>("address")?)?,
+            topics: serde_json::from_str(&rows.read::<String, _>("topics")?)?,
+            data: Vec::new(),
+        });
+    }
+    if let Some((hash, number, bloom)) = block {
+        write_log_bloom(connection, hash, number, bloom)?;
+    }
+    Ok(())
+}
+
+/// Rename each of `tables` in `schema` to `old_` followed by its name, and drop their indexes so
+/// that the tables taking their place can create their own.
+fn set_aside<T: AsRef<str>>(connection: &Connection, schema: &str, tables: &[T]) -> Result<()> {
+    for table in tables {
+        let table = table.as_ref();
+        connection.execute(format!(
+            "ALTER TABLE {schema}.{table} RENAME TO old_{table}"
+        ))?;
+        let query = format!(
+            "SELECT name FROM {schema}.sqlite_schema
+                WHERE type = 'index' AND sql IS NOT NULL AND tbl_name = ?"
+        );
+        let mut indexes = Vec::new();
+        for row in connection
+            .prepare(query)?
+            .into_iter()
+            .bind((1, format!("old_{table}").as_str()))?
+        {
+            indexes.push(row?.read::<&str, _>("name").to_string());
+        }
+        for index in indexes {
+            connection.execute(format!("DROP INDEX {schema}.{index}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the rows of `table` set aside by [`set_aside`] into the new `table`, converting the hex
+/// text of each hash and address to bytes, then drop the old table.
+fn convert_rows(connection: &Connection, schema: &str, table: &str) -> Result<()> {
+    let mut rows = connection.prepare(format!("SELECT * FROM {schema}.old_{table}"))?;
+    let columns = rows.column_names().to_vec();
+    let targets: Vec<&str> = (columns.iter())
+        .map(|column| match (table, column.as_str()) {
+            ("transactions", "from_address") => "from_id",
+            ("transactions", "to_address") => "to_id",
+            ("log", "address") => "address_id",
+            (_, column) => column,
+        })
+        .collect();
+    let mut insert = connection.prepare(format!(
+        "INSERT INTO {schema}.{table} ({}) VALUES ({})",
+        targets.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    ))?;
+    while rows.next()? == sqlite::State::Row {
+        for (index, target) in targets.iter().enumerate() {
+            let value = match rows.read::<sqlite::Value, _>(index)? {
+                sqlite::Value::String(text) => convert_text(connection, target, text)?,
+                value => value,
+            };
+            insert.bind((index + 1, value))?;
+        }
+        insert.next()?;
+        insert.reset()?;
+    }
+    drop((rows, insert));
+    connection.execute(format!("DROP TABLE {schema}.old_{table}"))?;
+    Ok(())
+}
+
+/// The value of `column` that an old database held as `text`: JSON stays as it was, token slots
+/// and interned addresses take their new form, and everything else is a hash or an address.
+fn convert_text(connection: &Connection, column: &str, text: String) -> Result<sqlite::Value> {
+    Ok(match column {
+        "receipts" | "topics" | "diff" | "trace" => sqlite::Value::String(text),
+        "slot" => sqlite::Value::Binary(TokenSlot::parse(&text)?.encode()),
+        "from_id" | "to_id" | "address_id" => {
+            sqlite::Value::Integer(intern(connection, Address(decode_hex32(&text)?))?)
+        }
+        _ => sqlite::Value::Binary(decode_hex32(&text)?.to_vec()),
+    })
+}
+
+fn read_hash(bytes: &[u8]) -> Result<Hash> {
+    Hash::try_from(bytes)
+}
+
+fn read_address(bytes: &[u8]) -> Result<Address> {
+    Address::try_from(bytes)
+}
+
+fn read_headers(
+    connection: &Connection,
+    query: &str,
+    keys: &[sqlite::Value],
+) -> Result<Vec<Header>> {
+    let mut rows = connection.prepare(query)?.into_iter();
+    for (index, key) in keys.iter().enumerate() {
+        rows = rows.bind((index + 1, key.clone()))?;
+    }
+    let mut headers = Vec::new();
+    for row in rows {
+        let row = row?;
+        headers.push(Header {
+            hash: read_hash(row.read::<&[u8], _>("hash"))?,
+            parent_hash: read_hash(row.read::<&[u8], _>("parent_hash"))?,
+            number: row.read::<i64, _>("number") as u64,
+            timestamp: row.read::<i64, _>("timestamp_nanos") as u64,
+            proposer: read_address(row.read::<&[u8], _>("proposer"))?,
+            transactions_root: read_hash(row.read::<&[u8], _>("transactions_root"))?,
+            state_root: read_hash(row.read::<&[u8], _>("state_root"))?,
+            gas_limit: row.read::<i64, _>("gas_limit") as u64,
+            signature: match row.read::<Option<&[u8]>, _>("signature") {
+                Some(bytes) => Some(Signature(bytes.try_into().map_err(|_| {
+                    Error::new(format!("bad block signature of {} bytes", bytes.len()))
+                })?)),
+                None => None,
+            },
+        });
+    }
+    Ok(headers)
+}
+
+fn read_blocks(connection: &Connection, query: &str, keys: &[sqlite::Value]) -> Result<Vec<Block>> {
+    read_headers(connection, query, keys)?
+        .into_iter()
+        .map(|header| {
+            let transactions = read_block_transactions(connection, header.hash)?;
+            Ok(Block {
+                header,
+                body: Body { transactions },
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn read_header(connection: &Connection, hash: Hash) -> Result<Option<Header>> {
+    let query = "SELECT * FROM block WHERE hash = ? LIMIT 1";
+    Ok(read_headers(connection, query, &[hash.0.to_vec().into()])?.pop())
+}
+
+pub(crate) fn read_canonical_header(
+    connection: &Connection,
+    number: u64,
+) -> Result<Option<Header>> {
+    let query = "SELECT * FROM block WHERE number = ? AND canonical = 1 LIMIT 1";
+    Ok(read_headers(connection, query, &[(number as i64).into()])?.pop())
+}
+
+pub(crate) fn read_block(connection: &Connection, hash: Hash) -> Result<Option<Block>> {
+    let query = "SELECT * FROM block WHERE hash = ? LIMIT 1";
+    Ok(read_blocks(connection, query, &[hash.0.to_vec().into()])?.pop())
+}
+
+pub(crate) fn read_canonical_block(connection: &Connection, number: u64) -> Result<Option<Block>> {
+    let query = "SELECT * FROM block WHERE number = ? AND canonical = 1 LIMIT 1";
+    Ok(read_blocks(connection, query, &[(number as i64).into()])?.pop())
+}
+
+/// Every block at height `number` marked canonical. A consistent database has exactly one.
+pub(crate) fn read_canonical_blocks(connection: &Connection, number: u64) -> Result<Vec<Block>> {
+    let query = "SELECT * FROM block WHERE number = ? AND canonical = 1";
+    read_blocks(connection, query, &[(number as i64).into()])
+}
+
+/// Up to `limit` canonical blocks numbered `from` to `to`, in order.
+pub(crate) fn read_canonical_range(
+    connection: &Connection,
+    from: u64,
+    to: u64,
+    limit: u64,
+) -> Result<Vec<Block>> {
+    let query = "SELECT * FROM block WHERE number BETWEEN ? AND ? AND canonical = 1
+        ORDER BY number LIMIT ?";
+    let keys = [from, to, limit].map(|key| sqlite::Value::Integer(key.min(i64::MAX as u64) as i64));
+    read_blocks(connection, query, &keys)
+}
+
+/// The block the head pointer names, which is the block new blocks build on. Databases written
+/// before the pointer existed fall back to the highest canonical block.
+pub(crate) fn read_head(connection: &Connection) -> Result<Option<Block>> {
+    let query = "SELECT block.* FROM head JOIN block ON block.hash = head.hash LIMIT 1";
+    match read_blocks(connection, query, &[])?.pop() {
+        Some(head) => Ok(Some(head)),
+        None => read_canonical_tip(connection),
+    }
+}
+
+/// The canonical block with the highest number. In a consistent database it is the head.
+pub(crate) fn read_canonical_tip(connection: &Connection) -> Result<Option<Block>> {
+    let query = "SELECT * FROM block WHERE canonical = 1 ORDER BY number DESC LIMIT 1";
+    Ok(read_blocks(connection, query, &[])?.pop())
+}
+
+/// The hash the head pointer holds, which may name a missing block in a damaged database.
+pub(crate) fn read_head_hash(connection: &Connection) -> Result<Option<Hash>> {
+    let mut rows = connection.prepare("SELECT hash FROM head")?.into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(read_hash(row?.read::<&[u8], _>("hash"))?))
+}
+
+/// Point the head at `hash`. [`set_canonical`] keeps the pointer in step with the canonical
+/// flags, in the same transaction, so this is only needed to start or repair it.
+pub(crate) fn write_head(connection: &Connection, hash: Hash) -> Result<()> {
+    connection.execute("DELETE FROM head")?;
+    let mut statement = connection.prepare("INSERT INTO head VALUES (?)")?;
+    statement.bind((1, &hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The protocol parameters of the chain the database holds, if they are recorded. Databases
+/// written before they were have none until [`write_chain_config`] records them.
+pub(crate) fn read_chain_config(connection: &Connection) -> Result<Option<ChainConfig>> {
+    if !has_table(connection, "main", "chain_config")? {
+        return Ok(None);
+    }
+    let mut rows = connection
+        .prepare("SELECT config FROM chain_config")?
+        .into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(row?.read::<&str, _>("config"))?))
+}
+
+/// Record the protocol parameters of `config`. The settings local to the node are not stored.
+pub(crate) fn write_chain_config(connection: &Connection, config: &ChainConfig) -> Result<()> {
+    connection.execute("DELETE FROM chain_config")?;
+    let mut statement = connection.prepare("INSERT INTO chain_config VALUES (?)")?;
+    statement.bind((1, serde_json::to_string(config)?.as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn read_block_is_canonical(connection: &Connection, hash: Hash) -> Result<Option<bool>> {
+    let query = "SELECT canonical FROM block WHERE hash = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(row?.read::<i64, _>("canonical") != 0))
+}
+
+/// Mark the block `hash` canonical or not, and move the head pointer onto it, or back to its
+/// parent if it was the head. Blocks are made canonical in ascending order and reverted in
+/// descending order, so the pointer always names the highest canonical block.
+pub(crate) fn set_canonical(connection: &Connection, hash: Hash, canonical: bool) -> Result<()> {
+    let mut statement = connection.prepare("UPDATE block SET canonical = ? WHERE hash = ?")?;
+    statement.bind((1, canonical as i64))?;
+    statement.bind((2, &hash.0[..]))?;
+    statement.next()?;
+    crash_point("set_canonical");
+    if canonical {
+        return write_head(connection, hash);
+    }
+    let query = "UPDATE head SET hash = (SELECT parent_hash FROM block WHERE hash = ?1)
+        WHERE hash = ?1";
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn write_block(connection: &Connection, block: &Block, canonical: bool) -> Result<()> {
+    let query = "INSERT INTO block VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &block.hash.0[..]))?;
+    statement.bind((2, &block.parent_hash.0[..]))?;
+    statement.bind((3, block.number as i64))?;
+    statement.bind((4, block.timestamp as i64))?;
+    statement.bind((5, &block.proposer.0[..]))?;
+    statement.bind((6, canonical as i64))?;
+    statement.bind((7, &block.transactions_root.0[..]))?;
+    statement.bind((8, &block.state_root.0[..]))?;
+    statement.bind((9, block.gas_limit as i64))?;
+    statement.bind((
+        10,
+        block.signature.as_ref().map(|signature| &signature.0[..]),
+    ))?;
+    statement.next()?;
+
+    let query = "INSERT INTO main.transactions
+        (hash, block_hash, position, kind, from_id, to_id, value, data, nonce, gas_limit,
+            gas_price, signatures)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+    for (position, (hash, transaction)) in block.body.transactions.iter().enumerate() {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, &hash.0[..]))?;
+        statement.bind((2, &block.hash.0[..]))?;
+        statement.bind((3, position as i64))?;
+        statement.bind((4, transaction.kind.to_i64()))?;
+        statement.bind((5, intern(connection, transaction.from_address)?))?;
+        let to_id = transaction.to_address.map(|a| intern(connection, a));
+        statement.bind((6, to_id.transpose()?))?;
+        statement.bind((7, transaction.value as i64))?;
+        statement.bind((8, &transaction.data[..]))?;
+        statement.bind((9, transaction.nonce as i64))?;
+        statement.bind((10, transaction.gas_limit as i64))?;
+        statement.bind((11, transaction.gas_price as i64))?;
+        let signatures = transaction
+            .signatures
+            .iter()
+            .flat_map(|signature| signature.0);
+        statement.bind((12, signatures.collect::<Vec<u8>>().as_slice()))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// The id of `address` in the `address` table, which names the addresses that many rows refer
+/// to by a small integer rather than by their 32 bytes, if it has one.
+fn read_address_id(connection: &Connection, address: Address) -> Result<Option<i64>> {
+    let query = "SELECT id FROM address WHERE address = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &address.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(row?.read::<i64, _>("id")))
+}
+
+/// The id of `address`, adding it to the `address` table if it has none yet.
+fn intern(connection: &Connection, address: Address) -> Result<i64> {
+    if let Some(id) = read_address_id(connection, address)? {
+        return Ok(id);
+    }
+    let query = "INSERT INTO address (address) VALUES (?) RETURNING id";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &address.0[..]))?;
+    match rows.next() {
+        Some(row) => Ok(row?.read::<i64, _>("id")),
+        None => Err(Error::new("interning an address returned no id")),
+    }
+}
+
+/// The columns of `transactions`, with the interned addresses its rows refer to by id.
+const TRANSACTION_COLUMNS: &str = "transactions.*,
+    (SELECT address FROM address WHERE id = transactions.from_id) AS from_address,
+    (SELECT address FROM address WHERE id = transactions.to_id) AS to_address";
+
+fn read_transaction_row(row: &sqlite::Row) -> Result<(Hash, Transaction)> {
+    Ok((
+        read_hash(row.read::<&[u8], _>("hash"))?,
+        Transaction {
+            kind: TransactionKind::try_from(row.read::<i64, _>("kind"))?,
+            from_address: read_address(row.read::<&[u8], _>("from_address"))?,
+            to_address: row
+                .read::<Option<&[u8]>, _>("to_address")
+                .map(read_address)
+                .transpose()?,
+            value: row.read::<i64, _>("value") as u64,
+            data: Bytes::copy_from_slice(row.read::<&[u8], _>("data")),
+            gas_limit: row.read::<i64, _>("gas_limit") as u64,
+            gas_price: row.read::<i64, _>("gas_price") as u64,
+            nonce: row.read::<i64, _>("nonce") as u64,
+            signatures: {
+                let bytes = row.read::<&[u8], _>("signatures");
+                if !bytes.len().is_multiple_of(65) {
+                    return Err(Error::new("stored signatures are not 65 bytes each"));
+                }
+                bytes
+                    .chunks_exact(65)
+                    .map(|chunk| Signature(chunk.try_into().unwrap()))
+                    .collect()
+            },
+        },
+    ))
+}
+
+pub(crate) fn read_block_transactions(
+    connection: &Connection,
+    block_hash: Hash,
+) -> Result<Vec<(Hash, Transaction)>> {
+    let query = format!(
+        "SELECT {TRANSACTION_COLUMNS} FROM transactions WHERE block_hash = ? ORDER BY position"
+    );
+    let mut transactions = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?
+    {
+        transactions.push(read_transaction_row(&row?)?);
+    }
+    Ok(transactions)
+}
+
+/// Look up a transaction included in a canonical block.
+pub(crate) fn read_transaction(connection: &Connection, hash: Hash) -> Result<Option<Transaction>> {
+    let query = format!(
+        "SELECT {TRANSACTION_COLUMNS} FROM transactions
+            JOIN block ON block.hash = transactions.block_hash
+            WHERE transactions.hash = ? AND block.canonical = 1 LIMIT 1"
+    );
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(read_transaction_row(&row?)?.1))
+}
+
+/// Up to `limit` canonical transactions sent or received by `address`, newest first, starting
+/// after the one at `before`, a block number and position in the block, if given.
+pub(crate) fn read_account_transactions(
+    connection: &Connection,
+    address: Address,
+    before: Option<(u64, u64)>,
+    limit: u64,
+) -> Result<Vec<(Hash, Transaction)>> {
+    let query = format!(
+        "SELECT {TRANSACTION_COLUMNS} FROM transactions
+            JOIN block ON block.hash = transactions.block_hash
+            WHERE (transactions.from_id = ?1 OR transactions.to_id = ?1)
+                AND block.canonical = 1 AND (block.number, transactions.position) < (?2, ?3)
+            ORDER BY block.number DESC, transactions.position DESC LIMIT ?4"
+    );
+    let Some(id) = read_address_id(connection, address)? else {
+        return Ok(Vec::new());
+    };
+    let (number, position) = before.unwrap_or((i64::MAX as u64, 0));
+    let rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, id))?
+        .bind((2, number as i64))?
+        .bind((3, position as i64))?
+        .bind((4, limit as i64))?;
+    let mut transactions = Vec::new();
+    for row in rows {
+        transactions.push(read_transaction_row(&row?)?);
+    }
+    Ok(transactions)
+}
+
+/// How many canonical transactions `address` sent or received.
+pub(crate) fn count_account_transactions(connection: &Connection, address: Address) -> Result<u64> {
+    let query = "SELECT COUNT(*) AS count FROM transactions
+        JOIN block ON block.hash = transactions.block_hash
+        WHERE (transactions.from_id = ?1 OR transactions.to_id = ?1) AND block.canonical = 1";
+    let Some(id) = read_address_id(connection, address)? else {
+        return Ok(0);
+    };
+    let mut rows = connection.prepare(query)?.into_iter().bind((1, id))?;
+    let count = match rows.next() {
+        Some(row) => row?.read::<i64, _>("count"),
+        None => 0,
+    };
+    Ok(count as u64)
+}
+
+/// The canonical block containing transaction `hash`, and the transaction's position in it.
+pub(crate) fn read_transaction_location(
+    connection: &Connection,
+    hash: Hash,
+) -> Result<Option<(Hash, usize)>> {
+    let query = "SELECT transactions.block_hash, transactions.position FROM transactions
+        JOIN block ON block.hash = transactions.block_hash
+        WHERE transactions.hash = ? AND block.canonical = 1 LIMIT 1";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    let row = row?;
+    Ok(Some((
+        read_hash(row.read::<&[u8], _>("block_hash"))?,
+        row.read::<i64, _>("position") as usize,
+    )))
+}
+
+/// The gas prices of the transactions in canonical blocks from height `number` on, ascending.
+pub(crate) fn read_gas_prices_since(connection: &Connection, number: u64) -> Result<Vec<u64>> {
+    let query = "SELECT transactions.gas_price FROM transactions
+        JOIN block ON block.hash = transactions.block_hash
+        WHERE block.canonical = 1 AND block.number >= ?
+        ORDER BY transactions.gas_price";
+    let rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, number as i64))?;
+    let mut prices = Vec::new();
+    for row in rows {
+        prices.push(row?.read::<i64, _>("gas_price") as u64);
+    }
+    Ok(prices)
+}
+
+pub(crate) fn read_account(connection: &Connection, address: Address) -> Result<Option<Account>> {
+    let query = "SELECT * FROM account WHERE address = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &address.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    let row = row?;
+    Ok(Some(Account {
+        balance: row.read::<i64, _>("balance") as u64,
+        nonce: row.read::<i64, _>("nonce") as u64,
+        stake: row.read::<i64, _>("stake") as u64,
+        unbonding: row.read::<i64, _>("unbonding") as u64,
+    }))
+}
+
+/// The balances of `addresses`, in order, with zero for those that have no account, read in one
+/// query however many there are. The addresses are bound as one blob, which the query splits
+/// into 32-byte pieces.
+pub(crate) fn read_balances(connection: &Connection, addresses: &[Address]) -> Result<Vec<u64>> {
+    let query = "WITH RECURSIVE piece (start) AS
+            (SELECT 1 UNION ALL SELECT start + 32 FROM piece WHERE start + 32 <= length(?1))
+        SELECT address, balance FROM account
+        WHERE address IN (SELECT substr(?1, start, 32) FROM piece)";
+    let joined: Vec<u8> = addresses.iter().flat_map(|address| address.0).collect();
+    let rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, joined.as_slice()))?;
+    let mut balances = HashMap::new();
+    for row in rows {
+        let row = row?;
+        let address = read_address(row.read::<&[u8], _>("address"))?;
+        balances.insert(address, row.read::<i64, _>("balance") as u64);
+    }
+    Ok(addresses
+        .iter()
+        .map(|address| balances.get(address).copied().unwrap_or_default())
+        .collect())
+}
+
+pub(crate) fn write_account(
+    connection: &Connection,
+    address: Address,
+    account: &Account,
+) -> Result<()> {
+    let query = "INSERT OR REPLACE INTO account VALUES (?, ?, ?, ?, ?)";
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &address.0[..]))?;
+    statement.bind((2, account.balance as i64))?;
+    statement.bind((3, account.nonce as i64))?;
+    statement.bind((4, account.stake as i64))?;
+    statement.bind((5, account.unbonding as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn read_accounts(connection: &Connection) -> Result<BTreeMap<Address, Account>> {
+    let mut accounts = BTreeMap::new();
+    for row in connection.prepare("SELECT * FROM account")?.into_iter() {
+        let row = row?;
+        accounts.insert(
+            read_address(row.read::<&[u8], _>("address"))?,
+            Account {
+                balance: row.read::<i64, _>("balance") as u64,
+                nonce: row.read::<i64, _>("nonce") as u64,
+                stake: row.read::<i64, _>("stake") as u64,
+                unbonding: row.read::<i64, _>("unbonding") as u64,
+            },
+        );
+    }
+    Ok(accounts)
+}
+
+/// All accounts holding a non-zero stake, ordered by address.
+pub(crate) fn read_stakes(connection: &Connection) -> Result<Vec<(Address, u64)>> {
+    let query = "SELECT address, stake FROM account WHERE stake > 0 ORDER BY address";
+    let mut stakes = Vec::new();
+    for row in connection.prepare(query)?.into_iter() {
+        let row = row?;
+        stakes.push((
+            read_address(row.read::<&[u8], _>("address"))?,
+            row.read::<i64, _>("stake") as u64,
+        ));
+    }
+    Ok(stakes)
+}
+
+/// All accounts with funds waiting to be released at the next epoch boundary.
+pub(crate) fn read_unbonding(connection: &Connection) -> Result<Vec<Address>> {
+    let query = "SELECT address FROM account WHERE unbonding > 0 ORDER BY address";
+    let mut addresses = Vec::new();
+    for row in connection.prepare(query)?.into_iter() {
+        addresses.push(read_address(row?.read::<&[u8], _>("address"))?);
+    }
+    Ok(addresses)
+}
+
+pub(crate) fn read_validator_set(
+    connection: &Connection,
+    epoch: u64,
+) -> Result<Option<ValidatorSet>> {
+    let query = "SELECT seed FROM epoch WHERE epoch = ?";
+    let mut seed = None;
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, epoch as i64))?
+    {
+        seed = Some(read_hash(row?.read::<&[u8], _>("seed"))?);
+    }
+    let Some(seed) = seed else {
+        return Ok(None);
+    };
+    let query = "SELECT address, stake FROM validator WHERE epoch = ? ORDER BY address";
+    let mut validators = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, epoch as i64))?
+    {
+        let row = row?;
+        validators.push(Validator {
+            address: read_address(row.read::<&[u8], _>("address"))?,
+            stake: row.read::<i64, _>("stake") as u64,
+        });
+    }
+    Ok(Some(ValidatorSet {
+        epoch,
+        seed,
+        validators,
+    }))
+}
+
+pub(crate) fn write_validator_set(
+    connection: &Connection,
+    validator_set: &ValidatorSet,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO epoch VALUES (?, ?)")?;
+    statement.bind((1, validator_set.epoch as i64))?;
+    statement.bind((2, &validator_set.seed.0[..]))?;
+    statement.next()?;
+    for validator in &validator_set.validators {
+        let mut statement =
+            connection.prepare("INSERT OR REPLACE INTO validator VALUES (?, ?, ?)")?;
+        statement.bind((1, validator_set.epoch as i64))?;
+        statement.bind((2, &validator.address.0[..]))?;
+        statement.bind((3, validator.stake as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Forget the validator sets of every epoch after `epoch`, which were entered by reverted blocks.
+pub(crate) fn delete_epochs_after(connection: &Connection, epoch: u64) -> Result<()> {
+    for query in [
+        "DELETE FROM epoch WHERE epoch > ?",
+        "DELETE FROM validator WHERE epoch > ?",
+    ] {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, epoch as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_account_undo(
+    connection: &Connection,
+    block_hash: Hash,
+    address: Address,
+    account: &Account,
+) -> Result<()> {
+    let query = "INSERT INTO account_undo VALUES (?, ?, ?, ?, ?, ?)";
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &block_hash.0[..]))?;
+    statement.bind((2, &address.0[..]))?;
+    statement.bind((3, account.balance as i64))?;
+    statement.bind((4, account.nonce as i64))?;
+    statement.bind((5, account.stake as i64))?;
+    statement.bind((6, account.unbonding as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The account values that were in place before `block_hash` was applied.
+pub(crate) fn read_account_undo(
+    connection: &Connection,
+    block_hash: Hash,
+) -> Result<Vec<(Address, Account)>> {
+    let query = "SELECT * FROM account_undo WHERE block_hash = ?";
+    let mut accounts = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?
+    {
+        let row = row?;
+        accounts.push((
+            read_address(row.read::<&[u8], _>("address"))?,
+            Account {
+                balance: row.read::<i64, _>("balance") as u64,
+                nonce: row.read::<i64, _>("nonce") as u64,
+                stake: row.read::<i64, _>("stake") as u64,
+                unbonding: row.read::<i64, _>("unbonding") as u64,
+            },
+        ));
+    }
+    Ok(accounts)
+}
+
+pub(crate) fn read_storage(connection: &Connection, address: Address, key: u64) -> Result<u64> {
+    let query = "SELECT value FROM storage WHERE address = ? AND key = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &address.0[..]))?
+        .bind((2, key as i64))?;
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("value") as u64)
+}
+
+/// Set a contract storage slot. Zero slots are deleted rather than stored.
+pub(crate) fn write_storage(
+    connection: &Connection,
+    address: Address,
+    key: u64,
+    value: u64,
+) -> Result<()> {
+    let mut statement = if value == 0 {
+        connection.prepare("DELETE FROM storage WHERE address = ? AND key = ?")?
+    } else {
+        let mut statement =
+            connection.prepare("INSERT OR REPLACE INTO storage VALUES (?, ?, ?)")?;
+        statement.bind((3, value as i64))?;
+        statement
+    };
+    statement.bind((1, &address.0[..]))?;
+    statement.bind((2, key as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn write_storage_undo(
+    connection: &Connection,
+    block_hash: Hash,
+    address: Address,
+    key: u64,
+    value: u64,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO storage_undo VALUES (?, ?, ?, ?)")?;
+    statement.bind((1, &block_hash.0[..]))?;
+    statement.bind((2, &address.0[..]))?;
+    statement.bind((3, key as i64))?;
+    statement.bind((4, value as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The storage slot values that were in place before `block_hash` was applied.
+pub(crate) fn read_storage_undo(
+    connection: &Connection,
+    block_hash: Hash,
+) -> Result<Vec<(Address, u64, u64)>> {
+    let query = "SELECT * FROM storage_undo WHERE block_hash = ?";
+    let mut slots = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?
+    {
+        let row = row?;
+        slots.push((
+            read_address(row.read::<&[u8], _>("address"))?,
+            row.read::<i64, _>("key") as u64,
+            row.read::<i64, _>("value") as u64,
+        ));
+    }
+    Ok(slots)
+}
+
+pub(crate) fn read_token_slot(
+    connection: &Connection,
+    token: Address,
+    slot: TokenSlot,
+) -> Result<u64> {
+    let query = "SELECT amount FROM token_slot WHERE token = ? AND slot = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &token.0[..]))?
+        .bind((2, slot.encode().as_slice()))?;
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("amount") as u64)
+}
+
+/// Set a token balance or allowance. Zero amounts are deleted rather than stored.
+pub(crate) fn write_token_slot(
+    connection: &Connection,
+    token: Address,
+    slot: TokenSlot,
+    amount: u64,
+) -> Result<()> {
+    let mut statement = if amount == 0 {
+        connection.prepare("DELETE FROM token_slot WHERE token = ? AND slot = ?")?
+    } else {
+        let mut statement =
+            connection.prepare("INSERT OR REPLACE INTO token_slot VALUES (?, ?, ?)")?;
+        statement.bind((3, amount as i64))?;
+        statement
+    };
+    statement.bind((1, &token.0[..]))?;
+    statement.bind((2, slot.encode().as_slice()))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn write_token_slot_undo(
+    connection: &Connection,
+    block_hash: Hash,
+    token: Address,
+    slot: TokenSlot,
+    amount: u64,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO token_slot_undo VALUES (?, ?, ?, ?)")?;
+    statement.bind((1, &block_hash.0[..]))?;
+    statement.bind((2, &token.0[..]))?;
+    statement.bind((3, slot.encode().as_slice()))?;
+    statement.bind((4, amount as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The token balances and allowances that were in place before `block_hash` was applied.
+pub(crate) fn read_token_slot_undo(
+    connection: &Connection,
+    block_hash: Hash,
+) -> Result<Vec<(Address, TokenSlot, u64)>> {
+    let query = "SELECT * FROM token_slot_undo WHERE block_hash = ?";
+    let mut slots = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?
+    {
+        let row = row?;
+        slots.push((
+            read_address(row.read::<&[u8], _>("token"))?,
+            TokenSlot::decode(row.read::<&[u8], _>("slot"))?,
+            row.read::<i64, _>("amount") as u64,
+        ));
+    }
+    Ok(slots)
+}
+
+/// The tables holding what applying a block wrote besides the state itself: undo records,
+/// attestations, deployed code, multisig policies, tokens, receipts, indexed or not, logs, traces,
+/// state diffs and state history.
+const BLOCK_EFFECTS: [&str; 18] = [
+    "account_undo",
+    "storage_undo",
+    "attestation",
+    "code",
+    "multisig",
+    "token",
+    "token_slot_undo",
+    "receipt",
+    "pending_receipts",
+    "log",
+    "log_topic",
+    "log_bloom",
+    "trace",
+    "state_diff",
+    "account_history",
+    "storage_history",
+    "token_slot_history",
+    "state_checkpoint",
+];
+
+/// Remove what was written when `block_hash` was applied. See [`BLOCK_EFFECTS`].
+pub(crate) fn delete_block_effects(connection: &Connection, block_hash: Hash) -> Result<()> {
+    let mut queries = Vec::new();
+    for table in BLOCK_EFFECTS {
+        for table in tiers(connection, table)? {
+            queries.push(format!("DELETE FROM {table} WHERE block_hash = ?1"));
+        }
+    }
+    // The archive, and cold storage, are no longer complete through a block they lose.
+    queries.push(
+        "UPDATE archive SET number = (SELECT number - 1 FROM block WHERE hash = ?1)
+            WHERE number >= (SELECT number FROM block WHERE hash = ?1)"
+            .to_string(),
+    );
+    if is_cold_attached(connection)? {
+        queries.push(
+            "UPDATE cold.moved_through SET number = (SELECT number - 1 FROM block WHERE hash = ?1)
+                WHERE number >= (SELECT number FROM block WHERE hash = ?1)"
+                .to_string(),
+        );
+    }
+    for query in queries {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, &block_hash.0[..]))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Remove what was written when the blocks above height `number` were applied, as
+/// [`delete_block_effects`] does for each of them.
+pub(crate) fn delete_effects_above(connection: &Connection, number: u64) -> Result<()> {
+    let mut queries = Vec::new();
+    for table in BLOCK_EFFECTS {
+        for table in tiers(connection, table)? {
+            queries.push(format!(
+                "DELETE FROM {table}
+                    WHERE block_hash IN (SELECT hash FROM block WHERE number > ?1)"
+            ));
+        }
+    }
+    queries.push("UPDATE archive SET number = ?1 WHERE number > ?1".to_string());
+    if is_cold_attached(connection)? {
+        queries.push("UPDATE cold.moved_through SET number = ?1 WHERE number > ?1".to_string());
+    }
+    for query in queries {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, number as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_attestation(
+    connection: &Connection,
+    checkpoint: Hash,
+    validator: Address,
+    block_hash: Hash,
+) -> Result<()> {
+    let query = "INSERT INTO attestation VALUES (?, ?, ?)";
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &checkpoint.0[..]))?;
+    statement.bind((2, &validator.0[..]))?;
+    statement.bind((3, &block_hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Validators that have attested to `checkpoint` on the canonical chain.
+pub(crate) fn read_attestations(connection: &Connection, checkpoint: Hash) -> Result<Vec<Address>> {
+    let query = "SELECT validator FROM attestation WHERE checkpoint = ?";
+    let mut validators = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &checkpoint.0[..]))?
+    {
+        validators.push(read_address(row?.read::<&[u8], _>("validator"))?);
+    }
+    Ok(validators)
+}
+
+pub(crate) fn read_code(connection: &Connection, address: Address) -> Result<Option<Vec<u8>>> {
+    let query = "SELECT code FROM code WHERE address = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &address.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(row?.read::<&[u8], _>("code").to_vec()))
+}
+
+/// Store `code` at `address`, deployed by block `block_hash`.
+pub(crate) fn write_code(
+    connection: &Connection,
+    address: Address,
+    code: &[u8],
+    block_hash: Hash,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO code VALUES (?, ?, ?)")?;
+    statement.bind((1, &address.0[..]))?;
+    statement.bind((2, code))?;
+    statement.bind((3, &block_hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn read_multisig(
+    connection: &Connection,
+    address: Address,
+) -> Result<Option<MultisigPolicy>> {
+    let query = "SELECT policy FROM multisig WHERE address = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &address.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(MultisigPolicy::decode(
+        row?.read::<&[u8], _>("policy"),
+    )?))
+}
+
+/// Register `policy` at `address`, created by block `block_hash`.
+pub(crate) fn write_multisig(
+    connection: &Connection,
+    address: Address,
+    policy: &MultisigPolicy,
+    block_hash: Hash,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO multisig VALUES (?, ?, ?)")?;
+    statement.bind((1, &address.0[..]))?;
+    statement.bind((2, policy.encode().as_slice()))?;
+    statement.bind((3, &block_hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn read_token(connection: &Connection, token: Address) -> Result<Option<TokenInfo>> {
+    let query = "SELECT info FROM token WHERE address = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &token.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(TokenInfo::decode(row?.read::<&[u8], _>("info"))?))
+}
+
+/// Store the description of `token`, created by block `block_hash`.
+pub(crate) fn write_token(
+    connection: &Connection,
+    token: Address,
+    info: &TokenInfo,
+    block_hash: Hash,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO token VALUES (?, ?, ?)")?;
+    statement.bind((1, &token.0[..]))?;
+    statement.bind((2, info.encode().as_slice()))?;
+    statement.bind((3, &block_hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn write_receipt(connection: &Connection, receipt: &TransactionReceipt) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO main.receipt VALUES (?, ?, ?, ?, ?, ?)")?;
+    statement.bind((1, &receipt.transaction_hash.0[..]))?;
+    statement.bind((2, &receipt.block_hash.0[..]))?;
+    statement.bind((3, receipt.status as i64))?;
+    statement.bind((4, receipt.gas_used as i64))?;
+    let contract_address = receipt.contract_address.as_ref();
+    statement.bind((5, contract_address.map(|address| &address.0[..])))?;
+    statement.bind((6, receipt.return_data.as_slice()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The receipt of a transaction included in a canonical block, whether or not it has been
+/// indexed yet.
+pub(crate) fn read_receipt(
+    connection: &Connection,
+    transaction_hash: Hash,
+) -> Result<Option<TransactionReceipt>> {
+    let query = "SELECT receipt.* FROM receipt JOIN block ON block.hash = receipt.block_hash
+        WHERE receipt.transaction_hash = ? AND block.canonical = 1 LIMIT 1";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &transaction_hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        let Some((block_hash, position)) = read_transaction_location(connection, transaction_hash)?
+        else {
+            return Ok(None);
+        };
+        let receipts = read_pending_receipts(connection, block_hash)?;
+        return Ok(receipts.and_then(|mut receipts| {
+            (position < receipts.len()).then(|| receipts.swap_remove(position))
+        }));
+    };
+    let row = row?;
+    Ok(Some(TransactionReceipt {
+        transaction_hash,
+        block_hash: read_hash(row.read::<&[u8], _>("block_hash"))?,
+        status: row.read::<i64, _>("status") != 0,
+        gas_used: row.read::<i64, _>("gas_used") as u64,
+        contract_address: row
+            .read::<Option<&[u8]>, _>("contract_address")
+            .map(read_address)
+            .transpose()?,
+        return_data: row.read::<&[u8], _>("return_data").to_vec(),
+        logs: read_transaction_logs(connection, transaction_hash)?
+            .into_iter()
+            .map(|entry| entry.log)
+            .collect(),
+    }))
+}
+
+/// Queue the receipts of `block` for the indexer. See [`crate::indexer`].
+pub(crate) fn write_pending_receipts(
+    connection: &Connection,
+    block: &Block,
+    receipts: &[TransactionReceipt],
+) -> Result<()> {
+    let query = "INSERT OR REPLACE INTO pending_receipts VALUES (?, ?, ?)";
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &block.hash.0[..]))?;
+    statement.bind((2, block.number as i64))?;
+    statement.bind((3, serde_json::to_string(receipts)?.as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The receipts of `block_hash` still waiting for the indexer.
+pub(crate) fn read_pending_receipts(
+    connection: &Connection,
+    block_hash: Hash,
+) -> Result<Option<Vec<TransactionReceipt>>> {
+    let query = "SELECT receipts FROM pending_receipts WHERE block_hash = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(
+        row?.read::<&str, _>("receipts"),
+    )?))
+}
+
+/// Up to `limit` blocks' queued receipts, lowest first.
+pub(crate) fn read_oldest_pending_receipts(
+    connection: &Connection,
+    limit: usize,
+) -> Result<Vec<(Hash, Vec<TransactionReceipt>)>> {
+    let query = "SELECT block_hash, receipts FROM pending_receipts ORDER BY number LIMIT ?";
+    let mut batch = Vec::new();
+    for row in connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, limit as i64))?
+    {
+        let row = row?;
+        batch.push((
+            read_hash(row.read::<&[u8], _>("block_hash"))?,
+            serde_json::from_str(row.read::<&str, _>("receipts"))?,
+        ));
+    }
+    Ok(batch)
+}
+
+pub(crate) fn delete_pending_receipts(connection: &Connection, block_hash: Hash) -> Result<()> {
+    let mut statement = connection.prepare("DELETE FROM pending_receipts WHERE block_hash = ?")?;
+    statement.bind((1, &block_hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// How many blocks have queued receipts, and the lowest of them.
+pub(crate) fn read_pending_receipts_backlog(connection: &Connection) -> Result<(u64, Option<u64>)> {
+    let query = "SELECT COUNT(*) AS count, MIN(number) AS lowest FROM pending_receipts";
+    let mut rows = connection.prepare(query)?.into_iter();
+    let Some(row) = rows.next() else {
+        return Ok((0, None));
+    };
+    let row = row?;
+    Ok((
+        row.read::<i64, _>("count") as u64,
+        row.read::<Option<i64>, _>("lowest").map(|n| n as u64),
+    ))
+}
+
+/// Replace the saved mempool with `transactions`, in admission order.
+pub(crate) fn write_mempool(connection: &Connection, transactions: &[Transaction]) -> Result<()> {
+    connection.execute("DELETE FROM mempool")?;
+    for (position, transaction) in transactions.iter().enumerate() {
+        let mut statement = connection.prepare("INSERT INTO mempool VALUES (?, ?)")?;
+        statement.bind((1, position as i64))?;
+        statement.bind((2, transaction.encode().as_slice()))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// The saved mempool, in admission order.
+pub(crate) fn read_mempool(connection: &Connection) -> Result<Vec<Transaction>> {
+    let mut transactions = Vec::new();
+    for row in connection
+        .prepare("SELECT raw FROM mempool ORDER BY position")?
+        .into_iter()
+    {
+        let row = row?;
+        transactions.push(Transaction::decode(row.read::<&[u8], _>("raw"))?);
+    }
+    Ok(transactions)
+}
+
+/// Store the logs in `receipts`, the receipts of `block`, numbering them through the block, index
+/// their topics and summarize them in the block's bloom.
+pub(crate) fn write_logs(
+    connection: &Connection,
+    block: &Block,
+    receipts: &[TransactionReceipt],
+) -> Result<()> {
+    let logs = receipts.iter().flat_map(|receipt| {
+        receipt
+            .logs
+            .iter()
+            .map(|log| (receipt.transaction_hash, log))
+    });
+    for (log_index, (transaction_hash, log)) in logs.enumerate() {
+        let mut statement =
+            connection.prepare("INSERT INTO main.log VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        statement.bind((1, &block.hash.0[..]))?;
+        statement.bind((2, block.number as i64))?;
+        statement.bind((3, &transaction_hash.0[..]))?;
+        statement.bind((4, log_index as i64))?;
+        statement.bind((5, intern(connection, log.address)?))?;
+        statement.bind((6, serde_json::to_string(&log.topics)?.as_str()))?;
+        statement.bind((7, log.data.as_slice()))?;
+        statement.next()?;
+        let (number, log_index) = (block.number as i64, log_index as i64);
+        write_log_topics(
+            connection,
+            "main",
+            block.hash,
+            number,
+            log_index,
+            &log.topics,
+        )?;
+    }
+    let mut logs = receipts.iter().flat_map(|receipt| &receipt.logs).peekable();
+    if logs.peek().is_some() {
+        let bloom = LogBloom::of(logs);
+        write_log_bloom(connection, block.hash, block.number as i64, bloom)?;
+    }
+    Ok(())
+}
+
+/// Add `bloom` to that of the logs of `block_hash`, numbered `number`. Blocks without logs have
+/// none.
+fn write_log_bloom(
+    connection: &Connection,
+    block_hash: Hash,
+    number: i64,
+    mut bloom: LogBloom,
+) -> Result<()> {
+    let query = "SELECT bloom FROM main.log_bloom WHERE block_hash = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?;
+    if let Some(row) = rows.next() {
+        bloom.include(&LogBloom::try_from(row?.read::<&[u8], _>("bloom"))?);
+    }
+    let mut statement =
+        connection.prepare("INSERT OR REPLACE INTO main.log_bloom VALUES (?, ?, ?)")?;
+    statement.bind((1, &block_hash.0[..]))?;
+    statement.bind((2, number))?;
+    statement.bind((3, bloom.as_bytes()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Index `topics`, those of log `log_index` of block `block_hash`, numbered `number`.
+fn write_log_topics(
+    connection: &Connection,
+    schema: &str,
+    block_hash: Hash,
+    number: i64,
+    log_index: i64,
+    topics: &[Hash],
+) -> Result<()> {
+    for (position, topic) in topics.iter().enumerate() {
+        let mut statement = connection.prepare(format!(
+            "INSERT INTO {schema}.log_topic VALUES (?, ?, ?, ?, ?)"
+        ))?;
+        statement.bind((1, &topic.0[..]))?;
+        statement.bind((2, position as i64))?;
+        statement.bind((3, &block_hash.0[..]))?;
+        statement.bind((4, number))?;
+        statement.bind((5, log_index))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// The columns of `log`, with the interned address of each log.
+const LOG_COLUMNS: &str =
+    "log.*, (SELECT address FROM address WHERE id = log.address_id) AS address";
+
+fn read_log_entries(
+    connection: &Connection,
+    query: &str,
+    keys: &[sqlite::Value],
+) -> Result<Vec<LogEntry>> {
+    let mut rows = connection.prepare(query)?.into_iter();
+    for (index, key) in keys.iter().enumerate() {
+        rows = rows.bind((index + 1, key.clone()))?;
+    }
+    let mut entries = Vec::new();
+    for row in rows {
+        let row = row?;
+        entries.push(LogEntry {
+            log: Log {
+                address: read_address(row.read::<&[u8], _>("address"))?,
+                topics: serde_json::from_str(row.read::<&str, _>("topics"))?,
+                data: row.read::<&[u8], _>("data").to_vec(),
+            },
+            block_hash: read_hash(row.read::<&[u8], _>("block_hash"))?,
+            block_number: row.read::<i64, _>("number") as u64,
+            transaction_hash: read_hash(row.read::<&[u8], _>("transaction_hash"))?,
+            log_index: row.read::<i64, _>("log_index") as u64,
+            removed: false,
+        });
+    }
+    Ok(entries)
+}
+
+/// The logs of `block_hash`, in order, whether or not it is canonical.
+pub(crate) fn read_block_logs(connection: &Connection, block_hash: Hash) -> Result<Vec<LogEntry>> {
+    let query = format!("SELECT {LOG_COLUMNS} FROM log WHERE block_hash = ? ORDER BY log_index");
+    read_log_entries(connection, &query, &[block_hash.0.to_vec().into()])
+}
+
+/// The logs of a transaction included in a canonical block.
+pub(crate) fn read_transaction_logs(
+    connection: &Connection,
+    transaction_hash: Hash,
+) -> Result<Vec<LogEntry>> {
+    let query = format!(
+        "SELECT {LOG_COLUMNS} FROM log JOIN block ON block.hash = log.block_hash
+            WHERE log.transaction_hash = ? AND block.canonical = 1 ORDER BY log.log_index"
+    );
+    read_log_entries(connection, &query, &[transaction_hash.0.to_vec().into()])
+}
+
+/// The logs of the canonical blocks numbered `from` to `to` that match `filter`, in order. With
+/// topics to match, only the logs with one of them at the position that allows the fewest, and of
+/// those the last, are read, through `log_topic`. The first topic usually names the kind of
+/// event, which many logs share. With only addresses to match, only the logs of the blocks whose
+/// bloom may hold one of them are read.
+pub(crate) fn read_logs(
+    connection: &Connection,
+    filter: &LogFilter,
+    from: u64,
+    to: u64,
+) -> Result<Vec<LogEntry>> {
+    let mut keys = vec![
+        (from as i64).into(),
+        (to.min(i64::MAX as u64) as i64).into(),
+    ];
+    let narrowest = (filter.topics.iter().enumerate())
+        .filter(|(_, topics)| !topics.is_empty())
+        .min_by_key(|&(position, topics)| (topics.len(), Reverse(position)));
+    if narrowest.is_none() && !filter.addresses.is_empty() {
+        let mut entries = Vec::new();
+        for block_hash in read_bloom_matches(connection, filter, &keys)? {
+            entries.extend(read_block_logs(connection, block_hash)?);
+        }
+        entries.retain(|entry| filter.matches(&entry.log));
+        return Ok(entries);
+    }
+    let query = match narrowest {
+        Some((position, topics)) => {
+            keys.push((position as i64).into());
+            keys.extend(topics.iter().map(|topic| topic.0.to_vec().into()));
+            let placeholders: Vec<String> =
+                (4..4 + topics.len()).map(|i| format!("?{i}")).collect();
+            let placeholders = placeholders.join(", ");
+            format!(
+                "SELECT {LOG_COLUMNS} FROM log_topic
+                    JOIN log ON log.block_hash = log_topic.block_hash
+                        AND log.log_index = log_topic.log_index
+                    JOIN block ON block.hash = log_topic.block_hash
+                    WHERE log_topic.topic IN ({placeholders}) AND log_topic.position = ?3
+                        AND log_topic.number BETWEEN ?1 AND ?2 AND block.canonical = 1
+                    ORDER BY log.number, log.log_index"
+            )
+        }
+        None => format!(
+            "SELECT {LOG_COLUMNS} FROM block JOIN log ON log.block_hash = block.hash
+                WHERE block.number BETWEEN ?1 AND ?2 AND block.canonical = 1
+                ORDER BY log.number, log.log_index"
+        ),
+    };
+    let mut entries = read_log_entries(connection, &query, &keys)?;
+    entries.retain(|entry| filter.matches(&entry.log));
+    Ok(entries)
+}
+
+/// The canonical blocks numbered between the first and second of `keys` whose bloom may hold a
+/// log matching `filter`, in order.
+fn read_bloom_matches(
+    connection: &Connection,
+    filter: &LogFilter,
+    keys: &[sqlite::Value],
+) -> Result<Vec<Hash>> {
+    let query = "SELECT log_bloom.block_hash, log_bloom.bloom FROM log_bloom
+        JOIN block ON block.hash = log_bloom.block_hash
+        WHERE log_bloom.number BETWEEN ?1 AND ?2 AND block.canonical = 1
+        ORDER BY log_bloom.number";
+    let mut rows = connection.prepare(query)?.into_iter();
+    for (index, key) in keys.iter().enumerate() {
+        rows = rows.bind((index + 1, key.clone()))?;
+    }
+    let mut matches = Vec::new();
+    for row in rows {
+        let row = row?;
+        if LogBloom::try_from(row.read::<&[u8], _>("bloom"))?.may_match(filter) {
+            matches.push(read_hash(row.read::<&[u8], _>("block_hash"))?);
+        }
+    }
+    Ok(matches)
+}
+
+pub(crate) fn write_state_diff(
+    connection: &Connection,
+    block_hash: Hash,
+    diff: &StateDiff,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO state_diff VALUES (?, ?)")?;
+    statement.bind((1, &block_hash.0[..]))?;
+    statement.bind((2, serde_json::to_string(diff)?.as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The state changes made by `block_hash`, if it is canonical.
+pub(crate) fn read_state_diff(
+    connection: &Connection,
+    block_hash: Hash,
+) -> Result<Option<StateDiff>> {
+    let query = "SELECT diff FROM state_diff WHERE block_hash = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(row?.read::<&str, _>("diff"))?))
+}
+
+/// Record the versions of the accounts, storage slots and token slots that `block` changed, for
+/// archive mode. See [`crate::archive`].
+pub(crate) fn write_state_history(
+    connection: &Connection,
+    block: &Header,
+    diff: &StateDiff,
+) -> Result<()> {
+    let number = block.number as i64;
+    let block_hash = &block.hash.0[..];
+    for account in &diff.accounts {
+        let query = "INSERT OR REPLACE INTO account_history VALUES (?, ?, ?, ?, ?, ?, ?)";
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, &account.address.0[..]))?;
+        statement.bind((2, number))?;
+        statement.bind((3, block_hash))?;
+        statement.bind((4, account.after.balance as i64))?;
+        statement.bind((5, account.after.nonce as i64))?;
+        statement.bind((6, account.after.stake as i64))?;
+        statement.bind((7, account.after.unbonding as i64))?;
+        statement.next()?;
+    }
+    for slot in &diff.storage {
+        let query = "INSERT OR REPLACE INTO storage_history VALUES (?, ?, ?, ?, ?)";
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, &slot.address.0[..]))?;
+        statement.bind((2, slot.key as i64))?;
+        statement.bind((3, number))?;
+        statement.bind((4, block_hash))?;
+        statement.bind((5, slot.after as i64))?;
+        statement.next()?;
+    }
+    for slot in &diff.tokens {
+        let query = "INSERT OR REPLACE INTO token_slot_history VALUES (?, ?, ?, ?, ?)";
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, &slot.token.0[..]))?;
+        statement.bind((2, slot.slot.encode().as_slice()))?;
+        statement.bind((3, number))?;
+        statement.bind((4, block_hash))?;
+        statement.bind((5, slot.after as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// The canonical block through which the state history is complete, if one was ever recorded.
+pub(crate) fn read_archived_through(connection: &Connection) -> Result<Option<u64>> {
+    let mut rows = connection
+        .prepare("SELECT number FROM archive")?
+        .into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(row?.read::<i64, _>("number") as u64))
+}
+
+pub(crate) fn write_archived_through(connection: &Connection, number: u64) -> Result<()> {
+    connection.execute("DELETE FROM archive")?;
+    let mut statement = connection.prepare("INSERT INTO archive VALUES (?)")?;
+    statement.bind((1, number as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Record `block` as a state checkpoint: the state history holds every change up to it, merged
+/// since the previous checkpoint. See [`crate::archive`].
+pub(crate) fn write_state_checkpoint(connection: &Connection, block: &Header) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO state_checkpoint VALUES (?, ?)")?;
+    statement.bind((1, block.number as i64))?;
+    statement.bind((2, &block.hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The height of the highest state checkpoint at or below `number`.
+pub(crate) fn read_state_checkpoint_at_or_below(
+    connection: &Connection,
+    number: u64,
+) -> Result<Option<u64>> {
+    let query = "SELECT MAX(number) AS number FROM state_checkpoint WHERE number <= ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, number as i64))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(row?
+        .read::<Option<i64>, _>("number")
+        .map(|number| number as u64))
+}
+
+/// The height of the highest state checkpoint, through which blocks have no undo records.
+pub(crate) fn read_last_state_checkpoint(connection: &Connection) -> Result<Option<u64>> {
+    let query = "SELECT MAX(number) AS number FROM state_checkpoint";
+    let Some(row) = connection.prepare(query)?.into_iter().next() else {
+        return Ok(None);
+    };
+    Ok(row?
+        .read::<Option<i64>, _>("number")
+        .map(|number| number as u64))
+}
+
+/// Delete the undo records of the blocks at and below height `number`, which can then no longer
+/// be reverted.
+pub(crate) fn delete_undo_through(connection: &Connection, number: u64) -> Result<()> {
+    for table in ["account_undo", "storage_undo", "token_slot_undo"] {
+        let query = format!(
+            "DELETE FROM {table} WHERE block_hash IN (SELECT hash FROM block WHERE number <= ?)"
+        );
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, number as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Overwrite the accounts, storage slots and token slots with the state after canonical block
+/// `number`, as recorded by [`write_state_history`].
+pub(crate) fn restore_state_history(connection: &Connection, number: u64) -> Result<()> {
+    create_history_views(connection, number)?;
+    connection.execute(
+        "DELETE FROM main.account;
+        INSERT INTO main.account SELECT * FROM temp.account;
+        DELETE FROM main.storage;
+        INSERT INTO main.storage SELECT * FROM temp.storage;
+        DELETE FROM main.token_slot;
+        INSERT INTO main.token_slot SELECT * FROM temp.token_slot;",
+    )?;
+    drop_history_views(connection)
+}
+
+/// Shadow the state tables of `connection` with temporary views of their contents after
+/// canonical block `number`, as recorded by [`write_state_history`], until
+/// [`drop_history_views`]. Unqualified names resolve to temporary objects first, so every query
+/// in this module reads the old state unchanged, while writes to those tables fail.
+pub(crate) fn create_history_views(connection: &Connection, number: u64) -> Result<()> {
+    connection.execute(format!(
+        "CREATE TEMP VIEW account AS
+            SELECT address, balance, nonce, stake, unbonding
+            FROM main.account_history AS version
+            WHERE number = (SELECT MAX(number) FROM main.account_history
+                WHERE address = version.address AND number <= {number});
+        CREATE TEMP VIEW storage AS
+            SELECT address, key, value FROM main.storage_history AS version
+            WHERE number = (SELECT MAX(number) FROM main.storage_history
+                WHERE address = version.address AND key = version.key AND number <= {number})
+                AND value != 0;
+        CREATE TEMP VIEW token_slot AS
+            SELECT token, slot, amount FROM main.token_slot_history AS version
+            WHERE number = (SELECT MAX(number) FROM main.token_slot_history
+                WHERE token = version.token AND slot = version.slot AND number <= {number})
+                AND amount != 0;
+        CREATE TEMP VIEW code AS
+            SELECT code.* FROM main.code AS code JOIN main.block AS block
+                ON block.hash = code.block_hash WHERE block.number <= {number};
+        CREATE TEMP VIEW multisig AS
+            SELECT multisig.* FROM main.multisig AS multisig JOIN main.block AS block
+                ON block.hash = multisig.block_hash WHERE block.number <= {number};
+        CREATE TEMP VIEW token AS
+            SELECT token.* FROM main.token AS token JOIN main.block AS block
+                ON block.hash = token.block_hash WHERE block.number <= {number};"
+    ))?;
+    Ok(())
+}
+
+pub(crate) fn drop_history_views(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "DROP VIEW IF EXISTS temp.account;
+        DROP VIEW IF EXISTS temp.storage;
+        DROP VIEW IF EXISTS temp.token_slot;
+        DROP VIEW IF EXISTS temp.code;
+        DROP VIEW IF EXISTS temp.multisig;
+        DROP VIEW IF EXISTS temp.token;",
+    )?;
+    Ok(())
+}
+
+pub(crate) fn write_trace(
+    connection: &Connection,
+    transaction_hash: Hash,
+    block: &Block,
+    trace: &Trace,
+) -> Result<()> {
+    let mut statement = connection.prepare("INSERT INTO trace VALUES (?, ?, ?, ?)")?;
+    statement.bind((1, &transaction_hash.0[..]))?;
+    statement.bind((2, &block.hash.0[..]))?;
+    statement.bind((3, block.number as i64))?;
+    statement.bind((4, serde_json::to_string(trace)?.as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The stored trace of a transaction included in a canonical block.
+pub(crate) fn read_trace(connection: &Connection, transaction_hash: Hash) -> Result<Option<Trace>> {
+    let query = "SELECT trace.trace FROM trace JOIN block ON block.hash = trace.block_hash
+        WHERE trace.transaction_hash = ? AND block.canonical = 1 LIMIT 1";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &transaction_hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(row?.read::<&str, _>("trace"))?))
+}
+
+/// Drop the stored traces of blocks below height `number`.
+pub(crate) fn delete_traces_before(connection: &Connection, number: u64) -> Result<()> {
+    let mut statement = connection.prepare("DELETE FROM trace WHERE number < ?")?;
+    statement.bind((1, number as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn count_receipts(connection: &Connection, block_hash: Hash) -> Result<u64> {
+    let query = "SELECT COUNT(*) AS count FROM receipt WHERE block_hash = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("count") as u64)
+}
+
+/// The gas used by the transactions of `block_hash` together, whether their receipts are
+/// indexed or still queued.
+pub(crate) fn read_block_gas_used(connection: &Connection, block_hash: Hash) -> Result<u64> {
+    if let Some(receipts) = read_pending_receipts(connection, block_hash)? {
+        return Ok(receipts.iter().map(|receipt| receipt.gas_used).sum());
+    }
+    let query = "SELECT COALESCE(SUM(gas_used), 0) AS gas_used FROM receipt WHERE block_hash = ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &block_hash.0[..]))?;
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("gas_used") as u64)
+}
+
+/// How many transactions the canonical blocks from height `number` on include.
+pub(crate) fn count_canonical_transactions(connection: &Connection, number: u64) -> Result<u64> {
+    let query = "SELECT COUNT(*) AS count FROM transactions
+        JOIN block ON block.hash = transactions.block_hash
+        WHERE block.canonical = 1 AND block.number >= ?";
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, number as i64))?;
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("count") as u64)
+}
+
+/// Tables whose rows belong to a block, and whether that block must also be canonical.
+const BLOCK_ROWS: [(&str, bool); 21] = [
+    ("block", false),
+    ("transactions", false),
+    ("account_undo", true),
+    ("storage_undo", true),
+    ("attestation", true),
+    ("code", true),
+    ("multisig", true),
+    ("token", true),
+    ("token_slot_undo", true),
+    ("receipt", true),
+    ("pending_receipts", true),
+    ("log", true),
+    ("log_topic", true),
+    ("log_bloom", true),
+    ("state_diff", true),
+    ("trace", true),
+    ("finalized", true),
+    ("account_history", true),
+    ("storage_history", true),
+    ("token_slot_history", true),
+    ("state_checkpoint", true),
+];
+
+fn orphan_condition(table: &str, canonical: bool) -> String {
+    let column = match table {
+        "finalized" => "hash",
+        "block" => "parent_hash",
+        _ => "block_hash",
+    };
+    let filter = if canonical { " AND canonical = 1" } else { "" };
+    let exception = if table == "block" {
+        " AND number > 0"
+    } else {
+        ""
+    };
+    format!("{column} NOT IN (SELECT hash FROM block WHERE 1 = 1{filter}){exception}")
+}
+
+/// The number of rows in each table that refer to a missing block, or to a non-canonical block
+/// where only canonical blocks may have them. Tables without orphans are omitted.
+pub(crate) fn count_orphans(connection: &Connection) -> Result<Vec<(&'static str, u64)>> {
+    let mut orphans = Vec::new();
+    for (table, canonical) in BLOCK_ROWS {
+        let condition = orphan_condition(table, canonical);
+        let query = format!("SELECT COUNT(*) AS count FROM {table} WHERE {condition}");
+        let mut rows = connection.prepare(query)?.into_iter();
+        let count = match rows.next() {
+            Some(row) => row?.read::<i64, _>("count") as u64,
+            None => 0,
+        };
+        if count > 0 {
+            orphans.push((table, count));
+        }
+    }
+    Ok(orphans)
+}
+
+pub(crate) fn delete_orphans(connection: &Connection) -> Result<()> {
+    for (table, canonical) in BLOCK_ROWS {
+        let condition = orphan_condition(table, canonical);
+        for table in tiers(connection, table)? {
+            connection.execute(format!("DELETE FROM {table} WHERE {condition}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete every block above height `number`, with its transactions.
+pub(crate) fn delete_blocks_above(connection: &Connection, number: u64) -> Result<()> {
+    let mut queries = Vec::new();
+    for table in tiers(connection, "transactions")? {
+        queries.push(format!(
+            "DELETE FROM {table} WHERE block_hash IN (SELECT hash FROM block WHERE number > ?)"
+        ));
+    }
+    queries.push("DELETE FROM block WHERE number > ?".to_string());
+    queries.push("DELETE FROM finalized WHERE number > ?".to_string());
+    if is_cold_attached(connection)? {
+        queries.push("UPDATE cold.moved_through SET number = ?1 WHERE number > ?1".to_string());
+    }
+    for query in queries {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, number as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_finalized(connection: &Connection, block: &Block) -> Result<()> {
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO finalized VALUES (?, ?)")?;
+    statement.bind((1, block.number as i64))?;
+    statement.bind((2, &block.hash.0[..]))?;
+    statement.next()?;
+    Ok(())
+}
+
+pub(crate) fn delete_finalized(connection: &Connection, number: u64) -> Result<()> {
+    let mut statement = connection.prepare("DELETE FROM finalized WHERE number = ?")?;
+    statement.bind((1, number as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The highest finalized checkpoint.
+pub(crate) fn read_finalized(connection: &Connection) -> Result<Option<Block>> {
+    let query = "SELECT block.* FROM finalized JOIN block ON block.hash = finalized.hash
+        ORDER BY finalized.number DESC LIMIT 1";
+    Ok(read_blocks(connection, query, &[])?.pop())
+}
+
+/// Bytes used by each table and index, largest first.
+pub(crate) fn read_table_sizes(connection: &Connection) -> Result<Vec<(String, u64)>> {
+    let query = "SELECT name, SUM(pgsize) AS size FROM dbstat GROUP BY name ORDER BY size DESC";
+    let mut sizes = Vec::new();
+    for row in connection.prepare(query)?.into_iter() {
+        let row = row?;
+        sizes.push((
+            row.read::<&str, _>("name").to_string(),
+            row.read::<i64, _>("size") as u64,
+        ));
+    }
+    Ok(sizes)
+}
+
+/// The size of the database file, in bytes.
+pub(crate) fn read_database_size(connection: &Connection) -> Result<u64> {
+    let query = "SELECT page_count * page_size AS size FROM pragma_page_count, pragma_page_size";
+    let mut rows = connection.prepare(query)?.into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("size") as u64)
+}
+
+/// Each table and index, as its kind, name and the table it belongs to, which for a table is
+/// itself.
+pub(crate) fn read_schema_objects(
+    connection: &Connection,
+) -> Result<Vec<(String, String, String)>> {
+    let query = "SELECT type, name, tbl_name FROM sqlite_schema
+        WHERE type IN ('table', 'index') ORDER BY name";
+    let mut objects = Vec::new();
+    for row in connection.prepare(query)?.into_iter() {
+        let row = row?;
+        objects.push((
+            row.read::<&str, _>("type").to_string(),
+            row.read::<&str, _>("name").to_string(),
+            row.read::<&str, _>("tbl_name").to_string(),
+        ));
+    }
+    Ok(objects)
+}
+
+/// How many rows `table` holds in the main database. Reads the whole table.
+pub(crate) fn count_rows(connection: &Connection, table: &str) -> Result<u64> {
+    let query = format!(
+        "SELECT COUNT(*) AS count FROM main.\"{}\"",
+        table.replace('"', "\"\"")
+    );
+    let mut rows = connection.prepare(query)?.into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("count") as u64)
+}
+
+/// The bytes on the database's free list, which compaction would return to the filesystem.
+pub(crate) fn read_free_size(connection: &Connection) -> Result<u64> {
+    let query =
+        "SELECT freelist_count * page_size AS size FROM pragma_freelist_count, pragma_page_size";
+    let mut rows = connection.prepare(query)?.into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(0);
+    };
+    Ok(row?.read::<i64, _>("size") as u64)
+}
+
+/// The path of the database file, or `None` for an in-memory database.
+pub(crate) fn read_database_path(connection: &Connection) -> Result<Option<std::path::PathBuf>> {
+    let query = "SELECT file FROM pragma_database_list WHERE name = 'main'";
+    let mut rows = connection.prepare(query)?.into_iter();
+    let Some(row) = rows.next() else {
+        return Ok(None);
+    };
+    let file = row?.read::<&str, _>("file").to_string();
+    Ok((!file.is_empty()).then(|| file.into()))
+}
+
+/// The tables whose rows of old blocks move to cold storage. See [`crate::cold`].
+const COLD_TABLES: [&str; 4] = ["transactions", "receipt", "log", "log_topic"];
+
+const COLD_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS cold.moved_through (number INTEGER);
+    PRAGMA cold.user_version = 4;
+    CREATE INDEX IF NOT EXISTS cold.transactions_hash ON transactions (hash);
+    CREATE INDEX IF NOT EXISTS cold.transactions_block_hash ON transactions (block_hash);
+    CREATE INDEX IF NOT EXISTS cold.transactions_from_id ON transactions (from_id);
+    CREATE INDEX IF NOT EXISTS cold.transactions_to_id ON transactions (to_id);
+    CREATE INDEX IF NOT EXISTS cold.receipt_transaction_hash ON receipt (transaction_hash);
+    CREATE INDEX IF NOT EXISTS cold.receipt_block_hash ON receipt (block_hash);
+    CREATE INDEX IF NOT EXISTS cold.log_position ON log (block_hash, log_index);
+    CREATE INDEX IF NOT EXISTS cold.log_transaction_hash ON log (transaction_hash);
+    CREATE INDEX IF NOT EXISTS cold.log_topic_topic ON log_topic (topic, position, number);
+    CREATE INDEX IF NOT EXISTS cold.log_topic_block_hash ON log_topic (block_hash);
+";
+
+/// Attach the cold storage file at `path` as the `cold` schema, creating its tables first if
+/// `writable`, and shadow the tables it shares with the main database with views of both.
+pub(crate) fn attach_cold(
+    connection: &Connection,
+    path: &std::path::Path,
+    writable: bool,
+) -> Result<()> {
+    let mut statement = connection.prepare("ATTACH ? AS cold")?;
+    statement.bind((1, path.to_string_lossy().as_ref()))?;
+    statement.next()?;
+    let version = schema_version(connection, "cold", "transactions")?;
+    let upgrade = version.is_some_and(|version| version < VERSION);
+    if writable {
+        connection.execute("PRAGMA cold.journal_mode = WAL")?;
+        transaction(connection, || {
+            let mut old = Vec::new();
+            if version == Some(0) {
+                for table in COLD_TABLES {
+                    if has_table(connection, "cold", table)? {
+                        old.push(table);
+                    }
+                }
+            }
+            set_aside(connection, "cold", &old)?;
+            connection.execute("DROP INDEX IF EXISTS cold.log_block_hash")?;
+            for table in COLD_TABLES {
+                connection.execute(format!(
+                    "CREATE TABLE IF NOT EXISTS cold.{table} AS SELECT * FROM main.{table} WHERE 0"
+                ))?;
+            }
+            connection.execute(COLD_SCHEMA)?;
+            for table in old {
+                convert_rows(connection, "cold", table)?;
+            }
+            if version.is_some_and(|version| version < 2) {
+                index_log_topics(connection, "cold")?;
+            }
+            if version.is_some_and(|version| version < 3) {
+                index_log_blooms(connection, "cold")?;
+            }
+            Ok(())
+        })?;
+    } else if upgrade {
+        return Err(Error::new(
+            "cold storage was written by an older version; open it for writing once to upgrade it",
+        ));
+    }
+    for table in COLD_TABLES {
+        connection.execute(format!(
+            "CREATE TEMP VIEW {table} AS
+                SELECT * FROM main.{table} UNION ALL SELECT * FROM cold.{table}"
+        ))?;
+    }
+    Ok(())
+}
+
+fn is_cold_attached(connection: &Connection) -> Result<bool> {
+    let query = "SELECT 1 FROM pragma_database_list WHERE name = 'cold'";
+    Ok(connection.prepare(query)?.into_iter().next().is_some())
+}
+
+/// `table` in each database that holds its rows: the main one, and cold storage if attached.
+fn tiers(connection: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut tiers = vec![format!("main.{table}")];
+    if COLD_TABLES.contains(&table) && is_cold_attached(connection)? {
+        tiers.push(format!("cold.{table}"));
+    }
+    Ok(tiers)
+}
+
+/// The height through which canonical blocks have been moved to cold storage, if any.
+pub(crate) fn read_cold_through(connection: &Connection) -> Result<Option<u64>> {
+    let query = "SELECT MAX(number) AS number FROM cold.moved_through";
+    let Some(row) = connection.prepare(query)?.into_iter().next() else {
+        return Ok(None);
+    };
+    Ok(row?
+        .read::<Option<i64>, _>("number")
+        .and_then(|number| u64::try_from(number).ok()))
+}
+
+/// Copy the transactions, receipts and logs of the canonical blocks numbered `first` to
+/// `through` to cold storage, and record that blocks have been moved through `through`.
+pub(crate) fn copy_to_cold(connection: &Connection, first: u64, through: u64) -> Result<()> {
+    for table in COLD_TABLES {
+        let mut statement = connection.prepare(format!(
+            "INSERT INTO cold.{table} SELECT * FROM main.{table}
+                WHERE block_hash IN
+                    (SELECT hash FROM block WHERE canonical = 1 AND number BETWEEN ?1 AND ?2)"
+        ))?;
+        statement.bind((1, first as i64))?;
+        statement.bind((2, through as i64))?;
+        statement.next()?;
+    }
+    connection.execute("DELETE FROM cold.moved_through")?;
+    let mut statement = connection.prepare("INSERT INTO cold.moved_through VALUES (?)")?;
+    statement.bind((1, through as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Delete from the main database the rows [`copy_to_cold`] copied of the canonical blocks
+/// numbered `first` to `through`.
+pub(crate) fn delete_moved(connection: &Connection, first: u64, through: u64) -> Result<()> {
+    for table in COLD_TABLES {
+        let mut statement = connection.prepare(format!(
+            "DELETE FROM main.{table}
+                WHERE block_hash IN
+                    (SELECT hash FROM block WHERE canonical = 1 AND number BETWEEN ?1 AND ?2)"
+        ))?;
+        statement.bind((1, first as i64))?;
+        statement.bind((2, through as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Write a consistent copy of the database to the new file `path`.
+pub(crate) fn copy_to(connection: &Connection, path: &std::path::Path) -> Result<()> {
+    let mut statement = connection.prepare("VACUUM INTO ?")?;
+    statement.bind((1, path.to_string_lossy().as_ref()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Delete side branches and traces, leaving the canonical chain and its state.
+pub(crate) fn delete_side_branches_and_traces(connection: &Connection) -> Result<()> {
+    connection.execute("DELETE FROM trace")?;
+    connection.execute("DELETE FROM block WHERE canonical = 0")?;
+    Ok(())
+}
+
+/// Rebuild the database file to reclaim free pages, then refresh the query planner statistics.
+/// Must not be called inside a transaction.
+pub(crate) fn compact(connection: &Connection) -> Result<()> {
+    connection.execute("VACUUM")?;
+    connection.execute("ANALYZE")?;
+    Ok(())
+}
+
+/// Run `f` inside a SQLite transaction that is always rolled back, for work against a temporarily
+/// rewound state.
+pub(crate) fn scratch<T>(connection: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    connection.execute("BEGIN")?;
+    let result = f();
+    connection.execute("ROLLBACK")?;
+    result
+}
+
+/// Abort the process if `BLOCKHEAD_CRASH_AT` names `point`, so that tests can check a node killed
+/// in the middle of a write restarts at a consistent head. Does nothing outside of tests.
+#[cfg(test)]
+pub(crate) fn crash_point(point: &str) {
+    if std::env::var("BLOCKHEAD_CRASH_AT").as_deref() == Ok(point) {
+        std::process::abort();
+    }
+}
+
+#[cfg(not(test))]
+pub(crate) fn crash_point(_point: &str) {}
+
+/// Run `f` inside a SQLite transaction, committing on success and rolling back on error,
+/// including a failed commit.
+pub(crate) fn transaction<T>(connection: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    connection.execute("BEGIN")?;
+    match f() {
+        Ok(value) => {
+            crash_point("commit_transaction");
+            // A COMMIT that fails, on a busy database or a deferred constraint, leaves the
+            // transaction open, and every later BEGIN on the connection would fail.
+            if let Err(error) = connection.execute("COMMIT") {
+                if let Err(rollback) = connection.execute("ROLLBACK") {
+                    log::error!("rolling back after a failed commit failed: {rollback}");
+                }
+                return Err(error.into());
+            }
+            Ok(value)
+        }
+        Err(error) => {
+            connection.execute("ROLLBACK")?;
+            Err(error)
+        }
+    }
+}
+
+#[test]
+fn test_transaction_rolls_back_failed_commit() {
+    let connection = sqlite::open(":memory:").unwrap();
+    connection
+        .execute(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE parent (id INTEGER PRIMARY KEY);
+            CREATE TABLE child (
+                parent INTEGER REFERENCES parent (id) DEFERRABLE INITIALLY DEFERRED
+            );",
+        )
+        .unwrap();
+    let insert = |query| transaction(&connection, || Ok(connection.execute(query)?));
+    // The missing parent is only found at COMMIT, which fails.
+    assert!(insert("INSERT INTO child VALUES (1)").is_err());
+    // The transaction is over, and none of it was kept.
+    insert("INSERT INTO parent VALUES (2)").unwrap();
+    let mut statement = connection.prepare("SELECT COUNT(*) FROM child").unwrap();
+    statement.next().unwrap();
+    assert_eq!(statement.read::<i64, _>(0).unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_upgrade_from_hex_text() {
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::token::TokenInfo;
+    use crate::{Blockchain, Blockhead};
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, path) = crate::pool::file_chain("upgrade");
+    let (validator, alice) = (testkit::validator(), Address([8; 32]));
+    let transaction = |kind, to_address, value, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        value,
+        data: data.into(),
+        gas_limit: 30_000,
+        ..testkit::transaction(kind, validator, nonce)
+    };
+    let info = TokenInfo {
+        name: "Blockhead Dollar".into(),
+        symbol: "BHD".into(),
+        decimals: 2,
+        total_supply: 1_000,
+    };
+    let token = Address::for_token(validator, 0, algorithm);
+    for transaction in [
+        transaction(TransactionKind::CreateToken, None, 0, info.encode(), 0),
+        transaction(
+            TransactionKind::TokenTransfer,
+            Some(alice),
+            300,
+            token.0.to_vec(),
+            1,
+        ),
+        transaction(TransactionKind::Transfer, Some(alice), 5, Vec::new(), 2),
+    ] {
+        blockhead.send_transaction(transaction).await.unwrap();
+    }
+    let block = blockhead.produce_block().unwrap();
+    blockhead.index_receipts(usize::MAX).unwrap();
+    let log = Log {
+        address: token,
+        topics: vec![Hash([1; 32])],
+        data: vec![2],
+    };
+    let receipt = TransactionReceipt {
+        logs: vec![log],
+        ..blockhead
+            .get_transaction_receipt(block.body.transactions[2].0)
+            .await
+            .unwrap()
+            .unwrap()
+    };
+    write_logs(
+        &blockhead.connection,
+        &block,
+        std::slice::from_ref(&receipt),
+    )
+    .unwrap();
+    let transactions = read_account_transactions(&blockhead.connection, alice, None, 10).unwrap();
+    assert_eq!(transactions.len(), 2);
+    let logs = read_block_logs(&blockhead.connection, block.hash).unwrap();
+    assert_eq!(logs.len(), 1);
+
+    // Rewrite every table as databases held it before hashes and addresses were bytes.
+    let hex = |column: &str| format!("nullif('0x' || lower(hex({column})), '0x')");
+    let query = "SELECT name FROM sqlite_schema
+        WHERE type = 'table' AND name NOT IN ('address', 'log_topic', 'log_bloom', 'chain_config')";
+    let tables: Vec<String> = (blockhead.connection.prepare(query).unwrap().into_iter())
+        .map(|row| row.unwrap().read::<&str, _>("name").to_string())
+        .collect();
+    for table in tables {
+        let query = "SELECT name, type FROM pragma_table_info(?)";
+        let mut columns = Vec::new();
+        for row in blockhead
+            .connection
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .bind((1, table.as_str()))
+            .unwrap()
+        {
+            let row = row.unwrap();
+            let name = row.read::<&str, _>("name");
+            let interned = |old: &str| {
+                let address = hex("address");
+                format!("(SELECT {address} FROM address WHERE id = {name}) AS {old}")
+            };
+            let bytes = [
+                "data",
+                "signatures",
+                "code",
+                "policy",
+                "info",
+                "return_data",
+                "raw",
+            ];
+            // Blocks were not signed before version 4.
+            if name == "signature" {
+                continue;
+            }
+            columns.push(match name {
+                "from_id" => interned("from_address"),
+                "to_id" => interned("to_address"),
+                "address_id" => interned("address"),
+                "slot" => format!(
+                    "CASE length(slot) WHEN 32 THEN 'balance:' || {}
+                        ELSE 'allowance:' || {} || ':' || {} END AS slot",
+                    hex("slot"),
+                    hex("substr(slot, 1, 32)"),
+                    hex("substr(slot, 33)")
+                ),
+                _ if row.read::<&str, _>("type") == "BLOB" && !bytes.contains(&name) => {
+                    format!("{} AS {name}", hex(name))
+                }
+                _ => name.to_string(),
+            });
+        }
+        blockhead
+            .connection
+            .execute(format!(
+                "CREATE TABLE old AS SELECT {} FROM {table};
+                DROP TABLE {table};
+                ALTER TABLE old RENAME TO {table};",
+                columns.join(", ")
+            ))
+            .unwrap();
+    }
+    blockhead
+        .connection
+        .execute(
+            "DROP TABLE address;
+            DROP TABLE log_topic;
+            DROP TABLE log_bloom;
+            DROP TABLE chain_config;
+            CREATE INDEX transactions_from_address ON transactions (from_address);
+            PRAGMA user_version = 0;",
+        )
+        .unwrap();
+    drop(blockhead);
+    assert!(Blockhead::new_read_only(&path).is_err());
+
+    let blockhead = Blockhead::new(&path).unwrap();
+    let connection = &blockhead.connection;
+    let mut block = block;
+    block.header.signature = None;
+    assert_eq!(blockhead.head().unwrap().encode(), block.encode());
+    let hashes = |transactions: Vec<(Hash, Transaction)>| -> Vec<Hash> {
+        transactions.into_iter().map(|(hash, _)| hash).collect()
+    };
+    assert_eq!(
+        hashes(read_account_transactions(connection, alice, None, 10).unwrap()),
+        hashes(transactions)
+    );
+    assert_eq!(read_block_logs(connection, block.hash).unwrap(), logs);
+    let filter = LogFilter {
+        addresses: Vec::new(),
+        topics: vec![vec![Hash([1; 32])]],
+    };
+    assert_eq!(read_logs(connection, &filter, 0, 10).unwrap(), logs);
+    let filter = LogFilter {
+        addresses: vec![token],
+        topics: Vec::new(),
+    };
+    assert_eq!(read_logs(connection, &filter, 0, 10).unwrap(), logs);
+    let hash = block.body.transactions[2].0;
+    let upgraded = blockhead.get_transaction_receipt(hash).await.unwrap();
+    assert_eq!(
+        serde_json::to_value(upgraded.unwrap()).unwrap(),
+        serde_json::to_value(receipt).unwrap()
+    );
+    assert_eq!(
+        blockhead.get_token_balance(token, alice).await.unwrap(),
+        300
+    );
+    assert_eq!(blockhead.get_balances(&[alice]).await.unwrap(), [5]);
+    let indexes: Vec<String> = read_schema_objects(connection)
+        .unwrap()
+        .into_iter()
+        .filter(|(kind, _, table)| kind == "index" && table == "transactions")
+        .map(|(_, name, _)| name)
+        .collect();
+    assert!(indexes.contains(&"transactions_from_id".to_string()));
+    assert!(!indexes.contains(&"transactions_from_address".to_string()));
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+    drop(blockhead);
+    Blockhead::new_read_only(&path).unwrap();
+}
+
+#[test]
+fn test_read_logs_by_topic() {
+    use crate::chain::side_block;
+    use crate::Blockhead;
+
+    let (blockhead, path) = crate::pool::file_chain("log-topics");
+    let genesis = blockhead.head().unwrap();
+    let blocks = [
+        blockhead.produce_block().unwrap(),
+        blockhead.produce_block().unwrap(),
+    ];
+    let side = side_block(&blockhead, &genesis, genesis.timestamp + 1);
+    blockhead.import_block(&side).unwrap();
+    let topic = |byte| Hash([byte; 32]);
+    let write = |block: &Block, logs: Vec<(u8, Vec<u8>)>| {
+        let logs = (logs.into_iter())
+            .map(|(address, topics)| Log {
+                address: Address([address; 32]),
+                topics: topics.into_iter().map(topic).collect(),
+                data: Vec::new(),
+            })
+            .collect();
+        let receipt = TransactionReceipt {
+            transaction_hash: Hash([block.number as u8; 32]),
+            block_hash: block.hash,
+            status: true,
+            gas_used: 0,
+            contract_address: None,
+            return_data: Vec::new(),
+            logs,
+        };
+        write_logs(&blockhead.connection, block, &[receipt]).unwrap();
+    };
+    write(
+        &blocks[0],
+        vec![(9, vec![1, 2]), (9, vec![2, 1]), (9, vec![])],
+    );
+    write(&blocks[1], vec![(9, vec![1]), (8, vec![3, 2])]);
+    write(&side, vec![(8, vec![1])]);
+
+    // Each matching log of a canonical block in the range, by block number and log index.
+    let read_filtered = |connection: &Connection, filter: LogFilter, from, to| {
+        (read_logs(connection, &filter, from, to)
+            .unwrap()
+            .into_iter())
+        .map(|entry| (entry.block_number, entry.log_index))
+        .collect::<Vec<_>>()
+    };
+    let read = |connection: &Connection, topics: Vec<Vec<u8>>, from, to| {
+        let filter = LogFilter {
+            addresses: Vec::new(),
+            topics: (topics.into_iter())
+                .map(|topics| topics.into_iter().map(topic).collect())
+                .collect(),
+        };
+        read_filtered(connection, filter, from, to)
+    };
+    let check = |connection: &Connection| {
+        assert_eq!(
+            read(connection, vec![vec![1]], 0, u64::MAX),
+            [(1, 0), (2, 0)]
+        );
+        assert_eq!(
+            read(connection, vec![vec![], vec![1, 2]], 0, u64::MAX),
+            [(1, 0), (1, 1), (2, 1)]
+        );
+        assert_eq!(read(connection, vec![vec![2], vec![1]], 0, 10), [(1, 1)]);
+        assert_eq!(read(connection, vec![vec![1]], 2, 2), [(2, 0)]);
+        assert_eq!(read(connection, vec![], 2, 2), [(2, 0), (2, 1)]);
+        assert!(read(connection, vec![vec![4]], 0, u64::MAX).is_empty());
+        // Addresses alone are matched through the blooms of the blocks.
+        let by_address = |addresses: &[u8], from, to| {
+            let filter = LogFilter {
+                addresses: addresses.iter().map(|&a| Address([a; 32])).collect(),
+                topics: Vec::new(),
+            };
+            read_filtered(connection, filter, from, to)
+        };
+        assert_eq!(by_address(&[8], 0, u64::MAX), [(2, 1)]);
+        assert_eq!(by_address(&[7, 9], 0, 1), [(1, 0), (1, 1), (1, 2)]);
+        assert!(by_address(&[8], 0, 1).is_empty());
+        assert!(by_address(&[7], 0, u64::MAX).is_empty());
+    };
+    check(&blockhead.connection);
+
+    // A database from before the blooms, or the index as well, gets them on upgrading.
+    blockhead
+        .connection
+        .execute(
+            "DROP TABLE log_bloom;
+            ALTER TABLE block DROP COLUMN signature;
+            PRAGMA user_version = 2;",
+        )
+        .unwrap();
+    drop(blockhead);
+    assert!(Blockhead::new_read_only(&path).is_err());
+    let blockhead = Blockhead::open(&path).unwrap();
+    check(&blockhead.connection);
+    blockhead
+        .connection
+        .execute(
+            "DROP TABLE log_topic;
+            DROP TABLE log_bloom;
+            DROP INDEX log_position;
+            CREATE INDEX log_block_hash ON log (block_hash);
+            ALTER TABLE block DROP COLUMN signature;
+            PRAGMA user_version = 1;",
+        )
+        .unwrap();
+    drop(blockhead);
+    assert!(Blockhead::new_read_only(&path).is_err());
+    let blockhead = Blockhead::open(&path).unwrap();
+    check(&blockhead.connection);
+    let indexes: Vec<String> = (read_schema_objects(&blockhead.connection).unwrap())
+        .into_iter()
+        .filter(|(kind, _, table)| kind == "index" && table == "log")
+        .map(|(_, name, _)| name)
+        .collect();
+    assert!(indexes.contains(&"log_position".to_string()));
+    assert!(!indexes.contains(&"log_block_hash".to_string()));
+    drop(blockhead);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
@@ -0,0 +1,502 @@
+//! Dev mode: instant sealing and time travel for local development.
+//!
+//! Rather than waiting for a slot, a dev node seals a block as soon as a transaction enters the
+//! mempool, so a test script sees its transaction's receipt as soon as it has sent it. It runs
+//! no consensus: the node must be the only validator, as on the `dev` chain preset.
+//!
+//! For testing contracts that depend on time or height, a dev node also serves `/dev` endpoints
+//! (see [`crate::HttpConfig::dev`]) that mine blocks on demand, move the node's time forward and
+//! fix the next block's timestamp. Moved time is the node's everywhere: block timestamps, the
+//! drift allowed to imported blocks and mempool expiry all follow it.
+//!
+//! Test suites isolate scenarios with [`Blockhead::snapshot`] and [`Blockhead::revert`], also
+//! served under `/dev`. Unlike the fast sync snapshots of [`crate::snapshot`], these copy
+//! nothing: a snapshot remembers the head, the mempool and the moved time, and reverting undoes
+//! the blocks since with the undo records a reorg uses, so it costs as much as the blocks it
+//! removes. It cannot go back past a state checkpoint, which prunes those records.
+use crate::block::Block;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::events::ChainEvent;
+use crate::hash::Hash;
+#[cfg(feature = "http")]
+use crate::http::{Request, Response};
+#[cfg(feature = "http")]
+use crate::runtime;
+use crate::state;
+use crate::status::TransactionStatus;
+use crate::transaction::Transaction;
+use crate::Blockhead;
+#[cfg(feature = "http")]
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How far dev mode has moved the node's time, and the timestamp it fixed for the next block.
+#[derive(Debug, Default)]
+pub(crate) struct TimeTravel {
+    /// Nanoseconds added to the clock.
+    offset: AtomicU64,
+    next_timestamp: Mutex<Option<u64>>,
+}
+
+impl TimeTravel {
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// The timestamp set by [`Blockhead::set_next_block_timestamp`], if no block has taken it.
+    pub(crate) fn next_timestamp(&self) -> Option<u64> {
+        *self.next_timestamp.lock().unwrap()
+    }
+}
+
+/// What [`Blockhead::revert`] goes back to.
+#[derive(Debug, Clone)]
+struct Snapshot {
+    id: u64,
+    head: Hash,
+    pending: Vec<Transaction>,
+    offset: u64,
+    next_timestamp: Option<u64>,
+}
+
+/// The snapshots taken and not yet reverted past, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct Snapshots {
+    next_id: u64,
+    saved: Vec<Snapshot>,
+}
+
+impl Blockhead {
+    /// Produce blocks until the mempool is empty, or until a block takes none of what is left,
+    /// returning the blocks produced. Produces nothing while production is paused.
+    pub(crate) fn seal_pending(&self) -> Result<Vec<Block>> {
+        let mut blocks = Vec::new();
+        if self.is_production_paused() {
+            return Ok(blocks);
+        }
+        while self.mempool.lock().unwrap().len() > 0 {
+            let block = self.produce_block()?;
+            let stalled = block.body.transactions.is_empty();
+            blocks.push(block);
+            if stalled {
+                break;
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Produce `count` blocks now, with whatever the mempool holds or empty, returning them.
+    pub(crate) fn mine(&self, count: u64) -> Result<Vec<Block>> {
+        (0..count).map(|_| self.produce_block()).collect()
+    }
+
+    /// Move the node's time `duration` ahead of its clock, on top of earlier moves, returning
+    /// how far ahead it is now.
+    pub(crate) fn increase_time(&self, duration: Duration) -> Duration {
+        let added = duration.as_nanos() as u64;
+        let offset = self.time_travel.offset.fetch_add(added, Ordering::SeqCst) + added;
+        log::info!("moved the node's time {}s ahead", duration.as_secs_f64());
+        Duration::from_nanos(offset)
+    }
+
+    /// Give the next block produced the timestamp `timestamp`, which must be past the median
+    /// time past of the head. Once it is produced, the node's time moves on to at least
+    /// `timestamp`, so that the blocks after it follow it.
+    pub(crate) fn set_next_block_timestamp(&self, timestamp: u64) -> Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        let median = self.median_time_past(&self.head()?.header)?;
+        if timestamp <= median {
+            return Err(Error::new(format!(
+                "timestamp {timestamp} is at or before the median time past {median}"
+            )));
+        }
+        *self.time_travel.next_timestamp.lock().unwrap() = Some(timestamp);
+        Ok(())
+    }
+
+    /// Remember the head, the mempool and the node's time, returning an ID to pass to
+    /// [`Blockhead::revert`].
+    pub(crate) fn snapshot(&self) -> Result<u64> {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.next_id += 1;
+        let snapshot = Snapshot {
+            id: snapshots.next_id,
+            head: self.head()?.hash,
+            pending: self.mempool.lock().unwrap().pending(),
+            offset: self.time_travel.offset(),
+            next_timestamp: self.time_travel.next_timestamp(),
+        };
+        log::info!("took snapshot {} at block {}", snapshot.id, snapshot.head);
+        snapshots.saved.push(snapshot);
+        Ok(snapshots.next_id)
+    }
+
+    /// Go back to snapshot `id`: delete the blocks produced or imported since, put back the
+    /// mempool and the node's time, and return the head. This discards snapshot `id` and those
+    /// taken after it, but not those taken before.
+    pub(crate) fn revert(&self, id: u64) -> Result<Block> {
+        self.ensure_writable("revert to a snapshot")?;
+        let _guard = self.write_lock.lock().unwrap();
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let index = snapshots
+            .saved
+            .iter()
+            .position(|snapshot| snapshot.id == id)
+            .ok_or_else(|| Error::new(format!("no snapshot {id}")))?;
+        let snapshot = snapshots.saved[index].clone();
+        let (head, reverted) = db::transaction(&self.connection, || {
+            if db::read_block_is_canonical(&self.connection, snapshot.head)? != Some(true) {
+                return Err(Error::new(format!(
+                    "snapshot {id} is of block {}, which is no longer canonical",
+                    snapshot.head
+                )));
+            }
+            let mut reverted = Vec::new();
+            let mut block = self.head()?;
+            while block.hash != snapshot.head {
+                state::revert_block(&self.connection, block.hash)?;
+                db::set_canonical(&self.connection, block.hash, false)?;
+                let parent_hash = block.parent_hash;
+                reverted.push(block);
+                block = db::read_block(&self.connection, parent_hash)?
+                    .ok_or_else(|| Error::new(format!("missing ancestor {parent_hash}")))?;
+            }
+            db::delete_epochs_after(&self.connection, self.config.staking.epoch_of(block.number))?;
+            db::delete_blocks_above(&self.connection, block.number)?;
+            // Deleting an orphaned block can orphan its descendants.
+            while !db::count_orphans(&self.connection)?.is_empty() {
+                db::delete_orphans(&self.connection)?;
+            }
+            Ok((block, reverted))
+        })?;
+        snapshots.saved.truncate(index);
+        self.imports.forget_recent();
+        // The reorg puts the reverted blocks' transactions back in the mempool, which is then
+        // replaced with the snapshot's.
+        self.publish_chain_events(vec![ChainEvent::Reorg {
+            ancestor: head.hash,
+            reverted,
+        }]);
+        {
+            let mut mempool = self.mempool.lock().unwrap();
+            let mut status = self.status.lock().unwrap();
+            let hash = |transaction: &Transaction| transaction.compute_hash(self.config.hash);
+            let discarded: Vec<Hash> = mempool.take().iter().map(hash).collect();
+            mempool.forget(&discarded);
+            let kept: Vec<Hash> = snapshot.pending.iter().map(hash).collect();
+            for hash in discarded.iter().filter(|hash| !kept.contains(hash)) {
+                let reason = format!("the chain was reverted to snapshot {id}");
+                status.publish(*hash, TransactionStatus::Dropped { reason });
+            }
+            for hash in &kept {
+                status.publish(*hash, TransactionStatus::Pending);
+            }
+            mempool.restore(snapshot.pending);
+        }
+        self.time_travel
+            .offset
+            .store(snapshot.offset, Ordering::SeqCst);
+        *self.time_travel.next_timestamp.lock().unwrap() = snapshot.next_timestamp;
+        log::info!("reverted to snapshot {id} at block {}", head.number);
+        Ok(head)
+    }
+
+    /// Clear the timestamp a just-produced block took from [`Blockhead::set_next_block_timestamp`]
+    /// and bring the node's time up to it. Called with the write lock held.
+    pub(crate) fn took_next_timestamp(&self, timestamp: u64) {
+        let mut next = self.time_travel.next_timestamp.lock().unwrap();
+        if *next != Some(timestamp) {
+            return;
+        }
+        *next = None;
+        let behind = timestamp.saturating_sub(self.now_nanos());
+        self.time_travel.offset.fetch_add(behind, Ordering::SeqCst);
+    }
+}
+
+/// Seal pending transactions into blocks as they arrive, until the task is dropped.
+pub(crate) async fn run(blockhead: Arc<Blockhead>) {
+    let mut events = blockhead.subscribe_chain_events();
+    // Seal before waiting: transactions that arrived before the subscription are not announced.
+    loop {
+        let sealer = blockhead.clone();
+        match tokio::task::spawn_blocking(move || sealer.seal_pending()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => log::error!("sealing failed: {error}"),
+            Err(error) => log::error!("sealing panicked: {error}"),
+        }
+        loop {
+            match events.recv().await {
+                // Missed events may have announced transactions, so check the mempool anyway.
+                Ok(ChainEvent::NewPendingTx(_)) | Err(RecvError::Lagged(_)) => break,
+                Ok(_) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+/// Answer `POST /dev/<action>`:
+///
+/// - `mine?blocks=<count>`: produce `count` blocks, one without it.
+/// - `increase_time?seconds=<seconds>`: move the node's time forward.
+/// - `set_next_block_timestamp?timestamp=<nanoseconds>`: fix the next block's timestamp.
+/// - `snapshot`: take a snapshot, answering its ID.
+/// - `revert?id=<id>`: go back to snapshot `id`.
+///
+/// Only routed while [`crate::HttpConfig::dev`] is set.
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, request: &Request) -> Response {
+    if request.method != "POST" {
+        return Response::error(405, "use POST");
+    }
+    let number = |name: &str| -> Result<Option<u64>> {
+        request
+            .query_param(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Error::new(format!("bad {name} {value:?}")))
+            })
+            .transpose()
+    };
+    let result = match request.path.trim_start_matches("/dev/") {
+        "mine" => match number("blocks") {
+            Ok(count) => {
+                let blockhead = blockhead.clone();
+                let count = count.unwrap_or(1);
+                runtime::spawn_blocking(move || blockhead.mine(count))
+                    .await
+                    .map(|blocks| {
+                        let blocks: Vec<_> = blocks
+                            .iter()
+                            .map(|block| {
+                                json!({
+                                    "number": block.number,
+                                    "hash": block.hash,
+                                    "timestamp": block.timestamp,
+                                })
+                            })
+                            .collect();
+                        json!({ "blocks": blocks })
+                    })
+            }
+            Err(error) => return Response::from_error(400, &error),
+        },
+        "increase_time" => match number("seconds") {
+            Ok(Some(seconds)) => {
+                let offset = blockhead.increase_time(Duration::from_secs(seconds));
+                Ok(json!({"offset_seconds": offset.as_secs_f64()}))
+            }
+            Ok(None) => return Response::error(400, "missing seconds"),
+            Err(error) => return Response::from_error(400, &error),
+        },
+        "set_next_block_timestamp" => match number("timestamp") {
+            Ok(Some(timestamp)) => {
+                let blockhead = blockhead.clone();
+                runtime::spawn_blocking(move || blockhead.set_next_block_timestamp(timestamp))
+                    .await
+                    .map(|()| json!({"next_timestamp": timestamp}))
+            }
+            Ok(None) => return Response::error(400, "missing timestamp"),
+            Err(error) => return Response::from_error(400, &error),
+        },
+        "snapshot" => {
+            let blockhead = blockhead.clone();
+            runtime::spawn_blocking(move || blockhead.snapshot())
+                .await
+                .map(|id| json!({ "id": id }))
+        }
+        "revert" => match number("id") {
+            Ok(Some(id)) => {
+                let blockhead = blockhead.clone();
+                runtime::spawn_blocking(move || blockhead.revert(id))
+                    .await
+                    .map(|head| json!({"number": head.number, "hash": head.hash}))
+            }
+            Ok(None) => return Response::error(400, "missing id"),
+            Err(error) => return Response::from_error(400, &error),
+        },
+        _ => return Response::error(404, format!("no endpoint {}", request.path)),
+    };
+    match result {
+        Ok(value) => Response::json(200, &value),
+        Err(error) => Response::from_error(400, &error),
+    }
+}
+
+#[tokio::test]
+async fn test_instant_sealing() {
+    use crate::address::Address;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+    use std::time::Duration;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let blockhead = Arc::new(blockhead);
+    let mut events = blockhead.subscribe_chain_events();
+    let sealer = tokio::spawn(run(blockhead.clone()));
+    let transfer = |nonce| Transaction {
+        to_address: Some(Address([8; 32])),
+        value: 5,
+        ..testkit::transaction(Default::default(), validator, nonce)
+    };
+    for nonce in 0..2 {
+        let hash = blockhead.send_transaction(transfer(nonce)).await.unwrap();
+        let block = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let ChainEvent::NewBlock(block) = events.recv().await.unwrap() {
+                    return block;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(block.number, nonce + 1);
+        assert_eq!(block.body.transactions[0].0, hash);
+    }
+    assert_eq!(blockhead.get_balance(Address([8; 32])).await.unwrap(), 10);
+    // Nothing is sealed without transactions.
+    assert!(blockhead.seal_pending().unwrap().is_empty());
+    sealer.abort();
+}
+
+#[test]
+fn test_time_travel() {
+    use crate::clock::Clock;
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let blockhead = &chain.blockhead;
+    let start = blockhead.head().unwrap().timestamp;
+    let blocks = blockhead.mine(3).unwrap();
+    assert_eq!(
+        blocks.iter().map(|block| block.number).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+
+    let hour = Duration::from_secs(3600);
+    assert_eq!(blockhead.increase_time(hour), hour);
+    let block = blockhead.produce_block().unwrap();
+    assert!(block.timestamp >= start + hour.as_nanos() as u64);
+    assert_eq!(
+        blockhead.now_nanos(),
+        chain.clock.now_nanos() + hour.as_nanos() as u64
+    );
+
+    let later = block.timestamp + 10 * hour.as_nanos() as u64;
+    assert!(blockhead.set_next_block_timestamp(start).is_err());
+    blockhead.set_next_block_timestamp(later).unwrap();
+    assert_eq!(blockhead.time_travel.next_timestamp(), Some(later));
+    assert_eq!(blockhead.produce_block().unwrap().timestamp, later);
+    assert_eq!(blockhead.time_travel.next_timestamp(), None);
+    // The blocks after it follow it rather than going back to the clock.
+    assert!(blockhead.produce_block().unwrap().timestamp >= later);
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_dev_endpoints() {
+    use crate::http::{send, serve_locally};
+    use crate::testkit::TestChain;
+
+    let mut chain = TestChain::new();
+    chain.blockhead.config.http.dev = true;
+    let blockhead = Arc::new(chain.blockhead);
+    let address = serve_locally(blockhead.clone()).await;
+    let post = |target: &str| format!("POST {target} HTTP/1.1\r\n\r\n");
+
+    let (status, body) = send(address, &post("/dev/mine?blocks=2")).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["blocks"][1]["number"], 2);
+    let (status, body) = send(address, &post("/dev/increase_time?seconds=60")).await;
+    assert_eq!((status, &body["offset_seconds"]), (200, &json!(60.0)));
+    let timestamp = blockhead.now_nanos() + 1_000_000_000_000;
+    let target = format!("/dev/set_next_block_timestamp?timestamp={timestamp}");
+    assert_eq!(send(address, &post(&target)).await.0, 200);
+    let (_, body) = send(address, &post("/dev/mine")).await;
+    assert_eq!(body["blocks"][0]["timestamp"], timestamp);
+    assert_eq!(
+        send(address, &post("/dev/set_next_block_timestamp?timestamp=1"))
+            .await
+            .0,
+        400
+    );
+    assert_eq!(send(address, &post("/dev/mine?blocks=x")).await.0, 400);
+    assert_eq!(send(address, "GET /dev/mine HTTP/1.1\r\n\r\n").await.0, 405);
+    let (_, body) = send(address, &post("/dev/snapshot")).await;
+    let id = body["id"].as_u64().unwrap();
+    send(address, &post("/dev/mine?blocks=2")).await;
+    let (status, body) = send(address, &post(&format!("/dev/revert?id={id}"))).await;
+    assert_eq!((status, &body["number"]), (200, &json!(3)));
+    assert_eq!(blockhead.head().unwrap().number, 3);
+    let (status, _) = send(address, &post(&format!("/dev/revert?id={id}"))).await;
+    assert_eq!(status, 400);
+    assert_eq!(send(address, &post("/dev/nothing")).await.0, 404);
+
+    let address = serve_locally(Arc::new(Blockhead::new(":memory:").unwrap())).await;
+    assert_eq!(send(address, &post("/dev/mine")).await.0, 404);
+}
+
+#[tokio::test]
+async fn test_snapshot_and_revert() {
+    use crate::address::Address;
+    use crate::block::BlockId;
+    use crate::testkit::TestChain;
+    use crate::Blockchain;
+
+    let chain = TestChain::new();
+    let blockhead = &chain.blockhead;
+    let bob = Address([8; 32]);
+    chain.transfer(chain.validator, bob, 5).await;
+    chain.produce();
+    let pending = chain.transfer(chain.validator, bob, 6).await;
+    blockhead.increase_time(Duration::from_secs(60));
+    let first = blockhead.snapshot().unwrap();
+    let head = blockhead.head().unwrap();
+
+    blockhead.increase_time(Duration::from_secs(60));
+    chain.produce();
+    let later = chain.transfer(chain.validator, bob, 7).await;
+    chain.produce();
+    let second = blockhead.snapshot().unwrap();
+    chain.transfer(chain.validator, bob, 8).await;
+    assert_eq!(blockhead.get_balance(bob).await.unwrap(), 18);
+
+    assert_eq!(blockhead.revert(first).unwrap().hash, head.hash);
+    assert_eq!(blockhead.head().unwrap().hash, head.hash);
+    assert!(blockhead.canonical_block(BlockId::Number(2)).is_err());
+    assert_eq!(blockhead.get_balance(bob).await.unwrap(), 5);
+    let mempool = blockhead.mempool.lock().unwrap().pending();
+    assert_eq!(
+        mempool
+            .iter()
+            .map(|transaction| transaction.compute_hash(blockhead.config.hash))
+            .collect::<Vec<_>>(),
+        [pending]
+    );
+    assert_eq!(blockhead.time_travel.offset(), 60_000_000_000);
+    assert!(matches!(
+        blockhead.get_transaction_status(later).await.unwrap(),
+        TransactionStatus::Dropped { .. }
+    ));
+    // Reverting discards the snapshot and those after it.
+    assert!(blockhead.revert(first).is_err());
+    assert!(blockhead.revert(second).is_err());
+
+    // The chain carries on from the snapshot.
+    let block = chain.produce();
+    assert_eq!(block.number, 2);
+    assert_eq!(block.body.transactions[0].0, pending);
+    assert_eq!(blockhead.get_balance(bob).await.unwrap(), 11);
+    let third = blockhead.snapshot().unwrap();
+    assert!(third > second);
+    chain.produce();
+    assert_eq!(blockhead.revert(third).unwrap().number, 2);
+}
@@ -1,3 +1,4 @@
+use serde_json::json;
 use std::{num::ParseIntError, panic::Location};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -41,10 +42,112 @@ impl<T, E: std::fmt::Debug> Context<T> for std::result::Result<T, E> {
     }
 }
 
+/// What went wrong, for callers that need to react to more than the message.
+///
+/// API error responses carry the kind as a numeric [`ErrorKind::code`] and its fields as
+/// [`ErrorKind::data`], so that clients in any language can branch on them:
+///
+/// | code   | kind                  | data                                  |
+/// |--------|-----------------------|---------------------------------------|
+/// | -32000 | [`ErrorKind::Other`]  | none                                  |
+/// | 3      | `Reverted`            | `return_data`, hex                    |
+/// | -32005 | `RateLimited`         | `retry_after_seconds`                 |
+/// | -32010 | `InsufficientFunds`   | `required`, `available`               |
+/// | -32011 | `NonceMismatch`       | `expected`, `actual`                  |
+/// | -32012 | `Underpriced`         | `minimum`, `offered`                  |
+///
+/// Codes are stable: a kind keeps its code across versions, and new kinds get new codes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ErrorKind {
+    #[default]
+    Other,
+    /// Contract execution reverted with `return_data`.
+    Reverted { return_data: Vec<u8> },
+    /// Refused because the caller asked too often. Asking again after `retry_after` may succeed.
+    RateLimited { retry_after: std::time::Duration },
+    /// The sender's balance of `available` cannot cover the `required` amount.
+    InsufficientFunds { required: u64, available: u64 },
+    /// The transaction's nonce is `actual`, but the sender's next is `expected`.
+    NonceMismatch { expected: u64, actual: u64 },
+    /// The transaction's gas price of `offered` is below the `minimum` the node accepts, or below
+    /// what replacing a waiting transaction takes.
+    Underpriced { minimum: u64, offered: u64 },
+}
+
+impl ErrorKind {
+    /// The kind's number in API error responses. See the table on [`ErrorKind`].
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::Other => -32000,
+            Self::Reverted { .. } => 3,
+            Self::RateLimited { .. } => -32005,
+            Self::InsufficientFunds { .. } => -32010,
+            Self::NonceMismatch { .. } => -32011,
+            Self::Underpriced { .. } => -32012,
+        }
+    }
+
+    /// The kind's fields as API error responses carry them, if it has any.
+    pub fn data(&self) -> Option<serde_json::Value> {
+        let data = match self {
+            Self::Other => return None,
+            Self::Reverted { return_data } => {
+                json!({"return_data": format!("0x{}", hex::encode(return_data))})
+            }
+            Self::RateLimited { retry_after } => {
+                json!({"retry_after_seconds": retry_after.as_secs_f64()})
+            }
+            Self::InsufficientFunds {
+                required,
+                available,
+            } => json!({"required": required, "available": available}),
+            Self::NonceMismatch { expected, actual } => {
+                json!({"expected": expected, "actual": actual})
+            }
+            Self::Underpriced { minimum, offered } => {
+                json!({"minimum": minimum, "offered": offered})
+            }
+        };
+        Some(data)
+    }
+
+    /// The kind an API error response with `code` and `data` describes, the inverse of
+    /// [`ErrorKind::code`] and [`ErrorKind::data`]. Codes this version does not know, and data
+    /// it cannot read, come back as [`ErrorKind::Other`].
+    pub fn from_code(code: i64, data: &serde_json::Value) -> Self {
+        let number = |name: &str| data[name].as_u64();
+        let kind = match code {
+            3 => data["return_data"]
+                .as_str()
+                .and_then(|hex| hex::decode(hex.trim_start_matches("0x")).ok())
+                .map(|return_data| Self::Reverted { return_data }),
+            -32005 => data["retry_after_seconds"]
+                .as_f64()
+                .and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok())
+                .map(|retry_after| Self::RateLimited { retry_after }),
+            -32010 => number("required")
+                .zip(number("available"))
+                .map(|(required, available)| Self::InsufficientFunds {
+                    required,
+                    available,
+                }),
+            -32011 => number("expected")
+                .zip(number("actual"))
+                .map(|(expected, actual)| Self::NonceMismatch { expected, actual }),
+            -32012 => number("minimum")
+                .zip(number("offered"))
+                .map(|(minimum, offered)| Self::Underpriced { minimum, offered }),
+            _ => None,
+        };
+        kind.unwrap_or_default()
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
     message: String,
     location: &'static Location<'static>,
+    kind: ErrorKind,
 }
 
 impl Error {
@@ -54,8 +157,27 @@ impl Error {
         Self {
             message: message.into(),
             location: Location::caller(),
+            kind: ErrorKind::Other,
+        }
+    }
+
+    #[track_caller]
+    pub fn with_kind(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            location: Location::caller(),
+            kind,
         }
     }
+
+    /// The message alone, without the location, for showing to API clients.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -72,6 +194,7 @@ impl From<Box<dyn std::error::Error>> for Error {
         Self {
             message: format!("dyn error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -82,6 +205,7 @@ impl From<regex::Error> for Error {
         Self {
             message: format!("regex error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -92,6 +216,7 @@ impl From<serde_yml::Error> for Error {
         Self {
             message: format!("yaml error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -102,6 +227,7 @@ impl From<serde_json::Error> for Error {
         Self {
             message: format!("json error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -112,6 +238,7 @@ impl From<std::io::Error> for Error {
         Self {
             message: format!("io error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -122,6 +249,18 @@ impl From<toml::de::Error> for Error {
         Self {
             message: format!("toml error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    #[track_caller]
+    fn from(error: toml::ser::Error) -> Self {
+        Self {
+            message: format!("toml error: {error:?}"),
+            location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -132,6 +271,7 @@ impl<T> From<std::sync::mpsc::SendError<T>> for Error {
         Self {
             message: format!("mpsc send error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -142,6 +282,7 @@ impl From<sqlite::Error> for Error {
         Self {
             message: format!("sqlite error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -152,6 +293,7 @@ impl From<String> for Error {
         Self {
             message: format!("error: {error}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -162,6 +304,7 @@ impl From<&str> for Error {
         Self {
             message: format!("error: {error}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -172,6 +315,7 @@ impl From<ParseIntError> for Error {
         Self {
             message: format!("parse int error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -183,6 +327,7 @@ impl From<anyhow::Error> for Error {
         Self {
             message: format!("anyhow error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
@@ -193,7 +338,53 @@ impl From<reqwest::Error> for Error {
         Self {
             message: format!("reqwest error: {error:?}"),
             location: Location::caller(),
+            kind: ErrorKind::Other,
         }
     }
 }
 */
+
+#[test]
+fn test_error_codes() {
+    use std::time::Duration;
+
+    let kinds = [
+        ErrorKind::Other,
+        ErrorKind::Reverted {
+            return_data: vec![1, 2],
+        },
+        ErrorKind::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        },
+        ErrorKind::InsufficientFunds {
+            required: 10,
+            available: 3,
+        },
+        ErrorKind::NonceMismatch {
+            expected: 4,
+            actual: 2,
+        },
+        ErrorKind::Underpriced {
+            minimum: 7,
+            offered: 5,
+        },
+    ];
+    let mut codes: Vec<i64> = kinds.iter().map(ErrorKind::code).collect();
+    for kind in &kinds {
+        let data = kind.data().unwrap_or_default();
+        assert_eq!(&ErrorKind::from_code(kind.code(), &data), kind);
+    }
+    assert_eq!(
+        ErrorKind::InsufficientFunds {
+            required: 10,
+            available: 3
+        }
+        .data(),
+        Some(json!({"required": 10, "available": 3}))
+    );
+    codes.sort_unstable();
+    codes.dedup();
+    assert_eq!(codes.len(), kinds.len());
+    assert_eq!(ErrorKind::from_code(-32010, &json!({})), ErrorKind::Other);
+    assert_eq!(ErrorKind::from_code(12345, &json!(null)), ErrorKind::Other);
+}
@@ -0,0 +1,221 @@
+//! Changes to the chain and the mempool, broadcast to the subsystems that follow them.
+//!
+//! Writers publish events only once the change they describe is committed, so a subscriber that
+//! reacts to an event by querying the node sees the change. Every subscriber sees the same
+//! sequence: writes are serialized, and each publishes its events in order before the next
+//! begins.
+//!
+//! A reorg is delivered removals first. One [`ChainEvent::Reorg`] carries every block leaving the
+//! canonical chain, newest first; the blocks of the new branch follow as
+//! [`ChainEvent::NewBlock`] events, oldest first. A consumer that undoes the reverted blocks in the
+//! order given and then applies the new ones tracks the canonical chain through reorgs of any
+//! depth. The node's own consumers follow this contract:
+//!
+//! - The mempool takes back the transactions of reverted blocks, and their status returns to
+//!   [`TransactionStatus::Pending`], before the new branch marks any of them included again.
+//! - Log subscriptions send the logs of reverted blocks again with `removed` set before the logs
+//!   of the new branch. See [`crate::logs`].
+//! - Receipts, indexed or not, are deleted with the reverted blocks' other effects.
+//!
+//! A finalized block is never reverted, so [`ChainEvent::FinalizedBlock`] is never retracted.
+//!
+//! [`TransactionStatus::Pending`]: crate::status::TransactionStatus::Pending
+use crate::block::{Block, Header};
+use crate::hash::Hash;
+use crate::status::TransactionStatus;
+use crate::Blockhead;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone)]
+pub(crate) enum ChainEvent {
+    /// `block` joined the canonical chain as its new head.
+    NewBlock(Block),
+    /// The canonical chain dropped the blocks above `ancestor`. `reverted` lists them newest
+    /// first. The blocks of the new branch, if any, follow as [`ChainEvent::NewBlock`] events.
+    Reorg {
+        ancestor: Hash,
+        reverted: Vec<Block>,
+    },
+    /// A transaction was admitted to the mempool.
+    NewPendingTx(Hash),
+    /// A transaction waited in the mempool longer than [`crate::MempoolConfig::max_age`] and was
+    /// dropped.
+    ExpiredPendingTx(Hash),
+    /// A checkpoint was finalized.
+    FinalizedBlock(Header),
+}
+
+/// Events buffered for each subscriber before the slowest starts missing them.
+const EVENT_CAPACITY: usize = 1024;
+
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<ChainEvent>,
+}
+
+impl EventBus {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn publish(&self, event: ChainEvent) {
+        // Nobody listening is not an error.
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Blockhead {
+    /// Follow every chain event from now on.
+    pub(crate) fn subscribe_chain_events(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Bring the mempool and transaction statuses up to date with committed chain `events`, then
+    /// broadcast them.
+    pub(crate) fn publish_chain_events(&self, events: Vec<ChainEvent>) {
+        let mut mempool = self.mempool.lock().unwrap();
+        let mut status = self.status.lock().unwrap();
+        for event in events {
+            match &event {
+                ChainEvent::NewBlock(block) => {
+                    for hash in mempool.remove_included(block) {
+                        let reason = "another transaction with its nonce was included".to_string();
+                        status.publish(hash, TransactionStatus::Dropped { reason });
+                    }
+                    status.publish_included(block);
+                }
+                ChainEvent::Reorg { reverted, .. } => {
+                    for hash in mempool.readmit(reverted, self.now_nanos()) {
+                        status.publish(hash, TransactionStatus::Pending);
+                    }
+                }
+                _ => {}
+            }
+            self.events.publish(event);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_chain_events() {
+    use crate::address::Address;
+    use crate::chain::side_block;
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let mut events = blockhead.subscribe_chain_events();
+    let transfer = testkit::transfer(validator, Address([8; 32]), 5, 0);
+    let hash = blockhead.send_transaction(transfer).await.unwrap();
+    let block1 = blockhead.produce_block().unwrap();
+    let checkpoint = blockhead.produce_block().unwrap();
+    let attestation = testkit::attestation(
+        checkpoint.hash,
+        1,
+        blockhead.config.chain_id,
+        blockhead.config.hash,
+    );
+    blockhead.send_transaction(attestation).await.unwrap();
+    let block3 = blockhead.produce_block().unwrap();
+
+    assert!(matches!(events.try_recv().unwrap(), ChainEvent::NewPendingTx(h) if h == hash));
+    assert!(matches!(events.try_recv().unwrap(), ChainEvent::NewBlock(b) if b.hash == block1.hash));
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        ChainEvent::NewBlock(b) if b.hash == checkpoint.hash
+    ));
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        ChainEvent::NewPendingTx(_)
+    ));
+    assert!(matches!(events.try_recv().unwrap(), ChainEvent::NewBlock(b) if b.hash == block3.hash));
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        ChainEvent::FinalizedBlock(h) if h.hash == checkpoint.hash
+    ));
+    assert!(events.try_recv().is_err());
+
+    // A side branch from the finalized checkpoint that overtakes the head reverts block 3.
+    let side3 = side_block(&blockhead, &checkpoint, block3.timestamp);
+    let side4 = side_block(&blockhead, &side3, block3.timestamp + 1);
+    blockhead.import_block(&side3).unwrap();
+    assert!(events.try_recv().is_err());
+    blockhead.import_block(&side4).unwrap();
+    match events.try_recv().unwrap() {
+        ChainEvent::Reorg { ancestor, reverted } => {
+            assert_eq!(ancestor, checkpoint.hash);
+            assert_eq!(reverted.len(), 1);
+            assert_eq!(reverted[0].header, block3.header);
+        }
+        event => panic!("expected a reorg, got {event:?}"),
+    }
+    assert!(matches!(events.try_recv().unwrap(), ChainEvent::NewBlock(b) if b.hash == side3.hash));
+    assert!(matches!(events.try_recv().unwrap(), ChainEvent::NewBlock(b) if b.hash == side4.hash));
+}
+
+#[tokio::test]
+async fn test_deep_reorg_events() {
+    use crate::address::Address;
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let recipient = Address([8; 32]);
+    let mut blocks = Vec::new();
+    let mut hashes = Vec::new();
+    for _ in 0..5 {
+        hashes.push(chain.transfer(chain.validator, recipient, 1).await);
+        blocks.push(chain.produce());
+    }
+    let mut events = chain.blockhead.subscribe_chain_events();
+    let mut statuses = chain.blockhead.subscribe_transaction_status();
+
+    // Six blocks off block 1 revert blocks 2 to 5.
+    let branch = chain.fork(&blocks[0], 6);
+    match events.try_recv().unwrap() {
+        ChainEvent::Reorg { ancestor, reverted } => {
+            assert_eq!(ancestor, blocks[0].hash);
+            let reverted: Vec<Hash> = reverted.iter().map(|block| block.hash).collect();
+            let expected: Vec<Hash> = blocks[1..].iter().rev().map(|block| block.hash).collect();
+            assert_eq!(reverted, expected);
+        }
+        event => panic!("expected a reorg, got {event:?}"),
+    }
+    for block in &branch {
+        assert!(
+            matches!(events.try_recv().unwrap(), ChainEvent::NewBlock(b) if b.hash == block.hash)
+        );
+    }
+    assert!(events.try_recv().is_err());
+
+    // The reverted transfers are pending again, in nonce order, and the state is block 1's.
+    let pending = chain.blockhead.mempool.lock().unwrap().pending();
+    let nonces: Vec<u64> = pending
+        .iter()
+        .map(|transaction| transaction.nonce)
+        .collect();
+    assert_eq!(nonces, vec![1, 2, 3, 4]);
+    for hash in &hashes[1..] {
+        let event = statuses.try_recv().unwrap();
+        assert_eq!(
+            (event.hash, event.status),
+            (*hash, TransactionStatus::Pending)
+        );
+    }
+    assert!(statuses.try_recv().is_err());
+    chain.assert_balance(recipient, 1);
+
+    // The new branch includes them again.
+    let block = chain.produce();
+    assert_eq!(block.body.transactions.len(), 4);
+    for hash in &hashes[1..] {
+        let event = statuses.try_recv().unwrap();
+        let included = TransactionStatus::Included { block: block.hash };
+        assert_eq!((event.hash, event.status), (*hash, included));
+    }
+    chain.assert_balance(recipient, 5);
+}
@@ -0,0 +1,430 @@
+use crate::address::Address;
+use crate::encoding::Reader;
+use crate::error::{Error, ErrorKind, Result};
+use crate::finality;
+use crate::genesis::ChainConfig;
+use crate::hash::Hash;
+use crate::multisig::{self, MultisigPolicy};
+use crate::staking::{self, DoubleSignEvidence};
+use crate::state::StateOverlay;
+use crate::token;
+use crate::trace::Trace;
+use crate::transaction::{Transaction, TransactionKind};
+#[cfg(feature = "vm")]
+use crate::vm;
+use crate::Log;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Why contract transactions and calls fail on nodes built without the VM.
+const NO_VM: &str = "contract code needs the vm feature, which this node was built without";
+
+/// What executing a transaction produced, for its receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExecutionOutcome {
+    /// False when contract code reverted or failed. The fee is still charged, but every other
+    /// effect of the transaction is rolled back.
+    pub status: bool,
+    pub gas_used: u64,
+    /// The address of the contract created by a deployment.
+    pub contract_address: Option<Address>,
+    /// The data returned by contract code, or its revert reason.
+    pub return_data: Vec<u8>,
+    /// The logs contract code emitted, empty when `status` is false.
+    pub logs: Vec<Log>,
+    /// Recorded when the node keeps traces of recent blocks.
+    pub trace: Option<Trace>,
+}
+
+impl ExecutionOutcome {
+    fn succeeded(gas_used: u64, contract_address: Option<Address>) -> Self {
+        Self {
+            status: true,
+            gas_used,
+            contract_address,
+            return_data: Vec::new(),
+            logs: Vec::new(),
+            trace: None,
+        }
+    }
+}
+
+/// Apply a single transaction, signed by `signers`, to `state`. An invalid transaction leaves
+/// `state` untouched; one whose contract execution fails is still applied, with `status` false.
+/// The signers are recovered by the caller, which can recover a whole block's at once with
+/// [`crate::verify::recover_signers`].
+pub(crate) fn execute_transaction(
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transaction: &Transaction,
+    signers: &[Address],
+) -> Result<ExecutionOutcome> {
+    if config.trace.retain_blocks == 0 {
+        return execute(state, config, transaction, signers, None);
+    }
+    let mut trace = Trace::default();
+    let mut outcome = execute(state, config, transaction, signers, Some(&mut trace))?;
+    outcome.trace = Some(trace);
+    Ok(outcome)
+}
+
+/// Apply a single transaction to `state` as [`execute_transaction`] does, recording a trace of
+/// its execution.
+pub(crate) fn trace_transaction(
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transaction: &Transaction,
+    signers: &[Address],
+) -> Result<Trace> {
+    let mut trace = Trace::default();
+    execute(state, config, transaction, signers, Some(&mut trace))?;
+    Ok(trace)
+}
+
+fn execute(
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transaction: &Transaction,
+    signers: &[Address],
+    mut trace: Option<&mut Trace>,
+) -> Result<ExecutionOutcome> {
+    let intrinsic_gas = config.gas.check(transaction)?;
+    let max_fee = transaction
+        .gas_limit
+        .checked_mul(transaction.gas_price)
+        .ok_or_else(|| {
+            Error::new(format!(
+                "fee for {} gas at {} overflows",
+                transaction.gas_limit, transaction.gas_price
+            ))
+        })?;
+    multisig::authorize(state, config.require_signatures, transaction, signers)?;
+    let snapshot = state.snapshot();
+    let result = charge_sender(state, transaction, max_fee).and_then(|()| {
+        apply(
+            state,
+            config,
+            transaction,
+            intrinsic_gas,
+            trace.as_deref_mut(),
+        )
+    });
+    match result {
+        Ok(outcome) => {
+            // `gas_used <= gas_limit`, so this cannot overflow given `max_fee` did not.
+            let refund = (transaction.gas_limit - outcome.gas_used) * transaction.gas_price;
+            state.account_mut(transaction.from_address)?.balance += refund;
+            if let Some(trace) = trace {
+                trace.status = outcome.status;
+                trace.gas_used = outcome.gas_used;
+                trace.return_data = outcome.return_data.clone();
+            }
+            Ok(outcome)
+        }
+        Err(error) => {
+            state.restore(snapshot);
+            Err(error)
+        }
+    }
+}
+
+/// Check the sender's nonce, bump it and take the fee.
+fn charge_sender(state: &mut StateOverlay, transaction: &Transaction, fee: u64) -> Result<()> {
+    let from = transaction.from_address;
+    let sender = state.account_mut(from)?;
+    if sender.nonce != transaction.nonce {
+        return Err(Error::with_kind(
+            ErrorKind::NonceMismatch {
+                expected: sender.nonce,
+                actual: transaction.nonce,
+            },
+            format!(
+                "{from} sent nonce {} but the account nonce is {}",
+                transaction.nonce, sender.nonce
+            ),
+        ));
+    }
+    sender.nonce += 1;
+    if sender.balance < fee {
+        return Err(Error::with_kind(
+            ErrorKind::InsufficientFunds {
+                required: fee,
+                available: sender.balance,
+            },
+            format!(
+                "{from} cannot pay a fee of {fee} with a balance of {}",
+                sender.balance
+            ),
+        ));
+    }
+    sender.balance -= fee;
+    Ok(())
+}
+
+/// Apply the kind-specific effects of `transaction`, which has already paid for `gas_limit`.
+fn apply(
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transaction: &Transaction,
+    intrinsic_gas: u64,
+    trace: Option<&mut Trace>,
+) -> Result<ExecutionOutcome> {
+    let from = transaction.from_address;
+    match transaction.kind {
+        TransactionKind::Transfer => {
+            let (to_address, created) = match transaction.to_address {
+                Some(to_address) => (to_address, None),
+                None => {
+                    if cfg!(not(feature = "vm")) {
+                        return Err(Error::new(NO_VM));
+                    }
+                    let contract = Address::for_contract(from, transaction.nonce, config.hash);
+                    if state.code(contract)?.is_some() {
+                        return Err(Error::new(format!("contract {contract} already exists")));
+                    }
+                    state.set_code(contract, transaction.data.to_vec());
+                    (contract, Some(contract))
+                }
+            };
+            let snapshot = state.snapshot();
+            let sender = state.account_mut(from)?;
+            if sender.balance < transaction.value {
+                return Err(Error::new(format!(
+                    "{from} cannot transfer {} with a balance of {}",
+                    transaction.value, sender.balance
+                )));
+            }
+            sender.balance -= transaction.value;
+            state.account_mut(to_address)?.balance += transaction.value;
+            if created.is_some() {
+                return Ok(ExecutionOutcome::succeeded(intrinsic_gas, created));
+            }
+            #[cfg(feature = "vm")]
+            {
+                let context = vm::Context {
+                    caller: from,
+                    address: to_address,
+                    value: transaction.value,
+                    data: transaction.data.clone(),
+                    gas_limit: transaction.gas_limit - intrinsic_gas,
+                    algorithm: config.hash,
+                };
+                let result = match trace {
+                    Some(trace) => vm::call_traced(state, &context, trace)?,
+                    None => vm::call(state, &context)?,
+                };
+                let (status, return_data) = match result.outcome {
+                    vm::Outcome::Return(data) => (true, data),
+                    vm::Outcome::Revert(data) => (false, data),
+                    vm::Outcome::Failure(reason) => {
+                        log::debug!("contract {to_address} failed: {reason}");
+                        (false, Vec::new())
+                    }
+                };
+                if !status {
+                    state.restore(snapshot);
+                }
+                return Ok(ExecutionOutcome {
+                    status,
+                    gas_used: intrinsic_gas + result.gas_used,
+                    contract_address: None,
+                    return_data,
+                    logs: result.logs,
+                    trace: None,
+                });
+            }
+            #[cfg(not(feature = "vm"))]
+            {
+                let _ = (snapshot, trace);
+                if state.code(to_address)?.is_some() {
+                    return Err(Error::new(NO_VM));
+                }
+                return Ok(ExecutionOutcome::succeeded(intrinsic_gas, None));
+            }
+        }
+        TransactionKind::Stake => staking::apply_stake(state, from, transaction.value)?,
+        TransactionKind::Unstake => staking::apply_unstake(state, from, transaction.value)?,
+        TransactionKind::ReportDoubleSign => {
+            let evidence = DoubleSignEvidence::decode(&transaction.data, config.hash)?;
+            staking::apply_double_sign_report(
+                state,
+                config.chain_id,
+                config.hash,
+                from,
+                &evidence,
+            )?;
+        }
+        TransactionKind::Attest => {
+            let checkpoint: [u8; 32] = transaction.data[..]
+                .try_into()
+                .map_err(|_| Error::new("attestation data must be a 32-byte checkpoint hash"))?;
+            finality::apply_attestation(
+                state,
+                &config.staking,
+                &config.finality,
+                from,
+                Hash(checkpoint),
+            )?;
+        }
+        TransactionKind::CreateMultisig => {
+            let policy = MultisigPolicy::decode(&transaction.data)?;
+            let address = policy.address(config.hash);
+            if state.multisig(address)?.is_some() {
+                return Err(Error::new(format!("multisig {address} already exists")));
+            }
+            state.set_multisig(address, policy);
+            let sender = state.account_mut(from)?;
+            if sender.balance < transaction.value {
+                return Err(Error::new(format!(
+                    "{from} cannot endow a multisig with {} from a balance of {}",
+                    transaction.value, sender.balance
+                )));
+            }
+            sender.balance -= transaction.value;
+            state.account_mut(address)?.balance += transaction.value;
+        }
+        TransactionKind::CreateToken => {
+            if transaction.value != 0 {
+                return Err(Error::new("token creation cannot carry value"));
+            }
+            let token = token::apply_create(
+                state,
+                from,
+                transaction.nonce,
+                &transaction.data,
+                config.hash,
+            )?;
+            return Ok(ExecutionOutcome::succeeded(intrinsic_gas, Some(token)));
+        }
+        TransactionKind::TokenTransfer
+        | TransactionKind::TokenApprove
+        | TransactionKind::TokenTransferFrom => {
+            let to = transaction
+                .to_address
+                .ok_or_else(|| Error::new("token transactions need a to_address"))?;
+            let mut reader = Reader::new(&transaction.data);
+            let token = Address::from(reader.bytes32()?);
+            let value = transaction.value;
+            match transaction.kind {
+                TransactionKind::TokenTransfer => {
+                    reader.finish()?;
+                    token::apply_transfer(state, token, from, to, value)?;
+                }
+                TransactionKind::TokenApprove => {
+                    reader.finish()?;
+                    token::apply_approve(state, token, from, to, value)?;
+                }
+                _ => {
+                    let owner = Address::from(reader.bytes32()?);
+                    reader.finish()?;
+                    token::apply_transfer_from(state, token, from, owner, to, value)?;
+                }
+            }
+        }
+    }
+    Ok(ExecutionOutcome::succeeded(intrinsic_gas, None))
+}
+
+/// Caller-supplied settings for a read-only call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallOverrides {
+    /// The caller, the zero address by default.
+    pub from: Option<Address>,
+    /// Funds moved from `from` to the callee before the call, zero by default.
+    pub value: Option<u64>,
+    /// The gas available, [`GasConfig::call_gas_limit`](crate::gas::GasConfig) by default.
+    pub gas: Option<u64>,
+    /// State assumed for the call only.
+    pub state: StateOverrides,
+}
+
+/// Values to assume for an account's state during one call or simulation, in place of its
+/// committed ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountOverride {
+    pub balance: Option<u64>,
+    pub nonce: Option<u64>,
+    pub code: Option<Vec<u8>>,
+    /// Storage slots to set, leaving the others as they are.
+    pub storage: BTreeMap<u64, u64>,
+}
+
+/// Overrides by account. See [`AccountOverride`].
+pub type StateOverrides = BTreeMap<Address, AccountOverride>;
+
+/// Write `overrides` into `state`.
+pub(crate) fn apply_overrides(state: &mut StateOverlay, overrides: &StateOverrides) -> Result<()> {
+    for (&address, account) in overrides {
+        let committed = state.account_mut(address)?;
+        if let Some(balance) = account.balance {
+            committed.balance = balance;
+        }
+        if let Some(nonce) = account.nonce {
+            committed.nonce = nonce;
+        }
+        if let Some(code) = &account.code {
+            state.set_code(address, code.clone());
+        }
+        for (&key, &value) in &account.storage {
+            state.set_storage(address, key, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the contract or precompile at `to` with `data` against the committed state, discarding
+/// any writes. A revert is returned as an [`ErrorKind::Reverted`] error carrying the revert data.
+pub(crate) fn call(
+    connection: &sqlite::Connection,
+    config: &ChainConfig,
+    to: Address,
+    data: Vec<u8>,
+    overrides: &CallOverrides,
+) -> Result<Vec<u8>> {
+    let mut state = StateOverlay::new(connection);
+    apply_overrides(&mut state, &overrides.state)?;
+    let from = overrides.from.unwrap_or(Address::zero());
+    let value = overrides.value.unwrap_or(0);
+    if value > 0 {
+        let caller = state.account_mut(from)?;
+        if caller.balance < value {
+            return Err(Error::new(format!(
+                "{from} cannot send {value} with a balance of {}",
+                caller.balance
+            )));
+        }
+        caller.balance -= value;
+        state.account_mut(to)?.balance += value;
+    }
+    #[cfg(not(feature = "vm"))]
+    {
+        let _ = (config, data);
+        match state.code(to)? {
+            Some(_) => Err(Error::new(NO_VM)),
+            None => Ok(Vec::new()),
+        }
+    }
+    #[cfg(feature = "vm")]
+    {
+        let context = vm::Context {
+            caller: from,
+            address: to,
+            value,
+            data: data.into(),
+            gas_limit: overrides.gas.unwrap_or(config.gas.call_gas_limit),
+            algorithm: config.hash,
+        };
+        match vm::call(&mut state, &context)?.outcome {
+            vm::Outcome::Return(data) => Ok(data),
+            vm::Outcome::Revert(return_data) => Err(Error::with_kind(
+                ErrorKind::Reverted { return_data },
+                format!("call to {to} reverted"),
+            )),
+            vm::Outcome::Failure(reason) => {
+                Err(Error::new(format!("call to {to} failed: {reason}")))
+            }
+        }
+    }
+}
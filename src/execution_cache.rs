@@ -0,0 +1,178 @@
+//! Results of recently executed blocks, kept so that executing a block again does not repeat the
+//! work.
+//!
+//! Each block the node produces or imports leaves its state changes and transaction outcomes
+//! here, and a reorg back onto its branch commits them again without executing the block. Tracing
+//! a transaction whose trace was not kept re-executes its block up to it, tracing each
+//! transaction on the way, and leaves those traces here for the others. A block's hash commits
+//! to its parent, whose state it was executed on, so its results never go stale.
+//!
+//! The cache holds up to [`MAX_BYTES`] of results, by an estimate of their size in memory, and
+//! drops the oldest first.
+use crate::execution::ExecutionOutcome;
+use crate::hash::Hash;
+use crate::state::OverlayContents;
+use crate::trace::Trace;
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+
+/// How many bytes of results the cache holds at most.
+const MAX_BYTES: usize = 64 << 20;
+
+/// What executing a block left to commit: the state overlay, with the proposer's reward, and
+/// each transaction's outcome.
+pub(crate) struct Applied {
+    pub state: OverlayContents,
+    pub outcomes: Vec<ExecutionOutcome>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Applied,
+    Traces,
+}
+
+#[derive(Default)]
+pub(crate) struct ExecutionCache {
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    applied: HashMap<Hash, Arc<Applied>>,
+    /// The traces of a block's first transactions, in order.
+    traces: HashMap<Hash, Arc<Vec<Trace>>>,
+    /// Every result's block, kind and size, oldest first.
+    order: VecDeque<(Hash, Kind, usize)>,
+    bytes: usize,
+}
+
+impl Entries {
+    fn insert(&mut self, hash: Hash, kind: Kind, size: usize) {
+        if let Some(index) = self
+            .order
+            .iter()
+            .position(|&(h, k, _)| h == hash && k == kind)
+        {
+            let (_, _, replaced) = self.order.remove(index).unwrap();
+            self.bytes -= replaced;
+        }
+        self.order.push_back((hash, kind, size));
+        self.bytes += size;
+        while self.bytes > MAX_BYTES {
+            let (hash, kind, size) = self.order.pop_front().unwrap();
+            match kind {
+                Kind::Applied => self.applied.remove(&hash).map(drop),
+                Kind::Traces => self.traces.remove(&hash).map(drop),
+            };
+            self.bytes -= size;
+        }
+    }
+}
+
+impl ExecutionCache {
+    pub(crate) fn applied(&self, hash: Hash) -> Option<Arc<Applied>> {
+        self.entries.lock().unwrap().applied.get(&hash).cloned()
+    }
+
+    pub(crate) fn insert_applied(&self, hash: Hash, applied: Applied) {
+        let size =
+            applied.state.size() + (applied.outcomes.iter()).map(outcome_size).sum::<usize>();
+        let mut entries = self.entries.lock().unwrap();
+        entries.applied.insert(hash, Arc::new(applied));
+        entries.insert(hash, Kind::Applied, size);
+    }
+
+    /// The traces of the first transactions of block `hash`, as many as were traced.
+    pub(crate) fn traces(&self, hash: Hash) -> Option<Arc<Vec<Trace>>> {
+        self.entries.lock().unwrap().traces.get(&hash).cloned()
+    }
+
+    pub(crate) fn insert_traces(&self, hash: Hash, traces: Vec<Trace>) {
+        let size = traces.iter().map(trace_size).sum();
+        let mut entries = self.entries.lock().unwrap();
+        entries.traces.insert(hash, Arc::new(traces));
+        entries.insert(hash, Kind::Traces, size);
+    }
+}
+
+fn outcome_size(outcome: &ExecutionOutcome) -> usize {
+    size_of::<ExecutionOutcome>()
+        + outcome.return_data.len()
+        + (outcome.logs.iter())
+            .map(|log| size_of::<crate::Log>() + log.topics.len() * 32 + log.data.len())
+            .sum::<usize>()
+        + outcome.trace.as_ref().map_or(0, trace_size)
+}
+
+fn trace_size(trace: &Trace) -> usize {
+    size_of::<Trace>()
+        + trace.return_data.len()
+        + (trace.steps.iter())
+            .map(|step| size_of_val(step) + step.op.len() + step.stack.len() * 8)
+            .sum::<usize>()
+        + size_of_val(trace.storage.as_slice())
+}
+
+#[test]
+fn test_execution_cache_eviction() {
+    let cache = ExecutionCache::default();
+    let trace = Trace {
+        return_data: vec![0; 1 << 20],
+        ..Default::default()
+    };
+    for i in 0..100u8 {
+        cache.insert_traces(Hash::from([i; 32]), vec![trace.clone()]);
+    }
+    let entries = cache.entries.lock().unwrap();
+    assert!(entries.bytes <= MAX_BYTES);
+    assert_eq!(entries.traces.len(), entries.order.len());
+    assert!(entries.traces.len() < 64);
+    drop(entries);
+    assert!(cache.traces(Hash::from([0; 32])).is_none());
+    assert!(cache.traces(Hash::from([99; 32])).is_some());
+    cache.insert_traces(Hash::from([99; 32]), Vec::new());
+    assert!(cache.traces(Hash::from([99; 32])).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_reorg_reapplies_cached_execution() {
+    use crate::address::Address;
+    use crate::chain::{side_block, staked_chain};
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = staked_chain(32);
+    let genesis = blockhead.head().unwrap();
+    let recipient = Address([8; 32]);
+    let transfer = testkit::transfer(validator, recipient, 5, 0);
+    let hash = blockhead.send_transaction(transfer).await.unwrap();
+    let block1 = blockhead.produce_block().unwrap();
+    assert!(blockhead.execution_cache.applied(block1.hash).is_some());
+
+    let side1 = side_block(&blockhead, &genesis, 11);
+    let side2 = side_block(&blockhead, &side1, 12);
+    blockhead.import_block(&side1).unwrap();
+    blockhead.import_block(&side2).unwrap();
+    assert_eq!(blockhead.head().unwrap().hash, side2.hash);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 0);
+    assert!(blockhead.execution_cache.applied(side2.hash).is_some());
+
+    // Reorging back commits block 1 again from the cache.
+    let block2 = side_block(&blockhead, &block1, 21);
+    let block3 = side_block(&blockhead, &block2, 22);
+    blockhead.import_block(&block2).unwrap();
+    blockhead.import_block(&block3).unwrap();
+    assert_eq!(blockhead.head().unwrap().hash, block3.hash);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 5);
+    let receipt = blockhead.get_transaction_receipt(hash).await.unwrap();
+    assert_eq!(receipt.unwrap().block_hash, block1.hash);
+
+    assert!(blockhead.execution_cache.traces(block1.hash).is_none());
+    let trace = blockhead.debug_trace_transaction(hash).await.unwrap();
+    assert_eq!(
+        blockhead.execution_cache.traces(block1.hash).unwrap()[..],
+        [trace.unwrap()]
+    );
+}
@@ -0,0 +1,40 @@
+//! A read-only block explorer for dev networks, served at `/explorer` by [`crate::http`] when
+//! [`HttpConfig::explorer`] is on.
+//!
+//! The page is static HTML whose script fetches everything it shows from `/graphql`: the latest
+//! blocks, a block with its transactions, a transaction with its receipt, and an address with its
+//! balance and recent transactions. It shows nothing an API client could not read.
+//!
+//! [`HttpConfig::explorer`]: crate::http::HttpConfig::explorer
+use crate::http::Response;
+
+const PAGE: &str = include_str!("explorer.html");
+
+/// Answer `/explorer` with the page.
+pub(crate) fn handle() -> Response {
+    Response {
+        status: 200,
+        content_type: "text/html; charset=utf-8",
+        headers: Vec::new(),
+        body: PAGE.as_bytes().to_vec(),
+    }
+}
+
+#[tokio::test]
+async fn test_explorer() {
+    use crate::genesis::ChainConfig;
+    use crate::http::{send_raw, serve_locally};
+    use crate::testkit::TestChain;
+    use std::sync::Arc;
+
+    let address = serve_locally(Arc::new(TestChain::new().blockhead)).await;
+    let response = send_raw(address, "GET /explorer HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404 "));
+
+    let mut config = ChainConfig::default();
+    config.http.explorer = true;
+    let address = serve_locally(Arc::new(TestChain::with_config(config).blockhead)).await;
+    let response = send_raw(address, "GET /explorer HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/html"));
+    assert!(response.ends_with(PAGE));
+}
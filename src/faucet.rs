@@ -0,0 +1,195 @@
+//! A faucet for dev and test networks, which sends a fixed amount to whoever asks, at most once
+//! per [`FaucetConfig::interval`] for each address. It is served at
+//! `POST /faucet?address=<address>` by [`crate::http`], which `blockhead faucet <endpoint>
+//! <address>` calls.
+//!
+//! The faucet pays from an account of the public dev wallet (see [`spec::dev_wallet`]), which the
+//! presets fund, so it needs no key of its own. Anyone can spend from that account anyway, so the
+//! faucet is only for chains whose funds are worthless: data directories made with
+//! `blockhead init --chain dev` or `--chain test` have it on, and nothing else does unless asked.
+use crate::address::Address;
+use crate::error::{Error, ErrorKind, Result};
+use crate::hash::Hash;
+#[cfg(feature = "http")]
+use crate::http::{Request, Response};
+use crate::spec;
+use crate::transaction::{Transaction, TransactionKind};
+use crate::{Blockchain, Blockhead};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http")]
+use serde_json::json;
+use std::collections::HashMap;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaucetConfig {
+    pub enabled: bool,
+    /// The index of the dev wallet account that pays.
+    pub account: u32,
+    /// What each request sends.
+    pub amount: u64,
+    /// How long an address waits after being paid before it can be paid again.
+    pub interval: Duration,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            account: 0,
+            amount: 1_000_000_000,
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// When the faucet last paid each address, in clock nanoseconds.
+#[derive(Debug, Default)]
+pub(crate) struct FaucetPayments {
+    paid: Mutex<HashMap<Address, u64>>,
+}
+
+impl Blockhead {
+    /// Send [`FaucetConfig::amount`] to `to` from the faucet account, returning the transfer's
+    /// hash. Fails with [`ErrorKind::RateLimited`] if `to` was paid less than
+    /// [`FaucetConfig::interval`] ago.
+    pub(crate) async fn faucet(&self, to: Address) -> Result<Hash> {
+        let config = &self.config.faucet;
+        if !config.enabled {
+            return Err(Error::new("the faucet is off"));
+        }
+        let interval = config.interval.as_nanos() as u64;
+        let now = self.now_nanos();
+        let previous = {
+            let mut paid = self.faucet_payments.paid.lock().unwrap();
+            // Addresses that may be paid again need not be remembered.
+            paid.retain(|_, at| now.saturating_sub(*at) < interval);
+            if let Some(at) = paid.get(&to) {
+                let retry_after = Duration::from_nanos(at + interval - now);
+                return Err(Error::with_kind(
+                    ErrorKind::RateLimited { retry_after },
+                    format!(
+                        "{to} was paid recently; try again in {}s",
+                        retry_after.as_secs().max(1)
+                    ),
+                ));
+            }
+            paid.insert(to, now)
+        };
+        let result = self.send_faucet_transfer(to).await;
+        if result.is_err() {
+            let mut paid = self.faucet_payments.paid.lock().unwrap();
+            match previous {
+                Some(at) => paid.insert(to, at),
+                None => paid.remove(&to),
+            };
+        }
+        result
+    }
+
+    async fn send_faucet_transfer(&self, to: Address) -> Result<Hash> {
+        let account = spec::dev_wallet().account(self.config.faucet.account)?;
+        let from = account.address(self.config.hash);
+        let mut transaction = Transaction {
+            kind: TransactionKind::Transfer,
+            from_address: from,
+            to_address: Some(to),
+            value: self.config.faucet.amount,
+            data: Bytes::new(),
+            gas_limit: self.config.gas.intrinsic_gas(0),
+            gas_price: self.estimate_fee().await?.normal,
+            nonce: self.get_pending_nonce(from).await?,
+            signatures: Vec::new(),
+        };
+        transaction.sign(&account, self.config.chain_id, self.config.hash)?;
+        self.send_transaction(transaction).await
+    }
+}
+
+/// Answer `POST /faucet?address=<address>` with the hash of the transfer, or `429` with
+/// `Retry-After` if the address was paid too recently. Only routed while the faucet is on.
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, request: &Request) -> Response {
+    let address = match request
+        .query_param("address")
+        .map(crate::hash::decode_hex32)
+    {
+        Some(Ok(address)) => Address::from(address),
+        Some(Err(error)) => return Response::error(400, format!("bad address: {error}")),
+        None => return Response::error(400, "missing address"),
+    };
+    match blockhead.faucet(address).await {
+        Ok(hash) => Response::json(
+            200,
+            &json!({
+                "hash": hash.to_string(),
+                "amount": blockhead.config.faucet.amount,
+            }),
+        ),
+        Err(error) => match error.kind() {
+            ErrorKind::RateLimited { retry_after } => {
+                let mut response = Response::from_error(429, &error);
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                response.headers.push(("Retry-After", seconds.to_string()));
+                response
+            }
+            _ => Response::from_error(400, &error),
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_faucet() {
+    let mut genesis = spec::load("dev").unwrap();
+    genesis.config.faucet = FaucetConfig {
+        enabled: true,
+        amount: 7,
+        interval: Duration::from_secs(60),
+        ..Default::default()
+    };
+    let clock = std::sync::Arc::new(crate::clock::ManualClock::new(0));
+    let blockhead = spec::open_dev(":memory:", genesis)
+        .unwrap()
+        .with_clock(clock.clone());
+    let alice = Address([8; 32]);
+    let bob = Address([9; 32]);
+
+    blockhead.faucet(alice).await.unwrap();
+    blockhead.faucet(bob).await.unwrap();
+    let error = blockhead.faucet(alice).await.unwrap_err();
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::RateLimited {
+            retry_after: Duration::from_secs(60)
+        }
+    );
+    clock.advance(Duration::from_secs(60));
+    blockhead.faucet(alice).await.unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(alice).await.unwrap(), 14);
+    assert_eq!(blockhead.get_balance(bob).await.unwrap(), 7);
+
+    #[cfg(feature = "http")]
+    {
+        use crate::http::{send, send_raw, serve_locally};
+
+        let address = serve_locally(Arc::new(blockhead)).await;
+        let request = |to: Address| format!("POST /faucet?address={to} HTTP/1.1\r\n\r\n");
+        let (status, body) = send(address, &request(Address([10; 32]))).await;
+        assert_eq!((status, &body["amount"]), (200, &json!(7)));
+        let response = send_raw(address, &request(Address([10; 32]))).await;
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(response.contains("Retry-After: 60\r\n"));
+        let (status, _) = send(address, "POST /faucet?address=0x12 HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 400);
+
+        let address = serve_locally(Arc::new(Blockhead::new(":memory:").unwrap())).await;
+        let (status, _) = send(address, &request(alice)).await;
+        assert_eq!(status, 404);
+    }
+}
@@ -0,0 +1,146 @@
+//! Gas price suggestions drawn from the prices paid in recent canonical blocks, served at
+//! `/fees` by [`crate::http`].
+use crate::db;
+use crate::error::Result;
+#[cfg(feature = "http")]
+use crate::http::Response;
+#[cfg(feature = "http")]
+use crate::Blockchain;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http")]
+use serde_json::json;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeeConfig {
+    /// How many of the most recent canonical blocks to sample.
+    pub history_blocks: u64,
+    /// The percentiles of the sampled gas prices suggested as slow, normal and fast.
+    pub percentiles: [u64; 3],
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            history_blocks: 20,
+            percentiles: [25, 50, 90],
+        }
+    }
+}
+
+/// Suggested gas prices, each the price paid by that percentile of recent transactions, but no
+/// less than the node admits. See [`crate::MempoolConfig::min_gas_price`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    pub slow: u64,
+    pub normal: u64,
+    pub fast: u64,
+}
+
+/// The nearest-rank `percentile` of ascending `prices`.
+fn percentile(prices: &[u64], percentile: u64) -> u64 {
+    if prices.is_empty() {
+        return 0;
+    }
+    let rank = (prices.len() - 1) * percentile.min(100) as usize / 100;
+    prices[rank]
+}
+
+impl Blockhead {
+    pub(crate) fn fee_estimate(&self) -> Result<FeeEstimate> {
+        let config = &self.config.fee;
+        let from = (self.head()?.number + 1).saturating_sub(config.history_blocks);
+        let prices = db::read_gas_prices_since(&self.reader(), from)?;
+        let floor = self.mempool.lock().unwrap().min_gas_price();
+        let [slow, normal, fast] = config
+            .percentiles
+            .map(|p| percentile(&prices, p).max(floor));
+        Ok(FeeEstimate { slow, normal, fast })
+    }
+}
+
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>) -> Response {
+    match blockhead.estimate_fee().await {
+        Ok(estimate) => Response::json(
+            200,
+            &json!({"slow": estimate.slow, "normal": estimate.normal, "fast": estimate.fast}),
+        ),
+        Err(error) => Response::from_error(500, &error),
+    }
+}
+
+#[test]
+fn test_percentile() {
+    assert_eq!(percentile(&[], 50), 0);
+    assert_eq!(percentile(&[7], 90), 7);
+    let prices: Vec<u64> = (1..=10).collect();
+    assert_eq!(percentile(&prices, 0), 1);
+    assert_eq!(percentile(&prices, 50), 5);
+    assert_eq!(percentile(&prices, 90), 9);
+    assert_eq!(percentile(&prices, 100), 10);
+}
+
+#[tokio::test]
+async fn test_estimate_fee() {
+    use crate::address::Address;
+    use crate::genesis::Genesis;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let sender = testkit::validator();
+    let genesis = Genesis {
+        alloc: vec![(sender, 10_000_000)],
+        validators: vec![(sender, 100)],
+        config: testkit::config(),
+        ..Default::default()
+    };
+    let blockhead = Blockhead::with_genesis(":memory:", genesis)
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap();
+    assert_eq!(
+        blockhead.estimate_fee().await.unwrap(),
+        FeeEstimate::default()
+    );
+    assert_eq!(blockhead.gas_price().await, 0);
+    // Without recent transactions, the price is the least the mempool admits.
+    let default = Blockhead::new(":memory:").unwrap();
+    assert_eq!(
+        default.gas_price().await,
+        default.config.mempool.min_gas_price
+    );
+    for nonce in 0..10 {
+        let transfer = Transaction {
+            to_address: Some(Address([8; 32])),
+            value: 1,
+            gas_price: nonce + 1,
+            ..testkit::transaction(Default::default(), sender, nonce)
+        };
+        blockhead.send_transaction(transfer).await.unwrap();
+        if nonce % 2 == 1 {
+            blockhead.produce_block().unwrap();
+        }
+    }
+    assert_eq!(
+        blockhead.estimate_fee().await.unwrap(),
+        FeeEstimate {
+            slow: 3,
+            normal: 5,
+            fast: 9
+        }
+    );
+    assert_eq!(blockhead.gas_price().await, 5);
+
+    #[cfg(feature = "http")]
+    {
+        let address = crate::http::serve_locally(Arc::new(blockhead)).await;
+        let (status, body) = crate::http::send(address, "GET /fees HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"slow": 3, "normal": 5, "fast": 9}));
+    }
+}
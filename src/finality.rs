@@ -0,0 +1,151 @@
+//! Checkpoint finality.
+//!
+//! Every [`FinalityConfig::checkpoint_interval`] blocks the canonical block at that height is a
+//! checkpoint. Validators of the checkpoint's epoch attest to it with
+//! [`crate::transaction::TransactionKind::Attest`] transactions, and once attestations covering
+//! at least two thirds of the epoch's stake are included on chain the checkpoint is finalized.
+//! Finality is sticky: the fork choice never reverts a finalized block, even if the attestations
+//! that finalized it are later reorganized away.
+use crate::address::Address;
+use crate::block::Block;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::hash::Hash;
+use crate::staking::StakingConfig;
+use crate::state::StateOverlay;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FinalityConfig {
+    pub checkpoint_interval: u64,
+}
+
+impl Default for FinalityConfig {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval: 32,
+        }
+    }
+}
+
+impl FinalityConfig {
+    pub(crate) fn is_checkpoint(&self, number: u64) -> bool {
+        number.is_multiple_of(self.checkpoint_interval)
+    }
+}
+
+/// Validate an attestation to `checkpoint` by `validator` and queue it on `state`. The
+/// attestation's signature has been checked already: see [`crate::multisig::authorize`].
+pub(crate) fn apply_attestation(
+    state: &mut StateOverlay,
+    staking: &StakingConfig,
+    finality: &FinalityConfig,
+    validator: Address,
+    checkpoint: Hash,
+) -> Result<()> {
+    let connection = state.connection();
+    let block = db::read_block(connection, checkpoint)?
+        .ok_or_else(|| Error::new(format!("attestation to unknown block {checkpoint}")))?;
+    if db::read_block_is_canonical(connection, checkpoint)? != Some(true) {
+        return Err(Error::new(format!(
+            "attestation to non-canonical block {checkpoint}"
+        )));
+    }
+    if !finality.is_checkpoint(block.number) {
+        return Err(Error::new(format!(
+            "block {checkpoint} at height {} is not a checkpoint",
+            block.number
+        )));
+    }
+    let finalized = db::read_finalized(connection)?;
+    if finalized.is_some_and(|finalized| finalized.number >= block.number) {
+        return Err(Error::new(format!(
+            "checkpoint {checkpoint} is already final"
+        )));
+    }
+    let epoch = staking.epoch_of(block.number);
+    let validator_set = db::read_validator_set(connection, epoch)?
+        .ok_or_else(|| Error::new(format!("no validator set for epoch {epoch}")))?;
+    if !validator_set.contains(validator) {
+        return Err(Error::new(format!(
+            "{validator} is not a validator in epoch {epoch}"
+        )));
+    }
+    if db::read_attestations(connection, checkpoint)?.contains(&validator)
+        || state.attestations.contains(&(checkpoint, validator))
+    {
+        return Err(Error::new(format!(
+            "{validator} already attested to {checkpoint}"
+        )));
+    }
+    state.attestations.push((checkpoint, validator));
+    Ok(())
+}
+
+/// Finalize any checkpoint that has reached a two-thirds supermajority of attested stake. Returns
+/// the newly finalized checkpoint, if any.
+pub(crate) fn update_finalized(
+    connection: &sqlite::Connection,
+    staking: &StakingConfig,
+    checkpoints: impl IntoIterator<Item = Hash>,
+) -> Result<Option<Block>> {
+    let mut newly_finalized: Option<Block> = None;
+    for checkpoint in checkpoints {
+        let Some(block) = db::read_block(connection, checkpoint)? else {
+            continue;
+        };
+        if !has_supermajority(connection, staking, &block)? {
+            continue;
+        }
+        let current = db::read_finalized(connection)?;
+        if current.is_some_and(|current| current.number >= block.number) {
+            continue;
+        }
+        log::info!(
+            "finalized checkpoint {} at height {}",
+            block.hash,
+            block.number
+        );
+        db::write_finalized(connection, &block)?;
+        newly_finalized = Some(block);
+    }
+    Ok(newly_finalized)
+}
+
+/// Whether the attestations to `checkpoint` on chain cover two thirds of its epoch's stake.
+fn has_supermajority(
+    connection: &sqlite::Connection,
+    staking: &StakingConfig,
+    checkpoint: &Block,
+) -> Result<bool> {
+    let Some(validator_set) =
+        db::read_validator_set(connection, staking.epoch_of(checkpoint.number))?
+    else {
+        return Ok(false);
+    };
+    let attesters = db::read_attestations(connection, checkpoint.hash)?;
+    let attested: u128 = validator_set
+        .validators
+        .iter()
+        .filter(|v| attesters.contains(&v.address))
+        .map(|v| v.stake as u128)
+        .sum();
+    Ok(attested * 3 >= validator_set.total_stake() * 2)
+}
+
+/// Forget the finalization of checkpoints whose attestations are no longer on chain. Only for
+/// rebuilding the chain as it stood before the blocks carrying them were applied; the fork
+/// choice never does this.
+pub(crate) fn unfinalize_unattested(
+    connection: &sqlite::Connection,
+    staking: &StakingConfig,
+) -> Result<()> {
+    while let Some(block) = db::read_finalized(connection)? {
+        if block.number == 0 || has_supermajority(connection, staking, &block)? {
+            break;
+        }
+        db::delete_finalized(connection, block.number)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, one per decoder that takes bytes from the
+//! network. Each panics only on a bug: input the decoder accepts must survive a round trip, and
+//! anything else must come back as an error. Built for tests and with the `fuzzing` feature.
+//!
+//! Run a target from the repository root with `cargo +nightly fuzz run <target>`, where the
+//! target is `transaction`, `block` or `graphql`.
+use crate::block::Block;
+use crate::hash::HashAlgorithm;
+use crate::transaction::Transaction;
+use crate::{Blockhead, Genesis};
+use std::sync::OnceLock;
+
+/// Decode `data` as a transaction, and if that succeeds, check it encodes back to `data`.
+pub fn transaction(data: &[u8]) {
+    if let Ok(transaction) = Transaction::decode(data) {
+        assert_eq!(transaction.encode(), data);
+    }
+}
+
+/// Decode `data` as a block, and if that succeeds, check it encodes back to `data` and its
+/// contents match its hashes.
+pub fn block(data: &[u8]) {
+    let algorithm = HashAlgorithm::default();
+    if let Ok(block) = Block::decode(data, algorithm) {
+        assert_eq!(block.encode(), data);
+        block.check_contents(algorithm).unwrap();
+    }
+}
+
+/// Parse `data` as a GraphQL query, and if that succeeds, run it against a node holding only a
+/// genesis block.
+pub fn graphql(data: &[u8]) {
+    static NODE: OnceLock<Blockhead> = OnceLock::new();
+    let Ok(query) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(selection) = crate::graphql::parse(query) {
+        let node =
+            NODE.get_or_init(|| Blockhead::with_genesis(":memory:", Genesis::default()).unwrap());
+        let _ = node.execute_graphql(&selection);
+    }
+}
+
+#[test]
+fn test_fuzz_entry_points() {
+    use crate::testkit::Gen;
+
+    for seed in 0..200 {
+        let mut gen = Gen::new(seed);
+        let encoded = gen.transaction().encode();
+        transaction(&encoded);
+        transaction(&gen.mutate(encoded));
+        let encoded = gen.block().encode();
+        block(&encoded);
+        block(&gen.mutate(encoded));
+        let query = b"{ chainId head { number parent { hash } transactions { hash } } }".to_vec();
+        graphql(&query);
+        graphql(&gen.mutate(query));
+    }
+}
@@ -0,0 +1,178 @@
+//! Intrinsic gas, transaction size limits and block gas limits.
+//!
+//! Every transaction pays for its own inclusion before anything it does is executed: a flat
+//! base cost plus a cost per byte of `data`. Both the mempool and block validation reject
+//! transactions whose `gas_limit` cannot cover that, or whose `data` exceeds the size limit.
+//!
+//! Each block also carries a gas limit that its transactions' gas together may not exceed. The
+//! genesis block's limit is the target set at genesis. Each later block may move the limit away
+//! from its parent's by at most the parent's limit divided by
+//! [`GasConfig::gas_limit_adjustment_divisor`], and producers move it toward the target, so a
+//! changed target takes effect gradually.
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GasConfig {
+    /// Gas charged for every transaction.
+    pub base_cost: u64,
+    /// Gas charged per byte of transaction data.
+    pub per_byte_cost: u64,
+    /// Additional gas charged for deploying a contract.
+    pub create_cost: u64,
+    /// The largest `data` payload a transaction may carry, in bytes.
+    pub max_data_size: usize,
+    /// The gas available to read-only calls.
+    pub call_gas_limit: u64,
+    /// The block gas limit producers aim for.
+    pub block_gas_target: u64,
+    /// A block's gas limit differs from its parent's by at most the parent's limit divided by
+    /// this.
+    pub gas_limit_adjustment_divisor: u64,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            base_cost: 21_000,
+            per_byte_cost: 16,
+            create_cost: 32_000,
+            max_data_size: 128 * 1024,
+            call_gas_limit: 10_000_000,
+            block_gas_target: 30_000_000,
+            gas_limit_adjustment_divisor: 1024,
+        }
+    }
+}
+
+impl GasConfig {
+    /// The largest change in gas limit allowed between a block and its child.
+    fn max_gas_limit_change(&self, parent_gas_limit: u64) -> u64 {
+        (parent_gas_limit / self.gas_limit_adjustment_divisor).max(1)
+    }
+
+    /// The gas limit a producer gives the child of a block with `parent_gas_limit`: a step
+    /// toward the target, no larger than the protocol allows.
+    pub(crate) fn next_block_gas_limit(&self, parent_gas_limit: u64) -> u64 {
+        let step = self.max_gas_limit_change(parent_gas_limit);
+        if parent_gas_limit < self.block_gas_target {
+            (parent_gas_limit + step).min(self.block_gas_target)
+        } else {
+            parent_gas_limit
+                .saturating_sub(step)
+                .max(self.block_gas_target)
+        }
+    }
+
+    /// Check that a block may raise or lower its parent's gas limit to `gas_limit`. A block must
+    /// have room for at least one transaction.
+    pub(crate) fn check_block_gas_limit(
+        &self,
+        parent_gas_limit: u64,
+        gas_limit: u64,
+    ) -> Result<()> {
+        let step = self.max_gas_limit_change(parent_gas_limit);
+        if gas_limit.abs_diff(parent_gas_limit) > step {
+            return Err(Error::new(format!(
+                "gas limit {gas_limit} moves more than {step} from the parent's {parent_gas_limit}"
+            )));
+        }
+        if gas_limit < self.base_cost {
+            return Err(Error::new(format!(
+                "gas limit {gas_limit} is below the base cost {}",
+                self.base_cost
+            )));
+        }
+        Ok(())
+    }
+
+    /// The gas a transaction carrying `data_len` bytes costs before execution.
+    pub(crate) fn intrinsic_gas(&self, data_len: usize) -> u64 {
+        self.base_cost
+            .saturating_add(self.per_byte_cost.saturating_mul(data_len as u64))
+    }
+
+    /// Check `transaction` against the size limit and its own gas limit, returning its intrinsic
+    /// gas.
+    pub(crate) fn check(&self, transaction: &Transaction) -> Result<u64> {
+        if transaction.data.len() > self.max_data_size {
+            return Err(Error::new(format!(
+                "transaction data is {} bytes, above the limit of {}",
+                transaction.data.len(),
+                self.max_data_size
+            )));
+        }
+        let mut intrinsic_gas = self.intrinsic_gas(transaction.data.len());
+        if transaction.is_deployment() {
+            intrinsic_gas = intrinsic_gas.saturating_add(self.create_cost);
+        }
+        if transaction.gas_limit > self.block_gas_target {
+            return Err(Error::new(format!(
+                "gas limit {} is above the block gas target {}",
+                transaction.gas_limit, self.block_gas_target
+            )));
+        }
+        if transaction.gas_limit < intrinsic_gas {
+            return Err(Error::new(format!(
+                "gas limit {} is below the intrinsic gas {intrinsic_gas}",
+                transaction.gas_limit
+            )));
+        }
+        Ok(intrinsic_gas)
+    }
+}
+
+#[test]
+fn test_intrinsic_gas_limits() {
+    use crate::address::Address;
+    use crate::testkit;
+    use bytes::Bytes;
+
+    let config = GasConfig {
+        base_cost: 100,
+        per_byte_cost: 10,
+        create_cost: 1_000,
+        max_data_size: 4,
+        call_gas_limit: 0,
+        block_gas_target: 2_000,
+        gas_limit_adjustment_divisor: 10,
+    };
+    let mut transaction = Transaction {
+        to_address: Some(Address([1; 32])),
+        data: vec![1, 2, 3].into(),
+        gas_limit: 130,
+        gas_price: 1,
+        ..testkit::transaction(Default::default(), Address::zero(), 0)
+    };
+    assert_eq!(config.check(&transaction).unwrap(), 130);
+    transaction.gas_limit = 129;
+    assert!(config.check(&transaction).is_err());
+    transaction.to_address = None;
+    assert!(config.check(&transaction).is_err());
+    transaction.gas_limit = 1_130;
+    assert_eq!(config.check(&transaction).unwrap(), 1_130);
+    transaction.data = vec![0; 5].into();
+    assert!(config.check(&transaction).is_err());
+    transaction.data = Bytes::new();
+    transaction.gas_limit = 2_001;
+    assert!(config.check(&transaction).is_err());
+}
+
+#[test]
+fn test_block_gas_limit_adjustment() {
+    let config = GasConfig {
+        block_gas_target: 1_000_000,
+        gas_limit_adjustment_divisor: 100,
+        ..Default::default()
+    };
+    assert_eq!(config.next_block_gas_limit(1_000_000), 1_000_000);
+    assert_eq!(config.next_block_gas_limit(500_000), 505_000);
+    assert_eq!(config.next_block_gas_limit(999_000), 1_000_000);
+    assert_eq!(config.next_block_gas_limit(2_000_000), 1_980_000);
+    assert!(config.check_block_gas_limit(500_000, 505_000).is_ok());
+    assert!(config.check_block_gas_limit(500_000, 495_000).is_ok());
+    assert!(config.check_block_gas_limit(500_000, 505_001).is_err());
+    assert!(config.check_block_gas_limit(20_000, 20_000).is_err());
+}
@@ -0,0 +1,174 @@
+use crate::address::Address;
+use crate::archive::{PruningConfig, StateHistory};
+use crate::block::Block;
+use crate::clock::TimestampConfig;
+use crate::cold::ColdStorageConfig;
+use crate::error::{Error, Result};
+use crate::faucet::FaucetConfig;
+use crate::fee::FeeConfig;
+use crate::finality::FinalityConfig;
+use crate::gas::GasConfig;
+use crate::hash::HashAlgorithm;
+use crate::http::HttpConfig;
+use crate::import_queue::ImportConfig;
+use crate::indexer::IndexerConfig;
+use crate::mempool::MempoolConfig;
+use crate::peers::PeerConfig;
+use crate::proof;
+use crate::reward::RewardConfig;
+use crate::runtime::RuntimeConfig;
+use crate::staking::StakingConfig;
+use crate::state::Account;
+use crate::trace::TraceConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Protocol parameters fixed at genesis, then the settings local to each node.
+///
+/// The node settings, from `trace` on, are not agreed at genesis, so chain specs leave them out.
+/// A node takes them from the `config.toml` of its data directory instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChainConfig {
+    /// Identifies the chain in transaction signatures, so that a transaction signed for one
+    /// chain cannot be replayed on another. See [`crate::transaction::Transaction::signing_hash`].
+    pub chain_id: u64,
+    /// The hash function of blocks, transactions, state and addresses. Everything that hashes for
+    /// the chain is handed it, so chains of different hash functions can share a process.
+    pub hash: HashAlgorithm,
+    pub staking: StakingConfig,
+    pub finality: FinalityConfig,
+    pub timestamp: TimestampConfig,
+    pub gas: GasConfig,
+    pub reward: RewardConfig,
+    /// Reject unsigned transactions, as any chain with value on it must. Only dev and test chains
+    /// turn this off. Signatures that are present are always checked.
+    pub require_signatures: bool,
+    /// Which traces the node keeps as it executes blocks.
+    #[serde(skip)]
+    pub trace: TraceConfig,
+    /// The recent blocks [`crate::Blockchain::estimate_fee`] reads prices from.
+    #[serde(skip)]
+    pub fee: FeeConfig,
+    /// How far receipt indexing may fall behind the chain, and how it is batched.
+    #[serde(skip)]
+    pub indexer: IndexerConfig,
+    /// The HTTP server's address and endpoints.
+    #[serde(skip)]
+    pub http: HttpConfig,
+    /// What the mempool admits, and the order this node's blocks take transactions in.
+    #[serde(skip)]
+    pub mempool: MempoolConfig,
+    /// Whether the node keeps every version of the state or only the latest.
+    #[serde(skip)]
+    pub history: StateHistory,
+    /// How often a pruned node checkpoints the state.
+    #[serde(skip)]
+    pub pruning: PruningConfig,
+    /// How many blocks from peers may wait to be imported, orphans included.
+    #[serde(skip)]
+    pub import: ImportConfig,
+    /// The nodes this one syncs blocks with.
+    #[serde(skip)]
+    pub peers: PeerConfig,
+    /// Whether the node pays out test funds, and how much.
+    #[serde(skip)]
+    pub faucet: FaucetConfig,
+    /// When finalized blocks' transactions, receipts and logs move to cold storage, and where.
+    #[serde(skip)]
+    pub cold: ColdStorageConfig,
+    /// The threads the node runs its tasks, database work and contract code on.
+    #[serde(skip)]
+    pub runtime: RuntimeConfig,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        Self {
+            chain_id: 1,
+            hash: Default::default(),
+            staking: Default::default(),
+            finality: Default::default(),
+            timestamp: Default::default(),
+            gas: Default::default(),
+            reward: Default::default(),
+            require_signatures: true,
+            trace: Default::default(),
+            fee: Default::default(),
+            indexer: Default::default(),
+            http: Default::default(),
+            mempool: Default::default(),
+            history: Default::default(),
+            pruning: Default::default(),
+            import: Default::default(),
+            peers: Default::default(),
+            faucet: Default::default(),
+            cold: Default::default(),
+            runtime: Default::default(),
+        }
+    }
+}
+
+impl ChainConfig {
+    /// Fail unless this config's protocol parameters are `stored`, those a database recorded
+    /// for its chain. Opening a chain with other parameters, above all another hash function,
+    /// would compute hashes and roots that disagree with those stored.
+    pub(crate) fn check_matches(&self, stored: &ChainConfig) -> Result<()> {
+        let (ours, theirs) = (serde_json::to_value(self)?, serde_json::to_value(stored)?);
+        let (Value::Object(ours), Value::Object(theirs)) = (ours, theirs) else {
+            unreachable!("a config serializes to an object");
+        };
+        let differing: Vec<&str> = (ours.iter())
+            .filter(|&(name, value)| theirs.get(name) != Some(value))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if differing.is_empty() {
+            return Ok(());
+        }
+        Err(Error::new(format!(
+            "the database holds a chain with other protocol parameters ({}): it uses {:?} hashes \
+             and chain ID {}",
+            differing.join(", "),
+            stored.hash,
+            stored.chain_id
+        )))
+    }
+}
+
+/// The initial state of a chain.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Genesis {
+    pub timestamp: u64,
+    /// Initial balances.
+    pub alloc: Vec<(Address, u64)>,
+    /// Initial stakes, which form the validator set of epoch 0.
+    pub validators: Vec<(Address, u64)>,
+    pub config: ChainConfig,
+}
+
+impl Genesis {
+    /// The accounts the chain starts with.
+    pub(crate) fn accounts(&self) -> BTreeMap<Address, Account> {
+        let mut accounts = BTreeMap::<Address, Account>::new();
+        for (address, balance) in &self.alloc {
+            accounts.entry(*address).or_default().balance += balance;
+        }
+        for (address, stake) in &self.validators {
+            accounts.entry(*address).or_default().stake += stake;
+        }
+        accounts
+    }
+
+    pub(crate) fn block(&self) -> Block {
+        Block::builder(self.config.hash)
+            .genesis()
+            .timestamp(self.timestamp)
+            .proposer(Address::zero())
+            .state_root(proof::state_root(&self.accounts(), self.config.hash))
+            .gas_limit(self.config.gas.block_gas_target)
+            .build()
+            .expect("genesis has every field")
+    }
+}
@@ -0,0 +1,710 @@
+//! GraphQL queries over the chain, served at `/graphql` by [`crate::http`].
+//!
+//! Explorers fetch a block with its transactions and their receipts, or an account with its
+//! history, in one request that names just the fields they show. This implements the part of
+//! GraphQL such queries use: a single query operation of nested fields, with aliases and literal
+//! arguments, and `__typename`. Fragments, variables, directives and mutations are not supported.
+//!
+//! ```graphql
+//! type Query {
+//!   chainId: Int!
+//!   hashAlgorithm: String!                       # blake2s, keccak256 or sha3_256
+//!   head: Block!
+//!   block(number: Int, hash: String): Block        # the head without arguments
+//!   transaction(hash: String!): Transaction      # stored, or waiting in the mempool
+//!   account(address: String!): Account!
+//!   estimateGas(data: String): Int!              # of a transfer or call carrying `data`
+//! }
+//! type Block {
+//!   hash: String!  number: Int!  parentHash: String!  parent: Block  timestamp: Int!
+//!   proposer: String!  transactionsRoot: String!  stateRoot: String!  gasLimit: Int!
+//!   transactionCount: Int!  transactions: [Transaction!]!
+//! }
+//! type Transaction {
+//!   hash: String!  kind: String!  from: String!  to: String  value: Int!  data: String!
+//!   nonce: Int!  gasLimit: Int!  gasPrice: Int!  block: Block  receipt: Receipt
+//!   cursor: String                                 # for Account.transactions; null if pending
+//!   confirmations: Int!                            # canonical blocks from its own to the head
+//!   raw: String!                                   # the encoding, signatures included
+//! }
+//! type Receipt {
+//!   transactionHash: String!  status: Boolean!  gasUsed: Int!  contractAddress: String
+//!   returnData: String!
+//! }
+//! type Account {
+//!   address: String!  balance: Int!  nonce: Int!  code: String
+//!   pendingNonce: Int!                           # counting transactions in the mempool
+//!   # sent or received, newest first, after the transaction with cursor `after`
+//!   transactions(limit: Int = 20, after: String): [Transaction!]!
+//!   transactionCount: Int!
+//! }
+//! ```
+//!
+//! Lists are paged as [`crate::cursor`] describes. Only canonical blocks are found. Hashes,
+//! addresses and byte strings are `0x`-prefixed hex; integers are JSON numbers, which may exceed
+//! GraphQL's 32-bit `Int`.
+use crate::address::Address;
+use crate::block::{Block, BlockId};
+use crate::cursor;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::hash::{decode_hex32, Hash};
+use crate::http::Response;
+use crate::transaction::Transaction;
+use crate::{Blockhead, TransactionReceipt};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+/// The most transactions `Account.transactions` returns.
+const MAX_ACCOUNT_TRANSACTIONS: u64 = 1000;
+/// The deepest nesting of fields a query may have.
+const MAX_DEPTH: usize = 16;
+
+/// A field requested in a selection set.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+    pub selection: Vec<Field>,
+}
+
+impl Field {
+    /// The key of the field in the result.
+    fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    fn argument(&self, name: &str) -> Option<&Value> {
+        self.arguments
+            .iter()
+            .find(|(argument, _)| argument == name)
+            .map(|(_, value)| value)
+            .filter(|value| !value.is_null())
+    }
+
+    fn string_argument(&self, name: &str) -> Result<Option<&str>> {
+        match self.argument(name) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) => Err(Error::new(format!(
+                "argument {name} of {} must be a string",
+                self.name
+            ))),
+        }
+    }
+
+    fn required_string_argument(&self, name: &str) -> Result<&str> {
+        self.string_argument(name)?
+            .ok_or_else(|| Error::new(format!("{} requires argument {name}", self.name)))
+    }
+
+    fn int_argument(&self, name: &str) -> Result<Option<u64>> {
+        match self.argument(name) {
+            None => Ok(None),
+            Some(value) => value.as_u64().map(Some).ok_or_else(|| {
+                Error::new(format!(
+                    "argument {name} of {} must be a non-negative integer",
+                    self.name
+                ))
+            }),
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(format!("{message} at offset {}", self.position))
+    }
+
+    /// Skip whitespace, commas and comments, which GraphQL ignores.
+    fn skip_ignored(&mut self) {
+        while let Some(c) = self.source[self.position..].chars().next() {
+            if c == '#' {
+                let rest = &self.source[self.position..];
+                self.position += rest.find('\n').unwrap_or(rest.len());
+            } else if c.is_whitespace() || c == ',' || c == '\u{feff}' {
+                self.position += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_ignored();
+        self.source[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected {expected:?}")));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<&'a str> {
+        self.skip_ignored();
+        let rest = &self.source[self.position..];
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if length == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error("expected a name"));
+        }
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    fn document(&mut self) -> Result<Vec<Field>> {
+        if self.peek() != Some('{') {
+            if self.name()? != "query" {
+                return Err(Error::new("only query operations are supported"));
+            }
+            if self.peek() != Some('{') {
+                self.name()?;
+            }
+        }
+        let selection = self.selection_set(0)?;
+        if self.peek().is_some() {
+            return Err(self.error("expected the end of the query"));
+        }
+        Ok(selection)
+    }
+
+    fn selection_set(&mut self, depth: usize) -> Result<Vec<Field>> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("query is nested too deeply"));
+        }
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while self.peek() != Some('}') {
+            if self.peek() == Some('.') {
+                return Err(self.error("fragments are not supported"));
+            }
+            fields.push(self.field(depth)?);
+        }
+        self.expect('}')?;
+        if fields.is_empty() {
+            return Err(self.error("empty selection set"));
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self, depth: usize) -> Result<Field> {
+        let mut alias = None;
+        let mut name = self.name()?.to_string();
+        if self.peek() == Some(':') {
+            self.expect(':')?;
+            alias = Some(name);
+            name = self.name()?.to_string();
+        }
+        let mut arguments = Vec::new();
+        if self.peek() == Some('(') {
+            self.expect('(')?;
+            while self.peek() != Some(')') {
+                let argument = self.name()?.to_string();
+                self.expect(':')?;
+                arguments.push((argument, self.value()?));
+            }
+            self.expect(')')?;
+        }
+        if self.peek() == Some('@') {
+            return Err(self.error("directives are not supported"));
+        }
+        let selection = match self.peek() {
+            Some('{') => self.selection_set(depth + 1)?,
+            _ => Vec::new(),
+        };
+        Ok(Field {
+            alias,
+            name,
+            arguments,
+            selection,
+        })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('$') => Err(self.error("variables are not supported")),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let rest = &self.source[self.position..];
+                let length = rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(rest.len(), |length| length + 1);
+                let value = rest[..length]
+                    .parse::<i64>()
+                    .map_err(|_| self.error("bad integer"))?;
+                self.position += length;
+                Ok(value.into())
+            }
+            _ => match self.name()? {
+                "true" => Ok(true.into()),
+                "false" => Ok(false.into()),
+                "null" => Ok(Value::Null),
+                name => Err(Error::new(format!("unsupported value {name}"))),
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        let mut chars = self.source[self.position..].chars();
+        while let Some(c) = chars.next() {
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(string),
+                '\n' => break,
+                '\\' => {
+                    let escaped = chars.next().ok_or_else(|| self.error("bad escape"))?;
+                    self.position += escaped.len_utf8();
+                    string.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '"' | '\\' | '/' => escaped,
+                        _ => return Err(self.error("unsupported escape")),
+                    });
+                }
+                _ => string.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+}
+
+/// Parse a query document into the fields of its operation.
+pub(crate) fn parse(query: &str) -> Result<Vec<Field>> {
+    Parser {
+        source: query,
+        position: 0,
+    }
+    .document()
+}
+
+/// The objects of the schema.
+enum Object {
+    Query,
+    Block(Box<Block>),
+    Transaction(Hash, Transaction),
+    Receipt(TransactionReceipt),
+    Account(Address),
+}
+
+impl Object {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Object::Query => "Query",
+            Object::Block(_) => "Block",
+            Object::Transaction(..) => "Transaction",
+            Object::Receipt(_) => "Receipt",
+            Object::Account(_) => "Account",
+        }
+    }
+}
+
+/// A field's value before its selection set, if any, is applied.
+enum Resolved {
+    Scalar(Value),
+    Object(Option<Object>),
+    List(Vec<Object>),
+}
+
+fn hex(bytes: &[u8]) -> Value {
+    format!("0x{}", hex::encode(bytes)).into()
+}
+
+fn parse_hash(s: &str) -> Result<Hash> {
+    Hash::from_hex(s)
+}
+
+impl Blockhead {
+    /// Execute the fields of a parsed query.
+    pub(crate) fn execute_graphql(&self, selection: &[Field]) -> Result<Value> {
+        self.resolve_selection(&Object::Query, selection)
+    }
+
+    fn resolve_selection(&self, object: &Object, selection: &[Field]) -> Result<Value> {
+        let mut result = Map::new();
+        for field in selection {
+            let value = match self.resolve_field(object, field)? {
+                Resolved::Scalar(value) if field.selection.is_empty() => value,
+                Resolved::Scalar(_) => {
+                    return Err(Error::new(format!(
+                        "{}.{} has no fields to select",
+                        object.type_name(),
+                        field.name
+                    )))
+                }
+                _ if field.selection.is_empty() => {
+                    return Err(Error::new(format!(
+                        "{}.{} needs a selection of fields",
+                        object.type_name(),
+                        field.name
+                    )))
+                }
+                Resolved::Object(None) => Value::Null,
+                Resolved::Object(Some(child)) => {
+                    self.resolve_selection(&child, &field.selection)?
+                }
+                Resolved::List(children) => Value::Array(
+                    children
+                        .iter()
+                        .map(|child| self.resolve_selection(child, &field.selection))
+                        .collect::<Result<_>>()?,
+                ),
+            };
+            result.insert(field.key().to_string(), value);
+        }
+        Ok(Value::Object(result))
+    }
+
+    fn resolve_field(&self, object: &Object, field: &Field) -> Result<Resolved> {
+        use Resolved::Scalar;
+        let connection = self.reader();
+        let unknown = || {
+            Error::new(format!(
+                "{} has no field {}",
+                object.type_name(),
+                field.name
+            ))
+        };
+        if field.name == "__typename" {
+            return Ok(Scalar(object.type_name().into()));
+        }
+        Ok(match object {
+            Object::Query => match field.name.as_str() {
+                "chainId" => Scalar(self.config.chain_id.into()),
+                "hashAlgorithm" => Scalar(serde_json::to_value(self.config.hash)?),
+                "head" => Resolved::Object(
+                    self.block(BlockId::Latest)?
+                        .map(Box::new)
+                        .map(Object::Block),
+                ),
+                "block" => {
+                    let id = match (
+                        field.int_argument("number")?,
+                        field.string_argument("hash")?,
+                    ) {
+                        (Some(number), None) => BlockId::Number(number),
+                        (None, Some(hash)) => BlockId::Hash(parse_hash(hash)?),
+                        (None, None) => BlockId::Latest,
+                        (Some(_), Some(_)) => {
+                            return Err(Error::new("block takes a number or a hash, not both"))
+                        }
+                    };
+                    let block = match id {
+                        BlockId::Hash(hash) => {
+                            match db::read_block_is_canonical(&connection, hash)? {
+                                Some(true) => self.block(id)?,
+                                _ => None,
+                            }
+                        }
+                        _ => self.block(id)?,
+                    };
+                    Resolved::Object(block.map(Box::new).map(Object::Block))
+                }
+                "transaction" => {
+                    let hash = parse_hash(field.required_string_argument("hash")?)?;
+                    let transaction = match db::read_transaction(&connection, hash)? {
+                        Some(transaction) => Some(transaction),
+                        None => self.mempool.lock().unwrap().get(hash),
+                    };
+                    Resolved::Object(transaction.map(|t| Object::Transaction(hash, t)))
+                }
+                "account" => {
+                    let address =
+                        Address::from(decode_hex32(field.required_string_argument("address")?)?);
+                    Resolved::Object(Some(Object::Account(address)))
+                }
+                "estimateGas" => {
+                    let data = match field.string_argument("data")? {
+                        Some(data) => hex::decode(data.trim_start_matches("0x"))
+                            .map_err(|error| Error::new(format!("bad data hex: {error}")))?,
+                        None => Vec::new(),
+                    };
+                    Scalar(self.config.gas.intrinsic_gas(data.len()).into())
+                }
+                _ => return Err(unknown()),
+            },
+            Object::Block(block) => match field.name.as_str() {
+                "hash" => Scalar(block.hash.to_string().into()),
+                "number" => Scalar(block.number.into()),
+                "parentHash" => Scalar(block.parent_hash.to_string().into()),
+                "parent" => Resolved::Object(match block.number {
+                    0 => None,
+                    _ => db::read_block(&connection, block.parent_hash)?
+                        .map(Box::new)
+                        .map(Object::Block),
+                }),
+                "timestamp" => Scalar(block.timestamp.into()),
+                "proposer" => Scalar(block.proposer.to_string().into()),
+                "transactionsRoot" => Scalar(block.transactions_root.to_string().into()),
+                "stateRoot" => Scalar(block.state_root.to_string().into()),
+                "gasLimit" => Scalar(block.gas_limit.into()),
+                "transactionCount" => Scalar(block.body.transactions.len().into()),
+                "transactions" => Resolved::List(
+                    block
+                        .body
+                        .transactions
+                        .iter()
+                        .map(|(hash, transaction)| Object::Transaction(*hash, transaction.clone()))
+                        .collect(),
+                ),
+                _ => return Err(unknown()),
+            },
+            Object::Transaction(hash, transaction) => match field.name.as_str() {
+                "hash" => Scalar(hash.to_string().into()),
+                "kind" => Scalar(format!("{:?}", transaction.kind).into()),
+                "from" => Scalar(transaction.from_address.to_string().into()),
+                "to" => Scalar(json!(transaction.to_address.map(|a| a.to_string()))),
+                "value" => Scalar(transaction.value.into()),
+                "data" => Scalar(hex(&transaction.data)),
+                "nonce" => Scalar(transaction.nonce.into()),
+                "gasLimit" => Scalar(transaction.gas_limit.into()),
+                "gasPrice" => Scalar(transaction.gas_price.into()),
+                "block" => {
+                    let location = db::read_transaction_location(&connection, *hash)?;
+                    let block = match location {
+                        Some((block_hash, _)) => db::read_block(&connection, block_hash)?,
+                        None => None,
+                    };
+                    Resolved::Object(block.map(Box::new).map(Object::Block))
+                }
+                "receipt" => {
+                    let receipt = db::read_receipt(&connection, *hash)?;
+                    Resolved::Object(receipt.map(Object::Receipt))
+                }
+                "confirmations" => Scalar(self.confirmations(*hash)?.into()),
+                "raw" => Scalar(hex(&transaction.encode())),
+                "cursor" => {
+                    let location = db::read_transaction_location(&connection, *hash)?;
+                    let header = match location {
+                        Some((block_hash, _)) => db::read_header(&connection, block_hash)?,
+                        None => None,
+                    };
+                    Scalar(match (header, location) {
+                        (Some(header), Some((_, position))) => {
+                            let position = [header.number, position as u64];
+                            cursor::encode("transactions", &position).into()
+                        }
+                        _ => Value::Null,
+                    })
+                }
+                _ => return Err(unknown()),
+            },
+            Object::Receipt(receipt) => match field.name.as_str() {
+                "transactionHash" => Scalar(receipt.transaction_hash.to_string().into()),
+                "status" => Scalar(receipt.status.into()),
+                "gasUsed" => Scalar(receipt.gas_used.into()),
+                "contractAddress" => Scalar(json!(receipt.contract_address.map(|a| a.to_string()))),
+                "returnData" => Scalar(hex(&receipt.return_data)),
+                _ => return Err(unknown()),
+            },
+            Object::Account(address) => match field.name.as_str() {
+                "address" => Scalar(address.to_string().into()),
+                "balance" => Scalar(self.account(*address)?.balance.into()),
+                "nonce" => Scalar(self.account(*address)?.nonce.into()),
+                "pendingNonce" => Scalar(self.pending_nonce(*address)?.into()),
+                "transactionCount" => {
+                    Scalar(db::count_account_transactions(&connection, *address)?.into())
+                }
+                "code" => Scalar(match db::read_code(&connection, *address)? {
+                    Some(code) => hex(&code),
+                    None => Value::Null,
+                }),
+                "transactions" => {
+                    let limit = field.int_argument("limit")?;
+                    let limit = cursor::page_size(limit, 20, MAX_ACCOUNT_TRANSACTIONS);
+                    let after = match field.string_argument("after")? {
+                        Some(after) => Some(cursor::decode("transactions", after)?.into()),
+                        None => None,
+                    };
+                    let transactions =
+                        db::read_account_transactions(&connection, *address, after, limit)?;
+                    Resolved::List(
+                        transactions
+                            .into_iter()
+                            .map(|(hash, transaction)| Object::Transaction(hash, transaction))
+                            .collect(),
+                    )
+                }
+                _ => return Err(unknown()),
+            },
+        })
+    }
+}
+
+/// Answer a GraphQL request body, `{"query": ...}`, with `{"data": ...}` or `{"errors": [...]}`.
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, body: &[u8]) -> Response {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(error) => return Response::error(400, format!("bad JSON: {error}")),
+    };
+    let Some(query) = request["query"].as_str() else {
+        return Response::error(400, "expected {\"query\": ...}");
+    };
+    let selection = match parse(query) {
+        Ok(selection) => selection,
+        Err(error) => return Response::json(400, &json!({"errors": [error_json(&error)]})),
+    };
+    let blockhead = blockhead.clone();
+    let result = tokio::task::spawn_blocking(move || blockhead.execute_graphql(&selection)).await;
+    match result {
+        Ok(Ok(data)) => Response::json(200, &json!({"data": data})),
+        Ok(Err(error)) => Response::json(200, &json!({"errors": [error_json(&error)]})),
+        Err(error) => Response::error(500, error),
+    }
+}
+
+/// A GraphQL error for `error`, with the kind's [`ErrorKind::code`] and [`ErrorKind::data`]
+/// as `extensions`.
+///
+/// [`ErrorKind::code`]: crate::ErrorKind::code
+/// [`ErrorKind::data`]: crate::ErrorKind::data
+fn error_json(error: &Error) -> Value {
+    let mut extensions = json!({"code": error.kind().code()});
+    if let Some(data) = error.kind().data() {
+        extensions["data"] = data;
+    }
+    json!({"message": error.message(), "extensions": extensions})
+}
+
+#[test]
+fn test_parse() {
+    let query = r#"query Recent {
+        # the head and its parent
+        latest: head { number parent { hash } }
+        account(address: "0xé", limit: -3, flag: true, none: null) { balance }
+    }"#;
+    let fields = parse(query).unwrap();
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0].alias.as_deref(), Some("latest"));
+    assert_eq!(fields[0].name, "head");
+    assert_eq!(fields[0].selection[1].selection[0].name, "hash");
+    assert_eq!(
+        fields[1].arguments,
+        vec![
+            ("address".to_string(), json!("0x\u{e9}")),
+            ("limit".to_string(), json!(-3)),
+            ("flag".to_string(), json!(true)),
+            ("none".to_string(), Value::Null),
+        ]
+    );
+    assert!(parse("{ head { number } ").is_err());
+    assert!(parse("mutation { head }").is_err());
+    assert!(parse("{ head { ...fields } }").is_err());
+    assert!(parse("{ block(number: $n) { hash } }").is_err());
+    assert!(parse(&format!("{}{}", "{ a ".repeat(20), "}".repeat(20))).is_err());
+    assert!(parse("{ head } # trailing").is_ok());
+}
+
+#[tokio::test]
+async fn test_graphql_endpoint() {
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let recipient = Address([8; 32]);
+    let hash = chain.transfer(chain.validator, recipient, 5).await;
+    chain.produce();
+    chain.produce();
+    let address = crate::http::serve_locally(Arc::new(chain.blockhead)).await;
+    let query = |query: &str| {
+        let body = json!({"query": query}).to_string();
+        let request = format!(
+            "POST /graphql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        async move { crate::http::send(address, &request).await }
+    };
+
+    let (status, body) = query(
+        "{ chainId head { number parent { number transactions { hash value receipt { status } } } } }",
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({"data": {"chainId": 1, "head": {"number": 2, "parent": {"number": 1, "transactions": [
+            {"hash": hash.to_string(), "value": 5, "receipt": {"status": true}}
+        ]}}}})
+    );
+
+    let (_, body) = query(&format!(
+        r#"{{ bob: account(address: "{recipient}") {{ balance __typename
+            transactions {{ from block {{ number }} }} }}
+            missing: block(number: 9) {{ hash }} }}"#
+    ))
+    .await;
+    assert_eq!(
+        body,
+        json!({"data": {
+            "bob": {"balance": 5, "__typename": "Account", "transactions": [
+                {"from": chain.validator.to_string(), "block": {"number": 1}}
+            ]},
+            "missing": null,
+        }})
+    );
+
+    let (_, body) = query("{ head { bogus } }").await;
+    assert_eq!(body["errors"][0]["message"], "Block has no field bogus");
+    let (_, body) = query("{ head }").await;
+    assert_eq!(
+        body["errors"][0]["message"],
+        "Query.head needs a selection of fields"
+    );
+    let (status, _) = query("{ head {").await;
+    assert_eq!(status, 400);
+
+    // Comments cost nothing but their length, however many lines they run to.
+    let (status, body) = query(&format!("{}{{ chainId }}", "#\n".repeat(300_000))).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({"data": {"chainId": 1}}));
+}
+
+#[tokio::test]
+async fn test_graphql_pagination() {
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let bob = Address([8; 32]);
+    for value in 1..=3 {
+        chain.transfer(chain.validator, bob, value).await;
+        chain.produce();
+    }
+    let pending = chain.transfer(chain.validator, bob, 4).await;
+    let blockhead = Arc::new(chain.blockhead);
+    let page = |after: &str| {
+        let query = format!(
+            r#"{{ account(address: "{bob}") {{ transactionCount
+                transactions(limit: 2{after}) {{ value cursor }} }} }}"#
+        );
+        blockhead.execute_graphql(&parse(&query).unwrap())
+    };
+
+    let first = page("").unwrap();
+    let account = &first["account"];
+    assert_eq!(account["transactionCount"], 3);
+    let values = |account: &Value| -> Vec<Value> {
+        let transactions = account["transactions"].as_array().unwrap();
+        transactions.iter().map(|t| t["value"].clone()).collect()
+    };
+    assert_eq!(values(account), [json!(3), json!(2)]);
+    let cursor = account["transactions"][1]["cursor"].as_str().unwrap();
+    let second = page(&format!(r#", after: "{cursor}""#)).unwrap();
+    assert_eq!(values(&second["account"]), [json!(1)]);
+
+    let query = format!(r#"{{ transaction(hash: "{pending}") {{ cursor }} }}"#);
+    let data = blockhead.execute_graphql(&parse(&query).unwrap()).unwrap();
+    assert_eq!(data["transaction"]["cursor"], Value::Null);
+    let error = page(r#", after: "0x12""#).unwrap_err();
+    assert!(error.message().contains("is not a cursor of transactions"));
+}
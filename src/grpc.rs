@@ -0,0 +1,667 @@
+//! The gRPC service `proto/blockhead.proto` defines, for backend services that prefer a typed,
+//! streaming API to JSON.
+//!
+//! gRPC proper runs over HTTP/2, which the HTTP server does not speak, so the service answers the
+//! gRPC-Web protocol, which carries the same calls over HTTP/1.1: a client POSTs
+//! `/blockhead.v1.Blockhead/<Method>` with a body of one length-prefixed message, and the response
+//! body holds the answer's message followed by the `grpc-status` and `grpc-message` trailers,
+//! framed the same way. gRPC-Web clients, such as tonic-web's, call it directly. Only binary,
+//! uncompressed messages (`application/grpc-web+proto`) are supported. A failed call's trailers
+//! also carry its [`ErrorKind::code`] as `blockhead-code`, the `code` of JSON error responses.
+//!
+//! `NewBlocks` keeps the connection open and sends each block that joins the canonical chain as a
+//! message of its own, until the client disconnects. A client that falls too far behind is sent
+//! `RESOURCE_EXHAUSTED` and disconnected.
+//!
+//! The service is only built with the `grpc` feature, and answered while [`HttpConfig::grpc`] is
+//! on.
+//!
+//! [`HttpConfig::grpc`]: crate::http::HttpConfig::grpc
+use crate::block::{Block, BlockId, Header};
+use crate::error::{Error, ErrorKind, Result};
+use crate::events::ChainEvent;
+use crate::hash::Hash;
+use crate::http::Response;
+use crate::transaction::Transaction;
+use crate::{Address, Blockchain, Blockhead, TransactionReceipt};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
+
+/// The path prefix of the service's methods.
+pub(crate) const PREFIX: &str = "/blockhead.v1.Blockhead/";
+const CONTENT_TYPE: &str = "application/grpc-web+proto";
+
+/// The flag of a frame holding a message, and of the frame holding the trailers.
+const DATA: u8 = 0;
+const TRAILERS: u8 = 0x80;
+const COMPRESSED: u8 = 1;
+
+// The gRPC status codes the service answers with.
+const OK: u32 = 0;
+const UNKNOWN: u32 = 2;
+const INVALID_ARGUMENT: u32 = 3;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const FAILED_PRECONDITION: u32 = 9;
+const UNIMPLEMENTED: u32 = 12;
+
+/// Whether `path` is the streaming `NewBlocks` method, which [`serve_new_blocks`] answers rather
+/// than [`handle`].
+pub(crate) fn is_new_blocks(path: &str) -> bool {
+    path.strip_prefix(PREFIX) == Some("NewBlocks")
+}
+
+/// Answer a call to unary `method` of the service, with `body` its framed request.
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, method: &str, body: &[u8]) -> Response {
+    let result = match unframe(body) {
+        Ok(request) => call(blockhead, method, request).await,
+        Err(status) => Err(status),
+    };
+    let mut body = Vec::new();
+    let trailers = match result {
+        Ok(message) => {
+            body = frame(DATA, &message.0);
+            Status::ok()
+        }
+        Err(status) => status,
+    };
+    body.extend(frame(TRAILERS, trailers.encode().as_bytes()));
+    Response {
+        status: 200,
+        content_type: CONTENT_TYPE,
+        headers: Vec::new(),
+        body,
+    }
+}
+
+async fn call(
+    blockhead: &Blockhead,
+    method: &str,
+    request: &[u8],
+) -> std::result::Result<Message, Status> {
+    let message = match method {
+        "GetBlock" => {
+            let block = blockhead.get_block(block_id(request)?).await?;
+            Message::default().optional(1, block.as_ref().map(block_message))
+        }
+        "GetTransaction" => {
+            let hash = Hash(bytes32(request)?);
+            let transaction = blockhead.get_transaction(hash).await?;
+            let transaction =
+                transaction.map(|transaction| transaction_message(hash, &transaction));
+            Message::default().optional(1, transaction)
+        }
+        "GetTransactionReceipt" => {
+            let receipt = blockhead
+                .get_transaction_receipt(Hash(bytes32(request)?))
+                .await?;
+            Message::default().optional(1, receipt.as_ref().map(receipt_message))
+        }
+        "SendTransaction" => {
+            let encoded = field(request, 1)?.unwrap_or(Field::Bytes(&[]));
+            let Field::Bytes(encoded) = encoded else {
+                return Err(Status::invalid("transaction must be bytes"));
+            };
+            let transaction = Transaction::decode(encoded).map_err(Status::invalid)?;
+            let hash = blockhead.send_transaction(transaction).await?;
+            Message::default().bytes(1, &hash.0)
+        }
+        "GetBalance" => {
+            let balance = blockhead.get_balance(Address(bytes32(request)?)).await?;
+            Message::default().uint64(1, balance)
+        }
+        "GetNonce" => {
+            let nonce = blockhead.get_nonce(Address(bytes32(request)?)).await?;
+            Message::default().uint64(1, nonce)
+        }
+        "ChainId" => Message::default().uint64(1, blockhead.chain_id().await),
+        _ => {
+            return Err(Status {
+                code: UNIMPLEMENTED,
+                message: format!("no unary method {method}"),
+                error_code: None,
+            })
+        }
+    };
+    Ok(message)
+}
+
+/// Answer a `NewBlocks` call on `writer`, with `headers` besides the content type: send each block
+/// that joins the canonical chain from now on until writing fails.
+pub(crate) async fn serve_new_blocks(
+    blockhead: Arc<Blockhead>,
+    headers: Vec<(&'static str, String)>,
+    mut writer: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut events = blockhead.subscribe_chain_events();
+    let mut head =
+        format!("HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nConnection: close\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes()).await?;
+    writer.flush().await?;
+    let trailers = loop {
+        match events.recv().await {
+            Ok(ChainEvent::NewBlock(block)) => {
+                writer
+                    .write_all(&frame(DATA, &block_message(&block).0))
+                    .await?;
+                writer.flush().await?;
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => {
+                break Status {
+                    code: RESOURCE_EXHAUSTED,
+                    message: format!("fell {missed} chain events behind"),
+                    error_code: None,
+                }
+            }
+            Err(RecvError::Closed) => break Status::ok(),
+        }
+    };
+    writer
+        .write_all(&frame(TRAILERS, trailers.encode().as_bytes()))
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// How a call ended.
+#[derive(Debug)]
+struct Status {
+    code: u32,
+    message: String,
+    /// The [`ErrorKind::code`] of the node's error, if one ended the call.
+    error_code: Option<i64>,
+}
+
+impl Status {
+    fn ok() -> Self {
+        Self {
+            code: OK,
+            message: String::new(),
+            error_code: None,
+        }
+    }
+
+    fn invalid(error: impl std::fmt::Display) -> Self {
+        Self {
+            code: INVALID_ARGUMENT,
+            message: error.to_string(),
+            error_code: None,
+        }
+    }
+
+    /// The trailers, as the trailers frame holds them.
+    fn encode(&self) -> String {
+        let mut trailers = format!("grpc-status:{}\r\n", self.code);
+        if !self.message.is_empty() {
+            trailers.push_str(&format!(
+                "grpc-message:{}\r\n",
+                percent_encode(&self.message)
+            ));
+        }
+        if let Some(code) = self.error_code {
+            trailers.push_str(&format!("blockhead-code:{code}\r\n"));
+        }
+        trailers
+    }
+}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        let code = match error.kind() {
+            ErrorKind::RateLimited { .. } => RESOURCE_EXHAUSTED,
+            ErrorKind::InsufficientFunds { .. }
+            | ErrorKind::NonceMismatch { .. }
+            | ErrorKind::Underpriced { .. } => FAILED_PRECONDITION,
+            ErrorKind::Other | ErrorKind::Reverted { .. } => UNKNOWN,
+        };
+        Self {
+            code,
+            message: error.message().to_string(),
+            error_code: Some(error.kind().code()),
+        }
+    }
+}
+
+/// `message` with the bytes a `grpc-message` trailer cannot hold percent-encoded.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::new();
+    for byte in message.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn frame(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![flag];
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The message of request `body`, which must be one uncompressed data frame.
+fn unframe(body: &[u8]) -> std::result::Result<&[u8], Status> {
+    let Some((&[flag, a, b, c, d], message)) = body.split_first_chunk::<5>() else {
+        return Err(Status::invalid("request is not a framed message"));
+    };
+    if flag & COMPRESSED != 0 {
+        return Err(Status {
+            code: UNIMPLEMENTED,
+            message: "compressed messages are not supported".to_string(),
+            error_code: None,
+        });
+    }
+    if flag != DATA || u32::from_be_bytes([a, b, c, d]) as usize != message.len() {
+        return Err(Status::invalid("request is not one framed message"));
+    }
+    Ok(message)
+}
+
+/// A protobuf message being written. As proto3 does, fields holding their type's default are left
+/// out, except `optional` ones.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+const VARINT: u64 = 0;
+const LEN: u64 = 2;
+
+impl Message {
+    fn key(&mut self, field: u32, wire_type: u64) {
+        write_varint(&mut self.0, (u64::from(field) << 3) | wire_type);
+    }
+
+    fn uint64(mut self, field: u32, value: u64) -> Self {
+        if value != 0 {
+            self.key(field, VARINT);
+            write_varint(&mut self.0, value);
+        }
+        self
+    }
+
+    fn bool(self, field: u32, value: bool) -> Self {
+        self.uint64(field, u64::from(value))
+    }
+
+    fn bytes(self, field: u32, value: &[u8]) -> Self {
+        if value.is_empty() {
+            return self;
+        }
+        self.present_bytes(field, value)
+    }
+
+    /// An `optional` or `repeated` bytes field, written even when empty.
+    fn present_bytes(mut self, field: u32, value: &[u8]) -> Self {
+        self.key(field, LEN);
+        write_varint(&mut self.0, value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn optional(self, field: u32, message: Option<Message>) -> Self {
+        match message {
+            Some(message) => self.present_bytes(field, &message.0),
+            None => self,
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| Error::new("message truncated in a varint"))?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::new("varint longer than 64 bits"))
+}
+
+/// The value of a field of a message being read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The fields of protobuf message `bytes` in the order written. Fixed-width fields, which none of
+/// the service's requests have, are skipped.
+fn fields(mut bytes: &[u8]) -> Result<Vec<(u32, Field<'_>)>> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let number = u32::try_from(key >> 3).map_err(|_| Error::new("field number too large"))?;
+        let skip = match key & 7 {
+            VARINT => {
+                fields.push((number, Field::Varint(read_varint(&mut bytes)?)));
+                0
+            }
+            LEN => {
+                let len = read_varint(&mut bytes)? as usize;
+                if bytes.len() < len {
+                    return Err(Error::new("message truncated in a field"));
+                }
+                fields.push((number, Field::Bytes(&bytes[..len])));
+                len
+            }
+            1 => 8,
+            5 => 4,
+            wire_type => return Err(Error::new(format!("unsupported wire type {wire_type}"))),
+        };
+        bytes = bytes
+            .get(skip..)
+            .ok_or_else(|| Error::new("message truncated in a field"))?;
+    }
+    Ok(fields)
+}
+
+/// The last value of field `number` of `message`, which wins if it is repeated.
+fn field(message: &[u8], number: u32) -> std::result::Result<Option<Field<'_>>, Status> {
+    let fields = fields(message).map_err(Status::invalid)?;
+    Ok((fields.into_iter().rev())
+        .find(|(field, _)| *field == number)
+        .map(|(_, value)| value))
+}
+
+/// The 32 bytes in field 1 of `message`: a `HashRequest`'s hash or an `AddressRequest`'s address.
+fn bytes32(message: &[u8]) -> std::result::Result<[u8; 32], Status> {
+    match field(message, 1)? {
+        Some(Field::Bytes(bytes)) => bytes
+            .try_into()
+            .map_err(|_| Status::invalid(format!("expected 32 bytes, got {}", bytes.len()))),
+        _ => Err(Status::invalid("expected 32 bytes in field 1")),
+    }
+}
+
+/// The block a `BlockRequest` names: the last of its `id` fields, as in a `oneof`.
+fn block_id(message: &[u8]) -> std::result::Result<BlockId, Status> {
+    let fields = fields(message).map_err(Status::invalid)?;
+    let id = fields.into_iter().rev().find_map(|(number, value)| {
+        let id = match (number, value) {
+            (1, _) => BlockId::Finalized,
+            (2, _) => BlockId::Pending,
+            (3, Field::Varint(number)) => BlockId::Number(number),
+            (4, Field::Bytes(hash)) => match hash.try_into() {
+                Ok(hash) => BlockId::Hash(Hash(hash)),
+                Err(_) => return Some(Err(Status::invalid("hash must be 32 bytes"))),
+            },
+            _ => return None,
+        };
+        Some(Ok(id))
+    });
+    id.unwrap_or(Ok(BlockId::Latest))
+}
+
+fn header_message(header: &Header) -> Message {
+    let signature = header.signature.as_ref().map_or(&[][..], |s| &s.0[..]);
+    Message::default()
+        .bytes(1, &header.hash.0)
+        .bytes(2, &header.parent_hash.0)
+        .uint64(3, header.number)
+        .uint64(4, header.timestamp)
+        .bytes(5, &header.proposer.0)
+        .bytes(6, &header.transactions_root.0)
+        .bytes(7, &header.state_root.0)
+        .uint64(8, header.gas_limit)
+        .bytes(9, signature)
+}
+
+fn block_message(block: &Block) -> Message {
+    let message = Message::default().optional(1, Some(header_message(&block.header)));
+    (block.body.transactions.iter()).fold(message, |message, (hash, transaction)| {
+        message.present_bytes(2, &transaction_message(*hash, transaction).0)
+    })
+}
+
+fn transaction_message(hash: Hash, transaction: &Transaction) -> Message {
+    let mut message = Message::default()
+        .bytes(1, &hash.0)
+        .uint64(2, transaction.kind.to_i64() as u64)
+        .bytes(3, &transaction.from_address.0);
+    if let Some(to_address) = transaction.to_address {
+        message = message.present_bytes(4, &to_address.0);
+    }
+    let message = message
+        .uint64(5, transaction.value)
+        .bytes(6, &transaction.data)
+        .uint64(7, transaction.gas_limit)
+        .uint64(8, transaction.gas_price)
+        .uint64(9, transaction.nonce);
+    (transaction.signatures.iter()).fold(message, |message, signature| {
+        message.present_bytes(10, &signature.0)
+    })
+}
+
+fn receipt_message(receipt: &TransactionReceipt) -> Message {
+    let mut message = Message::default()
+        .bytes(1, &receipt.transaction_hash.0)
+        .bytes(2, &receipt.block_hash.0)
+        .bool(3, receipt.status)
+        .uint64(4, receipt.gas_used);
+    if let Some(contract_address) = receipt.contract_address {
+        message = message.present_bytes(5, &contract_address.0);
+    }
+    let message = message.bytes(6, &receipt.return_data);
+    (receipt.logs.iter()).fold(message, |message, log| {
+        let topics = Message::default().bytes(1, &log.address.0);
+        let log_message = (log.topics.iter())
+            .fold(topics, |topics, topic| topics.present_bytes(2, &topic.0))
+            .bytes(3, &log.data);
+        message.present_bytes(7, &log_message.0)
+    })
+}
+
+/// Call `method` on the service at `address` with request `message`, for tests. Returns the
+/// answer's message, if any, and the trailers.
+#[cfg(test)]
+async fn call_locally(
+    address: std::net::SocketAddr,
+    method: &str,
+    message: Message,
+) -> (Option<Vec<u8>>, String) {
+    use tokio::io::AsyncReadExt;
+
+    let body = frame(DATA, &message.0);
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let head = format!(
+        "POST {PREFIX}{method} HTTP/1.1\r\nContent-Type: {CONTENT_TYPE}\r\n\
+         Content-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(&body).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let start = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let mut frames = &response[start..];
+    let (mut answer, mut trailers) = (None, String::new());
+    while let Some((&[flag, a, b, c, d], rest)) = frames.split_first_chunk::<5>() {
+        let len = u32::from_be_bytes([a, b, c, d]) as usize;
+        let payload = rest[..len].to_vec();
+        if flag == TRAILERS {
+            trailers = String::from_utf8(payload).unwrap();
+        } else {
+            answer = Some(payload);
+        }
+        frames = &rest[len..];
+    }
+    (answer, trailers)
+}
+
+#[test]
+fn test_protobuf() {
+    let message = Message::default()
+        .uint64(1, 300)
+        .uint64(2, 0)
+        .bytes(3, b"hi")
+        .bytes(4, b"")
+        .present_bytes(5, b"");
+    assert_eq!(message.0, [0x08, 0xac, 0x02, 0x1a, 2, b'h', b'i', 0x2a, 0]);
+    assert_eq!(
+        fields(&message.0).unwrap(),
+        [
+            (1, Field::Varint(300)),
+            (3, Field::Bytes(b"hi")),
+            (5, Field::Bytes(b""))
+        ]
+    );
+    // Fixed-width fields are skipped, and truncated ones refused.
+    let fixed = [0x09, 1, 2, 3, 4, 5, 6, 7, 8, 0x10, 1];
+    assert_eq!(fields(&fixed).unwrap(), [(2, Field::Varint(1))]);
+    assert!(fields(&fixed[..5]).is_err());
+    assert!(fields(&[0x1a, 5, 1]).is_err());
+
+    assert_eq!(
+        block_id(&Message::default().uint64(3, 7).0).unwrap(),
+        BlockId::Number(7)
+    );
+    assert_eq!(
+        block_id(
+            &Message::default()
+                .bool(1, true)
+                .uint64(3, 7)
+                .bool(2, true)
+                .0
+        )
+        .unwrap(),
+        BlockId::Pending
+    );
+    assert_eq!(block_id(&[]).unwrap(), BlockId::Latest);
+    assert!(block_id(&Message::default().bytes(4, &[1; 31]).0).is_err());
+
+    assert!(unframe(&[0, 0, 0, 0, 1, 8]).is_ok());
+    assert!(unframe(&[0, 0, 0, 0, 2, 8]).is_err());
+    assert_eq!(unframe(&[1, 0, 0, 0, 0]).unwrap_err().code, UNIMPLEMENTED);
+    assert_eq!(percent_encode("50% off\n"), "50%25 off%0A");
+}
+
+#[tokio::test]
+async fn test_grpc() {
+    use crate::http::serve_locally;
+    use crate::testkit::{self, TestChain};
+
+    let chain = TestChain::new();
+    let (validator, recipient) = (chain.validator, Address([8; 32]));
+    let blockhead = Arc::new(chain.blockhead);
+    let address = serve_locally(blockhead.clone()).await;
+    let bytes_request = |bytes: &[u8]| Message::default().bytes(1, bytes);
+    // Field 1 of an answer, where every method puts its result.
+    let result = |answer: Option<Vec<u8>>| match field(&answer.unwrap(), 1).unwrap() {
+        Some(Field::Bytes(bytes)) => bytes.to_vec(),
+        Some(Field::Varint(value)) => value.to_be_bytes().to_vec(),
+        None => Vec::new(),
+    };
+
+    let (answer, trailers) = call_locally(address, "ChainId", Message::default()).await;
+    assert_eq!(trailers, "grpc-status:0\r\n");
+    let chain_id = blockhead.chain_id().await;
+    assert_eq!(result(answer), chain_id.to_be_bytes());
+
+    let transfer = testkit::transfer(validator, recipient, 5, 0);
+    let encoded = transfer.encode();
+    let (answer, _) = call_locally(address, "SendTransaction", bytes_request(&encoded)).await;
+    let hash = transfer.compute_hash(blockhead.config.hash);
+    assert_eq!(result(answer), hash.0);
+
+    let (answer, _) = call_locally(address, "GetTransaction", bytes_request(&hash.0)).await;
+    let transaction = result(answer);
+    let transaction = fields(&transaction).unwrap();
+    assert!(transaction.contains(&(3, Field::Bytes(&validator.0))));
+    assert!(transaction.contains(&(4, Field::Bytes(&recipient.0))));
+    assert!(transaction.contains(&(5, Field::Varint(5))));
+
+    let block = blockhead.produce_block().unwrap();
+    let (answer, _) = call_locally(address, "GetBlock", Message::default().uint64(3, 1)).await;
+    let answer = result(answer);
+    let block_fields = fields(&answer).unwrap();
+    let Some((1, Field::Bytes(header))) = block_fields.first() else {
+        panic!("no header in {block_fields:?}");
+    };
+    let header = fields(header).unwrap();
+    assert!(header.contains(&(1, Field::Bytes(&block.header.hash.0))));
+    let transactions = block_fields.iter().filter(|(number, _)| *number == 2);
+    assert_eq!(transactions.count(), 1);
+    // A block that does not exist is an answer without one.
+    let (answer, trailers) =
+        call_locally(address, "GetBlock", Message::default().uint64(3, 9)).await;
+    assert_eq!(answer, Some(Vec::new()));
+    assert_eq!(trailers, "grpc-status:0\r\n");
+
+    let (answer, _) = call_locally(address, "GetTransactionReceipt", bytes_request(&hash.0)).await;
+    let receipt = result(answer);
+    assert!(fields(&receipt).unwrap().contains(&(3, Field::Varint(1))));
+    let (answer, _) = call_locally(address, "GetBalance", bytes_request(&recipient.0)).await;
+    assert_eq!(result(answer), 5u64.to_be_bytes());
+    let (answer, _) = call_locally(address, "GetNonce", bytes_request(&validator.0)).await;
+    assert_eq!(result(answer), 1u64.to_be_bytes());
+
+    // Failures end in trailers alone.
+    let overdraft = testkit::transfer(validator, recipient, u64::MAX, 1).encode();
+    let (answer, trailers) =
+        call_locally(address, "SendTransaction", bytes_request(&overdraft)).await;
+    assert!(answer.is_none());
+    assert!(trailers.starts_with("grpc-status:9\r\ngrpc-message:"));
+    assert!(trailers.ends_with("blockhead-code:-32010\r\n"));
+    let (_, trailers) = call_locally(address, "GetBalance", bytes_request(&[1; 20])).await;
+    assert!(trailers.starts_with("grpc-status:3\r\n"));
+    let (_, trailers) = call_locally(address, "Mine", Message::default()).await;
+    assert!(trailers.starts_with("grpc-status:12\r\n"));
+}
+
+#[tokio::test]
+async fn test_grpc_new_blocks() {
+    use crate::http::serve_locally;
+    use crate::testkit::TestChain;
+    use tokio::io::AsyncReadExt;
+
+    let blockhead = Arc::new(TestChain::new().blockhead);
+    let address = serve_locally(blockhead.clone()).await;
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let body = frame(DATA, &[]);
+    let head = format!(
+        "POST {PREFIX}NewBlocks HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(&body).await.unwrap();
+    // The head is only sent once the call follows the chain.
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200 OK\r\nContent-Type: application/grpc-web+proto\r\n"));
+    let blocks = [
+        blockhead.produce_block().unwrap(),
+        blockhead.produce_block().unwrap(),
+    ];
+    for block in blocks {
+        assert_eq!(stream.read_u8().await.unwrap(), DATA);
+        let mut message = vec![0; stream.read_u32().await.unwrap() as usize];
+        stream.read_exact(&mut message).await.unwrap();
+        let Some((1, Field::Bytes(header))) = fields(&message).unwrap().first().copied() else {
+            panic!("no header");
+        };
+        let header = fields(header).unwrap();
+        assert!(header.contains(&(1, Field::Bytes(&block.header.hash.0))));
+        assert!(header.contains(&(3, Field::Varint(block.header.number))));
+    }
+}
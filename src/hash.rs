@@ -1,13 +1,89 @@
+use crate::error::{Error, Result};
 use blake2::{Blake2s256, Digest};
+use serde::{Deserialize, Serialize};
+use sha3::{Keccak256, Sha3_256};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub(crate) struct Hash(pub [u8; 32]);
+pub struct Hash(pub [u8; 32]);
 
-impl From<&str> for Hash {
-    fn from(s: &str) -> Self {
-        let mut hasher = HashBuilder::new();
-        hasher.update(s.as_bytes());
-        hasher.finalize()
+impl From<[u8; 32]> for Hash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Hash(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for Hash {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        decode_bytes32(bytes).map(Hash)
+    }
+}
+
+impl Hash {
+    /// The all-zero hash, which stands for "none", as the parent of genesis does.
+    pub const fn zero() -> Self {
+        Hash([0; 32])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 32]
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The hash of the UTF-8 bytes of `s` with the default algorithm, for hashes no chain
+    /// computes, such as test data. See [`HashAlgorithm::digest`].
+    pub fn digest_of(s: &str) -> Self {
+        HashAlgorithm::default().digest(s.as_bytes())
+    }
+
+    /// Parse exactly `0x` followed by 64 hex digits of either case, as [`Display`] writes them.
+    /// The digits are decoded in time independent of their values, so parsing a secret hash
+    /// does not leak it through timing.
+    ///
+    /// [`Display`]: std::fmt::Display
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let digits = match s.strip_prefix("0x") {
+            Some(digits) if digits.len() == 64 => digits.as_bytes(),
+            _ => {
+                return Err(Error::new(format!(
+                    "expected 0x and 64 hex digits, not {} characters",
+                    s.len()
+                )))
+            }
+        };
+        let mut bytes = [0; 32];
+        let mut invalid = 0;
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks_exact(2)) {
+            let (high, low) = (hex_value(pair[0]), hex_value(pair[1]));
+            invalid |= (high | low) >> 4;
+            *byte = (high << 4) | (low & 0xf);
+        }
+        if invalid != 0 {
+            return Err(Error::new("hash has a character that is not a hex digit"));
+        }
+        Ok(Hash(bytes))
+    }
+}
+
+/// The value of hex digit `c`, or 0xff if it is not one, computed without branching on `c`.
+fn hex_value(c: u8) -> u8 {
+    let digit = c.wrapping_sub(b'0');
+    // Setting bit 5 folds upper case letters to lower case.
+    let letter = (c | 0x20).wrapping_sub(b'a');
+    let is_digit = ((digit < 10) as u8).wrapping_neg();
+    let is_letter = ((letter < 6) as u8).wrapping_neg();
+    (digit & is_digit) | (letter.wrapping_add(10) & is_letter) | !(is_digit | is_letter)
+}
+
+impl std::str::FromStr for Hash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Hash::from_hex(s)
     }
 }
 
@@ -16,24 +92,183 @@ impl std::fmt::Display for Hash {
         write!(f, "0x{}", hex::encode(self.0))
     }
 }
+
+impl serde::Serialize for Hash {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Hash {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Hash::from_hex(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Check that `bytes` holds exactly 32 bytes, as [`Hash`] and [`crate::address::Address`] do.
+pub(crate) fn decode_bytes32(bytes: &[u8]) -> Result<[u8; 32]> {
+    bytes
+        .try_into()
+        .map_err(|_| Error::new(format!("expected 32 bytes, not {}", bytes.len())))
+}
+
+/// Decode a hex string of 32 bytes, with or without the `0x` prefix that the `Display` impls of
+/// [`Hash`] and [`crate::address::Address`] write. For typed input; see [`Hash::from_hex`] for
+/// the strict form.
+pub(crate) fn decode_hex32(s: &str) -> Result<[u8; 32]> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    let bytes = hex::decode(digits).map_err(|e| Error::new(format!("bad hex {s:?}: {e:?}")))?;
+    bytes
+        .try_into()
+        .map_err(|_| Error::new(format!("expected 32 bytes in {s:?}")))
+}
+
+/// A 256-bit hash function.
+pub(crate) trait Hasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> Hash;
+}
+
+impl<D: Digest + Send> Hasher for D {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+    fn finalize(self: Box<Self>) -> Hash {
+        let digest = Digest::finalize(*self);
+        Hash(digest.as_slice().try_into().expect("a 256-bit digest"))
+    }
+}
+
+/// The hash function behind every [`Hash`] a chain computes: block and transaction hashes,
+/// state roots and derived addresses. Chosen at genesis, as changing it changes every hash, and
+/// passed to everything that hashes for a chain, so that one process can run chains of
+/// different algorithms. See [`crate::ChainConfig::hash`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake2s,
+    /// Keccak-256 as Ethereum uses it, which pads differently from the standardized SHA3-256.
+    Keccak256,
+    Sha3_256,
+}
+
+impl HashAlgorithm {
+    /// The hash of `data`.
+    pub fn digest(self, data: &[u8]) -> Hash {
+        let mut hasher = HashBuilder::new(self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub(crate) fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            HashAlgorithm::Blake2s => Box::new(Blake2s256::new()),
+            HashAlgorithm::Keccak256 => Box::new(Keccak256::new()),
+            HashAlgorithm::Sha3_256 => Box::new(Sha3_256::new()),
+        }
+    }
+}
+
+/// Which of the algorithms hashes the encoding `bytes` to `hash`, for databases that predate
+/// recording their chain's algorithm.
+pub(crate) fn algorithm_of(bytes: &[u8], hash: Hash) -> Option<HashAlgorithm> {
+    let algorithms = [
+        HashAlgorithm::Blake2s,
+        HashAlgorithm::Keccak256,
+        HashAlgorithm::Sha3_256,
+    ];
+    algorithms.into_iter().find(|algorithm| {
+        let mut hasher = algorithm.hasher();
+        hasher.update(bytes);
+        hasher.finalize() == hash
+    })
+}
+
 pub(crate) struct HashBuilder {
-    hasher: Blake2s256,
+    hasher: Box<dyn Hasher>,
 }
 
-// HashBuilder is currently built on Blake2s256, which is a 256-bit hash function.
 impl HashBuilder {
     #[inline]
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(algorithm: HashAlgorithm) -> Self {
         Self {
-            hasher: Blake2s256::new(),
+            hasher: algorithm.hasher(),
         }
     }
     #[inline]
     pub(crate) fn update<T: std::convert::AsRef<[u8]>>(&mut self, data: T) {
-        self.hasher.update(data);
+        self.hasher.update(data.as_ref());
     }
     #[inline]
     pub(crate) fn finalize(self) -> Hash {
-        Hash(self.hasher.finalize().into())
+        self.hasher.finalize()
     }
 }
+
+#[test]
+fn test_hash_from_hex() {
+    let hash = Hash::digest_of("message");
+    assert_eq!(Hash::from_hex(&hash.to_string()).unwrap(), hash);
+    assert_eq!(hash.to_string().parse::<Hash>().unwrap(), hash);
+    let upper = format!("0x{}", hex::encode_upper(hash.0));
+    assert_eq!(Hash::from_hex(&upper).unwrap(), hash);
+    let json = serde_json::to_string(&hash).unwrap();
+    assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), hash);
+
+    let digits = hex::encode(hash.0);
+    for bad in [
+        digits.clone(),
+        format!("0x{}", &digits[2..]),
+        format!("0x{digits}00"),
+        format!("0X{digits}"),
+        format!("0x{}g", &digits[1..]),
+        format!("0x{}é", &digits[2..]),
+        "abcdef".to_string(),
+    ] {
+        assert!(Hash::from_hex(&bad).is_err(), "{bad}");
+    }
+    assert!(serde_json::from_str::<Hash>(&format!("{digits:?}")).is_err());
+    for c in 0..=u8::MAX {
+        let expected = (c as char).to_digit(16).map_or(0xff, |value| value as u8);
+        assert_eq!(hex_value(c), expected);
+    }
+}
+
+#[test]
+fn test_hash_bytes() {
+    let hash = Hash::digest_of("message");
+    assert_eq!(Hash::try_from(&hash.as_bytes()[..]).unwrap(), hash);
+    assert_eq!(Hash::from(*hash.as_bytes()), hash);
+    let error = Hash::try_from(&hash.as_bytes()[1..]).unwrap_err();
+    assert_eq!(error.message(), "expected 32 bytes, not 31");
+    assert!(Hash::try_from(&[0; 33][..]).is_err());
+    assert!(Hash::zero().is_zero());
+    assert!(!hash.is_zero());
+}
+
+#[test]
+fn test_hash_algorithms() {
+    let empty = |algorithm: HashAlgorithm| algorithm.hasher().finalize().to_string();
+    assert_eq!(
+        empty(HashAlgorithm::Keccak256),
+        "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    assert_eq!(
+        empty(HashAlgorithm::Sha3_256),
+        "0xa7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"
+    );
+    let mut hasher = HashAlgorithm::Blake2s.hasher();
+    hasher.update(b"message");
+    assert_eq!(hasher.finalize(), Hash::digest_of("message"));
+    assert_eq!(
+        HashAlgorithm::Keccak256.digest(b""),
+        HashAlgorithm::Keccak256.hasher().finalize()
+    );
+}
@@ -0,0 +1,196 @@
+//! Liveness and readiness probes, served at `/health` and `/ready` by [`crate::http`].
+//!
+//! A node is healthy while its database answers queries, and ready to serve traffic once it is
+//! also caught up: not syncing, with a head no older than [`HttpConfig::max_head_age`] if that is
+//! set, and if it has peers, in touch with at least one. Load balancers route by readiness;
+//! orchestrators restart nodes that stop being healthy. The peer count is how many of
+//! [`PeerConfig::peers`] answered the sync task's last round, and is null for a node without
+//! peers.
+//!
+//! [`HttpConfig::max_head_age`]: crate::http::HttpConfig::max_head_age
+//! [`PeerConfig::peers`]: crate::peers::PeerConfig::peers
+use crate::http::Response;
+use crate::{Blockchain, Blockhead};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Health {
+    /// The error reading the database, if it could not be read.
+    pub database_error: Option<String>,
+    pub syncing: bool,
+    /// The height of the canonical head, if the database could be read.
+    pub head: Option<u64>,
+    /// How far the local clock is past the head's timestamp.
+    pub head_age: Option<Duration>,
+    /// How many peers answered the sync task, if the node has any.
+    pub peers: Option<usize>,
+}
+
+impl Health {
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.database_error.is_none()
+    }
+
+    /// Why the node is not ready, given the oldest head it may have, if any.
+    pub(crate) fn unready_reasons(&self, max_head_age: Option<Duration>) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if let Some(error) = &self.database_error {
+            reasons.push(format!("database unavailable: {error}"));
+        }
+        if self.syncing {
+            reasons.push("syncing".to_string());
+        }
+        if self.peers == Some(0) {
+            reasons.push("no peers reachable".to_string());
+        }
+        if let (Some(age), Some(max)) = (self.head_age, max_head_age) {
+            if age > max {
+                reasons.push(format!("head is {}s old", age.as_secs()));
+            }
+        }
+        reasons
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "database": self.database_error.as_deref().unwrap_or("ok"),
+            "syncing": self.syncing,
+            "head": self.head,
+            "head_age_seconds": self.head_age.map(|age| age.as_secs_f64()),
+            "peers": self.peers,
+        })
+    }
+}
+
+impl Blockhead {
+    /// The health of the database, the head and the node's peers. See [`Blockchain::syncing`]
+    /// for the rest.
+    pub(crate) fn health(&self) -> Health {
+        match self.head() {
+            Ok(head) => Health {
+                database_error: None,
+                syncing: false,
+                head: Some(head.number),
+                head_age: Some(Duration::from_nanos(
+                    self.now_nanos().saturating_sub(head.timestamp),
+                )),
+                peers: self.peer_count(),
+            },
+            Err(error) => Health {
+                database_error: Some(error.message().to_string()),
+                syncing: false,
+                head: None,
+                head_age: None,
+                peers: self.peer_count(),
+            },
+        }
+    }
+
+    fn peer_count(&self) -> Option<usize> {
+        let configured = !self.config.peers.peers.is_empty();
+        configured.then(|| self.peers.reachable())
+    }
+}
+
+async fn check(blockhead: &Arc<Blockhead>) -> Health {
+    let reader = blockhead.clone();
+    let mut health = match tokio::task::spawn_blocking(move || reader.health()).await {
+        Ok(health) => health,
+        Err(error) => Health {
+            database_error: Some(error.to_string()),
+            syncing: false,
+            head: None,
+            head_age: None,
+            peers: blockhead.peer_count(),
+        },
+    };
+    health.syncing = blockhead.syncing().await;
+    health
+}
+
+/// Answer `/health`: 200 while the database answers, and 503 otherwise.
+pub(crate) async fn handle_health(blockhead: &Arc<Blockhead>) -> Response {
+    let health = check(blockhead).await;
+    let status = if health.is_healthy() { 200 } else { 503 };
+    Response::json(status, &health.to_json())
+}
+
+/// Answer `/ready`: 200 when ready for traffic, and 503 with the reasons otherwise.
+pub(crate) async fn handle_ready(blockhead: &Arc<Blockhead>) -> Response {
+    let health = check(blockhead).await;
+    let reasons = health.unready_reasons(blockhead.config.http.max_head_age);
+    let mut body = health.to_json();
+    body["reasons"] = json!(reasons);
+    Response::json(if reasons.is_empty() { 200 } else { 503 }, &body)
+}
+
+#[tokio::test]
+async fn test_health_endpoints() {
+    use crate::testkit::TestChain;
+
+    let mut config = crate::genesis::ChainConfig::default();
+    config.http.max_head_age = Some(Duration::from_secs(30));
+    let chain = TestChain::with_config(config);
+    let clock = chain.clock.clone();
+    clock.advance(Duration::from_secs(100));
+    chain.produce();
+    clock.advance(Duration::from_secs(10));
+    let address = crate::http::serve_locally(Arc::new(chain.blockhead)).await;
+
+    let (status, body) = crate::http::send(address, "GET /health HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "database": "ok",
+            "syncing": false,
+            "head": 1,
+            "head_age_seconds": 10.0,
+            "peers": null,
+        })
+    );
+    let (status, body) = crate::http::send(address, "GET /ready HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["reasons"], json!([]));
+
+    clock.advance(Duration::from_secs(30));
+    let (status, body) = crate::http::send(address, "GET /ready HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 503);
+    assert_eq!(body["reasons"], json!(["head is 40s old"]));
+}
+
+#[tokio::test]
+async fn test_peer_health() {
+    use crate::http::{send, serve_locally, Endpoint};
+    use crate::peers::{self, Peer};
+    use crate::testkit::TestChain;
+
+    let source = TestChain::new();
+    source.produce_many(5);
+    let source = serve_locally(Arc::new(source.blockhead)).await;
+    let mut config = crate::genesis::ChainConfig::default();
+    config.peers.peers = vec![format!("http://{source}")];
+    let target = Arc::new(TestChain::with_config(config).blockhead);
+    let address = serve_locally(target.clone()).await;
+
+    // Until the sync task reaches a peer, the node is not ready.
+    let (status, body) = send(address, "GET /ready HTTP/1.1\r\n\r\n").await;
+    assert_eq!((status, &body["peers"]), (503, &json!(0)));
+    assert_eq!(body["reasons"], json!(["no peers reachable"]));
+
+    // The peer is five blocks ahead, so the node is syncing until it imports them.
+    let mut peers = [Peer::new(Endpoint::parse(&target.config.peers.peers[0]))];
+    peers::sync(&target, &mut peers).await;
+    let (status, body) = send(address, "GET /ready HTTP/1.1\r\n\r\n").await;
+    assert_eq!((status, &body["peers"]), (503, &json!(1)));
+    assert_eq!(body["reasons"], json!(["syncing"]));
+    while target.import_next() {}
+    let (status, body) = send(address, "GET /ready HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        (&body["head"], &body["syncing"]),
+        (&json!(5), &json!(false))
+    );
+}
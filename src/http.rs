@@ -0,0 +1,836 @@
+//! The node's HTTP server, for clients that cannot link the [`crate::Blockchain`] trait.
+//!
+//! The server speaks just enough HTTP/1.1 for API clients: each connection carries one request,
+//! whose body is sized by `Content-Length`, and is closed once the response is written, unless
+//! the request upgrades it to a WebSocket (see [`crate::websocket`]). It listens
+//! at [`HttpConfig::listen`], if set, and on a node with a data directory, also on a Unix socket
+//! there (see [`HttpConfig::ipc`]). It is only built with the `http` feature, without which this
+//! module holds just its config.
+#[cfg(feature = "http")]
+use crate::error::{Error, ErrorKind, Result};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+#[cfg(feature = "http")]
+use crate::metrics::{self, HttpMetrics};
+#[cfg(feature = "http")]
+use crate::spec::DataDir;
+#[cfg(feature = "http")]
+use crate::{
+    admin, balances, block_range, dev, explorer, faucet, fee, graphql, health, mempool, peers, rpc,
+    stats, websocket, Blockhead,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http")]
+use serde_json::{json, Value};
+#[cfg(feature = "http")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "http")]
+use std::time::Instant;
+#[cfg(feature = "http")]
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+#[cfg(feature = "http")]
+use tokio::net::TcpListener;
+#[cfg(all(feature = "http", unix))]
+use tokio::net::UnixListener;
+
+/// The longest request line or header accepted.
+#[cfg(feature = "http")]
+const MAX_LINE: usize = 8 * 1024;
+#[cfg(feature = "http")]
+const MAX_HEADERS: usize = 64;
+#[cfg(feature = "http")]
+const MAX_BODY: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// The address to listen on, such as `127.0.0.1:8545`. Without one, the server is off.
+    pub listen: Option<String>,
+    /// Also serve on a Unix socket in the data directory, `blockhead.ipc`, whether or not
+    /// [`HttpConfig::listen`] is set, so that local tools can reach the node without a network
+    /// port. Unix only.
+    pub ipc: bool,
+    /// Answer GraphQL queries at `/graphql`. See [`crate::graphql`].
+    pub graphql: bool,
+    /// Accept WebSocket connections at `/ws`, which carry log subscriptions. See
+    /// [`crate::websocket`].
+    pub websocket: bool,
+    /// Serve a read-only block explorer at `/explorer`, for dev networks. It reads through
+    /// `/graphql`, so needs [`HttpConfig::graphql`] too. See [`crate::explorer`].
+    pub explorer: bool,
+    /// Serve the `/dev` endpoints, which mine blocks on demand and move the node's time. Only for
+    /// chains the node alone produces; `blockhead run --dev` sets it. See [`crate::dev`].
+    pub dev: bool,
+    /// The oldest the head may be for `/ready` to report the node ready. Unset, the head's age
+    /// does not matter, as on dev chains that only produce blocks on demand. See
+    /// [`crate::health`].
+    pub max_head_age: Option<Duration>,
+    /// The bearer token that unlocks the `/admin` endpoints. Unset, they are off. See
+    /// [`crate::admin`].
+    pub admin_token: Option<String>,
+    /// Requests the node takes longer than this to answer are logged, with long hex strings
+    /// such as transaction data cut out. Unset, none are. See [`crate::metrics`].
+    pub slow_request: Option<Duration>,
+    /// Answer gRPC-Web calls at `/blockhead.v1.Blockhead/`. Only with the `grpc` feature. See
+    /// [`crate::grpc`].
+    pub grpc: bool,
+    /// The origins, such as `https://explorer.example`, whose browser pages may call the server,
+    /// or `*` for any. Empty, browsers keep pages on other origins from reading its responses.
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            ipc: true,
+            graphql: true,
+            websocket: true,
+            explorer: false,
+            dev: false,
+            max_head_age: None,
+            admin_token: None,
+            slow_request: Some(Duration::from_secs(1)),
+            grpc: true,
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub method: String,
+    /// The path, without the query string.
+    pub path: String,
+    /// The query string, without the `?`.
+    pub query: String,
+    /// The `Authorization` header, if sent.
+    pub authorization: Option<String>,
+    /// The `Origin` header, which browsers send with requests from another origin's pages.
+    pub origin: Option<String>,
+    /// The `Upgrade` header, naming the protocol a client wants the connection switched to.
+    pub upgrade: Option<String>,
+    /// The `Sec-WebSocket-Key` and `Sec-WebSocket-Version` headers of a WebSocket handshake.
+    pub websocket_key: Option<String>,
+    pub websocket_version: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[cfg(feature = "http")]
+impl Request {
+    /// The value of the first `name=value` pair in the query string. Values are not
+    /// percent-decoded.
+    pub(crate) fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    /// Headers besides `Content-Type`, `Content-Length` and `Connection`.
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+#[cfg(feature = "http")]
+impl Response {
+    pub(crate) fn json(status: u16, value: &Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    /// An empty response, such as the answer to a CORS preflight request.
+    pub(crate) fn no_content() -> Self {
+        Self {
+            status: 204,
+            content_type: "text/plain",
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A JSON `{"error": message}` body.
+    pub(crate) fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self::json(status, &json!({"error": message.to_string()}))
+    }
+
+    /// A JSON body for `error`: its message as `error`, its kind's [`ErrorKind::code`] as
+    /// `code`, and the kind's [`ErrorKind::data`], if any, as `data`.
+    pub(crate) fn from_error(status: u16, error: &Error) -> Self {
+        let mut body = json!({"error": error.message(), "code": error.kind().code()});
+        if let Some(data) = error.kind().data() {
+            body["data"] = data;
+        }
+        Self::json(status, &body)
+    }
+}
+
+#[cfg(feature = "http")]
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(feature = "http")]
+async fn read_line(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Result<String> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.len() > MAX_LINE {
+        return Err(Error::new("request line or header too long"));
+    }
+    let line = String::from_utf8(line).map_err(|_| Error::new("request is not UTF-8"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(feature = "http")]
+pub(crate) async fn read_request(stream: impl AsyncRead + Unpin) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let request_line = read_line(&mut reader).await?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::new(format!("bad request line {request_line:?}")));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut content_length = 0;
+    let mut authorization = None;
+    let mut origin = None;
+    let mut upgrade = None;
+    let mut websocket_key = None;
+    let mut websocket_version = None;
+    for _ in 0..=MAX_HEADERS {
+        let header = read_line(&mut reader).await?;
+        if header.is_empty() {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await?;
+            return Ok(Request {
+                method: method.to_string(),
+                path: path.to_string(),
+                query: query.to_string(),
+                authorization,
+                origin,
+                upgrade,
+                websocket_key,
+                websocket_version,
+                body,
+            });
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(Error::new(format!("bad header {header:?}")));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse()?;
+            if content_length > MAX_BODY {
+                return Err(Error::new(format!(
+                    "body of {content_length} bytes is too large"
+                )));
+            }
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.trim().to_string());
+        } else if name.eq_ignore_ascii_case("origin") {
+            origin = Some(value.trim().to_string());
+        } else if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = Some(value.trim().to_string());
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            websocket_key = Some(value.trim().to_string());
+        } else if name.eq_ignore_ascii_case("sec-websocket-version") {
+            websocket_version = Some(value.trim().to_string());
+        }
+    }
+    Err(Error::new("too many headers"))
+}
+
+#[cfg(feature = "http")]
+pub(crate) async fn write_response(
+    mut stream: impl AsyncWrite + Unpin,
+    response: &Response,
+) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    // A switch of protocols has no body, and the connection stays open for the new protocol.
+    if response.status != 101 {
+        head.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.content_type,
+            response.body.len()
+        ));
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// The response to `request`.
+#[cfg(feature = "http")]
+async fn route(
+    blockhead: &Arc<Blockhead>,
+    config: &HttpConfig,
+    metrics: &HttpMetrics,
+    request: &Request,
+) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        // A browser asking whether it may send the real request. The answer is in the headers
+        // [`cors_headers`] adds.
+        ("OPTIONS", _) => Response::no_content(),
+        ("GET", "/health") => health::handle_health(blockhead).await,
+        ("GET", "/ready") => health::handle_ready(blockhead).await,
+        ("GET", "/mempool/content") => mempool::handle_content(blockhead).await,
+        ("GET", "/mempool/status") => mempool::handle_status(blockhead).await,
+        ("POST", "/transactions") => mempool::handle_send(blockhead, &request.body).await,
+        ("GET", "/blocks") => block_range::handle(blockhead, request).await,
+        ("POST", "/blocks") => peers::handle(blockhead, &request.body).await,
+        ("GET", path) if path.starts_with("/blocks/") => {
+            peers::handle_get(blockhead, &path["/blocks/".len()..]).await
+        }
+        ("POST", "/balances") => balances::handle(blockhead, &request.body).await,
+        ("POST", path) if path.starts_with(rpc::PREFIX) => {
+            rpc::handle(blockhead, &path[rpc::PREFIX.len()..], &request.body).await
+        }
+        ("GET", "/stats") => stats::handle(blockhead, request).await,
+        ("GET", "/fees") => fee::handle(blockhead).await,
+        ("GET", "/metrics/http") => metrics.handle(),
+        ("GET", "/metrics/db") => metrics::handle_db(blockhead).await,
+        (_, path) if path.starts_with("/admin/") => admin::handle(blockhead, request).await,
+        (_, path) if path.starts_with("/dev/") && config.dev => {
+            dev::handle(blockhead, request).await
+        }
+        #[cfg(feature = "grpc")]
+        ("POST", path) if path.starts_with(grpc::PREFIX) && config.grpc => {
+            grpc::handle(blockhead, &path[grpc::PREFIX.len()..], &request.body).await
+        }
+        ("POST", "/graphql") if config.graphql => graphql::handle(blockhead, &request.body).await,
+        (_, "/graphql") if config.graphql => Response::error(405, "use POST"),
+        ("GET", "/explorer") if config.explorer => explorer::handle(),
+        ("POST", "/faucet") if blockhead.config.faucet.enabled => {
+            faucet::handle(blockhead, request).await
+        }
+        _ => Response::error(404, format!("no endpoint {}", request.path)),
+    }
+}
+
+/// The CORS headers to answer `request` with: none unless its `Origin` is one of
+/// [`HttpConfig::cors_origins`].
+#[cfg(feature = "http")]
+fn cors_headers(config: &HttpConfig, request: &Request) -> Vec<(&'static str, String)> {
+    let Some(origin) = &request.origin else {
+        return Vec::new();
+    };
+    if !config
+        .cors_origins
+        .iter()
+        .any(|allowed| allowed == "*" || allowed == origin)
+    {
+        return Vec::new();
+    }
+    let mut headers = vec![
+        ("Access-Control-Allow-Origin", origin.clone()),
+        ("Vary", "Origin".to_string()),
+    ];
+    if request.method == "OPTIONS" {
+        headers.extend([
+            ("Access-Control-Allow-Methods", "GET, POST".to_string()),
+            (
+                "Access-Control-Allow-Headers",
+                "Authorization, Content-Type, X-Grpc-Web, X-User-Agent".to_string(),
+            ),
+            ("Access-Control-Max-Age", "600".to_string()),
+        ]);
+    }
+    headers
+}
+
+/// What the node's listeners share: the node, its HTTP config and the metrics of every request.
+#[cfg(feature = "http")]
+pub(crate) struct Server {
+    blockhead: Arc<Blockhead>,
+    config: HttpConfig,
+    metrics: HttpMetrics,
+}
+
+#[cfg(feature = "http")]
+impl Server {
+    pub(crate) fn new(blockhead: Arc<Blockhead>) -> Arc<Self> {
+        Arc::new(Self {
+            config: blockhead.config.http.clone(),
+            blockhead,
+            metrics: HttpMetrics::default(),
+        })
+    }
+
+    /// Read one request from `stream` and write the response to it, then if the request opened a
+    /// WebSocket, serve that until it closes.
+    async fn answer(&self, stream: impl AsyncRead + AsyncWrite + Unpin) -> Result<()> {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let response = match read_request(&mut reader).await {
+            Ok(request) if request.path == "/ws" && self.config.websocket => {
+                let response = websocket::handshake(&request);
+                self.metrics
+                    .record(&request.path, response.status, Duration::ZERO);
+                if response.status == 101 {
+                    write_response(&mut writer, &response).await?;
+                    return websocket::serve(self.blockhead.clone(), reader, writer).await;
+                }
+                response
+            }
+            #[cfg(feature = "grpc")]
+            Ok(request) if grpc::is_new_blocks(&request.path) && self.config.grpc => {
+                self.metrics.record(&request.path, 200, Duration::ZERO);
+                let headers = cors_headers(&self.config, &request);
+                return grpc::serve_new_blocks(self.blockhead.clone(), headers, writer).await;
+            }
+            Ok(request) => {
+                let start = Instant::now();
+                let mut response =
+                    route(&self.blockhead, &self.config, &self.metrics, &request).await;
+                response
+                    .headers
+                    .extend(cors_headers(&self.config, &request));
+                let latency = start.elapsed();
+                self.metrics.record(&request.path, response.status, latency);
+                metrics::log_if_slow(&request, response.status, latency, self.config.slow_request);
+                response
+            }
+            Err(error) => Response::error(400, error.message()),
+        };
+        write_response(writer, &response).await
+    }
+}
+
+/// Answer connections accepted by `listener` until the task is dropped.
+#[cfg(feature = "http")]
+pub(crate) async fn serve(listener: TcpListener, server: Arc<Server>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                log::warn!("accepting an HTTP connection failed: {error}");
+                continue;
+            }
+        };
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(error) = server.answer(stream).await {
+                log::debug!("answering {peer} failed: {error}");
+            }
+        });
+    }
+}
+
+/// Answer connections accepted by `listener`, a Unix socket, until the task is dropped.
+#[cfg(all(feature = "http", unix))]
+pub(crate) async fn serve_ipc(listener: UnixListener, server: Arc<Server>) {
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                log::warn!("accepting an IPC connection failed: {error}");
+                continue;
+            }
+        };
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(error) = server.answer(stream).await {
+                log::debug!("answering an IPC connection failed: {error}");
+            }
+        });
+    }
+}
+
+/// Listen on a Unix socket at `path` that only the current user may connect to. A socket left
+/// there by a node that stopped without removing it is replaced, but not one a node still
+/// serves.
+#[cfg(all(feature = "http", unix))]
+pub(crate) fn bind_ipc(path: &Path) -> Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(Error::new(format!(
+                "a node is already serving at {}",
+                path.display()
+            )));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Listen at [`HttpConfig::listen`], if set, and on a Unix socket at `ipc`, if given and
+/// [`HttpConfig::ipc`] is on, and serve both until the task is dropped.
+#[cfg(feature = "http")]
+pub(crate) async fn run(blockhead: Arc<Blockhead>, ipc: Option<&Path>) -> Result<()> {
+    let server = Server::new(blockhead);
+    let tcp = match &server.config.listen {
+        Some(address) => {
+            let listener = TcpListener::bind(address).await?;
+            log::info!("serving HTTP at {}", listener.local_addr()?);
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(unix)]
+    let ipc = match ipc.filter(|_| server.config.ipc) {
+        Some(path) => {
+            let listener = bind_ipc(path)?;
+            log::info!("serving HTTP at {}", path.display());
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let _ = ipc;
+    tokio::join!(
+        async {
+            if let Some(listener) = tcp {
+                serve(listener, server.clone()).await;
+            }
+        },
+        async {
+            #[cfg(unix)]
+            if let Some(listener) = ipc {
+                serve_ipc(listener, server.clone()).await;
+            }
+        },
+    );
+    Ok(())
+}
+
+/// A connection to an [`Endpoint`], open but not yet sent a request.
+#[cfg(feature = "http")]
+pub(crate) enum Connection {
+    Tcp(tokio::net::TcpStream),
+    #[cfg(unix)]
+    Ipc(tokio::net::UnixStream),
+}
+
+/// Where a running node serves HTTP, as the command line names it.
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    /// A Unix socket, such as a data directory's. See [`HttpConfig::ipc`].
+    Ipc(PathBuf),
+    /// A network address, `<host>:<port>`.
+    Tcp(String),
+}
+
+#[cfg(feature = "http")]
+impl Endpoint {
+    /// Read `s`: `http://<host>:<port>` or `<host>:<port>`, a data directory, whose socket is
+    /// meant, or the path of a socket.
+    pub(crate) fn parse(s: &str) -> Self {
+        if let Some(address) = s.strip_prefix("http://") {
+            return Self::Tcp(address.trim_end_matches('/').to_string());
+        }
+        let path = Path::new(s);
+        if path.is_dir() {
+            return Self::Ipc(DataDir::new(path).ipc_path());
+        }
+        let is_address = s
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        if is_address && !path.exists() {
+            return Self::Tcp(s.to_string());
+        }
+        Self::Ipc(path.to_path_buf())
+    }
+
+    /// Send `method` `target`, with `body` unless empty, and return the response's status and
+    /// JSON body.
+    pub(crate) async fn request(
+        &self,
+        method: &str,
+        target: &str,
+        body: &[u8],
+    ) -> Result<(u16, Value)> {
+        let connection = self.connect().await?;
+        self.send(connection, method, target, body).await
+    }
+
+    /// Open a connection for a request. A request whose connection failed never reached the
+    /// node.
+    pub(crate) async fn connect(&self) -> Result<Connection> {
+        let unreachable =
+            |error: std::io::Error| Error::new(format!("no node is serving at {self}: {error}"));
+        match self {
+            Self::Tcp(address) => Ok(Connection::Tcp(
+                tokio::net::TcpStream::connect(address)
+                    .await
+                    .map_err(unreachable)?,
+            )),
+            #[cfg(unix)]
+            Self::Ipc(path) => Ok(Connection::Ipc(
+                tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(unreachable)?,
+            )),
+            #[cfg(not(unix))]
+            Self::Ipc(_) => Err(Error::new("IPC sockets are only supported on Unix")),
+        }
+    }
+
+    /// [`Endpoint::request`] over `connection`, opened by [`Endpoint::connect`].
+    pub(crate) async fn send(
+        &self,
+        connection: Connection,
+        method: &str,
+        target: &str,
+        body: &[u8],
+    ) -> Result<(u16, Value)> {
+        let head = format!(
+            "{method} {target} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+            match self {
+                Self::Tcp(address) => address.as_str(),
+                Self::Ipc(_) => "localhost",
+            },
+            body.len()
+        );
+        let response = match connection {
+            Connection::Tcp(stream) => exchange(stream, head.as_bytes(), body).await?,
+            #[cfg(unix)]
+            Connection::Ipc(stream) => exchange(stream, head.as_bytes(), body).await?,
+        };
+        let response =
+            String::from_utf8(response).map_err(|_| Error::new("response is not UTF-8"))?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| Error::new("truncated response"))?;
+        let status = head
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| Error::new(format!("bad status line {head:?}")))?;
+        let body = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(body)?
+        };
+        Ok((status, body))
+    }
+
+    /// [`Endpoint::request`], failing with the server's error message unless the status is a
+    /// success. The error has the kind the server's `code` and `data` describe.
+    pub(crate) async fn call(&self, method: &str, target: &str, body: &[u8]) -> Result<Value> {
+        let (status, value) = self.request(method, target, body).await?;
+        answer(target, status, value)
+    }
+}
+
+/// The body of a response to `target` with `status`, or unless the status is a success, the
+/// server's error, with the kind its `code` and `data` describe.
+#[cfg(feature = "http")]
+pub(crate) fn answer(target: &str, status: u16, value: Value) -> Result<Value> {
+    if status >= 400 {
+        let message = value["error"]
+            .as_str()
+            .map_or(value.to_string(), str::to_string);
+        let kind = value["code"]
+            .as_i64()
+            .map(|code| ErrorKind::from_code(code, &value["data"]))
+            .unwrap_or_default();
+        return Err(Error::with_kind(
+            kind,
+            format!("{target} answered {status}: {message}"),
+        ));
+    }
+    Ok(value)
+}
+
+#[cfg(feature = "http")]
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Ipc(path) => write!(f, "{}", path.display()),
+            Self::Tcp(address) => write!(f, "http://{address}"),
+        }
+    }
+}
+
+/// Write `head` and `body` to `stream`, and read the response until the server closes it.
+#[cfg(feature = "http")]
+async fn exchange(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    head: &[u8],
+    body: &[u8],
+) -> Result<Vec<u8>> {
+    stream.write_all(head).await?;
+    stream.write_all(body).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(response)
+}
+
+/// Send `request` to the server at `address` and return the whole response, for tests.
+#[cfg(all(test, feature = "http"))]
+pub(crate) async fn send_raw(address: std::net::SocketAddr, request: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Send `request` to the server at `address`, for tests.
+#[cfg(all(test, feature = "http"))]
+pub(crate) async fn send(address: std::net::SocketAddr, request: &str) -> (u16, Value) {
+    let response = send_raw(address, request).await;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+/// Serve `blockhead` with its [`HttpConfig`] on a free local port, for tests.
+#[cfg(all(test, feature = "http"))]
+pub(crate) async fn serve_locally(blockhead: Arc<Blockhead>) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Server::new(blockhead)));
+    address
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_http_server() {
+    let request = read_request(&b"POST /graphql?x=1 HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}"[..]);
+    assert_eq!(
+        request.await.unwrap(),
+        Request {
+            method: "POST".to_string(),
+            path: "/graphql".to_string(),
+            query: "x=1".to_string(),
+            authorization: None,
+            origin: None,
+            upgrade: None,
+            websocket_key: None,
+            websocket_version: None,
+            body: b"{}".to_vec(),
+        }
+    );
+    let oversized = format!(
+        "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        MAX_BODY + 1
+    );
+    assert!(read_request(oversized.as_bytes()).await.is_err());
+
+    let address = serve_locally(Arc::new(Blockhead::new(":memory:").unwrap())).await;
+    let (status, body) = send(address, "GET /nowhere HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "no endpoint /nowhere");
+    let (status, _) = send(address, "nonsense\r\n\r\n").await;
+    assert_eq!(status, 400);
+
+    let endpoint = Endpoint::parse(&format!("http://{address}/"));
+    assert_eq!(endpoint, Endpoint::Tcp(address.to_string()));
+    assert_eq!(Endpoint::parse(&address.to_string()), endpoint);
+    assert!(endpoint.call("GET", "/health", b"").await.is_ok());
+    let error = endpoint.call("GET", "/nowhere", b"").await.unwrap_err();
+    assert_eq!(
+        error.message(),
+        "/nowhere answered 404: no endpoint /nowhere"
+    );
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_cors() {
+    use crate::genesis::ChainConfig;
+    use crate::testkit::TestChain;
+
+    let mut config = ChainConfig::default();
+    config.http.cors_origins = vec!["https://explorer.example".to_string()];
+    let address = serve_locally(Arc::new(TestChain::with_config(config).blockhead)).await;
+    let preflight = send_raw(
+        address,
+        "OPTIONS /graphql HTTP/1.1\r\nOrigin: https://explorer.example\r\n\r\n",
+    )
+    .await;
+    assert!(preflight.starts_with("HTTP/1.1 204 No Content\r\n"));
+    assert!(preflight.contains("Access-Control-Allow-Origin: https://explorer.example\r\n"));
+    assert!(preflight.contains("Access-Control-Allow-Methods: GET, POST\r\n"));
+    let response = send_raw(
+        address,
+        "GET /health HTTP/1.1\r\nOrigin: https://explorer.example\r\n\r\n",
+    )
+    .await;
+    assert!(response.contains("Access-Control-Allow-Origin: https://explorer.example\r\n"));
+    assert!(!response.contains("Access-Control-Allow-Methods"));
+    let response = send_raw(
+        address,
+        "GET /health HTTP/1.1\r\nOrigin: https://elsewhere.example\r\n\r\n",
+    )
+    .await;
+    assert!(!response.contains("Access-Control"));
+
+    let mut config = ChainConfig::default();
+    config.http.cors_origins = vec!["*".to_string()];
+    let address = serve_locally(Arc::new(TestChain::with_config(config).blockhead)).await;
+    let response = send_raw(
+        address,
+        "GET /health HTTP/1.1\r\nOrigin: https://elsewhere.example\r\n\r\n",
+    )
+    .await;
+    assert!(response.contains("Access-Control-Allow-Origin: https://elsewhere.example\r\n"));
+}
+
+#[cfg(all(feature = "http", unix))]
+#[tokio::test]
+async fn test_ipc() {
+    let path = std::env::temp_dir().join(format!("blockhead-{}.ipc", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let server = Server::new(Arc::new(Blockhead::new(":memory:").unwrap()));
+    let task = tokio::spawn(serve_ipc(bind_ipc(&path).unwrap(), server.clone()));
+    let endpoint = Endpoint::parse(path.to_str().unwrap());
+    assert_eq!(endpoint, Endpoint::Ipc(path.clone()));
+    let (status, body) = endpoint.request("GET", "/health", b"").await.unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(body["database"], "ok");
+    let (status, _) = endpoint.request("POST", "/graphql", b"{}").await.unwrap();
+    assert_eq!(status, 400);
+    // Only one node may serve at a path, but a dead node's socket is replaced.
+    assert!(bind_ipc(&path).is_err());
+    task.abort();
+    let _ = task.await;
+    assert!(endpoint.request("GET", "/health", b"").await.is_err());
+    drop(bind_ipc(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,439 @@
+//! A bounded queue between the blocks that arrive from other nodes, through [`crate::peers`],
+//! and the executor.
+//!
+//! [`Blockhead::submit_block`] only queues a block; a background task imports queued blocks one
+//! at a time under the write lock. Blocks that extend the head, or the chain of blocks queued to
+//! extend it, go ahead of side branches, so that syncing a long chain is not held up behind forks.
+//! The queue holds at most [`ImportConfig::capacity`] blocks and tells each sender, through the
+//! returned [`ImportSignal`], when to slow down and when a block was turned away, so a fast sender
+//! cannot grow it without bound.
+//!
+//! A queued block whose parent is not stored waits in a bounded pool of orphans instead of
+//! failing, and is queued again once the parent is imported. [`Blockhead::missing_blocks`] lists
+//! the parents nothing has supplied yet, for the sync layer to request from peers.
+//!
+//! Peers send the same blocks again and again, so both paths drop blocks the node already has
+//! before validating them: first against the hashes of the blocks stored most recently, then
+//! against the database. [`Blockhead::has_block`] also checks the blocks waiting here, so that
+//! the sync layer need not even decode a block whose hash it was sent alongside.
+//! [`ChainStats::duplicate_blocks`] counts the blocks dropped this way.
+//!
+//! [`ChainStats::duplicate_blocks`]: crate::stats::ChainStats::duplicate_blocks
+use crate::block::Block;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::hash::Hash;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// How many stored block hashes the import path remembers.
+const RECENT_BLOCKS: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportConfig {
+    /// The most blocks that may wait to be imported.
+    pub capacity: usize,
+    /// How many waiting blocks make the queue ask senders to slow down.
+    pub slow_down_at: usize,
+    /// The most blocks that may wait for their parents. Past it, the oldest are dropped.
+    pub orphan_capacity: usize,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            slow_down_at: 768,
+            orphan_capacity: 256,
+        }
+    }
+}
+
+/// What a sender should do after submitting a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSignal {
+    /// The block was queued; keep sending.
+    Queued,
+    /// The block was queued, but the queue is filling up: pause before sending more.
+    SlowDown,
+    /// The queue is full and the block was not taken: send it again later.
+    Full,
+    /// The block is stored already, so it was not taken.
+    Known,
+}
+
+#[derive(Default)]
+pub(crate) struct ImportQueue {
+    lanes: Mutex<Lanes>,
+    /// Wakes the import task when a block is queued.
+    queued: Notify,
+    recent: Mutex<RecentBlocks>,
+    /// Blocks dropped because they were stored or queued already.
+    duplicates: AtomicU64,
+}
+
+/// The hashes of the blocks stored most recently, which are the ones peers are likeliest to
+/// send again.
+#[derive(Default)]
+struct RecentBlocks {
+    hashes: HashSet<Hash>,
+    order: VecDeque<Hash>,
+}
+
+impl RecentBlocks {
+    fn insert(&mut self, hash: Hash) {
+        if self.hashes.insert(hash) {
+            self.order.push_back(hash);
+        }
+        if self.order.len() > RECENT_BLOCKS {
+            let oldest = self.order.pop_front().unwrap();
+            self.hashes.remove(&oldest);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Lanes {
+    /// Blocks extending the head, each the child of the one before it, in arrival order.
+    extensions: VecDeque<Block>,
+    /// Every other block: side branches, and blocks whose parents have not arrived yet.
+    side: VecDeque<Block>,
+    /// The last block put in `extensions`, which may already be importing.
+    tip: Option<Hash>,
+    /// Blocks whose parents are not stored, by parent.
+    orphans: HashMap<Hash, Vec<Block>>,
+    /// The orphans' parents and hashes, oldest first.
+    orphan_order: VecDeque<(Hash, Hash)>,
+    /// Every block queued or orphaned.
+    hashes: HashSet<Hash>,
+}
+
+impl Lanes {
+    fn len(&self) -> usize {
+        self.extensions.len() + self.side.len()
+    }
+
+    fn pop(&mut self) -> Option<Block> {
+        let block = self
+            .extensions
+            .pop_front()
+            .or_else(|| self.side.pop_front())?;
+        self.hashes.remove(&block.hash);
+        Some(block)
+    }
+
+    /// Hold `block` until its parent is imported, dropping the oldest orphans past `capacity`.
+    fn buffer_orphan(&mut self, block: Block, capacity: usize) {
+        self.hashes.insert(block.hash);
+        self.orphan_order.push_back((block.parent_hash, block.hash));
+        self.orphans
+            .entry(block.parent_hash)
+            .or_default()
+            .push(block);
+        while self.orphan_order.len() > capacity {
+            let (parent, hash) = self.orphan_order.pop_front().unwrap();
+            let siblings = self.orphans.get_mut(&parent).unwrap();
+            siblings.retain(|orphan| orphan.hash != hash);
+            if siblings.is_empty() {
+                self.orphans.remove(&parent);
+            }
+            self.hashes.remove(&hash);
+        }
+    }
+
+    /// Queue the orphans waiting for `parent`, which was just imported, ahead of other blocks in
+    /// their lane.
+    fn release_orphans(&mut self, parent: Hash, parent_is_head: bool) {
+        let Some(children) = self.orphans.remove(&parent) else {
+            return;
+        };
+        self.orphan_order
+            .retain(|(waiting_for, _)| *waiting_for != parent);
+        for child in children {
+            if parent_is_head {
+                self.extensions.push_front(child);
+            } else {
+                self.side.push_front(child);
+            }
+        }
+    }
+}
+
+impl ImportQueue {
+    pub(crate) fn len(&self) -> usize {
+        self.lanes.lock().unwrap().len()
+    }
+
+    /// Remember that block `hash` is stored.
+    pub(crate) fn remember(&self, hash: Hash) {
+        self.recent.lock().unwrap().insert(hash);
+    }
+
+    /// Forget the recently stored blocks, after deleting blocks that may be among them.
+    pub(crate) fn forget_recent(&self) {
+        *self.recent.lock().unwrap() = RecentBlocks::default();
+    }
+
+    pub(crate) fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+impl Blockhead {
+    /// Queue `block` for import by the background task, returning how the sender should pace
+    /// the blocks it sends next. Blocks already queued are taken again without effect.
+    pub fn submit_block(&self, block: Block) -> Result<ImportSignal> {
+        self.ensure_writable("import a block")?;
+        if self.is_known_block(block.hash)? {
+            return Ok(ImportSignal::Known);
+        }
+        let head = db::read_head(&self.reader())?
+            .ok_or_else(|| Error::new("chain has no genesis block"))?;
+        let config = &self.config.import;
+        let mut lanes = self.imports.lanes.lock().unwrap();
+        if lanes.hashes.contains(&block.hash) {
+            self.imports.duplicates.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let extends = block.parent_hash == head.hash || Some(block.parent_hash) == lanes.tip;
+            if lanes.len() >= config.capacity {
+                // Side branches give way to blocks extending the head.
+                let evicted = if extends { lanes.side.pop_back() } else { None };
+                let Some(evicted) = evicted else {
+                    return Ok(ImportSignal::Full);
+                };
+                lanes.hashes.remove(&evicted.hash);
+            }
+            lanes.hashes.insert(block.hash);
+            if extends {
+                lanes.tip = Some(block.hash);
+                lanes.extensions.push_back(block);
+            } else {
+                lanes.side.push_back(block);
+            }
+            self.imports.queued.notify_one();
+        }
+        Ok(if lanes.len() >= config.slow_down_at {
+            ImportSignal::SlowDown
+        } else {
+            ImportSignal::Queued
+        })
+    }
+
+    /// Whether block `hash` is stored, counting it as a duplicate if so.
+    pub(crate) fn is_known_block(&self, hash: Hash) -> Result<bool> {
+        let recent = self.imports.recent.lock().unwrap().hashes.contains(&hash);
+        let known = recent || db::read_block_is_canonical(&self.reader(), hash)?.is_some();
+        if known {
+            self.imports.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(known)
+    }
+
+    /// Whether block `hash` is stored, queued or orphaned, counting it as a duplicate if so. Lets
+    /// the sync layer skip decoding blocks the node has before submitting them.
+    pub(crate) fn has_block(&self, hash: Hash) -> Result<bool> {
+        if self.imports.lanes.lock().unwrap().hashes.contains(&hash) {
+            self.imports.duplicates.fetch_add(1, Ordering::Relaxed);
+            return Ok(true);
+        }
+        self.is_known_block(hash)
+    }
+
+    /// The parents of orphaned blocks that are neither stored nor queued, oldest first. The sync
+    /// layer should request them from peers.
+    pub fn missing_blocks(&self) -> Vec<Hash> {
+        let lanes = self.imports.lanes.lock().unwrap();
+        let mut missing = Vec::new();
+        for (parent, _) in &lanes.orphan_order {
+            if !lanes.hashes.contains(parent) && !missing.contains(parent) {
+                missing.push(*parent);
+            }
+        }
+        missing
+    }
+
+    /// Import the next queued block, returning whether there was one. A block that fails to
+    /// import is logged and dropped.
+    pub(crate) fn import_next(&self) -> bool {
+        let Some(block) = self.imports.lanes.lock().unwrap().pop() else {
+            return false;
+        };
+        let hash = block.hash;
+        if let Err(error) = self.import_queued(block) {
+            log::warn!("dropping queued block {hash}: {error}");
+        }
+        true
+    }
+
+    /// Import `block`, or hold it as an orphan if its parent is not stored yet.
+    fn import_queued(&self, block: Block) -> Result<()> {
+        if db::read_block_is_canonical(&self.reader(), block.parent_hash)?.is_none() {
+            log::debug!(
+                "holding block {} until its parent {} arrives",
+                block.hash,
+                block.parent_hash
+            );
+            let capacity = self.config.import.orphan_capacity;
+            let mut lanes = self.imports.lanes.lock().unwrap();
+            lanes.buffer_orphan(block, capacity);
+            return Ok(());
+        }
+        self.import_block(&block)?;
+        let is_head = self.head()?.hash == block.hash;
+        let mut lanes = self.imports.lanes.lock().unwrap();
+        lanes.release_orphans(block.hash, is_head);
+        Ok(())
+    }
+}
+
+/// Import blocks as they are queued, until the task is dropped.
+pub(crate) async fn run(blockhead: Arc<Blockhead>) {
+    loop {
+        blockhead.imports.queued.notified().await;
+        loop {
+            let importer = blockhead.clone();
+            match tokio::task::spawn_blocking(move || importer.import_next()).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) => log::error!("importing a queued block panicked: {error}"),
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_import_queue() {
+    use crate::chain::side_block;
+    use crate::genesis::ChainConfig;
+    use crate::testkit::TestChain;
+    use std::time::Duration;
+
+    let source = TestChain::new();
+    let blocks: Vec<_> = (0..4)
+        .map(|_| {
+            source.advance(Duration::from_secs(1));
+            source.produce()
+        })
+        .collect();
+    let target = TestChain::with_config(ChainConfig {
+        import: ImportConfig {
+            capacity: 3,
+            slow_down_at: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let genesis = target.head();
+    let submit = |block: &Block| target.blockhead.submit_block(block.clone()).unwrap();
+    assert_eq!(submit(&blocks[0]), ImportSignal::Queued);
+    assert!(target.blockhead.import_next());
+    assert_eq!(target.head().hash, blocks[0].hash);
+
+    let side = side_block(&target.blockhead, &genesis, 500);
+    assert_eq!(submit(&side), ImportSignal::Queued);
+    assert_eq!(submit(&blocks[1]), ImportSignal::SlowDown);
+    assert_eq!(submit(&blocks[1]), ImportSignal::SlowDown);
+    assert_eq!(submit(&blocks[2]), ImportSignal::SlowDown);
+    // Once full, the side block makes room for the next extension, but nothing makes room for
+    // another side block.
+    assert_eq!(submit(&blocks[3]), ImportSignal::SlowDown);
+    assert_eq!(submit(&side), ImportSignal::Full);
+    assert_eq!(target.blockhead.imports.len(), 3);
+
+    // Extensions are imported in order, ahead of anything else.
+    assert!(target.blockhead.import_next());
+    assert_eq!(target.head().hash, blocks[1].hash);
+    assert_eq!(submit(&side), ImportSignal::SlowDown);
+    while target.blockhead.import_next() {}
+    assert_eq!(target.head().hash, blocks[3].hash);
+    assert_eq!(target.blockhead.imports.len(), 0);
+    // The side block was imported last, without reorging the longer chain.
+    assert!(db::read_block(&target.blockhead.connection, side.hash)
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_known_blocks() {
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let block = chain.produce();
+    let imported = chain.fork(&block, 1).pop().unwrap();
+    assert_eq!(chain.blockhead.imports.duplicates(), 0);
+
+    // Sent again, produced and imported blocks are dropped before validation.
+    chain.blockhead.import_block(&block).unwrap();
+    chain.blockhead.import_block(&imported).unwrap();
+    assert_eq!(chain.head().hash, imported.hash);
+    assert_eq!(
+        chain.blockhead.submit_block(block.clone()).unwrap(),
+        ImportSignal::Known
+    );
+    assert_eq!(chain.blockhead.imports.len(), 0);
+    // Blocks that dropped out of the recent hashes are found in the database.
+    chain.blockhead.imports.forget_recent();
+    assert!(chain.blockhead.is_known_block(block.hash).unwrap());
+    assert!(!chain.blockhead.is_known_block(Hash([9; 32])).unwrap());
+    assert_eq!(chain.blockhead.chain_stats(1).unwrap().duplicate_blocks, 4);
+
+    let hash = |index: usize| {
+        let mut bytes = [0; 32];
+        bytes[..8].copy_from_slice(&(index as u64).to_le_bytes());
+        Hash(bytes)
+    };
+    let mut recent = RecentBlocks::default();
+    for index in 0..=RECENT_BLOCKS {
+        recent.insert(hash(index));
+    }
+    assert_eq!(recent.hashes.len(), RECENT_BLOCKS);
+    assert!(!recent.hashes.contains(&hash(0)));
+    assert!(recent.hashes.contains(&hash(RECENT_BLOCKS)));
+}
+
+#[test]
+fn test_orphan_blocks() {
+    use crate::genesis::ChainConfig;
+    use crate::testkit::TestChain;
+    use std::time::Duration;
+
+    let source = TestChain::new();
+    let blocks: Vec<_> = (0..4)
+        .map(|_| {
+            source.advance(Duration::from_secs(1));
+            source.produce()
+        })
+        .collect();
+    let target = TestChain::with_config(ChainConfig {
+        import: ImportConfig {
+            orphan_capacity: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let submit = |block: &Block| target.blockhead.submit_block(block.clone()).unwrap();
+
+    // Blocks arriving ahead of their parents wait for them.
+    for block in [&blocks[3], &blocks[2], &blocks[1]] {
+        submit(block);
+    }
+    while target.blockhead.import_next() {}
+    assert_eq!(target.head().number, 0);
+    // Only two orphans fit, so block 4 was dropped for block 2.
+    assert_eq!(target.blockhead.missing_blocks(), vec![blocks[0].hash]);
+    assert_eq!(submit(&blocks[3]), ImportSignal::Queued);
+    assert_eq!(submit(&blocks[2]), ImportSignal::Queued);
+    assert_eq!(target.blockhead.imports.duplicates(), 1);
+
+    submit(&blocks[0]);
+    while target.blockhead.import_next() {}
+    assert_eq!(target.head().hash, blocks[3].hash);
+    assert!(target.blockhead.missing_blocks().is_empty());
+    assert_eq!(target.blockhead.imports.len(), 0);
+}
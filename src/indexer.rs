@@ -0,0 +1,180 @@
+//! Receipt indexing off the block-import path.
+//!
+//! Committing a block stores its receipts as a single queued row rather than one indexed row per
+//! transaction. A background task moves queued receipts into the `receipt` table, oldest block
+//! first, in short batches between imports. Receipts read the same either way: lookups fall back
+//! to the queue. So that the queue stays bounded, a commit that leaves more than
+//! [`IndexerConfig::max_lag`] blocks queued indexes the oldest itself.
+use crate::db;
+use crate::error::Result;
+use crate::events::ChainEvent;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+use sqlite::Connection;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexerConfig {
+    /// The most blocks whose receipts may wait to be indexed.
+    pub max_lag: u64,
+    /// How many blocks the background task indexes under each hold of the write lock.
+    pub batch_size: usize,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        Self {
+            max_lag: 64,
+            batch_size: 16,
+        }
+    }
+}
+
+/// How far the receipt index trails the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexStatus {
+    /// Blocks whose receipts are queued.
+    pub backlog: u64,
+    /// The height below which every canonical block is indexed.
+    pub indexed_below: u64,
+}
+
+/// Index the receipts of up to `limit` queued blocks, oldest first, returning how many blocks
+/// were indexed.
+pub(crate) fn index_pending(connection: &Connection, limit: usize) -> Result<usize> {
+    let batch = db::read_oldest_pending_receipts(connection, limit)?;
+    for (block_hash, receipts) in &batch {
+        for receipt in receipts {
+            db::write_receipt(connection, receipt)?;
+        }
+        db::delete_pending_receipts(connection, *block_hash)?;
+    }
+    Ok(batch.len())
+}
+
+/// Index the oldest queued blocks beyond the `max_lag` allowed.
+pub(crate) fn enforce_max_lag(connection: &Connection, config: &IndexerConfig) -> Result<()> {
+    let (backlog, _) = db::read_pending_receipts_backlog(connection)?;
+    if backlog > config.max_lag {
+        index_pending(connection, (backlog - config.max_lag) as usize)?;
+    }
+    Ok(())
+}
+
+impl Blockhead {
+    /// Index the receipts of up to `limit` queued blocks. See [`index_pending`].
+    pub(crate) fn index_receipts(&self, limit: usize) -> Result<usize> {
+        self.ensure_writable("index receipts")?;
+        let _guard = self.write_lock.lock().unwrap();
+        db::transaction(&self.connection, || index_pending(&self.connection, limit))
+    }
+
+    pub(crate) fn index_status(&self) -> Result<IndexStatus> {
+        let (backlog, lowest) = db::read_pending_receipts_backlog(&self.reader())?;
+        let indexed_below = match lowest {
+            Some(number) => number,
+            None => self.head()?.number + 1,
+        };
+        Ok(IndexStatus {
+            backlog,
+            indexed_below,
+        })
+    }
+}
+
+/// Index queued receipts as blocks are committed, and move old blocks to cold storage after, until
+/// the task is dropped. See [`crate::cold`].
+pub(crate) async fn run(blockhead: Arc<Blockhead>) {
+    let mut events = blockhead.subscribe_chain_events();
+    let batch_size = blockhead.config.indexer.batch_size;
+    loop {
+        // Work through the backlog a batch at a time, letting imports in between batches.
+        loop {
+            let indexer = blockhead.clone();
+            match tokio::task::spawn_blocking(move || indexer.index_receipts(batch_size)).await {
+                Ok(Ok(indexed)) if indexed == batch_size => continue,
+                Ok(Ok(_)) => break,
+                Ok(Err(error)) => log::error!("indexing receipts failed: {error}"),
+                Err(error) => log::error!("indexing receipts panicked: {error}"),
+            }
+            break;
+        }
+        // Then move what is now old enough to cold storage, as far as it is indexed.
+        loop {
+            let mover = blockhead.clone();
+            let limit = batch_size.max(1) as u64;
+            match tokio::task::spawn_blocking(move || mover.move_to_cold(limit)).await {
+                Ok(Ok(moved)) if moved == limit => continue,
+                Ok(Ok(_)) => break,
+                Ok(Err(error)) => log::error!("moving blocks to cold storage failed: {error}"),
+                Err(error) => log::error!("moving blocks to cold storage panicked: {error}"),
+            }
+            break;
+        }
+        loop {
+            match events.recv().await {
+                Ok(ChainEvent::NewBlock(_)) | Err(RecvError::Lagged(_)) => break,
+                Ok(_) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_receipt_indexing() {
+    use crate::block::BlockId;
+    use crate::testkit::TestChain;
+    use crate::Blockchain;
+
+    let chain = TestChain::new();
+    let recipient = crate::address::Address([8; 32]);
+    let mut hashes = Vec::new();
+    for _ in 0..3 {
+        hashes.push(chain.transfer(chain.validator, recipient, 1).await);
+        chain.produce();
+    }
+    // Queued receipts read like indexed ones.
+    assert_eq!(
+        chain.blockhead.index_status().unwrap(),
+        IndexStatus {
+            backlog: 3,
+            indexed_below: 1
+        }
+    );
+    let queued = chain.blockhead.get_transaction_receipt(hashes[1]).await;
+    assert!(queued.unwrap().unwrap().status);
+    assert_eq!(chain.blockhead.index_receipts(2).unwrap(), 2);
+    assert_eq!(chain.blockhead.index_status().unwrap().indexed_below, 3);
+    let indexed = chain.blockhead.get_transaction_receipt(hashes[1]).await;
+    assert_eq!(indexed.unwrap().unwrap().transaction_hash, hashes[1]);
+    assert!(chain.blockhead.check_integrity().unwrap().is_consistent());
+
+    // Reverting a block drops its receipts, queued or indexed.
+    let genesis = chain.blockhead.canonical_block(BlockId::Number(0)).unwrap();
+    chain.fork(&genesis, 4);
+    assert_eq!(chain.blockhead.index_status().unwrap().backlog, 4);
+    for hash in &hashes {
+        let receipt = chain.blockhead.get_transaction_receipt(*hash).await;
+        assert!(receipt.unwrap().is_none());
+    }
+
+    // The backlog never exceeds the configured lag.
+    let mut config = crate::genesis::ChainConfig::default();
+    config.indexer.max_lag = 2;
+    let chain = TestChain::with_config(config);
+    chain.produce_many(5);
+    assert_eq!(chain.blockhead.index_status().unwrap().backlog, 2);
+    let blockhead = Arc::new(chain.blockhead);
+    let task = tokio::spawn(run(blockhead.clone()));
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while blockhead.index_status().unwrap().backlog > 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+    task.abort();
+}
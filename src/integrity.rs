@@ -0,0 +1,314 @@
+//! Consistency checks over the stored chain, and repair by truncation.
+//!
+//! Every block is written in a single SQLite transaction, so a healthy database never holds half
+//! a block. Copying the file mid-write, disk faults or manual edits can still leave one behind,
+//! and `blockhead db check` finds the damage: breaks in the canonical hash chain, blocks or
+//! transactions whose contents no longer match their hashes, canonical blocks missing the
+//! receipts or state diff written alongside them, a head pointer that does not name the highest
+//! canonical block, and rows that refer to missing blocks.
+//! `blockhead db repair` reverts the canonical chain to the last block below the first problem
+//! and deletes everything above it.
+use crate::db;
+use crate::error::{Error, Result};
+use crate::events::ChainEvent;
+use crate::state;
+use crate::Blockhead;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IntegrityReport {
+    pub problems: Vec<String>,
+    /// The highest canonical block at and below which the chain is consistent.
+    pub last_consistent: u64,
+}
+
+impl IntegrityReport {
+    pub(crate) fn is_consistent(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Blockhead {
+    pub(crate) fn check_integrity(&self) -> Result<IntegrityReport> {
+        let head = db::read_canonical_tip(&self.connection)?
+            .ok_or_else(|| Error::new("chain has no genesis block"))?;
+        let mut problems = Vec::new();
+        let mut first_bad = None;
+        let mut previous = None;
+        for number in 0..=head.number {
+            let mut blocks = db::read_canonical_blocks(&self.connection, number)?;
+            let mut block_problems = Vec::new();
+            if blocks.len() != 1 {
+                block_problems.push(format!(
+                    "{} canonical blocks at height {number}",
+                    blocks.len()
+                ));
+            }
+            if let Some(block) = blocks.pop() {
+                if block.compute_hash(self.config.hash) != block.hash {
+                    block_problems.push(format!("block {} does not match its hash", block.hash));
+                }
+                if let Some(parent_hash) = previous {
+                    if block.parent_hash != parent_hash {
+                        block_problems.push(format!(
+                            "block {} at height {number} does not link to {parent_hash}",
+                            block.hash
+                        ));
+                    }
+                }
+                for (hash, transaction) in &block.body.transactions {
+                    if transaction.compute_hash(self.config.hash) != *hash {
+                        block_problems.push(format!("transaction {hash} does not match its hash"));
+                    }
+                }
+                if block.body.transactions_root(self.config.hash) != block.transactions_root {
+                    block_problems.push(format!(
+                        "block {} does not match its transactions root",
+                        block.hash
+                    ));
+                }
+                let queued = db::read_pending_receipts(&self.connection, block.hash)?;
+                let receipts = db::count_receipts(&self.connection, block.hash)?
+                    + queued.map_or(0, |receipts| receipts.len() as u64);
+                if receipts != block.body.transactions.len() as u64 {
+                    block_problems.push(format!(
+                        "block {} has {} transactions but {receipts} receipts",
+                        block.hash,
+                        block.body.transactions.len()
+                    ));
+                }
+                if db::read_state_diff(&self.connection, block.hash)?.is_none() {
+                    block_problems.push(format!("block {} has no state diff", block.hash));
+                }
+                previous = Some(block.hash);
+            }
+            if !block_problems.is_empty() {
+                first_bad.get_or_insert(number);
+                problems.extend(block_problems);
+            }
+        }
+        let pointer = db::read_head_hash(&self.connection)?;
+        if pointer != Some(head.hash) {
+            let named = match pointer {
+                Some(hash) => {
+                    problems.push(format!(
+                        "head pointer names {hash}, but the highest canonical block is {}",
+                        head.hash
+                    ));
+                    db::read_header(&self.connection, hash)?
+                }
+                None => {
+                    problems.push("head pointer is missing".to_string());
+                    None
+                }
+            };
+            // Keep the blocks below the one the pointer names, which were committed with it.
+            let bad = match named {
+                Some(named) if named.number < head.number => named.number + 1,
+                Some(named) if named.number == head.number => head.number,
+                _ => head.number + 1,
+            };
+            first_bad = Some(first_bad.map_or(bad, |first: u64| first.min(bad)));
+        }
+        for (table, count) in db::count_orphans(&self.connection)? {
+            problems.push(format!("{count} orphaned rows in {table}"));
+        }
+        let last_consistent = match first_bad {
+            Some(0) => return Err(Error::new("the genesis block is damaged")),
+            Some(number) => number - 1,
+            None => head.number,
+        };
+        Ok(IntegrityReport {
+            problems,
+            last_consistent,
+        })
+    }
+
+    /// Truncate the chain to the last consistent block and remove orphaned rows, returning the
+    /// report the repair acted on.
+    pub(crate) fn repair(&self) -> Result<IntegrityReport> {
+        self.ensure_writable("repair the database")?;
+        let report = self.check_integrity()?;
+        if report.is_consistent() {
+            return Ok(report);
+        }
+        let _guard = self.write_lock.lock().unwrap();
+        let reverted = db::transaction(&self.connection, || {
+            let head = db::read_canonical_tip(&self.connection)?
+                .ok_or_else(|| Error::new("chain has no genesis block"))?;
+            let mut reverted = Vec::new();
+            for number in (report.last_consistent + 1..=head.number).rev() {
+                for block in db::read_canonical_blocks(&self.connection, number)? {
+                    state::revert_block(&self.connection, block.hash)?;
+                    db::set_canonical(&self.connection, block.hash, false)?;
+                    reverted.push(block);
+                }
+            }
+            db::delete_epochs_after(
+                &self.connection,
+                self.config.staking.epoch_of(report.last_consistent),
+            )?;
+            db::delete_blocks_above(&self.connection, report.last_consistent)?;
+            let last_consistent =
+                db::read_canonical_header(&self.connection, report.last_consistent)?.ok_or_else(
+                    || {
+                        Error::new(format!(
+                            "missing canonical block {}",
+                            report.last_consistent
+                        ))
+                    },
+                )?;
+            db::write_head(&self.connection, last_consistent.hash)?;
+            // Deleting an orphaned block can orphan its descendants.
+            while !db::count_orphans(&self.connection)?.is_empty() {
+                db::delete_orphans(&self.connection)?;
+            }
+            Ok(reverted)
+        })?;
+        self.imports.forget_recent();
+        self.publish_chain_events(vec![ChainEvent::Reorg {
+            ancestor: self.head()?.hash,
+            reverted,
+        }]);
+        log::info!(
+            "repaired database, truncated to block {}",
+            report.last_consistent
+        );
+        Ok(report)
+    }
+}
+
+#[tokio::test]
+async fn test_check_and_repair() {
+    use crate::address::Address;
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    for nonce in 0..3 {
+        let transfer = testkit::transfer(validator, Address([8; 32]), 1, nonce);
+        blockhead.send_transaction(transfer).await.unwrap();
+        blockhead.produce_block().unwrap();
+    }
+    blockhead.index_receipts(3).unwrap();
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+
+    // Lose block 2's receipt, as if its batch had been cut short.
+    let block2 = db::read_canonical_block(&blockhead.connection, 2)
+        .unwrap()
+        .unwrap();
+    blockhead
+        .connection
+        .execute(format!(
+            "DELETE FROM receipt WHERE block_hash = X'{}'",
+            hex::encode(block2.hash.0)
+        ))
+        .unwrap();
+    let report = blockhead.check_integrity().unwrap();
+    assert_eq!(report.last_consistent, 1);
+    assert_eq!(report.problems.len(), 1);
+
+    blockhead.repair().unwrap();
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+    assert_eq!(blockhead.head().unwrap().number, 1);
+    assert_eq!(blockhead.get_balance(Address([8; 32])).await.unwrap(), 1);
+    assert_eq!(blockhead.get_nonce(validator).await.unwrap(), 1);
+}
+
+#[test]
+fn test_head_pointer() {
+    let (blockhead, _) = crate::chain::staked_chain(32);
+    let block1 = blockhead.produce_block().unwrap();
+    blockhead.produce_block().unwrap();
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+
+    // A pointer left behind the canonical tip, as no committed write can leave it.
+    db::write_head(&blockhead.connection, block1.hash).unwrap();
+    assert_eq!(blockhead.head().unwrap().hash, block1.hash);
+    let report = blockhead.check_integrity().unwrap();
+    assert_eq!(report.last_consistent, 1);
+    assert_eq!(report.problems.len(), 1);
+    blockhead.repair().unwrap();
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+    assert_eq!(blockhead.produce_block().unwrap().number, 2);
+
+    blockhead.connection.execute("DELETE FROM head").unwrap();
+    let report = blockhead.check_integrity().unwrap();
+    assert_eq!(report.last_consistent, 2);
+    blockhead.repair().unwrap();
+    assert!(blockhead.check_integrity().unwrap().is_consistent());
+}
+
+/// Run [`crash_child`] in a new process that writes to the database at `path` and aborts at
+/// `point`.
+#[cfg(test)]
+fn crash_at(point: &str, path: &std::path::Path) {
+    let status = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "integrity::crash_child", "--include-ignored"])
+        .env("BLOCKHEAD_CRASH_AT", point)
+        .env("BLOCKHEAD_CRASH_DB", path)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    // Aborting leaves no exit code, unlike finishing the test or failing it.
+    assert_eq!(status.code(), None, "{point} was never reached");
+}
+
+/// The write [`test_crash_recovery`] kills: produce a block or, to crash in the middle of a
+/// reorg, import a branch off genesis longer than the chain.
+#[test]
+#[ignore]
+fn crash_child() {
+    let (Ok(point), Ok(path)) = (
+        std::env::var("BLOCKHEAD_CRASH_AT"),
+        std::env::var("BLOCKHEAD_CRASH_DB"),
+    ) else {
+        return;
+    };
+    let blockhead = Blockhead::open(path)
+        .unwrap()
+        .with_proposer_key(crate::testkit::validator_key())
+        .unwrap();
+    if point == "reorg_to" {
+        let mut parent = db::read_canonical_block(&blockhead.connection, 0)
+            .unwrap()
+            .unwrap();
+        for timestamp in 1..=3 {
+            let block = crate::chain::side_block(&blockhead, &parent, timestamp);
+            blockhead.import_block(&block).unwrap();
+            parent = block;
+        }
+    } else {
+        blockhead.produce_block().unwrap();
+    }
+}
+
+#[test]
+fn test_crash_recovery() {
+    for point in [
+        "commit_block",
+        "set_canonical",
+        "reorg_to",
+        "commit_transaction",
+    ] {
+        let (blockhead, path) = crate::pool::file_chain(&format!("crash-{point}"));
+        blockhead.produce_block().unwrap();
+        let head = blockhead.produce_block().unwrap();
+        drop(blockhead);
+
+        crash_at(point, &path);
+        let blockhead = Blockhead::open(&path)
+            .unwrap()
+            .with_proposer_key(crate::testkit::validator_key())
+            .unwrap();
+        assert_eq!(blockhead.head().unwrap().hash, head.hash, "{point}");
+        assert!(blockhead.check_integrity().unwrap().is_consistent());
+        // The state is the head's: the blocks below it execute to their stored state roots.
+        assert_eq!(blockhead.replay(1, 2).unwrap().divergence, None);
+        assert_eq!(blockhead.produce_block().unwrap().parent_hash, head.hash);
+        drop(blockhead);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}
@@ -0,0 +1,955 @@
+#![allow(dead_code)]
+//! Blockhead: a proof-of-stake blockchain node that can be embedded as a library.
+//!
+//! [`Blockhead`] opens a chain stored in SQLite, from a [`Genesis`] if it has no blocks yet,
+//! and answers queries through the [`Blockchain`] trait. That interface covers the main
+//! categories of blockchain interactions:
+//!
+//! 1. Block queries: Fetching blocks by hash/number and latest block
+//! 2. Transaction operations: Querying, sending, and getting receipts
+//! 3. Account operations: Balance and nonce queries, and native token balances
+//! 4. Contract interactions: Calls and gas estimation
+//! 5. Chain information: Chain ID, sync status, gas price
+//!
+//! The trait uses async/await for all operations since blockchain RPCs are typically network
+//! calls. Besides the node itself, [`Blockhead`], the `mock` feature provides an in-memory
+//! implementation for testing clients: see `mock::MockBlockchain`. The `testkit` feature provides
+//! a real node on a deterministic in-memory chain for integration tests: see
+//! `testkit::TestChain`.
+//!
+//! The types re-exported here are the crate's public API. Everything else is internal to the
+//! node and may change between versions.
+pub use crate::address::Address;
+pub use crate::archive::{PruningConfig, StateHistory};
+pub use crate::block::{Block, BlockId, Body, Header};
+pub use crate::block_range::BlockRange;
+pub use crate::builder::BlockOrdering;
+pub use crate::client::{BalanceWatch, Client, NonceManager};
+pub use crate::clock::TimestampConfig;
+pub use crate::cold::ColdStorageConfig;
+pub use crate::error::{Error, ErrorKind, Result};
+pub use crate::execution::{AccountOverride, CallOverrides, StateOverrides};
+pub use crate::faucet::FaucetConfig;
+pub use crate::fee::{FeeConfig, FeeEstimate};
+pub use crate::finality::FinalityConfig;
+pub use crate::gas::GasConfig;
+pub use crate::genesis::{ChainConfig, Genesis};
+pub use crate::hash::{Hash, HashAlgorithm};
+pub use crate::http::HttpConfig;
+pub use crate::import_queue::{ImportConfig, ImportSignal};
+pub use crate::indexer::IndexerConfig;
+pub use crate::mempool::{MempoolConfig, MempoolContent, MempoolStatus};
+pub use crate::peers::PeerConfig;
+pub use crate::proof::{AccountProof, ProofStep};
+#[cfg(feature = "http")]
+pub use crate::remote::{Remote, RetryPolicy};
+pub use crate::reward::{BlockReward, RewardConfig};
+#[cfg(feature = "http")]
+pub use crate::rpc::HttpBlockchain;
+pub use crate::runtime::RuntimeConfig;
+pub use crate::signer::{Signature, Signer};
+pub use crate::simulate::Simulation;
+pub use crate::staking::StakingConfig;
+pub use crate::state::{Account, AccountDiff, StateDiff, StorageDiff, TokenDiff};
+pub use crate::stats::ChainStats;
+pub use crate::status::TransactionStatus;
+pub use crate::token::{TokenInfo, TokenSlot};
+pub use crate::trace::{StorageAccess, Trace, TraceConfig, TraceStep};
+pub use crate::transaction::{Builder as TransactionBuilder, Transaction, TransactionKind};
+/// The type of [`Transaction::data`], re-exported so that callers need not depend on `bytes`.
+pub use bytes::Bytes;
+
+use crate::clock::{Clock, SystemClock};
+use crate::events::{ChainEvent, EventBus};
+use crate::import_queue::ImportQueue;
+use crate::mempool::Mempool;
+use crate::peers::PeerStatus;
+use crate::pool::{ReadConnection, ReaderPool};
+use crate::staking::ValidatorSet;
+use crate::state::StateOverlay;
+use crate::status::StatusTracker;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+mod address;
+#[cfg(feature = "cli")]
+mod address_book;
+mod admin;
+mod archive;
+#[cfg(feature = "cli")]
+mod attach;
+#[cfg(feature = "http")]
+mod balances;
+#[cfg(any(test, feature = "bench"))]
+pub mod bench;
+mod block;
+mod block_range;
+mod bloom;
+mod builder;
+mod chain;
+#[cfg(feature = "cli")]
+mod cli;
+mod client;
+mod clock;
+mod cold;
+#[cfg(feature = "cli")]
+mod console;
+#[cfg(feature = "http")]
+mod cursor;
+mod db;
+mod dev;
+mod encoding;
+mod error;
+mod events;
+mod execution;
+mod execution_cache;
+#[cfg(feature = "http")]
+mod explorer;
+mod faucet;
+mod fee;
+mod finality;
+#[cfg(all(feature = "http", any(test, feature = "fuzzing")))]
+pub mod fuzzing;
+mod gas;
+mod genesis;
+#[cfg(feature = "http")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hash;
+#[cfg(feature = "http")]
+mod health;
+mod http;
+mod import_queue;
+mod indexer;
+mod integrity;
+mod logs;
+mod maintenance;
+mod mempool;
+#[cfg(feature = "http")]
+mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod multisig;
+mod parallel;
+mod peers;
+mod pool;
+#[cfg(feature = "vm")]
+mod precompile;
+mod proof;
+#[cfg(feature = "http")]
+mod remote;
+mod replay;
+mod reward;
+#[cfg(feature = "http")]
+mod rpc;
+mod runtime;
+mod signer;
+mod simulate;
+mod snapshot;
+mod spec;
+mod staking;
+mod state;
+mod stats;
+mod status;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+mod token;
+mod trace;
+mod transaction;
+mod verify;
+#[cfg(feature = "vm")]
+mod vm;
+mod wallet;
+#[cfg(feature = "http")]
+mod websocket;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionReceipt {
+    pub transaction_hash: Hash,
+    pub block_hash: Hash,
+    pub status: bool,
+    pub gas_used: u64,
+    /// The address of the contract created by a deployment transaction.
+    pub contract_address: Option<Address>,
+    /// The data returned by contract code, or its revert reason when `status` is false.
+    #[serde(with = "crate::trace::hex_bytes")]
+    pub return_data: Vec<u8>,
+    pub logs: Vec<Log>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Log {
+    /// The contract that emitted the log.
+    pub address: Address,
+    pub topics: Vec<Hash>,
+    #[serde(with = "crate::trace::hex_bytes")]
+    pub data: Vec<u8>,
+}
+
+#[async_trait::async_trait]
+pub trait Blockchain {
+    // Block related
+    /// The block identified by `id`. A hash may name a block off the canonical chain, and
+    /// [`BlockId::Pending`] the block the mempool would produce now.
+    async fn get_block(&self, id: BlockId) -> Result<Option<Block>>;
+    /// A block's header alone, without reading its transactions.
+    async fn get_header(&self, id: BlockId) -> Result<Option<Header>>;
+    /// The canonical encoding of a block, as `0x`-prefixed hex.
+    async fn get_raw_block(&self, hash: Hash) -> Result<Option<String>>;
+    /// What the proposer of canonical block `id` was paid for it.
+    async fn get_block_rewards(&self, id: BlockId) -> Result<BlockReward>;
+
+    // Transaction related
+    /// Transaction `hash`, whether included in a block or waiting in the mempool.
+    async fn get_transaction(&self, hash: Hash) -> Result<Option<Transaction>>;
+    async fn get_transaction_receipt(&self, hash: Hash) -> Result<Option<TransactionReceipt>>;
+    /// The canonical encoding of a transaction, as `0x`-prefixed hex.
+    async fn get_raw_transaction(&self, hash: Hash) -> Result<Option<String>>;
+    async fn send_transaction(&self, transaction: Transaction) -> Result<Hash>;
+    /// Where transaction `hash` is between submission and inclusion in the canonical chain.
+    async fn get_transaction_status(&self, hash: Hash) -> Result<TransactionStatus>;
+    /// The mempool's transactions by sender and nonce. See [`MempoolContent`].
+    async fn get_mempool_content(&self) -> Result<MempoolContent>;
+    /// How many transactions [`Blockchain::get_mempool_content`] would list in each group.
+    async fn get_mempool_status(&self) -> Result<MempoolStatus>;
+
+    // Account related
+    async fn get_balance(&self, address: Address) -> Result<u64>;
+    /// The balances of `addresses`, in order, in one request however many there are.
+    async fn get_balances(&self, addresses: &[Address]) -> Result<Vec<u64>>;
+    /// The balance of `address` in the state after `block`, which must be canonical or pending.
+    async fn get_balance_at(&self, address: Address, block: BlockId) -> Result<u64>;
+    async fn get_nonce(&self, address: Address) -> Result<u64>;
+    /// The nonce for `address`'s next transaction: its account nonce, advanced past each
+    /// consecutive nonce it already has waiting in the mempool. Wallets sending several
+    /// transactions before the first is included number them with this.
+    async fn get_pending_nonce(&self, address: Address) -> Result<u64>;
+
+    // Token related
+    /// The name, symbol, decimals and supply of the token at `token`.
+    async fn get_token_info(&self, token: Address) -> Result<Option<TokenInfo>>;
+    async fn get_token_balance(&self, token: Address, holder: Address) -> Result<u64>;
+    /// How much of `owner`'s `token` `spender` may still transfer.
+    async fn get_token_allowance(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+    ) -> Result<u64>;
+
+    /// A Merkle proof of the account of `address` against the state root of `block`, which must
+    /// be canonical or pending. `None` if the account is empty.
+    async fn get_proof(&self, address: Address, block: BlockId) -> Result<Option<AccountProof>>;
+
+    // Contract related
+    /// Run a read-only call against the state after `block`, which must be canonical or pending.
+    async fn call(
+        &self,
+        to: Address,
+        data: Vec<u8>,
+        overrides: CallOverrides,
+        block: BlockId,
+    ) -> Result<Vec<u8>>;
+    async fn estimate_gas(&self, to: Address, data: Vec<u8>) -> u64;
+    /// What `transaction` would do if included after `block`, which must be canonical or pending,
+    /// with `overrides` written over its state, without committing it. See [`Simulation`].
+    async fn simulate_transaction(
+        &self,
+        transaction: Transaction,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Simulation>;
+    /// Simulate `transactions` in order after `block`, each seeing the effects of those before
+    /// it. Each simulation's state diff holds only its own transaction's changes.
+    async fn simulate_bundle(
+        &self,
+        transactions: Vec<Transaction>,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Vec<Simulation>>;
+
+    // Debugging
+    async fn debug_trace_transaction(&self, hash: Hash) -> Result<Option<Trace>>;
+    async fn get_state_diff(&self, block_hash: Hash) -> Result<Option<StateDiff>>;
+
+    // Chain related
+    /// The ID that transactions for this chain are signed with. See [`ChainConfig::chain_id`].
+    async fn chain_id(&self) -> u64;
+    /// The hash function of the chain, which addresses and signatures for it are derived with.
+    /// See [`ChainConfig::hash`].
+    async fn hash_algorithm(&self) -> HashAlgorithm;
+    /// Whether the node is catching up with peers whose heads are ahead of its own.
+    async fn syncing(&self) -> bool;
+    /// The gas price a transaction should offer to be included soon: the normal price of
+    /// [`Blockchain::estimate_fee`], and no less than the node admits.
+    async fn gas_price(&self) -> u64;
+    /// Slow, normal and fast gas prices, from those paid in recent blocks.
+    async fn estimate_fee(&self) -> Result<FeeEstimate>;
+    /// Activity over the `window` most recent blocks. See [`ChainStats`].
+    async fn get_chain_stats(&self, window: u64) -> Result<ChainStats>;
+}
+
+pub struct Blockhead {
+    connection: sqlite::ConnectionThreadSafe,
+    config: ChainConfig,
+    /// Source of block timestamps and the reference for rejecting blocks from the future.
+    clock: Arc<dyn Clock>,
+
+    /// Transactions waiting to be included by [`Blockhead::produce_block`].
+    mempool: Mutex<Mempool>,
+    /// Transactions that left the mempool, and subscribers to status changes.
+    status: Mutex<StatusTracker>,
+    /// Chain and mempool changes, for the subsystems that follow them.
+    events: EventBus,
+    /// Blocks from other nodes waiting to be imported.
+    imports: ImportQueue,
+    /// The node's peers, as the sync task last found them.
+    peers: PeerStatus,
+    /// The results of recently executed blocks. See [`crate::execution_cache`].
+    execution_cache: execution_cache::ExecutionCache,
+    /// Whom the faucet paid recently. See [`crate::faucet`].
+    faucet_payments: faucet::FaucetPayments,
+    /// How far dev mode has moved the node's time. See [`crate::dev`].
+    time_travel: dev::TimeTravel,
+    /// What dev mode can revert the chain to. See [`Blockhead::snapshot`].
+    snapshots: Mutex<dev::Snapshots>,
+    /// Set while an operator has paused block production. See [`crate::admin`].
+    production_paused: AtomicBool,
+    /// Notified when an operator asks the node to shut down.
+    shutdown: Notify,
+    /// Serializes block production and import.
+    write_lock: Mutex<()>,
+    /// Set for databases opened with [`Blockhead::new_read_only`].
+    read_only: bool,
+    /// Connections for the query methods, so they do not wait behind writes to `connection`.
+    readers: ReaderPool,
+    /// What contract execution waits for. See [`crate::runtime`].
+    vm_slots: runtime::VmSlots,
+    /// The keys of the validators this node proposes blocks for, by address. See
+    /// [`Blockhead::with_proposer_key`].
+    proposer_keys: HashMap<Address, Arc<dyn Signer>>,
+}
+
+/// Read connections opened alongside the writer for file-backed databases.
+const READER_POOL_SIZE: usize = 4;
+
+impl Blockhead {
+    pub fn new<T: AsRef<Path>>(db_filename: T) -> Result<Self> {
+        Self::with_genesis(db_filename, Genesis::default())
+    }
+
+    /// Open the chain in an existing database with the protocol parameters it records, and the
+    /// defaults for the settings local to each node. Unlike [`Blockhead::new`], fails rather than
+    /// create a database if there is none at the path.
+    pub fn open<T: AsRef<Path>>(db_filename: T) -> Result<Self> {
+        let path = db_filename.as_ref();
+        if !path.is_file() {
+            return Err(Error::new(format!("no database at {}", path.display())));
+        }
+        let flags = sqlite::OpenFlags::new().with_read_only();
+        let connection = sqlite::Connection::open_with_flags(path, flags)?;
+        let config = db::read_chain_config(&connection)?.ok_or_else(|| {
+            Error::new("the database does not record its chain config; open it with its genesis")
+        })?;
+        drop(connection);
+        Self::with_genesis(
+            path,
+            Genesis {
+                config,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Open the database, initializing it from `genesis` if it has no blocks yet.
+    /// A database that holds a chain already must have been created with the same protocol
+    /// parameters as `genesis.config`, which it records.
+    pub fn with_genesis<T: AsRef<Path>>(db_filename: T, genesis: Genesis) -> Result<Self> {
+        let path = db_filename.as_ref();
+        let connection = sqlite::Connection::open_thread_safe(path)?;
+        // Each connection to ":memory:" is a separate database, so only files can be pooled.
+        let in_memory = path == Path::new(":memory:");
+        if !in_memory {
+            connection.execute("PRAGMA journal_mode = WAL")?;
+        }
+        db::upgrade(&connection)?;
+        connection.execute(db::SCHEMA)?;
+        match db::read_chain_config(&connection)? {
+            Some(stored) => genesis.config.check_matches(&stored)?,
+            // A chain from before configs were recorded must at least hash as it did.
+            None => {
+                if let Some(header) = db::read_canonical_header(&connection, 0)? {
+                    let algorithm = hash::algorithm_of(&header.encode(), header.hash);
+                    if algorithm != Some(genesis.config.hash) {
+                        return Err(Error::new(format!(
+                            "the database holds a chain with {algorithm:?} hashes, not {:?}",
+                            genesis.config.hash
+                        )));
+                    }
+                    db::write_chain_config(&connection, &genesis.config)?;
+                }
+            }
+        }
+        let cold = genesis.config.cold.path_for(path)?;
+        if let Some(cold) = &cold {
+            db::attach_cold(&connection, cold, true)?;
+            // Finish a move to cold storage cut short after copying.
+            if let Some(through) = db::read_cold_through(&connection)? {
+                db::delete_moved(&connection, 0, through)?;
+            }
+        }
+        if db::read_head_hash(&connection)?.is_none() {
+            // Databases written before the head pointer existed start it at their canonical tip.
+            if let Some(head) = db::read_canonical_tip(&connection)? {
+                db::write_head(&connection, head.hash)?;
+            }
+        }
+        if db::read_head(&connection)?.is_none() {
+            db::transaction(&connection, || {
+                let block = genesis.block();
+                let mut state = StateOverlay::new(&connection);
+                for (address, account) in genesis.accounts() {
+                    *state.account_mut(address)? = account;
+                }
+                state.commit(block.hash)?;
+                let validator_set = ValidatorSet {
+                    epoch: 0,
+                    seed: block.hash,
+                    validators: db::read_stakes(&connection)?
+                        .into_iter()
+                        .map(|(address, stake)| staking::Validator { address, stake })
+                        .collect(),
+                };
+                db::write_validator_set(&connection, &validator_set)?;
+                db::write_chain_config(&connection, &genesis.config)?;
+                db::write_block(&connection, &block, true)?;
+                db::write_head(&connection, block.hash)?;
+                db::write_finalized(&connection, &block)
+            })?;
+        }
+        let blockhead = Self {
+            connection,
+            mempool: Mutex::new(Mempool::new(
+                genesis.config.gas.clone(),
+                &genesis.config.mempool,
+                genesis.config.hash,
+            )),
+            vm_slots: runtime::VmSlots::new(&genesis.config.runtime),
+            config: genesis.config,
+            clock: Arc::new(SystemClock::new()),
+            status: Mutex::new(StatusTracker::new()),
+            events: EventBus::new(),
+            imports: Default::default(),
+            peers: Default::default(),
+            execution_cache: Default::default(),
+            faucet_payments: Default::default(),
+            time_travel: Default::default(),
+            snapshots: Default::default(),
+            production_paused: Default::default(),
+            shutdown: Notify::new(),
+            write_lock: Default::default(),
+            read_only: false,
+            readers: if in_memory {
+                ReaderPool::empty()
+            } else {
+                ReaderPool::open(path, cold.as_deref(), READER_POOL_SIZE)?
+            },
+            proposer_keys: HashMap::new(),
+        };
+        if blockhead.config.history == StateHistory::Archive {
+            blockhead.sync_archive()?;
+        }
+        Ok(blockhead)
+    }
+
+    /// Open an existing database without write access, for serving reads from a file that a node
+    /// in another process is writing, with the protocol parameters the database records. Methods
+    /// that would modify the database fail. Cold storage is read from beside the database, if
+    /// the writer keeps it there.
+    pub fn new_read_only<T: AsRef<Path>>(db_filename: T) -> Result<Self> {
+        let flags = sqlite::OpenFlags::new().with_read_only();
+        let path = db_filename.as_ref();
+        let mut connection = sqlite::Connection::open_thread_safe_with_flags(path, flags)?;
+        // The writer may briefly hold locks that block reads.
+        connection.set_busy_timeout(5_000)?;
+        db::check_upgraded(&connection)?;
+        let cold = cold::default_path(path);
+        if cold.exists() {
+            db::attach_cold(&connection, &cold, false)?;
+        }
+        if db::read_head(&connection)?.is_none() {
+            return Err(Error::new("read-only database has no chain"));
+        }
+        let config = db::read_chain_config(&connection)?.ok_or_else(|| {
+            Error::new(
+                "the database does not record its chain config; open it for writing once to \
+                 record it",
+            )
+        })?;
+        Ok(Self {
+            connection,
+            mempool: Mutex::new(Mempool::new(
+                config.gas.clone(),
+                &config.mempool,
+                config.hash,
+            )),
+            vm_slots: runtime::VmSlots::new(&config.runtime),
+            config,
+            clock: Arc::new(SystemClock::new()),
+            status: Mutex::new(StatusTracker::new()),
+            events: EventBus::new(),
+            imports: Default::default(),
+            peers: Default::default(),
+            execution_cache: Default::default(),
+            faucet_payments: Default::default(),
+            time_travel: Default::default(),
+            snapshots: Default::default(),
+            production_paused: Default::default(),
+            shutdown: Notify::new(),
+            write_lock: Default::default(),
+            read_only: true,
+            readers: ReaderPool::empty(),
+            proposer_keys: HashMap::new(),
+        })
+    }
+
+    /// Fail if the database was opened read-only, naming the attempted `operation`.
+    fn ensure_writable(&self, operation: &str) -> Result<()> {
+        if self.read_only {
+            return Err(Error::new(format!(
+                "cannot {operation}: the database is open read-only"
+            )));
+        }
+        Ok(())
+    }
+
+    fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Propose blocks for the validator whose key `key` holds, signing them with it. A node
+    /// without the key of the validator elected for a height cannot produce its block.
+    pub fn with_proposer_key(mut self, key: impl Signer + 'static) -> Result<Self> {
+        self.proposer_keys
+            .insert(key.address(self.config.hash)?, Arc::new(key));
+        Ok(self)
+    }
+
+    /// The node's time: its clock, plus however far dev mode has moved it on. See
+    /// [`Blockhead::increase_time`].
+    pub(crate) fn now_nanos(&self) -> u64 {
+        self.clock.now_nanos() + self.time_travel.offset()
+    }
+
+    /// A connection for queries that only read committed state.
+    fn reader(&self) -> ReadConnection<'_> {
+        self.readers.get(&self.connection)
+    }
+
+    fn account(&self, address: Address) -> Result<Account> {
+        Ok(db::read_account(&self.reader(), address)?.unwrap_or_default())
+    }
+
+    /// Check `transaction`'s signatures and admit it to the mempool, returning its hash. The
+    /// sender's balance must cover the most it and the sender's other waiting transactions can
+    /// cost.
+    fn admit(&self, transaction: Transaction) -> Result<Hash> {
+        self.ensure_writable("send a transaction")?;
+        multisig::authorize(
+            &mut StateOverlay::new(&self.reader()),
+            self.config.require_signatures,
+            &transaction,
+            &transaction.signers(self.config.chain_id, self.config.hash)?,
+        )?;
+        let from = transaction.from_address;
+        let balance = self.account(from)?.balance;
+        // Hold the mempool until the events are out, so they cannot trail the transaction's
+        // inclusion.
+        let mut mempool = self.mempool.lock().unwrap();
+        self.drop_expired(&mut mempool);
+        let cost = mempool
+            .committed_balance(from, transaction.nonce)
+            .saturating_add(transaction.max_cost());
+        if cost > balance {
+            return Err(Error::with_kind(
+                ErrorKind::InsufficientFunds {
+                    required: cost,
+                    available: balance,
+                },
+                format!(
+                    "{from} has a balance of {balance}, but its waiting transactions may cost {cost}"
+                ),
+            ));
+        }
+        let (hash, replaced) = mempool.insert(transaction, self.now_nanos())?;
+        let mut status = self.status.lock().unwrap();
+        if let Some(replaced) = replaced {
+            status.publish(replaced, TransactionStatus::Replaced { by: hash });
+        }
+        status.publish(hash, TransactionStatus::Pending);
+        self.events.publish(ChainEvent::NewPendingTx(hash));
+        Ok(hash)
+    }
+
+    /// The nonce `address`'s next transaction needs, past those waiting in the mempool.
+    fn pending_nonce(&self, address: Address) -> Result<u64> {
+        let nonce = self.account(address)?.nonce;
+        Ok(self.mempool.lock().unwrap().next_nonce(address, nonce))
+    }
+}
+
+#[async_trait::async_trait]
+impl Blockchain for Blockhead {
+    async fn get_block(&self, id: BlockId) -> Result<Option<Block>> {
+        runtime::block(|| self.block(id))
+    }
+
+    async fn get_header(&self, id: BlockId) -> Result<Option<Header>> {
+        runtime::block(|| self.header(id))
+    }
+
+    async fn get_raw_block(&self, hash: Hash) -> Result<Option<String>> {
+        let block = runtime::block(|| db::read_block(&self.reader(), hash))?;
+        Ok(block.map(|block| format!("0x{}", hex::encode(block.encode()))))
+    }
+
+    async fn get_block_rewards(&self, id: BlockId) -> Result<BlockReward> {
+        runtime::block(|| self.block_rewards(id))
+    }
+
+    async fn get_transaction(&self, hash: Hash) -> Result<Option<Transaction>> {
+        if let Some(transaction) = runtime::block(|| db::read_transaction(&self.reader(), hash))? {
+            return Ok(Some(transaction));
+        }
+        Ok(self.mempool.lock().unwrap().get(hash))
+    }
+
+    async fn get_transaction_receipt(&self, hash: Hash) -> Result<Option<TransactionReceipt>> {
+        runtime::block(|| db::read_receipt(&self.reader(), hash))
+    }
+
+    async fn get_raw_transaction(&self, hash: Hash) -> Result<Option<String>> {
+        let transaction = runtime::block(|| db::read_transaction(&self.reader(), hash))?;
+        Ok(transaction.map(|transaction| format!("0x{}", hex::encode(transaction.encode()))))
+    }
+
+    async fn send_transaction(&self, transaction: Transaction) -> Result<Hash> {
+        runtime::block(|| self.admit(transaction))
+    }
+
+    async fn get_transaction_status(&self, hash: Hash) -> Result<TransactionStatus> {
+        runtime::block(|| self.transaction_status(hash))
+    }
+
+    async fn get_mempool_content(&self) -> Result<MempoolContent> {
+        runtime::block(|| self.mempool_content())
+    }
+
+    async fn get_mempool_status(&self) -> Result<MempoolStatus> {
+        Ok(runtime::block(|| self.mempool_content())?.status())
+    }
+
+    async fn get_balance(&self, address: Address) -> Result<u64> {
+        Ok(runtime::block(|| self.account(address))?.balance)
+    }
+
+    async fn get_balances(&self, addresses: &[Address]) -> Result<Vec<u64>> {
+        runtime::block(|| db::read_balances(&self.reader(), addresses))
+    }
+
+    async fn get_balance_at(&self, address: Address, block: BlockId) -> Result<u64> {
+        runtime::block(|| {
+            self.query_at(block, |connection| {
+                Ok(db::read_account(connection, address)?
+                    .unwrap_or_default()
+                    .balance)
+            })
+        })
+    }
+
+    async fn get_nonce(&self, address: Address) -> Result<u64> {
+        Ok(runtime::block(|| self.account(address))?.nonce)
+    }
+
+    async fn get_pending_nonce(&self, address: Address) -> Result<u64> {
+        runtime::block(|| self.pending_nonce(address))
+    }
+
+    async fn get_proof(&self, address: Address, block: BlockId) -> Result<Option<AccountProof>> {
+        runtime::block(|| self.account_proof(address, block))
+    }
+
+    async fn get_token_info(&self, token: Address) -> Result<Option<TokenInfo>> {
+        runtime::block(|| self.token_info(token))
+    }
+
+    async fn get_token_balance(&self, token: Address, holder: Address) -> Result<u64> {
+        runtime::block(|| self.token_slot(token, TokenSlot::Balance(holder)))
+    }
+
+    async fn get_token_allowance(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+    ) -> Result<u64> {
+        runtime::block(|| self.token_slot(token, TokenSlot::Allowance { owner, spender }))
+    }
+
+    async fn call(
+        &self,
+        to: Address,
+        data: Vec<u8>,
+        overrides: CallOverrides,
+        block: BlockId,
+    ) -> Result<Vec<u8>> {
+        self.vm_slots
+            .run(|| {
+                self.query_at(block, |connection| {
+                    execution::call(connection, &self.config, to, data, &overrides)
+                })
+            })
+            .await
+    }
+
+    async fn estimate_gas(&self, _to: Address, data: Vec<u8>) -> u64 {
+        self.config.gas.intrinsic_gas(data.len())
+    }
+
+    async fn simulate_transaction(
+        &self,
+        transaction: Transaction,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Simulation> {
+        self.vm_slots
+            .run(|| self.simulate(&transaction, &overrides, block))
+            .await
+    }
+
+    async fn simulate_bundle(
+        &self,
+        transactions: Vec<Transaction>,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Vec<Simulation>> {
+        self.vm_slots
+            .run(|| self.simulate_many(&transactions, &overrides, block))
+            .await
+    }
+
+    async fn debug_trace_transaction(&self, hash: Hash) -> Result<Option<Trace>> {
+        self.vm_slots.run(|| self.trace_transaction(hash)).await
+    }
+
+    async fn get_state_diff(&self, block_hash: Hash) -> Result<Option<StateDiff>> {
+        runtime::block(|| db::read_state_diff(&self.reader(), block_hash))
+    }
+
+    async fn chain_id(&self) -> u64 {
+        self.config.chain_id
+    }
+
+    async fn hash_algorithm(&self) -> HashAlgorithm {
+        self.config.hash
+    }
+
+    async fn syncing(&self) -> bool {
+        runtime::block(|| self.head()).is_ok_and(|head| self.peers.is_ahead_of(head.number))
+    }
+
+    async fn gas_price(&self) -> u64 {
+        let floor = self.mempool.lock().unwrap().min_gas_price();
+        match self.estimate_fee().await {
+            Ok(estimate) => estimate.normal.max(floor),
+            Err(error) => {
+                log::warn!("estimating the gas price failed: {error}");
+                floor
+            }
+        }
+    }
+
+    async fn estimate_fee(&self) -> Result<FeeEstimate> {
+        runtime::block(|| self.fee_estimate())
+    }
+
+    async fn get_chain_stats(&self, window: u64) -> Result<ChainStats> {
+        runtime::block(|| self.chain_stats(window))
+    }
+}
+
+#[cfg(feature = "cli")]
+/// Run the `blockhead` command line with `args`, which exclude the program name.
+pub async fn run_cli(args: &[String]) -> Result<()> {
+    cli::run(args).await
+}
+
+#[cfg(feature = "cli")]
+/// The runtime to run the `blockhead` command line with `args` on, with the threads the node
+/// config of the data directory they name asks for. See [`RuntimeConfig`].
+pub fn cli_runtime(args: &[String]) -> Result<tokio::runtime::Runtime> {
+    cli::runtime(args)
+}
+
+#[tokio::test]
+async fn test_get_none_block_by_hash() {
+    let blockhead = Blockhead::new(":memory:").unwrap();
+    let block_result = blockhead
+        .get_block(BlockId::Hash(Hash::digest_of("abcdef")))
+        .await;
+    assert!(block_result.is_ok());
+    assert!(block_result.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_get_inserted_block_by_hash() {
+    use crate::testkit;
+    let genesis = Genesis {
+        alloc: vec![(Address([0; 32]), 1_000_000)],
+        config: testkit::config(),
+        ..Default::default()
+    };
+    let blockhead = Blockhead::with_genesis(":memory:", genesis).unwrap();
+    let latest_block = blockhead.get_block(BlockId::Latest).await.unwrap().unwrap();
+    assert_eq!(latest_block.number, 0);
+
+    let transaction = Transaction {
+        data: vec![1, 2, 3].into(),
+        gas_limit: 21_048,
+        gas_price: 1,
+        ..testkit::transfer(Address([0; 32]), Address([1; 32]), 100, 0)
+    };
+    let block_hash = blockhead.send_transaction(transaction).await.unwrap();
+    let block_result = blockhead.get_block(BlockId::Hash(block_hash)).await;
+    assert!(block_result.is_ok());
+    assert!(block_result.unwrap().is_none());
+}
+
+#[test]
+fn test_sqlite_mem() {
+    let connection = sqlite::open(":memory:").unwrap();
+
+    let query = "
+        CREATE TABLE users (name TEXT, age INTEGER);
+        INSERT INTO users VALUES ('Alice', 42);
+        INSERT INTO users VALUES ('Bob', 69);
+    ";
+    assert!(connection.execute(query).is_ok());
+}
+
+#[tokio::test]
+async fn test_read_only() {
+    use crate::testkit;
+    let path = std::env::temp_dir().join(format!("blockhead-read-only-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut genesis = Genesis {
+        validators: vec![(testkit::validator(), 100)],
+        ..Default::default()
+    };
+    genesis.config.chain_id = 5;
+    let writer = Blockhead::with_genesis(&path, genesis)
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap();
+    writer.produce_block().unwrap();
+
+    let reader = Blockhead::new_read_only(&path).unwrap();
+    assert_eq!(reader.chain_id().await, 5);
+    assert_eq!(
+        reader
+            .get_header(BlockId::Latest)
+            .await
+            .unwrap()
+            .unwrap()
+            .number,
+        1
+    );
+    let transaction = testkit::transfer(Address([0; 32]), Address([1; 32]), 0, 0);
+    let error = reader.send_transaction(transaction).await.unwrap_err();
+    assert!(error.to_string().contains("read-only"));
+    assert!(reader.produce_block().is_err());
+    assert!(reader
+        .call(
+            Address([1; 32]),
+            vec![],
+            Default::default(),
+            BlockId::Number(0)
+        )
+        .await
+        .is_err());
+
+    // Blocks written afterwards are visible to the reader.
+    writer.produce_block().unwrap();
+    assert_eq!(
+        reader
+            .get_header(BlockId::Latest)
+            .await
+            .unwrap()
+            .unwrap()
+            .number,
+        2
+    );
+    drop((reader, writer));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_chain_config_is_recorded() {
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+
+    let path = std::env::temp_dir().join(format!("blockhead-config-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut genesis = Genesis {
+        validators: vec![(testkit::validator(), 100)],
+        ..Default::default()
+    };
+    genesis.config.chain_id = 5;
+    genesis.config.mempool.max_per_sender = 3;
+    let hash = Blockhead::with_genesis(&path, genesis.clone())
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap()
+        .produce_block()
+        .unwrap()
+        .hash;
+
+    // Another chain's parameters are refused; the node's own settings may change.
+    let error = Blockhead::new(&path).err().unwrap();
+    assert!(error.message().contains("chain_id"), "{error}");
+    let mut local = genesis.clone();
+    local.config.mempool.max_per_sender = 5;
+    Blockhead::with_genesis(&path, local).unwrap();
+    let blockhead = Blockhead::open(&path).unwrap();
+    assert_eq!(blockhead.config.chain_id, 5);
+    assert_eq!(blockhead.head().unwrap().hash, hash);
+    let mut keccak = genesis.config.clone();
+    keccak.hash = HashAlgorithm::Keccak256;
+    db::write_chain_config(&blockhead.connection, &keccak).unwrap();
+    drop(blockhead);
+    let error = Blockhead::with_genesis(&path, genesis.clone())
+        .err()
+        .unwrap();
+    assert!(error.message().contains("Keccak256"), "{error}");
+
+    // A database from before configs were recorded gets the one it is opened with, once its
+    // genesis block shows the hash function is right.
+    let connection = sqlite::open(&path).unwrap();
+    connection.execute("DROP TABLE chain_config").unwrap();
+    drop(connection);
+    assert!(Blockhead::open(&path).is_err());
+    assert!(Blockhead::new_read_only(&path).is_err());
+    Blockhead::with_genesis(&path, genesis).unwrap();
+    assert_eq!(Blockhead::open(&path).unwrap().config.chain_id, 5);
+    std::fs::remove_file(&path).unwrap();
+
+    let missing = path.with_extension("missing");
+    assert!(Blockhead::open(&missing).is_err());
+    assert!(!missing.exists());
+}
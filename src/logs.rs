@@ -0,0 +1,290 @@
+//! Logs emitted by contract code, and subscriptions that follow them along the canonical chain.
+//!
+//! A [`LogSubscription`] can start in the past: it first sends the matching logs of canonical
+//! blocks from a given height, then those of each new block. When blocks whose logs it sent leave
+//! the canonical chain, it sends their matching logs again with `removed` set, newest first,
+//! before any logs of the new branch.
+use crate::address::Address;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::events::ChainEvent;
+use crate::hash::Hash;
+use crate::runtime;
+use crate::{Blockhead, Log};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// The most blocks a subscription reads before handing back what it found, so a backfill over a
+/// long range arrives in pieces.
+const BATCH_BLOCKS: u64 = 256;
+/// The most recent blocks a subscription remembers sending, which bounds the depth of reorg it
+/// can send removals for. Reorgs never pass the finalized checkpoint, so older blocks are
+/// forgotten as it advances.
+const REORG_WINDOW: usize = 4096;
+
+/// Which logs to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LogFilter {
+    /// The contracts whose logs match, or every contract if empty.
+    pub addresses: Vec<Address>,
+    /// For each position, the topics that match there, or any topic if empty. Logs with fewer
+    /// topics than there are constrained positions do not match.
+    pub topics: Vec<Vec<Hash>>,
+}
+
+impl LogFilter {
+    pub(crate) fn matches(&self, log: &Log) -> bool {
+        if !self.addresses.is_empty() && !self.addresses.contains(&log.address) {
+            return false;
+        }
+        self.topics
+            .iter()
+            .enumerate()
+            .all(|(position, topics)| match log.topics.get(position) {
+                _ if topics.is_empty() => true,
+                Some(topic) => topics.contains(topic),
+                None => false,
+            })
+    }
+}
+
+/// A log with where it was emitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LogEntry {
+    pub log: Log,
+    pub block_hash: Hash,
+    pub block_number: u64,
+    pub transaction_hash: Hash,
+    /// The position of the log among all logs of its block.
+    pub log_index: u64,
+    /// Set when a subscription retracts a log sent before, as its block left the canonical chain.
+    pub removed: bool,
+}
+
+pub(crate) struct LogSubscription {
+    blockhead: Arc<Blockhead>,
+    filter: LogFilter,
+    events: broadcast::Receiver<ChainEvent>,
+    /// The height of the next block to send logs for.
+    next_block: u64,
+    /// The recent blocks whose logs were sent, by height, with the entries sent for each.
+    sent: BTreeMap<u64, (Hash, Vec<LogEntry>)>,
+}
+
+impl Blockhead {
+    /// Follow the logs matching `filter`, from canonical block `from_block` if given, and from
+    /// the next block otherwise.
+    pub(crate) fn subscribe_logs(
+        self: &Arc<Self>,
+        filter: LogFilter,
+        from_block: Option<u64>,
+    ) -> Result<LogSubscription> {
+        // Subscribe before reading the head, so no block lands between the two unnoticed.
+        let events = self.subscribe_chain_events();
+        let next_block = match from_block {
+            Some(number) => number,
+            None => self.head()?.number + 1,
+        };
+        Ok(LogSubscription {
+            blockhead: self.clone(),
+            filter,
+            events,
+            next_block,
+            sent: BTreeMap::new(),
+        })
+    }
+}
+
+impl LogSubscription {
+    /// The next entries to deliver, waiting for the chain to change if there are none yet.
+    pub(crate) async fn next(&mut self) -> Result<Vec<LogEntry>> {
+        loop {
+            let (entries, caught_up) = runtime::block(|| self.catch_up())?;
+            if !entries.is_empty() {
+                return Ok(entries);
+            }
+            if caught_up {
+                // The events only say when to look again; the database says what changed, so a
+                // subscription that lagged behind the events loses nothing.
+                match self.events.recv().await {
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Err(Error::new("the node shut down")),
+                }
+            }
+        }
+    }
+
+    /// Retract the sent blocks that are no longer canonical, then read ahead up to
+    /// [`BATCH_BLOCKS`] canonical blocks, returning the entries and whether it reached the head.
+    fn catch_up(&mut self) -> Result<(Vec<LogEntry>, bool)> {
+        let connection = self.blockhead.reader();
+        let mut entries = Vec::new();
+        while let Some((&number, (hash, _))) = self.sent.last_key_value() {
+            let canonical = db::read_canonical_header(&connection, number)?;
+            if canonical.is_some_and(|header| header.hash == *hash) {
+                break;
+            }
+            let (_, (_, sent)) = self.sent.pop_last().unwrap();
+            entries.extend(sent.into_iter().rev().map(|entry| LogEntry {
+                removed: true,
+                ..entry
+            }));
+            self.next_block = number;
+        }
+        let head = self.blockhead.head()?.number;
+        let last = head.min(self.next_block.saturating_add(BATCH_BLOCKS - 1));
+        let mut headers = Vec::new();
+        for number in self.next_block..=last {
+            match db::read_canonical_header(&connection, number)? {
+                Some(header) => headers.push(header),
+                None => break,
+            }
+        }
+        if let (Some(first), Some(last)) = (headers.first(), headers.last()) {
+            let mut logs = db::read_logs(&connection, &self.filter, first.number, last.number)?
+                .into_iter()
+                .peekable();
+            for header in headers {
+                // Logs of a block that replaced this one since are left for the next look.
+                let matched: Vec<LogEntry> = std::iter::from_fn(|| {
+                    logs.next_if(|entry| entry.block_number == header.number)
+                })
+                .filter(|entry| entry.block_hash == header.hash)
+                .collect();
+                entries.extend(matched.iter().cloned());
+                self.sent.insert(header.number, (header.hash, matched));
+                self.next_block = header.number + 1;
+            }
+        }
+        while self.sent.len() > REORG_WINDOW {
+            self.sent.pop_first();
+        }
+        Ok((entries, self.next_block > head))
+    }
+}
+
+#[cfg(test)]
+fn topic(word: u64) -> Hash {
+    let mut topic = [0; 32];
+    topic[24..].copy_from_slice(&word.to_be_bytes());
+    Hash(topic)
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_log_subscription() {
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use crate::transaction::Transaction;
+    use crate::vm::{opcode::*, push};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    // Emit one log with the call data word as both its topic and its data.
+    let code = [
+        push(0),
+        vec![CALLDATALOAD],
+        push(24),
+        vec![MSTORE],
+        push(1),
+        push(0),
+        push(8),
+        push(24),
+        vec![LOG, STOP],
+    ]
+    .concat();
+    let chain = TestChain::new();
+    let validator = chain.validator;
+    let contract = Address::for_contract(validator, 0, algorithm);
+    let call = |nonce: u64, data: Vec<u8>| Transaction {
+        data: data.into(),
+        gas_limit: 100_000,
+        ..testkit::transfer(validator, contract, 0, nonce)
+    };
+    chain
+        .send(Transaction {
+            to_address: None,
+            ..call(0, code)
+        })
+        .await;
+    chain.produce();
+    let mut hashes = Vec::new();
+    for word in [1u64, 2] {
+        hashes.push(chain.send(call(word, word.to_be_bytes().to_vec())).await);
+        chain.produce();
+    }
+    let receipt = chain.blockhead.get_transaction_receipt(hashes[0]).await;
+    let logs = receipt.unwrap().unwrap().logs;
+    assert_eq!(
+        logs,
+        vec![Log {
+            address: contract,
+            topics: vec![topic(1)],
+            data: 1u64.to_be_bytes().to_vec(),
+        }]
+    );
+    // Receipts keep their logs once indexed.
+    chain.blockhead.index_receipts(10).unwrap();
+    let receipt = chain.blockhead.get_transaction_receipt(hashes[0]).await;
+    assert_eq!(receipt.unwrap().unwrap().logs, logs);
+
+    let blockhead = Arc::new(chain.blockhead);
+    let mut all = blockhead
+        .subscribe_logs(LogFilter::default(), Some(0))
+        .unwrap();
+    let filter = LogFilter {
+        addresses: vec![contract],
+        topics: vec![vec![topic(2), topic(3)]],
+    };
+    let mut filtered = blockhead.subscribe_logs(filter, Some(0)).unwrap();
+    let mut live = blockhead
+        .subscribe_logs(LogFilter::default(), None)
+        .unwrap();
+    let backfill = all.next().await.unwrap();
+    let topics: Vec<_> = backfill
+        .iter()
+        .map(|e| (e.log.topics[0], e.block_number))
+        .collect();
+    assert_eq!(topics, vec![(topic(1), 2), (topic(2), 3)]);
+    assert_eq!(backfill[1].transaction_hash, hashes[1]);
+    let backfill = filtered.next().await.unwrap();
+    assert_eq!(backfill.len(), 1);
+    assert_eq!(backfill[0].log.topics, vec![topic(2)]);
+
+    let block2 = db::read_canonical_block(&blockhead.reader(), 2)
+        .unwrap()
+        .unwrap();
+    let transaction = call(3, 3u64.to_be_bytes().to_vec());
+    blockhead.send_transaction(transaction).await.unwrap();
+    blockhead.produce_block().unwrap();
+    for subscription in [&mut all, &mut filtered, &mut live] {
+        let entries = subscription.next().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].log.topics, vec![topic(3)]);
+        assert_eq!(entries[0].block_number, 4);
+        assert!(!entries[0].removed);
+    }
+
+    // A longer branch off block 2 reverts blocks 3 and 4: their logs come back removed, newest
+    // first, and the new branch has none.
+    let mut parent = block2;
+    for _ in 0..3 {
+        let block = crate::chain::side_block(&blockhead, &parent, parent.timestamp + 1_000);
+        blockhead.import_block(&block).unwrap();
+        parent = block;
+    }
+    let removed = all.next().await.unwrap();
+    let topics: Vec<_> = removed
+        .iter()
+        .map(|e| (e.log.topics[0], e.removed))
+        .collect();
+    assert_eq!(topics, vec![(topic(3), true), (topic(2), true)]);
+    let removed = filtered.next().await.unwrap();
+    assert_eq!(removed.len(), 2);
+    let removed = live.next().await.unwrap();
+    assert_eq!(removed.len(), 1);
+    assert!(removed[0].removed);
+    assert_eq!(removed[0].block_number, 4);
+}
@@ -0,0 +1,243 @@
+//! Scheduled database compaction.
+//!
+//! Deleting reorged blocks, pruned traces and old undo records leaves free pages behind that
+//! SQLite only returns to the filesystem on `VACUUM`. A vacuum rewrites the whole file and holds
+//! the write lock while it does, so it runs at most once per interval and, optionally, only inside
+//! a daily window of quiet hours.
+//!
+//! [`Blockhead::db_stats`] reports what the database holds and how much disk it takes, so
+//! operators can see what pruning or compaction would win before running them.
+use crate::db;
+use crate::error::Result;
+use crate::Blockhead;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct MaintenanceConfig {
+    /// The least time between compactions.
+    pub interval: Duration,
+    /// UTC hours `[start, end)` during which compaction may run; the window may wrap past
+    /// midnight. `None` allows any time.
+    pub window: Option<(u64, u64)>,
+    /// How often the scheduler wakes to check whether compaction is due.
+    pub poll_interval: Duration,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(24 * 60 * 60),
+            window: None,
+            poll_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    /// Whether to compact at `now`, given the time of the last compaction.
+    pub(crate) fn is_due(&self, last_run: Option<u64>, now: u64) -> bool {
+        if let Some(last_run) = last_run {
+            if now.saturating_sub(last_run) < self.interval.as_nanos() as u64 {
+                return false;
+            }
+        }
+        let Some((start, end)) = self.window else {
+            return true;
+        };
+        let hour = now / NANOS_PER_HOUR % 24;
+        if start <= end {
+            (start..end).contains(&hour)
+        } else {
+            hour >= start || hour < end
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CompactionReport {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// What the database holds and how much disk it takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DbStats {
+    pub files: DbFileSizes,
+    /// Tables, largest first counting their indexes.
+    pub tables: Vec<TableStats>,
+}
+
+/// The sizes of the database's files, in bytes, which are cheap to read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct DbFileSizes {
+    pub database: u64,
+    /// Free pages in the database file, which compaction would return to the filesystem.
+    pub free: u64,
+    /// The write-ahead log, holding writes not yet copied into the database file.
+    pub wal: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TableStats {
+    pub name: String,
+    pub rows: u64,
+    /// Bytes of the table's own pages.
+    pub size: u64,
+    /// Bytes of each of the table's indexes, by name.
+    pub indexes: Vec<(String, u64)>,
+}
+
+impl TableStats {
+    /// Bytes of the table and its indexes together.
+    pub(crate) fn total_size(&self) -> u64 {
+        self.size + self.indexes.iter().map(|(_, size)| size).sum::<u64>()
+    }
+}
+
+impl Blockhead {
+    /// Vacuum and analyze the database.
+    pub(crate) fn compact(&self) -> Result<CompactionReport> {
+        self.ensure_writable("compact the database")?;
+        let _guard = self.write_lock.lock().unwrap();
+        let size_before = db::read_database_size(&self.connection)?;
+        db::compact(&self.connection)?;
+        let size_after = db::read_database_size(&self.connection)?;
+        log::info!("compacted database from {size_before} to {size_after} bytes");
+        Ok(CompactionReport {
+            size_before,
+            size_after,
+        })
+    }
+
+    /// Bytes used by each table and index, largest first, for reporting.
+    pub(crate) fn table_sizes(&self) -> Result<Vec<(String, u64)>> {
+        db::read_table_sizes(&self.connection)
+    }
+
+    /// Row counts and sizes of every table and index, and the sizes of the database's files.
+    /// Reads every page of the database, so takes as long as a full scan.
+    pub(crate) fn db_stats(&self) -> Result<DbStats> {
+        let connection = self.reader();
+        let sizes: HashMap<String, u64> = db::read_table_sizes(&connection)?.into_iter().collect();
+        let size = |name: &str| sizes.get(name).copied().unwrap_or_default();
+        let objects = db::read_schema_objects(&connection)?;
+        let mut tables = Vec::new();
+        for (kind, name, _) in &objects {
+            if kind != "table" {
+                continue;
+            }
+            let indexes = objects
+                .iter()
+                .filter(|(kind, _, table)| kind == "index" && table == name)
+                .map(|(_, index, _)| (index.clone(), size(index)))
+                .collect();
+            tables.push(TableStats {
+                name: name.clone(),
+                rows: db::count_rows(&connection, name)?,
+                size: size(name),
+                indexes,
+            });
+        }
+        tables.sort_by_key(|table| std::cmp::Reverse(table.total_size()));
+        Ok(DbStats {
+            files: self.db_file_sizes()?,
+            tables,
+        })
+    }
+
+    /// The sizes of the database's files, without reading its pages.
+    pub(crate) fn db_file_sizes(&self) -> Result<DbFileSizes> {
+        let connection = self.reader();
+        let wal = match db::read_database_path(&connection)? {
+            Some(path) => {
+                let mut wal = path.into_os_string();
+                wal.push("-wal");
+                std::fs::metadata(wal).map_or(0, |metadata| metadata.len())
+            }
+            None => 0,
+        };
+        Ok(DbFileSizes {
+            database: db::read_database_size(&connection)?,
+            free: db::read_free_size(&connection)?,
+            wal,
+        })
+    }
+}
+
+/// Compact `blockhead` whenever `config` says it is due, until the task is dropped.
+pub(crate) async fn run(blockhead: Arc<Blockhead>, config: MaintenanceConfig) {
+    let mut last_run = None;
+    loop {
+        tokio::time::sleep(config.poll_interval).await;
+        let now = blockhead.now_nanos();
+        if !config.is_due(last_run, now) {
+            continue;
+        }
+        last_run = Some(now);
+        let blockhead = blockhead.clone();
+        match tokio::task::spawn_blocking(move || blockhead.compact()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => log::error!("compaction failed: {error}"),
+            Err(error) => log::error!("compaction panicked: {error}"),
+        }
+    }
+}
+
+#[test]
+fn test_compaction_schedule() {
+    let hour = NANOS_PER_HOUR;
+    let config = MaintenanceConfig {
+        interval: Duration::from_secs(12 * 60 * 60),
+        window: Some((22, 4)),
+        ..Default::default()
+    };
+    assert!(config.is_due(None, 23 * hour));
+    assert!(config.is_due(None, (24 + 3) * hour));
+    assert!(!config.is_due(None, 12 * hour));
+    assert!(!config.is_due(Some(23 * hour), (24 + 2) * hour));
+    assert!(config.is_due(Some(hour), (24 + 2) * hour));
+
+    let blockhead = Blockhead::new(":memory:").unwrap();
+    let report = blockhead.compact().unwrap();
+    assert!(report.size_after > 0);
+    let sizes = blockhead.table_sizes().unwrap();
+    assert!(sizes.iter().any(|(name, _)| name == "block"));
+}
+
+#[test]
+fn test_db_stats() {
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    chain.produce_many(3);
+    let stats = chain.blockhead.db_stats().unwrap();
+    let block = stats
+        .tables
+        .iter()
+        .find(|table| table.name == "block")
+        .unwrap();
+    assert_eq!(block.rows, 4);
+    assert!(block.size > 0);
+    assert!(block
+        .indexes
+        .iter()
+        .any(|(name, size)| name == "block_number" && *size > 0));
+    let sizes: Vec<u64> = stats.tables.iter().map(TableStats::total_size).collect();
+    assert!(sizes.windows(2).all(|pair| pair[0] >= pair[1]));
+    assert!(stats.files.database > 0);
+    assert_eq!(stats.files.wal, 0);
+
+    let path = std::env::temp_dir().join(format!("blockhead-stats-{}.db", std::process::id()));
+    let blockhead = Blockhead::new(&path).unwrap();
+    let files = blockhead.db_file_sizes().unwrap();
+    assert!(files.database > 0);
+    assert!(files.wal > 0);
+    drop(blockhead);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
@@ -0,0 +1,851 @@
+//! Transactions admitted by the node but not yet included in a block.
+//!
+//! The pool lives in memory, but a node with a data directory saves it to the database on a
+//! timer and at shutdown, and takes it back at startup. Saved transactions are admitted again as
+//! if newly sent, except those whose nonce the chain has passed meanwhile.
+use crate::address::Address;
+use crate::block::Block;
+use crate::builder::BlockOrdering;
+use crate::db;
+use crate::error::{Error, ErrorKind, Result};
+use crate::events::ChainEvent;
+use crate::gas::GasConfig;
+use crate::hash::{Hash, HashAlgorithm};
+#[cfg(feature = "http")]
+use crate::http::Response;
+use crate::status::TransactionStatus;
+use crate::transaction::Transaction;
+#[cfg(any(test, feature = "http"))]
+use crate::Blockchain;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http")]
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MempoolConfig {
+    /// The most transactions one sender may have waiting.
+    pub max_per_sender: usize,
+    /// The lowest gas price admitted, above zero so that accounts holding nothing cannot fill the
+    /// pool for free. Transactions already waiting are kept when it rises.
+    pub min_gas_price: u64,
+    /// The order in which this node's blocks take transactions from the pool. See
+    /// [`crate::builder`].
+    pub ordering: BlockOrdering,
+    /// How long building a block may spend applying transactions before it defers the rest to
+    /// the next block. Unset, only the block gas limit bounds it.
+    pub build_time: Option<Duration>,
+    /// How often to save the pool, so that a crash loses at most this much of it. Unset, it is
+    /// only saved at shutdown.
+    pub persist_interval: Option<Duration>,
+    /// How long a transaction may wait before it is dropped, counted by the node's clock from
+    /// its admission. Ages restart with the node. Unset, transactions wait until included or
+    /// replaced.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_per_sender: 64,
+            min_gas_price: 1,
+            ordering: BlockOrdering::Fifo,
+            build_time: None,
+            persist_interval: Some(Duration::from_secs(60)),
+            max_age: Some(Duration::from_secs(3 * 60 * 60)),
+        }
+    }
+}
+
+/// Transactions by sender, then nonce.
+pub(crate) type BySender = BTreeMap<Address, BTreeMap<u64, Transaction>>;
+
+/// The mempool's transactions, split by whether they can be included as things stand.
+#[derive(Debug, Clone, Default)]
+pub struct MempoolContent {
+    /// Transactions whose nonces follow on from their sender's account nonce without a gap.
+    pub pending: BySender,
+    /// Transactions past a gap in their sender's nonces, which wait for it to be filled, and
+    /// those whose nonce the sender has already spent.
+    pub queued: BySender,
+}
+
+impl MempoolContent {
+    /// Sort `transactions`, with `account_nonce` giving each sender's account nonce.
+    pub(crate) fn group(
+        transactions: Vec<Transaction>,
+        mut account_nonce: impl FnMut(Address) -> Result<u64>,
+    ) -> Result<Self> {
+        let mut by_sender = BySender::new();
+        for transaction in transactions {
+            by_sender
+                .entry(transaction.from_address)
+                .or_default()
+                .insert(transaction.nonce, transaction);
+        }
+        let mut content = Self::default();
+        for (sender, transactions) in by_sender {
+            let mut next = account_nonce(sender)?;
+            for (nonce, transaction) in transactions {
+                let group = if nonce == next {
+                    next += 1;
+                    &mut content.pending
+                } else {
+                    &mut content.queued
+                };
+                group.entry(sender).or_default().insert(nonce, transaction);
+            }
+        }
+        Ok(content)
+    }
+
+    pub(crate) fn status(&self) -> MempoolStatus {
+        let count = |group: &BySender| group.values().map(BTreeMap::len).sum();
+        MempoolStatus {
+            pending: count(&self.pending),
+            queued: count(&self.queued),
+        }
+    }
+}
+
+/// How many transactions a [`MempoolContent`] holds in each group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolStatus {
+    pub pending: usize,
+    pub queued: usize,
+}
+
+pub(crate) struct Mempool {
+    gas: GasConfig,
+    algorithm: HashAlgorithm,
+    max_per_sender: usize,
+    min_gas_price: u64,
+    max_age: Option<Duration>,
+    transactions: Vec<(Hash, Transaction)>,
+    /// When each transaction was admitted, in clock nanoseconds. Kept apart from `transactions`
+    /// so that a transaction keeps its age while a dev-mode revert has it taken, and forgotten
+    /// once it is included or dropped.
+    admitted: HashMap<Hash, u64>,
+}
+
+impl Mempool {
+    /// An empty pool for a chain that hashes with `algorithm`.
+    pub(crate) fn new(gas: GasConfig, config: &MempoolConfig, algorithm: HashAlgorithm) -> Self {
+        Self {
+            gas,
+            algorithm,
+            max_per_sender: config.max_per_sender,
+            min_gas_price: config.min_gas_price,
+            max_age: config.max_age,
+            transactions: Vec::new(),
+            admitted: HashMap::new(),
+        }
+    }
+
+    /// Apply the admission limits of `config` to transactions admitted from now on, and its
+    /// [`MempoolConfig::max_age`] to every transaction from the next expiry on.
+    pub(crate) fn reconfigure(&mut self, config: &MempoolConfig) {
+        self.max_per_sender = config.max_per_sender;
+        self.min_gas_price = config.min_gas_price;
+        self.max_age = config.max_age;
+    }
+
+    pub(crate) fn min_gas_price(&self) -> u64 {
+        self.min_gas_price
+    }
+
+    /// Admit `transaction` if it passes the stateless checks, returning its hash and the hash of
+    /// the transaction it replaced. A pending transaction with the same sender and nonce is
+    /// replaced in place, but only by one paying a higher gas price. Otherwise the sender may not
+    /// have more than [`MempoolConfig::max_per_sender`] transactions waiting. `now` is the
+    /// admission time its age counts from.
+    pub(crate) fn insert(
+        &mut self,
+        transaction: Transaction,
+        now: u64,
+    ) -> Result<(Hash, Option<Hash>)> {
+        self.gas.check(&transaction)?;
+        let hash = transaction.compute_hash(self.algorithm);
+        if transaction.gas_price < self.min_gas_price {
+            return Err(Error::with_kind(
+                ErrorKind::Underpriced {
+                    minimum: self.min_gas_price,
+                    offered: transaction.gas_price,
+                },
+                format!(
+                    "transaction {hash} pays gas price {}, below the minimum {}",
+                    transaction.gas_price, self.min_gas_price
+                ),
+            ));
+        }
+        let from = transaction.from_address;
+        let existing = self.transactions.iter_mut().find(|(_, pending)| {
+            pending.from_address == from && pending.nonce == transaction.nonce
+        });
+        if let Some((pending_hash, pending)) = existing {
+            if transaction.gas_price <= pending.gas_price {
+                return Err(Error::with_kind(
+                    ErrorKind::Underpriced {
+                        minimum: pending.gas_price.saturating_add(1),
+                        offered: transaction.gas_price,
+                    },
+                    format!(
+                        "transaction {hash} does not pay more than pending transaction \
+                         {pending_hash} with the same nonce"
+                    ),
+                ));
+            }
+            let replaced = std::mem::replace(pending_hash, hash);
+            *pending = transaction;
+            self.admitted.remove(&replaced);
+            self.admitted.insert(hash, now);
+            return Ok((hash, Some(replaced)));
+        }
+        let waiting = self
+            .transactions
+            .iter()
+            .filter(|(_, pending)| pending.from_address == from)
+            .count();
+        if waiting >= self.max_per_sender {
+            return Err(Error::new(format!(
+                "{from} already has {waiting} transactions waiting"
+            )));
+        }
+        self.transactions.push((hash, transaction));
+        self.admitted.insert(hash, now);
+        Ok((hash, None))
+    }
+
+    /// The most `address`'s waiting transactions can take from its balance, leaving out any with
+    /// nonce `except`. See [`Transaction::max_cost`].
+    pub(crate) fn committed_balance(&self, address: Address, except: u64) -> u64 {
+        self.transactions
+            .iter()
+            .filter(|(_, pending)| pending.from_address == address && pending.nonce != except)
+            .fold(0, |total, (_, pending)| {
+                total.saturating_add(pending.max_cost())
+            })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub(crate) fn contains(&self, hash: Hash) -> bool {
+        self.transactions
+            .iter()
+            .any(|(pending, _)| *pending == hash)
+    }
+
+    /// A copy of pending transaction `hash`.
+    pub(crate) fn get(&self, hash: Hash) -> Option<Transaction> {
+        self.transactions
+            .iter()
+            .find(|(pending, _)| *pending == hash)
+            .map(|(_, transaction)| transaction.clone())
+    }
+
+    /// Remove the transactions included in `block`, and those that no longer can be because it
+    /// holds another with the same sender and nonce, returning the hashes of the latter. Those
+    /// are replacements admitted while the block was built from the transactions they replaced.
+    pub(crate) fn remove_included(&mut self, block: &Block) -> Vec<Hash> {
+        let included = &block.body.transactions;
+        let superseded: Vec<Hash> = (self.transactions.iter())
+            .filter(|(hash, pending)| {
+                included.iter().any(|(included_hash, transaction)| {
+                    included_hash != hash
+                        && transaction.from_address == pending.from_address
+                        && transaction.nonce == pending.nonce
+                })
+            })
+            .map(|(hash, _)| *hash)
+            .collect();
+        let mut hashes: Vec<Hash> = included.iter().map(|(hash, _)| *hash).collect();
+        hashes.extend(&superseded);
+        self.remove(&hashes);
+        superseded
+    }
+
+    /// Remove transactions `hashes`, which left the pool for good, and forget their admission
+    /// times.
+    pub(crate) fn remove(&mut self, hashes: &[Hash]) {
+        self.forget(hashes);
+        self.transactions.retain(|(hash, _)| !hashes.contains(hash));
+    }
+
+    /// The first nonce from `nonce` on that `address` has no transaction waiting for. A gap in
+    /// the sender's nonces stops the count, as nothing past it can be included yet.
+    pub(crate) fn next_nonce(&self, address: Address, mut nonce: u64) -> u64 {
+        while self.transactions.iter().any(|(_, transaction)| {
+            transaction.from_address == address && transaction.nonce == nonce
+        }) {
+            nonce += 1;
+        }
+        nonce
+    }
+
+    /// A copy of every transaction, in admission order.
+    pub(crate) fn pending(&self) -> Vec<Transaction> {
+        self.transactions
+            .iter()
+            .map(|(_, transaction)| transaction.clone())
+            .collect()
+    }
+
+    /// Remove every transaction, in admission order, keeping their admission times. See
+    /// [`Mempool::restore`].
+    pub(crate) fn take(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.transactions)
+            .into_iter()
+            .map(|(_, transaction)| transaction)
+            .collect()
+    }
+
+    /// Take back the transactions of `reverted`, blocks that left the canonical chain listed
+    /// newest first, ahead of those admitted since, returning the hashes of those readmitted. A
+    /// transaction is left out if the pool already has it, or another from the same sender with
+    /// the same nonce. Readmitted transactions count their age from `now`.
+    pub(crate) fn readmit(&mut self, reverted: &[Block], now: u64) -> Vec<Hash> {
+        let mut readmitted: Vec<(Hash, Transaction)> = Vec::new();
+        let transactions = reverted
+            .iter()
+            .rev()
+            .flat_map(|block| &block.body.transactions);
+        for (hash, transaction) in transactions {
+            let conflicts = self.transactions.iter().any(|(pending_hash, pending)| {
+                pending_hash == hash
+                    || (pending.from_address == transaction.from_address
+                        && pending.nonce == transaction.nonce)
+            });
+            if !conflicts {
+                readmitted.push((*hash, transaction.clone()));
+            }
+        }
+        let hashes: Vec<Hash> = readmitted.iter().map(|(hash, _)| *hash).collect();
+        for hash in &hashes {
+            self.admitted.insert(*hash, now);
+        }
+        let newer = std::mem::replace(&mut self.transactions, readmitted);
+        self.transactions.extend(newer);
+        hashes
+    }
+
+    /// Put transactions back at the front of the pool, as a dev-mode revert does with those of
+    /// the snapshot it returns to.
+    pub(crate) fn restore(&mut self, transactions: Vec<Transaction>) {
+        let restored = transactions
+            .into_iter()
+            .map(|transaction| (transaction.compute_hash(self.algorithm), transaction))
+            .collect();
+        let newer = std::mem::replace(&mut self.transactions, restored);
+        self.transactions.extend(newer);
+    }
+
+    /// Forget the admission times of `hashes`, transactions that left the pool for good.
+    pub(crate) fn forget<'a>(&mut self, hashes: impl IntoIterator<Item = &'a Hash>) {
+        for hash in hashes {
+            self.admitted.remove(hash);
+        }
+    }
+
+    /// Remove the transactions that have waited longer than [`MempoolConfig::max_age`] at time
+    /// `now`, returning their hashes. A transaction with no admission time counts its age from
+    /// `now`.
+    pub(crate) fn expire(&mut self, now: u64) -> Vec<Hash> {
+        let Some(max_age) = self.max_age else {
+            return Vec::new();
+        };
+        let cutoff = now.saturating_sub(max_age.as_nanos() as u64);
+        let mut expired = Vec::new();
+        for (hash, _) in &self.transactions {
+            if *self.admitted.entry(*hash).or_insert(now) < cutoff {
+                self.admitted.remove(hash);
+                expired.push(*hash);
+            }
+        }
+        if !expired.is_empty() {
+            self.transactions
+                .retain(|(hash, _)| !expired.contains(hash));
+        }
+        expired
+    }
+}
+
+impl Blockhead {
+    /// Drop the transactions of `mempool`, the node's locked mempool, that have waited longer
+    /// than [`MempoolConfig::max_age`], marking each dropped and announcing it.
+    pub(crate) fn drop_expired(&self, mempool: &mut Mempool) {
+        let expired = mempool.expire(self.now_nanos());
+        if expired.is_empty() {
+            return;
+        }
+        log::info!("dropping {} expired transactions", expired.len());
+        let mut status = self.status.lock().unwrap();
+        for hash in expired {
+            let reason = "waited longer than the mempool's maximum age".to_string();
+            status.publish(hash, TransactionStatus::Dropped { reason });
+            self.events.publish(ChainEvent::ExpiredPendingTx(hash));
+        }
+    }
+
+    /// Save the mempool to the database, replacing what was saved before, and return how many
+    /// transactions were saved.
+    pub(crate) fn persist_mempool(&self) -> Result<usize> {
+        self.ensure_writable("save the mempool")?;
+        let _guard = self.write_lock.lock().unwrap();
+        let pending = self.mempool.lock().unwrap().pending();
+        db::transaction(&self.connection, || {
+            db::write_mempool(&self.connection, &pending)
+        })?;
+        Ok(pending.len())
+    }
+
+    /// The mempool's transactions, grouped. See [`MempoolContent`].
+    pub(crate) fn mempool_content(&self) -> Result<MempoolContent> {
+        let transactions = self.mempool.lock().unwrap().pending();
+        MempoolContent::group(transactions, |address| Ok(self.account(address)?.nonce))
+    }
+
+    /// Admit the transactions of the saved mempool again, returning how many were admitted.
+    /// Those whose nonce the sender's account has passed are dropped, and those that no longer
+    /// pass admission are dropped with a warning.
+    pub(crate) fn restore_mempool(&self) -> Result<usize> {
+        self.ensure_writable("restore the mempool")?;
+        let mut restored = 0;
+        for transaction in db::read_mempool(&self.reader())? {
+            if transaction.nonce < self.account(transaction.from_address)?.nonce {
+                continue;
+            }
+            let hash = transaction.compute_hash(self.config.hash);
+            match self.admit(transaction) {
+                Ok(_) => restored += 1,
+                Err(error) => log::warn!("dropping saved transaction {hash}: {error}"),
+            }
+        }
+        Ok(restored)
+    }
+}
+
+/// Answer `/mempool/content` with each group's transactions by sender and nonce.
+#[cfg(feature = "http")]
+pub(crate) async fn handle_content(blockhead: &Arc<Blockhead>) -> Response {
+    let algorithm = blockhead.config.hash;
+    let group_json = |group: &BySender| {
+        let senders: Map<String, Value> = group
+            .iter()
+            .map(|(sender, transactions)| {
+                let by_nonce: Map<String, Value> = transactions
+                    .iter()
+                    .map(|(nonce, transaction)| {
+                        let value = json!({
+                            "hash": transaction.compute_hash(algorithm).to_string(),
+                            "to": transaction.to_address.map(|address| address.to_string()),
+                            "value": transaction.value,
+                            "gas_limit": transaction.gas_limit,
+                            "gas_price": transaction.gas_price,
+                        });
+                        (nonce.to_string(), value)
+                    })
+                    .collect();
+                (sender.to_string(), Value::Object(by_nonce))
+            })
+            .collect();
+        Value::Object(senders)
+    };
+    match blockhead.get_mempool_content().await {
+        Ok(content) => Response::json(
+            200,
+            &json!({
+                "pending": group_json(&content.pending),
+                "queued": group_json(&content.queued),
+            }),
+        ),
+        Err(error) => Response::error(500, error.message()),
+    }
+}
+
+/// Answer `/mempool/status` with the number of transactions in each group.
+#[cfg(feature = "http")]
+pub(crate) async fn handle_status(blockhead: &Arc<Blockhead>) -> Response {
+    match blockhead.get_mempool_status().await {
+        Ok(status) => Response::json(
+            200,
+            &json!({"pending": status.pending, "queued": status.queued}),
+        ),
+        Err(error) => Response::error(500, error.message()),
+    }
+}
+
+/// Answer `POST /transactions`, whose body is `{"raw": "0x..."}`, an encoded signed transaction,
+/// by admitting it, with its hash.
+#[cfg(feature = "http")]
+pub(crate) async fn handle_send(blockhead: &Arc<Blockhead>, body: &[u8]) -> Response {
+    let transaction = serde_json::from_slice::<Value>(body)
+        .map_err(Error::from)
+        .and_then(|body| {
+            let raw = body["raw"]
+                .as_str()
+                .ok_or_else(|| Error::new("body needs a \"raw\" transaction"))?;
+            let bytes = hex::decode(raw.trim_start_matches("0x"))
+                .map_err(|error| Error::new(format!("bad transaction hex: {error}")))?;
+            Transaction::decode(&bytes)
+        });
+    let result = match transaction {
+        Ok(transaction) => blockhead.send_transaction(transaction).await,
+        Err(error) => return Response::from_error(400, &error),
+    };
+    match result {
+        Ok(hash) => Response::json(200, &json!({"hash": hash.to_string()})),
+        Err(error) => Response::from_error(400, &error),
+    }
+}
+
+/// Save the mempool every [`MempoolConfig::persist_interval`], if set, until the task is dropped.
+pub(crate) async fn run(blockhead: Arc<Blockhead>) {
+    let Some(interval) = blockhead.config.mempool.persist_interval else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        let blockhead = blockhead.clone();
+        match tokio::task::spawn_blocking(move || blockhead.persist_mempool()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => log::error!("saving the mempool failed: {error}"),
+            Err(error) => log::error!("saving the mempool panicked: {error}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_pending_nonce() {
+    use crate::testkit;
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let validator = chain.validator;
+    let recipient = Address([8; 32]);
+    assert_eq!(
+        chain.blockhead.get_pending_nonce(validator).await.unwrap(),
+        0
+    );
+    chain.transfer(validator, recipient, 1).await;
+    chain.transfer(validator, recipient, 1).await;
+    assert_eq!(chain.blockhead.get_nonce(validator).await.unwrap(), 0);
+    assert_eq!(
+        chain.blockhead.get_pending_nonce(validator).await.unwrap(),
+        2
+    );
+    // A transaction past a gap does not count.
+    let gapped = testkit::transfer(validator, recipient, 1, 3);
+    chain.send(gapped).await;
+    assert_eq!(
+        chain.blockhead.get_pending_nonce(validator).await.unwrap(),
+        2
+    );
+    assert_eq!(
+        chain.blockhead.get_pending_nonce(recipient).await.unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_mempool_persistence() {
+    use crate::spec;
+    use crate::testkit;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let path = std::env::temp_dir().join(format!("blockhead-mempool-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let open = || spec::open_dev(&path, spec::load("dev").unwrap()).unwrap();
+    let account = spec::dev_wallet().account(0).unwrap();
+    let sender = account.address(algorithm);
+    let recipient = Address([8; 32]);
+    let blockhead = open();
+    let mut hashes = Vec::new();
+    for nonce in 0..2 {
+        let mut transfer = Transaction {
+            gas_price: 30,
+            ..testkit::transfer(sender, recipient, 5, nonce)
+        };
+        transfer
+            .sign(&account, spec::DEV_CHAIN_ID, algorithm)
+            .unwrap();
+        hashes.push(blockhead.send_transaction(transfer).await.unwrap());
+    }
+    assert_eq!(blockhead.persist_mempool().unwrap(), 2);
+    drop(blockhead);
+
+    // The restarted node has both transactions pending again, in order.
+    let blockhead = open();
+    assert_eq!(blockhead.restore_mempool().unwrap(), 2);
+    assert_eq!(blockhead.get_pending_nonce(sender).await.unwrap(), 2);
+    for hash in &hashes {
+        assert_eq!(
+            blockhead.get_transaction_status(*hash).await.unwrap(),
+            TransactionStatus::Pending
+        );
+    }
+    let block = blockhead.produce_block().unwrap();
+    assert_eq!(block.body.transactions.len(), 2);
+    assert_eq!(block.body.transactions[0].0, hashes[0]);
+    drop(blockhead);
+
+    // Once included, the saved transactions are stale and dropped.
+    let blockhead = open();
+    assert_eq!(blockhead.restore_mempool().unwrap(), 0);
+    assert_eq!(blockhead.get_balance(recipient).await.unwrap(), 10);
+    drop(blockhead);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_mempool_content() {
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let chain = TestChain::new();
+    let validator = chain.validator;
+    let recipient = Address([8; 32]);
+    chain.transfer(validator, recipient, 1).await;
+    chain.produce();
+    let transfer = |nonce| testkit::transfer(validator, recipient, 1, nonce);
+    for nonce in [1, 2, 4] {
+        chain.send(transfer(nonce)).await;
+    }
+    let content = chain.blockhead.get_mempool_content().await.unwrap();
+    let nonces = |group: &BySender| group[&validator].keys().copied().collect::<Vec<_>>();
+    assert_eq!(nonces(&content.pending), vec![1, 2]);
+    assert_eq!(nonces(&content.queued), vec![4]);
+    assert_eq!(
+        content.pending[&validator][&2].compute_hash(algorithm),
+        transfer(2).compute_hash(algorithm)
+    );
+    assert_eq!(
+        chain.blockhead.get_mempool_status().await.unwrap(),
+        MempoolStatus {
+            pending: 2,
+            queued: 1
+        }
+    );
+
+    #[cfg(feature = "http")]
+    {
+        let address = crate::http::serve_locally(Arc::new(chain.blockhead)).await;
+        let (status, body) =
+            crate::http::send(address, "GET /mempool/status HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, serde_json::json!({"pending": 2, "queued": 1}));
+        let (status, body) =
+            crate::http::send(address, "GET /mempool/content HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        let queued = &body["queued"][validator.to_string()]["4"];
+        assert_eq!(
+            queued["hash"],
+            transfer(4).compute_hash(algorithm).to_string()
+        );
+        assert_eq!(queued["to"], recipient.to_string());
+        assert_eq!(
+            body["pending"][validator.to_string()]
+                .as_object()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_admission_limits() {
+    use crate::testkit;
+    use crate::testkit::{TestChain, VALIDATOR_BALANCE};
+
+    let mut config = testkit::config();
+    config.mempool.max_per_sender = 3;
+    let chain = TestChain::with_config(config);
+    let validator = chain.validator;
+    let transfer = |nonce, value, gas_price| Transaction {
+        gas_price,
+        ..testkit::transfer(validator, Address([8; 32]), value, nonce)
+    };
+    // Waiting transactions count against the balance together.
+    let half = VALIDATOR_BALANCE / 2;
+    chain.send(transfer(0, half, 0)).await;
+    let error = chain
+        .blockhead
+        .send_transaction(transfer(1, half + 1, 0))
+        .await
+        .unwrap_err();
+    assert!(error.message().contains("may cost"));
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::InsufficientFunds {
+            required: 2 * half + 1,
+            available: VALIDATOR_BALANCE
+        }
+    );
+    // A replacement's cost stands in for the one it replaces.
+    chain.send(transfer(0, half - 21_000, 1)).await;
+    chain.send(transfer(1, half - 21_000, 0)).await;
+
+    chain.send(transfer(2, 0, 0)).await;
+    let error = chain
+        .blockhead
+        .send_transaction(transfer(3, 0, 0))
+        .await
+        .unwrap_err();
+    assert!(error
+        .message()
+        .contains("already has 3 transactions waiting"));
+    // Replacing a waiting transaction is still allowed at the cap.
+    chain.send(transfer(2, 0, 1)).await;
+    chain.produce();
+    chain.send(transfer(3, 0, 0)).await;
+
+    chain
+        .blockhead
+        .mempool
+        .lock()
+        .unwrap()
+        .reconfigure(&MempoolConfig {
+            min_gas_price: 2,
+            ..Default::default()
+        });
+    let error = chain
+        .blockhead
+        .send_transaction(transfer(4, 0, 1))
+        .await
+        .unwrap_err();
+    assert!(error.message().contains("below the minimum 2"));
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::Underpriced {
+            minimum: 2,
+            offered: 1
+        }
+    );
+    chain.send(transfer(4, 0, 2)).await;
+
+    // Over HTTP, the kind comes back as a code with data.
+    #[cfg(feature = "http")]
+    {
+        use crate::http::{send, serve_locally, Endpoint};
+
+        let address = serve_locally(Arc::new(chain.blockhead)).await;
+        let raw = format!("0x{}", hex::encode(transfer(4, 0, 2).encode()));
+        let body = json!({ "raw": raw }).to_string();
+        let request = format!(
+            "POST /transactions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (status, response) = send(address, &request).await;
+        assert_eq!(status, 400);
+        assert_eq!(response["code"], -32012);
+        assert_eq!(response["data"], json!({"minimum": 3, "offered": 2}));
+        let error = Endpoint::Tcp(address.to_string())
+            .call("POST", "/transactions", body.as_bytes())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &ErrorKind::Underpriced {
+                minimum: 3,
+                offered: 2
+            }
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_free_transactions_refused_by_default() {
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use crate::ChainConfig;
+
+    let config = ChainConfig {
+        require_signatures: false,
+        ..Default::default()
+    };
+    let chain = TestChain::with_config(config);
+    let free = testkit::transfer(Address([9; 32]), Address([8; 32]), 0, 0);
+    let error = chain.blockhead.send_transaction(free).await.unwrap_err();
+    assert_eq!(
+        error.kind(),
+        &ErrorKind::Underpriced {
+            minimum: 1,
+            offered: 0
+        }
+    );
+    // Paying the floor then needs a balance to pay it from.
+    let paid = Transaction {
+        gas_price: 1,
+        ..testkit::transfer(Address([9; 32]), Address([8; 32]), 0, 0)
+    };
+    let error = chain.blockhead.send_transaction(paid).await.unwrap_err();
+    assert!(matches!(
+        error.kind(),
+        ErrorKind::InsufficientFunds { available: 0, .. }
+    ));
+}
+
+#[tokio::test]
+async fn test_expiry() {
+    use crate::testkit;
+    use crate::testkit::TestChain;
+
+    let algorithm = HashAlgorithm::default();
+    let mut config = testkit::config();
+    config.mempool.max_age = Some(Duration::from_secs(10));
+    let chain = TestChain::with_config(config);
+    let mut events = chain.blockhead.subscribe_chain_events();
+    let recipient = Address([8; 32]);
+    let stale = chain.transfer(chain.validator, recipient, 1).await;
+    chain.advance(Duration::from_secs(6));
+    let fresh = chain.transfer(recipient, chain.validator, 0).await;
+    chain.advance(Duration::from_secs(6));
+    assert_eq!(chain.blockhead.mempool.lock().unwrap().len(), 2);
+
+    // The older transaction is dropped before the block is built, the newer one included.
+    let block = chain.produce();
+    assert_eq!(block.body.transactions.len(), 1);
+    assert_eq!(block.body.transactions[0].0, fresh);
+    assert!(matches!(
+        chain.blockhead.get_transaction_status(stale).await.unwrap(),
+        TransactionStatus::Dropped { reason } if reason.contains("maximum age")
+    ));
+    let expired: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+        .filter_map(|event| match event {
+            ChainEvent::ExpiredPendingTx(hash) => Some(hash),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(expired, vec![stale]);
+
+    // A transaction keeps its age while a revert has it taken.
+    let mut mempool = Mempool::new(
+        GasConfig::default(),
+        &chain.blockhead.config.mempool,
+        algorithm,
+    );
+    let transaction = Transaction {
+        to_address: Some(recipient),
+        value: 1,
+        ..testkit::transaction(
+            crate::transaction::TransactionKind::Transfer,
+            chain.validator,
+            0,
+        )
+    };
+    let (hash, _) = mempool.insert(transaction, 0).unwrap();
+    let taken = mempool.take();
+    assert!(mempool.expire(20_000_000_000).is_empty());
+    mempool.restore(taken);
+    assert_eq!(mempool.expire(20_000_000_000), vec![hash]);
+    assert_eq!(mempool.len(), 0);
+}
@@ -0,0 +1,247 @@
+//! Call counts, error counts and latencies of the HTTP server's endpoints, served at
+//! `/metrics/http` by [`crate::http`], and the log of slow requests. The sizes of the database's
+//! files are served at `/metrics/db`; the sizes of its tables take a scan of the whole database,
+//! so are only served to operators, by [`crate::admin`].
+//!
+//! Latency is the time the node took to answer a request once it was read, so a slow client does
+//! not make an endpoint look slow. Requests too malformed to route are not counted.
+use crate::http::{Request, Response};
+use crate::runtime;
+use crate::Blockhead;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The upper bounds of the latency histogram's buckets. One more bucket counts slower calls.
+const BUCKETS: [Duration; 8] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+];
+
+/// The endpoints counted under their own path. Any other path is counted as `other`, so that
+/// requests for made-up paths cannot grow the table, except that `/admin` and `/dev` each count
+/// every path under them.
+const ENDPOINTS: [&str; 14] = [
+    "/balances",
+    "/blocks",
+    "/explorer",
+    "/faucet",
+    "/graphql",
+    "/health",
+    "/mempool/content",
+    "/mempool/status",
+    "/metrics/db",
+    "/metrics/http",
+    "/ready",
+    "/stats",
+    "/transactions",
+    "/ws",
+];
+
+/// Hex strings with more digits than this, such as transaction data, are cut from the slow
+/// request log. Hashes and addresses fit.
+const MAX_LOGGED_HEX: usize = 64;
+/// The most characters of a request body the slow request log shows.
+const MAX_LOGGED_BODY: usize = 2048;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct EndpointMetrics {
+    pub calls: u64,
+    /// Calls answered with a 4xx or 5xx status.
+    pub errors: u64,
+    /// Calls by latency: each entry counts those slower than the previous bucket's bound but no
+    /// slower than its own, and the last entry those slower than every bound.
+    pub latencies: [u64; BUCKETS.len() + 1],
+    pub total_latency: Duration,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HttpMetrics {
+    endpoints: Mutex<BTreeMap<&'static str, EndpointMetrics>>,
+}
+
+impl HttpMetrics {
+    /// Count a call to `path`, answered with `status` after `latency`.
+    pub(crate) fn record(&self, path: &str, status: u16, latency: Duration) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let metrics = endpoints.entry(endpoint(path)).or_default();
+        metrics.calls += 1;
+        if status >= 400 {
+            metrics.errors += 1;
+        }
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(BUCKETS.len());
+        metrics.latencies[bucket] += 1;
+        metrics.total_latency += latency;
+    }
+
+    /// The metrics of each endpoint called so far.
+    pub(crate) fn snapshot(&self) -> BTreeMap<&'static str, EndpointMetrics> {
+        self.endpoints.lock().unwrap().clone()
+    }
+
+    /// Answer `/metrics/http` with each endpoint's metrics, the histogram's buckets keyed by
+    /// their upper bound in seconds.
+    pub(crate) fn handle(&self) -> Response {
+        let endpoints: serde_json::Map<String, Value> = self
+            .snapshot()
+            .into_iter()
+            .map(|(endpoint, metrics)| {
+                let bounds = BUCKETS
+                    .iter()
+                    .map(|bound| bound.as_secs_f64().to_string())
+                    .chain(["+Inf".to_string()]);
+                let latencies: serde_json::Map<String, Value> =
+                    bounds.zip(metrics.latencies.map(Value::from)).collect();
+                let value = json!({
+                    "calls": metrics.calls,
+                    "errors": metrics.errors,
+                    "latency_seconds": latencies,
+                    "total_latency_seconds": metrics.total_latency.as_secs_f64(),
+                });
+                (endpoint.to_string(), value)
+            })
+            .collect();
+        Response::json(200, &json!({"endpoints": endpoints}))
+    }
+}
+
+/// Answer `/metrics/db` with the sizes of the database's files, in bytes.
+pub(crate) async fn handle_db(blockhead: &Arc<Blockhead>) -> Response {
+    let blockhead = blockhead.clone();
+    match runtime::spawn_blocking(move || blockhead.db_file_sizes()).await {
+        Ok(files) => Response::json(
+            200,
+            &json!({
+                "database_bytes": files.database,
+                "free_bytes": files.free,
+                "wal_bytes": files.wal,
+            }),
+        ),
+        Err(error) => Response::from_error(500, &error),
+    }
+}
+
+/// The endpoint a request for `path` is counted under.
+fn endpoint(path: &str) -> &'static str {
+    if path.starts_with("/admin/") {
+        return "/admin";
+    }
+    if path.starts_with("/dev/") {
+        return "/dev";
+    }
+    if path.starts_with("/blocks/") {
+        return "/blocks";
+    }
+    ENDPOINTS
+        .into_iter()
+        .find(|endpoint| *endpoint == path)
+        .unwrap_or("other")
+}
+
+/// Log `request`, answered with `status` after `latency`, if that is slower than `threshold`.
+/// Long hex strings are cut from its query string and body, and long bodies are truncated.
+pub(crate) fn log_if_slow(
+    request: &Request,
+    status: u16,
+    latency: Duration,
+    threshold: Option<Duration>,
+) {
+    if threshold.is_none_or(|threshold| latency <= threshold) {
+        return;
+    }
+    let body: String = String::from_utf8_lossy(&request.body)
+        .chars()
+        .take(MAX_LOGGED_BODY)
+        .collect();
+    log::warn!(
+        "slow request {} {}?{} took {:.3}s, answered {status}: {}",
+        request.method,
+        request.path,
+        redact(&request.query),
+        latency.as_secs_f64(),
+        redact(&body),
+    );
+}
+
+/// `text` with each `0x` hex string longer than [`MAX_LOGGED_HEX`] digits replaced by its size.
+fn redact(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("0x") {
+        let digits = rest[start + 2..]
+            .bytes()
+            .take_while(u8::is_ascii_hexdigit)
+            .count();
+        let end = start + 2 + digits;
+        redacted.push_str(&rest[..start]);
+        if digits > MAX_LOGGED_HEX {
+            redacted.push_str(&format!("0x<{} bytes>", digits / 2));
+        } else {
+            redacted.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+#[test]
+fn test_http_metrics() {
+    let metrics = HttpMetrics::default();
+    metrics.record("/stats", 200, Duration::from_micros(500));
+    metrics.record("/stats", 400, Duration::from_millis(20));
+    metrics.record("/admin/prune", 200, Duration::from_secs(10));
+    metrics.record("/wp-login.php", 404, Duration::ZERO);
+    let snapshot = metrics.snapshot();
+    assert_eq!(
+        snapshot.keys().copied().collect::<Vec<_>>(),
+        vec!["/admin", "/stats", "other"]
+    );
+    let stats = &snapshot["/stats"];
+    assert_eq!((stats.calls, stats.errors), (2, 1));
+    assert_eq!(stats.latencies, [1, 0, 0, 1, 0, 0, 0, 0, 0]);
+    assert_eq!(stats.total_latency, Duration::from_micros(20_500));
+    assert_eq!(snapshot["/admin"].latencies[BUCKETS.len()], 1);
+
+    let hash = format!("0x{}", "ab".repeat(32));
+    let data = format!("0x{}", "cd".repeat(100));
+    assert_eq!(
+        redact(&format!("{{\"hash\":\"{hash}\",\"raw\":\"{data}\"}}")),
+        format!("{{\"hash\":\"{hash}\",\"raw\":\"0x<100 bytes>\"}}")
+    );
+    assert_eq!(redact("0x"), "0x");
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    use crate::http::{send, serve_locally};
+    use std::sync::Arc;
+
+    let address = serve_locally(Arc::new(Blockhead::new(":memory:").unwrap())).await;
+    for request in ["GET /health", "GET /health", "GET /nowhere", "PUT /graphql"] {
+        send(address, &format!("{request} HTTP/1.1\r\n\r\n")).await;
+    }
+    let (status, body) = send(address, "GET /metrics/db HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert!(body["database_bytes"].as_u64().unwrap() > 0);
+    let (status, body) = send(address, "GET /metrics/http HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    let endpoints = &body["endpoints"];
+    assert_eq!(endpoints["/health"]["calls"], 2);
+    assert_eq!(endpoints["/health"]["errors"], 0);
+    assert_eq!(endpoints["other"]["errors"], 1);
+    assert_eq!(endpoints["/graphql"]["errors"], 1);
+    let latencies = endpoints["/health"]["latency_seconds"].as_object().unwrap();
+    assert_eq!(latencies.len(), BUCKETS.len() + 1);
+    assert_eq!(latencies.values().filter_map(Value::as_u64).sum::<u64>(), 2);
+}
@@ -0,0 +1,504 @@
+//! An in-memory [`Blockchain`] for testing code that talks to a node, without running one.
+//!
+//! [`MockBlockchain`] answers from a [`MockState`] the test fills in, records every call, and
+//! fails the methods the test tells it to. It checks nothing: a sent transaction is recorded and
+//! reported pending, whatever it says. Built for tests and with the `mock` feature.
+use crate::address::Address;
+use crate::block::{Block, BlockId, Header};
+use crate::error::{Error, Result};
+use crate::execution::{CallOverrides, StateOverrides};
+use crate::fee::FeeEstimate;
+use crate::hash::{Hash, HashAlgorithm};
+use crate::mempool::{MempoolContent, MempoolStatus};
+use crate::proof::{self, AccountProof};
+use crate::reward::BlockReward;
+use crate::simulate::Simulation;
+use crate::state::{Account, StateDiff};
+use crate::stats::ChainStats;
+use crate::status::TransactionStatus;
+use crate::token::{TokenInfo, TokenSlot};
+use crate::trace::Trace;
+use crate::transaction::Transaction;
+use crate::{Blockchain, TransactionReceipt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+/// What a [`MockBlockchain`] answers with. Anything left empty reads as absent or zero.
+#[derive(Debug, Clone, Default)]
+pub struct MockState {
+    /// The canonical chain, genesis first. [`BlockId::Finalized`] and [`BlockId::Pending`] both
+    /// resolve to the last block.
+    pub blocks: Vec<Block>,
+    pub transactions: HashMap<Hash, Transaction>,
+    pub receipts: HashMap<Hash, TransactionReceipt>,
+    pub statuses: HashMap<Hash, TransactionStatus>,
+    /// The state at every block alike.
+    pub accounts: BTreeMap<Address, Account>,
+    pub tokens: HashMap<Address, TokenInfo>,
+    pub token_slots: HashMap<(Address, TokenSlot), u64>,
+    pub rewards: HashMap<Hash, BlockReward>,
+    /// What a call to each address returns.
+    pub call_results: HashMap<Address, Vec<u8>>,
+    pub traces: HashMap<Hash, Trace>,
+    /// What simulating each transaction, by hash, reports.
+    pub simulations: HashMap<Hash, Simulation>,
+    pub state_diffs: HashMap<Hash, StateDiff>,
+    pub gas_estimate: u64,
+    pub chain_id: u64,
+    pub hash_algorithm: HashAlgorithm,
+    pub syncing: bool,
+    pub gas_price: u64,
+    pub fee_estimate: FeeEstimate,
+    pub chain_stats: ChainStats,
+    /// Transactions sent through the mock, in order.
+    pub sent: Vec<Transaction>,
+}
+
+impl MockState {
+    /// The sent transactions still pending, grouped against the accounts' nonces.
+    fn mempool_content(&self) -> Result<MempoolContent> {
+        let pending = self
+            .sent
+            .iter()
+            .filter(|transaction| {
+                let hash = transaction.compute_hash(self.hash_algorithm);
+                self.statuses.get(&hash) == Some(&TransactionStatus::Pending)
+            })
+            .cloned()
+            .collect();
+        MempoolContent::group(pending, |address| {
+            Ok(self
+                .accounts
+                .get(&address)
+                .copied()
+                .unwrap_or_default()
+                .nonce)
+        })
+    }
+}
+
+/// A call made to the mock: the method and its arguments, formatted with `Debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCall {
+    pub method: &'static str,
+    pub args: String,
+}
+
+#[derive(Default)]
+pub struct MockBlockchain {
+    state: Mutex<MockState>,
+    calls: Mutex<Vec<MockCall>>,
+    /// The error each failing method returns, and how many more times it fails; `None` fails
+    /// until [`MockBlockchain::clear_failures`].
+    failures: Mutex<HashMap<&'static str, (String, Option<usize>)>>,
+}
+
+impl MockBlockchain {
+    pub fn new(state: MockState) -> Self {
+        Self {
+            state: Mutex::new(state),
+            ..Default::default()
+        }
+    }
+
+    /// The responses, to read or change between calls.
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Every call so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The calls so far to `method`.
+    pub fn calls_to(&self, method: &str) -> Vec<MockCall> {
+        self.calls()
+            .into_iter()
+            .filter(|call| call.method == method)
+            .collect()
+    }
+
+    /// Make `method` fail with `message` from now on. Only methods that return a `Result` can
+    /// fail.
+    pub fn fail(&self, method: &'static str, message: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.insert(method, (message.to_string(), None));
+    }
+
+    /// Make the next `times` calls to `method` fail with `message`.
+    pub fn fail_times(&self, method: &'static str, message: &str, times: usize) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.insert(method, (message.to_string(), Some(times)));
+    }
+
+    pub fn clear_failures(&self) {
+        self.failures.lock().unwrap().clear();
+    }
+
+    fn log(&self, method: &'static str, args: String) {
+        self.calls.lock().unwrap().push(MockCall { method, args });
+    }
+
+    /// Record a call to a fallible method and fail it if it is set to fail.
+    fn record(&self, method: &'static str, args: String) -> Result<()> {
+        self.log(method, args);
+        let mut failures = self.failures.lock().unwrap();
+        let Some((message, remaining)) = failures.get_mut(method) else {
+            return Ok(());
+        };
+        let error = Error::new(message.clone());
+        if let Some(remaining) = remaining {
+            *remaining -= 1;
+            if *remaining == 0 {
+                failures.remove(method);
+            }
+        }
+        Err(error)
+    }
+
+    fn block(&self, id: BlockId) -> Option<Block> {
+        let state = self.state();
+        match id {
+            BlockId::Latest | BlockId::Finalized | BlockId::Pending => state.blocks.last().cloned(),
+            BlockId::Number(number) => state.blocks.get(number as usize).cloned(),
+            BlockId::Hash(hash) => state.blocks.iter().find(|b| b.hash == hash).cloned(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Blockchain for MockBlockchain {
+    async fn get_block(&self, id: BlockId) -> Result<Option<Block>> {
+        self.record("get_block", format!("{id:?}"))?;
+        Ok(self.block(id))
+    }
+
+    async fn get_header(&self, id: BlockId) -> Result<Option<Header>> {
+        self.record("get_header", format!("{id:?}"))?;
+        Ok(self.block(id).map(|block| block.header))
+    }
+
+    async fn get_raw_block(&self, hash: Hash) -> Result<Option<String>> {
+        self.record("get_raw_block", format!("{hash:?}"))?;
+        let block = self.block(BlockId::Hash(hash));
+        Ok(block.map(|block| format!("0x{}", hex::encode(block.encode()))))
+    }
+
+    async fn get_block_rewards(&self, id: BlockId) -> Result<BlockReward> {
+        self.record("get_block_rewards", format!("{id:?}"))?;
+        let block = self
+            .block(id)
+            .ok_or_else(|| Error::new(format!("no block {id}")))?;
+        let reward = self.state().rewards.get(&block.hash).copied();
+        Ok(reward.unwrap_or(BlockReward {
+            proposer: block.proposer,
+            subsidy: 0,
+            fees: 0,
+        }))
+    }
+
+    async fn get_transaction(&self, hash: Hash) -> Result<Option<Transaction>> {
+        self.record("get_transaction", format!("{hash:?}"))?;
+        Ok(self.state().transactions.get(&hash).cloned())
+    }
+
+    async fn get_transaction_receipt(&self, hash: Hash) -> Result<Option<TransactionReceipt>> {
+        self.record("get_transaction_receipt", format!("{hash:?}"))?;
+        Ok(self.state().receipts.get(&hash).cloned())
+    }
+
+    async fn get_raw_transaction(&self, hash: Hash) -> Result<Option<String>> {
+        self.record("get_raw_transaction", format!("{hash:?}"))?;
+        let transaction = self.state().transactions.get(&hash).cloned();
+        Ok(transaction.map(|transaction| format!("0x{}", hex::encode(transaction.encode()))))
+    }
+
+    async fn send_transaction(&self, transaction: Transaction) -> Result<Hash> {
+        self.record("send_transaction", format!("{transaction:?}"))?;
+        let mut state = self.state();
+        let hash = transaction.compute_hash(state.hash_algorithm);
+        state.statuses.insert(hash, TransactionStatus::Pending);
+        state.transactions.insert(hash, transaction.clone());
+        state.sent.push(transaction);
+        Ok(hash)
+    }
+
+    async fn get_transaction_status(&self, hash: Hash) -> Result<TransactionStatus> {
+        self.record("get_transaction_status", format!("{hash:?}"))?;
+        let status = self.state().statuses.get(&hash).cloned();
+        Ok(status.unwrap_or(TransactionStatus::Unknown))
+    }
+
+    async fn get_mempool_content(&self) -> Result<MempoolContent> {
+        self.record("get_mempool_content", String::new())?;
+        self.state().mempool_content()
+    }
+
+    async fn get_mempool_status(&self) -> Result<MempoolStatus> {
+        self.record("get_mempool_status", String::new())?;
+        Ok(self.state().mempool_content()?.status())
+    }
+
+    async fn get_balance(&self, address: Address) -> Result<u64> {
+        self.record("get_balance", format!("{address:?}"))?;
+        let account = self.state().accounts.get(&address).copied();
+        Ok(account.unwrap_or_default().balance)
+    }
+
+    async fn get_balances(&self, addresses: &[Address]) -> Result<Vec<u64>> {
+        self.record("get_balances", format!("{addresses:?}"))?;
+        let state = self.state();
+        Ok(addresses
+            .iter()
+            .map(|address| {
+                state
+                    .accounts
+                    .get(address)
+                    .copied()
+                    .unwrap_or_default()
+                    .balance
+            })
+            .collect())
+    }
+
+    async fn get_balance_at(&self, address: Address, block: BlockId) -> Result<u64> {
+        self.record("get_balance_at", format!("{address:?}, {block:?}"))?;
+        let account = self.state().accounts.get(&address).copied();
+        Ok(account.unwrap_or_default().balance)
+    }
+
+    async fn get_nonce(&self, address: Address) -> Result<u64> {
+        self.record("get_nonce", format!("{address:?}"))?;
+        let account = self.state().accounts.get(&address).copied();
+        Ok(account.unwrap_or_default().nonce)
+    }
+
+    async fn get_pending_nonce(&self, address: Address) -> Result<u64> {
+        self.record("get_pending_nonce", format!("{address:?}"))?;
+        let state = self.state();
+        let mut nonce = state
+            .accounts
+            .get(&address)
+            .copied()
+            .unwrap_or_default()
+            .nonce;
+        let pending = |transaction: &Transaction| {
+            let status = state
+                .statuses
+                .get(&transaction.compute_hash(state.hash_algorithm));
+            transaction.from_address == address && status == Some(&TransactionStatus::Pending)
+        };
+        while state
+            .sent
+            .iter()
+            .any(|transaction| pending(transaction) && transaction.nonce == nonce)
+        {
+            nonce += 1;
+        }
+        Ok(nonce)
+    }
+
+    async fn get_token_info(&self, token: Address) -> Result<Option<TokenInfo>> {
+        self.record("get_token_info", format!("{token:?}"))?;
+        Ok(self.state().tokens.get(&token).cloned())
+    }
+
+    async fn get_token_balance(&self, token: Address, holder: Address) -> Result<u64> {
+        self.record("get_token_balance", format!("{token:?}, {holder:?}"))?;
+        let slot = (token, TokenSlot::Balance(holder));
+        Ok(self.state().token_slots.get(&slot).copied().unwrap_or(0))
+    }
+
+    async fn get_token_allowance(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+    ) -> Result<u64> {
+        self.record(
+            "get_token_allowance",
+            format!("{token:?}, {owner:?}, {spender:?}"),
+        )?;
+        let slot = (token, TokenSlot::Allowance { owner, spender });
+        Ok(self.state().token_slots.get(&slot).copied().unwrap_or(0))
+    }
+
+    async fn get_proof(&self, address: Address, block: BlockId) -> Result<Option<AccountProof>> {
+        self.record("get_proof", format!("{address:?}, {block:?}"))?;
+        let state = self.state();
+        Ok(proof::prove(&state.accounts, address, state.hash_algorithm))
+    }
+
+    async fn call(
+        &self,
+        to: Address,
+        data: Vec<u8>,
+        overrides: CallOverrides,
+        block: BlockId,
+    ) -> Result<Vec<u8>> {
+        self.record(
+            "call",
+            format!("{to:?}, {data:?}, {overrides:?}, {block:?}"),
+        )?;
+        Ok(self
+            .state()
+            .call_results
+            .get(&to)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn estimate_gas(&self, to: Address, data: Vec<u8>) -> u64 {
+        self.log("estimate_gas", format!("{to:?}, {data:?}"));
+        self.state().gas_estimate
+    }
+
+    async fn simulate_transaction(
+        &self,
+        transaction: Transaction,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Simulation> {
+        self.record(
+            "simulate_transaction",
+            format!("{transaction:?}, {overrides:?}, {block:?}"),
+        )?;
+        let state = self.state();
+        let hash = transaction.compute_hash(state.hash_algorithm);
+        Ok(state.simulations.get(&hash).cloned().unwrap_or_default())
+    }
+
+    async fn simulate_bundle(
+        &self,
+        transactions: Vec<Transaction>,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Vec<Simulation>> {
+        self.record(
+            "simulate_bundle",
+            format!("{transactions:?}, {overrides:?}, {block:?}"),
+        )?;
+        let state = self.state();
+        Ok(transactions
+            .iter()
+            .map(|transaction| {
+                let hash = transaction.compute_hash(state.hash_algorithm);
+                let simulation = state.simulations.get(&hash);
+                simulation.cloned().unwrap_or_default()
+            })
+            .collect())
+    }
+
+    async fn debug_trace_transaction(&self, hash: Hash) -> Result<Option<Trace>> {
+        self.record("debug_trace_transaction", format!("{hash:?}"))?;
+        Ok(self.state().traces.get(&hash).cloned())
+    }
+
+    async fn get_state_diff(&self, block_hash: Hash) -> Result<Option<StateDiff>> {
+        self.record("get_state_diff", format!("{block_hash:?}"))?;
+        Ok(self.state().state_diffs.get(&block_hash).cloned())
+    }
+
+    async fn chain_id(&self) -> u64 {
+        self.log("chain_id", String::new());
+        self.state().chain_id
+    }
+
+    async fn hash_algorithm(&self) -> HashAlgorithm {
+        self.log("hash_algorithm", String::new());
+        self.state().hash_algorithm
+    }
+
+    async fn syncing(&self) -> bool {
+        self.log("syncing", String::new());
+        self.state().syncing
+    }
+
+    async fn gas_price(&self) -> u64 {
+        self.log("gas_price", String::new());
+        self.state().gas_price
+    }
+
+    async fn estimate_fee(&self) -> Result<FeeEstimate> {
+        self.record("estimate_fee", String::new())?;
+        Ok(self.state().fee_estimate)
+    }
+
+    async fn get_chain_stats(&self, window: u64) -> Result<ChainStats> {
+        self.record("get_chain_stats", format!("{window:?}"))?;
+        Ok(self.state().chain_stats.clone())
+    }
+}
+
+#[tokio::test]
+async fn test_mock_blockchain() {
+    use crate::testkit::Gen;
+
+    let algorithm = HashAlgorithm::default();
+    let mut gen = Gen::new(1);
+    let (genesis, block1) = (gen.block(), gen.block());
+    let alice = gen.address();
+    let mut state = MockState {
+        blocks: vec![genesis.clone(), block1.clone()],
+        chain_id: 7,
+        ..Default::default()
+    };
+    state.accounts.insert(
+        alice,
+        Account {
+            balance: 40,
+            nonce: 2,
+            ..Default::default()
+        },
+    );
+    let mock = MockBlockchain::new(state);
+    let latest = mock.get_block(BlockId::Latest).await.unwrap().unwrap();
+    assert_eq!(latest.hash, block1.hash);
+    let header = mock.get_header(BlockId::Number(0)).await.unwrap().unwrap();
+    assert_eq!(header, genesis.header);
+    assert_eq!(mock.get_balance(alice).await.unwrap(), 40);
+    assert_eq!(mock.get_nonce(gen.address()).await.unwrap(), 0);
+    assert_eq!(mock.chain_id().await, 7);
+    let proof = mock
+        .get_proof(alice, BlockId::Latest)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(proof.verify(
+        proof::state_root(&mock.state().accounts, algorithm),
+        algorithm
+    ));
+
+    let transaction = Transaction {
+        from_address: alice,
+        nonce: 2,
+        ..gen.transaction()
+    };
+    let hash = mock.send_transaction(transaction.clone()).await.unwrap();
+    assert_eq!(
+        mock.get_transaction_status(hash).await.unwrap(),
+        TransactionStatus::Pending
+    );
+    assert_eq!(mock.state().sent.len(), 1);
+    assert_eq!(mock.calls_to("send_transaction").len(), 1);
+    assert_eq!(mock.calls()[0].method, "get_block");
+    assert_eq!(mock.calls().len(), 8);
+    assert_eq!(mock.get_pending_nonce(alice).await.unwrap(), 3);
+    let content = mock.get_mempool_content().await.unwrap();
+    assert_eq!(
+        content.pending[&alice][&2].compute_hash(algorithm),
+        transaction.compute_hash(algorithm)
+    );
+    assert_eq!(mock.get_mempool_status().await.unwrap().queued, 0);
+
+    mock.fail_times("get_balance", "connection reset", 2);
+    assert!(mock.get_balance(alice).await.is_err());
+    assert!(mock.get_balance(alice).await.is_err());
+    assert_eq!(mock.get_balance(alice).await.unwrap(), 40);
+    mock.fail("send_transaction", "mempool full");
+    let error = mock.send_transaction(transaction).await.unwrap_err();
+    assert!(error.to_string().contains("mempool full"));
+    mock.clear_failures();
+    assert!(mock.get_block(BlockId::Number(5)).await.unwrap().is_none());
+}
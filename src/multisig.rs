@@ -0,0 +1,190 @@
+//! Accounts controlled by M of N keys.
+//!
+//! A [`TransactionKind::CreateMultisig`](crate::transaction::TransactionKind) transaction
+//! registers a [`MultisigPolicy`] at the address derived from it, endowed with the transaction's
+//! `value`. No single key controls that address: a transaction sent from it must carry
+//! signatures from at least `threshold` of the policy's signers. Signers sign the same
+//! [`Transaction::signing_hash`] independently, and `blockhead multisig combine` merges their
+//! partially signed copies into one transaction.
+use crate::address::Address;
+use crate::encoding::Reader;
+use crate::error::{Error, Result};
+use crate::hash::{HashAlgorithm, HashBuilder};
+use crate::state::StateOverlay;
+use crate::transaction::{Transaction, TransactionKind};
+
+/// The most signers a policy may list.
+pub(crate) const MAX_SIGNERS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MultisigPolicy {
+    /// How many distinct signers must sign a transaction.
+    pub threshold: u8,
+    /// The addresses allowed to sign, sorted and without duplicates.
+    pub signers: Vec<Address>,
+}
+
+impl MultisigPolicy {
+    pub(crate) fn new(threshold: u8, mut signers: Vec<Address>) -> Result<Self> {
+        signers.sort();
+        signers.dedup();
+        if signers.len() > MAX_SIGNERS {
+            return Err(Error::new(format!(
+                "a multisig policy lists at most {MAX_SIGNERS} signers, not {}",
+                signers.len()
+            )));
+        }
+        if threshold == 0 || threshold as usize > signers.len() {
+            return Err(Error::new(format!(
+                "threshold {threshold} is not between 1 and {} signers",
+                signers.len()
+            )));
+        }
+        Ok(Self { threshold, signers })
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = vec![self.threshold, self.signers.len() as u8];
+        for signer in &self.signers {
+            out.extend_from_slice(&signer.0);
+        }
+        out
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let threshold = reader.u8()?;
+        let count = reader.u8()?;
+        let signers = (0..count)
+            .map(|_| Ok(Address::from(reader.bytes32()?)))
+            .collect::<Result<Vec<_>>>()?;
+        reader.finish()?;
+        let policy = Self::new(threshold, signers)?;
+        if policy.signers.len() != count as usize {
+            return Err(Error::new("multisig policy lists a signer twice"));
+        }
+        Ok(policy)
+    }
+
+    /// The address the policy controls. It depends only on the threshold and the set of
+    /// signers, and the chain's hash function `algorithm`, so every signer can compute it before
+    /// it exists.
+    pub(crate) fn address(&self, algorithm: HashAlgorithm) -> Address {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(b"multisig");
+        hasher.update(self.encode());
+        Address::from(*hasher.finalize().as_bytes())
+    }
+}
+
+/// Check that `transaction`, whose signatures were made by `signers` (see
+/// [`Transaction::signers`]), carries the signatures its sender needs: `threshold` signers of a
+/// multisig sender, or else the sender's own signature, which may be left out unless
+/// `require_signatures`. Attestations always need it: they finalize blocks on the validator's
+/// say alone.
+pub(crate) fn authorize(
+    state: &mut StateOverlay,
+    require_signatures: bool,
+    transaction: &Transaction,
+    signers: &[Address],
+) -> Result<()> {
+    let from = transaction.from_address;
+    let require_signatures = require_signatures || transaction.kind == TransactionKind::Attest;
+    let Some(policy) = state.multisig(from)? else {
+        return match signers {
+            [] if require_signatures => Err(Error::new("transaction is not signed")),
+            [] => Ok(()),
+            [signer] if *signer == from => Ok(()),
+            [signer] => Err(Error::new(format!(
+                "transaction from {from} is signed by {signer}, or was signed for another chain"
+            ))),
+            _ => Err(Error::new(format!(
+                "transaction from {from} carries {} signatures but it is not a multisig",
+                signers.len()
+            ))),
+        };
+    };
+    for (i, signer) in signers.iter().enumerate() {
+        if !policy.signers.contains(signer) {
+            return Err(Error::new(format!(
+                "{signer} is not a signer of multisig {from}"
+            )));
+        }
+        if signers[..i].contains(signer) {
+            return Err(Error::new(format!("{signer} signed twice")));
+        }
+    }
+    if signers.len() < policy.threshold as usize {
+        return Err(Error::new(format!(
+            "multisig {from} needs {} signatures, got {}",
+            policy.threshold,
+            signers.len()
+        )));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multisig() {
+    use crate::testkit;
+    use crate::transaction::TransactionKind;
+    use crate::wallet::{self, Wallet};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let wallet = Wallet::from_mnemonic(&wallet::mnemonic_from_entropy(&[5; 16]), "").unwrap();
+    let keys: Vec<_> = (0..4).map(|index| wallet.account(index).unwrap()).collect();
+    let policy = MultisigPolicy::new(
+        2,
+        keys[..3].iter().map(|key| key.address(algorithm)).collect(),
+    )
+    .unwrap();
+    assert_eq!(MultisigPolicy::decode(&policy.encode()).unwrap(), policy);
+    let reordered = keys[..3]
+        .iter()
+        .rev()
+        .map(|key| key.address(algorithm))
+        .collect();
+    assert_eq!(
+        MultisigPolicy::new(2, reordered)
+            .unwrap()
+            .address(algorithm),
+        policy.address(algorithm)
+    );
+    assert!(MultisigPolicy::new(4, policy.signers.clone()).is_err());
+
+    let multisig = policy.address(algorithm);
+    let create = Transaction {
+        value: 1_000,
+        data: policy.encode().into(),
+        gas_limit: 25_000,
+        ..testkit::transaction(TransactionKind::CreateMultisig, validator, 0)
+    };
+    blockhead.send_transaction(create).await.unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(multisig).await.unwrap(), 1_000);
+
+    let transfer = testkit::transfer(multisig, Address([8; 32]), 300, 0);
+    assert!(blockhead.send_transaction(transfer.clone()).await.is_err());
+    let chain_id = blockhead.chain_id().await;
+    let mut first = transfer.clone();
+    first.add_signature(&keys[0], chain_id, algorithm).unwrap();
+    assert!(blockhead.send_transaction(first.clone()).await.is_err());
+    let mut outsider = first.clone();
+    outsider
+        .add_signature(&keys[3], chain_id, algorithm)
+        .unwrap();
+    assert!(blockhead.send_transaction(outsider).await.is_err());
+
+    let mut second = transfer.clone();
+    second.add_signature(&keys[2], chain_id, algorithm).unwrap();
+    first.combine(&second).unwrap();
+    let mut other = transfer.clone();
+    other.value = 1;
+    assert!(first.combine(&other).is_err());
+    blockhead.send_transaction(first).await.unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.get_balance(multisig).await.unwrap(), 700);
+    assert_eq!(blockhead.get_balance(Address([8; 32])).await.unwrap(), 300);
+}
@@ -0,0 +1,286 @@
+//! Parallel execution of the plain transfers in an imported block.
+//!
+//! A transfer of value to an account without code, from one that is not a multisig, reads and
+//! writes nothing but the sender's and the recipient's accounts. Within each run of consecutive
+//! such transfers, those whose accounts no earlier transfer of the run touches are executed at
+//! once, spread over the machine's cores, each against the state from before the run. The rest
+//! follow one after another, in block order, on top of them. The outcome is the same as executing
+//! the block in order, since the transfers executed at once share no accounts with each other or
+//! with those before them in the run. Each is checked afterwards to have touched only its two
+//! accounts, and if one touched anything else, the run is executed again in order. Other
+//! transactions may touch any state and are executed in order between runs.
+use crate::address::Address;
+use crate::error::Result;
+use crate::execution::{self, ExecutionOutcome};
+use crate::genesis::ChainConfig;
+use crate::hash::Hash;
+use crate::state::{Account, StateOverlay};
+use crate::transaction::{Transaction, TransactionKind};
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+
+/// Below this many transfers that can run at once, starting threads costs more than it saves.
+const MIN_PARALLEL: usize = 8;
+
+/// A transfer that touches only `accounts`.
+struct Transfer<'a> {
+    index: usize,
+    transaction: &'a Transaction,
+    signers: Vec<Address>,
+    accounts: [Address; 2],
+}
+
+/// The outcome of executing each of `transactions`, signed by `signers`, on `state`, in order,
+/// as [`execution::execute_transaction`] gives them one after another. `state` must be an
+/// overlay over `connection`, which the transfers executed in parallel read through.
+pub(crate) fn execute_transactions(
+    connection: &sqlite::ConnectionThreadSafe,
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transactions: &[(Hash, Transaction)],
+    signers: Vec<Result<Vec<Address>>>,
+) -> Result<Vec<Result<ExecutionOutcome>>> {
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    execute_on_threads(connection, state, config, transactions, signers, threads)
+}
+
+/// [`execute_transactions`], on up to `threads` threads.
+fn execute_on_threads(
+    connection: &sqlite::ConnectionThreadSafe,
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transactions: &[(Hash, Transaction)],
+    signers: Vec<Result<Vec<Address>>>,
+    threads: usize,
+) -> Result<Vec<Result<ExecutionOutcome>>> {
+    let mut outcomes: Vec<Option<Result<ExecutionOutcome>>> =
+        transactions.iter().map(|_| None).collect();
+    let mut run = Vec::new();
+    for (index, ((_, transaction), signers)) in transactions.iter().zip(signers).enumerate() {
+        let signers = match signers {
+            Ok(signers) => signers,
+            Err(error) => {
+                execute_run(connection, state, config, &mut run, threads, &mut outcomes)?;
+                outcomes[index] = Some(Err(error));
+                continue;
+            }
+        };
+        if let Some(accounts) = plain_transfer(state, transaction, &signers) {
+            run.push(Transfer {
+                index,
+                transaction,
+                signers,
+                accounts,
+            });
+            continue;
+        }
+        execute_run(connection, state, config, &mut run, threads, &mut outcomes)?;
+        let outcome = execution::execute_transaction(state, config, transaction, &signers);
+        outcomes[index] = Some(outcome);
+    }
+    execute_run(connection, state, config, &mut run, threads, &mut outcomes)?;
+    Ok(outcomes
+        .into_iter()
+        .map(|outcome| outcome.expect("every transaction is executed"))
+        .collect())
+}
+
+/// The accounts `transaction` touches, if it is a plain transfer given the state so far.
+fn plain_transfer(
+    state: &StateOverlay,
+    transaction: &Transaction,
+    signers: &[Address],
+) -> Option<[Address; 2]> {
+    let from = transaction.from_address;
+    let to = transaction.to_address?;
+    let plain = transaction.kind == TransactionKind::Transfer
+        && signers.len() <= 1
+        && state.code(to).ok()?.is_none()
+        && state.multisig(from).ok()?.is_none();
+    plain.then_some([from, to])
+}
+
+/// Execute the transfers of `run`, leaving it empty, and record their outcomes.
+fn execute_run(
+    connection: &sqlite::ConnectionThreadSafe,
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    run: &mut Vec<Transfer>,
+    threads: usize,
+    outcomes: &mut [Option<Result<ExecutionOutcome>>],
+) -> Result<()> {
+    let run = std::mem::take(run);
+    let mut touched = HashSet::new();
+    let (independent, dependent): (Vec<&Transfer>, Vec<&Transfer>) =
+        run.iter().partition(|transfer| {
+            let independent = transfer.accounts.iter().all(|a| !touched.contains(a));
+            touched.extend(transfer.accounts);
+            independent
+        });
+    if threads > 1 && independent.len() >= MIN_PARALLEL {
+        let mut start = Vec::with_capacity(independent.len());
+        for transfer in &independent {
+            let mut accounts = Vec::with_capacity(2);
+            for address in transfer.accounts {
+                accounts.push((address, state.account(address)?));
+            }
+            start.push(accounts);
+        }
+        if let Some(results) = speculate(connection, config, &independent, start, threads) {
+            for (transfer, (outcome, accounts)) in independent.iter().zip(results) {
+                for (address, account) in accounts {
+                    *state.account_mut(address)? = account;
+                }
+                outcomes[transfer.index] = Some(outcome);
+            }
+            for transfer in dependent {
+                outcomes[transfer.index] = Some(execute(state, config, transfer));
+            }
+            return Ok(());
+        }
+        log::debug!("a transfer touched other state than its accounts; executing in order");
+    }
+    for transfer in &run {
+        outcomes[transfer.index] = Some(execute(state, config, transfer));
+    }
+    Ok(())
+}
+
+fn execute(
+    state: &mut StateOverlay,
+    config: &ChainConfig,
+    transfer: &Transfer,
+) -> Result<ExecutionOutcome> {
+    execution::execute_transaction(state, config, transfer.transaction, &transfer.signers)
+}
+
+/// What a transfer executed on its own produced, and the accounts it left.
+type Speculated = (Result<ExecutionOutcome>, HashMap<Address, Account>);
+
+/// Execute each of `transfers` on its own overlay holding its accounts as in `start`, returning
+/// the outcome and the accounts it leaves, or `None` if any touched other state.
+fn speculate(
+    connection: &sqlite::ConnectionThreadSafe,
+    config: &ChainConfig,
+    transfers: &[&Transfer],
+    start: Vec<Vec<(Address, Account)>>,
+    threads: usize,
+) -> Option<Vec<Speculated>> {
+    let jobs: Vec<_> = transfers.iter().zip(start).collect();
+    let chunk_size = jobs.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = jobs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(transfer, accounts)| {
+                            let mut state =
+                                StateOverlay::with_accounts(connection, accounts.iter().copied());
+                            let outcome = execute(&mut state, config, transfer);
+                            Some((outcome, state.into_accounts(&transfer.accounts)?))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+#[test]
+fn test_parallel_execution() {
+    use crate::genesis::Genesis;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::Blockhead;
+
+    let algorithm = HashAlgorithm::default();
+    let validator = testkit::validator();
+    let senders: Vec<Address> = (100..120).map(|i| Address([i; 32])).collect();
+    let mut alloc = vec![(validator, 1_000_000)];
+    alloc.extend(senders.iter().map(|&sender| (sender, 100_000)));
+    let genesis = Genesis {
+        alloc,
+        validators: vec![(validator, 100)],
+        config: testkit::config(),
+        ..Default::default()
+    };
+    let transfer = |from: Address, to: Address, value: u64, nonce: u64| Transaction {
+        gas_price: 1,
+        ..testkit::transfer(from, to, value, nonce)
+    };
+    let mut transactions = Vec::new();
+    for (i, &sender) in senders.iter().enumerate() {
+        transactions.push(transfer(sender, Address([150 + i as u8; 32]), 10, 0));
+    }
+    // Transfers that depend on earlier ones: a second from the same sender, one to a sender, and
+    // one out of funds received earlier in the block.
+    transactions.push(transfer(senders[0], Address([151; 32]), 5, 1));
+    transactions.push(transfer(senders[3], senders[4], 7, 1));
+    transactions.push(Transaction {
+        gas_price: 0,
+        ..transfer(Address([150; 32]), senders[9], 10, 0)
+    });
+    // A staking transaction between the runs, and one that fails.
+    transactions.push(Transaction {
+        kind: TransactionKind::Stake,
+        to_address: None,
+        ..transfer(senders[5], senders[5], 1, 1)
+    });
+    transactions.push(transfer(senders[6], senders[7], 1_000_000, 1));
+    transactions.extend((10..20).map(|i| transfer(senders[i], senders[i - 10], 1, 1)));
+    let transactions: Vec<(Hash, Transaction)> = transactions
+        .into_iter()
+        .map(|transaction| (transaction.compute_hash(algorithm), transaction))
+        .collect();
+    let signers = || transactions.iter().map(|_| Ok(Vec::new())).collect();
+
+    let blockhead = Blockhead::with_genesis(":memory:", genesis)
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap();
+    let config = &blockhead.config;
+    let mut sequential = StateOverlay::new(&blockhead.connection);
+    let expected: Vec<_> = transactions
+        .iter()
+        .map(|(_, transaction)| {
+            execution::execute_transaction(&mut sequential, config, transaction, &[])
+        })
+        .collect();
+    // One thread executes in order; more execute the independent transfers at once.
+    for threads in [1, 4] {
+        let mut parallel = StateOverlay::new(&blockhead.connection);
+        let outcomes = execute_on_threads(
+            &blockhead.connection,
+            &mut parallel,
+            config,
+            &transactions,
+            signers(),
+            threads,
+        )
+        .unwrap();
+        assert_eq!(outcomes.len(), expected.len());
+        for (outcome, expected) in outcomes.iter().zip(&expected) {
+            match (outcome, expected) {
+                (Ok(outcome), Ok(expected)) => assert_eq!(outcome, expected),
+                (Err(error), Err(expected)) => assert_eq!(error.message(), expected.message()),
+                _ => panic!("{outcome:?} is not {expected:?}"),
+            }
+        }
+        assert_eq!(parallel.diff(), sequential.diff());
+        assert_eq!(
+            parallel.state_root(algorithm).unwrap(),
+            sequential.state_root(algorithm).unwrap()
+        );
+        assert_eq!(
+            parallel.account(senders[4]).unwrap().balance,
+            100_000 - 21_010 + 7 + 1
+        );
+    }
+    assert!(expected[24].is_err());
+}
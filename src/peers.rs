@@ -0,0 +1,493 @@
+//! Block sync between nodes over their HTTP APIs. Each node lists the nodes it syncs with in
+//! [`PeerConfig::peers`].
+//!
+//! Every [`PeerConfig::poll_interval`], a background task asks each peer for the canonical blocks
+//! past the local head with `GET /blocks`, and submits them to the import queue (see
+//! [`crate::import_queue`]), stopping for the round as soon as the queue asks it to slow down or
+//! turns a block away. It then announces the local head to each peer with `POST /blocks`, which
+//! answers with the peer's own [`ImportSignal`]: a peer that asks to slow down is sent nothing
+//! for a poll interval, and one whose queue is full nothing until its `Retry-After` has passed.
+//! Blocks travel with their hashes both ways, so that the receiving node skips decoding those it
+//! has already (see [`Blockhead::has_block`]).
+//!
+//! Blocks that arrive before their parents wait in the import queue's orphan pool. At the end of
+//! each round the task asks the peers for the parents they are waiting for, by hash with
+//! `GET /blocks/<hash>`, which serves any stored block, canonical or not.
+//!
+//! Each round also records how many peers answered and the highest head among them, which
+//! `/health` reports and [`crate::Blockchain::syncing`] compares with the local head.
+//!
+//! The sync layer is only built with the `http` feature, without which this module holds just
+//! its config.
+#[cfg(feature = "http")]
+use crate::block::Block;
+#[cfg(feature = "http")]
+use crate::db;
+#[cfg(feature = "http")]
+use crate::error::{Error, ErrorKind, Result};
+#[cfg(feature = "http")]
+use crate::hash::Hash;
+#[cfg(feature = "http")]
+use crate::http::{Endpoint, Response};
+#[cfg(feature = "http")]
+use crate::import_queue::ImportSignal;
+#[cfg(feature = "http")]
+use crate::{runtime, Blockhead};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http")]
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "http")]
+use std::time::Instant;
+
+/// How far a peer's head may be past the local one without the node counting as syncing, since
+/// new blocks reach nodes at slightly different times.
+const SYNC_SLACK: u64 = 2;
+
+/// How long a peer whose import queue is full should wait before sending blocks again.
+#[cfg(feature = "http")]
+const FULL_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerConfig {
+    /// The HTTP APIs of the nodes to sync with, each `http://<host>:<port>`, or the data
+    /// directory or socket of a node on the same machine. Empty, the node does not sync.
+    pub peers: Vec<String>,
+    /// How long the sync task waits between rounds.
+    pub poll_interval: Duration,
+    /// The most blocks asked of a peer in one round.
+    pub batch: u64,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            poll_interval: Duration::from_secs(2),
+            batch: 100,
+        }
+    }
+}
+
+/// What the sync task learned of the node's peers in its last round.
+#[derive(Default)]
+pub(crate) struct PeerStatus {
+    /// How many peers answered.
+    reachable: AtomicUsize,
+    /// The highest head the peers that answered have.
+    best_head: AtomicU64,
+}
+
+impl PeerStatus {
+    pub(crate) fn reachable(&self) -> usize {
+        self.reachable.load(Ordering::Relaxed)
+    }
+
+    /// Whether a peer's head is more than [`SYNC_SLACK`] blocks past block number `head`.
+    pub(crate) fn is_ahead_of(&self, head: u64) -> bool {
+        self.best_head.load(Ordering::Relaxed) > head.saturating_add(SYNC_SLACK)
+    }
+
+    #[cfg(feature = "http")]
+    fn record(&self, reachable: usize, best_head: u64) {
+        self.reachable.store(reachable, Ordering::Relaxed);
+        self.best_head.store(best_head, Ordering::Relaxed);
+    }
+}
+
+/// A node the sync task pulls blocks from and announces blocks to.
+#[cfg(feature = "http")]
+pub(crate) struct Peer {
+    endpoint: Endpoint,
+    /// The last block the peer took from the node.
+    announced: Option<Hash>,
+    /// When the peer may be sent blocks again, after it asked the node to back off.
+    resume_at: Option<Instant>,
+}
+
+#[cfg(feature = "http")]
+impl Peer {
+    pub(crate) fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            announced: None,
+            resume_at: None,
+        }
+    }
+}
+
+/// Sync with the node's peers every [`PeerConfig::poll_interval`], until the task is dropped.
+#[cfg(feature = "http")]
+pub(crate) async fn run(blockhead: Arc<Blockhead>) {
+    let config = &blockhead.config.peers;
+    if config.peers.is_empty() {
+        return;
+    }
+    let mut peers: Vec<_> = (config.peers.iter())
+        .map(|peer| Peer::new(Endpoint::parse(peer)))
+        .collect();
+    loop {
+        sync(&blockhead, &mut peers).await;
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+/// One round of sync with `peers`: pull the blocks each has past the local head, announce the
+/// head to each, then fetch the parents orphaned blocks are missing.
+#[cfg(feature = "http")]
+pub(crate) async fn sync(blockhead: &Blockhead, peers: &mut [Peer]) {
+    let (mut reachable, mut best_head) = (0, 0);
+    for peer in peers.iter_mut() {
+        match pull(blockhead, peer).await {
+            Ok(head) => {
+                reachable += 1;
+                best_head = best_head.max(head);
+            }
+            Err(error) => {
+                log::debug!("pulling blocks from {} failed: {error}", peer.endpoint);
+                continue;
+            }
+        }
+        if let Err(error) = announce(blockhead, peer).await {
+            log::debug!("announcing the head to {} failed: {error}", peer.endpoint);
+        }
+    }
+    blockhead.peers.record(reachable, best_head);
+    fetch_missing(blockhead, peers).await;
+}
+
+/// Submit the canonical blocks `peer` has past the local head, until the import queue asks for
+/// a pause or is full, returning the number of the peer's head, or of the local head if the
+/// peer's is no higher.
+#[cfg(feature = "http")]
+async fn pull(blockhead: &Blockhead, peer: &Peer) -> Result<u64> {
+    let head = runtime::block(|| blockhead.head())?;
+    let target = format!(
+        "/blocks?from={}&limit={}&total=true",
+        head.number + 1,
+        blockhead.config.peers.batch
+    );
+    let page = peer.endpoint.call("GET", &target, b"").await?;
+    // The page counts the blocks from the one asked for to the peer's head.
+    let peer_head = head.number + page["total"].as_u64().unwrap_or(0);
+    let entries = page["blocks"]
+        .as_array()
+        .ok_or_else(|| Error::new(format!("{target} answered without blocks")))?;
+    for entry in entries {
+        match runtime::block(|| submit(blockhead, entry))?.1 {
+            ImportSignal::Queued | ImportSignal::Known => {}
+            ImportSignal::SlowDown | ImportSignal::Full => break,
+        }
+    }
+    Ok(peer_head)
+}
+
+/// Submit the parents that orphaned blocks are waiting for, up to [`PeerConfig::batch`] of them,
+/// each from the first of `peers` that has it, until the import queue asks for a pause or is
+/// full.
+#[cfg(feature = "http")]
+async fn fetch_missing(blockhead: &Blockhead, peers: &[Peer]) {
+    let missing = blockhead.missing_blocks();
+    for hash in missing
+        .into_iter()
+        .take(blockhead.config.peers.batch as usize)
+    {
+        for peer in peers {
+            let entry = match peer
+                .endpoint
+                .request("GET", &format!("/blocks/{hash}"), b"")
+                .await
+            {
+                Ok((200, entry)) => entry,
+                Ok(_) => continue,
+                Err(error) => {
+                    log::debug!("asking {} for block {hash} failed: {error}", peer.endpoint);
+                    continue;
+                }
+            };
+            match runtime::block(|| submit(blockhead, &entry)) {
+                Ok((_, ImportSignal::SlowDown | ImportSignal::Full)) => return,
+                Ok(_) => break,
+                Err(error) => log::debug!("{} sent a bad block {hash}: {error}", peer.endpoint),
+            }
+        }
+    }
+}
+
+/// Send the local head to `peer`, unless it took it already or asked the node to back off.
+#[cfg(feature = "http")]
+async fn announce(blockhead: &Blockhead, peer: &mut Peer) -> Result<()> {
+    let head = runtime::block(|| blockhead.head())?;
+    if peer.announced == Some(head.hash) || peer.resume_at.is_some_and(|at| Instant::now() < at) {
+        return Ok(());
+    }
+    let body = json!({
+        "hash": head.hash.to_string(),
+        "raw": format!("0x{}", hex::encode(head.encode())),
+    });
+    match (peer.endpoint)
+        .call("POST", "/blocks", body.to_string().as_bytes())
+        .await
+    {
+        Ok(answer) => {
+            peer.announced = Some(head.hash);
+            peer.resume_at = (answer["signal"] == "slow_down")
+                .then(|| Instant::now() + blockhead.config.peers.poll_interval);
+            Ok(())
+        }
+        Err(error) => match error.kind() {
+            ErrorKind::RateLimited { retry_after } => {
+                peer.resume_at = Some(Instant::now() + *retry_after);
+                Ok(())
+            }
+            _ => Err(error),
+        },
+    }
+}
+
+/// Submit the block whose canonical encoding a `/blocks` entry or a `POST /blocks` body holds as
+/// `raw`, returning its hash and the import queue's signal. If the entry's `hash` names a block
+/// the node has already, the block is not decoded.
+#[cfg(feature = "http")]
+fn submit(blockhead: &Blockhead, entry: &Value) -> Result<(Hash, ImportSignal)> {
+    let claimed = entry["hash"].as_str().map(str::parse::<Hash>).transpose()?;
+    if let Some(hash) = claimed {
+        if blockhead.has_block(hash)? {
+            return Ok((hash, ImportSignal::Known));
+        }
+    }
+    let raw = entry["raw"]
+        .as_str()
+        .ok_or_else(|| Error::new("block needs a \"raw\" encoding"))?;
+    let bytes = hex::decode(raw.trim_start_matches("0x"))
+        .map_err(|error| Error::new(format!("bad block hex: {error}")))?;
+    let block = Block::decode(&bytes, blockhead.config.hash)?;
+    let hash = block.hash;
+    if claimed.is_some_and(|claimed| claimed != hash) {
+        return Err(Error::new(format!(
+            "block {hash} was sent as {}",
+            claimed.unwrap()
+        )));
+    }
+    Ok((hash, blockhead.submit_block(block)?))
+}
+
+/// Answer `POST /blocks`, whose body holds a block's canonical encoding as `raw` and optionally
+/// its `hash`, with the block's hash and the import queue's [`ImportSignal`] as `signal`, or 429
+/// with `Retry-After` if the queue is full.
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, body: &[u8]) -> Response {
+    let body = match serde_json::from_slice::<Value>(body) {
+        Ok(body) => body,
+        Err(error) => return Response::from_error(400, &error.into()),
+    };
+    let submitter = blockhead.clone();
+    match runtime::spawn_blocking(move || submit(&submitter, &body)).await {
+        Ok((_, ImportSignal::Full)) => {
+            let error = Error::with_kind(
+                ErrorKind::RateLimited {
+                    retry_after: FULL_RETRY_AFTER,
+                },
+                "the import queue is full",
+            );
+            let mut response = Response::from_error(429, &error);
+            let seconds = FULL_RETRY_AFTER.as_secs();
+            response.headers.push(("Retry-After", seconds.to_string()));
+            response
+        }
+        Ok((hash, signal)) => {
+            Response::json(200, &json!({"hash": hash.to_string(), "signal": signal}))
+        }
+        Err(error) => Response::from_error(400, &error),
+    }
+}
+
+/// Answer `GET /blocks/<hash>` with the stored block `hash`, canonical or not, as its number,
+/// hash and canonical encoding, or 404 if there is none.
+#[cfg(feature = "http")]
+pub(crate) async fn handle_get(blockhead: &Arc<Blockhead>, hash: &str) -> Response {
+    let hash = match hash.parse::<Hash>() {
+        Ok(hash) => hash,
+        Err(error) => return Response::from_error(400, &error),
+    };
+    let reader = blockhead.clone();
+    match runtime::spawn_blocking(move || db::read_block(&reader.reader(), hash)).await {
+        Ok(Some(block)) => Response::json(
+            200,
+            &json!({
+                "number": block.number,
+                "hash": block.hash.to_string(),
+                "raw": format!("0x{}", hex::encode(block.encode())),
+            }),
+        ),
+        Ok(None) => Response::error(404, format!("no block {hash}")),
+        Err(error) => Response::from_error(500, &error),
+    }
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_sync_with_peer() {
+    use crate::testkit::TestChain;
+
+    let source = TestChain::new();
+    source.produce_many(3);
+    let head = source.head();
+    let address = crate::http::serve_locally(Arc::new(source.blockhead)).await;
+
+    let target = TestChain::new();
+    let genesis = target.head();
+    let mut peers = [Peer::new(Endpoint::Tcp(address.to_string()))];
+    sync(&target.blockhead, &mut peers).await;
+    // The pulled blocks are only queued, so the head announced is still the genesis block.
+    assert_eq!(peers[0].announced, Some(genesis.hash));
+    while target.blockhead.import_next() {}
+    assert_eq!(target.head().hash, head.hash);
+    // The source has the target's new head, so takes it without importing it again.
+    sync(&target.blockhead, &mut peers).await;
+    assert_eq!(peers[0].announced, Some(head.hash));
+    assert_eq!(peers[0].resume_at, None);
+
+    // An unreachable peer is skipped until it answers.
+    let mut peers = [Peer::new(Endpoint::Tcp("127.0.0.1:1".to_string()))];
+    sync(&target.blockhead, &mut peers).await;
+    assert_eq!(peers[0].announced, None);
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_peer_backpressure() {
+    use crate::genesis::ChainConfig;
+    use crate::http::{send, send_raw};
+    use crate::import_queue::ImportConfig;
+    use crate::testkit::TestChain;
+
+    let receiver = TestChain::with_config(ChainConfig {
+        import: ImportConfig {
+            capacity: 1,
+            slow_down_at: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let receiver = Arc::new(receiver.blockhead);
+    let address = crate::http::serve_locally(receiver.clone()).await;
+    let sender = TestChain::new();
+    let mut peers = [Peer::new(Endpoint::Tcp(address.to_string()))];
+
+    // The receiver takes the first block but asks for a pause, which holds back the next.
+    let first = sender.produce();
+    sync(&sender.blockhead, &mut peers).await;
+    assert_eq!(peers[0].announced, Some(first.hash));
+    assert!(peers[0].resume_at.is_some());
+    let second = sender.produce();
+    sync(&sender.blockhead, &mut peers).await;
+    assert_eq!(peers[0].announced, Some(first.hash));
+
+    // Once the pause is over, the full queue turns the next block away until `Retry-After`.
+    peers[0].resume_at = None;
+    sync(&sender.blockhead, &mut peers).await;
+    assert_eq!(peers[0].announced, Some(first.hash));
+    assert!(peers[0].resume_at.is_some());
+    assert_eq!(receiver.imports.len(), 1);
+
+    let body = json!({"raw": format!("0x{}", hex::encode(second.encode()))}).to_string();
+    let request = format!(
+        "POST /blocks HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    let response = send_raw(address, &request).await;
+    assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+    assert!(response.contains("Retry-After: 1\r\n"));
+    assert!(receiver.import_next());
+    let (status, answer) = send(address, &request).await;
+    assert_eq!(
+        (status, answer),
+        (
+            200,
+            json!({"hash": second.hash.to_string(), "signal": "slow_down"})
+        )
+    );
+    let (status, _) = send(address, "POST /blocks HTTP/1.1\r\n\r\n{}").await;
+    assert_eq!(status, 400);
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_known_blocks_not_decoded() {
+    use crate::http::send;
+    use crate::testkit::TestChain;
+
+    let source = TestChain::new();
+    source.produce_many(2);
+    let address = crate::http::serve_locally(Arc::new(source.blockhead)).await;
+    let target = TestChain::new();
+    let duplicates = || target.blockhead.imports.duplicates();
+
+    // Pulled again before they are imported, the queued blocks are skipped by hash.
+    let mut peers = [Peer::new(Endpoint::Tcp(address.to_string()))];
+    sync(&target.blockhead, &mut peers).await;
+    assert_eq!(duplicates(), 0);
+    sync(&target.blockhead, &mut peers).await;
+    assert_eq!(duplicates(), 2);
+    assert_eq!(target.blockhead.imports.len(), 2);
+
+    // A block sent with the hash of one the node has is not even decoded.
+    while target.blockhead.import_next() {}
+    let head = target.head();
+    let served = crate::http::serve_locally(Arc::new(target.blockhead)).await;
+    let post = |body: Value| async move {
+        let body = body.to_string();
+        let request = format!(
+            "POST /blocks HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        send(served, &request).await
+    };
+    let (status, answer) = post(json!({"hash": head.hash.to_string(), "raw": "0x00"})).await;
+    assert_eq!((status, &answer["signal"]), (200, &json!("known")));
+    let raw = format!("0x{}", hex::encode(head.encode()));
+    let (status, answer) = post(json!({"hash": Hash([7; 32]).to_string(), "raw": raw})).await;
+    assert_eq!(status, 400);
+    assert!(answer["error"].as_str().unwrap().contains("was sent as"));
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_missing_parents_fetched() {
+    use crate::http::send;
+    use crate::testkit::TestChain;
+
+    let source = TestChain::new();
+    let blocks: Vec<_> = (0..3).map(|_| source.produce()).collect();
+    let address = crate::http::serve_locally(Arc::new(source.blockhead)).await;
+    let target = TestChain::new();
+    let peers = [Peer::new(Endpoint::Tcp(address.to_string()))];
+
+    // The last block arrives first and waits for its parents, fetched one round at a time.
+    target.blockhead.submit_block(blocks[2].clone()).unwrap();
+    for parent in [&blocks[1], &blocks[0]] {
+        while target.blockhead.import_next() {}
+        assert_eq!(target.blockhead.missing_blocks(), vec![parent.hash]);
+        fetch_missing(&target.blockhead, &peers).await;
+    }
+    while target.blockhead.import_next() {}
+    assert_eq!(target.head().hash, blocks[2].hash);
+    assert!(target.blockhead.missing_blocks().is_empty());
+
+    let (status, entry) = send(
+        address,
+        &format!("GET /blocks/{} HTTP/1.1\r\n\r\n", blocks[1].hash),
+    )
+    .await;
+    assert_eq!((status, entry["number"].as_u64()), (200, Some(2)));
+    let unknown = Hash([7; 32]);
+    let (status, _) = send(address, &format!("GET /blocks/{unknown} HTTP/1.1\r\n\r\n")).await;
+    assert_eq!(status, 404);
+    let (status, _) = send(address, "GET /blocks/0x12 HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 400);
+}
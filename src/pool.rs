@@ -0,0 +1,154 @@
+//! Read-only SQLite connections shared by query methods.
+//!
+//! The main connection serializes every statement behind its mutex, so without a pool an RPC
+//! read waits for any block import in flight. With the database in WAL mode, readers on their
+//! own connections see the last committed state and run alongside the single writer.
+use crate::db;
+use crate::error::Result;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+pub(crate) struct ReaderPool {
+    idle: Mutex<Vec<sqlite::ConnectionThreadSafe>>,
+    available: Condvar,
+    size: usize,
+}
+
+impl ReaderPool {
+    /// A pool with no connections, whose readers all fall back to the writer.
+    pub(crate) fn empty() -> Self {
+        Self {
+            idle: Mutex::new(Vec::new()),
+            available: Condvar::new(),
+            size: 0,
+        }
+    }
+
+    /// Open `size` readers of the database at `path`, each attaching cold storage at `cold` if
+    /// given.
+    pub(crate) fn open(path: &Path, cold: Option<&Path>, size: usize) -> Result<Self> {
+        let mut idle = Vec::new();
+        for _ in 0..size {
+            let flags = sqlite::OpenFlags::new().with_read_only();
+            let mut connection = sqlite::Connection::open_thread_safe_with_flags(path, flags)?;
+            connection.set_busy_timeout(5_000)?;
+            if let Some(cold) = cold {
+                db::attach_cold(&connection, cold, false)?;
+            }
+            idle.push(connection);
+        }
+        Ok(Self {
+            idle: Mutex::new(idle),
+            available: Condvar::new(),
+            size,
+        })
+    }
+
+    /// Borrow a reader, waiting for one to be returned if all are in use. An empty pool hands out
+    /// `fallback` instead.
+    pub(crate) fn get<'a>(&'a self, fallback: &'a sqlite::Connection) -> ReadConnection<'a> {
+        if self.size == 0 {
+            return ReadConnection {
+                pool: self,
+                pooled: None,
+                fallback,
+            };
+        }
+        let mut idle = self.idle.lock().unwrap();
+        loop {
+            if let Some(connection) = idle.pop() {
+                return ReadConnection {
+                    pool: self,
+                    pooled: Some(connection),
+                    fallback,
+                };
+            }
+            idle = self.available.wait(idle).unwrap();
+        }
+    }
+}
+
+/// A connection borrowed from a [`ReaderPool`], returned when dropped.
+pub(crate) struct ReadConnection<'a> {
+    pool: &'a ReaderPool,
+    pooled: Option<sqlite::ConnectionThreadSafe>,
+    fallback: &'a sqlite::Connection,
+}
+
+impl ReadConnection<'_> {
+    /// Whether this is a connection of its own, rather than the writer shared with block
+    /// production.
+    pub(crate) fn is_pooled(&self) -> bool {
+        self.pooled.is_some()
+    }
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = sqlite::Connection;
+
+    fn deref(&self) -> &sqlite::Connection {
+        match &self.pooled {
+            Some(connection) => connection,
+            None => self.fallback,
+        }
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.pooled.take() {
+            self.pool.idle.lock().unwrap().push(connection);
+            self.pool.available.notify_one();
+        }
+    }
+}
+
+#[cfg(any(test, feature = "bench"))]
+pub(crate) fn file_chain(name: &str) -> (crate::Blockhead, std::path::PathBuf) {
+    use crate::genesis::Genesis;
+    use crate::testkit;
+
+    let path = std::env::temp_dir().join(format!("blockhead-{name}-{}.db", std::process::id()));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+    let genesis = Genesis {
+        alloc: vec![(testkit::validator(), 1_000_000)],
+        validators: vec![(testkit::validator(), 100)],
+        config: testkit::config(),
+        ..Default::default()
+    };
+    let blockhead = crate::Blockhead::with_genesis(&path, genesis).unwrap();
+    (
+        blockhead
+            .with_proposer_key(testkit::validator_key())
+            .unwrap(),
+        path,
+    )
+}
+
+#[tokio::test]
+async fn test_pooled_reads_see_commits() {
+    use crate::block::BlockId;
+    use crate::Blockchain;
+
+    let (blockhead, path) = file_chain("pool");
+    assert!(blockhead.readers.size > 0);
+    let held: Vec<_> = (0..blockhead.readers.size - 1)
+        .map(|_| blockhead.reader())
+        .collect();
+    blockhead.produce_block().unwrap();
+    assert_eq!(
+        blockhead
+            .get_header(BlockId::Latest)
+            .await
+            .unwrap()
+            .unwrap()
+            .number,
+        1
+    );
+    drop(held);
+    drop(blockhead);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,132 @@
+//! Native implementations of common cryptographic primitives, exposed at reserved addresses.
+//!
+//! A precompile is called like any contract, from a transaction, `call()` or the `CALL` opcode,
+//! but runs natively for a fixed base cost plus a cost per 32-byte word of input.
+use crate::address::Address;
+use crate::hash::HashAlgorithm;
+use crate::vm::{Outcome, VmResult};
+use blake2::Digest;
+
+pub(crate) struct Precompile {
+    pub name: &'static str,
+    pub address: Address,
+    base_gas: u64,
+    word_gas: u64,
+    /// Runs on the input, for a chain hashing with the given algorithm.
+    run: fn(&[u8], HashAlgorithm) -> Vec<u8>,
+}
+
+pub(crate) const BLAKE2S: Address = Address::reserved(1);
+pub(crate) const SHA256: Address = Address::reserved(2);
+pub(crate) const RECOVER: Address = Address::reserved(3);
+
+const PRECOMPILES: [Precompile; 3] = [
+    Precompile {
+        name: "blake2s",
+        address: BLAKE2S,
+        base_gas: 60,
+        word_gas: 12,
+        run: |input, _| blake2::Blake2s256::digest(input).to_vec(),
+    },
+    Precompile {
+        name: "sha256",
+        address: SHA256,
+        base_gas: 60,
+        word_gas: 12,
+        run: |input, _| sha2::Sha256::digest(input).to_vec(),
+    },
+    Precompile {
+        name: "recover",
+        address: RECOVER,
+        base_gas: 3_000,
+        word_gas: 0,
+        run: recover,
+    },
+];
+
+/// The precompile at `address`, if there is one.
+pub(crate) fn get(address: Address) -> Option<&'static Precompile> {
+    PRECOMPILES.iter().find(|p| p.address == address)
+}
+
+impl Precompile {
+    pub(crate) fn gas_cost(&self, input_len: usize) -> u64 {
+        let words = input_len.div_ceil(32) as u64;
+        self.base_gas
+            .saturating_add(self.word_gas.saturating_mul(words))
+    }
+
+    /// Run the precompile on `input` for a chain that hashes with `algorithm`.
+    pub(crate) fn call(&self, input: &[u8], gas_limit: u64, algorithm: HashAlgorithm) -> VmResult {
+        let gas_used = self.gas_cost(input.len());
+        if gas_used > gas_limit {
+            return VmResult {
+                outcome: Outcome::Failure(format!("out of gas in {}", self.name)),
+                gas_used: gas_limit,
+                logs: Vec::new(),
+            };
+        }
+        VmResult {
+            outcome: Outcome::Return((self.run)(input, algorithm)),
+            gas_used,
+            logs: Vec::new(),
+        }
+    }
+}
+
+/// Recover the signer of a message hash. The input is the 32-byte hash, the 64-byte `r || s`
+/// signature and a one byte recovery id; the output is the signer's address under `algorithm`, or
+/// empty if the signature is invalid.
+fn recover(input: &[u8], algorithm: HashAlgorithm) -> Vec<u8> {
+    use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+    if input.len() != 97 {
+        return Vec::new();
+    }
+    let Ok(signature) = Signature::from_slice(&input[32..96]) else {
+        return Vec::new();
+    };
+    let Some(recovery_id) = RecoveryId::from_byte(input[96]) else {
+        return Vec::new();
+    };
+    match VerifyingKey::recover_from_prehash(&input[..32], &signature, recovery_id) {
+        Ok(key) => Address::from_public_key(&key, algorithm).0.to_vec(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn test_precompiles() {
+    use k256::ecdsa::SigningKey;
+
+    let sha256 = get(SHA256).unwrap();
+    let algorithm = HashAlgorithm::default();
+    let result = sha256.call(b"abc", 1_000, algorithm);
+    assert_eq!(
+        result.outcome,
+        Outcome::Return(
+            hex::decode("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+                .unwrap()
+        )
+    );
+    assert_eq!(result.gas_used, 72);
+    assert!(matches!(
+        sha256.call(b"abc", 71, algorithm).outcome,
+        Outcome::Failure(_)
+    ));
+
+    let key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+    let message = [0x22; 32];
+    let (signature, recovery_id) = key.sign_prehash_recoverable(&message).unwrap();
+    let mut input = message.to_vec();
+    input.extend_from_slice(&signature.to_bytes());
+    input.push(recovery_id.to_byte());
+    let signer = Address::from_public_key(key.verifying_key(), algorithm);
+    assert_eq!(
+        get(RECOVER).unwrap().call(&input, 3_000, algorithm).outcome,
+        Outcome::Return(signer.0.to_vec())
+    );
+    let keccak = Address::from_public_key(key.verifying_key(), HashAlgorithm::Keccak256);
+    assert_eq!(recover(&input, HashAlgorithm::Keccak256), keccak.0.to_vec());
+    assert_eq!(recover(&input[..96], algorithm), Vec::<u8>::new());
+}
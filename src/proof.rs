@@ -0,0 +1,218 @@
+//! The state root, and Merkle proofs of accounts against it.
+//!
+//! Every header commits to the accounts after its block through `state_root`: the root of a
+//! binary Merkle tree whose leaves are the non-empty accounts, ordered by address. A light client
+//! holding a trusted header can check an [`AccountProof`] from any node with
+//! [`AccountProof::verify`], without the rest of the state. Contract storage, code and tokens
+//! are not part of the root.
+//!
+//! Leaves and inner nodes hash under different prefixes, so no leaf can pass for a subtree. A
+//! node without a sibling at the end of a level moves up unchanged.
+use crate::address::Address;
+use crate::block::BlockId;
+use crate::db;
+use crate::error::Result;
+use crate::hash::{Hash, HashAlgorithm, HashBuilder};
+use crate::state::Account;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A sibling on the path from a leaf to the root, and which side of the path it is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProofStep {
+    Left(Hash),
+    Right(Hash),
+}
+
+/// Proves that `address` held `account` in the state committed to by some state root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountProof {
+    pub address: Address,
+    pub account: Account,
+    /// The siblings from the leaf upwards.
+    pub branch: Vec<ProofStep>,
+}
+
+impl AccountProof {
+    /// Whether the proof leads from its account to `state_root`, on a chain that hashes with
+    /// `algorithm`.
+    pub(crate) fn verify(&self, state_root: Hash, algorithm: HashAlgorithm) -> bool {
+        let mut hash = leaf(self.address, &self.account, algorithm);
+        for step in &self.branch {
+            hash = match step {
+                ProofStep::Left(sibling) => node(*sibling, hash, algorithm),
+                ProofStep::Right(sibling) => node(hash, *sibling, algorithm),
+            };
+        }
+        hash == state_root
+    }
+}
+
+fn leaf(address: Address, account: &Account, algorithm: HashAlgorithm) -> Hash {
+    let mut hasher = HashBuilder::new(algorithm);
+    hasher.update([0]);
+    hasher.update(address.0);
+    hasher.update(account.balance.to_be_bytes());
+    hasher.update(account.nonce.to_be_bytes());
+    hasher.update(account.stake.to_be_bytes());
+    hasher.update(account.unbonding.to_be_bytes());
+    hasher.finalize()
+}
+
+fn node(left: Hash, right: Hash, algorithm: HashAlgorithm) -> Hash {
+    let mut hasher = HashBuilder::new(algorithm);
+    hasher.update([1]);
+    hasher.update(left.0);
+    hasher.update(right.0);
+    hasher.finalize()
+}
+
+fn leaves(accounts: &BTreeMap<Address, Account>, algorithm: HashAlgorithm) -> Vec<(Address, Hash)> {
+    accounts
+        .iter()
+        .filter(|(_, account)| **account != Account::default())
+        .map(|(address, account)| (*address, leaf(*address, account, algorithm)))
+        .collect()
+}
+
+fn next_level(level: &[Hash], algorithm: HashAlgorithm) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(*left, *right, algorithm),
+            [only] => *only,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// The root over `accounts` under `algorithm`. Empty accounts are left out, so creating and
+/// emptying an account leaves the root as it was.
+pub(crate) fn state_root(accounts: &BTreeMap<Address, Account>, algorithm: HashAlgorithm) -> Hash {
+    let mut level: Vec<Hash> = (leaves(accounts, algorithm).into_iter())
+        .map(|(_, hash)| hash)
+        .collect();
+    if level.is_empty() {
+        return HashBuilder::new(algorithm).finalize();
+    }
+    while level.len() > 1 {
+        level = next_level(&level, algorithm);
+    }
+    level[0]
+}
+
+/// The proof of `address`'s account in `accounts` under `algorithm`, or `None` if the account
+/// is empty. The tree cannot prove that an account is empty.
+pub(crate) fn prove(
+    accounts: &BTreeMap<Address, Account>,
+    address: Address,
+    algorithm: HashAlgorithm,
+) -> Option<AccountProof> {
+    let leaves = leaves(accounts, algorithm);
+    let mut index = leaves.binary_search_by_key(&address, |(a, _)| *a).ok()?;
+    let mut level: Vec<Hash> = leaves.into_iter().map(|(_, hash)| hash).collect();
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            branch.push(if sibling < index {
+                ProofStep::Left(level[sibling])
+            } else {
+                ProofStep::Right(level[sibling])
+            });
+        }
+        level = next_level(&level, algorithm);
+        index /= 2;
+    }
+    Some(AccountProof {
+        address,
+        account: accounts[&address],
+        branch,
+    })
+}
+
+impl Blockhead {
+    /// The proof of `address`'s account against the state root of `block`.
+    pub(crate) fn account_proof(
+        &self,
+        address: Address,
+        block: BlockId,
+    ) -> Result<Option<AccountProof>> {
+        self.query_at(block, |connection| {
+            Ok(prove(
+                &db::read_accounts(connection)?,
+                address,
+                self.config.hash,
+            ))
+        })
+    }
+}
+
+#[test]
+fn test_account_proofs() {
+    let accounts: BTreeMap<Address, Account> = (1..=5)
+        .map(|n| {
+            let account = Account {
+                balance: n as u64 * 10,
+                ..Default::default()
+            };
+            (Address([n; 32]), account)
+        })
+        .chain([(Address([9; 32]), Account::default())])
+        .collect();
+    let algorithm = HashAlgorithm::default();
+    let root = state_root(&accounts, algorithm);
+    for n in 1..=5 {
+        let proof = prove(&accounts, Address([n; 32]), algorithm).unwrap();
+        assert!(proof.verify(root, algorithm));
+        let mut forged = proof.clone();
+        forged.account.balance += 1;
+        assert!(!forged.verify(root, algorithm));
+    }
+    assert!(prove(&accounts, Address([9; 32]), algorithm).is_none());
+    assert!(prove(&accounts, Address([6; 32]), algorithm).is_none());
+    let mut without_empty = accounts.clone();
+    without_empty.remove(&Address([9; 32]));
+    assert_eq!(state_root(&without_empty, algorithm), root);
+    assert_ne!(state_root(&accounts, HashAlgorithm::Sha3_256), root);
+}
+
+#[tokio::test]
+async fn test_get_proof() {
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let recipient = Address([8; 32]);
+    let transfer = Transaction {
+        to_address: Some(recipient),
+        value: 5,
+        ..testkit::transaction(Default::default(), validator, 0)
+    };
+    blockhead.send_transaction(transfer).await.unwrap();
+    let block = blockhead.produce_block().unwrap();
+    let genesis = blockhead.canonical_block(BlockId::Number(0)).unwrap();
+
+    let proof = blockhead
+        .get_proof(recipient, BlockId::Latest)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(proof.account.balance, 5);
+    assert!(proof.verify(block.state_root, blockhead.config.hash));
+    assert!(!proof.verify(genesis.state_root, blockhead.config.hash));
+    let proof = blockhead
+        .get_proof(validator, BlockId::Number(0))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(proof.account.nonce, 0);
+    assert!(proof.verify(genesis.state_root, blockhead.config.hash));
+    assert!(blockhead
+        .get_proof(recipient, BlockId::Number(0))
+        .await
+        .unwrap()
+        .is_none());
+}
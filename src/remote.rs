@@ -0,0 +1,387 @@
+//! A node reached over HTTP through one or more endpoints, for clients that must ride out a flaky
+//! node: `blockhead attach`, `blockhead call` and [`crate::HttpBlockchain`].
+//!
+//! A [`Remote`] tracks the health of each endpoint. An endpoint that fails a request, by not
+//! answering in time, by dropping or refusing the connection, or by answering that it is
+//! overloaded or broken (`429` or `5xx`), is passed over for [`RetryPolicy::cooldown`]. Each
+//! request goes to the first healthy endpoint in the order given, so traffic returns to a
+//! preferred endpoint once its cooldown is over. Once every endpoint has failed, a request waits
+//! before trying again, twice as long each time, until [`RetryPolicy::attempts`] tries are spent.
+//! Other answers, errors included, are the node's word and are not retried.
+//!
+//! A request that failed after it was sent may still have been carried out. Only requests that
+//! have the same effect however often they are carried out (see [`is_idempotent`]) are retried
+//! then. The others, such as a `POST /transactions` that sends a transaction, are only retried
+//! if the connection could not be made, so that the node never saw them; otherwise they fail
+//! with the error.
+use crate::error::{Error, Result};
+use crate::http::{self, Endpoint};
+use crate::rpc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How a [`Remote`] retries requests and fails over between endpoints.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// How many times a request is tried, across all endpoints, before it fails.
+    pub attempts: u32,
+    /// The wait after every endpoint has failed, doubled after each further such round.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// How long each try may take.
+    pub timeout: Duration,
+    /// How long an endpoint that failed is passed over while healthy ones remain.
+    pub cooldown: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// `POST` targets that only read, and so are as safe to retry as a `GET`.
+const READ_ONLY_POSTS: [&str; 2] = ["/graphql", "/balances"];
+
+/// Whether carrying out `method` `target` twice has the effect of doing it once, so that a try
+/// that may have reached the node can be retried: `GET`s, the `POST`s in [`READ_ONLY_POSTS`], and
+/// the [`crate::rpc`] methods but `send_transaction`.
+pub(crate) fn is_idempotent(method: &str, target: &str) -> bool {
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let read_only_rpc =
+        (path.strip_prefix(rpc::PREFIX)).is_some_and(|m| m != rpc::SEND_TRANSACTION);
+    method == "GET" || (method == "POST" && (READ_ONLY_POSTS.contains(&path) || read_only_rpc))
+}
+
+/// See the [module documentation](self).
+#[derive(Debug)]
+pub struct Remote {
+    endpoints: Vec<Endpoint>,
+    policy: RetryPolicy,
+    /// Until when each endpoint is passed over, by index, if it failed its last request.
+    unhealthy_until: Mutex<Vec<Option<Instant>>>,
+}
+
+/// How a try at a request went.
+enum Outcome {
+    /// The node answered, with a status other than `429` and `5xx`.
+    Answered(u16, Value),
+    /// The endpoint failed, after the node may have seen the request if `reached`. `answer` is
+    /// the node's own answer, if it gave one.
+    Failed {
+        error: Error,
+        reached: bool,
+        answer: Option<(u16, Value)>,
+    },
+}
+
+impl Remote {
+    /// A remote reached through `endpoints`, preferred in order while healthy.
+    pub(crate) fn new(endpoints: Vec<Endpoint>) -> Result<Self> {
+        if endpoints.is_empty() {
+            return Err(Error::new("no endpoints given"));
+        }
+        Ok(Self {
+            unhealthy_until: Mutex::new(vec![None; endpoints.len()]),
+            endpoints,
+            policy: RetryPolicy::default(),
+        })
+    }
+
+    /// Read a comma-separated list of endpoints, each `http://<host>:<port>`, `<host>:<port>`, a
+    /// data directory, whose IPC socket is meant, or the path of a socket.
+    pub fn parse(s: &str) -> Result<Self> {
+        Self::new(
+            s.split(',')
+                .map(str::trim)
+                .filter(|endpoint| !endpoint.is_empty())
+                .map(Endpoint::parse)
+                .collect(),
+        )
+    }
+
+    pub fn with_policy(self, policy: RetryPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// The endpoint the next request goes to first.
+    pub(crate) fn current(&self) -> &Endpoint {
+        &self.endpoints[self.choose().0]
+    }
+
+    /// The index of the first healthy endpoint, or if none is, of the one whose cooldown ends
+    /// first, and whether every endpoint is unhealthy.
+    fn choose(&self) -> (usize, bool) {
+        let now = Instant::now();
+        let unhealthy_until = self.unhealthy_until.lock().unwrap();
+        let healthy = unhealthy_until
+            .iter()
+            .position(|until| until.is_none_or(|until| until <= now));
+        match healthy {
+            Some(index) => (index, false),
+            None => {
+                let soonest = (0..self.endpoints.len()).min_by_key(|&index| unhealthy_until[index]);
+                (soonest.expect("there is an endpoint"), true)
+            }
+        }
+    }
+
+    fn set_health(&self, index: usize, healthy: bool) {
+        self.unhealthy_until.lock().unwrap()[index] =
+            (!healthy).then(|| Instant::now() + self.policy.cooldown);
+    }
+
+    /// [`Endpoint::request`] through the first endpoint to answer, retried as the
+    /// [module documentation](self) describes.
+    pub(crate) async fn request(
+        &self,
+        method: &str,
+        target: &str,
+        body: &[u8],
+    ) -> Result<(u16, Value)> {
+        let idempotent = is_idempotent(method, target);
+        let mut backoff = self.policy.backoff;
+        let mut failure = None;
+        for attempt in 1..=self.policy.attempts.max(1) {
+            let (index, all_unhealthy) = self.choose();
+            // Every endpoint failed since the last wait.
+            if all_unhealthy && attempt > 1 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.policy.max_backoff);
+            }
+            let (error, reached, answer) = match self.attempt(index, method, target, body).await {
+                Outcome::Answered(status, value) => {
+                    self.set_health(index, true);
+                    return Ok((status, value));
+                }
+                Outcome::Failed {
+                    error,
+                    reached,
+                    answer,
+                } => (error, reached, answer),
+            };
+            self.set_health(index, false);
+            log::debug!("attempt {attempt} of {method} {target} failed: {error}");
+            if reached && !idempotent {
+                return match answer {
+                    Some(answer) => Ok(answer),
+                    None => Err(Error::with_kind(
+                        error.kind().clone(),
+                        format!(
+                            "{method} {target} may have reached the node, so was not retried: {}",
+                            error.message()
+                        ),
+                    )),
+                };
+            }
+            failure = Some(error);
+        }
+        let failure = failure.expect("at least one attempt is made");
+        Err(Error::with_kind(
+            failure.kind().clone(),
+            format!(
+                "{method} {target} failed after {} attempts: {}",
+                self.policy.attempts.max(1),
+                failure.message()
+            ),
+        ))
+    }
+
+    /// Try `method` `target` once, at endpoint `index`.
+    async fn attempt(&self, index: usize, method: &str, target: &str, body: &[u8]) -> Outcome {
+        let endpoint = &self.endpoints[index];
+        let connected = AtomicBool::new(false);
+        let request = async {
+            let connection = endpoint.connect().await?;
+            connected.store(true, Ordering::Relaxed);
+            endpoint.send(connection, method, target, body).await
+        };
+        let result = tokio::time::timeout(self.policy.timeout, request).await;
+        let reached = connected.load(Ordering::Relaxed);
+        let error = match result {
+            Ok(Ok((status, value))) if status != 429 && status < 500 => {
+                return Outcome::Answered(status, value);
+            }
+            Ok(Ok((status, value))) => {
+                let error = match http::answer(target, status, value.clone()) {
+                    Err(error) => error,
+                    Ok(_) => unreachable!("{status} is an error status"),
+                };
+                return Outcome::Failed {
+                    error,
+                    reached,
+                    answer: Some((status, value)),
+                };
+            }
+            Ok(Err(error)) => error,
+            Err(_) => Error::new(format!(
+                "{endpoint} did not answer within {}s",
+                self.policy.timeout.as_secs_f64()
+            )),
+        };
+        Outcome::Failed {
+            error,
+            reached,
+            answer: None,
+        }
+    }
+
+    /// [`Remote::request`], failing with the server's error unless the status is a success. See
+    /// [`Endpoint::call`].
+    pub(crate) async fn call(&self, method: &str, target: &str, body: &[u8]) -> Result<Value> {
+        let (status, value) = self.request(method, target, body).await?;
+        http::answer(target, status, value)
+    }
+}
+
+impl std::fmt::Display for Remote {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.current())?;
+        if self.endpoints.len() > 1 {
+            write!(f, " (of {} endpoints)", self.endpoints.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_remote() {
+    use crate::http::serve_locally;
+    use crate::Blockhead;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    // Nothing listens at a port just given up.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+    drop(listener);
+    // Nothing answers at a port whose connections are never accepted.
+    let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let silent_endpoint = Endpoint::Tcp(silent.local_addr().unwrap().to_string());
+    let live = serve_locally(Arc::new(Blockhead::new(":memory:").unwrap())).await;
+    let live = Endpoint::Tcp(live.to_string());
+    let policy = RetryPolicy {
+        attempts: 3,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(2),
+        timeout: Duration::from_millis(200),
+        cooldown: Duration::from_secs(60),
+    };
+
+    let remote = Remote::new(vec![dead.clone(), silent_endpoint.clone(), live.clone()])
+        .unwrap()
+        .with_policy(policy.clone());
+    let health = remote.call("GET", "/health", b"").await.unwrap();
+    assert_eq!(health["database"], "ok");
+    assert_eq!(remote.current(), &live);
+    assert_eq!(remote.to_string(), format!("{live} (of 3 endpoints)"));
+    // The node's own errors are not retried.
+    let error = remote.call("GET", "/nowhere", b"").await.unwrap_err();
+    assert_eq!(
+        error.message(),
+        "/nowhere answered 404: no endpoint /nowhere"
+    );
+
+    let remote = Remote::new(vec![silent_endpoint.clone(), dead.clone()])
+        .unwrap()
+        .with_policy(policy.clone());
+    let error = remote.call("GET", "/health", b"").await.unwrap_err();
+    assert!(error
+        .message()
+        .starts_with("GET /health failed after 3 attempts: "));
+    assert!(error.message().contains("did not answer within 0.2s"));
+
+    // A send that may have reached the node is not retried, but one that never left is.
+    let remote = Remote::new(vec![silent_endpoint.clone(), live.clone()])
+        .unwrap()
+        .with_policy(policy.clone());
+    let error = remote
+        .call("POST", "/transactions", b"{}")
+        .await
+        .unwrap_err();
+    assert!(error
+        .message()
+        .starts_with("POST /transactions may have reached the node, so was not retried: "));
+    let remote = Remote::new(vec![dead, live.clone()])
+        .unwrap()
+        .with_policy(policy.clone());
+    let error = remote
+        .call("POST", "/transactions", b"{}")
+        .await
+        .unwrap_err();
+    assert!(error.message().starts_with("/transactions answered 400: "));
+    // Read-only POSTs are retried like GETs.
+    let remote = Remote::new(vec![silent_endpoint, live.clone()])
+        .unwrap()
+        .with_policy(policy);
+    let query = remote.call("POST", "/graphql", b"{\"query\": \"{chainId}\"}");
+    assert!(query.await.unwrap()["data"]["chainId"].is_u64());
+    drop(silent);
+
+    let remote = Remote::parse(&format!("{live}, {live}")).unwrap();
+    assert_eq!(remote.endpoints, vec![live.clone(), live]);
+    assert!(Remote::parse(" , ").is_err());
+}
+
+#[tokio::test]
+async fn test_remote_health() {
+    use crate::http::{serve, Server};
+    use crate::Blockhead;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    let serve_at = |listener: TcpListener| {
+        let blockhead = Arc::new(Blockhead::new(":memory:").unwrap());
+        tokio::spawn(serve(listener, Server::new(blockhead)))
+    };
+    let preferred = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let preferred_address = preferred.local_addr().unwrap();
+    let preferred_endpoint = Endpoint::Tcp(preferred_address.to_string());
+    drop(preferred);
+    let fallback = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let fallback_endpoint = Endpoint::Tcp(fallback.local_addr().unwrap().to_string());
+    let _fallback = serve_at(fallback);
+    let cooldown = Duration::from_millis(300);
+    let remote = Remote::new(vec![preferred_endpoint.clone(), fallback_endpoint.clone()])
+        .unwrap()
+        .with_policy(RetryPolicy {
+            cooldown,
+            ..RetryPolicy::default()
+        });
+
+    // The preferred endpoint is passed over while it cools down, not tried first each time.
+    assert_eq!(remote.current(), &preferred_endpoint);
+    remote.call("GET", "/health", b"").await.unwrap();
+    assert_eq!(remote.current(), &fallback_endpoint);
+    remote.call("GET", "/health", b"").await.unwrap();
+    assert_eq!(remote.current(), &fallback_endpoint);
+
+    // Once it has cooled down, requests go back to it, and stay while it answers.
+    let _preferred = serve_at(TcpListener::bind(preferred_address).await.unwrap());
+    tokio::time::sleep(cooldown).await;
+    assert_eq!(remote.current(), &preferred_endpoint);
+    remote.call("GET", "/health", b"").await.unwrap();
+    assert_eq!(remote.current(), &preferred_endpoint);
+    // Policies read from config files default what they leave out.
+    let policy: RetryPolicy = toml::from_str("attempts = 2").unwrap();
+    assert_eq!(policy.attempts, 2);
+    assert_eq!(policy.cooldown, RetryPolicy::default().cooldown);
+    assert!(is_idempotent("POST", "/graphql?x=1"));
+    assert!(!is_idempotent("POST", "/faucet?address=0x00"));
+    assert!(is_idempotent("POST", "/rpc/get_balance"));
+    assert!(!is_idempotent("POST", "/rpc/send_transaction"));
+}
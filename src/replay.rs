@@ -0,0 +1,227 @@
+//! Re-execution of stored blocks, to find where the node's state or receipts stopped matching what
+//! the chain recorded.
+//!
+//! `blockhead replay --from <N> --to <M>` rewinds to block `N - 1`, executes each canonical block
+//! of the range again from its stored body, and compares every receipt and each block's state root
+//! with the stored ones. It stops at the first difference, which points at an execution change
+//! between node versions or at state that was edited outside of block execution.
+use crate::block::{Block, BlockId};
+use crate::db;
+use crate::error::{Error, Result};
+use crate::execution::{self, ExecutionOutcome};
+use crate::hash::Hash;
+use crate::reward;
+use crate::state::StateOverlay;
+use crate::verify;
+use crate::{Blockhead, TransactionReceipt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ReplayReport {
+    /// How many blocks were executed, including the divergent one if any.
+    pub replayed: u64,
+    pub divergence: Option<Divergence>,
+}
+
+/// The first point at which re-execution disagreed with the stored chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Divergence {
+    pub block_number: u64,
+    pub block_hash: Hash,
+    /// The transaction whose receipt differs, or none if only the block's state root does.
+    pub transaction: Option<Hash>,
+    pub reason: String,
+}
+
+impl Blockhead {
+    /// Execute canonical blocks `from..=to` again on top of the state after block `from - 1`, and
+    /// report the first receipt or state root that differs from the stored one. Nothing the
+    /// replay writes is kept, and production and import wait until it is done.
+    pub(crate) fn replay(&self, from: u64, to: u64) -> Result<ReplayReport> {
+        if from == 0 {
+            return Err(Error::new("cannot replay genesis, which has no parent"));
+        }
+        if from > to {
+            return Err(Error::new(format!("empty range {from}..={to}")));
+        }
+        let start = self.canonical_block(BlockId::Number(from - 1))?;
+        let mut blocks = Vec::new();
+        for number in from..=to {
+            let block = self.canonical_block(BlockId::Number(number))?;
+            // Rewinding deletes the receipts of the blocks it passes, so read them first.
+            let receipts = self.stored_receipts(&block)?;
+            blocks.push((block, receipts));
+        }
+        self.with_state_at(&start, || {
+            let mut parent = &start;
+            let mut report = ReplayReport {
+                replayed: 0,
+                divergence: None,
+            };
+            for (block, receipts) in &blocks {
+                report.replayed += 1;
+                if let Some(divergence) = self.replay_block(parent, block, receipts)? {
+                    report.divergence = Some(divergence);
+                    break;
+                }
+                parent = block;
+            }
+            Ok(report)
+        })
+    }
+
+    /// The receipts stored for `block`, whether or not the indexer has taken them yet.
+    fn stored_receipts(&self, block: &Block) -> Result<Vec<TransactionReceipt>> {
+        let connection = self.reader();
+        if let Some(receipts) = db::read_pending_receipts(&connection, block.hash)? {
+            return Ok(receipts);
+        }
+        block
+            .body
+            .transactions
+            .iter()
+            .map(|(hash, _)| {
+                db::read_receipt(&connection, *hash)?
+                    .ok_or_else(|| Error::new(format!("missing receipt of transaction {hash}")))
+            })
+            .collect()
+    }
+
+    /// Execute `block` on top of `parent`, committing its effects if they match `receipts` and
+    /// its state root, and returning the first difference otherwise.
+    fn replay_block(
+        &self,
+        parent: &Block,
+        block: &Block,
+        receipts: &[TransactionReceipt],
+    ) -> Result<Option<Divergence>> {
+        let diverged = |transaction, reason| {
+            Ok(Some(Divergence {
+                block_number: block.number,
+                block_hash: block.hash,
+                transaction,
+                reason,
+            }))
+        };
+        let mut state = StateOverlay::new(&self.connection);
+        self.enter_block(&mut state, parent)?;
+        let mut fees = 0;
+        let transactions = block.body.transactions.iter().map(|(_, t)| t);
+        let signers = verify::recover_signers(transactions, self.config.chain_id, self.config.hash);
+        for (((hash, transaction), signers), receipt) in
+            block.body.transactions.iter().zip(signers).zip(receipts)
+        {
+            let outcome = match signers.and_then(|signers| {
+                execution::execute_transaction(&mut state, &self.config, transaction, &signers)
+            }) {
+                Ok(outcome) => outcome,
+                Err(error) => return diverged(Some(*hash), format!("failed: {error}")),
+            };
+            if let Some(reason) = compare_receipt(receipt, &outcome) {
+                return diverged(Some(*hash), reason);
+            }
+            fees += outcome.gas_used * transaction.gas_price;
+        }
+        reward::credit(&mut state, &self.config.reward, block.proposer, fees)?;
+        let state_root = state.state_root(self.config.hash)?;
+        if state_root != block.state_root {
+            return diverged(
+                None,
+                format!(
+                    "state root is {state_root}, but the block claims {}",
+                    block.state_root
+                ),
+            );
+        }
+        state.commit(block.hash)?;
+        Ok(None)
+    }
+}
+
+/// How `outcome` differs from the stored `receipt`, if it does.
+fn compare_receipt(receipt: &TransactionReceipt, outcome: &ExecutionOutcome) -> Option<String> {
+    if outcome.status != receipt.status {
+        return Some(format!(
+            "status is {}, but was stored as {}",
+            outcome.status, receipt.status
+        ));
+    }
+    if outcome.gas_used != receipt.gas_used {
+        return Some(format!(
+            "used {} gas, but was stored as using {}",
+            outcome.gas_used, receipt.gas_used
+        ));
+    }
+    if outcome.contract_address != receipt.contract_address {
+        return Some(format!(
+            "created contract {:?}, but was stored as creating {:?}",
+            outcome.contract_address, receipt.contract_address
+        ));
+    }
+    if outcome.return_data != receipt.return_data {
+        return Some("returned different data than stored".to_string());
+    }
+    if outcome.logs != receipt.logs {
+        return Some(format!(
+            "emitted {} logs that differ from the {} stored",
+            outcome.logs.len(),
+            receipt.logs.len()
+        ));
+    }
+    None
+}
+
+#[tokio::test]
+async fn test_replay() {
+    use crate::address::Address;
+    use crate::state::Account;
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let recipient = Address([8; 32]);
+    for transfers in [2, 0, 1] {
+        for _ in 0..transfers {
+            chain.transfer(chain.validator, recipient, 1).await;
+        }
+        chain.produce();
+    }
+    // Replay reads receipts whether or not they were indexed.
+    chain.blockhead.index_receipts(1).unwrap();
+    let report = chain.blockhead.replay(1, 3).unwrap();
+    assert_eq!(
+        report,
+        ReplayReport {
+            replayed: 3,
+            divergence: None,
+        }
+    );
+    // Replaying kept nothing: the head's state is as before.
+    chain.assert_balance(recipient, 3);
+    assert!(chain.blockhead.replay(0, 3).is_err());
+    assert!(chain.blockhead.replay(3, 2).is_err());
+    assert!(chain.blockhead.replay(1, 4).is_err());
+
+    // A stored receipt that execution does not reproduce.
+    let block3 = chain.head();
+    let mut receipts = db::read_pending_receipts(&chain.blockhead.connection, block3.hash)
+        .unwrap()
+        .unwrap();
+    receipts[0].gas_used += 1;
+    db::write_pending_receipts(&chain.blockhead.connection, &block3, &receipts).unwrap();
+    let divergence = chain.blockhead.replay(2, 3).unwrap().divergence.unwrap();
+    assert_eq!(divergence.block_number, 3);
+    assert_eq!(divergence.transaction, Some(block3.body.transactions[0].0));
+    assert!(divergence.reason.contains("gas"));
+
+    // State edited outside of any block shows up at the first replayed block.
+    let stray = Account {
+        balance: 1,
+        ..Default::default()
+    };
+    db::write_account(&chain.blockhead.connection, Address([9; 32]), &stray).unwrap();
+    let report = chain.blockhead.replay(1, 3).unwrap();
+    assert_eq!(report.replayed, 1);
+    let divergence = report.divergence.unwrap();
+    assert_eq!(divergence.block_number, 1);
+    assert_eq!(divergence.transaction, None);
+    assert!(divergence.reason.contains("state root"));
+}
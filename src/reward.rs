@@ -0,0 +1,131 @@
+//! What a block's proposer earns for it.
+//!
+//! There is no coinbase transaction: after a block's transactions are executed, its proposer is
+//! credited with the subsidy fixed at genesis plus the fees its transactions paid, as part of the
+//! block's state transition. Both are derived from the block and its receipts, so neither is
+//! stored separately.
+use crate::address::Address;
+use crate::block::BlockId;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::state::StateOverlay;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewardConfig {
+    /// Newly issued funds credited to the proposer of every block after genesis.
+    pub block_subsidy: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockReward {
+    pub proposer: Address,
+    pub subsidy: u64,
+    /// The gas used by the block's transactions, each at its gas price.
+    pub fees: u64,
+}
+
+impl BlockReward {
+    pub(crate) fn total(&self) -> u64 {
+        self.subsidy + self.fees
+    }
+}
+
+/// Credit `proposer` with the block subsidy and `fees`.
+pub(crate) fn credit(
+    state: &mut StateOverlay,
+    config: &RewardConfig,
+    proposer: Address,
+    fees: u64,
+) -> Result<()> {
+    let account = state.account_mut(proposer)?;
+    account.balance = account
+        .balance
+        .checked_add(config.block_subsidy + fees)
+        .ok_or_else(|| Error::new(format!("reward overflows the balance of {proposer}")))?;
+    Ok(())
+}
+
+impl Blockhead {
+    /// The reward paid to the proposer of canonical block `id`.
+    pub(crate) fn block_rewards(&self, id: BlockId) -> Result<BlockReward> {
+        let block = self.canonical_block(id)?;
+        if block.number == 0 {
+            return Ok(BlockReward {
+                proposer: block.proposer,
+                subsidy: 0,
+                fees: 0,
+            });
+        }
+        let connection = self.reader();
+        let mut fees = 0;
+        for (hash, transaction) in &block.body.transactions {
+            let receipt = db::read_receipt(&connection, *hash)?
+                .ok_or_else(|| Error::new(format!("missing receipt for {hash}")))?;
+            fees += receipt.gas_used * transaction.gas_price;
+        }
+        Ok(BlockReward {
+            proposer: block.proposer,
+            subsidy: self.config.reward.block_subsidy,
+            fees,
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_block_rewards() {
+    use crate::genesis::{ChainConfig, Genesis};
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let (validator, sender) = (testkit::validator(), Address([9; 32]));
+    let genesis = Genesis {
+        alloc: vec![(sender, 100_000)],
+        validators: vec![(validator, 100)],
+        config: ChainConfig {
+            reward: RewardConfig { block_subsidy: 50 },
+            ..testkit::config()
+        },
+        ..Default::default()
+    };
+    let blockhead = Blockhead::with_genesis(":memory:", genesis)
+        .unwrap()
+        .with_proposer_key(testkit::validator_key())
+        .unwrap();
+    let transfer = Transaction {
+        to_address: Some(Address([8; 32])),
+        value: 5,
+        gas_limit: 30_000,
+        gas_price: 2,
+        ..testkit::transaction(Default::default(), sender, 0)
+    };
+    blockhead.send_transaction(transfer).await.unwrap();
+    let block1 = blockhead.produce_block().unwrap();
+    blockhead.produce_block().unwrap();
+
+    let reward = blockhead
+        .get_block_rewards(BlockId::Hash(block1.hash))
+        .await
+        .unwrap();
+    assert_eq!(
+        reward,
+        BlockReward {
+            proposer: validator,
+            subsidy: 50,
+            fees: 42_000,
+        }
+    );
+    let empty = blockhead.get_block_rewards(BlockId::Latest).await.unwrap();
+    assert_eq!(empty.total(), 50);
+    assert_eq!(
+        blockhead.get_balance(validator).await.unwrap(),
+        reward.total() + empty.total()
+    );
+    assert_eq!(
+        blockhead.get_balance(sender).await.unwrap(),
+        100_000 - 5 - 42_000
+    );
+}
@@ -0,0 +1,521 @@
+//! The [`Blockchain`] trait over HTTP, so that a [`crate::Client`] drives a node in another
+//! process as it does one embedded in its own.
+//!
+//! The server answers each of the trait's methods at `POST /rpc/<method>`, named as in the trait,
+//! with a JSON object of the method's arguments by name as the body, and `{"result": ...}` as the
+//! response. Blocks, headers and transactions travel as their `0x`-prefixed hex encodings (see
+//! [`crate::encoding`]), byte strings as `0x`-prefixed hex, and everything else as its serde JSON
+//! form. A method that fails is answered as every other endpoint's errors are, with the error's
+//! `code` and `data`, so that the caller gets the same [`crate::ErrorKind`].
+//!
+//! [`HttpBlockchain`] implements the trait against that endpoint through a [`Remote`], which
+//! retries and fails over as its [`crate::RetryPolicy`] says. Every method but `send_transaction`
+//! only reads, so is retried as a `GET` would be.
+use crate::address::Address;
+use crate::block::{Block, BlockId, Header};
+use crate::encoding::Reader;
+use crate::error::{Error, Result};
+use crate::execution::{CallOverrides, StateOverrides};
+use crate::hash::{Hash, HashAlgorithm};
+use crate::http::Response;
+use crate::mempool::{BySender, MempoolContent, MempoolStatus};
+use crate::remote::Remote;
+use crate::transaction::Transaction;
+use crate::{
+    AccountProof, BlockReward, Blockchain, Blockhead, ChainStats, FeeEstimate, Simulation,
+    StateDiff, TokenInfo, Trace, TransactionReceipt, TransactionStatus,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The path prefix of the methods.
+pub(crate) const PREFIX: &str = "/rpc/";
+
+/// The one method that changes the node, and so is not retried once it may have reached it.
+pub(crate) const SEND_TRANSACTION: &str = "send_transaction";
+
+/// Answer a call to `method`, with `body` its arguments.
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, method: &str, body: &[u8]) -> Response {
+    let params = match body {
+        [] => json!({}),
+        body => match serde_json::from_slice(body) {
+            Ok(params) => params,
+            Err(error) => return Response::from_error(400, &Error::from(error)),
+        },
+    };
+    match call(blockhead, method, &params).await {
+        Ok(Some(result)) => Response::json(200, &json!({"result": result})),
+        Ok(None) => Response::error(404, format!("no method {method}")),
+        Err(error) => Response::from_error(400, &error),
+    }
+}
+
+/// The result of `method` on `chain` with arguments `params`, or `None` if there is no such
+/// method.
+async fn call(chain: &Blockhead, method: &str, params: &Value) -> Result<Option<Value>> {
+    let id = || param::<BlockId>(params, "id");
+    let hash = || param::<Hash>(params, "hash");
+    let address = || param::<Address>(params, "address");
+    let block = || param::<BlockId>(params, "block");
+    let overrides = || param::<StateOverrides>(params, "overrides");
+    let result = match method {
+        "get_block" => json!(chain.get_block(id()?).await?.map(|b| to_hex(&b.encode()))),
+        "get_header" => {
+            let header = chain.get_header(id()?).await?;
+            json!(header.map(|header| to_hex(&header.encode_signed())))
+        }
+        "get_raw_block" => json!(chain.get_raw_block(hash()?).await?),
+        "get_block_rewards" => to_json(chain.get_block_rewards(id()?).await?)?,
+        "get_transaction" => {
+            let transaction = chain.get_transaction(hash()?).await?;
+            json!(transaction.map(|transaction| to_hex(&transaction.encode())))
+        }
+        "get_transaction_receipt" => to_json(chain.get_transaction_receipt(hash()?).await?)?,
+        "get_raw_transaction" => json!(chain.get_raw_transaction(hash()?).await?),
+        SEND_TRANSACTION => {
+            let transaction = decode_transaction(&param::<String>(params, "transaction")?)?;
+            to_json(chain.send_transaction(transaction).await?)?
+        }
+        "get_transaction_status" => to_json(chain.get_transaction_status(hash()?).await?)?,
+        "get_mempool_content" => {
+            let content = chain.get_mempool_content().await?;
+            json!({
+                "pending": encode_by_sender(&content.pending),
+                "queued": encode_by_sender(&content.queued),
+            })
+        }
+        "get_mempool_status" => to_json(chain.get_mempool_status().await?)?,
+        "get_balance" => json!(chain.get_balance(address()?).await?),
+        "get_balances" => {
+            let addresses: Vec<Address> = param(params, "addresses")?;
+            json!(chain.get_balances(&addresses).await?)
+        }
+        "get_balance_at" => json!(chain.get_balance_at(address()?, block()?).await?),
+        "get_nonce" => json!(chain.get_nonce(address()?).await?),
+        "get_pending_nonce" => json!(chain.get_pending_nonce(address()?).await?),
+        "get_token_info" => to_json(chain.get_token_info(param(params, "token")?).await?)?,
+        "get_token_balance" => json!(
+            chain
+                .get_token_balance(param(params, "token")?, param(params, "holder")?)
+                .await?
+        ),
+        "get_token_allowance" => json!(
+            chain
+                .get_token_allowance(
+                    param(params, "token")?,
+                    param(params, "owner")?,
+                    param(params, "spender")?
+                )
+                .await?
+        ),
+        "get_proof" => to_json(chain.get_proof(address()?, block()?).await?)?,
+        "call" => {
+            let to = param(params, "to")?;
+            let data = from_hex(&param::<String>(params, "data")?)?;
+            let overrides: CallOverrides = param(params, "overrides")?;
+            json!(to_hex(&chain.call(to, data, overrides, block()?).await?))
+        }
+        "estimate_gas" => {
+            let data = from_hex(&param::<String>(params, "data")?)?;
+            json!(chain.estimate_gas(param(params, "to")?, data).await)
+        }
+        "simulate_transaction" => {
+            let transaction = decode_transaction(&param::<String>(params, "transaction")?)?;
+            let simulation = chain.simulate_transaction(transaction, overrides()?, block()?);
+            to_json(simulation.await?)?
+        }
+        "simulate_bundle" => {
+            let transactions = param::<Vec<String>>(params, "transactions")?;
+            let transactions = (transactions.iter())
+                .map(|transaction| decode_transaction(transaction))
+                .collect::<Result<_>>()?;
+            let simulations = chain.simulate_bundle(transactions, overrides()?, block()?);
+            to_json(simulations.await?)?
+        }
+        "debug_trace_transaction" => to_json(chain.debug_trace_transaction(hash()?).await?)?,
+        "get_state_diff" => to_json(chain.get_state_diff(param(params, "block_hash")?).await?)?,
+        "chain_id" => json!(chain.chain_id().await),
+        "hash_algorithm" => to_json(chain.hash_algorithm().await)?,
+        "syncing" => json!(chain.syncing().await),
+        "gas_price" => json!(chain.gas_price().await),
+        "estimate_fee" => to_json(chain.estimate_fee().await?)?,
+        "get_chain_stats" => to_json(chain.get_chain_stats(param(params, "window")?).await?)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(result))
+}
+
+/// Argument `name` of `params`.
+fn param<T: DeserializeOwned>(params: &Value, name: &str) -> Result<T> {
+    serde_json::from_value(params[name].clone())
+        .map_err(|error| Error::new(format!("bad argument {name}: {error}")))
+}
+
+fn to_json(value: impl Serialize) -> Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>> {
+    hex::decode(s.trim_start_matches("0x")).map_err(|error| Error::new(format!("bad hex: {error}")))
+}
+
+fn decode_transaction(s: &str) -> Result<Transaction> {
+    Transaction::decode(&from_hex(s)?)
+}
+
+/// Transactions by sender and nonce, each as its hex encoding.
+fn encode_by_sender(transactions: &BySender) -> BTreeMap<Address, BTreeMap<u64, String>> {
+    (transactions.iter())
+        .map(|(sender, by_nonce)| {
+            let by_nonce = (by_nonce.iter())
+                .map(|(nonce, transaction)| (*nonce, to_hex(&transaction.encode())))
+                .collect();
+            (*sender, by_nonce)
+        })
+        .collect()
+}
+
+fn decode_by_sender(encoded: BTreeMap<Address, BTreeMap<u64, String>>) -> Result<BySender> {
+    (encoded.into_iter())
+        .map(|(sender, by_nonce)| {
+            let by_nonce = (by_nonce.into_iter())
+                .map(|(nonce, transaction)| Ok((nonce, decode_transaction(&transaction)?)))
+                .collect::<Result<_>>()?;
+            Ok((sender, by_nonce))
+        })
+        .collect()
+}
+
+/// A node reached over HTTP, through the `/rpc` endpoint. See the
+/// [module documentation](self).
+///
+/// The chain ID and hash algorithm, which never change, are asked once, by
+/// [`HttpBlockchain::connect`]. The trait's other infallible methods, `syncing`, `gas_price` and
+/// `estimate_gas`, cannot report a failed request: they log it and return `false` or zero.
+#[derive(Debug)]
+pub struct HttpBlockchain {
+    remote: Remote,
+    chain_id: u64,
+    algorithm: HashAlgorithm,
+}
+
+impl HttpBlockchain {
+    /// Reach the node through `remote`, asking it for its chain's ID and hash algorithm.
+    pub async fn connect(remote: Remote) -> Result<Self> {
+        let mut chain = Self {
+            remote,
+            chain_id: 0,
+            algorithm: HashAlgorithm::default(),
+        };
+        chain.chain_id = chain.rpc("chain_id", json!({})).await?;
+        chain.algorithm = chain.rpc("hash_algorithm", json!({})).await?;
+        Ok(chain)
+    }
+
+    /// The endpoints the node is reached through.
+    pub fn remote(&self) -> &Remote {
+        &self.remote
+    }
+
+    async fn rpc<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let target = format!("{PREFIX}{method}");
+        let body = params.to_string();
+        let mut response = self.remote.call("POST", &target, body.as_bytes()).await?;
+        serde_json::from_value(response["result"].take())
+            .map_err(|error| Error::new(format!("bad result from {target}: {error}")))
+    }
+
+    /// [`HttpBlockchain::rpc`] for the infallible methods, which log a failure and return the
+    /// default instead.
+    async fn rpc_or_default<T: DeserializeOwned + Default>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> T {
+        self.rpc(method, params).await.unwrap_or_else(|error| {
+            log::warn!("{method} failed at {}: {error}", self.remote);
+            T::default()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Blockchain for HttpBlockchain {
+    async fn get_block(&self, id: BlockId) -> Result<Option<Block>> {
+        let block: Option<String> = self.rpc("get_block", json!({"id": id})).await?;
+        (block.as_deref())
+            .map(|block| Block::decode(&from_hex(block)?, self.algorithm))
+            .transpose()
+    }
+
+    async fn get_header(&self, id: BlockId) -> Result<Option<Header>> {
+        let header: Option<String> = self.rpc("get_header", json!({"id": id})).await?;
+        let Some(header) = header else {
+            return Ok(None);
+        };
+        let bytes = from_hex(&header)?;
+        let mut reader = Reader::new(&bytes);
+        let header = Header::read_signed(&mut reader, self.algorithm)?;
+        reader.finish()?;
+        Ok(Some(header))
+    }
+
+    async fn get_raw_block(&self, hash: Hash) -> Result<Option<String>> {
+        self.rpc("get_raw_block", json!({"hash": hash})).await
+    }
+
+    async fn get_block_rewards(&self, id: BlockId) -> Result<BlockReward> {
+        self.rpc("get_block_rewards", json!({"id": id})).await
+    }
+
+    async fn get_transaction(&self, hash: Hash) -> Result<Option<Transaction>> {
+        let transaction: Option<String> =
+            self.rpc("get_transaction", json!({"hash": hash})).await?;
+        transaction.as_deref().map(decode_transaction).transpose()
+    }
+
+    async fn get_transaction_receipt(&self, hash: Hash) -> Result<Option<TransactionReceipt>> {
+        self.rpc("get_transaction_receipt", json!({"hash": hash}))
+            .await
+    }
+
+    async fn get_raw_transaction(&self, hash: Hash) -> Result<Option<String>> {
+        self.rpc("get_raw_transaction", json!({"hash": hash})).await
+    }
+
+    async fn send_transaction(&self, transaction: Transaction) -> Result<Hash> {
+        let transaction = to_hex(&transaction.encode());
+        self.rpc(SEND_TRANSACTION, json!({"transaction": transaction}))
+            .await
+    }
+
+    async fn get_transaction_status(&self, hash: Hash) -> Result<TransactionStatus> {
+        self.rpc("get_transaction_status", json!({"hash": hash}))
+            .await
+    }
+
+    async fn get_mempool_content(&self) -> Result<MempoolContent> {
+        let mut content: Value = self.rpc("get_mempool_content", json!({})).await?;
+        let mut group = |name: &str| -> Result<BySender> {
+            decode_by_sender(serde_json::from_value(content[name].take())?)
+        };
+        Ok(MempoolContent {
+            pending: group("pending")?,
+            queued: group("queued")?,
+        })
+    }
+
+    async fn get_mempool_status(&self) -> Result<MempoolStatus> {
+        self.rpc("get_mempool_status", json!({})).await
+    }
+
+    async fn get_balance(&self, address: Address) -> Result<u64> {
+        self.rpc("get_balance", json!({"address": address})).await
+    }
+
+    async fn get_balances(&self, addresses: &[Address]) -> Result<Vec<u64>> {
+        self.rpc("get_balances", json!({"addresses": addresses}))
+            .await
+    }
+
+    async fn get_balance_at(&self, address: Address, block: BlockId) -> Result<u64> {
+        let params = json!({"address": address, "block": block});
+        self.rpc("get_balance_at", params).await
+    }
+
+    async fn get_nonce(&self, address: Address) -> Result<u64> {
+        self.rpc("get_nonce", json!({"address": address})).await
+    }
+
+    async fn get_pending_nonce(&self, address: Address) -> Result<u64> {
+        self.rpc("get_pending_nonce", json!({"address": address}))
+            .await
+    }
+
+    async fn get_token_info(&self, token: Address) -> Result<Option<TokenInfo>> {
+        self.rpc("get_token_info", json!({"token": token})).await
+    }
+
+    async fn get_token_balance(&self, token: Address, holder: Address) -> Result<u64> {
+        let params = json!({"token": token, "holder": holder});
+        self.rpc("get_token_balance", params).await
+    }
+
+    async fn get_token_allowance(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+    ) -> Result<u64> {
+        let params = json!({"token": token, "owner": owner, "spender": spender});
+        self.rpc("get_token_allowance", params).await
+    }
+
+    async fn get_proof(&self, address: Address, block: BlockId) -> Result<Option<AccountProof>> {
+        let params = json!({"address": address, "block": block});
+        self.rpc("get_proof", params).await
+    }
+
+    async fn call(
+        &self,
+        to: Address,
+        data: Vec<u8>,
+        overrides: CallOverrides,
+        block: BlockId,
+    ) -> Result<Vec<u8>> {
+        let params =
+            json!({"to": to, "data": to_hex(&data), "overrides": overrides, "block": block});
+        from_hex(&self.rpc::<String>("call", params).await?)
+    }
+
+    async fn estimate_gas(&self, to: Address, data: Vec<u8>) -> u64 {
+        let params = json!({"to": to, "data": to_hex(&data)});
+        self.rpc_or_default("estimate_gas", params).await
+    }
+
+    async fn simulate_transaction(
+        &self,
+        transaction: Transaction,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Simulation> {
+        let transaction = to_hex(&transaction.encode());
+        let params = json!({"transaction": transaction, "overrides": overrides, "block": block});
+        self.rpc("simulate_transaction", params).await
+    }
+
+    async fn simulate_bundle(
+        &self,
+        transactions: Vec<Transaction>,
+        overrides: StateOverrides,
+        block: BlockId,
+    ) -> Result<Vec<Simulation>> {
+        let transactions: Vec<String> = (transactions.iter())
+            .map(|transaction| to_hex(&transaction.encode()))
+            .collect();
+        let params = json!({"transactions": transactions, "overrides": overrides, "block": block});
+        self.rpc("simulate_bundle", params).await
+    }
+
+    async fn debug_trace_transaction(&self, hash: Hash) -> Result<Option<Trace>> {
+        self.rpc("debug_trace_transaction", json!({"hash": hash}))
+            .await
+    }
+
+    async fn get_state_diff(&self, block_hash: Hash) -> Result<Option<StateDiff>> {
+        self.rpc("get_state_diff", json!({"block_hash": block_hash}))
+            .await
+    }
+
+    async fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    async fn hash_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    async fn syncing(&self) -> bool {
+        self.rpc_or_default("syncing", json!({})).await
+    }
+
+    async fn gas_price(&self) -> u64 {
+        self.rpc_or_default("gas_price", json!({})).await
+    }
+
+    async fn estimate_fee(&self) -> Result<FeeEstimate> {
+        self.rpc("estimate_fee", json!({})).await
+    }
+
+    async fn get_chain_stats(&self, window: u64) -> Result<ChainStats> {
+        self.rpc("get_chain_stats", json!({"window": window})).await
+    }
+}
+
+#[tokio::test]
+async fn test_http_blockchain() {
+    use crate::http::serve_locally;
+    use crate::testkit::{self, TestChain};
+    use crate::{Client, ErrorKind};
+    use std::time::Duration;
+
+    let blockhead = Arc::new(TestChain::new().blockhead);
+    let address = serve_locally(blockhead.clone()).await;
+    let remote = Remote::parse(&address.to_string()).unwrap();
+    let client = Client::new(HttpBlockchain::connect(remote).await.unwrap())
+        .with_poll_interval(Duration::from_millis(10));
+    let http = client.chain();
+    assert_eq!(http.chain_id().await, blockhead.chain_id().await);
+    assert_eq!(http.hash_algorithm().await, blockhead.config.hash);
+
+    let key = testkit::validator_key();
+    let validator = testkit::validator();
+    let to = Address([7; 32]);
+    let hash = client.transfer(&key, to, 1_000).await.unwrap();
+    assert_eq!(http.get_pending_nonce(validator).await.unwrap(), 1);
+    assert_eq!(
+        http.get_transaction_status(hash).await.unwrap(),
+        TransactionStatus::Pending
+    );
+    let content = http.get_mempool_content().await.unwrap();
+    let algorithm = blockhead.config.hash;
+    assert_eq!(
+        content.pending[&validator][&0].compute_hash(algorithm),
+        hash
+    );
+    assert_eq!(http.get_mempool_status().await.unwrap().pending, 1);
+
+    let block = blockhead.produce_block().unwrap();
+    let receipt = client
+        .wait_for_receipt(hash, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(receipt.block_hash, block.hash);
+    assert_eq!(http.get_balance(to).await.unwrap(), 1_000);
+    assert_eq!(
+        http.get_balances(&[to, validator]).await.unwrap(),
+        blockhead.get_balances(&[to, validator]).await.unwrap()
+    );
+    let id = BlockId::Hash(block.hash);
+    let fetched = http.get_block(id).await.unwrap().unwrap();
+    assert_eq!(fetched.encode(), block.encode());
+    assert_eq!(
+        http.get_header(id).await.unwrap(),
+        Some(block.header.clone())
+    );
+    let transaction = http.get_transaction(hash).await.unwrap().unwrap();
+    assert_eq!(transaction.compute_hash(algorithm), hash);
+    assert_eq!(
+        http.get_raw_block(block.hash).await.unwrap(),
+        blockhead.get_raw_block(block.hash).await.unwrap()
+    );
+    assert_eq!(
+        http.estimate_fee().await.unwrap(),
+        blockhead.estimate_fee().await.unwrap()
+    );
+    assert_eq!(http.gas_price().await, blockhead.gas_price().await);
+    assert!(!http.syncing().await);
+    assert!(http.get_block(BlockId::Number(9)).await.unwrap().is_none());
+
+    // The node's errors arrive as themselves.
+    let overdraft = testkit::transfer(validator, to, u64::MAX, 1);
+    let error = http.send_transaction(overdraft).await.unwrap_err();
+    let local = blockhead
+        .send_transaction(testkit::transfer(validator, to, u64::MAX, 1))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), local.kind());
+    assert_ne!(error.kind(), &ErrorKind::Other);
+    let error = http.rpc::<Value>("nothing", json!({})).await.unwrap_err();
+    assert_eq!(
+        error.message(),
+        "/rpc/nothing answered 404: no method nothing"
+    );
+}
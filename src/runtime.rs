@@ -0,0 +1,237 @@
+//! The threads a node runs on, which operators size for their hardware with the `runtime` table
+//! of the node config.
+//!
+//! The command line runs the node's async tasks, such as serving HTTP and following the mempool,
+//! on [`RuntimeConfig::worker_threads`] threads. Reading the database and executing contract
+//! code block the thread they run on, so the [`crate::Blockchain`] methods run them through
+//! [`block`]: on a worker of a multi-threaded runtime, the worker's other tasks move to a
+//! thread of the runtime's blocking pool, of at most [`RuntimeConfig::blocking_threads`], until
+//! the call returns. HTTP handlers and background tasks, which can hand the pool a handle to the
+//! node of their own, run such work there outright with `spawn_blocking`. Contract calls,
+//! simulations and traces, which can run far longer than a query, also wait for one of
+//! [`RuntimeConfig::vm_threads`] slots, so that however many arrive at once, they leave the rest
+//! of the machine to block production and queries.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// Threads running the node's async tasks. Unset, one per core.
+    pub worker_threads: Option<usize>,
+    /// The most threads blocked at once on the database or contract code. Unset, tokio's default
+    /// of 512.
+    pub blocking_threads: Option<usize>,
+    /// The most contract calls, simulations and traces running at once. Unset, one per core.
+    pub vm_threads: Option<usize>,
+}
+
+impl RuntimeConfig {
+    /// The runtime the command line runs a node on.
+    pub(crate) fn build(&self) -> Result<tokio::runtime::Runtime> {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all().thread_name("blockhead-worker");
+        if let Some(threads) = self.worker_threads {
+            builder.worker_threads(at_least_one("worker_threads", threads)?);
+        }
+        if let Some(threads) = self.blocking_threads {
+            builder.max_blocking_threads(at_least_one("blocking_threads", threads)?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+fn at_least_one(name: &str, threads: usize) -> Result<usize> {
+    match threads {
+        0 => Err(Error::new(format!("runtime.{name} must be at least 1"))),
+        threads => Ok(threads),
+    }
+}
+
+fn cores() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Run `f`, which blocks, without holding up the async tasks that share the calling thread. On a
+/// worker of a multi-threaded runtime, its other tasks move to another thread while `f` runs;
+/// anywhere else, `f` just runs.
+pub(crate) fn block<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// Run `f`, which blocks, on the blocking pool, for callers that can give it everything it needs
+/// to own, such as HTTP handlers with a clone of the node's `Arc`.
+#[cfg(feature = "http")]
+pub(crate) async fn spawn_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|error| Error::new(format!("task panicked: {error}")))?
+}
+
+/// The slots contract execution waits for. See [`RuntimeConfig::vm_threads`].
+pub(crate) struct VmSlots {
+    slots: Semaphore,
+}
+
+impl VmSlots {
+    pub(crate) fn new(config: &RuntimeConfig) -> Self {
+        let slots = config.vm_threads.unwrap_or_else(cores).max(1);
+        Self {
+            slots: Semaphore::new(slots),
+        }
+    }
+
+    /// Run `f`, which executes contract code, in a slot once one is free, as [`block`] does.
+    pub(crate) async fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _slot = self
+            .slots
+            .acquire()
+            .await
+            .expect("the slots are never closed");
+        block(f)
+    }
+}
+
+#[test]
+fn test_runtime() {
+    use crate::address::Address;
+    use crate::block::BlockId;
+    use crate::genesis::Genesis;
+    use crate::{Blockchain, Blockhead};
+    use std::sync::Arc;
+
+    let config = RuntimeConfig {
+        worker_threads: Some(2),
+        blocking_threads: Some(2),
+        vm_threads: Some(1),
+    };
+    let runtime = config.build().unwrap();
+    assert_eq!(runtime.metrics().num_workers(), 2);
+    let zero = RuntimeConfig {
+        worker_threads: Some(0),
+        ..Default::default()
+    };
+    assert!(zero.build().is_err());
+
+    let validator = Address([7; 32]);
+    let mut genesis = Genesis {
+        alloc: vec![(validator, 1_000)],
+        ..Default::default()
+    };
+    genesis.config.runtime = config;
+    let blockhead = Arc::new(Blockhead::with_genesis(":memory:", genesis).unwrap());
+    assert_eq!(blockhead.vm_slots.slots.available_permits(), 1);
+    // Queries and calls on the workers, several at once, run as they do anywhere else.
+    let balances = runtime.block_on(async {
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let blockhead = blockhead.clone();
+                tokio::spawn(async move {
+                    let data = Vec::new();
+                    let call = blockhead.call(validator, data, Default::default(), BlockId::Latest);
+                    call.await.unwrap();
+                    blockhead.get_balance(validator).await.unwrap()
+                })
+            })
+            .collect();
+        let mut balances = Vec::new();
+        for task in tasks {
+            balances.push(task.await.unwrap());
+        }
+        balances
+    });
+    assert_eq!(balances, [1_000; 8]);
+    assert_eq!(block(|| 5), 5);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_rpc_responsive_during_imports() {
+    use crate::block::BlockId;
+    use crate::chain::side_block;
+    use crate::http::{send, serve_locally};
+    use crate::pool::file_chain;
+    use crate::{import_queue, READER_POOL_SIZE};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    let (blockhead, path) = file_chain("responsive");
+    let blockhead = Arc::new(blockhead);
+    let genesis = blockhead.block(BlockId::Latest).unwrap().unwrap();
+    let mut blocks = vec![side_block(&blockhead, &genesis, genesis.timestamp + 1)];
+    for _ in 1..300 {
+        let parent = blocks.last().unwrap();
+        blocks.push(side_block(&blockhead, parent, parent.timestamp + 1));
+    }
+    let last = blocks.last().unwrap().hash;
+    let patience = Duration::from_secs(10);
+    // One worker, so a handler blocking it would hold up every other request.
+    let config = RuntimeConfig {
+        worker_threads: Some(1),
+        ..Default::default()
+    };
+    config.build().unwrap().block_on(async {
+        tokio::spawn(import_queue::run(blockhead.clone()));
+        let address = serve_locally(blockhead.clone()).await;
+        for block in blocks {
+            blockhead.submit_block(block).unwrap();
+        }
+
+        // Pages of blocks and other queries are answered while the imports run.
+        for _ in 0..20 {
+            let page = send(address, "GET /blocks?limit=1000 HTTP/1.1\r\n\r\n");
+            let (status, _) = timeout(patience, page).await.unwrap();
+            assert_eq!(status, 200);
+            let status = send(address, "GET /mempool/status HTTP/1.1\r\n\r\n");
+            assert_eq!(timeout(patience, status).await.unwrap().0, 200);
+        }
+
+        // With every reader taken, a page waits for one, but other requests do not wait for it.
+        let (taken, release, released) = {
+            let (taken_sender, taken) = std::sync::mpsc::channel();
+            let (release, release_receiver) = std::sync::mpsc::channel::<()>();
+            let released = Arc::new(AtomicBool::new(false));
+            let (holder, holder_released) = (blockhead.clone(), released.clone());
+            std::thread::spawn(move || {
+                let held: Vec<_> = (0..READER_POOL_SIZE).map(|_| holder.reader()).collect();
+                taken_sender.send(()).unwrap();
+                // Let go in the end regardless, so that a blocked worker fails the test rather
+                // than hanging it.
+                let _ = release_receiver.recv_timeout(patience);
+                holder_released.store(true, Ordering::SeqCst);
+                drop(held);
+            });
+            (taken, release, released)
+        };
+        taken.recv().unwrap();
+        let page = tokio::spawn(send(address, "GET /blocks HTTP/1.1\r\n\r\n"));
+        // Give the page time to reach the pool.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (status, _) = send(address, "GET /metrics/http HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert!(!released.load(Ordering::SeqCst));
+        assert!(!page.is_finished());
+        release.send(()).unwrap();
+        assert_eq!(timeout(patience, page).await.unwrap().unwrap().0, 200);
+
+        while blockhead.head().unwrap().hash != last {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+    drop(blockhead);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
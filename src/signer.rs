@@ -0,0 +1,140 @@
+//! Transaction signing, behind a trait so keys need not live in this process.
+//!
+//! [`Signer`] is implemented here for keys held in memory, such as those derived by
+//! [`crate::wallet`]. A remote signing service or a hardware module plugs in by implementing the
+//! same three methods; nothing that signs transactions needs the private key itself.
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::hash::{Hash, HashAlgorithm};
+use crate::wallet::ExtendedKey;
+use k256::ecdsa::{RecoveryId, SigningKey, VerifyingKey};
+
+/// A recoverable secp256k1 signature over a 32-byte hash: `r`, `s` and the recovery id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 65]);
+
+impl Signature {
+    /// The address, on a chain that hashes with `algorithm`, of the key that signed `hash`.
+    pub fn recover(&self, hash: Hash, algorithm: HashAlgorithm) -> Result<Address> {
+        let signature = k256::ecdsa::Signature::from_slice(&self.0[..64])
+            .map_err(|error| Error::new(format!("malformed signature: {error}")))?;
+        let recovery_id = RecoveryId::from_byte(self.0[64])
+            .ok_or_else(|| Error::new(format!("bad recovery id {}", self.0[64])))?;
+        let key = VerifyingKey::recover_from_prehash(&hash.0, &signature, recovery_id)
+            .map_err(|error| Error::new(format!("cannot recover signer: {error}")))?;
+        Ok(Address::from_public_key(&key, algorithm))
+    }
+}
+
+pub trait Signer: Send + Sync {
+    fn public_key(&self) -> Result<VerifyingKey>;
+
+    fn sign_hash(&self, hash: Hash) -> Result<Signature>;
+
+    /// The address of the key on a chain that hashes with `algorithm`.
+    fn address(&self, algorithm: HashAlgorithm) -> Result<Address> {
+        Ok(Address::from_public_key(&self.public_key()?, algorithm))
+    }
+}
+
+impl Signer for SigningKey {
+    fn public_key(&self) -> Result<VerifyingKey> {
+        Ok(*self.verifying_key())
+    }
+
+    fn sign_hash(&self, hash: Hash) -> Result<Signature> {
+        let (signature, recovery_id) = self
+            .sign_prehash_recoverable(&hash.0)
+            .map_err(|error| Error::new(format!("signing failed: {error}")))?;
+        let mut bytes = [0; 65];
+        bytes[..64].copy_from_slice(&signature.to_bytes());
+        bytes[64] = recovery_id.to_byte();
+        Ok(Signature(bytes))
+    }
+}
+
+impl Signer for ExtendedKey {
+    fn public_key(&self) -> Result<VerifyingKey> {
+        self.key.public_key()
+    }
+
+    fn sign_hash(&self, hash: Hash) -> Result<Signature> {
+        self.key.sign_hash(hash)
+    }
+}
+
+#[test]
+fn test_sign_and_recover() {
+    let algorithm = HashAlgorithm::default();
+    let key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+    let signer: &dyn Signer = &key;
+    let hash = Hash::digest_of("message");
+    let signature = signer.sign_hash(hash).unwrap();
+    assert_eq!(
+        signature.recover(hash, algorithm).unwrap(),
+        signer.address(algorithm).unwrap()
+    );
+    assert_ne!(
+        signature
+            .recover(Hash::digest_of("other"), algorithm)
+            .unwrap(),
+        signer.address(algorithm).unwrap()
+    );
+    let mut bad = signature;
+    bad.0[64] = 9;
+    assert!(bad.recover(hash, algorithm).is_err());
+}
+
+#[tokio::test]
+async fn test_signed_transactions() {
+    use crate::genesis::{ChainConfig, Genesis};
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::wallet::{self, Wallet};
+    use crate::{Blockchain, Blockhead};
+
+    let algorithm = HashAlgorithm::default();
+    let wallet = Wallet::from_mnemonic(&wallet::mnemonic_from_entropy(&[3; 16]), "").unwrap();
+    let account = wallet.account(0).unwrap();
+    let sender = account.address(algorithm);
+    let genesis = Genesis {
+        alloc: vec![(sender, 100_000)],
+        validators: vec![(sender, 100)],
+        config: ChainConfig {
+            require_signatures: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let blockhead = Blockhead::with_genesis(":memory:", genesis)
+        .unwrap()
+        .with_proposer_key(wallet.account(0).unwrap())
+        .unwrap();
+    let mut transfer = Transaction {
+        to_address: Some(Address([8; 32])),
+        value: 5,
+        gas_price: 1,
+        ..testkit::transaction(Default::default(), sender, 0)
+    };
+    assert!(blockhead.send_transaction(transfer.clone()).await.is_err());
+    let chain_id = blockhead.chain_id().await;
+    let other = wallet.account(1).unwrap();
+    assert!(transfer.sign(&other, chain_id, algorithm).is_err());
+    transfer.signatures = vec![other
+        .sign_hash(transfer.signing_hash(chain_id, algorithm))
+        .unwrap()];
+    assert!(blockhead.send_transaction(transfer.clone()).await.is_err());
+    // A signature made for another chain does not authorize the sender here.
+    transfer.sign(&account, chain_id + 1, algorithm).unwrap();
+    let error = blockhead.send_transaction(transfer.clone()).await;
+    assert!(error.unwrap_err().message().contains("another chain"));
+
+    let signer: Box<dyn Signer> = Box::new(account);
+    transfer.sign(signer.as_ref(), chain_id, algorithm).unwrap();
+    let hash = blockhead.send_transaction(transfer.clone()).await.unwrap();
+    blockhead.produce_block().unwrap();
+    let stored = blockhead.get_transaction(hash).await.unwrap().unwrap();
+    assert_eq!(stored.signatures, transfer.signatures);
+    let decoded = Transaction::decode(&transfer.encode()).unwrap();
+    assert_eq!(decoded.compute_hash(algorithm), hash);
+}
@@ -0,0 +1,316 @@
+//! Dry runs of transactions against the chain's state, for wallet previews and gas estimates.
+use crate::address::Address;
+use crate::block::BlockId;
+use crate::error::{Error, Result};
+use crate::execution::{self, StateOverrides};
+use crate::state::{StateDiff, StateOverlay};
+use crate::transaction::Transaction;
+use crate::{Blockhead, Log};
+use serde::{Deserialize, Serialize};
+
+/// What a transaction would do if included, as [`crate::execution::ExecutionOutcome`] would
+/// record it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Simulation {
+    /// False when contract code would revert or fail.
+    pub status: bool,
+    pub gas_used: u64,
+    /// The address of the contract a deployment would create.
+    pub contract_address: Option<Address>,
+    /// The data contract code would return, or its revert reason.
+    #[serde(with = "crate::trace::hex_bytes")]
+    pub return_data: Vec<u8>,
+    pub logs: Vec<Log>,
+    /// The changes to state, including the sender's fee. The proposer's reward is left out.
+    pub state_diff: StateDiff,
+}
+
+impl Blockhead {
+    /// Apply `transaction` to the state after block `id`, with `overrides` written over it, and
+    /// report what it did, committing nothing. An unsigned transaction is simulated as if its
+    /// sender had signed it, so wallets can preview a transaction before signing it. A
+    /// transaction that could not be included, such as one with the wrong nonce, is an error.
+    pub(crate) fn simulate(
+        &self,
+        transaction: &Transaction,
+        overrides: &StateOverrides,
+        id: BlockId,
+    ) -> Result<Simulation> {
+        let transactions = std::slice::from_ref(transaction);
+        let mut simulations = self.simulate_many(transactions, overrides, id)?;
+        Ok(simulations.remove(0))
+    }
+
+    /// Simulate `transactions` one after another, each seeing the effects of those before it,
+    /// as [`Blockhead::simulate`] does one. Each simulation's state diff holds only the changes
+    /// its own transaction made, so the overrides are not among them. If any transaction could
+    /// not be included, the bundle is an error.
+    pub(crate) fn simulate_many(
+        &self,
+        transactions: &[Transaction],
+        overrides: &StateOverrides,
+        id: BlockId,
+    ) -> Result<Vec<Simulation>> {
+        let mut signers = Vec::new();
+        for transaction in transactions {
+            signers.push(if transaction.signatures.is_empty() {
+                vec![transaction.from_address]
+            } else {
+                transaction.signers(self.config.chain_id, self.config.hash)?
+            });
+        }
+        self.query_at(id, |connection| {
+            let mut state = StateOverlay::new(connection);
+            execution::apply_overrides(&mut state, overrides)?;
+            let mut simulations = Vec::new();
+            let mut before = state.diff();
+            for (i, (transaction, signers)) in transactions.iter().zip(&signers).enumerate() {
+                let outcome =
+                    execution::execute_transaction(&mut state, &self.config, transaction, signers)
+                        .map_err(|error| {
+                            Error::new(format!("transaction {i} of the bundle failed: {error}"))
+                        })?;
+                let after = state.diff();
+                simulations.push(Simulation {
+                    status: outcome.status,
+                    gas_used: outcome.gas_used,
+                    contract_address: outcome.contract_address,
+                    return_data: outcome.return_data,
+                    logs: outcome.logs,
+                    state_diff: after.since(&before),
+                });
+                before = after;
+            }
+            Ok(simulations)
+        })
+    }
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_simulate_transaction() {
+    use crate::hash::Hash;
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use crate::transaction::TransactionKind;
+    use crate::vm::{opcode::*, push};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    // Log the call data word as a topic, with no data.
+    let emitter = [
+        push(0),
+        vec![CALLDATALOAD],
+        push(24),
+        vec![MSTORE],
+        push(1),
+        push(0),
+        push(0),
+        push(0),
+        vec![LOG, STOP],
+    ]
+    .concat();
+    let reverter = [
+        push(99),
+        push(0),
+        vec![MSTORE],
+        push(8),
+        push(0),
+        vec![REVERT],
+    ]
+    .concat();
+    let chain = TestChain::new();
+    let validator = chain.validator;
+    let transaction = |to_address, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        data: data.into(),
+        gas_limit: 100_000,
+        gas_price: 1,
+        ..testkit::transaction(TransactionKind::Transfer, validator, nonce)
+    };
+    chain.send(transaction(None, emitter, 0)).await;
+    chain.send(transaction(None, reverter, 1)).await;
+    chain.produce();
+    let (emitter, reverter) = (
+        Address::for_contract(validator, 0, algorithm),
+        Address::for_contract(validator, 1, algorithm),
+    );
+    let balance = chain.balance(validator);
+
+    let call = transaction(Some(emitter), 5u64.to_be_bytes().to_vec(), 2);
+    let simulation = chain
+        .blockhead
+        .simulate_transaction(call.clone(), Default::default(), BlockId::Latest)
+        .await
+        .unwrap();
+    assert!(simulation.status);
+    let mut topic = [0; 32];
+    topic[31] = 5;
+    assert_eq!(simulation.logs.len(), 1);
+    assert_eq!(simulation.logs[0].topics, vec![Hash(topic)]);
+    let [sender] = simulation.state_diff.accounts.as_slice() else {
+        panic!("expected only the sender to change");
+    };
+    assert_eq!(sender.address, validator);
+    assert_eq!(sender.after.nonce, 3);
+    assert_eq!(sender.after.balance, balance - simulation.gas_used);
+    // Nothing was committed.
+    assert_eq!(chain.blockhead.get_nonce(validator).await.unwrap(), 2);
+    chain.assert_balance(validator, balance);
+
+    let simulation = chain
+        .blockhead
+        .simulate_transaction(
+            transaction(Some(reverter), vec![], 2),
+            Default::default(),
+            BlockId::Latest,
+        )
+        .await
+        .unwrap();
+    assert!(!simulation.status);
+    assert_eq!(simulation.return_data, 99u64.to_be_bytes());
+    assert!(simulation.logs.is_empty());
+
+    // Against the pending block, the call must come after the transactions waiting.
+    chain.send(call.clone()).await;
+    let pending =
+        chain
+            .blockhead
+            .simulate_transaction(call.clone(), Default::default(), BlockId::Pending);
+    assert!(pending.await.is_err());
+    let next = transaction(Some(emitter), vec![], 3);
+    let pending = chain
+        .blockhead
+        .simulate_transaction(next, Default::default(), BlockId::Pending);
+    assert!(pending.await.unwrap().status);
+}
+
+#[tokio::test]
+async fn test_simulate_bundle() {
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use crate::Blockchain;
+
+    let chain = TestChain::new();
+    let validator = chain.validator;
+    let (alice, bob) = (Address([8; 32]), Address([9; 32]));
+    let transfer = |from_address, to_address, value, nonce| {
+        testkit::transfer(from_address, to_address, value, nonce)
+    };
+    // Alice can only pass on what the validator sends her first, and then sends it all back.
+    let bundle = vec![
+        transfer(validator, alice, 100, 0),
+        transfer(alice, bob, 60, 0),
+        transfer(alice, validator, 40, 1),
+    ];
+    let simulations = chain
+        .blockhead
+        .simulate_bundle(bundle.clone(), Default::default(), BlockId::Latest)
+        .await
+        .unwrap();
+    assert!(simulations.iter().all(|simulation| simulation.status));
+    let changes = |i: usize| -> Vec<(Address, u64, u64)> {
+        simulations[i]
+            .state_diff
+            .accounts
+            .iter()
+            .map(|diff| (diff.address, diff.before.balance, diff.after.balance))
+            .collect()
+    };
+    let balance = chain.balance(validator);
+    assert_eq!(
+        changes(0),
+        vec![(alice, 0, 100), (validator, balance, balance - 100)]
+    );
+    assert_eq!(changes(1), vec![(alice, 100, 40), (bob, 0, 60)]);
+    assert_eq!(
+        changes(2),
+        vec![(alice, 40, 0), (validator, balance - 100, balance - 60)]
+    );
+    chain.assert_balance(alice, 0);
+
+    // Out of order, alice has nothing to send.
+    let error = chain
+        .blockhead
+        .simulate_bundle(vec![bundle[1].clone()], Default::default(), BlockId::Latest)
+        .await
+        .unwrap_err();
+    assert!(error.message().contains("transaction 0 of the bundle"));
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_state_overrides() {
+    use crate::execution::{AccountOverride, CallOverrides};
+    use crate::testkit;
+    use crate::testkit::TestChain;
+    use crate::vm::{opcode::*, push};
+    use crate::Blockchain;
+
+    let chain = TestChain::new();
+    let (alice, bob, contract) = (Address([8; 32]), Address([9; 32]), Address([10; 32]));
+    // Return storage slot 0.
+    let code = [
+        push(0),
+        vec![SLOAD],
+        push(0),
+        vec![MSTORE],
+        push(8),
+        push(0),
+        vec![RETURN],
+    ]
+    .concat();
+    let overrides = StateOverrides::from([
+        (
+            alice,
+            AccountOverride {
+                balance: Some(1_000),
+                nonce: Some(5),
+                ..Default::default()
+            },
+        ),
+        (
+            contract,
+            AccountOverride {
+                code: Some(code),
+                storage: [(0, 42)].into(),
+                ..Default::default()
+            },
+        ),
+    ]);
+
+    let call = CallOverrides {
+        state: overrides.clone(),
+        ..Default::default()
+    };
+    let result = chain
+        .blockhead
+        .call(contract, vec![], call, BlockId::Latest)
+        .await;
+    assert_eq!(result.unwrap(), 42u64.to_be_bytes());
+    let result = chain
+        .blockhead
+        .call(contract, vec![], Default::default(), BlockId::Latest)
+        .await;
+    assert!(result.unwrap().is_empty());
+
+    // Alice can send what she does not have, and her diff starts from the overridden state.
+    let transfer = testkit::transfer(alice, bob, 600, 5);
+    let simulation = chain
+        .blockhead
+        .simulate_transaction(transfer.clone(), overrides, BlockId::Latest)
+        .await
+        .unwrap();
+    let diff = &simulation.state_diff.accounts;
+    assert_eq!(diff.len(), 2);
+    assert_eq!((diff[0].address, diff[0].before.balance), (alice, 1_000));
+    assert_eq!((diff[0].after.balance, diff[0].after.nonce), (400, 6));
+    assert_eq!((diff[1].address, diff[1].after.balance), (bob, 600));
+    let unfunded =
+        chain
+            .blockhead
+            .simulate_transaction(transfer, Default::default(), BlockId::Latest);
+    assert!(unfunded.await.is_err());
+    chain.assert_balance(alice, 0);
+}
@@ -0,0 +1,185 @@
+//! Fast sync from a snapshot of the chain at its finalized checkpoint.
+//!
+//! A snapshot is a copy of a node's database rewound to the finalized checkpoint, without side
+//! branches or traces. A new node starts from it instead of executing every block since
+//! genesis, then imports the blocks above the checkpoint as usual, which finalize the checkpoint
+//! again. The receiving node trusts nothing in the file but the checkpoint hash it was given out
+//! of band: it checks that the stored chain links that checkpoint back to its own genesis block
+//! and that the accounts match the checkpoint's state root.
+use crate::block::Header;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::finality;
+use crate::genesis::Genesis;
+use crate::hash::Hash;
+use crate::proof;
+use crate::state;
+use crate::Blockhead;
+use std::path::Path;
+
+impl Blockhead {
+    /// Write a snapshot of the chain at the finalized checkpoint to `path`, which must not exist,
+    /// and return the checkpoint's header for receivers to check it against.
+    pub(crate) fn export_snapshot(&self, path: &Path) -> Result<Header> {
+        if path.exists() {
+            return Err(Error::new(format!("{} already exists", path.display())));
+        }
+        // Hold off writers so that the copy and the finalized checkpoint read below agree.
+        let _guard = self.write_lock.lock().unwrap();
+        let finalized = self.finalized()?;
+        db::copy_to(&self.connection, path)?;
+        let copy = sqlite::Connection::open(path)?;
+        db::transaction(&copy, || {
+            let mut head =
+                db::read_head(&copy)?.ok_or_else(|| Error::new("snapshot copy has no blocks"))?;
+            while head.number > finalized.number {
+                state::revert_block(&copy, head.hash)?;
+                db::set_canonical(&copy, head.hash, false)?;
+                head = db::read_block(&copy, head.parent_hash)?
+                    .ok_or_else(|| Error::new(format!("missing ancestor {}", head.parent_hash)))?;
+            }
+            db::delete_epochs_after(&copy, self.config.staking.epoch_of(finalized.number))?;
+            finality::unfinalize_unattested(&copy, &self.config.staking)?;
+            db::delete_side_branches_and_traces(&copy)?;
+            while !db::count_orphans(&copy)?.is_empty() {
+                db::delete_orphans(&copy)?;
+            }
+            Ok(())
+        })?;
+        db::compact(&copy)?;
+        log::info!(
+            "exported snapshot at block {} ({}) to {}",
+            finalized.number,
+            finalized.hash,
+            path.display()
+        );
+        Ok(finalized.header)
+    }
+
+    /// Start a node at `path`, which must not exist, from the snapshot at `snapshot`, trusting
+    /// only that `checkpoint` is the hash of a finalized block of the chain that starts at
+    /// `genesis`.
+    pub(crate) fn from_snapshot(
+        snapshot: &Path,
+        path: &Path,
+        genesis: Genesis,
+        checkpoint: Hash,
+    ) -> Result<Self> {
+        if path.exists() {
+            return Err(Error::new(format!("{} already exists", path.display())));
+        }
+        std::fs::copy(snapshot, path)?;
+        let genesis_hash = genesis.block().hash;
+        let result = Self::with_genesis(path, genesis).and_then(|blockhead| {
+            blockhead.verify_snapshot(genesis_hash, checkpoint)?;
+            Ok(blockhead)
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    fn verify_snapshot(&self, genesis_hash: Hash, checkpoint: Hash) -> Result<()> {
+        let head = self.head()?;
+        if head.hash != checkpoint {
+            return Err(Error::new(format!(
+                "snapshot is at block {}, not the trusted checkpoint {checkpoint}",
+                head.hash
+            )));
+        }
+        let first = db::read_canonical_header(&self.connection, 0)?;
+        if first.map(|header| header.hash) != Some(genesis_hash) {
+            return Err(Error::new(
+                "snapshot is of a chain with another genesis block",
+            ));
+        }
+        // Checks that every block hash matches its contents and that the chain links the
+        // checkpoint to genesis.
+        let report = self.check_integrity()?;
+        if let Some(problem) = report.problems.first() {
+            return Err(Error::new(format!("snapshot is damaged: {problem}")));
+        }
+        let state_root = proof::state_root(&db::read_accounts(&self.connection)?, self.config.hash);
+        if state_root != head.state_root {
+            return Err(Error::new(format!(
+                "snapshot accounts have root {state_root}, but the checkpoint commits to {}",
+                head.state_root
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_sync() {
+    use crate::address::Address;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let transaction = |kind, to_address, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        value: if kind == TransactionKind::Transfer {
+            5
+        } else {
+            0
+        },
+        data: data.into(),
+        gas_limit: 21_512,
+        ..testkit::transaction(kind, validator, nonce)
+    };
+    let transfer = transaction(TransactionKind::Transfer, Some(Address([8; 32])), vec![], 0);
+    blockhead.send_transaction(transfer).await.unwrap();
+    blockhead.produce_block().unwrap();
+    let checkpoint = blockhead.produce_block().unwrap();
+    let attestation = testkit::attestation(
+        checkpoint.hash,
+        1,
+        blockhead.config.chain_id,
+        blockhead.config.hash,
+    );
+    blockhead.send_transaction(attestation).await.unwrap();
+    let block3 = blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.finalized().unwrap().hash, checkpoint.hash);
+
+    let dir = std::env::temp_dir().join(format!("blockhead-snapshot-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let snapshot = dir.join("snapshot.db");
+    let header = blockhead.export_snapshot(&snapshot).unwrap();
+    assert_eq!(header, checkpoint.header);
+
+    let genesis = || Genesis {
+        alloc: vec![(validator, 100_000)],
+        validators: vec![(validator, 100)],
+        config: blockhead.config.clone(),
+        ..Default::default()
+    };
+    let wrong = Blockhead::from_snapshot(&snapshot, &dir.join("wrong.db"), genesis(), block3.hash);
+    assert!(wrong.is_err());
+    assert!(!dir.join("wrong.db").exists());
+
+    let synced =
+        Blockhead::from_snapshot(&snapshot, &dir.join("node.db"), genesis(), checkpoint.hash)
+            .unwrap()
+            .with_clock(std::sync::Arc::new(crate::clock::ManualClock::new(0)));
+    assert_eq!(synced.head().unwrap().hash, checkpoint.hash);
+    assert_eq!(synced.get_balance(Address([8; 32])).await.unwrap(), 5);
+    synced.import_block(&block3).unwrap();
+    assert_eq!(synced.head().unwrap().hash, block3.hash);
+    assert_eq!(synced.finalized().unwrap().hash, checkpoint.hash);
+    assert_eq!(synced.get_nonce(validator).await.unwrap(), 2);
+
+    // A snapshot whose accounts were edited no longer matches the checkpoint's state root.
+    let tampered = sqlite::Connection::open(&snapshot).unwrap();
+    tampered
+        .execute("UPDATE account SET balance = balance + 1")
+        .unwrap();
+    drop(tampered);
+    let result =
+        Blockhead::from_snapshot(&snapshot, &dir.join("bad.db"), genesis(), checkpoint.hash);
+    assert!(result.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
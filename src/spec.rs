@@ -0,0 +1,421 @@
+//! Chain specifications and the data directories `blockhead init` creates from them.
+//!
+//! A chain spec is a [`Genesis`] written as TOML: the initial accounts and the protocol
+//! parameters every node of the chain must agree on. `dev` and `test` are built in; any other
+//! spec is read from a file. A data directory holds the spec as `genesis.toml`, the settings
+//! local to the node as `config.toml`, and the database as `chain.db`.
+//!
+//! Both presets fund accounts of the well-known [`dev_wallet`], whose seed phrase is public.
+//! They are for local development and testing only.
+use crate::address::Address;
+use crate::archive::{PruningConfig, StateHistory};
+use crate::cold::ColdStorageConfig;
+use crate::error::{Error, Result};
+use crate::faucet::FaucetConfig;
+use crate::fee::FeeConfig;
+use crate::genesis::Genesis;
+use crate::hash::decode_hex32;
+use crate::http::HttpConfig;
+use crate::import_queue::ImportConfig;
+use crate::indexer::IndexerConfig;
+use crate::mempool::MempoolConfig;
+use crate::peers::PeerConfig;
+use crate::reward::RewardConfig;
+use crate::runtime::RuntimeConfig;
+use crate::trace::TraceConfig;
+use crate::wallet::{self, ExtendedKey, Wallet};
+use crate::Blockhead;
+use k256::ecdsa::SigningKey;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What each funded dev account starts with.
+pub(crate) const DEV_BALANCE: u64 = 1_000_000_000_000;
+/// What each dev validator starts with at stake.
+pub(crate) const DEV_STAKE: u64 = 1_000_000;
+/// The chain ID of both presets, which no public network uses.
+pub(crate) const DEV_CHAIN_ID: u64 = 1337;
+/// How many dev accounts the `dev` preset funds.
+pub(crate) const DEV_ACCOUNTS: u32 = 10;
+/// How many dev accounts the `test` preset funds and stakes.
+const TEST_VALIDATORS: u32 = 4;
+
+/// The seed phrase of all-zero entropy, which anyone can derive.
+pub(crate) fn dev_mnemonic() -> String {
+    wallet::mnemonic_from_entropy(&[0; 16])
+}
+
+pub(crate) fn dev_wallet() -> Wallet {
+    Wallet::from_mnemonic(&dev_mnemonic(), "").expect("the dev seed phrase is valid")
+}
+
+/// The built-in spec called `name`:
+///
+/// - `dev`: dev accounts 0 to 9 funded, account 0 as the single validator, and unsigned
+///   transactions allowed.
+/// - `test`: dev accounts 0 to 3 as equally staked validators, signatures required as by default
+///   and a block subsidy, closer to how a public network runs.
+pub(crate) fn preset(name: &str) -> Result<Option<Genesis>> {
+    let wallet = dev_wallet();
+    let mut genesis = Genesis::default();
+    genesis.config.chain_id = DEV_CHAIN_ID;
+    let algorithm = genesis.config.hash;
+    let dev_account = |index| -> Result<Address> { Ok(wallet.account(index)?.address(algorithm)) };
+    match name {
+        "dev" => {
+            for index in 0..DEV_ACCOUNTS {
+                genesis.alloc.push((dev_account(index)?, DEV_BALANCE));
+            }
+            genesis.validators = vec![(dev_account(0)?, DEV_STAKE)];
+            genesis.config.require_signatures = false;
+        }
+        "test" => {
+            for index in 0..TEST_VALIDATORS {
+                let address = dev_account(index)?;
+                genesis.alloc.push((address, DEV_BALANCE));
+                genesis.validators.push((address, DEV_STAKE));
+            }
+            genesis.config.reward = RewardConfig {
+                block_subsidy: 1_000,
+            };
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(genesis))
+}
+
+/// The dev wallet accounts among `genesis`'s validators, with their indexes, whose keys propose
+/// the blocks of a preset chain.
+pub(crate) fn dev_validators(genesis: &Genesis) -> Result<Vec<(u32, ExtendedKey)>> {
+    let mut accounts = funded_dev_accounts(genesis)?;
+    accounts.retain(|(_, account)| {
+        let address = account.address(genesis.config.hash);
+        (genesis.validators.iter()).any(|(validator, _)| *validator == address)
+    });
+    Ok(accounts)
+}
+
+/// Open the chain of `genesis` at `db_filename`, as [`Blockhead::with_genesis`], proposing
+/// blocks for the validators that are dev accounts. See [`dev_validators`].
+pub(crate) fn open_dev(db_filename: impl AsRef<Path>, genesis: Genesis) -> Result<Blockhead> {
+    let validators = dev_validators(&genesis)?;
+    let mut blockhead = Blockhead::with_genesis(db_filename, genesis)?;
+    for (_, key) in validators {
+        blockhead = blockhead.with_proposer_key(key)?;
+    }
+    Ok(blockhead)
+}
+
+/// The dev wallet accounts `genesis` funds, with their indexes: those from account 0 up to the
+/// first that it does not fund.
+pub(crate) fn funded_dev_accounts(genesis: &Genesis) -> Result<Vec<(u32, ExtendedKey)>> {
+    let wallet = dev_wallet();
+    let mut accounts = Vec::new();
+    for index in 0.. {
+        let account = wallet.account(index)?;
+        if !genesis
+            .alloc
+            .iter()
+            .any(|(address, _)| *address == account.address(genesis.config.hash))
+        {
+            break;
+        }
+        accounts.push((index, account));
+    }
+    Ok(accounts)
+}
+
+/// The preset called `chain`, or else the spec in the file at that path.
+pub(crate) fn load(chain: &str) -> Result<Genesis> {
+    if let Some(genesis) = preset(chain)? {
+        return Ok(genesis);
+    }
+    let contents = std::fs::read_to_string(chain)
+        .map_err(|error| Error::new(format!("no preset or spec file {chain:?}: {error}")))?;
+    Ok(toml::from_str(&contents)?)
+}
+
+/// Settings local to one node, which may differ between nodes of the same chain.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct NodeConfig {
+    /// Whether to keep every past state. Comes first, as TOML needs plain values before tables.
+    pub history: StateHistory,
+    /// The most detailed log messages to emit, such as `info` or `debug`. Unset, the level is
+    /// left as the process started.
+    pub log_level: Option<String>,
+    /// Files holding the hex private keys of the validators the node proposes blocks for,
+    /// relative to the data directory. See [`Blockhead::with_proposer_key`].
+    pub proposer_keys: Vec<PathBuf>,
+    pub trace: TraceConfig,
+    pub fee: FeeConfig,
+    pub indexer: IndexerConfig,
+    pub http: HttpConfig,
+    pub mempool: MempoolConfig,
+    pub pruning: PruningConfig,
+    pub import: ImportConfig,
+    pub peers: PeerConfig,
+    pub faucet: FaucetConfig,
+    pub cold: ColdStorageConfig,
+    pub runtime: RuntimeConfig,
+}
+
+pub(crate) struct DataDir {
+    pub path: PathBuf,
+}
+
+impl DataDir {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub(crate) fn genesis_path(&self) -> PathBuf {
+        self.path.join("genesis.toml")
+    }
+
+    pub(crate) fn config_path(&self) -> PathBuf {
+        self.path.join("config.toml")
+    }
+
+    pub(crate) fn db_path(&self) -> PathBuf {
+        self.path.join("chain.db")
+    }
+
+    /// Where the node serves its HTTP API to local tools. See [`crate::HttpConfig::ipc`].
+    pub(crate) fn ipc_path(&self) -> PathBuf {
+        self.path.join("blockhead.ipc")
+    }
+
+    /// Write `genesis` and a default node config to the directory, which need not exist but must
+    /// not hold a chain yet, and create the database. The node config turns the faucet on for
+    /// chains with the presets' chain ID, whose funds are worthless, and proposes blocks with
+    /// the keys of the validators that are dev accounts, written beside it.
+    pub(crate) fn init(&self, genesis: &Genesis) -> Result<Blockhead> {
+        if self.genesis_path().exists() || self.db_path().exists() {
+            return Err(Error::new(format!(
+                "{} already holds a chain",
+                self.path.display()
+            )));
+        }
+        std::fs::create_dir_all(&self.path)?;
+        std::fs::write(self.genesis_path(), toml::to_string(genesis)?)?;
+        if !self.config_path().exists() {
+            let mut node = NodeConfig::default();
+            if genesis.config.chain_id == DEV_CHAIN_ID {
+                node.faucet.enabled = true;
+                for (index, key) in dev_validators(genesis)? {
+                    let file = PathBuf::from(format!("validator-{index}.key"));
+                    std::fs::write(self.path.join(&file), hex::encode(key.key.to_bytes()))?;
+                    node.proposer_keys.push(file);
+                }
+            }
+            std::fs::write(self.config_path(), toml::to_string(&node)?)?;
+        }
+        self.open()
+    }
+
+    pub(crate) fn node_config(&self) -> Result<NodeConfig> {
+        Ok(toml::from_str(&read(&self.config_path())?)?)
+    }
+
+    /// Open the chain in the directory with its genesis and node config.
+    pub(crate) fn open(&self) -> Result<Blockhead> {
+        self.open_with(self.genesis()?)
+    }
+
+    /// Open the chain in the directory with `genesis`, its own with some settings changed,
+    /// proposing blocks with the keys the node config names.
+    pub(crate) fn open_with(&self, genesis: Genesis) -> Result<Blockhead> {
+        let mut blockhead = Blockhead::with_genesis(self.db_path(), genesis)?;
+        for file in self.node_config()?.proposer_keys {
+            let path = self.path.join(file);
+            let bytes = decode_hex32(read(&path)?.trim())?;
+            let key = SigningKey::from_slice(&bytes)
+                .map_err(|error| Error::new(format!("bad key in {}: {error}", path.display())))?;
+            blockhead = blockhead.with_proposer_key(key)?;
+        }
+        Ok(blockhead)
+    }
+
+    /// [`DataDir::open`], failing rather than creating the database if the directory has none.
+    pub(crate) fn open_existing(&self) -> Result<Blockhead> {
+        if !self.db_path().is_file() {
+            return Err(Error::new(format!(
+                "{} holds no chain",
+                self.path.display()
+            )));
+        }
+        self.open()
+    }
+
+    /// The directory's genesis, with the node config in place of the defaults for the settings
+    /// local to each node. Applies the node config's log level.
+    pub(crate) fn genesis(&self) -> Result<Genesis> {
+        let mut genesis: Genesis = toml::from_str(&read(&self.genesis_path())?)?;
+        let node = self.node_config()?;
+        apply_log_level(node.log_level.as_deref())?;
+        genesis.config.trace = node.trace;
+        genesis.config.fee = node.fee;
+        genesis.config.indexer = node.indexer;
+        genesis.config.http = node.http;
+        genesis.config.mempool = node.mempool;
+        genesis.config.history = node.history;
+        genesis.config.pruning = node.pruning;
+        genesis.config.import = node.import;
+        genesis.config.peers = node.peers;
+        genesis.config.faucet = node.faucet;
+        genesis.config.cold = node.cold;
+        genesis.config.runtime = node.runtime;
+        Ok(genesis)
+    }
+
+    /// Read the node config again and apply the settings that can change while `blockhead`
+    /// runs: the log level and the mempool's admission limits and maximum age. The rest wait for
+    /// a restart.
+    pub(crate) fn reload(&self, blockhead: &Blockhead) -> Result<()> {
+        let node = self.node_config()?;
+        apply_log_level(node.log_level.as_deref())?;
+        blockhead.mempool.lock().unwrap().reconfigure(&node.mempool);
+        log::info!("reloaded {}", self.config_path().display());
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|error| Error::new(format!("cannot read {}: {error}", path.display())))
+}
+
+fn apply_log_level(level: Option<&str>) -> Result<()> {
+    if let Some(level) = level {
+        let filter = level
+            .parse()
+            .map_err(|_| Error::new(format!("bad log level {level:?}")))?;
+        log::set_max_level(filter);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_init() {
+    use crate::block::BlockId;
+    use crate::hash::HashAlgorithm;
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let path = std::env::temp_dir().join(format!("blockhead-init-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let dir = DataDir::new(&path);
+    let genesis = load("dev").unwrap();
+    let blockhead = dir.init(&genesis).unwrap();
+    let dev = dev_wallet().account(0).unwrap().address(algorithm);
+    assert_eq!(blockhead.get_balance(dev).await.unwrap(), DEV_BALANCE);
+    assert!(blockhead.config.faucet.enabled);
+    let genesis_hash = blockhead.canonical_block(BlockId::Number(0)).unwrap().hash;
+    assert_eq!(genesis_hash, genesis.block().hash);
+    drop(blockhead);
+    assert!(dir.init(&genesis).is_err());
+    assert_eq!(
+        dir.open()
+            .unwrap()
+            .canonical_block(BlockId::Number(0))
+            .unwrap()
+            .hash,
+        genesis_hash
+    );
+
+    // A spec file written out from a preset describes the same chain.
+    let test = load("test").unwrap();
+    let spec = path.join("custom.toml");
+    std::fs::write(&spec, toml::to_string(&test).unwrap()).unwrap();
+    let custom = load(spec.to_str().unwrap()).unwrap();
+    assert_eq!(custom.block().hash, test.block().hash);
+    assert!(custom.config.require_signatures);
+    assert_eq!(custom.config.reward.block_subsidy, 1_000);
+    assert!(load(path.join("missing.toml").to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_reload() {
+    use crate::testkit;
+    use crate::transaction::Transaction;
+
+    let path = std::env::temp_dir().join(format!("blockhead-reload-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let dir = DataDir::new(&path);
+    let blockhead = dir.init(&load("dev").unwrap()).unwrap();
+    let transfer = Transaction {
+        to_address: Some(Address([8; 32])),
+        gas_price: 1,
+        ..testkit::transaction(Default::default(), Address([7; 32]), 0)
+    };
+    let admit = |transaction: &Transaction| {
+        blockhead
+            .mempool
+            .lock()
+            .unwrap()
+            .insert(transaction.clone(), 0)
+    };
+    admit(&transfer).unwrap();
+
+    let mut node = NodeConfig {
+        log_level: Some("debug".to_string()),
+        ..Default::default()
+    };
+    node.mempool.min_gas_price = 2;
+    std::fs::write(dir.config_path(), toml::to_string(&node).unwrap()).unwrap();
+    dir.reload(&blockhead).unwrap();
+    assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    let next = Transaction {
+        nonce: 1,
+        ..transfer.clone()
+    };
+    assert!(admit(&next).is_err());
+    admit(&Transaction {
+        gas_price: 2,
+        ..next
+    })
+    .unwrap();
+
+    node.log_level = Some("loud".to_string());
+    std::fs::write(dir.config_path(), toml::to_string(&node).unwrap()).unwrap();
+    assert!(dir.reload(&blockhead).is_err());
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_dev_accounts() {
+    use crate::hash::HashAlgorithm;
+
+    let algorithm = HashAlgorithm::default();
+    let dev = load("dev").unwrap();
+    let accounts = funded_dev_accounts(&dev).unwrap();
+    assert_eq!(accounts.len(), DEV_ACCOUNTS as usize);
+    for ((index, account), (address, balance)) in accounts.iter().zip(&dev.alloc) {
+        assert_eq!(account.address(algorithm), *address);
+        assert_eq!(*balance, DEV_BALANCE);
+        assert_eq!(
+            account.address(algorithm),
+            dev_wallet().account(*index).unwrap().address(algorithm)
+        );
+    }
+    // Scripts written against a dev chain rely on these never changing.
+    let (_, first) = &accounts[0];
+    assert_eq!(
+        first.address(algorithm).to_string(),
+        "0x5c0f13d900f5a04b0138f5985f826d2ef2bb0ae94f0446b4e3a46b5faf0b7932"
+    );
+    assert_eq!(
+        hex::encode(first.key.to_bytes()),
+        "6bb7e84b9b3c3ba155da0724b842e6d361c2d149794440faccb708a0519367d8"
+    );
+    assert_eq!(dev.validators, vec![(first.address(algorithm), DEV_STAKE)]);
+
+    let test = load("test").unwrap();
+    assert_eq!(
+        funded_dev_accounts(&test).unwrap().len(),
+        TEST_VALIDATORS as usize
+    );
+    assert!(funded_dev_accounts(&Genesis::default()).unwrap().is_empty());
+}
@@ -0,0 +1,370 @@
+//! Proof-of-stake: staking transactions, per-epoch validator sets, proposer selection and
+//! slashing.
+//!
+//! The validator set for epoch `e` is a snapshot of every account whose stake meets
+//! [`StakingConfig::min_validator_stake`] at the end of epoch `e - 1`. Each epoch also carries a
+//! randomness beacon seed, derived from the previous seed and the hash of the last block of the
+//! previous epoch, which drives stake-weighted proposer selection for every block in the epoch.
+use crate::address::Address;
+use crate::block::{Header, SIGNED_HEADER_LEN};
+use crate::db;
+use crate::encoding::Reader;
+use crate::error::{Error, Result};
+use crate::hash::{Hash, HashAlgorithm, HashBuilder};
+use crate::state::StateOverlay;
+use serde::{Deserialize, Serialize};
+
+/// Percentage of the slashed amount paid to the account that reported the offence. The rest is
+/// burned.
+pub(crate) const WHISTLEBLOWER_REWARD_PERCENT: u64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StakingConfig {
+    /// Number of blocks per epoch. Block `n` belongs to epoch `n / epoch_length`.
+    pub epoch_length: u64,
+    /// The smallest stake that earns a place in the validator set.
+    pub min_validator_stake: u64,
+}
+
+impl Default for StakingConfig {
+    fn default() -> Self {
+        Self {
+            epoch_length: 32,
+            min_validator_stake: 1,
+        }
+    }
+}
+
+impl StakingConfig {
+    pub(crate) fn epoch_of(&self, number: u64) -> u64 {
+        number / self.epoch_length
+    }
+
+    /// Whether block `number` is the first block of a new epoch (other than the genesis epoch).
+    pub(crate) fn is_epoch_start(&self, number: u64) -> bool {
+        number > 0 && number.is_multiple_of(self.epoch_length)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Validator {
+    pub address: Address,
+    pub stake: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ValidatorSet {
+    pub epoch: u64,
+    /// The randomness beacon output for this epoch.
+    pub seed: Hash,
+    /// Ordered by address.
+    pub validators: Vec<Validator>,
+}
+
+impl ValidatorSet {
+    pub(crate) fn total_stake(&self) -> u128 {
+        self.validators.iter().map(|v| v.stake as u128).sum()
+    }
+
+    pub(crate) fn contains(&self, address: Address) -> bool {
+        self.validators.iter().any(|v| v.address == address)
+    }
+
+    /// Pick the proposer of block `number`, weighted by stake, drawing with the chain's hash
+    /// function `algorithm`.
+    pub(crate) fn select_proposer(&self, number: u64, algorithm: HashAlgorithm) -> Option<Address> {
+        let total_stake = self.total_stake();
+        if total_stake == 0 {
+            return None;
+        }
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(self.seed.0);
+        hasher.update(number.to_be_bytes());
+        let digest = hasher.finalize();
+        let draw = u128::from_be_bytes(digest.0[..16].try_into().unwrap()) % total_stake;
+        let mut cumulative = 0u128;
+        for validator in &self.validators {
+            cumulative += validator.stake as u128;
+            if draw < cumulative {
+                return Some(validator.address);
+            }
+        }
+        unreachable!("draw is always below the total stake")
+    }
+}
+
+/// Derive the beacon seed for `epoch` from the previous epoch's seed and the hash of the block
+/// that closed it, hashing with `algorithm`.
+pub(crate) fn next_seed(
+    previous_seed: Hash,
+    epoch: u64,
+    last_block_hash: Hash,
+    algorithm: HashAlgorithm,
+) -> Hash {
+    let mut hasher = HashBuilder::new(algorithm);
+    hasher.update(previous_seed.0);
+    hasher.update(epoch.to_be_bytes());
+    hasher.update(last_block_hash.0);
+    hasher.finalize()
+}
+
+/// Run the epoch transition that precedes the first block of `epoch`: release unbonded funds and
+/// snapshot the new validator set. Must run before any of the block's transactions are applied,
+/// since the snapshot reads stakes from the committed state. The seed is hashed with `algorithm`.
+pub(crate) fn begin_epoch(
+    state: &mut StateOverlay,
+    config: &StakingConfig,
+    previous: &ValidatorSet,
+    parent_hash: Hash,
+    algorithm: HashAlgorithm,
+) -> Result<ValidatorSet> {
+    let epoch = previous.epoch + 1;
+    for address in db::read_unbonding(state.connection())? {
+        let account = state.account_mut(address)?;
+        account.balance = account
+            .balance
+            .checked_add(account.unbonding)
+            .ok_or_else(|| {
+                Error::new(format!("released stake overflows the balance of {address}"))
+            })?;
+        account.unbonding = 0;
+    }
+    let mut validators: Vec<Validator> = db::read_stakes(state.connection())?
+        .into_iter()
+        .filter(|(_, stake)| *stake >= config.min_validator_stake)
+        .map(|(address, stake)| Validator { address, stake })
+        .collect();
+    if validators.is_empty() {
+        // Keep the chain live rather than halting with nobody eligible to propose.
+        log::warn!("no account meets the minimum stake for epoch {epoch}, keeping validators");
+        validators = previous.validators.clone();
+    }
+    let validator_set = ValidatorSet {
+        epoch,
+        seed: next_seed(previous.seed, epoch, parent_hash, algorithm),
+        validators,
+    };
+    db::write_validator_set(state.connection(), &validator_set)?;
+    Ok(validator_set)
+}
+
+pub(crate) fn apply_stake(state: &mut StateOverlay, from: Address, amount: u64) -> Result<()> {
+    let account = state.account_mut(from)?;
+    if account.balance < amount {
+        return Err(Error::new(format!(
+            "{from} cannot stake {amount} with a balance of {}",
+            account.balance
+        )));
+    }
+    account.balance -= amount;
+    account.stake += amount;
+    Ok(())
+}
+
+pub(crate) fn apply_unstake(state: &mut StateOverlay, from: Address, amount: u64) -> Result<()> {
+    let account = state.account_mut(from)?;
+    if account.stake < amount {
+        return Err(Error::new(format!(
+            "{from} cannot unstake {amount} with a stake of {}",
+            account.stake
+        )));
+    }
+    account.stake -= amount;
+    account.unbonding += amount;
+    Ok(())
+}
+
+/// Proof that a validator proposed two different blocks at the same height: both headers, each
+/// with the proposer's signature. The blocks need not have been imported, and no one but the
+/// holder of the validator's key can make the signatures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DoubleSignEvidence {
+    pub first: Header,
+    pub second: Header,
+}
+
+impl DoubleSignEvidence {
+    /// The length of the encoding of evidence with both headers signed.
+    pub(crate) const LEN: usize = 2 * SIGNED_HEADER_LEN;
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        [self.first.encode_signed(), self.second.encode_signed()].concat()
+    }
+
+    /// Decode evidence from a chain that hashes with `algorithm`.
+    pub(crate) fn decode(data: &[u8], algorithm: HashAlgorithm) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let first = Header::read_signed(&mut reader, algorithm)?;
+        let second = Header::read_signed(&mut reader, algorithm)?;
+        reader.finish()?;
+        Ok(Self { first, second })
+    }
+
+    /// Check that the evidence shows the same proposer signing two blocks at one height of chain
+    /// `chain_id`, which hashes with `algorithm`, and return that proposer.
+    pub(crate) fn verify(&self, chain_id: u64, algorithm: HashAlgorithm) -> Result<Address> {
+        let (first, second) = (&self.first, &self.second);
+        if first.hash == second.hash {
+            return Err(Error::new(
+                "double-sign evidence names the same block twice",
+            ));
+        }
+        if first.number != second.number || first.proposer != second.proposer {
+            return Err(Error::new(format!(
+                "blocks {} and {} are not conflicting proposals",
+                first.hash, second.hash
+            )));
+        }
+        for header in [first, second] {
+            header
+                .check_signature(chain_id, algorithm)
+                .map_err(|error| {
+                    Error::new(format!("double-sign evidence is not signed: {error}"))
+                })?;
+        }
+        Ok(first.proposer)
+    }
+}
+
+/// Slash the offender's entire stake, including funds still unbonding, and pay the reporter
+/// their share.
+pub(crate) fn apply_double_sign_report(
+    state: &mut StateOverlay,
+    chain_id: u64,
+    algorithm: HashAlgorithm,
+    reporter: Address,
+    evidence: &DoubleSignEvidence,
+) -> Result<u64> {
+    let offender = evidence.verify(chain_id, algorithm)?;
+    let account = state.account_mut(offender)?;
+    let slashed = account.stake + account.unbonding;
+    if slashed == 0 {
+        return Err(Error::new(format!("{offender} has no stake left to slash")));
+    }
+    account.stake = 0;
+    account.unbonding = 0;
+    log::warn!("slashed {slashed} from {offender} for double-signing");
+    state.account_mut(reporter)?.balance += slashed * WHISTLEBLOWER_REWARD_PERCENT / 100;
+    Ok(slashed)
+}
+
+#[test]
+fn test_select_proposer_is_deterministic_and_weighted() {
+    let validator_set = ValidatorSet {
+        epoch: 0,
+        seed: Hash::digest_of("seed"),
+        validators: vec![
+            Validator {
+                address: Address([1; 32]),
+                stake: 1,
+            },
+            Validator {
+                address: Address([2; 32]),
+                stake: 99,
+            },
+        ],
+    };
+    let algorithm = HashAlgorithm::default();
+    let picks: Vec<Address> = (0..1000)
+        .map(|number| validator_set.select_proposer(number, algorithm).unwrap())
+        .collect();
+    let heavy = picks.iter().filter(|a| **a == Address([2; 32])).count();
+    assert!(heavy > 900, "heavy validator picked {heavy} times");
+    assert_eq!(
+        validator_set.select_proposer(7, algorithm),
+        validator_set.select_proposer(7, algorithm)
+    );
+}
+
+#[test]
+fn test_double_sign_evidence() {
+    use crate::block::Block;
+    use crate::signer::Signer;
+    use crate::testkit;
+
+    let algorithm = HashAlgorithm::default();
+    let key = testkit::validator_key();
+    let block = |state: &str, signer: &dyn Signer| {
+        let mut block = Block::builder(algorithm)
+            .genesis()
+            .timestamp(5)
+            .proposer(testkit::validator())
+            .state_root(Hash::digest_of(state))
+            .gas_limit(1_000_000)
+            .build()
+            .unwrap();
+        block.header.signature = Some(signer.sign_hash(block.signing_hash(1, algorithm)).unwrap());
+        block.header
+    };
+    let evidence = DoubleSignEvidence {
+        first: block("a", &key),
+        second: block("b", &key),
+    };
+    let encoded = evidence.encode();
+    assert_eq!(encoded.len(), DoubleSignEvidence::LEN);
+    assert_eq!(
+        DoubleSignEvidence::decode(&encoded, algorithm).unwrap(),
+        evidence
+    );
+    assert!(DoubleSignEvidence::decode(&encoded[1..], algorithm).is_err());
+    assert_eq!(evidence.verify(1, algorithm).unwrap(), testkit::validator());
+    // Signatures for another chain, or by anyone but the proposer, prove nothing.
+    assert!(evidence.verify(2, algorithm).is_err());
+    let forger = k256::ecdsa::SigningKey::from_slice(&[9; 32]).unwrap();
+    let forged = DoubleSignEvidence {
+        second: block("b", &forger),
+        ..evidence.clone()
+    };
+    assert!(forged.verify(1, algorithm).is_err());
+    let unsigned = DoubleSignEvidence {
+        second: Header {
+            signature: None,
+            ..evidence.second.clone()
+        },
+        ..evidence.clone()
+    };
+    assert!(unsigned.verify(1, algorithm).is_err());
+    let same = DoubleSignEvidence {
+        second: evidence.first.clone(),
+        ..evidence
+    };
+    assert!(same.verify(1, algorithm).is_err());
+}
+
+#[test]
+fn test_begin_epoch_releases_unbonding() {
+    use crate::state::Account;
+    use crate::Blockhead;
+
+    let blockhead = Blockhead::new(":memory:").unwrap();
+    let connection = &blockhead.connection;
+    let algorithm = HashAlgorithm::default();
+    let previous = blockhead.validator_set(0).unwrap();
+    let parent = blockhead.head().unwrap().hash;
+    let address = Address([9; 32]);
+    let unbonding = Account {
+        balance: 5,
+        unbonding: 3,
+        ..Default::default()
+    };
+    db::write_account(connection, address, &unbonding).unwrap();
+    let config = StakingConfig::default();
+    let mut state = StateOverlay::new(connection);
+    begin_epoch(&mut state, &config, &previous, parent, algorithm).unwrap();
+    let account = state.account_mut(address).unwrap();
+    assert_eq!((account.balance, account.unbonding), (8, 0));
+
+    // A release that would overflow the balance fails the block rather than wrapping.
+    let full = Account {
+        balance: u64::MAX,
+        ..unbonding
+    };
+    db::write_account(connection, address, &full).unwrap();
+    let mut state = StateOverlay::new(connection);
+    let error = begin_epoch(&mut state, &config, &previous, parent, algorithm);
+    assert_eq!(
+        error.unwrap_err().message(),
+        format!("released stake overflows the balance of {address}")
+    );
+}
@@ -0,0 +1,610 @@
+use crate::address::Address;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::hash::{Hash, HashAlgorithm};
+use crate::multisig::MultisigPolicy;
+use crate::proof;
+use crate::token::{TokenInfo, TokenSlot};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// The per-address state tracked by the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    /// The number of transactions sent from this account.
+    pub nonce: u64,
+    /// Funds locked as validator stake.
+    pub stake: u64,
+    /// Funds released from stake that are returned to `balance` at the next epoch boundary. They
+    /// remain slashable until then.
+    pub unbonding: u64,
+}
+
+/// A write buffer over the committed chain state. Blocks are executed against an overlay so
+/// that nothing reaches the database until the whole block has been applied.
+pub(crate) struct StateOverlay<'a> {
+    connection: &'a sqlite::Connection,
+    accounts: HashMap<Address, Account>,
+    /// The committed value of every account in `accounts`, used to write undo records.
+    original: HashMap<Address, Account>,
+    /// Contract code deployed by the block being executed.
+    code: HashMap<Address, Vec<u8>>,
+    /// Multisig policies registered by the block being executed.
+    multisig: HashMap<Address, MultisigPolicy>,
+    /// Contract storage slots read or written so far, alongside their committed values.
+    storage: HashMap<(Address, u64), u64>,
+    original_storage: HashMap<(Address, u64), u64>,
+    /// Tokens created by the block being executed.
+    tokens: HashMap<Address, TokenInfo>,
+    /// Token balances and allowances read or written so far, alongside their committed values.
+    token_slots: HashMap<(Address, TokenSlot), u64>,
+    original_token_slots: HashMap<(Address, TokenSlot), u64>,
+    /// Checkpoint attestations `(checkpoint, validator)` made by the block being executed.
+    pub attestations: Vec<(Hash, Address)>,
+}
+
+impl<'a> StateOverlay<'a> {
+    pub(crate) fn new(connection: &'a sqlite::Connection) -> Self {
+        Self {
+            connection,
+            accounts: HashMap::new(),
+            original: HashMap::new(),
+            code: HashMap::new(),
+            multisig: HashMap::new(),
+            storage: HashMap::new(),
+            original_storage: HashMap::new(),
+            tokens: HashMap::new(),
+            token_slots: HashMap::new(),
+            original_token_slots: HashMap::new(),
+            attestations: Vec::new(),
+        }
+    }
+
+    /// An overlay that starts from `accounts` as their committed values rather than reading them.
+    pub(crate) fn with_accounts(
+        connection: &'a sqlite::Connection,
+        accounts: impl IntoIterator<Item = (Address, Account)>,
+    ) -> Self {
+        let mut overlay = Self::new(connection);
+        for (address, account) in accounts {
+            overlay.original.insert(address, account);
+            overlay.accounts.insert(address, account);
+        }
+        overlay
+    }
+
+    /// The accounts as the overlay leaves them, or `None` if it read or wrote any account but
+    /// `addresses`, or any state besides accounts.
+    pub(crate) fn into_accounts(self, addresses: &[Address]) -> Option<HashMap<Address, Account>> {
+        let untouched = self.code.is_empty()
+            && self.multisig.is_empty()
+            && self.storage.is_empty()
+            && self.tokens.is_empty()
+            && self.token_slots.is_empty()
+            && self.attestations.is_empty();
+        let only = self
+            .accounts
+            .keys()
+            .all(|address| addresses.contains(address));
+        (untouched && only).then_some(self.accounts)
+    }
+
+    /// A copy of what the overlay holds, which [`StateOverlay::from_contents`] sets up again.
+    pub(crate) fn contents(&self) -> OverlayContents {
+        OverlayContents {
+            accounts: self.accounts.clone(),
+            original: self.original.clone(),
+            code: self.code.clone(),
+            multisig: self.multisig.clone(),
+            storage: self.storage.clone(),
+            original_storage: self.original_storage.clone(),
+            tokens: self.tokens.clone(),
+            token_slots: self.token_slots.clone(),
+            original_token_slots: self.original_token_slots.clone(),
+            attestations: self.attestations.clone(),
+        }
+    }
+
+    /// An overlay over `connection` holding `contents`, which must have been taken from an
+    /// overlay over the same committed state.
+    pub(crate) fn from_contents(
+        connection: &'a sqlite::Connection,
+        contents: OverlayContents,
+    ) -> Self {
+        Self {
+            connection,
+            accounts: contents.accounts,
+            original: contents.original,
+            code: contents.code,
+            multisig: contents.multisig,
+            storage: contents.storage,
+            original_storage: contents.original_storage,
+            tokens: contents.tokens,
+            token_slots: contents.token_slots,
+            original_token_slots: contents.original_token_slots,
+            attestations: contents.attestations,
+        }
+    }
+
+    /// Capture the uncommitted changes so that a failed transaction can be rolled back.
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            accounts: self.accounts.clone(),
+            code: self.code.clone(),
+            multisig: self.multisig.clone(),
+            storage: self.storage.clone(),
+            tokens: self.tokens.clone(),
+            token_slots: self.token_slots.clone(),
+            attestations: self.attestations.len(),
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) {
+        self.accounts = snapshot.accounts;
+        self.code = snapshot.code;
+        self.multisig = snapshot.multisig;
+        self.storage = snapshot.storage;
+        self.tokens = snapshot.tokens;
+        self.token_slots = snapshot.token_slots;
+        self.attestations.truncate(snapshot.attestations);
+    }
+
+    pub(crate) fn connection(&self) -> &'a sqlite::Connection {
+        self.connection
+    }
+
+    pub(crate) fn account(&mut self, address: Address) -> Result<Account> {
+        Ok(*self.account_mut(address)?)
+    }
+
+    pub(crate) fn account_mut(&mut self, address: Address) -> Result<&mut Account> {
+        if !self.accounts.contains_key(&address) {
+            let account = db::read_account(self.connection, address)?.unwrap_or_default();
+            self.original.insert(address, account);
+            self.accounts.insert(address, account);
+        }
+        Ok(self.accounts.get_mut(&address).unwrap())
+    }
+
+    pub(crate) fn code(&self, address: Address) -> Result<Option<Vec<u8>>> {
+        match self.code.get(&address) {
+            Some(code) => Ok(Some(code.clone())),
+            None => db::read_code(self.connection, address),
+        }
+    }
+
+    pub(crate) fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.code.insert(address, code);
+    }
+
+    pub(crate) fn multisig(&self, address: Address) -> Result<Option<MultisigPolicy>> {
+        match self.multisig.get(&address) {
+            Some(policy) => Ok(Some(policy.clone())),
+            None => db::read_multisig(self.connection, address),
+        }
+    }
+
+    pub(crate) fn set_multisig(&mut self, address: Address, policy: MultisigPolicy) {
+        self.multisig.insert(address, policy);
+    }
+
+    pub(crate) fn storage(&mut self, address: Address, key: u64) -> Result<u64> {
+        Ok(*self.storage_mut(address, key)?)
+    }
+
+    pub(crate) fn set_storage(&mut self, address: Address, key: u64, value: u64) -> Result<()> {
+        *self.storage_mut(address, key)? = value;
+        Ok(())
+    }
+
+    fn storage_mut(&mut self, address: Address, key: u64) -> Result<&mut u64> {
+        let slot = (address, key);
+        if !self.storage.contains_key(&slot) {
+            let value = match self.original_storage.get(&slot) {
+                Some(value) => *value,
+                None => db::read_storage(self.connection, address, key)?,
+            };
+            self.original_storage.insert(slot, value);
+            self.storage.insert(slot, value);
+        }
+        Ok(self.storage.get_mut(&slot).unwrap())
+    }
+
+    pub(crate) fn token(&self, token: Address) -> Result<Option<TokenInfo>> {
+        match self.tokens.get(&token) {
+            Some(info) => Ok(Some(info.clone())),
+            None => db::read_token(self.connection, token),
+        }
+    }
+
+    pub(crate) fn set_token(&mut self, token: Address, info: TokenInfo) {
+        self.tokens.insert(token, info);
+    }
+
+    pub(crate) fn token_slot(&mut self, token: Address, slot: TokenSlot) -> Result<u64> {
+        Ok(*self.token_slot_mut(token, slot)?)
+    }
+
+    pub(crate) fn set_token_slot(
+        &mut self,
+        token: Address,
+        slot: TokenSlot,
+        amount: u64,
+    ) -> Result<()> {
+        *self.token_slot_mut(token, slot)? = amount;
+        Ok(())
+    }
+
+    fn token_slot_mut(&mut self, token: Address, slot: TokenSlot) -> Result<&mut u64> {
+        let key = (token, slot);
+        if !self.token_slots.contains_key(&key) {
+            let amount = match self.original_token_slots.get(&key) {
+                Some(amount) => *amount,
+                None => db::read_token_slot(self.connection, token, slot)?,
+            };
+            self.original_token_slots.insert(key, amount);
+            self.token_slots.insert(key, amount);
+        }
+        Ok(self.token_slots.get_mut(&key).unwrap())
+    }
+
+    /// The state root, hashed with `algorithm`, over the committed accounts with the uncommitted
+    /// changes applied.
+    pub(crate) fn state_root(&self, algorithm: HashAlgorithm) -> Result<Hash> {
+        let mut accounts = db::read_accounts(self.connection)?;
+        accounts.extend(&self.accounts);
+        Ok(proof::state_root(&accounts, algorithm))
+    }
+
+    /// The accounts, storage slots and token slots the overlay changes, with their committed
+    /// values and the new ones.
+    pub(crate) fn diff(&self) -> StateDiff {
+        let mut diff = StateDiff::default();
+        for (address, account) in &self.accounts {
+            let original = &self.original[address];
+            if account != original {
+                diff.accounts.push(AccountDiff {
+                    address: *address,
+                    before: *original,
+                    after: *account,
+                });
+            }
+        }
+        for (&(address, key), value) in &self.storage {
+            let original = self.original_storage[&(address, key)];
+            if *value != original {
+                diff.storage.push(StorageDiff {
+                    address,
+                    key,
+                    before: original,
+                    after: *value,
+                });
+            }
+        }
+        for (&(token, slot), amount) in &self.token_slots {
+            let original = self.original_token_slots[&(token, slot)];
+            if *amount != original {
+                diff.tokens.push(TokenDiff {
+                    token,
+                    slot,
+                    before: original,
+                    after: *amount,
+                });
+            }
+        }
+        diff.accounts.sort_by_key(|account| account.address);
+        diff.storage.sort_by_key(|slot| (slot.address, slot.key));
+        diff.tokens.sort_by_key(|slot| (slot.token, slot.slot));
+        diff
+    }
+
+    /// Write every changed account back to the database, recording the previous values against
+    /// `block_hash` so that the block can be reverted during a reorg, along with the
+    /// [`StateDiff`], which is also returned.
+    pub(crate) fn commit(self, block_hash: Hash) -> Result<StateDiff> {
+        let diff = self.diff();
+        for account in &diff.accounts {
+            db::write_account_undo(
+                self.connection,
+                block_hash,
+                account.address,
+                &account.before,
+            )?;
+            db::write_account(self.connection, account.address, &account.after)?;
+        }
+        for slot in &diff.storage {
+            db::write_storage_undo(
+                self.connection,
+                block_hash,
+                slot.address,
+                slot.key,
+                slot.before,
+            )?;
+            db::write_storage(self.connection, slot.address, slot.key, slot.after)?;
+        }
+        for slot in &diff.tokens {
+            db::write_token_slot_undo(
+                self.connection,
+                block_hash,
+                slot.token,
+                slot.slot,
+                slot.before,
+            )?;
+            db::write_token_slot(self.connection, slot.token, slot.slot, slot.after)?;
+        }
+        db::write_state_diff(self.connection, block_hash, &diff)?;
+        for (address, code) in &self.code {
+            db::write_code(self.connection, *address, code, block_hash)?;
+        }
+        for (address, policy) in &self.multisig {
+            db::write_multisig(self.connection, *address, policy, block_hash)?;
+        }
+        for (token, info) in &self.tokens {
+            db::write_token(self.connection, *token, info, block_hash)?;
+        }
+        for (checkpoint, validator) in &self.attestations {
+            db::write_attestation(self.connection, *checkpoint, *validator, block_hash)?;
+        }
+        Ok(diff)
+    }
+}
+
+/// Everything a [`StateOverlay`] holds but its connection, to set it up again later over the same
+/// committed state. See [`StateOverlay::contents`].
+#[derive(Clone)]
+pub(crate) struct OverlayContents {
+    accounts: HashMap<Address, Account>,
+    original: HashMap<Address, Account>,
+    code: HashMap<Address, Vec<u8>>,
+    multisig: HashMap<Address, MultisigPolicy>,
+    storage: HashMap<(Address, u64), u64>,
+    original_storage: HashMap<(Address, u64), u64>,
+    tokens: HashMap<Address, TokenInfo>,
+    token_slots: HashMap<(Address, TokenSlot), u64>,
+    original_token_slots: HashMap<(Address, TokenSlot), u64>,
+    attestations: Vec<(Hash, Address)>,
+}
+
+impl OverlayContents {
+    /// Roughly how many bytes the contents take in memory.
+    pub(crate) fn size(&self) -> usize {
+        use std::mem::size_of;
+
+        (self.accounts.len() + self.original.len()) * size_of::<(Address, Account)>()
+            + self
+                .code
+                .values()
+                .map(|code| 32 + code.len())
+                .sum::<usize>()
+            + (self.multisig.values())
+                .map(|policy| size_of::<(Address, MultisigPolicy)>() + policy.signers.len() * 32)
+                .sum::<usize>()
+            + (self.storage.len() + self.original_storage.len())
+                * size_of::<((Address, u64), u64)>()
+            + (self.tokens.values())
+                .map(|info| size_of::<(Address, TokenInfo)>() + info.name.len() + info.symbol.len())
+                .sum::<usize>()
+            + (self.token_slots.len() + self.original_token_slots.len())
+                * size_of::<((Address, TokenSlot), u64)>()
+            + self.attestations.len() * size_of::<(Hash, Address)>()
+    }
+}
+
+/// The uncommitted contents of a [`StateOverlay`] at some point during execution.
+pub(crate) struct Snapshot {
+    accounts: HashMap<Address, Account>,
+    code: HashMap<Address, Vec<u8>>,
+    multisig: HashMap<Address, MultisigPolicy>,
+    storage: HashMap<(Address, u64), u64>,
+    tokens: HashMap<Address, TokenInfo>,
+    token_slots: HashMap<(Address, TokenSlot), u64>,
+    attestations: usize,
+}
+
+/// The accounts, storage slots and token slots changed by a block, with their values before
+/// and after it.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub accounts: Vec<AccountDiff>,
+    pub storage: Vec<StorageDiff>,
+    /// Absent from diffs stored before tokens existed.
+    #[serde(default)]
+    pub tokens: Vec<TokenDiff>,
+}
+
+impl StateDiff {
+    /// The changes of this diff followed by those of the `next` block's, as one diff.
+    pub(crate) fn then(&self, next: &StateDiff) -> StateDiff {
+        StateDiff {
+            accounts: merge_changes(
+                &self.accounts,
+                &next.accounts,
+                |diff| diff.address,
+                |first, last| AccountDiff {
+                    before: first.before,
+                    ..last.clone()
+                },
+            ),
+            storage: merge_changes(
+                &self.storage,
+                &next.storage,
+                |diff| (diff.address, diff.key),
+                |first, last| StorageDiff {
+                    before: first.before,
+                    ..last.clone()
+                },
+            ),
+            tokens: merge_changes(
+                &self.tokens,
+                &next.tokens,
+                |diff| (diff.token, diff.slot),
+                |first, last| TokenDiff {
+                    before: first.before,
+                    ..last.clone()
+                },
+            ),
+        }
+    }
+
+    /// The changes between `earlier` and this diff, both taken of the same overlay against the
+    /// same committed state.
+    pub(crate) fn since(&self, earlier: &StateDiff) -> StateDiff {
+        StateDiff {
+            accounts: changes_since(
+                &self.accounts,
+                &earlier.accounts,
+                |diff| diff.address,
+                |diff| (diff.before, diff.after),
+                |diff, before, after| AccountDiff {
+                    before,
+                    after,
+                    ..diff.clone()
+                },
+            ),
+            storage: changes_since(
+                &self.storage,
+                &earlier.storage,
+                |diff| (diff.address, diff.key),
+                |diff| (diff.before, diff.after),
+                |diff, before, after| StorageDiff {
+                    before,
+                    after,
+                    ..diff.clone()
+                },
+            ),
+            tokens: changes_since(
+                &self.tokens,
+                &earlier.tokens,
+                |diff| (diff.token, diff.slot),
+                |diff| (diff.before, diff.after),
+                |diff, before, after| TokenDiff {
+                    before,
+                    after,
+                    ..diff.clone()
+                },
+            ),
+        }
+    }
+}
+
+/// The entries of `earlier` and `later` as one diff, taking the `before` of an entry in both from
+/// `earlier` and its `after` from `later`. Entries that end where they started are kept.
+fn merge_changes<T: Clone, K: Ord>(
+    earlier: &[T],
+    later: &[T],
+    key: impl Fn(&T) -> K,
+    make: impl Fn(&T, &T) -> T,
+) -> Vec<T> {
+    let mut entries: BTreeMap<K, T> = BTreeMap::new();
+    for diff in earlier {
+        entries.insert(key(diff), diff.clone());
+    }
+    for diff in later {
+        let merged = match entries.get(&key(diff)) {
+            Some(first) => make(first, diff),
+            None => diff.clone(),
+        };
+        entries.insert(key(diff), merged);
+    }
+    entries.into_values().collect()
+}
+
+/// The entries of `later` whose values moved since `earlier`. An entry missing from one of the
+/// diffs holds its committed value, the `before` of the entry in the other.
+fn changes_since<T, K: Ord, V: PartialEq>(
+    later: &[T],
+    earlier: &[T],
+    key: impl Fn(&T) -> K,
+    values: impl Fn(&T) -> (V, V),
+    make: impl Fn(&T, V, V) -> T,
+) -> Vec<T> {
+    let mut entries: BTreeMap<K, (Option<&T>, Option<&T>)> = BTreeMap::new();
+    for diff in earlier {
+        entries.entry(key(diff)).or_default().0 = Some(diff);
+    }
+    for diff in later {
+        entries.entry(key(diff)).or_default().1 = Some(diff);
+    }
+    let mut changes = Vec::new();
+    for (earlier, later) in entries.into_values() {
+        let (start, end, entry) = match (earlier, later) {
+            (Some(earlier), Some(later)) => (values(earlier).1, values(later).1, later),
+            (Some(earlier), None) => {
+                let (committed, start) = values(earlier);
+                (start, committed, earlier)
+            }
+            (None, Some(later)) => {
+                let (committed, end) = values(later);
+                (committed, end, later)
+            }
+            (None, None) => unreachable!(),
+        };
+        if start != end {
+            changes.push(make(entry, start, end));
+        }
+    }
+    changes
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    pub address: Address,
+    pub before: Account,
+    pub after: Account,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageDiff {
+    pub address: Address,
+    pub key: u64,
+    pub before: u64,
+    pub after: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenDiff {
+    pub token: Address,
+    pub slot: TokenSlot,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// Write the values `diff` leaves behind, redoing a block's state changes on top of the state
+/// before it.
+pub(crate) fn apply_diff(connection: &sqlite::Connection, diff: &StateDiff) -> Result<()> {
+    for account in &diff.accounts {
+        db::write_account(connection, account.address, &account.after)?;
+    }
+    for slot in &diff.storage {
+        db::write_storage(connection, slot.address, slot.key, slot.after)?;
+    }
+    for slot in &diff.tokens {
+        db::write_token_slot(connection, slot.token, slot.slot, slot.after)?;
+    }
+    Ok(())
+}
+
+/// Undo the state changes made by canonical block `block_hash`, which must not be at or below a
+/// state checkpoint, as those blocks have no undo records left. See [`crate::archive`].
+pub(crate) fn revert_block(connection: &sqlite::Connection, block_hash: Hash) -> Result<()> {
+    let checkpoint = db::read_last_state_checkpoint(connection)?;
+    if let (Some(checkpoint), Some(header)) = (checkpoint, db::read_header(connection, block_hash)?)
+    {
+        if header.number <= checkpoint {
+            return Err(Error::new(format!(
+                "cannot revert block {block_hash}: its undo records were pruned at state checkpoint {checkpoint}"
+            )));
+        }
+    }
+    for (address, account) in db::read_account_undo(connection, block_hash)? {
+        db::write_account(connection, address, &account)?;
+    }
+    for (address, key, value) in db::read_storage_undo(connection, block_hash)? {
+        db::write_storage(connection, address, key, value)?;
+    }
+    for (token, slot, amount) in db::read_token_slot_undo(connection, block_hash)? {
+        db::write_token_slot(connection, token, slot, amount)?;
+    }
+    db::delete_block_effects(connection, block_hash)
+}
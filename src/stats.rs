@@ -0,0 +1,151 @@
+//! Chain activity summaries for dashboards, served at `/stats` by [`crate::http`].
+use crate::db;
+use crate::error::{Error, Result};
+#[cfg(feature = "http")]
+use crate::http::{Request, Response};
+#[cfg(feature = "http")]
+use crate::Blockchain;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http")]
+use serde_json::json;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The most blocks a window may cover, as the gas average reads every block in it.
+pub(crate) const MAX_WINDOW: u64 = 10_000;
+/// The window `/stats` covers unless asked for another.
+const DEFAULT_WINDOW: u64 = 100;
+
+/// Activity over the most recent blocks, and totals for the whole chain.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainStats {
+    pub head: u64,
+    /// How many blocks the window covers: those asked for, short of genesis.
+    pub window: u64,
+    /// Transactions in all canonical blocks.
+    pub total_transactions: u64,
+    /// Transactions in the window.
+    pub window_transactions: u64,
+    /// The mean time between consecutive blocks in the window.
+    pub average_block_time: Duration,
+    /// The mean gas the window's blocks used.
+    pub average_gas_used: u64,
+    /// Transactions waiting in the mempool.
+    pub mempool_depth: usize,
+    /// The size of the database file, in bytes.
+    pub database_size: u64,
+    /// Blocks received again after they were stored or queued, and dropped before validation,
+    /// since the node started.
+    pub duplicate_blocks: u64,
+}
+
+impl Blockhead {
+    /// Stats over the `window` most recent blocks, which may not exceed [`MAX_WINDOW`].
+    pub(crate) fn chain_stats(&self, window: u64) -> Result<ChainStats> {
+        if window > MAX_WINDOW {
+            return Err(Error::new(format!(
+                "window of {window} blocks exceeds {MAX_WINDOW}"
+            )));
+        }
+        let connection = self.reader();
+        let head = self.head()?;
+        let start = (head.number + 1).saturating_sub(window).max(1);
+        let covered = (head.number + 1).saturating_sub(start);
+        let mut stats = ChainStats {
+            head: head.number,
+            window: covered,
+            total_transactions: db::count_canonical_transactions(&connection, 0)?,
+            window_transactions: db::count_canonical_transactions(&connection, start)?,
+            mempool_depth: self.mempool.lock().unwrap().len(),
+            database_size: db::read_database_size(&connection)?,
+            duplicate_blocks: self.imports.duplicates(),
+            ..Default::default()
+        };
+        if covered == 0 {
+            return Ok(stats);
+        }
+        let before = db::read_canonical_header(&connection, start - 1)?
+            .ok_or_else(|| Error::new(format!("missing canonical block {}", start - 1)))?;
+        stats.average_block_time =
+            Duration::from_nanos(head.timestamp.saturating_sub(before.timestamp) / covered);
+        let mut gas_used = 0;
+        for number in start..=head.number {
+            let header = db::read_canonical_header(&connection, number)?
+                .ok_or_else(|| Error::new(format!("missing canonical block {number}")))?;
+            gas_used += db::read_block_gas_used(&connection, header.hash)?;
+        }
+        stats.average_gas_used = gas_used / covered;
+        Ok(stats)
+    }
+}
+
+/// Answer `/stats?window=<blocks>`.
+#[cfg(feature = "http")]
+pub(crate) async fn handle(blockhead: &Arc<Blockhead>, request: &Request) -> Response {
+    let window = match request.query_param("window").map(str::parse).transpose() {
+        Ok(window) => window.unwrap_or(DEFAULT_WINDOW),
+        Err(error) => return Response::error(400, format!("bad window: {error}")),
+    };
+    match blockhead.get_chain_stats(window).await {
+        Ok(stats) => Response::json(
+            200,
+            &json!({
+                "head": stats.head,
+                "window": stats.window,
+                "total_transactions": stats.total_transactions,
+                "window_transactions": stats.window_transactions,
+                "average_block_time_seconds": stats.average_block_time.as_secs_f64(),
+                "average_gas_used": stats.average_gas_used,
+                "mempool_depth": stats.mempool_depth,
+                "database_size": stats.database_size,
+                "duplicate_blocks": stats.duplicate_blocks,
+            }),
+        ),
+        Err(error) => Response::error(400, error.message()),
+    }
+}
+
+#[tokio::test]
+async fn test_chain_stats() {
+    use crate::address::Address;
+    use crate::testkit::TestChain;
+
+    let chain = TestChain::new();
+    let clock = chain.clock.clone();
+    let recipient = Address([8; 32]);
+    for transfers in [2, 0, 1, 3] {
+        for _ in 0..transfers {
+            chain.transfer(chain.validator, recipient, 1).await;
+        }
+        clock.advance(Duration::from_secs(2));
+        chain.produce();
+    }
+    chain.transfer(chain.validator, recipient, 1).await;
+
+    let stats = chain.blockhead.chain_stats(2).unwrap();
+    assert_eq!(stats.head, 4);
+    assert_eq!(stats.window, 2);
+    assert_eq!(stats.total_transactions, 6);
+    assert_eq!(stats.window_transactions, 4);
+    assert_eq!(stats.average_block_time, Duration::from_secs(2));
+    assert_eq!(stats.average_gas_used, 4 * 21_000 / 2);
+    assert_eq!(stats.mempool_depth, 1);
+    assert!(stats.database_size > 0);
+    // Windows stop at genesis.
+    assert_eq!(chain.blockhead.chain_stats(50).unwrap().window, 4);
+    assert!(chain.blockhead.chain_stats(MAX_WINDOW + 1).is_err());
+
+    #[cfg(feature = "http")]
+    {
+        let address = crate::http::serve_locally(Arc::new(chain.blockhead)).await;
+        let (status, body) =
+            crate::http::send(address, "GET /stats?window=2 HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["window_transactions"], 4);
+        assert_eq!(body["average_block_time_seconds"], 2.0);
+        let (status, _) = crate::http::send(address, "GET /stats?window=x HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 400);
+    }
+}
@@ -0,0 +1,272 @@
+//! Where a transaction is on its way from submission to inclusion in the canonical chain.
+use crate::block::Block;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::hash::Hash;
+use crate::runtime;
+use crate::{Blockhead, TransactionReceipt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Never submitted to this node, or left the mempool too long ago to be remembered.
+    Unknown,
+    /// Waiting in the mempool.
+    Pending,
+    /// Included in canonical block `block`.
+    Included { block: Hash },
+    /// Removed from the mempool because it failed to apply when a block was produced, or waited
+    /// longer than [`crate::MempoolConfig::max_age`].
+    Dropped { reason: String },
+    /// Removed from the mempool by transaction `by`, from the same sender with the same nonce.
+    Replaced { by: Hash },
+}
+
+/// A transaction entering a new status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TransactionEvent {
+    pub hash: Hash,
+    pub status: TransactionStatus,
+}
+
+/// How many dropped and replaced transactions are remembered.
+const REMEMBERED: usize = 4096;
+/// Events buffered for each subscriber before the slowest starts missing them.
+const EVENT_CAPACITY: usize = 1024;
+/// How often [`Blockhead::wait_for_confirmations`] looks for blocks it was not told about, which
+/// a read-only database gets from the writer in another process.
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Publishes status changes and remembers transactions that left the mempool without being
+/// included, which neither the mempool nor the chain can answer for.
+pub(crate) struct StatusTracker {
+    removed: HashMap<Hash, TransactionStatus>,
+    order: VecDeque<Hash>,
+    events: broadcast::Sender<TransactionEvent>,
+}
+
+impl StatusTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            removed: HashMap::new(),
+            order: VecDeque::new(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<TransactionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Record that transaction `hash` entered `status` and tell the subscribers.
+    pub(crate) fn publish(&mut self, hash: Hash, status: TransactionStatus) {
+        match status {
+            TransactionStatus::Dropped { .. } | TransactionStatus::Replaced { .. } => {
+                if self.removed.insert(hash, status.clone()).is_none() {
+                    self.order.push_back(hash);
+                }
+                while self.order.len() > REMEMBERED {
+                    let oldest = self.order.pop_front().unwrap();
+                    self.removed.remove(&oldest);
+                }
+            }
+            _ => {
+                self.removed.remove(&hash);
+            }
+        }
+        // Nobody listening is not an error.
+        let _ = self.events.send(TransactionEvent { hash, status });
+    }
+
+    /// Record that the transactions of `block` were included in the canonical chain.
+    pub(crate) fn publish_included(&mut self, block: &Block) {
+        for (hash, _) in &block.body.transactions {
+            self.publish(*hash, TransactionStatus::Included { block: block.hash });
+        }
+    }
+
+    fn removed(&self, hash: Hash) -> Option<&TransactionStatus> {
+        self.removed.get(&hash)
+    }
+}
+
+impl Blockhead {
+    /// The status of transaction `hash`, looked up in the canonical chain, then the mempool, then
+    /// the transactions recently removed from the mempool.
+    pub(crate) fn transaction_status(&self, hash: Hash) -> Result<TransactionStatus> {
+        if let Some((block, _)) = db::read_transaction_location(&self.reader(), hash)? {
+            return Ok(TransactionStatus::Included { block });
+        }
+        if self.mempool.lock().unwrap().contains(hash) {
+            return Ok(TransactionStatus::Pending);
+        }
+        let status = self.status.lock().unwrap();
+        Ok(status
+            .removed(hash)
+            .cloned()
+            .unwrap_or(TransactionStatus::Unknown))
+    }
+
+    /// How many canonical blocks, counting its own, include transaction `hash` or build on the
+    /// one that does. Zero unless it is in the canonical chain.
+    pub(crate) fn confirmations(&self, hash: Hash) -> Result<u64> {
+        let connection = self.reader();
+        let Some((block_hash, _)) = db::read_transaction_location(&connection, hash)? else {
+            return Ok(0);
+        };
+        let header = db::read_header(&connection, block_hash)?
+            .ok_or_else(|| Error::new(format!("missing block {block_hash}")))?;
+        let head =
+            db::read_head(&connection)?.ok_or_else(|| Error::new("chain has no genesis block"))?;
+        Ok((head.number + 1).saturating_sub(header.number))
+    }
+
+    /// Wait until transaction `hash` has at least `confirmations` confirmations, then return its
+    /// receipt. The depth is measured afresh at every new head, so a reorg that moves or removes
+    /// the transaction restarts the count rather than resolving on a stale branch.
+    pub(crate) async fn wait_for_confirmations(
+        &self,
+        hash: Hash,
+        confirmations: u64,
+    ) -> Result<TransactionReceipt> {
+        let mut events = self.subscribe_chain_events();
+        loop {
+            let confirmed = runtime::block(|| {
+                if self.confirmations(hash)? < confirmations.max(1) {
+                    return Ok(None);
+                }
+                db::read_receipt(&self.reader(), hash)
+            })?;
+            if let Some(receipt) = confirmed {
+                return Ok(receipt);
+            }
+            // Any event, or missing some, is worth a fresh look.
+            let _ = tokio::time::timeout(CONFIRMATION_POLL_INTERVAL, events.recv()).await;
+        }
+    }
+
+    /// Follow every transaction status change from now on.
+    pub(crate) fn subscribe_transaction_status(&self) -> broadcast::Receiver<TransactionEvent> {
+        self.status.lock().unwrap().subscribe()
+    }
+}
+
+#[tokio::test]
+async fn test_transaction_status() {
+    use crate::address::Address;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let mut events = blockhead.subscribe_transaction_status();
+    let transfer = |value, gas_price| Transaction {
+        to_address: Some(Address([8; 32])),
+        value,
+        gas_price,
+        ..testkit::transaction(Default::default(), validator, 0)
+    };
+    let first = blockhead.send_transaction(transfer(1, 1)).await.unwrap();
+    assert_eq!(
+        blockhead.get_transaction_status(first).await.unwrap(),
+        TransactionStatus::Pending
+    );
+    assert!(blockhead.send_transaction(transfer(2, 1)).await.is_err());
+    let second = blockhead.send_transaction(transfer(2, 2)).await.unwrap();
+    assert_eq!(
+        blockhead.get_transaction_status(first).await.unwrap(),
+        TransactionStatus::Replaced { by: second }
+    );
+    // Admission checks the sender's balance, but not that its nonce is next.
+    let gapped = Transaction {
+        from_address: Address([9; 32]),
+        nonce: 1,
+        ..transfer(0, 0)
+    };
+    let gapped = blockhead.send_transaction(gapped).await.unwrap();
+
+    let block = blockhead.produce_block().unwrap();
+    assert_eq!(
+        blockhead.get_transaction_status(second).await.unwrap(),
+        TransactionStatus::Included { block: block.hash }
+    );
+    assert!(matches!(
+        blockhead.get_transaction_status(gapped).await.unwrap(),
+        TransactionStatus::Dropped { .. }
+    ));
+    assert_eq!(
+        blockhead
+            .get_transaction_status(Hash([0; 32]))
+            .await
+            .unwrap(),
+        TransactionStatus::Unknown
+    );
+
+    let mut statuses = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        statuses.push((event.hash, event.status));
+    }
+    assert_eq!(statuses.len(), 6);
+    assert_eq!(statuses[0], (first, TransactionStatus::Pending));
+    assert_eq!(
+        statuses[1],
+        (first, TransactionStatus::Replaced { by: second })
+    );
+    assert_eq!(statuses[2], (second, TransactionStatus::Pending));
+    assert_eq!(
+        statuses[4],
+        (second, TransactionStatus::Included { block: block.hash })
+    );
+    assert_eq!(statuses[5].0, gapped);
+}
+
+#[tokio::test]
+async fn test_wait_for_confirmations() {
+    use crate::address::Address;
+    use crate::chain::side_block;
+    use crate::testkit;
+    use crate::transaction::Transaction;
+    use crate::Blockchain;
+
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let genesis = blockhead.head().unwrap();
+    let transfer = Transaction {
+        to_address: Some(Address([8; 32])),
+        value: 5,
+        ..testkit::transaction(Default::default(), validator, 0)
+    };
+    let hash = blockhead.send_transaction(transfer).await.unwrap();
+    assert_eq!(blockhead.confirmations(hash).unwrap(), 0);
+    blockhead.produce_block().unwrap();
+    assert_eq!(blockhead.confirmations(hash).unwrap(), 1);
+
+    // A longer branch without the transaction takes it out of the chain again, and back into the
+    // mempool.
+    let side1 = side_block(&blockhead, &genesis, 11);
+    blockhead.import_block(&side1).unwrap();
+    blockhead
+        .import_block(&side_block(&blockhead, &side1, 12))
+        .unwrap();
+    assert_eq!(blockhead.confirmations(hash).unwrap(), 0);
+    assert_eq!(
+        blockhead.transaction_status(hash).unwrap(),
+        TransactionStatus::Pending
+    );
+
+    let waiting = blockhead.wait_for_confirmations(hash, 3);
+    let producing = async {
+        let mut blocks = Vec::new();
+        for _ in 0..3 {
+            blocks.push(blockhead.produce_block().unwrap());
+            tokio::task::yield_now().await;
+        }
+        blocks
+    };
+    let (receipt, blocks) = tokio::join!(waiting, producing);
+    assert_eq!(receipt.unwrap().block_hash, blocks[0].hash);
+    assert_eq!(blockhead.confirmations(hash).unwrap(), 3);
+}
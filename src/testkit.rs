@@ -0,0 +1,367 @@
+//! A deterministic chain for tests.
+//!
+//! [`TestChain`] runs an in-memory node whose clock only moves when the test moves it, with a
+//! single funded validator, so tests produce blocks on demand and get the same hashes every run.
+//! Its helpers panic rather than return errors, as a failed step fails the test anyway. Built for
+//! tests and with the `testkit` feature, so that integration tests outside the crate can use it.
+//!
+//! [`Gen`] generates the arbitrary addresses, transactions and blocks that property tests feed to
+//! the codecs.
+use crate::address::Address;
+use crate::block::{Block, Body};
+use crate::clock::ManualClock;
+use crate::genesis::{ChainConfig, Genesis};
+use crate::hash::{Hash, HashAlgorithm};
+use crate::mempool::MempoolConfig;
+use crate::transaction::{Transaction, TransactionKind};
+use crate::{Blockchain, Blockhead};
+use bytes::Bytes;
+use k256::ecdsa::SigningKey;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What the validator starts with.
+pub const VALIDATOR_BALANCE: u64 = 1_000_000_000;
+
+/// The key of the validator of [`TestChain`], and of the other chains tests build, which sign
+/// their blocks with it.
+pub fn validator_key() -> SigningKey {
+    SigningKey::from_slice(&[7; 32]).unwrap()
+}
+
+/// The address of [`validator_key`] on chains of the default hash algorithm. See
+/// [`validator_of`].
+pub fn validator() -> Address {
+    validator_of(HashAlgorithm::default())
+}
+
+/// The address of [`validator_key`] on chains that hash with `algorithm`.
+pub fn validator_of(algorithm: HashAlgorithm) -> Address {
+    Address::from_public_key(validator_key().verifying_key(), algorithm)
+}
+
+pub struct TestChain {
+    pub blockhead: Blockhead,
+    pub(crate) clock: Arc<ManualClock>,
+    /// The only validator, which proposes every block.
+    pub validator: Address,
+}
+
+/// The default parameters, but taking unsigned and free transactions, as tests send from
+/// addresses whose keys they do not hold and that may hold nothing.
+pub fn config() -> ChainConfig {
+    ChainConfig {
+        require_signatures: false,
+        mempool: MempoolConfig {
+            min_gas_price: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+impl Default for TestChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestChain {
+    pub fn new() -> Self {
+        Self::with_config(config())
+    }
+
+    /// A chain with protocol parameters `config` and the usual validator.
+    pub fn with_config(config: ChainConfig) -> Self {
+        let validator = validator_of(config.hash);
+        let genesis = Genesis {
+            alloc: vec![(validator, VALIDATOR_BALANCE)],
+            validators: vec![(validator, 100)],
+            config,
+            ..Default::default()
+        };
+        let clock = Arc::new(ManualClock::new(0));
+        let blockhead = Blockhead::with_genesis(":memory:", genesis)
+            .unwrap()
+            .with_clock(clock.clone())
+            .with_proposer_key(validator_key())
+            .unwrap();
+        Self {
+            blockhead,
+            clock,
+            validator,
+        }
+    }
+
+    pub fn head(&self) -> Block {
+        self.blockhead.head().unwrap()
+    }
+
+    /// Produce a block out of the mempool.
+    pub fn produce(&self) -> Block {
+        self.blockhead.produce_block().unwrap()
+    }
+
+    pub fn produce_many(&self, count: usize) -> Vec<Block> {
+        (0..count).map(|_| self.produce()).collect()
+    }
+
+    /// Move the clock, and so the timestamps of the blocks produced next, forward.
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// The nonce `address`'s next transaction needs, counting those still in the mempool.
+    pub fn next_nonce(&self, address: Address) -> u64 {
+        self.blockhead.pending_nonce(address).unwrap()
+    }
+
+    pub async fn send(&self, transaction: Transaction) -> Hash {
+        self.blockhead.send_transaction(transaction).await.unwrap()
+    }
+
+    /// Queue a free transfer of `value` from `from` to `to`.
+    pub async fn transfer(&self, from: Address, to: Address, value: u64) -> Hash {
+        self.send(transfer(from, to, value, self.next_nonce(from)))
+            .await
+    }
+
+    /// Import `length` empty blocks signed by the validator branching off canonical block
+    /// `ancestor`, returning them. A branch longer than the canonical chain above `ancestor`
+    /// reorgs onto it.
+    pub fn fork(&self, ancestor: &Block, length: usize) -> Vec<Block> {
+        let mut parent = ancestor.clone();
+        let mut branch = Vec::new();
+        for _ in 0..length {
+            // Distinct timestamps keep the branch's hashes apart from the canonical blocks'.
+            let mut block = Block::new(
+                self.blockhead.config.hash,
+                parent.hash,
+                parent.number + 1,
+                parent.timestamp + 1_000,
+                self.validator,
+                parent.state_root,
+                parent.gas_limit,
+                Body::default(),
+            );
+            let config = &self.blockhead.config;
+            (block.header)
+                .sign(&validator_key(), config.chain_id, config.hash)
+                .unwrap();
+            self.blockhead.import_block(&block).unwrap();
+            parent = block.clone();
+            branch.push(block);
+        }
+        branch
+    }
+
+    pub fn balance(&self, address: Address) -> u64 {
+        self.blockhead.account(address).unwrap().balance
+    }
+
+    #[track_caller]
+    pub fn assert_balance(&self, address: Address, expected: u64) {
+        assert_eq!(self.balance(address), expected, "balance of {address}");
+    }
+}
+
+/// An unsigned, free transaction of `kind` from `from` at `nonce`, with no recipient, value or
+/// data and the gas of a plain transfer. Tests set the fields that matter to them with struct
+/// update syntax: `Transaction { value: 5, ..transaction(kind, from, 0) }`.
+pub fn transaction(kind: TransactionKind, from: Address, nonce: u64) -> Transaction {
+    Transaction {
+        kind,
+        from_address: from,
+        to_address: None,
+        value: 0,
+        data: Bytes::new(),
+        gas_limit: 21_000,
+        gas_price: 0,
+        nonce,
+        signatures: Vec::new(),
+    }
+}
+
+/// A free attestation to `checkpoint` by the validator at `nonce`, signed for chain `chain_id`,
+/// which hashes with `algorithm`.
+pub fn attestation(
+    checkpoint: Hash,
+    nonce: u64,
+    chain_id: u64,
+    algorithm: HashAlgorithm,
+) -> Transaction {
+    let mut attestation = Transaction {
+        data: checkpoint.0.to_vec().into(),
+        gas_limit: 21_512,
+        ..transaction(TransactionKind::Attest, validator_of(algorithm), nonce)
+    };
+    attestation
+        .sign(&validator_key(), chain_id, algorithm)
+        .unwrap();
+    attestation
+}
+
+/// An unsigned, free transfer of `value` from `from` to `to` at `nonce`.
+pub fn transfer(from: Address, to: Address, value: u64, nonce: u64) -> Transaction {
+    Transaction {
+        to_address: Some(to),
+        value,
+        ..transaction(TransactionKind::Transfer, from, nonce)
+    }
+}
+
+/// A seeded source of arbitrary values for property tests. The same seed gives the same values,
+/// so a failing case can be replayed from the seed in its panic message.
+pub(crate) struct Gen {
+    state: u64,
+}
+
+impl Gen {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift never leaves zero.
+        Self {
+            state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        }
+    }
+
+    pub(crate) fn u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A value in `0..bound`.
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.u64() % bound
+    }
+
+    pub(crate) fn bool(&mut self) -> bool {
+        self.u64() & 1 == 1
+    }
+
+    /// Small, boundary and arbitrary values in roughly equal measure.
+    pub(crate) fn amount(&mut self) -> u64 {
+        match self.below(3) {
+            0 => self.below(256),
+            1 => [0, 1, u64::MAX - 1, u64::MAX][self.below(4) as usize],
+            _ => self.u64(),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.below(max_len as u64 + 1) as usize;
+        (0..len).map(|_| self.u64() as u8).collect()
+    }
+
+    pub(crate) fn bytes32(&mut self) -> [u8; 32] {
+        std::array::from_fn(|_| self.u64() as u8)
+    }
+
+    pub(crate) fn address(&mut self) -> Address {
+        Address(self.bytes32())
+    }
+
+    pub(crate) fn hash(&mut self) -> Hash {
+        Hash(self.bytes32())
+    }
+
+    /// A transaction that need not be valid, only encodable: its signatures are arbitrary bytes.
+    pub(crate) fn transaction(&mut self) -> Transaction {
+        let kind = TransactionKind::try_from(self.below(10) as i64).unwrap();
+        let to_address = if self.bool() {
+            Some(self.address())
+        } else {
+            None
+        };
+        let signatures = (0..self.below(3))
+            .map(|_| crate::signer::Signature(std::array::from_fn(|_| self.u64() as u8)))
+            .collect();
+        Transaction {
+            kind,
+            from_address: self.address(),
+            to_address,
+            value: self.amount(),
+            data: self.bytes(64).into(),
+            gas_limit: self.amount(),
+            gas_price: self.amount(),
+            nonce: self.amount(),
+            signatures,
+        }
+    }
+
+    /// A block of a chain of the default hash algorithm, signed with arbitrary bytes or not at
+    /// all.
+    pub(crate) fn block(&mut self) -> Block {
+        let algorithm = HashAlgorithm::default();
+        let transactions = (0..self.below(4))
+            .map(|_| {
+                let transaction = self.transaction();
+                (transaction.compute_hash(algorithm), transaction)
+            })
+            .collect();
+        let mut block = Block::new(
+            algorithm,
+            self.hash(),
+            self.amount(),
+            self.amount(),
+            self.address(),
+            self.hash(),
+            self.amount(),
+            Body { transactions },
+        );
+        if self.bool() {
+            block.header.signature = Some(crate::signer::Signature(std::array::from_fn(|_| {
+                self.u64() as u8
+            })));
+        }
+        block
+    }
+
+    /// `bytes` with a few bytes overwritten, inserted or removed.
+    pub(crate) fn mutate(&mut self, mut bytes: Vec<u8>) -> Vec<u8> {
+        for _ in 0..=self.below(3) {
+            let at = self.below(bytes.len() as u64 + 1) as usize;
+            match self.below(3) {
+                0 if at < bytes.len() => bytes[at] = self.u64() as u8,
+                1 => bytes.insert(at, self.u64() as u8),
+                _ if at < bytes.len() => {
+                    bytes.remove(at);
+                }
+                _ => {}
+            }
+        }
+        bytes
+    }
+}
+
+#[tokio::test]
+async fn test_test_chain() {
+    use crate::block::BlockId;
+
+    let chain = TestChain::new();
+    let recipient = Address([8; 32]);
+    chain.transfer(chain.validator, recipient, 5).await;
+    chain.transfer(chain.validator, recipient, 6).await;
+    let block1 = chain.produce();
+    assert_eq!(block1.body.transactions.len(), 2);
+    chain.assert_balance(recipient, 11);
+    chain.assert_balance(chain.validator, VALIDATOR_BALANCE - 11);
+
+    chain.advance(Duration::from_secs(10));
+    let block2 = chain.produce();
+    assert_eq!(block2.timestamp, 10_000_000_000);
+
+    // Without a real clock, the same steps give the same blocks.
+    let replay = TestChain::new();
+    replay.transfer(replay.validator, recipient, 5).await;
+    replay.transfer(replay.validator, recipient, 6).await;
+    assert_eq!(replay.produce().hash, block1.hash);
+
+    // A longer branch from genesis drops both blocks and their transfers.
+    let genesis = chain.blockhead.canonical_block(BlockId::Number(0)).unwrap();
+    let branch = chain.fork(&genesis, 3);
+    assert_eq!(chain.head().hash, branch[2].hash);
+    chain.assert_balance(recipient, 0);
+    assert_eq!(chain.blockhead.get_nonce(chain.validator).await.unwrap(), 0);
+}
@@ -0,0 +1,317 @@
+//! Fungible tokens kept by the protocol itself rather than by contract code.
+//!
+//! A [`TransactionKind::CreateToken`] transaction mints a token's whole supply to its creator, at
+//! [`Address::for_token`]. Holders move tokens with [`TransactionKind::TokenTransfer`], and may
+//! allow another account to spend up to some amount on their behalf with
+//! [`TransactionKind::TokenApprove`], which that account draws on with
+//! [`TransactionKind::TokenTransferFrom`]. Balances and allowances are [`TokenSlot`]s of the
+//! token, reverted with the blocks that changed them like any other state.
+//!
+//! [`TransactionKind::CreateToken`]: crate::transaction::TransactionKind::CreateToken
+//! [`TransactionKind::TokenTransfer`]: crate::transaction::TransactionKind::TokenTransfer
+//! [`TransactionKind::TokenApprove`]: crate::transaction::TransactionKind::TokenApprove
+//! [`TransactionKind::TokenTransferFrom`]: crate::transaction::TransactionKind::TokenTransferFrom
+use crate::address::Address;
+use crate::db;
+use crate::encoding::{self, Reader};
+use crate::error::{Error, Result};
+use crate::hash::{decode_hex32, HashAlgorithm};
+use crate::state::StateOverlay;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+
+/// The longest token name or symbol, in bytes.
+const MAX_NAME_LEN: usize = 64;
+
+/// What a token is called and how much of it exists.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub name: String,
+    pub symbol: String,
+    /// How many decimal places wallets show. Amounts are always whole units of the smallest
+    /// denomination.
+    pub decimals: u8,
+    /// Minted to the creator; no more is ever minted.
+    pub total_supply: u64,
+}
+
+impl TokenInfo {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encoding::write_var_bytes(&mut out, self.name.as_bytes());
+        encoding::write_var_bytes(&mut out, self.symbol.as_bytes());
+        out.push(self.decimals);
+        out.extend_from_slice(&self.total_supply.to_be_bytes());
+        out
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let mut text = |field| {
+            let bytes = reader.var_bytes()?;
+            if bytes.len() > MAX_NAME_LEN {
+                return Err(Error::new(format!(
+                    "token {field} is {} bytes, above the limit of {MAX_NAME_LEN}",
+                    bytes.len()
+                )));
+            }
+            String::from_utf8(bytes.to_vec())
+                .map_err(|_| Error::new(format!("token {field} is not UTF-8")))
+        };
+        let name = text("name")?;
+        let symbol = text("symbol")?;
+        let info = Self {
+            name,
+            symbol,
+            decimals: reader.u8()?,
+            total_supply: reader.u64()?,
+        };
+        reader.finish()?;
+        Ok(info)
+    }
+}
+
+/// An amount held in a token's state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TokenSlot {
+    /// What the holder owns.
+    Balance(Address),
+    /// What `spender` may still transfer out of `owner`'s balance.
+    Allowance { owner: Address, spender: Address },
+}
+
+impl TokenSlot {
+    /// The slot's key in the database: the holder's address, or the owner's followed by the
+    /// spender's.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        match self {
+            TokenSlot::Balance(holder) => holder.0.to_vec(),
+            TokenSlot::Allowance { owner, spender } => [owner.0, spender.0].concat(),
+        }
+    }
+
+    pub(crate) fn decode(key: &[u8]) -> Result<Self> {
+        match key.len() {
+            32 => Ok(TokenSlot::Balance(Address::try_from(key)?)),
+            64 => Ok(TokenSlot::Allowance {
+                owner: Address::try_from(&key[..32])?,
+                spender: Address::try_from(&key[32..])?,
+            }),
+            len => Err(Error::new(format!("bad token slot of {len} bytes"))),
+        }
+    }
+
+    /// Parse a slot's key as databases written before keys were binary hold it, such as
+    /// `balance:0x…`.
+    pub(crate) fn parse(key: &str) -> Result<Self> {
+        let parts: Vec<&str> = key.split(':').collect();
+        match parts.as_slice() {
+            ["balance", holder] => Ok(TokenSlot::Balance(Address(decode_hex32(holder)?))),
+            ["allowance", owner, spender] => Ok(TokenSlot::Allowance {
+                owner: Address(decode_hex32(owner)?),
+                spender: Address(decode_hex32(spender)?),
+            }),
+            _ => Err(Error::new(format!("bad token slot {key}"))),
+        }
+    }
+}
+
+/// Create the token described by `data` on behalf of `creator`'s transaction with `nonce`,
+/// returning its address, derived with `algorithm`.
+pub(crate) fn apply_create(
+    state: &mut StateOverlay,
+    creator: Address,
+    nonce: u64,
+    data: &[u8],
+    algorithm: HashAlgorithm,
+) -> Result<Address> {
+    let info = TokenInfo::decode(data)?;
+    let token = Address::for_token(creator, nonce, algorithm);
+    if state.token(token)?.is_some() {
+        return Err(Error::new(format!("token {token} already exists")));
+    }
+    state.set_token_slot(token, TokenSlot::Balance(creator), info.total_supply)?;
+    state.set_token(token, info);
+    Ok(token)
+}
+
+/// Move `amount` of `token` from `from` to `to`.
+pub(crate) fn apply_transfer(
+    state: &mut StateOverlay,
+    token: Address,
+    from: Address,
+    to: Address,
+    amount: u64,
+) -> Result<()> {
+    if state.token(token)?.is_none() {
+        return Err(Error::new(format!("no token at {token}")));
+    }
+    let balance = state.token_slot(token, TokenSlot::Balance(from))?;
+    if balance < amount {
+        return Err(Error::new(format!(
+            "{from} cannot transfer {amount} of token {token} with a balance of {balance}"
+        )));
+    }
+    state.set_token_slot(token, TokenSlot::Balance(from), balance - amount)?;
+    let received = state.token_slot(token, TokenSlot::Balance(to))?;
+    // The supply is fixed, so no balance can exceed it.
+    state.set_token_slot(token, TokenSlot::Balance(to), received + amount)
+}
+
+/// Allow `spender` to transfer up to `amount` of `owner`'s `token`, replacing any earlier
+/// allowance.
+pub(crate) fn apply_approve(
+    state: &mut StateOverlay,
+    token: Address,
+    owner: Address,
+    spender: Address,
+    amount: u64,
+) -> Result<()> {
+    if state.token(token)?.is_none() {
+        return Err(Error::new(format!("no token at {token}")));
+    }
+    state.set_token_slot(token, TokenSlot::Allowance { owner, spender }, amount)
+}
+
+/// Move `amount` of `owner`'s `token` to `to` on behalf of `spender`, drawing on its allowance.
+pub(crate) fn apply_transfer_from(
+    state: &mut StateOverlay,
+    token: Address,
+    spender: Address,
+    owner: Address,
+    to: Address,
+    amount: u64,
+) -> Result<()> {
+    let slot = TokenSlot::Allowance { owner, spender };
+    let allowance = state.token_slot(token, slot)?;
+    if allowance < amount {
+        return Err(Error::new(format!(
+            "{spender} may transfer {allowance} of {owner}'s token {token}, not {amount}"
+        )));
+    }
+    state.set_token_slot(token, slot, allowance - amount)?;
+    apply_transfer(state, token, owner, to, amount)
+}
+
+impl Blockhead {
+    pub(crate) fn token_info(&self, token: Address) -> Result<Option<TokenInfo>> {
+        db::read_token(&self.reader(), token)
+    }
+
+    pub(crate) fn token_slot(&self, token: Address, slot: TokenSlot) -> Result<u64> {
+        db::read_token_slot(&self.reader(), token, slot)
+    }
+}
+
+#[tokio::test]
+async fn test_tokens() {
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (blockhead, validator) = crate::chain::staked_chain(32);
+    let (alice, bob) = (Address([8; 32]), Address([9; 32]));
+    let info = TokenInfo {
+        name: "Blockhead Dollar".into(),
+        symbol: "BHD".into(),
+        decimals: 2,
+        total_supply: 1_000,
+    };
+    assert_eq!(TokenInfo::decode(&info.encode()).unwrap(), info);
+    let slot = TokenSlot::Allowance {
+        owner: alice,
+        spender: bob,
+    };
+    assert_eq!(TokenSlot::decode(&slot.encode()).unwrap(), slot);
+    assert_eq!(
+        TokenSlot::parse(&format!("allowance:{alice}:{bob}")).unwrap(),
+        slot
+    );
+    assert!(TokenSlot::decode(&[0; 33]).is_err());
+
+    let transaction = |kind, to_address, value, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        value,
+        data: data.into(),
+        gas_limit: 30_000,
+        ..testkit::transaction(kind, validator, nonce)
+    };
+    let create = transaction(TransactionKind::CreateToken, None, 0, info.encode(), 0);
+    let hash = blockhead.send_transaction(create).await.unwrap();
+    blockhead.produce_block().unwrap();
+    let token = Address::for_token(validator, 0, algorithm);
+    let receipt = blockhead.get_transaction_receipt(hash).await.unwrap();
+    assert_eq!(receipt.unwrap().contract_address, Some(token));
+    assert_eq!(blockhead.get_token_info(token).await.unwrap(), Some(info));
+
+    let data = token.0.to_vec();
+    let transfer = transaction(
+        TransactionKind::TokenTransfer,
+        Some(alice),
+        300,
+        data.clone(),
+        1,
+    );
+    let approve = transaction(
+        TransactionKind::TokenApprove,
+        Some(alice),
+        200,
+        data.clone(),
+        2,
+    );
+    let overdrawn = transaction(
+        TransactionKind::TokenTransfer,
+        Some(bob),
+        800,
+        data.clone(),
+        3,
+    );
+    for transaction in [transfer, approve, overdrawn] {
+        blockhead.send_transaction(transaction).await.unwrap();
+    }
+    blockhead.produce_block().unwrap();
+    assert_eq!(
+        blockhead.get_token_balance(token, validator).await.unwrap(),
+        700
+    );
+    assert_eq!(
+        blockhead.get_token_balance(token, alice).await.unwrap(),
+        300
+    );
+    assert_eq!(blockhead.get_token_balance(token, bob).await.unwrap(), 0);
+    assert_eq!(
+        blockhead
+            .get_token_allowance(token, validator, alice)
+            .await
+            .unwrap(),
+        200
+    );
+
+    // Alice spends part of her allowance, then cannot spend more than what is left.
+    let spend = |value, nonce| Transaction {
+        from_address: alice,
+        ..transaction(
+            TransactionKind::TokenTransferFrom,
+            Some(bob),
+            value,
+            [token.0, validator.0].concat(),
+            nonce,
+        )
+    };
+    blockhead.send_transaction(spend(150, 0)).await.unwrap();
+    blockhead.send_transaction(spend(100, 1)).await.unwrap();
+    blockhead.produce_block().unwrap();
+    assert_eq!(
+        blockhead.get_token_balance(token, validator).await.unwrap(),
+        550
+    );
+    assert_eq!(blockhead.get_token_balance(token, bob).await.unwrap(), 150);
+    assert_eq!(
+        blockhead
+            .get_token_allowance(token, validator, alice)
+            .await
+            .unwrap(),
+        50
+    );
+}
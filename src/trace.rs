@@ -0,0 +1,190 @@
+//! Instruction-level traces of transaction execution, for debugging contracts.
+//!
+//! Traces are produced on demand by re-executing a transaction against the state its block was
+//! built on. Nodes can also keep the traces of their most recent blocks, recorded as the blocks
+//! are executed, so that tracing them is a lookup.
+use crate::address::Address;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::execution;
+use crate::hash::Hash;
+use crate::state::StateOverlay;
+use crate::verify;
+use crate::Blockhead;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceConfig {
+    /// How many of the most recent blocks to keep traces for. Zero disables recording, leaving
+    /// every trace to be computed on demand.
+    pub retain_blocks: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Trace {
+    pub status: bool,
+    pub gas_used: u64,
+    #[serde(with = "hex_bytes")]
+    pub return_data: Vec<u8>,
+    pub steps: Vec<TraceStep>,
+    /// Every contract storage read and write, in execution order.
+    pub storage: Vec<StorageAccess>,
+}
+
+/// The machine state just before an instruction executed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// The call depth, zero for the contract the transaction called.
+    pub depth: usize,
+    pub pc: usize,
+    pub op: String,
+    pub gas_remaining: u64,
+    pub stack: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageAccess {
+    pub address: Address,
+    pub key: u64,
+    /// The value read, or the value written.
+    pub value: u64,
+    pub write: bool,
+}
+
+/// Serde for byte strings as `0x`-prefixed hex.
+pub(crate) mod hex_bytes {
+    pub fn serialize<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("0x{}", hex::encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        hex::decode(s.strip_prefix("0x").unwrap_or(&s)).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Blockhead {
+    /// The trace of canonical transaction `hash`, or `None` if it has not been included.
+    pub(crate) fn trace_transaction(&self, hash: Hash) -> Result<Option<Trace>> {
+        if let Some(trace) = db::read_trace(&self.connection, hash)? {
+            return Ok(Some(trace));
+        }
+        let Some((block_hash, position)) = db::read_transaction_location(&self.connection, hash)?
+        else {
+            return Ok(None);
+        };
+        let block = db::read_block(&self.connection, block_hash)?
+            .ok_or_else(|| Error::new(format!("missing block {block_hash}")))?;
+        let parent = db::read_block(&self.connection, block.parent_hash)?
+            .ok_or_else(|| Error::new(format!("missing ancestor {}", block.parent_hash)))?;
+        if let Some(traces) = self.execution_cache.traces(block_hash) {
+            if let Some(trace) = traces.get(position) {
+                return Ok(Some(trace.clone()));
+            }
+        }
+        // Trace every transaction on the way, for tracing the others later.
+        let traces = self.with_state_at(&parent, || {
+            let mut state = StateOverlay::new(&self.connection);
+            self.enter_block(&mut state, &parent)?;
+            let transactions = &block.body.transactions[..=position];
+            let (chain_id, algorithm) = (self.config.chain_id, self.config.hash);
+            let signers =
+                verify::recover_signers(transactions.iter().map(|(_, t)| t), chain_id, algorithm);
+            let mut traces = Vec::with_capacity(transactions.len());
+            for ((_, transaction), signers) in transactions.iter().zip(signers) {
+                let trace =
+                    execution::trace_transaction(&mut state, &self.config, transaction, &signers?)?;
+                traces.push(trace);
+            }
+            Ok(traces)
+        })?;
+        let trace = traces.last().cloned();
+        self.execution_cache.insert_traces(block_hash, traces);
+        Ok(trace)
+    }
+}
+
+#[cfg(feature = "vm")]
+#[tokio::test]
+async fn test_trace_transaction() {
+    use crate::hash::HashAlgorithm;
+    use crate::testkit;
+    use crate::transaction::{Transaction, TransactionKind};
+    use crate::vm::{opcode::*, push};
+    use crate::Blockchain;
+
+    let algorithm = HashAlgorithm::default();
+    let (mut blockhead, validator) = crate::chain::staked_chain(32);
+    blockhead.config.trace.retain_blocks = 1;
+    // Add one to slot 0.
+    let code = [push(1), push(0), vec![SLOAD, ADD], push(0), vec![SSTORE]].concat();
+    let transaction = |to_address, data: Vec<u8>, nonce| Transaction {
+        to_address,
+        data: data.into(),
+        gas_limit: 60_000,
+        ..testkit::transaction(TransactionKind::Transfer, validator, nonce)
+    };
+    blockhead
+        .send_transaction(transaction(None, code, 0))
+        .await
+        .unwrap();
+    blockhead.produce_block().unwrap();
+    let contract = Address::for_contract(validator, 0, algorithm);
+    let first = blockhead
+        .send_transaction(transaction(Some(contract), vec![], 1))
+        .await
+        .unwrap();
+    blockhead.produce_block().unwrap();
+    assert!(db::read_trace(&blockhead.connection, first)
+        .unwrap()
+        .is_some());
+    let second = blockhead
+        .send_transaction(transaction(Some(contract), vec![], 2))
+        .await
+        .unwrap();
+    blockhead.produce_block().unwrap();
+    // Only the latest block's traces are kept.
+    assert!(db::read_trace(&blockhead.connection, first)
+        .unwrap()
+        .is_none());
+
+    let trace = blockhead
+        .debug_trace_transaction(first)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(trace.status);
+    assert_eq!(trace.steps.len(), 6);
+    assert_eq!(trace.steps[2].op, "SLOAD");
+    assert_eq!(trace.steps[2].stack, vec![1, 0]);
+    assert_eq!(
+        trace.storage,
+        vec![
+            StorageAccess {
+                address: contract,
+                key: 0,
+                value: 0,
+                write: false
+            },
+            StorageAccess {
+                address: contract,
+                key: 0,
+                value: 1,
+                write: true
+            },
+        ]
+    );
+    assert_eq!(
+        blockhead.debug_trace_transaction(second).await.unwrap(),
+        db::read_trace(&blockhead.connection, second).unwrap()
+    );
+    // Re-execution leaves the chain as it was.
+    assert_eq!(
+        db::read_storage(&blockhead.connection, contract, 0).unwrap(),
+        2
+    );
+    assert_eq!(blockhead.head().unwrap().number, 3);
+}
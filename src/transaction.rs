@@ -1,23 +1,512 @@
 use crate::address::Address;
-use crate::block::BlockHash;
-use crate::hash::{Hash, HashBuilder};
+use crate::encoding::Reader;
+use crate::error::{Error, Result};
+use crate::hash::{Hash, HashAlgorithm, HashBuilder};
+use crate::multisig::MAX_SIGNERS;
+use crate::signer::{Signature, Signer};
+use crate::staking::DoubleSignEvidence;
+use bytes::Bytes;
+
+/// The kind of state transition a transaction requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TransactionKind {
+    /// Move `value` from `from_address` to `to_address`. Without a `to_address` this deploys
+    /// `data` as contract code at [`Address::for_contract`], endowed with `value`.
+    #[default]
+    Transfer,
+    /// Lock `value` of the sender's balance as validator stake.
+    Stake,
+    /// Release `value` of the sender's stake. The funds are returned at the next epoch boundary.
+    Unstake,
+    /// Report a validator for proposing two blocks at the same height. `data` carries the
+    /// encoded [`crate::staking::DoubleSignEvidence`].
+    ReportDoubleSign,
+    /// Attest to a finality checkpoint. `data` carries the 32-byte checkpoint block hash.
+    Attest,
+    /// Register the [`crate::multisig::MultisigPolicy`] encoded in `data` at its address,
+    /// endowed with `value`.
+    CreateMultisig,
+    /// Create the token described by the [`crate::token::TokenInfo`] encoded in `data`, minting
+    /// its supply to the sender.
+    CreateToken,
+    /// Move `value` of the token whose address is `data` to `to_address`.
+    TokenTransfer,
+    /// Allow `to_address` to transfer up to `value` of the sender's token whose address is
+    /// `data`.
+    TokenApprove,
+    /// Move `value` of a token to `to_address`, out of the balance of an owner who approved the
+    /// sender. `data` carries the token address, then the owner's.
+    TokenTransferFrom,
+}
+
+impl TransactionKind {
+    pub(crate) fn to_i64(self) -> i64 {
+        match self {
+            TransactionKind::Transfer => 0,
+            TransactionKind::Stake => 1,
+            TransactionKind::Unstake => 2,
+            TransactionKind::ReportDoubleSign => 3,
+            TransactionKind::Attest => 4,
+            TransactionKind::CreateMultisig => 5,
+            TransactionKind::CreateToken => 6,
+            TransactionKind::TokenTransfer => 7,
+            TransactionKind::TokenApprove => 8,
+            TransactionKind::TokenTransferFrom => 9,
+        }
+    }
+}
+
+impl TryFrom<i64> for TransactionKind {
+    type Error = Error;
+
+    fn try_from(value: i64) -> Result<Self> {
+        match value {
+            0 => Ok(TransactionKind::Transfer),
+            1 => Ok(TransactionKind::Stake),
+            2 => Ok(TransactionKind::Unstake),
+            3 => Ok(TransactionKind::ReportDoubleSign),
+            4 => Ok(TransactionKind::Attest),
+            5 => Ok(TransactionKind::CreateMultisig),
+            6 => Ok(TransactionKind::CreateToken),
+            7 => Ok(TransactionKind::TokenTransfer),
+            8 => Ok(TransactionKind::TokenApprove),
+            9 => Ok(TransactionKind::TokenTransferFrom),
+            _ => Err(Error::new(format!("unknown transaction kind {value}"))),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
-pub(crate) struct Transaction {
+pub struct Transaction {
+    pub kind: TransactionKind,
     pub from_address: Address,
-    pub to_address: Address,
+    /// The recipient of a transfer. Only transfers use it; `None` makes a transfer a contract
+    /// deployment.
+    pub to_address: Option<Address>,
     pub value: u64,
-    pub data: Vec<u8>,
+    /// Shared between clones of the transaction rather than copied.
+    pub data: Bytes,
+    /// The most gas the sender is willing to pay for.
+    pub gas_limit: u64,
+    /// Price per unit of gas, charged to the sender.
+    pub gas_price: u64,
+    /// Must equal the sender's account nonce, which each included transaction increments.
+    pub nonce: u64,
+    /// Proves that `from_address` sent the transaction: the sender's signature, or those of
+    /// enough signers of a multisig sender. See [`Transaction::sign`] and
+    /// [`crate::multisig::authorize`].
+    pub signatures: Vec<Signature>,
 }
 
 impl Transaction {
-    pub(crate) fn compute_hash(&self, hash: BlockHash) -> Hash {
-        let mut hasher = HashBuilder::new();
-        hasher.update(&hash.0);
-        hasher.update(&self.from_address.0);
-        hasher.update(&self.to_address.0);
-        hasher.update(self.value.to_be_bytes());
-        hasher.update(&self.data);
+    /// Start a transaction of `kind`, whose fields are checked by [`Builder::build`].
+    pub fn builder(kind: TransactionKind) -> Builder {
+        Builder {
+            kind,
+            from_address: None,
+            to_address: None,
+            value: 0,
+            data: Bytes::new(),
+            gas_limit: None,
+            gas_price: 0,
+            nonce: None,
+        }
+    }
+
+    pub fn is_deployment(&self) -> bool {
+        self.kind == TransactionKind::Transfer && self.to_address.is_none()
+    }
+
+    /// The most the transaction can take from the sender's balance: its fee at the full gas
+    /// limit, plus the value of kinds that spend it. Saturates rather than overflowing.
+    pub fn max_cost(&self) -> u64 {
+        let fee = self.gas_limit.saturating_mul(self.gas_price);
+        match self.kind {
+            TransactionKind::Transfer
+            | TransactionKind::Stake
+            | TransactionKind::CreateMultisig => fee.saturating_add(self.value),
+            _ => fee,
+        }
+    }
+
+    /// The canonical encoding of every field but the signatures.
+    pub fn encode_unsigned(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.write_unsigned(&mut |bytes| out.extend_from_slice(bytes));
+        out
+    }
+
+    /// The canonical encoding of the transaction: the unsigned encoding, then a count of
+    /// signatures and the signatures. See [`crate::encoding`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.write(&mut |bytes| out.extend_from_slice(bytes));
+        out
+    }
+
+    /// The length of [`Transaction::encode`].
+    fn encoded_len(&self) -> usize {
+        let to_address = if self.to_address.is_some() { 33 } else { 1 };
+        1 + 32 + to_address + 8 + 4 + self.data.len() + 3 * 8 + 1 + 65 * self.signatures.len()
+    }
+
+    /// Pass the unsigned encoding to `write` piece by piece, so that hashing it copies nothing.
+    fn write_unsigned(&self, write: &mut impl FnMut(&[u8])) {
+        write(&[self.kind.to_i64() as u8]);
+        write(&self.from_address.0);
+        match self.to_address {
+            Some(to_address) => {
+                write(&[1]);
+                write(&to_address.0);
+            }
+            None => write(&[0]),
+        }
+        write(&self.value.to_be_bytes());
+        write(&(self.data.len() as u32).to_be_bytes());
+        write(&self.data);
+        write(&self.gas_limit.to_be_bytes());
+        write(&self.gas_price.to_be_bytes());
+        write(&self.nonce.to_be_bytes());
+    }
+
+    /// Pass the encoding to `write` piece by piece.
+    fn write(&self, write: &mut impl FnMut(&[u8])) {
+        self.write_unsigned(write);
+        write(&[self.signatures.len() as u8]);
+        for signature in &self.signatures {
+            write(&signature.0);
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let transaction = Self::read(&mut reader)?;
+        reader.finish()?;
+        Ok(transaction)
+    }
+
+    pub(crate) fn read(reader: &mut Reader) -> Result<Self> {
+        let kind = TransactionKind::try_from(reader.u8()? as i64)?;
+        let from_address = Address::from(reader.bytes32()?);
+        let to_address = match reader.u8()? {
+            0 => None,
+            1 => Some(Address::from(reader.bytes32()?)),
+            tag => return Err(Error::new(format!("bad recipient tag {tag}"))),
+        };
+        Ok(Self {
+            kind,
+            from_address,
+            to_address,
+            value: reader.u64()?,
+            data: Bytes::copy_from_slice(reader.var_bytes()?),
+            gas_limit: reader.u64()?,
+            gas_price: reader.u64()?,
+            nonce: reader.u64()?,
+            signatures: {
+                let count = reader.u8()? as usize;
+                if count > MAX_SIGNERS {
+                    return Err(Error::new(format!("{count} signatures is too many")));
+                }
+                (0..count)
+                    .map(|_| Ok(Signature(reader.take(65)?.try_into().unwrap())))
+                    .collect::<Result<_>>()?
+            },
+        })
+    }
+
+    /// The transaction's hash on a chain that hashes with `algorithm`.
+    pub fn compute_hash(&self, algorithm: HashAlgorithm) -> Hash {
+        let mut hasher = HashBuilder::new(algorithm);
+        self.write(&mut |bytes| hasher.update(bytes));
+        hasher.finalize()
+    }
+
+    /// The hash the sender signs: the unsigned encoding, prefixed with the ID of the chain the
+    /// transaction is meant for, so that its signatures are worthless on any other chain, and
+    /// hashed with that chain's `algorithm`.
+    pub fn signing_hash(&self, chain_id: u64, algorithm: HashAlgorithm) -> Hash {
+        let mut hasher = HashBuilder::new(algorithm);
+        hasher.update(chain_id.to_be_bytes());
+        self.write_unsigned(&mut |bytes| hasher.update(bytes));
         hasher.finalize()
     }
+
+    /// Sign the transaction for chain `chain_id`, which hashes with `algorithm`, with `signer`,
+    /// which must hold the key of `from_address`.
+    pub fn sign(
+        &mut self,
+        signer: &dyn Signer,
+        chain_id: u64,
+        algorithm: HashAlgorithm,
+    ) -> Result<()> {
+        let address = signer.address(algorithm)?;
+        if address != self.from_address {
+            return Err(Error::new(format!(
+                "signer {address} cannot sign for {}",
+                self.from_address
+            )));
+        }
+        self.signatures = vec![signer.sign_hash(self.signing_hash(chain_id, algorithm))?];
+        Ok(())
+    }
+
+    /// Add `signer`'s signature to those already collected, as one of the signers of a multisig
+    /// sender, for chain `chain_id`, which hashes with `algorithm`.
+    pub fn add_signature(
+        &mut self,
+        signer: &dyn Signer,
+        chain_id: u64,
+        algorithm: HashAlgorithm,
+    ) -> Result<()> {
+        let address = signer.address(algorithm)?;
+        if self.signers(chain_id, algorithm)?.contains(&address) {
+            return Err(Error::new(format!("{address} has already signed")));
+        }
+        if self.signatures.len() >= MAX_SIGNERS {
+            return Err(Error::new(
+                "transaction carries the most signatures allowed",
+            ));
+        }
+        self.signatures
+            .push(signer.sign_hash(self.signing_hash(chain_id, algorithm))?);
+        self.signatures.sort_by_key(|signature| signature.0);
+        Ok(())
+    }
+
+    /// Merge in the signatures of `other`, a copy of the same transaction signed by other
+    /// signers.
+    pub fn combine(&mut self, other: &Transaction) -> Result<()> {
+        if other.encode_unsigned() != self.encode_unsigned() {
+            return Err(Error::new(
+                "cannot combine signatures of different transactions",
+            ));
+        }
+        for signature in &other.signatures {
+            if !self.signatures.contains(signature) {
+                self.signatures.push(*signature);
+            }
+        }
+        if self.signatures.len() > MAX_SIGNERS {
+            return Err(Error::new(format!(
+                "{} signatures is too many",
+                self.signatures.len()
+            )));
+        }
+        self.signatures.sort_by_key(|signature| signature.0);
+        Ok(())
+    }
+
+    /// The addresses whose keys made the signatures, in order, if made for chain `chain_id`,
+    /// which hashes with `algorithm`. A signature made for another chain recovers to some
+    /// unrelated address.
+    pub fn signers(&self, chain_id: u64, algorithm: HashAlgorithm) -> Result<Vec<Address>> {
+        let hash = self.signing_hash(chain_id, algorithm);
+        self.signatures
+            .iter()
+            .map(|signature| signature.recover(hash, algorithm))
+            .collect()
+    }
+}
+
+/// Assembles a [`Transaction`], checking that its fields make sense for its kind before it is
+/// signed or sent, rather than when it fails in a block. The sender, gas limit and nonce are
+/// required; the value and gas price default to zero and the data to empty.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    kind: TransactionKind,
+    from_address: Option<Address>,
+    to_address: Option<Address>,
+    value: u64,
+    data: Bytes,
+    gas_limit: Option<u64>,
+    gas_price: u64,
+    nonce: Option<u64>,
+}
+
+impl Builder {
+    pub fn from(mut self, from_address: Address) -> Self {
+        self.from_address = Some(from_address);
+        self
+    }
+
+    pub fn to(mut self, to_address: Address) -> Self {
+        self.to_address = Some(to_address);
+        self
+    }
+
+    pub fn value(mut self, value: u64) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn data(mut self, data: impl Into<Bytes>) -> Self {
+        self.data = data.into();
+        self
+    }
+
+    pub fn gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = Some(gas_limit);
+        self
+    }
+
+    pub fn gas_price(mut self, gas_price: u64) -> Self {
+        self.gas_price = gas_price;
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// The unsigned transaction, or an error naming the first field that is missing or does not
+    /// suit the kind.
+    pub fn build(self) -> Result<Transaction> {
+        let kind = self.kind;
+        let required = |field: Option<u64>, name: &str| {
+            field.ok_or_else(|| Error::new(format!("{kind:?} transaction has no {name}")))
+        };
+        let from_address = self
+            .from_address
+            .ok_or_else(|| Error::new(format!("{kind:?} transaction has no sender")))?;
+        let gas_limit = required(self.gas_limit, "gas limit")?;
+        let nonce = required(self.nonce, "nonce")?;
+        let (needs_recipient, data_len) = match kind {
+            TransactionKind::Transfer => (None, None),
+            TransactionKind::Stake | TransactionKind::Unstake => (Some(false), None),
+            TransactionKind::ReportDoubleSign => (Some(false), Some(DoubleSignEvidence::LEN)),
+            TransactionKind::Attest => (Some(false), Some(32)),
+            TransactionKind::CreateMultisig | TransactionKind::CreateToken => (Some(false), None),
+            TransactionKind::TokenTransfer | TransactionKind::TokenApprove => {
+                (Some(true), Some(32))
+            }
+            TransactionKind::TokenTransferFrom => (Some(true), Some(64)),
+        };
+        match (needs_recipient, self.to_address) {
+            (Some(true), None) => {
+                return Err(Error::new(format!(
+                    "{kind:?} transaction needs a recipient"
+                )))
+            }
+            (Some(false), Some(_)) => {
+                return Err(Error::new(format!(
+                    "{kind:?} transaction takes no recipient"
+                )))
+            }
+            _ => {}
+        }
+        if let Some(len) = data_len.filter(|len| *len != self.data.len()) {
+            return Err(Error::new(format!(
+                "{kind:?} transaction needs {len} bytes of data, not {}",
+                self.data.len()
+            )));
+        }
+        if kind == TransactionKind::CreateToken && self.value != 0 {
+            return Err(Error::new("token creation cannot carry value"));
+        }
+        Ok(Transaction {
+            kind,
+            from_address,
+            to_address: self.to_address,
+            value: self.value,
+            data: self.data,
+            gas_limit,
+            gas_price: self.gas_price,
+            nonce,
+            signatures: Vec::new(),
+        })
+    }
+
+    /// The transaction signed by `signer` for chain `chain_id`, which hashes with `algorithm`.
+    /// See [`Transaction::sign`].
+    pub fn build_signed(
+        self,
+        signer: &dyn Signer,
+        chain_id: u64,
+        algorithm: HashAlgorithm,
+    ) -> Result<Transaction> {
+        let mut transaction = self.build()?;
+        transaction.sign(signer, chain_id, algorithm)?;
+        Ok(transaction)
+    }
+}
+
+#[test]
+fn test_encoding_round_trip() {
+    use crate::testkit;
+    let transaction = Transaction {
+        value: 9,
+        data: vec![1, 2, 3].into(),
+        gas_limit: 21_048,
+        gas_price: 2,
+        ..testkit::transaction(TransactionKind::Stake, Address([3; 32]), 4)
+    };
+    let encoded = transaction.encode();
+    let decoded = Transaction::decode(&encoded).unwrap();
+    assert_eq!(decoded.encode(), encoded);
+    let algorithm = HashAlgorithm::default();
+    assert_eq!(
+        decoded.compute_hash(algorithm),
+        transaction.compute_hash(algorithm)
+    );
+    assert!(Transaction::decode(&encoded[..encoded.len() - 1]).is_err());
+    assert!(Transaction::decode(&[encoded.as_slice(), &[0]].concat()).is_err());
+}
+
+#[test]
+fn test_transaction_builder() {
+    let algorithm = HashAlgorithm::default();
+    let wallet = crate::spec::dev_wallet();
+    let account = wallet.account(0).unwrap();
+    let transfer = || {
+        Transaction::builder(TransactionKind::Transfer)
+            .from(account.address(algorithm))
+            .to(Address([8; 32]))
+            .value(5)
+            .gas_limit(21_000)
+            .gas_price(2)
+    };
+    let signed = transfer()
+        .nonce(3)
+        .build_signed(&account, 1, algorithm)
+        .unwrap();
+    assert_eq!(signed.nonce, 3);
+    assert_eq!(
+        signed.signers(1, algorithm).unwrap(),
+        vec![account.address(algorithm)]
+    );
+    let error = transfer().build().unwrap_err();
+    assert_eq!(error.message(), "Transfer transaction has no nonce");
+
+    let error = Transaction::builder(TransactionKind::Stake)
+        .from(account.address(algorithm))
+        .to(Address([8; 32]))
+        .gas_limit(21_000)
+        .nonce(0)
+        .build()
+        .unwrap_err();
+    assert_eq!(error.message(), "Stake transaction takes no recipient");
+    let attest = || {
+        Transaction::builder(TransactionKind::Attest)
+            .from(account.address(algorithm))
+            .gas_limit(21_000)
+            .nonce(0)
+    };
+    let error = attest().data(vec![1; 31]).build().unwrap_err();
+    assert_eq!(
+        error.message(),
+        "Attest transaction needs 32 bytes of data, not 31"
+    );
+    assert!(attest().data(vec![1; 32]).build().is_ok());
+    let error = Transaction::builder(TransactionKind::TokenTransfer)
+        .from(account.address(algorithm))
+        .data(vec![1; 32])
+        .gas_limit(21_000)
+        .nonce(0)
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        error.message(),
+        "TokenTransfer transaction needs a recipient"
+    );
 }
@@ -0,0 +1,86 @@
+//! Signature recovery for a batch of transactions, spread over the machine's cores.
+//!
+//! Recovering a signer is the most expensive stateless check on a transaction and does not depend
+//! on the state, so a block's signatures are all recovered up front, in parallel, before its
+//! transactions are applied one after another.
+use crate::address::Address;
+use crate::error::Result;
+use crate::hash::HashAlgorithm;
+use crate::transaction::Transaction;
+use std::num::NonZeroUsize;
+
+/// Below this many transactions, starting threads costs more than it saves.
+const MIN_PARALLEL: usize = 8;
+
+/// The signers of each of `transactions`, signed for chain `chain_id`, which hashes with
+/// `algorithm`, in order, or the error recovering them.
+pub(crate) fn recover_signers<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    chain_id: u64,
+    algorithm: HashAlgorithm,
+) -> Vec<Result<Vec<Address>>> {
+    let transactions: Vec<&Transaction> = transactions.into_iter().collect();
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    if threads == 1 || transactions.len() < MIN_PARALLEL {
+        return (transactions.iter())
+            .map(|t| t.signers(chain_id, algorithm))
+            .collect();
+    }
+    let chunk_size = transactions.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = transactions
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|t| t.signers(chain_id, algorithm))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+#[test]
+fn test_recover_signers() {
+    use crate::signer::Signature;
+    use crate::testkit;
+    use crate::wallet::{self, Wallet};
+
+    let algorithm = HashAlgorithm::default();
+    let wallet = Wallet::from_mnemonic(&wallet::mnemonic_from_entropy(&[3; 16]), "").unwrap();
+    let keys: Vec<_> = (0..4).map(|index| wallet.account(index).unwrap()).collect();
+    let mut transactions: Vec<Transaction> = (0..40)
+        .map(|i| {
+            let key = &keys[i % keys.len()];
+            let mut transaction = Transaction {
+                to_address: Some(Address([8; 32])),
+                value: 1,
+                ..testkit::transaction(Default::default(), key.address(algorithm), i as u64)
+            };
+            transaction.sign(key, 1, algorithm).unwrap();
+            transaction
+        })
+        .collect();
+    transactions[17].signatures[0] = Signature([0; 65]);
+    let signers = recover_signers(transactions.iter(), 1, algorithm);
+    assert_eq!(signers.len(), transactions.len());
+    for (i, (signers, transaction)) in signers.iter().zip(&transactions).enumerate() {
+        if i == 17 {
+            assert!(signers.is_err());
+        } else {
+            assert_eq!(signers.as_ref().unwrap(), &vec![transaction.from_address]);
+        }
+    }
+    // Signatures made for one chain do not recover to the sender on another.
+    let elsewhere = recover_signers(transactions.iter(), 2, algorithm);
+    assert_ne!(
+        elsewhere[0].as_ref().ok(),
+        Some(&vec![transactions[0].from_address])
+    );
+}
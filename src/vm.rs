@@ -0,0 +1,657 @@
+//! A small stack machine for contract code.
+//!
+//! Words on the stack are `u64`. Memory is a byte array that grows on demand, and each contract
+//! has its own `u64 -> u64` storage. Multi-byte immediates and memory words are big-endian.
+//! Execution ends with `STOP`, `RETURN` or `REVERT`, or fails (out of gas, bad opcode, stack
+//! misuse), in which case all gas is consumed.
+use crate::address::Address;
+use crate::error::Result;
+use crate::hash::{Hash, HashAlgorithm};
+use crate::precompile;
+use crate::state::StateOverlay;
+use crate::trace::{StorageAccess, Trace, TraceStep};
+use crate::Log;
+use bytes::Bytes;
+
+pub(crate) mod opcode {
+    pub const STOP: u8 = 0x00;
+    pub const ADD: u8 = 0x01;
+    pub const SUB: u8 = 0x02;
+    pub const MUL: u8 = 0x03;
+    pub const DIV: u8 = 0x04;
+    pub const MOD: u8 = 0x05;
+    pub const LT: u8 = 0x10;
+    pub const GT: u8 = 0x11;
+    pub const EQ: u8 = 0x12;
+    pub const ISZERO: u8 = 0x13;
+    /// Followed by an 8-byte immediate.
+    pub const PUSH: u8 = 0x20;
+    pub const POP: u8 = 0x21;
+    pub const DUP: u8 = 0x22;
+    pub const SWAP: u8 = 0x23;
+    pub const CALLVALUE: u8 = 0x30;
+    pub const CALLDATASIZE: u8 = 0x31;
+    /// `offset -> word` read from call data, zero padded.
+    pub const CALLDATALOAD: u8 = 0x32;
+    /// `memory_offset, data_offset, len ->`
+    pub const CALLDATACOPY: u8 = 0x33;
+    /// `memory_offset ->` writes the 32-byte caller address to memory.
+    pub const CALLER: u8 = 0x34;
+    /// `memory_offset ->` writes the 32-byte contract address to memory.
+    pub const ADDRESS: u8 = 0x35;
+    pub const MLOAD: u8 = 0x40;
+    /// `offset, value ->`
+    pub const MSTORE: u8 = 0x41;
+    pub const SLOAD: u8 = 0x50;
+    /// `key, value ->`
+    pub const SSTORE: u8 = 0x51;
+    pub const JUMP: u8 = 0x60;
+    /// `destination, condition ->`
+    pub const JUMPI: u8 = 0x61;
+    /// `address_offset, input_offset, input_len, output_offset, output_len -> success`
+    ///
+    /// Calls the contract or precompile whose address is in memory at `address_offset` with all
+    /// remaining gas, copying at most `output_len` bytes of its return data to memory.
+    pub const CALL: u8 = 0x70;
+    /// `offset, len, topics_offset, topic_count ->`
+    ///
+    /// Emits a log of memory `offset..offset + len`, with up to four 32-byte topics read from
+    /// memory at `topics_offset`. Logs of a call that does not return are discarded.
+    pub const LOG: u8 = 0x80;
+    /// `offset, len ->`
+    pub const RETURN: u8 = 0xf0;
+    /// `offset, len ->`
+    pub const REVERT: u8 = 0xf1;
+
+    pub fn name(op: u8) -> &'static str {
+        match op {
+            STOP => "STOP",
+            ADD => "ADD",
+            SUB => "SUB",
+            MUL => "MUL",
+            DIV => "DIV",
+            MOD => "MOD",
+            LT => "LT",
+            GT => "GT",
+            EQ => "EQ",
+            ISZERO => "ISZERO",
+            PUSH => "PUSH",
+            POP => "POP",
+            DUP => "DUP",
+            SWAP => "SWAP",
+            CALLVALUE => "CALLVALUE",
+            CALLDATASIZE => "CALLDATASIZE",
+            CALLDATALOAD => "CALLDATALOAD",
+            CALLDATACOPY => "CALLDATACOPY",
+            CALLER => "CALLER",
+            ADDRESS => "ADDRESS",
+            MLOAD => "MLOAD",
+            MSTORE => "MSTORE",
+            SLOAD => "SLOAD",
+            SSTORE => "SSTORE",
+            JUMP => "JUMP",
+            JUMPI => "JUMPI",
+            CALL => "CALL",
+            LOG => "LOG",
+            RETURN => "RETURN",
+            REVERT => "REVERT",
+            _ => "INVALID",
+        }
+    }
+}
+
+const STACK_LIMIT: usize = 1024;
+const CALL_DEPTH_LIMIT: usize = 64;
+const MEMORY_LIMIT: usize = 1 << 20;
+
+const GAS_BASE: u64 = 3;
+const GAS_SLOAD: u64 = 200;
+const GAS_SSTORE: u64 = 5_000;
+const GAS_MEMORY_WORD: u64 = 3;
+const GAS_CALL: u64 = 700;
+const GAS_LOG: u64 = 375;
+const GAS_LOG_TOPIC: u64 = 375;
+const GAS_LOG_BYTE: u64 = 8;
+const MAX_LOG_TOPICS: u64 = 4;
+
+/// The environment a contract runs in.
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    pub caller: Address,
+    pub address: Address,
+    pub value: u64,
+    pub data: Bytes,
+    pub gas_limit: u64,
+    /// The chain's hash function, which precompiles derive addresses with.
+    pub algorithm: HashAlgorithm,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Return(Vec<u8>),
+    Revert(Vec<u8>),
+    Failure(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VmResult {
+    pub outcome: Outcome,
+    pub gas_used: u64,
+    /// The logs emitted, empty unless the outcome is [`Outcome::Return`].
+    pub logs: Vec<Log>,
+}
+
+/// Why execution stopped early.
+enum Halt {
+    Revert(Vec<u8>),
+    Failure(String),
+}
+
+impl From<&str> for Halt {
+    fn from(reason: &str) -> Self {
+        Halt::Failure(reason.to_string())
+    }
+}
+
+struct Machine<'c, 't> {
+    code: &'c [u8],
+    context: &'c Context,
+    trace: Option<&'t mut Trace>,
+    pc: usize,
+    stack: Vec<u64>,
+    memory: Vec<u8>,
+    gas_used: u64,
+    depth: usize,
+    logs: Vec<Log>,
+}
+
+/// Run whatever is at `context.address`: a precompile, contract code, or nothing, which succeeds
+/// without using gas. Writes are left in `state` as for [`execute`].
+pub(crate) fn call(state: &mut StateOverlay, context: &Context) -> Result<VmResult> {
+    call_at_depth(state, context, 0, None)
+}
+
+/// [`call`], recording every instruction executed and storage slot accessed into `trace`.
+pub(crate) fn call_traced(
+    state: &mut StateOverlay,
+    context: &Context,
+    trace: &mut Trace,
+) -> Result<VmResult> {
+    call_at_depth(state, context, 0, Some(trace))
+}
+
+fn call_at_depth(
+    state: &mut StateOverlay,
+    context: &Context,
+    depth: usize,
+    trace: Option<&mut Trace>,
+) -> Result<VmResult> {
+    if let Some(precompile) = precompile::get(context.address) {
+        return Ok(precompile.call(&context.data, context.gas_limit, context.algorithm));
+    }
+    match state.code(context.address)? {
+        Some(code) => execute_at_depth(state, &code, context, depth, trace),
+        None => Ok(VmResult {
+            outcome: Outcome::Return(Vec::new()),
+            gas_used: 0,
+            logs: Vec::new(),
+        }),
+    }
+}
+
+/// Run `code` in `context`. Storage writes go straight to `state`; callers roll them back when
+/// the outcome is not [`Outcome::Return`]. Only database failures are returned as errors.
+pub(crate) fn execute(
+    state: &mut StateOverlay,
+    code: &[u8],
+    context: &Context,
+) -> Result<VmResult> {
+    execute_at_depth(state, code, context, 0, None)
+}
+
+fn execute_at_depth(
+    state: &mut StateOverlay,
+    code: &[u8],
+    context: &Context,
+    depth: usize,
+    trace: Option<&mut Trace>,
+) -> Result<VmResult> {
+    let mut machine = Machine {
+        code,
+        context,
+        trace,
+        pc: 0,
+        stack: Vec::new(),
+        memory: Vec::new(),
+        gas_used: 0,
+        depth,
+        logs: Vec::new(),
+    };
+    let outcome = match machine.run(state) {
+        Ok(Ok(return_data)) => Outcome::Return(return_data),
+        Ok(Err(Halt::Revert(return_data))) => Outcome::Revert(return_data),
+        Ok(Err(Halt::Failure(reason))) => {
+            machine.gas_used = context.gas_limit;
+            Outcome::Failure(reason)
+        }
+        Err(error) => return Err(error),
+    };
+    if !matches!(outcome, Outcome::Return(_)) {
+        machine.logs.clear();
+    }
+    Ok(VmResult {
+        outcome,
+        gas_used: machine.gas_used,
+        logs: machine.logs,
+    })
+}
+
+type Step<T> = std::result::Result<T, Halt>;
+
+impl Machine<'_, '_> {
+    fn charge(&mut self, gas: u64) -> Step<()> {
+        self.gas_used = self.gas_used.saturating_add(gas);
+        if self.gas_used > self.context.gas_limit {
+            return Err("out of gas".into());
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Step<u64> {
+        self.stack.pop().ok_or_else(|| "stack underflow".into())
+    }
+
+    fn push(&mut self, value: u64) -> Step<()> {
+        if self.stack.len() >= STACK_LIMIT {
+            return Err("stack overflow".into());
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Grow memory to cover `offset..offset + len`, charging for new words.
+    fn touch_memory(&mut self, offset: u64, len: u64) -> Step<std::ops::Range<usize>> {
+        let end = offset.checked_add(len).ok_or("memory offset overflow")? as usize;
+        if end > MEMORY_LIMIT {
+            return Err("memory limit exceeded".into());
+        }
+        if end > self.memory.len() {
+            let new_words = (end - self.memory.len()).div_ceil(32) as u64;
+            self.charge(new_words * GAS_MEMORY_WORD)?;
+            self.memory.resize(end.div_ceil(32) * 32, 0);
+        }
+        Ok(offset as usize..end)
+    }
+
+    fn record_storage(&mut self, key: u64, value: u64, write: bool) {
+        if let Some(trace) = self.trace.as_deref_mut() {
+            trace.storage.push(StorageAccess {
+                address: self.context.address,
+                key,
+                value,
+                write,
+            });
+        }
+    }
+
+    fn binary(&mut self, f: impl FnOnce(u64, u64) -> u64) -> Step<()> {
+        let a = self.pop()?;
+        let b = self.pop()?;
+        self.push(f(a, b))
+    }
+
+    fn run(&mut self, state: &mut StateOverlay) -> Result<Step<Vec<u8>>> {
+        loop {
+            match self.step(state) {
+                Ok(Some(return_data)) => return Ok(Ok(return_data)),
+                Ok(None) => {}
+                Err(StepError::Halt(halt)) => return Ok(Err(halt)),
+                Err(StepError::Database(error)) => return Err(error),
+            }
+        }
+    }
+
+    /// Execute one instruction, returning the return data if execution finished.
+    fn step(
+        &mut self,
+        state: &mut StateOverlay,
+    ) -> std::result::Result<Option<Vec<u8>>, StepError> {
+        use opcode::*;
+
+        let Some(&op) = self.code.get(self.pc) else {
+            return Ok(Some(Vec::new()));
+        };
+        let cost = match op {
+            SLOAD => GAS_SLOAD,
+            SSTORE => GAS_SSTORE,
+            CALL => GAS_CALL,
+            _ => GAS_BASE,
+        };
+        if let Some(trace) = self.trace.as_deref_mut() {
+            trace.steps.push(TraceStep {
+                depth: self.depth,
+                pc: self.pc,
+                op: opcode::name(op).to_string(),
+                gas_remaining: self.context.gas_limit - self.gas_used,
+                stack: self.stack.clone(),
+            });
+        }
+        self.pc += 1;
+        self.charge(cost)?;
+        match op {
+            STOP => return Ok(Some(Vec::new())),
+            ADD => self.binary(u64::wrapping_add)?,
+            SUB => self.binary(u64::wrapping_sub)?,
+            MUL => self.binary(u64::wrapping_mul)?,
+            DIV => self.binary(|a, b| a.checked_div(b).unwrap_or(0))?,
+            MOD => self.binary(|a, b| a.checked_rem(b).unwrap_or(0))?,
+            LT => self.binary(|a, b| (a < b) as u64)?,
+            GT => self.binary(|a, b| (a > b) as u64)?,
+            EQ => self.binary(|a, b| (a == b) as u64)?,
+            ISZERO => {
+                let a = self.pop()?;
+                self.push((a == 0) as u64)?;
+            }
+            PUSH => {
+                let immediate = self
+                    .code
+                    .get(self.pc..self.pc + 8)
+                    .ok_or("truncated push immediate")?;
+                let value = u64::from_be_bytes(immediate.try_into().unwrap());
+                self.pc += 8;
+                self.push(value)?;
+            }
+            POP => {
+                self.pop()?;
+            }
+            DUP => {
+                let a = *self.stack.last().ok_or("stack underflow")?;
+                self.push(a)?;
+            }
+            SWAP => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.push(a)?;
+                self.push(b)?;
+            }
+            CALLVALUE => self.push(self.context.value)?,
+            CALLDATASIZE => self.push(self.context.data.len() as u64)?,
+            CALLDATALOAD => {
+                let offset = self.pop()? as usize;
+                let mut word = [0u8; 8];
+                for (i, byte) in word.iter_mut().enumerate() {
+                    *byte = offset
+                        .checked_add(i)
+                        .and_then(|index| self.context.data.get(index))
+                        .copied()
+                        .unwrap_or(0);
+                }
+                self.push(u64::from_be_bytes(word))?;
+            }
+            CALLDATACOPY => {
+                let memory_offset = self.pop()?;
+                let data_offset = self.pop()? as usize;
+                let len = self.pop()?;
+                let range = self.touch_memory(memory_offset, len)?;
+                for (i, byte) in self.memory[range].iter_mut().enumerate() {
+                    *byte = data_offset
+                        .checked_add(i)
+                        .and_then(|index| self.context.data.get(index))
+                        .copied()
+                        .unwrap_or(0);
+                }
+            }
+            CALLER | ADDRESS => {
+                let offset = self.pop()?;
+                let range = self.touch_memory(offset, 32)?;
+                let address = if op == CALLER {
+                    self.context.caller
+                } else {
+                    self.context.address
+                };
+                self.memory[range].copy_from_slice(&address.0);
+            }
+            MLOAD => {
+                let offset = self.pop()?;
+                let range = self.touch_memory(offset, 8)?;
+                let value = u64::from_be_bytes(self.memory[range].try_into().unwrap());
+                self.push(value)?;
+            }
+            MSTORE => {
+                let offset = self.pop()?;
+                let value = self.pop()?;
+                let range = self.touch_memory(offset, 8)?;
+                self.memory[range].copy_from_slice(&value.to_be_bytes());
+            }
+            SLOAD => {
+                let key = self.pop()?;
+                let value = state.storage(self.context.address, key)?;
+                self.record_storage(key, value, false);
+                self.push(value)?;
+            }
+            SSTORE => {
+                let key = self.pop()?;
+                let value = self.pop()?;
+                state.set_storage(self.context.address, key, value)?;
+                self.record_storage(key, value, true);
+            }
+            JUMP => {
+                self.pc = self.pop()? as usize;
+            }
+            JUMPI => {
+                let destination = self.pop()? as usize;
+                if self.pop()? != 0 {
+                    self.pc = destination;
+                }
+            }
+            CALL => {
+                let address_offset = self.pop()?;
+                let input_offset = self.pop()?;
+                let input_len = self.pop()?;
+                let output_offset = self.pop()?;
+                let output_len = self.pop()?;
+                let range = self.touch_memory(address_offset, 32)?;
+                let address = Address::try_from(&self.memory[range]).unwrap();
+                let range = self.touch_memory(input_offset, input_len)?;
+                let data = Bytes::copy_from_slice(&self.memory[range]);
+                let output = self.touch_memory(output_offset, output_len)?;
+                let (success, return_data) = if self.depth >= CALL_DEPTH_LIMIT {
+                    (false, Vec::new())
+                } else {
+                    let context = Context {
+                        caller: self.context.address,
+                        address,
+                        value: 0,
+                        data,
+                        gas_limit: self.context.gas_limit - self.gas_used,
+                        algorithm: self.context.algorithm,
+                    };
+                    let snapshot = state.snapshot();
+                    let trace = self.trace.as_deref_mut();
+                    let result = call_at_depth(state, &context, self.depth + 1, trace)?;
+                    self.charge(result.gas_used)?;
+                    match result.outcome {
+                        Outcome::Return(data) => {
+                            self.logs.extend(result.logs);
+                            (true, data)
+                        }
+                        Outcome::Revert(data) => {
+                            state.restore(snapshot);
+                            (false, data)
+                        }
+                        Outcome::Failure(_) => {
+                            state.restore(snapshot);
+                            (false, Vec::new())
+                        }
+                    }
+                };
+                let len = return_data.len().min(output.len());
+                self.memory[output.start..output.start + len].copy_from_slice(&return_data[..len]);
+                self.push(success as u64)?;
+            }
+            LOG => {
+                let offset = self.pop()?;
+                let len = self.pop()?;
+                let topics_offset = self.pop()?;
+                let topic_count = self.pop()?;
+                if topic_count > MAX_LOG_TOPICS {
+                    return Err(Halt::Failure(format!("{topic_count} log topics")).into());
+                }
+                self.charge(
+                    GAS_LOG
+                        .saturating_add(topic_count * GAS_LOG_TOPIC)
+                        .saturating_add(len.saturating_mul(GAS_LOG_BYTE)),
+                )?;
+                let range = self.touch_memory(offset, len)?;
+                let data = self.memory[range].to_vec();
+                let range = self.touch_memory(topics_offset, topic_count * 32)?;
+                let topics = self.memory[range]
+                    .chunks(32)
+                    .map(|topic| Hash::try_from(topic).unwrap())
+                    .collect();
+                self.logs.push(Log {
+                    address: self.context.address,
+                    topics,
+                    data,
+                });
+            }
+            RETURN | REVERT => {
+                let offset = self.pop()?;
+                let len = self.pop()?;
+                let range = self.touch_memory(offset, len)?;
+                let data = self.memory[range].to_vec();
+                if op == REVERT {
+                    return Err(Halt::Revert(data).into());
+                }
+                return Ok(Some(data));
+            }
+            _ => return Err(Halt::Failure(format!("invalid opcode {op:#04x}")).into()),
+        }
+        Ok(None)
+    }
+}
+
+enum StepError {
+    Halt(Halt),
+    Database(crate::error::Error),
+}
+
+impl From<Halt> for StepError {
+    fn from(halt: Halt) -> Self {
+        StepError::Halt(halt)
+    }
+}
+
+impl From<&str> for StepError {
+    fn from(reason: &str) -> Self {
+        StepError::Halt(reason.into())
+    }
+}
+
+impl From<crate::error::Error> for StepError {
+    fn from(error: crate::error::Error) -> Self {
+        StepError::Database(error)
+    }
+}
+
+/// Assemble a `PUSH` instruction.
+pub(crate) fn push(value: u64) -> Vec<u8> {
+    let mut code = vec![opcode::PUSH];
+    code.extend_from_slice(&value.to_be_bytes());
+    code
+}
+
+#[cfg(test)]
+fn run(code: &[u8], data: &[u8], gas_limit: u64) -> VmResult {
+    let connection = sqlite::open(":memory:").unwrap();
+    connection.execute(crate::db::SCHEMA).unwrap();
+    let mut state = StateOverlay::new(&connection);
+    let context = Context {
+        caller: Address([1; 32]),
+        address: Address([2; 32]),
+        value: 0,
+        data: Bytes::copy_from_slice(data),
+        gas_limit,
+        algorithm: HashAlgorithm::default(),
+    };
+    execute(&mut state, code, &context).unwrap()
+}
+
+#[test]
+fn test_return_and_revert_data() {
+    use opcode::*;
+
+    // Return calldata[0..8] + 1 as an 8-byte word.
+    let code = [
+        push(0),
+        vec![CALLDATALOAD],
+        push(1),
+        vec![ADD],
+        push(0),
+        vec![MSTORE],
+        push(8),
+        push(0),
+        vec![RETURN],
+    ]
+    .concat();
+    let result = run(&code, &41u64.to_be_bytes(), 1_000);
+    assert_eq!(
+        result.outcome,
+        Outcome::Return(42u64.to_be_bytes().to_vec())
+    );
+
+    let code = [
+        push(7),
+        push(0),
+        vec![MSTORE],
+        push(8),
+        push(0),
+        vec![REVERT],
+    ]
+    .concat();
+    let result = run(&code, &[], 1_000);
+    assert_eq!(result.outcome, Outcome::Revert(7u64.to_be_bytes().to_vec()));
+    assert!(result.gas_used < 1_000);
+}
+
+#[test]
+fn test_call_precompile() {
+    use opcode::*;
+
+    // sha256 of the 3-byte call data, returning the first word of the digest.
+    let code = [
+        push(3),
+        push(0),
+        push(32),
+        vec![CALLDATACOPY],
+        push(precompile::SHA256.0[31] as u64),
+        push(24),
+        vec![MSTORE],
+        push(32),
+        push(64),
+        push(3),
+        push(32),
+        push(0),
+        vec![CALL, POP],
+        push(8),
+        push(64),
+        vec![RETURN],
+    ]
+    .concat();
+    let result = run(&code, b"abc", 10_000);
+    assert_eq!(
+        result.outcome,
+        Outcome::Return(hex::decode("ba7816bf8f01cfea").unwrap())
+    );
+}
+
+#[test]
+fn test_failure_consumes_all_gas() {
+    let result = run(&[opcode::ADD], &[], 1_000);
+    assert!(matches!(result.outcome, Outcome::Failure(_)));
+    assert_eq!(result.gas_used, 1_000);
+
+    let infinite_loop = [push(0), vec![opcode::JUMP]].concat();
+    let result = run(&infinite_loop, &[], 1_000);
+    assert_eq!(result.outcome, Outcome::Failure("out of gas".to_string()));
+}
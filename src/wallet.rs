@@ -0,0 +1,323 @@
+//! Keys for many accounts derived from one backed-up seed phrase.
+//!
+//! A seed phrase encodes 16 bytes of entropy as one word per byte from [`WORDS`], followed by a
+//! checksum word for the first byte of the entropy's SHA-256. As in BIP39, the phrase and an
+//! optional passphrase are stretched into a 64-byte seed with 2048 rounds of PBKDF2-HMAC-SHA512,
+//! but the word list is blockhead's own, so phrases do not carry over to other wallets. Keys are
+//! derived from the seed with BIP32 over secp256k1, and the `n`th account lives at
+//! [`ACCOUNT_PATH`]`/n`.
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::hash::HashAlgorithm;
+use hmac::{Hmac, Mac};
+use k256::ecdsa::SigningKey;
+use k256::elliptic_curve::ff::PrimeField;
+use k256::{NonZeroScalar, Scalar};
+use sha2::{Digest, Sha256, Sha512};
+
+type HmacSha512 = Hmac<Sha512>;
+
+/// Child indexes from here up derive hardened keys, which need the parent's private key.
+pub(crate) const HARDENED: u32 = 0x8000_0000;
+/// The parent of every account key. The coin type is not registered anywhere.
+pub(crate) const ACCOUNT_PATH: &str = "m/44'/9000'/0'/0";
+const ENTROPY_BYTES: usize = 16;
+const SEED_ROUNDS: u32 = 2048;
+
+pub(crate) const WORDS: [&str; 256] = [
+    "able", "acid", "acorn", "actor", "adapt", "admit", "adult", "agent", "agree", "alarm",
+    "album", "alert", "alley", "alpha", "amber", "angle", "ankle", "apple", "april", "arena",
+    "argue", "armor", "arrow", "atlas", "audit", "autumn", "avoid", "awake", "badge", "baker",
+    "bamboo", "banjo", "barn", "basin", "beach", "beard", "bench", "berry", "bird", "blade",
+    "bloom", "board", "bonus", "border", "bottle", "brave", "bread", "brick", "bridge", "bronze",
+    "brush", "bubble", "bucket", "butter", "cabin", "cable", "cactus", "camel", "candle", "canoe",
+    "canvas", "carbon", "cargo", "carpet", "castle", "cattle", "cedar", "cement", "chalk",
+    "cherry", "chess", "chief", "circle", "civic", "clay", "cliff", "clock", "cloud", "coast",
+    "cobalt", "coffee", "comet", "copper", "coral", "cotton", "cousin", "coyote", "crane",
+    "crater", "cream", "daisy", "dancer", "delta", "denim", "desert", "dinner", "doctor", "donkey",
+    "dragon", "drum", "eagle", "earth", "echo", "elbow", "ember", "engine", "equal", "estate",
+    "exotic", "fabric", "falcon", "fancy", "fence", "ferry", "fiber", "fiddle", "finger", "flame",
+    "flock", "flute", "forest", "fossil", "fox", "frost", "fruit", "galaxy", "garden", "garlic",
+    "gentle", "giant", "ginger", "globe", "glove", "golden", "grain", "grape", "gravel", "guitar",
+    "hammer", "harbor", "hazel", "helmet", "hero", "hollow", "honey", "hotel", "humble", "husky",
+    "igloo", "image", "index", "indigo", "island", "ivory", "jacket", "jaguar", "jelly", "jewel",
+    "jigsaw", "jungle", "kayak", "kernel", "kettle", "kitten", "koala", "ladder", "lagoon",
+    "laser", "lemon", "lens", "lily", "linen", "lizard", "lotus", "lumber", "lunar", "magnet",
+    "mango", "maple", "marble", "meadow", "melon", "meteor", "mirror", "mosaic", "muffin",
+    "museum", "napkin", "nectar", "needle", "nickel", "noodle", "oasis", "ocean", "olive", "onion",
+    "orbit", "orchid", "otter", "oyster", "paddle", "palace", "panda", "paper", "parrot", "peanut",
+    "pebble", "pencil", "pepper", "piano", "pigeon", "pillow", "pilot", "planet", "plaza",
+    "pocket", "polar", "poppy", "potato", "prism", "puzzle", "quartz", "quiver", "rabbit", "radar",
+    "raven", "ribbon", "river", "rocket", "saddle", "salmon", "sandal", "satin", "scarf", "shadow",
+    "silver", "sketch", "socket", "spider", "spruce", "squash", "stable", "summit", "sunset",
+    "swan", "tablet", "tiger", "timber", "tomato", "tulip", "tunnel", "turtle", "velvet", "violin",
+    "walnut", "willow",
+];
+
+/// A seed phrase for fresh entropy from the operating system.
+pub(crate) fn generate_mnemonic() -> Result<String> {
+    let mut entropy = [0; ENTROPY_BYTES];
+    getrandom::getrandom(&mut entropy)
+        .map_err(|error| Error::new(format!("no entropy available: {error}")))?;
+    Ok(mnemonic_from_entropy(&entropy))
+}
+
+pub(crate) fn mnemonic_from_entropy(entropy: &[u8; ENTROPY_BYTES]) -> String {
+    let checksum = Sha256::digest(entropy)[0];
+    entropy
+        .iter()
+        .chain([&checksum])
+        .map(|byte| WORDS[*byte as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The entropy a seed phrase encodes, checking its words and checksum.
+pub(crate) fn mnemonic_to_entropy(phrase: &str) -> Result<[u8; ENTROPY_BYTES]> {
+    let bytes = phrase
+        .split_whitespace()
+        .map(|word| {
+            let word = word.to_ascii_lowercase();
+            WORDS
+                .binary_search(&word.as_str())
+                .map(|index| index as u8)
+                .map_err(|_| Error::new(format!("{word:?} is not a seed phrase word")))
+        })
+        .collect::<Result<Vec<u8>>>()?;
+    let Some((checksum, entropy)) = bytes.split_last() else {
+        return Err(Error::new("empty seed phrase"));
+    };
+    let entropy: [u8; ENTROPY_BYTES] = entropy.try_into().map_err(|_| {
+        Error::new(format!(
+            "seed phrase has {} words, expected {}",
+            bytes.len(),
+            ENTROPY_BYTES + 1
+        ))
+    })?;
+    if Sha256::digest(entropy)[0] != *checksum {
+        return Err(Error::new("seed phrase checksum does not match"));
+    }
+    Ok(entropy)
+}
+
+/// The 64-byte seed for a valid seed phrase and `passphrase`.
+pub(crate) fn mnemonic_to_seed(phrase: &str, passphrase: &str) -> Result<[u8; 64]> {
+    let entropy = mnemonic_to_entropy(phrase)?;
+    let normalized = mnemonic_from_entropy(&entropy);
+    let salt = format!("mnemonic{passphrase}");
+    Ok(pbkdf2_sha512(
+        normalized.as_bytes(),
+        salt.as_bytes(),
+        SEED_ROUNDS,
+    ))
+}
+
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> [u8; 64] {
+    let mut mac = HmacSha512::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// PBKDF2 with HMAC-SHA512, for a single 64-byte block of output.
+fn pbkdf2_sha512(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 64] {
+    let mut block = hmac_sha512(password, &[salt, &1u32.to_be_bytes()]);
+    let mut output = block;
+    for _ in 1..rounds {
+        block = hmac_sha512(password, &[&block]);
+        for (out, byte) in output.iter_mut().zip(block) {
+            *out ^= byte;
+        }
+    }
+    output
+}
+
+/// Parse a derivation path such as `m/44'/9000'/0'/0/3` into child indexes, where `'` marks a
+/// hardened index.
+pub(crate) fn parse_path(path: &str) -> Result<Vec<u32>> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(Error::new(format!(
+            "derivation path {path:?} does not start at m"
+        )));
+    }
+    parts
+        .map(|part| {
+            let (digits, hardened) = match part.strip_suffix('\'') {
+                Some(digits) => (digits, HARDENED),
+                None => (part, 0),
+            };
+            let index: u32 = digits.parse()?;
+            if index >= HARDENED {
+                return Err(Error::new(format!(
+                    "index {index} in {path:?} is out of range"
+                )));
+            }
+            Ok(index | hardened)
+        })
+        .collect()
+}
+
+/// A private key together with the chain code that derives its children.
+#[derive(Clone)]
+pub(crate) struct ExtendedKey {
+    pub key: SigningKey,
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    /// The root of the key tree for `seed`.
+    pub(crate) fn master(seed: &[u8]) -> Result<Self> {
+        Self::from_hmac(hmac_sha512(b"Bitcoin seed", &[seed]), None)
+    }
+
+    /// Split an HMAC output into a key, offset by `parent` if given, and a chain code.
+    fn from_hmac(output: [u8; 64], parent: Option<&SigningKey>) -> Result<Self> {
+        let (left, right) = output.split_at(32);
+        let tweak: Option<Scalar> = Scalar::from_repr(*k256::FieldBytes::from_slice(left)).into();
+        let mut scalar = tweak.ok_or_else(|| Error::new("derived key is out of range"))?;
+        if let Some(parent) = parent {
+            scalar += parent.as_nonzero_scalar().as_ref();
+        }
+        let scalar: Option<NonZeroScalar> = NonZeroScalar::new(scalar).into();
+        let scalar = scalar.ok_or_else(|| Error::new("derived key is zero"))?;
+        Ok(Self {
+            key: SigningKey::from(scalar),
+            chain_code: right.try_into().unwrap(),
+        })
+    }
+
+    pub(crate) fn derive_child(&self, index: u32) -> Result<Self> {
+        let index_bytes = index.to_be_bytes();
+        let output = if index >= HARDENED {
+            let key = self.key.to_bytes();
+            hmac_sha512(&self.chain_code, &[&[0], &key, &index_bytes])
+        } else {
+            let point = self.key.verifying_key().to_encoded_point(true);
+            hmac_sha512(&self.chain_code, &[point.as_bytes(), &index_bytes])
+        };
+        Self::from_hmac(output, Some(&self.key))
+    }
+
+    pub(crate) fn derive_path(&self, path: &str) -> Result<Self> {
+        parse_path(path)?
+            .into_iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(index))
+    }
+
+    /// The key's address on a chain that hashes with `algorithm`.
+    pub(crate) fn address(&self, algorithm: HashAlgorithm) -> Address {
+        Address::from_public_key(self.key.verifying_key(), algorithm)
+    }
+}
+
+/// The accounts of one seed phrase.
+pub(crate) struct Wallet {
+    master: ExtendedKey,
+}
+
+impl Wallet {
+    pub(crate) fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self> {
+        let seed = mnemonic_to_seed(phrase, passphrase)?;
+        Ok(Self {
+            master: ExtendedKey::master(&seed)?,
+        })
+    }
+
+    /// The key at `path`, for accounts outside the default layout.
+    pub(crate) fn derive(&self, path: &str) -> Result<ExtendedKey> {
+        self.master.derive_path(path)
+    }
+
+    /// The key of account `index`, at [`ACCOUNT_PATH`]`/index`.
+    pub(crate) fn account(&self, index: u32) -> Result<ExtendedKey> {
+        self.derive(&format!("{ACCOUNT_PATH}/{index}"))
+    }
+}
+
+#[test]
+fn test_mnemonic() {
+    assert!(WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    let phrase = generate_mnemonic().unwrap();
+    assert_eq!(phrase.split(' ').count(), ENTROPY_BYTES + 1);
+    let entropy = mnemonic_to_entropy(&phrase).unwrap();
+    assert_eq!(mnemonic_from_entropy(&entropy), phrase);
+    assert_eq!(
+        mnemonic_to_entropy(&format!("  {}\n", phrase.to_uppercase())).unwrap(),
+        entropy
+    );
+
+    let mut words: Vec<&str> = phrase.split(' ').collect();
+    let last = WORDS.binary_search(&words[ENTROPY_BYTES]).unwrap();
+    words[ENTROPY_BYTES] = WORDS[(last + 1) % WORDS.len()];
+    assert!(mnemonic_to_entropy(&words.join(" ")).is_err());
+    assert!(mnemonic_to_entropy(&words[1..].join(" ")).is_err());
+    assert!(mnemonic_to_entropy("able zebra").is_err());
+
+    let seed = mnemonic_to_seed(&phrase, "").unwrap();
+    assert_ne!(seed, mnemonic_to_seed(&phrase, "secret").unwrap());
+}
+
+#[test]
+fn test_pbkdf2() {
+    // The seed of BIP39's first English test vector.
+    let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                  abandon abandon about";
+    assert_eq!(
+        hex::encode(pbkdf2_sha512(
+            phrase.as_bytes(),
+            b"mnemonicTREZOR",
+            SEED_ROUNDS
+        )),
+        "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264\
+         c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+    );
+}
+
+#[test]
+fn test_bip32_derivation() {
+    let algorithm = HashAlgorithm::default();
+    // BIP32 test vector 1.
+    let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+    let master = ExtendedKey::master(&seed).unwrap();
+    assert_eq!(
+        hex::encode(master.key.to_bytes()),
+        "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35"
+    );
+    assert_eq!(
+        hex::encode(master.chain_code),
+        "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508"
+    );
+    let child = master.derive_path("m/0'").unwrap();
+    assert_eq!(
+        hex::encode(child.key.to_bytes()),
+        "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea"
+    );
+    let grandchild = master.derive_path("m/0'/1").unwrap();
+    assert_eq!(
+        hex::encode(grandchild.key.to_bytes()),
+        "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368"
+    );
+    assert_eq!(
+        hex::encode(grandchild.chain_code),
+        "2a7857631386ba23dacac34180dd1983734e444fdbf774041578e9b6adb37c19"
+    );
+
+    assert_eq!(parse_path("m").unwrap(), Vec::<u32>::new());
+    assert!(parse_path("0/1").is_err());
+    assert!(parse_path("m/x").is_err());
+    assert!(parse_path("m/2147483648").is_err());
+
+    let phrase = mnemonic_from_entropy(&[7; ENTROPY_BYTES]);
+    let wallet = Wallet::from_mnemonic(&phrase, "").unwrap();
+    let first = wallet.account(0).unwrap().address(algorithm);
+    assert_eq!(
+        first,
+        wallet
+            .derive(&format!("{ACCOUNT_PATH}/0"))
+            .unwrap()
+            .address(algorithm)
+    );
+    assert_ne!(first, wallet.account(1).unwrap().address(algorithm));
+}
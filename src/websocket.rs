@@ -0,0 +1,697 @@
+//! WebSocket connections at `/ws`, which carry log subscriptions, so that clients hear of new
+//! logs without polling.
+//!
+//! The server speaks just enough of RFC 6455 for that: it accepts the opening handshake, reads
+//! masked client frames, reassembling fragmented messages, and answers pings and closes.
+//! Messages are JSON text. A client subscribes with
+//!
+//! ```text
+//! {"id": 1, "subscribe": "logs", "addresses": ["0x…"], "topics": [["0x…"], []], "from_block": 0}
+//! ```
+//!
+//! where every field but `id` and `subscribe` may be left out, with the meaning they have in a
+//! [`LogFilter`], and `from_block` starts the subscription in the past. The server answers
+//! `{"id": 1, "subscription": <number>}`, then sends the matching logs as
+//! `{"subscription": <number>, "logs": [...]}`, including the `removed` entries a reorg brings
+//! (see [`crate::logs`]). `{"id": 2, "unsubscribe": <number>}` ends a subscription. A command
+//! that fails is answered with `{"id": ..., "error": <message>, "code": <code>}`.
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::hash::Hash;
+use crate::http::{Request, Response};
+use crate::logs::{LogEntry, LogFilter, LogSubscription};
+use crate::Blockhead;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Appended to a client's key to compute the handshake's accept key. See RFC 6455, section 1.3.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The largest message accepted, counting all its fragments.
+const MAX_MESSAGE: usize = 64 * 1024;
+/// The most subscriptions one connection may hold.
+const MAX_SUBSCRIPTIONS: usize = 16;
+/// Messages queued for a connection before its subscriptions wait for the client to read.
+const OUTGOING_BUFFER: usize = 64;
+
+/// Close codes. See RFC 6455, section 7.4.1.
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// A close, with its code if it gave one.
+    Close(Option<u16>),
+}
+
+impl Message {
+    fn opcode(&self) -> u8 {
+        match self {
+            Self::Text(_) => 0x1,
+            Self::Binary(_) => 0x2,
+            Self::Close(_) => 0x8,
+            Self::Ping(_) => 0x9,
+            Self::Pong(_) => 0xa,
+        }
+    }
+}
+
+/// A failure to read a message.
+enum ReadError {
+    /// The connection ended or failed.
+    Io(std::io::Error),
+    /// The client broke the protocol, and the connection is to be closed with `code`.
+    Protocol { code: u16, error: Error },
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+fn protocol_error(message: impl Into<String>) -> ReadError {
+    ReadError::Protocol {
+        code: CLOSE_PROTOCOL_ERROR,
+        error: Error::new(message),
+    }
+}
+
+/// The answer to `request`, a `GET /ws`: `101 Switching Protocols` if it is a WebSocket
+/// handshake the server accepts, and an error otherwise.
+pub(crate) fn handshake(request: &Request) -> Response {
+    if !request
+        .upgrade
+        .as_deref()
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+    {
+        return Response::error(400, "expected a WebSocket upgrade");
+    }
+    if request.websocket_version.as_deref() != Some("13") {
+        let mut response = Response::error(426, "only WebSocket version 13 is supported");
+        response
+            .headers
+            .push(("Sec-WebSocket-Version", "13".to_string()));
+        return response;
+    }
+    let Some(key) = &request.websocket_key else {
+        return Response::error(400, "no Sec-WebSocket-Key");
+    };
+    Response {
+        status: 101,
+        content_type: "",
+        headers: vec![
+            ("Upgrade", "websocket".to_string()),
+            ("Connection", "Upgrade".to_string()),
+            ("Sec-WebSocket-Accept", accept_key(key)),
+        ],
+        body: Vec::new(),
+    }
+}
+
+/// The `Sec-WebSocket-Accept` answering the client's `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+/// Serve a connection whose handshake has been answered until either side closes it. `reader`
+/// and `writer` are its two halves.
+pub(crate) async fn serve(
+    blockhead: Arc<Blockhead>,
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel(OUTGOING_BUFFER);
+    let reading = async move {
+        let mut connection = Connection {
+            blockhead,
+            sender,
+            subscriptions: BTreeMap::new(),
+            next_subscription: 1,
+        };
+        let result = connection.read(reader).await;
+        for task in connection.subscriptions.into_values() {
+            task.abort();
+        }
+        result
+    };
+    // Writing ends once reading has ended and the subscriptions have let go of the sender.
+    let (read, write) = tokio::join!(reading, write_all(writer, receiver));
+    read.and(write)
+}
+
+/// Write the messages from `receiver` until it closes or a close is written.
+async fn write_all(
+    mut writer: impl AsyncWrite + Unpin,
+    mut receiver: mpsc::Receiver<Message>,
+) -> Result<()> {
+    while let Some(message) = receiver.recv().await {
+        write_message(&mut writer, &message, None).await?;
+        if let Message::Close(_) = message {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct Connection {
+    blockhead: Arc<Blockhead>,
+    sender: mpsc::Sender<Message>,
+    /// The task sending each subscription's logs, by subscription number.
+    subscriptions: BTreeMap<u64, JoinHandle<()>>,
+    next_subscription: u64,
+}
+
+#[derive(Deserialize)]
+struct Command {
+    #[serde(default)]
+    id: Value,
+    subscribe: Option<String>,
+    unsubscribe: Option<u64>,
+    #[serde(default)]
+    addresses: Vec<Address>,
+    #[serde(default)]
+    topics: Vec<Vec<Hash>>,
+    from_block: Option<u64>,
+}
+
+impl Connection {
+    /// Read and act on messages until the client closes the connection or breaks the protocol.
+    async fn read(&mut self, mut reader: impl AsyncRead + Unpin) -> Result<()> {
+        let mut partial = None;
+        loop {
+            let message = match read_message(&mut reader, &mut partial).await {
+                Ok(message) => message,
+                Err(ReadError::Io(error)) if error.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(());
+                }
+                Err(ReadError::Io(error)) => return Err(error.into()),
+                Err(ReadError::Protocol { code, error }) => {
+                    let _ = self.sender.send(Message::Close(Some(code))).await;
+                    return Err(error);
+                }
+            };
+            let reply = match message {
+                Message::Text(text) => Message::Text(self.execute(&text).to_string()),
+                Message::Binary(_) => {
+                    let error = Error::new("expected a JSON text message");
+                    Message::Text(error_json(&Value::Null, &error).to_string())
+                }
+                Message::Ping(payload) => Message::Pong(payload),
+                Message::Pong(_) => continue,
+                Message::Close(code) => {
+                    let _ = self
+                        .sender
+                        .send(Message::Close(Some(code.unwrap_or(CLOSE_NORMAL))))
+                        .await;
+                    return Ok(());
+                }
+            };
+            if self.sender.send(reply).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run the command in `text`, returning the answer.
+    fn execute(&mut self, text: &str) -> Value {
+        let command: Command = match serde_json::from_str(text) {
+            Ok(command) => command,
+            Err(error) => {
+                let error = Error::new(format!("bad command: {error}"));
+                return error_json(&Value::Null, &error);
+            }
+        };
+        let result = match (&command.subscribe, command.unsubscribe) {
+            (Some(kind), None) if kind == "logs" => self.subscribe_logs(&command),
+            (Some(kind), None) => Err(Error::new(format!(
+                "no subscription to {kind:?}; only \"logs\""
+            ))),
+            (None, Some(subscription)) => Ok(self.unsubscribe(subscription)),
+            _ => Err(Error::new(
+                "expected one of \"subscribe\" and \"unsubscribe\"",
+            )),
+        };
+        match result {
+            Ok(mut answer) => {
+                answer["id"] = command.id;
+                answer
+            }
+            Err(error) => error_json(&command.id, &error),
+        }
+    }
+
+    fn subscribe_logs(&mut self, command: &Command) -> Result<Value> {
+        self.subscriptions.retain(|_, task| !task.is_finished());
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Err(Error::new(format!(
+                "a connection may hold at most {MAX_SUBSCRIPTIONS} subscriptions"
+            )));
+        }
+        let filter = LogFilter {
+            addresses: command.addresses.clone(),
+            topics: command.topics.clone(),
+        };
+        let logs = self.blockhead.subscribe_logs(filter, command.from_block)?;
+        let subscription = self.next_subscription;
+        self.next_subscription += 1;
+        let task = tokio::spawn(send_logs(subscription, logs, self.sender.clone()));
+        self.subscriptions.insert(subscription, task);
+        Ok(json!({ "subscription": subscription }))
+    }
+
+    fn unsubscribe(&mut self, subscription: u64) -> Value {
+        let task = self.subscriptions.remove(&subscription);
+        if let Some(task) = &task {
+            task.abort();
+        }
+        json!({ "unsubscribed": task.is_some() })
+    }
+}
+
+/// Send `logs`' entries as subscription `subscription`'s until the subscription or the
+/// connection ends.
+async fn send_logs(subscription: u64, mut logs: LogSubscription, sender: mpsc::Sender<Message>) {
+    loop {
+        let message = match logs.next().await {
+            Ok(entries) => json!({
+                "subscription": subscription,
+                "logs": entries.iter().map(entry_json).collect::<Vec<_>>(),
+            }),
+            Err(error) => json!({ "subscription": subscription, "error": error.message() }),
+        };
+        let ended = message.get("error").is_some();
+        if sender
+            .send(Message::Text(message.to_string()))
+            .await
+            .is_err()
+            || ended
+        {
+            return;
+        }
+    }
+}
+
+fn entry_json(entry: &LogEntry) -> Value {
+    json!({
+        "address": entry.log.address,
+        "topics": entry.log.topics,
+        "data": format!("0x{}", hex::encode(&entry.log.data)),
+        "block_hash": entry.block_hash,
+        "block_number": entry.block_number,
+        "transaction_hash": entry.transaction_hash,
+        "log_index": entry.log_index,
+        "removed": entry.removed,
+    })
+}
+
+/// The answer to command `id` that failed with `error`, shaped like the HTTP error bodies of
+/// [`Response::from_error`].
+fn error_json(id: &Value, error: &Error) -> Value {
+    let mut body = json!({"id": id, "error": error.message(), "code": error.kind().code()});
+    if let Some(data) = error.kind().data() {
+        body["data"] = data;
+    }
+    body
+}
+
+/// Read the next message, after any fragments of it already in `partial`, which holds the
+/// opcode and payload of a fragmented message until its last fragment arrives. Control messages
+/// may arrive between fragments, and are returned as they do.
+async fn read_message(
+    reader: &mut (impl AsyncRead + Unpin),
+    partial: &mut Option<(u8, Vec<u8>)>,
+) -> std::result::Result<Message, ReadError> {
+    loop {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        if head[0] & 0x70 != 0 {
+            return Err(protocol_error("reserved bits set without an extension"));
+        }
+        let opcode = head[0] & 0x0f;
+        if head[1] & 0x80 == 0 {
+            return Err(protocol_error("client frames must be masked"));
+        }
+        let len = match head[1] & 0x7f {
+            126 => reader.read_u16().await? as u64,
+            127 => reader.read_u64().await?,
+            len => len as u64,
+        };
+        let control = opcode & 0x08 != 0;
+        if control && (!fin || len > 125) {
+            return Err(protocol_error("control frames must be whole and short"));
+        }
+        let buffered = partial.as_ref().map_or(0, |(_, payload)| payload.len());
+        if len > (MAX_MESSAGE - buffered) as u64 {
+            return Err(ReadError::Protocol {
+                code: CLOSE_TOO_BIG,
+                error: Error::new(format!("messages are limited to {MAX_MESSAGE} bytes")),
+            });
+        }
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask).await?;
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        let (opcode, payload) = match (opcode, partial.take()) {
+            (0x8, partial_message) => {
+                *partial = partial_message;
+                let code =
+                    (payload.len() >= 2).then(|| u16::from_be_bytes([payload[0], payload[1]]));
+                return Ok(Message::Close(code));
+            }
+            (0x9 | 0xa, partial_message) => {
+                *partial = partial_message;
+                return Ok(match opcode {
+                    0x9 => Message::Ping(payload),
+                    _ => Message::Pong(payload),
+                });
+            }
+            (0x1 | 0x2, None) => (opcode, payload),
+            (0x0, Some((opcode, mut buffered))) => {
+                buffered.extend_from_slice(&payload);
+                (opcode, buffered)
+            }
+            (0x0, None) => {
+                return Err(protocol_error("continuation without a message to continue"))
+            }
+            (0x1 | 0x2, Some(_)) => {
+                return Err(protocol_error("new message before the last ended"))
+            }
+            _ => return Err(protocol_error(format!("unknown opcode {opcode:#x}"))),
+        };
+        if !fin {
+            *partial = Some((opcode, payload));
+            continue;
+        }
+        return match opcode {
+            0x1 => String::from_utf8(payload)
+                .map(Message::Text)
+                .map_err(|_| ReadError::Protocol {
+                    code: CLOSE_INVALID_DATA,
+                    error: Error::new("text message is not UTF-8"),
+                }),
+            _ => Ok(Message::Binary(payload)),
+        };
+    }
+}
+
+/// Write `message` as a single frame, masked with `mask` if given, as clients must mask theirs.
+pub(crate) async fn write_message(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &Message,
+    mask: Option<[u8; 4]>,
+) -> Result<()> {
+    let code;
+    let payload: &[u8] = match message {
+        Message::Text(text) => text.as_bytes(),
+        Message::Binary(payload) | Message::Ping(payload) | Message::Pong(payload) => payload,
+        Message::Close(close) => {
+            code = close.map(u16::to_be_bytes);
+            code.as_ref().map_or(&[][..], |code| &code[..])
+        }
+    };
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut frame = vec![0x80 | message.opcode()];
+    match payload.len() {
+        len @ 0..=125 => frame.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(i, byte)| byte ^ mask[i % 4]),
+            );
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// SHA-1, which the handshake's accept key needs and nothing else should. See RFC 3174.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard base64, with padding. See RFC 4648, section 4.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn test_handshake_digests() {
+    assert_eq!(
+        hex::encode(sha1(b"abc")),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    assert_eq!(
+        hex::encode(sha1(&[b'a'; 1_000])),
+        "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+    );
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    // The example of RFC 6455, section 1.3.
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[tokio::test]
+async fn test_websocket_subscription() {
+    use crate::db;
+    use crate::http::{send, serve_locally};
+    use crate::testkit::TestChain;
+    use crate::{Log, TransactionReceipt};
+    use tokio::io::AsyncBufReadExt;
+
+    // Logs written straight to the database, as if contract code had emitted them.
+    let chain = TestChain::new();
+    let contract = Address([9; 32]);
+    let mut blocks = Vec::new();
+    for word in [1u8, 2] {
+        let block = chain.produce();
+        let receipt = TransactionReceipt {
+            transaction_hash: block.hash,
+            block_hash: block.hash,
+            status: true,
+            gas_used: 0,
+            contract_address: None,
+            return_data: Vec::new(),
+            logs: vec![Log {
+                address: contract,
+                topics: vec![Hash([word; 32])],
+                data: vec![word],
+            }],
+        };
+        let connection = &chain.blockhead.connection;
+        db::transaction(connection, || {
+            db::write_logs(connection, &block, &[receipt])
+        })
+        .unwrap();
+        blocks.push(block);
+    }
+    let address = serve_locally(Arc::new(chain.blockhead)).await;
+
+    let (status, body) = send(address, "GET /ws HTTP/1.1\r\n\r\n").await;
+    assert_eq!(
+        (status, body["error"].as_str()),
+        (400, Some("expected a WebSocket upgrade"))
+    );
+    let old_version = "GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 8\r\n\r\n";
+    assert_eq!(send(address, old_version).await.0, 426);
+
+    let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = tokio::io::BufReader::new(reader);
+    writer
+        .write_all(
+            b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).await.unwrap();
+    }
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    // Server frames are unmasked, which read_message refuses, so the test reads them itself.
+    async fn receive(reader: &mut (impl AsyncRead + Unpin)) -> Message {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[1] & 0x80, 0);
+        let len = match head[1] {
+            126 => reader.read_u16().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).await.unwrap();
+        match head[0] & 0x0f {
+            0x1 => Message::Text(String::from_utf8(payload).unwrap()),
+            0x8 => Message::Close(Some(u16::from_be_bytes([payload[0], payload[1]]))),
+            0xa => Message::Pong(payload),
+            opcode => panic!("unexpected opcode {opcode}"),
+        }
+    }
+    async fn receive_json(reader: &mut (impl AsyncRead + Unpin)) -> Value {
+        match receive(reader).await {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("expected text, got {message:?}"),
+        }
+    }
+    let mask = Some([1, 2, 3, 4]);
+    let command = json!({
+        "id": 1,
+        "subscribe": "logs",
+        "topics": [[Hash([2; 32])]],
+        "from_block": 0,
+    });
+    let message = Message::Text(command.to_string());
+    write_message(&mut writer, &message, mask).await.unwrap();
+    assert_eq!(
+        receive_json(&mut reader).await,
+        json!({"id": 1, "subscription": 1})
+    );
+    assert_eq!(
+        receive_json(&mut reader).await,
+        json!({"subscription": 1, "logs": [{
+            "address": contract,
+            "topics": [Hash([2; 32])],
+            "data": "0x02",
+            "block_hash": blocks[1].hash,
+            "block_number": 2,
+            "transaction_hash": blocks[1].hash,
+            "log_index": 0,
+            "removed": false,
+        }]})
+    );
+
+    // A fragmented command, with a ping between its fragments.
+    let fragment = |head: u8, payload: &[u8]| {
+        let mut frame = vec![head, 0x80 | payload.len() as u8, 1, 2, 3, 4];
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ (i % 4 + 1) as u8),
+        );
+        frame
+    };
+    let command = json!({"id": "two", "unsubscribe": 1}).to_string();
+    let (first, rest) = command.as_bytes().split_at(5);
+    writer.write_all(&fragment(0x01, first)).await.unwrap();
+    let ping = Message::Ping(b"hi".to_vec());
+    write_message(&mut writer, &ping, mask).await.unwrap();
+    writer.write_all(&fragment(0x80, rest)).await.unwrap();
+    assert_eq!(receive(&mut reader).await, Message::Pong(b"hi".to_vec()));
+    assert_eq!(
+        receive_json(&mut reader).await,
+        json!({"id": "two", "unsubscribed": true})
+    );
+
+    let command = Message::Text(json!({"id": 3, "subscribe": "blocks"}).to_string());
+    write_message(&mut writer, &command, mask).await.unwrap();
+    let answer = receive_json(&mut reader).await;
+    assert_eq!(answer["id"], 3);
+    assert_eq!(
+        answer["error"],
+        "no subscription to \"blocks\"; only \"logs\""
+    );
+
+    let close = Message::Close(Some(CLOSE_NORMAL));
+    write_message(&mut writer, &close, mask).await.unwrap();
+    assert_eq!(receive(&mut reader).await, close);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
@@ -0,0 +1,28 @@
+//! The test chain as a downstream crate sees it, through the `testkit` feature.
+
+#[tokio::test]
+async fn test_test_chain_outside_the_crate() {
+    use blockhead::testkit::{self, TestChain, VALIDATOR_BALANCE};
+    use blockhead::{Address, BlockId, Blockchain};
+    use std::time::Duration;
+
+    let chain = TestChain::new();
+    let recipient = Address([8; 32]);
+    chain.transfer(chain.validator, recipient, 5).await;
+    let nonce = chain.next_nonce(chain.validator);
+    chain
+        .send(testkit::transfer(chain.validator, recipient, 6, nonce))
+        .await;
+    let block = chain.produce();
+    assert_eq!(block.body.transactions.len(), 2);
+    chain.assert_balance(recipient, 11);
+    chain.assert_balance(chain.validator, VALIDATOR_BALANCE - 11);
+
+    chain.advance(Duration::from_secs(10));
+    assert_eq!(chain.produce().timestamp, 10_000_000_000);
+
+    // The node behind it answers like any other.
+    let latest = chain.blockhead.get_block(BlockId::Latest).await.unwrap();
+    assert_eq!(latest.unwrap().number, 2);
+    assert_eq!(chain.blockhead.get_balance(recipient).await.unwrap(), 11);
+}